@@ -0,0 +1,50 @@
+use super::datastore::SledHolder;
+use super::errors::Error;
+
+use indradb::Result;
+
+/// Whether an operation reads or writes the graph.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+/// Decides whether operations on a tenant's partition are allowed, so that
+/// services embedding the datastore can enforce tenant-level permissions
+/// in the storage layer. Set with `SledDatastore::set_access_policy`.
+///
+/// The policy is consulted once per call on a `SledTransaction`, including
+/// its `Transaction` methods, and once per `bulk_insert` and committed
+/// `SledBatch`. It sees the calls themselves, not the reads and writes
+/// they cause, and administrative calls on `SledDatastore`, such as
+/// maintenance and partition management, aren't checked.
+pub trait AccessPolicy: Send + Sync {
+    /// Checks an operation, returning the reason it's denied if it is. The
+    /// denial is surfaced to the caller as `Error::AccessDenied`.
+    ///
+    /// # Arguments
+    /// * `tenant`: The tenant of the partition the operation is on, or
+    ///   `None` for the unpartitioned data.
+    /// * `kind`: Whether the operation reads or writes.
+    /// * `operation`: The name of the called method, e.g. `"get_vertices"`.
+    fn authorize(&self, tenant: Option<u32>, kind: AccessKind, operation: &str) -> std::result::Result<(), String>;
+}
+
+/// Runs an operation past the access policy, if one is set.
+pub(crate) fn authorize(holder: &SledHolder, kind: AccessKind, operation: &str) -> Result<()> {
+    let policy = match *holder.access_policy.read().unwrap() {
+        Some(ref policy) => policy.clone(),
+        None => return Ok(()),
+    };
+
+    match policy.authorize(holder.partition, kind, operation) {
+        Ok(()) => Ok(()),
+        Err(reason) => Err(Error::AccessDenied {
+            tenant: holder.partition,
+            operation: operation.to_string(),
+            reason,
+        }
+        .into()),
+    }
+}
@@ -0,0 +1,121 @@
+use std::convert::TryInto;
+
+use super::datastore::SledHolder;
+use super::decode::Decoder;
+use super::errors::map_err;
+
+use chrono::offset::Utc;
+use chrono::{DateTime, Datelike, NaiveDate};
+use indradb::{util, EdgeDirection, Result, Type};
+use sled::Tree;
+use uuid::Uuid;
+
+/// Encodes a day so that days sort in order.
+fn encode_day(day: NaiveDate) -> [u8; 4] {
+    ((day.num_days_from_ce() as u32) ^ (1 << 31)).to_be_bytes()
+}
+
+fn prefix(id: Uuid, t: &Type) -> Vec<u8> {
+    util::build(&[util::Component::Uuid(id), util::Component::Type(t)])
+}
+
+fn key(id: Uuid, t: &Type, day: NaiveDate) -> Vec<u8> {
+    let mut key = prefix(id, t);
+    key.extend_from_slice(&encode_day(day));
+    key
+}
+
+/// Decodes the outbound and inbound counts of a day.
+fn decode_counts(value: &[u8]) -> Option<(u64, u64)> {
+    if value.len() != 16 {
+        return None;
+    }
+
+    let outbound = u64::from_be_bytes(value[..8].try_into().unwrap());
+    let inbound = u64::from_be_bytes(value[8..].try_into().unwrap());
+    Some((outbound, inbound))
+}
+
+/// Adds to the counts of a vertex's edges of a type on a day.
+fn increment(holder: &SledHolder, tree: &Tree, key: &[u8], outbound: u64, inbound: u64) -> Result<()> {
+    holder.retrier.run(|| {
+        tree.update_and_fetch(key, |old| {
+            // A malformed value is reported when it's read, and replaced on
+            // the next write.
+            let (old_outbound, old_inbound) = old.and_then(decode_counts).unwrap_or((0, 0));
+            let mut value = Vec::with_capacity(16);
+            value.extend_from_slice(&(old_outbound + outbound).to_be_bytes());
+            value.extend_from_slice(&(old_inbound + inbound).to_be_bytes());
+            Some(value)
+        })
+    })?;
+
+    Ok(())
+}
+
+/// Counts an edge write on the day of its update datetime, for both of
+/// its vertices, if edge activity is enabled.
+pub(crate) fn record_edge_write(
+    holder: &SledHolder,
+    outbound_id: Uuid,
+    t: &Type,
+    inbound_id: Uuid,
+    update_datetime: DateTime<Utc>,
+) -> Result<()> {
+    let tree = match holder.edge_activity {
+        Some(ref tree) => tree,
+        None => return Ok(()),
+    };
+
+    let day = update_datetime.date_naive();
+    increment(holder, tree, &key(outbound_id, t, day), 1, 0)?;
+    increment(holder, tree, &key(inbound_id, t, day), 0, 1)
+}
+
+/// Gets the number of edge writes of a vertex per day, for the days with
+/// any, in day order.
+pub(crate) fn get(
+    tree: &Tree,
+    id: Uuid,
+    t: &Type,
+    direction: EdgeDirection,
+    low: Option<NaiveDate>,
+    high: Option<NaiveDate>,
+) -> Result<Vec<(NaiveDate, u64)>> {
+    let prefix = prefix(id, t);
+    let start = match low {
+        Some(low) => key(id, t, low),
+        None => prefix.clone(),
+    };
+    let mut days = Vec::new();
+
+    for item in tree.range(start..) {
+        let (k, v) = map_err(item)?;
+
+        if !k.starts_with(&prefix) {
+            break;
+        }
+
+        let mut decoder = Decoder::key(tree, &k);
+        decoder.skip(prefix.len())?;
+        let encoded = u32::from_be_bytes(decoder.read_bytes(4)?.try_into().unwrap());
+        let day =
+            NaiveDate::from_num_days_from_ce_opt((encoded ^ (1 << 31)) as i32).ok_or_else(|| decoder.corruption())?;
+
+        if high.is_some_and(|high| day > high) {
+            break;
+        }
+
+        let (outbound, inbound) = decode_counts(&v).ok_or_else(|| decoder.corruption())?;
+        let count = match direction {
+            EdgeDirection::Outbound => outbound,
+            EdgeDirection::Inbound => inbound,
+        };
+
+        if count > 0 {
+            days.push((day, count));
+        }
+    }
+
+    Ok(days)
+}
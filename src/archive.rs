@@ -0,0 +1,303 @@
+use super::datastore::SledHolder;
+use super::deadline::Deadline;
+use super::decode::Decoder;
+use super::dedup;
+use super::errors::map_err;
+use super::managers::{
+    read_vertex_value, EdgeManager, EdgePropertyManager, EdgeRangeManager, VertexManager, VertexPropertyManager,
+};
+
+use chrono::offset::Utc;
+use chrono::DateTime;
+use indradb::{util, Result, Vertex};
+use sled::{Batch, IVec, Tree};
+use uuid::Uuid;
+
+/// The trees that archived vertices, and their properties and edges, are
+/// moved to by `SledDatastore::archive_cold_vertices`. They're keyed the
+/// way their live counterparts are.
+pub(crate) struct ArchiveTrees {
+    pub(crate) vertices: Tree,
+    pub(crate) vertex_properties: Tree,
+    pub(crate) edges: Tree,
+    pub(crate) edge_properties: Tree,
+}
+
+/// Whether a vertex hasn't been touched since `cutoff`: it was created
+/// before then, and none of its edges have been created or updated since.
+/// Vertices created before creation datetimes were tracked are judged by
+/// their edges alone.
+fn is_cold(
+    holder: &SledHolder,
+    id: Uuid,
+    created_datetime: Option<DateTime<Utc>>,
+    cutoff: DateTime<Utc>,
+) -> Result<bool> {
+    if let Some(created_datetime) = created_datetime {
+        if created_datetime >= cutoff {
+            return Ok(false);
+        }
+    }
+
+    for edge_range_manager in &[EdgeRangeManager::new(holder), EdgeRangeManager::new_reversed(holder)] {
+        for item in edge_range_manager.iterate_for_owner(id) {
+            let (_, _, update_datetime, _) = item?;
+
+            if update_datetime >= cutoff {
+                return Ok(false);
+            }
+        }
+    }
+
+    Ok(true)
+}
+
+/// Copies a hot edge, along with its properties, into the archive under
+/// `owner_id`.
+fn copy_edge(
+    holder: &SledHolder,
+    owner_id: Uuid,
+    edge_key: &[u8],
+    edges_batch: &mut Batch,
+    edge_properties_batch: &mut Batch,
+) -> Result<()> {
+    let owner_prefix = util::build(&[util::Component::Uuid(owner_id)]);
+
+    if let Some(value) = holder.retrier.run(|| holder.edges.get(edge_key))? {
+        edges_batch.insert([&owner_prefix[..], edge_key].concat(), value);
+    }
+
+    for item in holder.edge_properties.scan_prefix(edge_key) {
+        let (k, v) = map_err(item)?;
+        let value_json = dedup::resolve(&holder.values.store, &holder.edge_properties, &k, &v)?;
+        edge_properties_batch.insert([&owner_prefix[..], &k[..]].concat(), value_json.into_owned());
+    }
+
+    Ok(())
+}
+
+/// Moves a vertex, its properties, its edges and their properties from the
+/// hot trees into the archive trees.
+fn archive_vertex(holder: &SledHolder, id: Uuid, vertex_key: &[u8], vertex_value: &[u8]) -> Result<()> {
+    let mut vertex_properties_batch = Batch::default();
+    let mut edges_batch = Batch::default();
+    let mut edge_properties_batch = Batch::default();
+
+    for item in holder.vertex_properties.scan_prefix(vertex_key) {
+        let (k, v) = map_err(item)?;
+        let value_json = dedup::resolve(&holder.values.store, &holder.vertex_properties, &k, &v)?;
+        vertex_properties_batch.insert(&k, value_json.into_owned());
+    }
+
+    for item in EdgeRangeManager::new(holder).iterate_for_owner(id) {
+        let (outbound_id, t, _, inbound_id) = item?;
+        let (outbound_id, inbound_id) = holder.edge_range_layout.canonical_ids(outbound_id, &t, inbound_id);
+        let edge_key = EdgeManager::build_key(outbound_id, &t, inbound_id);
+        copy_edge(holder, id, &edge_key, &mut edges_batch, &mut edge_properties_batch)?;
+    }
+
+    for item in EdgeRangeManager::new_reversed(holder).iterate_for_owner(id) {
+        let (inbound_id, t, _, outbound_id) = item?;
+        let (outbound_id, inbound_id) = holder.edge_range_layout.canonical_ids(outbound_id, &t, inbound_id);
+        let edge_key = EdgeManager::build_key(outbound_id, &t, inbound_id);
+        copy_edge(holder, id, &edge_key, &mut edges_batch, &mut edge_properties_batch)?;
+    }
+
+    // The vertex goes last, so that an interrupted archival never leaves a
+    // vertex in the archive without the rest of its data.
+    holder.retrier.run(|| {
+        holder
+            .archive
+            .vertex_properties
+            .apply_batch(vertex_properties_batch.clone())
+    })?;
+    holder
+        .retrier
+        .run(|| holder.archive.edges.apply_batch(edges_batch.clone()))?;
+    holder.retrier.run(|| {
+        holder
+            .archive
+            .edge_properties
+            .apply_batch(edge_properties_batch.clone())
+    })?;
+    holder
+        .retrier
+        .run(|| holder.archive.vertices.insert(vertex_key, vertex_value))?;
+
+    VertexManager::new(holder).delete(id, &Deadline::new(None))
+}
+
+/// Archives every vertex that hasn't been touched since `cutoff`. Returns
+/// the number of vertices archived.
+pub(crate) fn archive_cold_vertices(holder: &SledHolder, cutoff: DateTime<Utc>) -> Result<u64> {
+    // Inbound edges can't all be found while indexing is deferred, and
+    // those that were missed would be lost rather than archived.
+    if holder.is_indexing_deferred() {
+        return Ok(0);
+    }
+
+    let mut archived = 0;
+
+    // Moving vertices out as we go is fine, since sled iterators don't hold
+    // locks.
+    for item in holder.vertices.iter().keys() {
+        let k = map_err(item)?;
+        let id = Decoder::key(&holder.vertices, &k).read_uuid()?;
+        let _guard = holder.write_guard();
+
+        // The vertex may have been deleted or replaced before the guard was
+        // taken.
+        let v = match holder.retrier.run(|| holder.vertices.get(&k))? {
+            Some(v) => v,
+            None => continue,
+        };
+
+        let (_, created_datetime) = read_vertex_value(&holder.vertices, &k, &v)?;
+
+        if is_cold(holder, id, created_datetime, cutoff)? {
+            archive_vertex(holder, id, &k, &v)?;
+            archived += 1;
+        }
+    }
+
+    Ok(archived)
+}
+
+/// Whether a vertex is in the archive.
+pub(crate) fn is_archived(holder: &SledHolder, id: Uuid) -> Result<bool> {
+    let key = util::build(&[util::Component::Uuid(id)]);
+    holder.retrier.run(|| holder.archive.vertices.contains_key(&key))
+}
+
+/// Reads the archived records prefixed by `prefix` from `tree`.
+fn read_prefixed(tree: &Tree, prefix: &[u8]) -> Result<Vec<(IVec, IVec)>> {
+    tree.scan_prefix(prefix).map(map_err).collect()
+}
+
+/// Removes the archived records prefixed by `prefix` from `tree`.
+fn remove_prefixed(holder: &SledHolder, tree: &Tree, prefix: &[u8]) -> Result<()> {
+    let mut batch = Batch::default();
+
+    for item in tree.scan_prefix(prefix).keys() {
+        batch.remove(map_err(item)?);
+    }
+
+    holder.retrier.run(|| tree.apply_batch(batch.clone()))
+}
+
+/// Moves a vertex and its data out of the archive and back into the hot
+/// trees. Returns whether the vertex was archived.
+///
+/// Archived edges are restored if their other vertex is hot. If it's
+/// archived too, they stay in the archive, to be restored along with it.
+/// If it no longer exists, they're dropped. Data that was written to the
+/// hot trees since the vertex was archived takes precedence.
+pub(crate) fn unarchive(holder: &SledHolder, id: Uuid) -> Result<bool> {
+    let _guard = holder.write_guard();
+    let vertex_key = util::build(&[util::Component::Uuid(id)]);
+
+    let vertex_value = match holder.retrier.run(|| holder.archive.vertices.get(&vertex_key))? {
+        Some(vertex_value) => vertex_value,
+        None => return Ok(false),
+    };
+
+    let vertex_manager = VertexManager::new(holder);
+    let vertex_property_manager = VertexPropertyManager::new(holder);
+    let edge_manager = EdgeManager::new(holder);
+    let edge_property_manager = EdgePropertyManager::new(holder);
+
+    if !vertex_manager.exists(id)? {
+        let (t, created_datetime) = read_vertex_value(&holder.archive.vertices, &vertex_key, &vertex_value)?;
+        let vertex = Vertex::with_id(id, t);
+        vertex_manager.create_at(&vertex, created_datetime.unwrap_or_else(Utc::now))?;
+    }
+
+    for (k, v) in read_prefixed(&holder.archive.vertex_properties, &vertex_key)? {
+        let mut decoder = Decoder::key(&holder.archive.vertex_properties, &k);
+        decoder.read_uuid()?;
+        let name = decoder.read_fixed_length_string()?;
+
+        if vertex_property_manager.get(id, &name)?.is_none() {
+            vertex_property_manager.set(id, &name, &serde_json::from_slice(&v)?)?;
+        }
+    }
+
+    let edge_properties = read_prefixed(&holder.archive.edge_properties, &vertex_key)?;
+
+    for (k, v) in read_prefixed(&holder.archive.edges, &vertex_key)? {
+        let mut decoder = Decoder::key(&holder.archive.edges, &k);
+        decoder.read_uuid()?;
+        let outbound_id = decoder.read_uuid()?;
+        let t = decoder.read_type()?;
+        let inbound_id = decoder.read_uuid()?;
+        let update_datetime = Decoder::value(&holder.archive.edges, &k, &v).read_datetime()?;
+        let other_id = if outbound_id == id { inbound_id } else { outbound_id };
+
+        let edge_prefix = &k[vertex_key.len()..];
+        let properties = edge_properties
+            .iter()
+            .filter(|(k, _)| k[vertex_key.len()..].starts_with(edge_prefix));
+
+        if vertex_manager.exists(other_id)? {
+            if edge_manager.get(outbound_id, &t, inbound_id)?.is_none() {
+                edge_manager.set(outbound_id, &t, inbound_id, update_datetime)?;
+
+                for (k, v) in properties {
+                    let name = read_edge_property_name(holder, k)?;
+                    edge_property_manager.set(outbound_id, &t, inbound_id, &name, &serde_json::from_slice(v)?)?;
+                }
+            }
+        } else if is_archived(holder, other_id)? {
+            hand_over_edge(holder, other_id, edge_prefix, &v, properties)?;
+        }
+    }
+
+    // As when archiving, the vertex goes last, so that an interrupted
+    // restore can be finished by calling this again.
+    remove_prefixed(holder, &holder.archive.vertex_properties, &vertex_key)?;
+    remove_prefixed(holder, &holder.archive.edges, &vertex_key)?;
+    remove_prefixed(holder, &holder.archive.edge_properties, &vertex_key)?;
+    holder.retrier.run(|| holder.archive.vertices.remove(&vertex_key))?;
+    Ok(true)
+}
+
+/// Reads the name of an archived edge property.
+fn read_edge_property_name(holder: &SledHolder, key: &[u8]) -> Result<String> {
+    let mut decoder = Decoder::key(&holder.archive.edge_properties, key);
+    decoder.read_uuid()?;
+    decoder.read_uuid()?;
+    decoder.read_type()?;
+    decoder.read_uuid()?;
+    decoder.read_fixed_length_string()
+}
+
+/// Files an archived edge, along with its properties, under the other
+/// vertex it connects, which is still archived.
+fn hand_over_edge<'a, I>(
+    holder: &SledHolder,
+    owner_id: Uuid,
+    edge_key: &[u8],
+    edge_value: &[u8],
+    properties: I,
+) -> Result<()>
+where
+    I: Iterator<Item = &'a (IVec, IVec)>,
+{
+    let owner_prefix = util::build(&[util::Component::Uuid(owner_id)]);
+    let mut batch = Batch::default();
+
+    for (k, v) in properties {
+        batch.insert([&owner_prefix[..], &k[owner_prefix.len()..]].concat(), &v[..]);
+    }
+
+    holder
+        .retrier
+        .run(|| holder.archive.edge_properties.apply_batch(batch.clone()))?;
+    holder.retrier.run(|| {
+        holder
+            .archive
+            .edges
+            .insert([&owner_prefix[..], edge_key].concat(), edge_value)
+    })?;
+    Ok(())
+}
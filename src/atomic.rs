@@ -0,0 +1,99 @@
+use super::datastore::SledHolder;
+use super::reindex::IndexWriter;
+
+use indradb::Result;
+use sled::transaction::{TransactionError, Transactional};
+use sled::{Batch, IVec, Tree};
+
+/// Groups writes to several trees so that they're applied in a single sled
+/// transaction: either all of them survive a crash, or none of them do,
+/// and readers never see some trees written and not others.
+///
+/// Only the trees that make up a logical record - e.g. an edge, its range
+/// entries and its properties - are written this way. Counters and
+/// indexes that are rebuilt from those trees, like the catalog and the
+/// full-text index, are updated once the transaction is committed.
+#[derive(Default)]
+pub(crate) struct MultiBatch {
+    trees: Vec<Tree>,
+    batches: Vec<Batch>,
+    // The size of the keys and values written, for the datastore's pending
+    // write bytes.
+    bytes: usize,
+}
+
+impl MultiBatch {
+    fn batch(&mut self, tree: &Tree) -> &mut Batch {
+        let i = match self.trees.iter().position(|other| other.name() == tree.name()) {
+            Some(i) => i,
+            None => {
+                self.trees.push(tree.clone());
+                self.batches.push(Batch::default());
+                self.trees.len() - 1
+            }
+        };
+
+        &mut self.batches[i]
+    }
+
+    pub(crate) fn insert<K: Into<IVec>, V: Into<IVec>>(&mut self, tree: &Tree, key: K, value: V) {
+        let (key, value) = (key.into(), value.into());
+        self.bytes += key.len() + value.len();
+        self.batch(tree).insert(key, value);
+    }
+
+    pub(crate) fn remove<K: Into<IVec>>(&mut self, tree: &Tree, key: K) {
+        let key = key.into();
+        self.bytes += key.len();
+        self.batch(tree).remove(key);
+    }
+
+    /// Inserts an entry into a derived index, and into the tree it's being
+    /// rebuilt in, if any.
+    pub(crate) fn insert_index<K: Into<IVec>, V: Into<IVec>>(&mut self, index: &IndexWriter, key: K, value: V) {
+        let (key, value) = (key.into(), value.into());
+
+        for tree in index.trees() {
+            self.bytes += key.len() + value.len();
+            self.batch(tree).insert(key.clone(), value.clone());
+        }
+    }
+
+    /// Removes an entry from a derived index, and from the tree it's being
+    /// rebuilt in, if any.
+    pub(crate) fn remove_index<K: Into<IVec>>(&mut self, index: &IndexWriter, key: K) {
+        let key = key.into();
+
+        for tree in index.trees() {
+            self.bytes += key.len();
+            self.batch(tree).remove(key.clone());
+        }
+    }
+
+    /// Applies the writes. Sled retries the transaction itself if it
+    /// conflicts with another one, while transient storage errors are
+    /// retried under the datastore's retry policy. The writes count towards
+    /// its pending write bytes while they're applied.
+    pub(crate) fn apply(&self, holder: &SledHolder) -> Result<()> {
+        if self.trees.is_empty() {
+            return Ok(());
+        }
+
+        let _in_flight = holder.pending_writes.hold(self.bytes);
+        holder.retrier.run(|| {
+            let result = self.trees[..].transaction(|txs| {
+                for (tx, batch) in txs.iter().zip(&self.batches) {
+                    tx.apply_batch(batch)?;
+                }
+
+                Ok(())
+            });
+
+            match result {
+                Ok(()) => Ok(()),
+                Err(TransactionError::Storage(err)) => Err(err),
+                Err(TransactionError::Abort(())) => unreachable!(),
+            }
+        })
+    }
+}
@@ -0,0 +1,118 @@
+use std::convert::TryInto;
+
+use super::datastore::SledHolder;
+use super::decode::Decoder;
+use super::errors::map_err;
+
+use chrono::offset::Utc;
+use chrono::DateTime;
+use indradb::Result;
+use serde_json::{json, Value as JsonValue};
+use sled::Tree;
+
+/// The audit log's tree, keyed by the datetime each entry was recorded at
+/// and a sequence number. Written to if `SledConfig::with_audit_log` is
+/// set.
+pub(crate) struct AuditLog {
+    pub(crate) enabled: bool,
+    pub(crate) entries: Tree,
+}
+
+/// An entry in the audit log, recording a mutating API call.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuditEntry {
+    /// When the call completed.
+    pub datetime: DateTime<Utc>,
+    /// The name of the API call, e.g. `create_edge`.
+    pub operation: String,
+    /// The caller-supplied context of the transaction, e.g. a user name.
+    pub context: Option<String>,
+    /// A description of the call's arguments.
+    pub details: String,
+}
+
+// Keys are `(timestamp in nanoseconds, sequence number)`, both big-endian,
+// so that the log is ordered chronologically and entries recorded in the
+// same nanosecond don't collide.
+fn key(datetime: DateTime<Utc>, sequence: u64) -> Vec<u8> {
+    // Datetimes that can't be given in nanoseconds since the epoch, such as
+    // far-off query bounds, are clamped to the ends of the log.
+    let nanos = match datetime.timestamp_nanos_opt() {
+        Some(nanos) => nanos.max(0) as u64,
+        None if datetime.timestamp() < 0 => 0,
+        None => u64::MAX,
+    };
+
+    let mut key = Vec::with_capacity(16);
+    key.extend_from_slice(&nanos.to_be_bytes());
+    key.extend_from_slice(&sequence.to_be_bytes());
+    key
+}
+
+fn datetime_from_key(tree: &Tree, key: &[u8]) -> Result<DateTime<Utc>> {
+    let mut decoder = Decoder::key(tree, key);
+    let nanos = u64::from_be_bytes(decoder.read_bytes(8)?.try_into().unwrap());
+    decoder.skip(8)?;
+
+    if !decoder.is_empty() {
+        return Err(decoder.corruption());
+    }
+
+    DateTime::from_timestamp((nanos / 1_000_000_000) as i64, (nanos % 1_000_000_000) as u32)
+        .ok_or_else(|| decoder.corruption())
+}
+
+fn read_string(value: &JsonValue, field: &str) -> Option<String> {
+    value[field].as_str().map(|s| s.to_string())
+}
+
+/// Appends an entry to the audit log, if it's enabled.
+pub(crate) fn record(holder: &SledHolder, context: Option<&str>, operation: &str, details: String) -> Result<()> {
+    if !holder.audit.enabled {
+        return Ok(());
+    }
+
+    let sequence = map_err(holder.db.generate_id())?;
+    let value = json!({
+        "operation": operation,
+        "context": context,
+        "details": details,
+    });
+
+    let value_json = serde_json::to_vec(&value)?;
+    map_err(holder.audit.entries.insert(key(Utc::now(), sequence), value_json))?;
+    Ok(())
+}
+
+/// Gets the audit log entries recorded between `low` and `high`
+/// (inclusive), oldest first.
+pub(crate) fn entries(
+    holder: &SledHolder,
+    low: Option<DateTime<Utc>>,
+    high: Option<DateTime<Utc>>,
+) -> Result<Vec<AuditEntry>> {
+    let low_key = match low {
+        Some(low) => key(low, 0),
+        None => Vec::new(),
+    };
+    let iter = match high {
+        Some(high) => holder.audit.entries.range(low_key..=key(high, u64::MAX)),
+        None => holder.audit.entries.range(low_key..),
+    };
+
+    let mut entries = Vec::new();
+
+    for item in iter {
+        let (k, v) = map_err(item)?;
+        let value: JsonValue = serde_json::from_slice(&v)?;
+
+        entries.push(AuditEntry {
+            datetime: datetime_from_key(&holder.audit.entries, &k)?,
+            operation: read_string(&value, "operation").unwrap_or_default(),
+            context: read_string(&value, "context"),
+            details: read_string(&value, "details").unwrap_or_default(),
+        });
+    }
+
+    Ok(entries)
+}
@@ -0,0 +1,780 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::mem::{self, size_of};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use super::access::{self, AccessKind};
+use super::atomic::MultiBatch;
+use super::cache::json_size;
+use super::datastore::{SledHolder, SledTransaction};
+use super::limits;
+use super::managers::{EdgeManager, EdgePropertyManager, EdgeRangeItem, VertexManager, VertexPropertyManager};
+use super::validate::{self, Mutation};
+
+use chrono::offset::Utc;
+use chrono::DateTime;
+use indradb::{EdgeDirection, EdgeKey, Result, SpecificVertexQuery, Type, Vertex};
+use serde_json::Value as JsonValue;
+use uuid::Uuid;
+
+/// Groups writes so that they're applied with one sled transaction per
+/// kind of item, rather than one write per item. Created by
+/// `SledTransaction::begin_batch`.
+///
+/// Nothing is written until `commit` is called. Batches aren't
+/// transactional as a whole: the vertices, the edges, the vertex
+/// properties and the edge properties are each applied atomically, but a
+/// crash midway through a commit can leave some of them written and not
+/// others, and concurrent readers may see a partially applied batch.
+pub struct SledBatch<'a> {
+    trans: &'a SledTransaction,
+    writes: PendingWrites,
+    // The approximate size of `writes`, counted in the datastore's pending
+    // write bytes until the batch is committed or dropped.
+    bytes: usize,
+}
+
+impl<'a> SledBatch<'a> {
+    pub(crate) fn new(trans: &'a SledTransaction) -> Self {
+        SledBatch {
+            trans,
+            writes: PendingWrites::default(),
+            bytes: 0,
+        }
+    }
+
+    fn count(&mut self, bytes: usize) {
+        self.bytes += bytes;
+        self.trans.holder.pending_writes.update(0, bytes);
+    }
+
+    /// Adds a vertex to create. Like `Transaction::create_vertex`, this does
+    /// nothing if the vertex already exists.
+    pub fn create_vertex(&mut self, vertex: &Vertex) -> &mut Self {
+        self.count(vertex_size(vertex));
+        self.writes.vertices.push(vertex.clone());
+        self
+    }
+
+    /// Adds an edge to create, or whose update datetime to refresh if it
+    /// already exists. Like `Transaction::create_edge`, this does nothing
+    /// unless both of its vertices exist, possibly by being created in
+    /// this batch.
+    pub fn create_edge(&mut self, key: &EdgeKey) -> &mut Self {
+        self.count(edge_key_size(key));
+        self.writes.edges.push(key.clone());
+        self
+    }
+
+    /// Adds a vertex property to set. This does nothing unless the vertex
+    /// exists. If the same property is set more than once in a batch, the
+    /// last value wins.
+    pub fn set_vertex_property(&mut self, id: Uuid, name: &str, value: &JsonValue) -> &mut Self {
+        self.count(size_of::<Uuid>() + property_size(name, value));
+        self.writes
+            .vertex_properties
+            .push((id, name.to_string(), value.clone()));
+        self
+    }
+
+    /// Adds an edge property to set. This does nothing unless the edge
+    /// exists. If the same property is set more than once in a batch, the
+    /// last value wins.
+    pub fn set_edge_property(&mut self, key: &EdgeKey, name: &str, value: &JsonValue) -> &mut Self {
+        self.count(edge_key_size(key) + property_size(name, value));
+        self.writes
+            .edge_properties
+            .push((key.clone(), name.to_string(), value.clone()));
+        self
+    }
+
+    /// Whether nothing has been added to the batch.
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    /// Applies the batch. Vertices are written first, then edges, then
+    /// vertex properties, then edge properties, so each can depend on
+    /// what came before it in the batch.
+    pub fn commit(mut self) -> Result<()> {
+        access::authorize(&self.trans.holder, AccessKind::Write, "commit_batch")?;
+        mem::take(&mut self.writes).apply(self.trans, "commit_batch")
+    }
+}
+
+impl<'a> Drop for SledBatch<'a> {
+    fn drop(&mut self) {
+        self.trans.holder.pending_writes.update(self.bytes, 0);
+    }
+}
+
+fn vertex_size(vertex: &Vertex) -> usize {
+    size_of::<Vertex>() + vertex.t.0.len()
+}
+
+fn edge_key_size(key: &EdgeKey) -> usize {
+    size_of::<EdgeKey>() + key.t.0.len()
+}
+
+fn property_size(name: &str, value: &JsonValue) -> usize {
+    size_of::<String>() + name.len() + json_size(value)
+}
+
+/// The approximate size of the writes that batches and write buffers have
+/// staged, and of those being applied. See `SledDatastore::memory_usage`.
+#[derive(Default)]
+pub(crate) struct PendingBytes(AtomicUsize);
+
+impl PendingBytes {
+    pub(crate) fn get(&self) -> usize {
+        self.0.load(Ordering::Acquire)
+    }
+
+    /// Replaces `old` bytes of the total with `new` ones.
+    pub(crate) fn update(&self, old: usize, new: usize) {
+        if new > old {
+            self.0.fetch_add(new - old, Ordering::AcqRel);
+        } else {
+            self.0.fetch_sub(old - new, Ordering::AcqRel);
+        }
+    }
+
+    /// Counts `bytes` until the returned guard is dropped.
+    pub(crate) fn hold(&self, bytes: usize) -> HeldBytes<'_> {
+        self.update(0, bytes);
+        HeldBytes { pending: self, bytes }
+    }
+}
+
+/// Returned by `PendingBytes::hold`.
+pub(crate) struct HeldBytes<'a> {
+    pending: &'a PendingBytes,
+    bytes: usize,
+}
+
+impl<'a> Drop for HeldBytes<'a> {
+    fn drop(&mut self) {
+        self.pending.update(self.bytes, 0);
+    }
+}
+
+/// The writes staged by a transaction with a write buffer, which are
+/// counted in the datastore's pending write bytes until they're taken.
+pub(crate) struct WriteBuffer {
+    holder: Arc<SledHolder>,
+    writes: PendingWrites,
+    bytes: usize,
+}
+
+impl WriteBuffer {
+    pub(crate) fn new(holder: Arc<SledHolder>) -> Self {
+        WriteBuffer {
+            holder,
+            writes: PendingWrites::default(),
+            bytes: 0,
+        }
+    }
+
+    pub(crate) fn writes(&self) -> &PendingWrites {
+        &self.writes
+    }
+
+    /// Runs `f` on the staged writes, and recounts their size. Staging
+    /// already scans the writes, so this doesn't change its complexity.
+    pub(crate) fn update<T, F: FnOnce(&mut PendingWrites) -> T>(&mut self, f: F) -> T {
+        let result = f(&mut self.writes);
+        let bytes = self.writes.approximate_size();
+        self.holder.pending_writes.update(self.bytes, bytes);
+        self.bytes = bytes;
+        result
+    }
+
+    /// Takes the staged writes, leaving the buffer empty.
+    pub(crate) fn take(&mut self) -> PendingWrites {
+        self.update(mem::take)
+    }
+}
+
+impl Drop for WriteBuffer {
+    fn drop(&mut self) {
+        self.holder.pending_writes.update(self.bytes, 0);
+    }
+}
+
+/// Writes that are waiting to be applied, by a `SledBatch` or by a
+/// transaction with a write buffer. Only the latter stages deletes, which
+/// are applied before everything else.
+#[derive(Clone, Default)]
+pub(crate) struct PendingWrites {
+    pub(crate) vertices: Vec<Vertex>,
+    pub(crate) edges: Vec<EdgeKey>,
+    pub(crate) vertex_properties: Vec<(Uuid, String, JsonValue)>,
+    pub(crate) edge_properties: Vec<(EdgeKey, String, JsonValue)>,
+    /// When the edges staged by a write buffer were staged, which reads
+    /// report as their update datetimes until they're committed.
+    pub(crate) edge_datetimes: HashMap<EdgeKey, DateTime<Utc>>,
+    deleted_vertices: Vec<Uuid>,
+    deleted_edges: Vec<EdgeKey>,
+    deleted_vertex_properties: Vec<(Uuid, String)>,
+    deleted_edge_properties: Vec<(EdgeKey, String)>,
+}
+
+impl PendingWrites {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+            && self.edges.is_empty()
+            && self.vertex_properties.is_empty()
+            && self.edge_properties.is_empty()
+            && self.deleted_vertices.is_empty()
+            && self.deleted_edges.is_empty()
+            && self.deleted_vertex_properties.is_empty()
+            && self.deleted_edge_properties.is_empty()
+    }
+
+    /// Approximates the heap memory used by the writes.
+    pub(crate) fn approximate_size(&self) -> usize {
+        let vertices: usize = self.vertices.iter().map(vertex_size).sum();
+        let edges: usize = self.edges.iter().chain(&self.deleted_edges).map(edge_key_size).sum();
+        let vertex_properties: usize = self
+            .vertex_properties
+            .iter()
+            .map(|(_, name, value)| size_of::<Uuid>() + property_size(name, value))
+            .sum();
+        let edge_properties: usize = self
+            .edge_properties
+            .iter()
+            .map(|(key, name, value)| edge_key_size(key) + property_size(name, value))
+            .sum();
+        let edge_datetimes: usize = self
+            .edge_datetimes
+            .keys()
+            .map(|key| edge_key_size(key) + size_of::<DateTime<Utc>>())
+            .sum();
+        let deleted_properties: usize = self
+            .deleted_vertex_properties
+            .iter()
+            .map(|(_, name)| size_of::<(Uuid, String)>() + name.len())
+            .chain(
+                self.deleted_edge_properties
+                    .iter()
+                    .map(|(key, name)| edge_key_size(key) + size_of::<String>() + name.len()),
+            )
+            .sum();
+
+        vertices
+            + edges
+            + vertex_properties
+            + edge_properties
+            + edge_datetimes
+            + deleted_properties
+            + self.deleted_vertices.len() * size_of::<Uuid>()
+    }
+
+    /// The vertices whose data the writes change, or `None` if deleting a
+    /// vertex could cascade to others.
+    pub(crate) fn touched_vertices(&self) -> Option<Vec<Uuid>> {
+        if !self.deleted_vertices.is_empty() {
+            return None;
+        }
+
+        let vertex_ids = self.vertices.iter().map(|vertex| vertex.id);
+        let vertex_property_ids = self
+            .vertex_properties
+            .iter()
+            .map(|&(id, _, _)| id)
+            .chain(self.deleted_vertex_properties.iter().map(|&(id, _)| id));
+        let edge_keys = self
+            .edges
+            .iter()
+            .chain(self.deleted_edges.iter())
+            .chain(self.edge_properties.iter().map(|(key, _, _)| key))
+            .chain(self.deleted_edge_properties.iter().map(|(key, _)| key));
+
+        Some(
+            vertex_ids
+                .chain(vertex_property_ids)
+                .chain(edge_keys.flat_map(|key| vec![key.outbound_id, key.inbound_id]))
+                .collect(),
+        )
+    }
+
+    /// Gets the type of a vertex as it will be once the writes are applied,
+    /// given its type as committed, if it exists.
+    pub(crate) fn vertex(&self, id: Uuid, committed: Option<Type>) -> Option<Type> {
+        match self.vertices.iter().rev().find(|vertex| vertex.id == id) {
+            Some(vertex) => Some(vertex.t.clone()),
+            None if self.deleted_vertices.contains(&id) => None,
+            None => committed,
+        }
+    }
+
+    /// Whether a vertex is among the vertices to create.
+    pub(crate) fn creates_vertex(&self, id: Uuid) -> bool {
+        self.vertices.iter().any(|vertex| vertex.id == id)
+    }
+
+    /// Gets the update datetime of an edge as it will be once the writes
+    /// are applied, given its update datetime as committed, if it exists.
+    /// The edges of deleted vertices are taken to be deleted with them.
+    pub(crate) fn edge(&self, key: &EdgeKey, committed: Option<DateTime<Utc>>) -> Option<DateTime<Utc>> {
+        match self.edge_datetimes.get(key) {
+            Some(datetime) => Some(*datetime),
+            None if self.deletes_edge(key) => None,
+            None => committed,
+        }
+    }
+
+    /// Whether any vertices or edges are to be deleted, which can hide any
+    /// number of committed edges from reads.
+    pub(crate) fn has_deletes(&self) -> bool {
+        !self.deleted_vertices.is_empty() || !self.deleted_edges.is_empty()
+    }
+
+    /// Gets the edge ranges of a vertex as they will be once the writes are
+    /// applied, given its committed edge ranges in the given direction,
+    /// which are expected to already be filtered to `t`, `low` and `high`.
+    /// Staged edges are the newest, so they come first.
+    pub(crate) fn edge_range(
+        &self,
+        id: Uuid,
+        direction: EdgeDirection,
+        t: Option<&Type>,
+        low: Option<DateTime<Utc>>,
+        high: Option<DateTime<Utc>>,
+        committed: Vec<EdgeRangeItem>,
+    ) -> Vec<EdgeRangeItem> {
+        let to_key = |first_id: Uuid, t: Type, second_id: Uuid| match direction {
+            EdgeDirection::Outbound => EdgeKey::new(first_id, t, second_id),
+            EdgeDirection::Inbound => EdgeKey::new(second_id, t, first_id),
+        };
+
+        let mut edges: Vec<EdgeRangeItem> = self
+            .edge_datetimes
+            .iter()
+            .filter(|&(key, datetime)| {
+                let first_id = match direction {
+                    EdgeDirection::Outbound => key.outbound_id,
+                    EdgeDirection::Inbound => key.inbound_id,
+                };
+
+                first_id == id
+                    && t.is_none_or(|t| t == &key.t)
+                    && low.is_none_or(|low| *datetime >= low)
+                    && high.is_none_or(|high| *datetime <= high)
+            })
+            .map(|(key, datetime)| match direction {
+                EdgeDirection::Outbound => (key.outbound_id, key.t.clone(), *datetime, key.inbound_id),
+                EdgeDirection::Inbound => (key.inbound_id, key.t.clone(), *datetime, key.outbound_id),
+            })
+            .collect();
+
+        edges.sort_by_key(|edge| Reverse(edge.2));
+
+        for (first_id, t, datetime, second_id) in committed {
+            let key = to_key(first_id, t, second_id);
+
+            if !self.edge_datetimes.contains_key(&key) && !self.deletes_edge(&key) {
+                edges.push((first_id, key.t, datetime, second_id));
+            }
+        }
+
+        edges
+    }
+
+    fn deletes_edge(&self, key: &EdgeKey) -> bool {
+        self.deleted_edges.contains(key)
+            || self.deleted_vertices.contains(&key.outbound_id)
+            || self.deleted_vertices.contains(&key.inbound_id)
+    }
+
+    /// Gets the value of a vertex property as it will be once the writes
+    /// are applied, given its committed value, if any.
+    pub(crate) fn vertex_property(&self, id: Uuid, name: &str, committed: Option<JsonValue>) -> Option<JsonValue> {
+        let staged = self
+            .vertex_properties
+            .iter()
+            .rev()
+            .find(|&&(staged_id, ref staged_name, _)| staged_id == id && staged_name == name);
+
+        match staged {
+            Some((_, _, value)) => Some(value.clone()),
+            None if self.deletes_vertex_property(id, name) => None,
+            None => committed,
+        }
+    }
+
+    fn deletes_vertex_property(&self, id: Uuid, name: &str) -> bool {
+        self.deleted_vertices.contains(&id)
+            || self
+                .deleted_vertex_properties
+                .iter()
+                .any(|&(deleted_id, ref deleted_name)| deleted_id == id && deleted_name == name)
+    }
+
+    /// Gets the properties of a vertex as they will be once the writes are
+    /// applied, given its committed properties, in name order.
+    pub(crate) fn vertex_properties_of(
+        &self,
+        id: Uuid,
+        committed: Vec<(String, JsonValue)>,
+    ) -> Vec<(String, JsonValue)> {
+        let mut properties: Vec<(String, JsonValue)> = committed
+            .into_iter()
+            .filter(|(name, _)| !self.deletes_vertex_property(id, name))
+            .collect();
+
+        for &(staged_id, ref name, ref value) in &self.vertex_properties {
+            if staged_id == id {
+                properties.retain(|(existing_name, _)| existing_name != name);
+                properties.push((name.clone(), value.clone()));
+            }
+        }
+
+        properties.sort_by(|a, b| a.0.cmp(&b.0));
+        properties
+    }
+
+    /// Gets the value of an edge property as it will be once the writes are
+    /// applied, given its committed value, if any.
+    pub(crate) fn edge_property(&self, key: &EdgeKey, name: &str, committed: Option<JsonValue>) -> Option<JsonValue> {
+        let staged = self
+            .edge_properties
+            .iter()
+            .rev()
+            .find(|(staged_key, staged_name, _)| staged_key == key && staged_name == name);
+
+        match staged {
+            Some((_, _, value)) => Some(value.clone()),
+            None if self.deletes_edge_property(key, name) => None,
+            None => committed,
+        }
+    }
+
+    fn deletes_edge_property(&self, key: &EdgeKey, name: &str) -> bool {
+        self.deletes_edge(key)
+            || self
+                .deleted_edge_properties
+                .iter()
+                .any(|(deleted_key, deleted_name)| deleted_key == key && deleted_name == name)
+    }
+
+    /// Gets the properties of an edge as they will be once the writes are
+    /// applied, given its committed properties, in name order.
+    pub(crate) fn edge_properties_of(
+        &self,
+        key: &EdgeKey,
+        committed: Vec<(String, JsonValue)>,
+    ) -> Vec<(String, JsonValue)> {
+        let mut properties: Vec<(String, JsonValue)> = committed
+            .into_iter()
+            .filter(|(name, _)| !self.deletes_edge_property(key, name))
+            .collect();
+
+        for (staged_key, name, value) in &self.edge_properties {
+            if staged_key == key {
+                properties.retain(|(existing_name, _)| existing_name != name);
+                properties.push((name.clone(), value.clone()));
+            }
+        }
+
+        properties.sort_by(|a, b| a.0.cmp(&b.0));
+        properties
+    }
+
+    /// Stages the deletion of a vertex, along with its edges and the
+    /// properties of both, dropping any staged writes to them. `committed`
+    /// is whether the vertex exists outside of the staged writes.
+    pub(crate) fn delete_vertex(&mut self, id: Uuid, committed: bool) {
+        let touches = |key: &EdgeKey| key.outbound_id == id || key.inbound_id == id;
+        self.vertices.retain(|vertex| vertex.id != id);
+        self.edges.retain(|key| !touches(key));
+        self.edge_datetimes.retain(|key, _| !touches(key));
+        self.vertex_properties.retain(|&(staged_id, _, _)| staged_id != id);
+        self.edge_properties.retain(|(key, _, _)| !touches(key));
+
+        if committed && !self.deleted_vertices.contains(&id) {
+            self.deleted_vertices.push(id);
+        }
+    }
+
+    /// Stages the deletion of an edge and its properties, dropping any
+    /// staged writes to them. `committed` is whether the edge exists outside
+    /// of the staged writes.
+    pub(crate) fn delete_edge(&mut self, key: &EdgeKey, committed: bool) {
+        self.edges.retain(|staged_key| staged_key != key);
+        self.edge_datetimes.remove(key);
+        self.edge_properties.retain(|(staged_key, _, _)| staged_key != key);
+
+        if committed && !self.deleted_edges.contains(key) {
+            self.deleted_edges.push(key.clone());
+        }
+    }
+
+    /// Stages the deletion of a vertex property, dropping any staged values
+    /// of it.
+    pub(crate) fn delete_vertex_property(&mut self, id: Uuid, name: &str) {
+        self.vertex_properties
+            .retain(|&(staged_id, ref staged_name, _)| staged_id != id || staged_name != name);
+
+        if !self.deletes_vertex_property(id, name) {
+            self.deleted_vertex_properties.push((id, name.to_string()));
+        }
+    }
+
+    /// Stages the deletion of an edge property, dropping any staged values
+    /// of it.
+    pub(crate) fn delete_edge_property(&mut self, key: &EdgeKey, name: &str) {
+        self.edge_properties
+            .retain(|(staged_key, staged_name, _)| staged_key != key || staged_name != name);
+
+        if !self.deletes_edge_property(key, name) {
+            self.deleted_edge_properties.push((key.clone(), name.to_string()));
+        }
+    }
+
+    /// Runs all of the writes past the datastore's write validators and
+    /// property limits, so that nothing is written if any of them is
+    /// rejected. Writes are validated as they were added, before
+    /// duplicates and writes whose vertices or edges don't exist are
+    /// dropped.
+    fn validate(&self, holder: &SledHolder) -> Result<()> {
+        for key in &self.deleted_edges {
+            validate::check(holder, &Mutation::DeleteEdge(key))?;
+        }
+
+        for &(id, ref name) in &self.deleted_vertex_properties {
+            validate::check(holder, &Mutation::DeleteVertexProperty { id, name })?;
+        }
+
+        for (key, name) in &self.deleted_edge_properties {
+            validate::check(holder, &Mutation::DeleteEdgeProperty { key, name })?;
+        }
+
+        for vertex in &self.vertices {
+            validate::check(holder, &Mutation::CreateVertex(vertex))?;
+        }
+
+        for key in &self.edges {
+            validate::check(holder, &Mutation::CreateEdge(key))?;
+        }
+
+        // Properties new to their owner count towards the limit on the
+        // number of properties for those that come after them.
+        let mut new_vertex_properties: HashMap<Uuid, HashSet<&str>> = HashMap::new();
+        for &(id, ref name, ref value) in &self.vertex_properties {
+            validate::check(holder, &Mutation::SetVertexProperty { id, name, value })?;
+            let names = new_vertex_properties.entry(id).or_default();
+            let pending = names.iter().filter(|&&pending_name| pending_name != name).count() as u64;
+
+            if limits::check_vertex_property(holder, id, name, value, pending)? {
+                names.insert(name);
+            }
+        }
+
+        let mut new_edge_properties: HashMap<&EdgeKey, HashSet<&str>> = HashMap::new();
+        for (key, name, value) in &self.edge_properties {
+            validate::check(holder, &Mutation::SetEdgeProperty { key, name, value })?;
+            let names = new_edge_properties.entry(key).or_default();
+            let pending = names.iter().filter(|&&pending_name| pending_name != name).count() as u64;
+
+            if limits::check_edge_property(holder, key, name, value, pending)? {
+                names.insert(name);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Applies the writes, with one sled transaction per kind of item, and
+    /// records them in the audit log as `operation`. Vertices are written
+    /// first, then edges, then vertex properties, then edge properties, so
+    /// each can depend on what came before it.
+    pub(crate) fn apply(self, trans: &SledTransaction, operation: &str) -> Result<()> {
+        self.apply_with(trans, operation, MultiBatch::default())
+    }
+
+    /// Like `apply`, with the writes in `tail` applied in the same sled
+    /// transaction as the last kind of item written, or on their own if
+    /// nothing else is.
+    pub(crate) fn apply_with(self, trans: &SledTransaction, operation: &str, tail: MultiBatch) -> Result<()> {
+        let guard = trans.holder.write_guard();
+
+        if let Some(ids) = self.touched_vertices() {
+            guard.touch(&ids);
+        }
+
+        self.apply_locked(trans, operation, tail)
+    }
+
+    /// Like `apply_with`, for callers that already hold a write guard.
+    pub(crate) fn apply_locked(self, trans: &SledTransaction, operation: &str, tail: MultiBatch) -> Result<()> {
+        let holder = &trans.holder;
+        let _in_flight = holder.pending_writes.hold(self.approximate_size());
+        self.validate(holder)?;
+        let vertex_manager = VertexManager::new(holder);
+        let edge_manager = EdgeManager::new(holder);
+
+        // Deletes go first, so that what's staged after them can recreate
+        // what they delete.
+        for key in &self.deleted_edges {
+            if let Some(update_datetime) = edge_manager.get(key.outbound_id, &key.t, key.inbound_id)? {
+                edge_manager.delete(key.outbound_id, &key.t, key.inbound_id, update_datetime)?;
+            }
+        }
+
+        if !self.deleted_vertices.is_empty() {
+            let q = SpecificVertexQuery::new(self.deleted_vertices.clone()).into();
+            trans.delete_vertices_locked(&q, None)?;
+        }
+
+        let vertex_property_manager = VertexPropertyManager::new(holder);
+        for &(id, ref name) in &self.deleted_vertex_properties {
+            vertex_property_manager.delete(id, name)?;
+        }
+
+        let edge_property_manager = EdgePropertyManager::new(holder);
+        for (key, name) in &self.deleted_edge_properties {
+            edge_property_manager.delete(key.outbound_id, &key.t, key.inbound_id, name)?;
+        }
+
+        let deletes = self.deleted_vertices.len()
+            + self.deleted_edges.len()
+            + self.deleted_vertex_properties.len()
+            + self.deleted_edge_properties.len();
+
+        // Everything to write is worked out before anything is written, so
+        // that `tail` can go with the last of it. Vertices and edges being
+        // created count as existing.
+        let mut seen_vertices = HashSet::new();
+        let mut vertices = Vec::new();
+        let mut vertex_exists: HashMap<Uuid, bool> = HashMap::new();
+
+        for vertex in self.vertices {
+            if seen_vertices.insert(vertex.id) && !vertex_manager.exists(vertex.id)? {
+                vertex_exists.insert(vertex.id, true);
+                vertices.push(vertex);
+            }
+        }
+
+        let mut check_vertex = |id: Uuid| -> Result<bool> {
+            if let Some(exists) = vertex_exists.get(&id) {
+                return Ok(*exists);
+            }
+
+            let exists = vertex_manager.exists(id)?;
+            vertex_exists.insert(id, exists);
+            Ok(exists)
+        };
+
+        let mut seen_edges = HashSet::new();
+        let mut edges = Vec::new();
+
+        for key in self.edges {
+            let key = holder.edge_range_layout.canonical_key(&key);
+
+            if !seen_edges.contains(&key) && check_vertex(key.outbound_id)? && check_vertex(key.inbound_id)? {
+                seen_edges.insert(key.clone());
+                edges.push(key);
+            }
+        }
+
+        let mut vertex_properties = Vec::new();
+        let mut vertex_property_indexes: HashMap<(Uuid, String), usize> = HashMap::new();
+
+        for (id, name, value) in self.vertex_properties {
+            if !check_vertex(id)? {
+                continue;
+            }
+
+            match vertex_property_indexes.get(&(id, name.clone())) {
+                Some(&i) => vertex_properties[i] = (id, name, value),
+                None => {
+                    vertex_property_indexes.insert((id, name.clone()), vertex_properties.len());
+                    vertex_properties.push((id, name, value));
+                }
+            }
+        }
+
+        let mut edge_exists: HashMap<EdgeKey, bool> = HashMap::new();
+        let mut edge_properties = Vec::new();
+        let mut edge_property_indexes: HashMap<(EdgeKey, String), usize> = HashMap::new();
+
+        for (key, name, value) in self.edge_properties {
+            let key = holder.edge_range_layout.canonical_key(&key);
+            let exists = match edge_exists.get(&key) {
+                Some(exists) => *exists,
+                None => {
+                    let exists = seen_edges.contains(&key)
+                        || edge_manager.get(key.outbound_id, &key.t, key.inbound_id)?.is_some();
+                    edge_exists.insert(key.clone(), exists);
+                    exists
+                }
+            };
+
+            if !exists {
+                continue;
+            }
+
+            let index_key = (key.clone(), name.clone());
+            match edge_property_indexes.get(&index_key) {
+                Some(&i) => edge_properties[i] = (key, name, value),
+                None => {
+                    edge_property_indexes.insert(index_key, edge_properties.len());
+                    edge_properties.push((key, name, value));
+                }
+            }
+        }
+
+        let mut tail = Some(tail);
+
+        if !vertices.is_empty() {
+            let last = edges.is_empty() && vertex_properties.is_empty() && edge_properties.is_empty();
+            let batch = if last {
+                tail.take().unwrap()
+            } else {
+                MultiBatch::default()
+            };
+            vertex_manager.create_many(&vertices, batch)?;
+        }
+
+        if !edges.is_empty() {
+            let last = vertex_properties.is_empty() && edge_properties.is_empty();
+            let batch = if last {
+                tail.take().unwrap()
+            } else {
+                MultiBatch::default()
+            };
+            edge_manager.set_many_with(&edges, Utc::now(), batch)?;
+        }
+
+        if !vertex_properties.is_empty() {
+            let batch = if edge_properties.is_empty() {
+                tail.take().unwrap()
+            } else {
+                MultiBatch::default()
+            };
+            vertex_property_manager.set_many_with(&vertex_properties, batch)?;
+        }
+
+        if !edge_properties.is_empty() {
+            edge_property_manager.set_many_with(&edge_properties, tail.take().unwrap())?;
+        }
+
+        if let Some(tail) = tail {
+            tail.apply(holder)?;
+        }
+
+        trans.audit(
+            operation,
+            format_args!(
+                "{} vertices, {} edges, {} vertex properties, {} edge properties, {} deletes",
+                vertices.len(),
+                edges.len(),
+                vertex_properties.len(),
+                edge_properties.len(),
+                deletes
+            ),
+        )
+    }
+}
@@ -0,0 +1,214 @@
+use std::collections::{HashMap, VecDeque};
+use std::mem::size_of;
+use std::sync::{Arc, Mutex};
+
+use indradb::{Edge, EdgeProperties, EdgeProperty, NamedProperty, Vertex, VertexProperties, VertexProperty};
+use serde_json::Value as JsonValue;
+
+/// A cached query result.
+#[derive(Clone)]
+pub(crate) enum CachedResult {
+    Vertices(Vec<Vertex>),
+    Edges(Vec<Edge>),
+    VertexProperties(Vec<VertexProperty>),
+    AllVertexProperties(Vec<VertexProperties>),
+    EdgeProperties(Vec<EdgeProperty>),
+    AllEdgeProperties(Vec<EdgeProperties>),
+}
+
+/// A query result type that can be stored in the result cache.
+pub(crate) trait Cacheable: Clone {
+    fn into_cached(self) -> CachedResult;
+    fn from_cached(cached: CachedResult) -> Option<Self>;
+}
+
+macro_rules! impl_cacheable {
+    ($t:ty, $variant:ident) => {
+        impl Cacheable for $t {
+            fn into_cached(self) -> CachedResult {
+                CachedResult::$variant(self)
+            }
+
+            fn from_cached(cached: CachedResult) -> Option<Self> {
+                match cached {
+                    CachedResult::$variant(value) => Some(value),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+impl_cacheable!(Vec<Vertex>, Vertices);
+impl_cacheable!(Vec<Edge>, Edges);
+impl_cacheable!(Vec<VertexProperty>, VertexProperties);
+impl_cacheable!(Vec<VertexProperties>, AllVertexProperties);
+impl_cacheable!(Vec<EdgeProperty>, EdgeProperties);
+impl_cacheable!(Vec<EdgeProperties>, AllEdgeProperties);
+
+pub(crate) fn json_size(value: &JsonValue) -> usize {
+    size_of::<JsonValue>()
+        + match *value {
+            JsonValue::String(ref s) => s.len(),
+            JsonValue::Array(ref values) => values.iter().map(json_size).sum(),
+            JsonValue::Object(ref map) => map.iter().map(|(k, v)| k.len() + json_size(v)).sum(),
+            _ => 0,
+        }
+}
+
+fn named_properties_size(props: &[NamedProperty]) -> usize {
+    props
+        .iter()
+        .map(|prop| size_of::<NamedProperty>() + prop.name.len() + json_size(&prop.value))
+        .sum()
+}
+
+impl CachedResult {
+    /// Approximates the heap memory used by the result.
+    fn approximate_size(&self) -> usize {
+        match *self {
+            CachedResult::Vertices(ref vertices) => vertices.iter().map(|v| size_of::<Vertex>() + v.t.0.len()).sum(),
+            CachedResult::Edges(ref edges) => edges.iter().map(|e| size_of::<Edge>() + e.key.t.0.len()).sum(),
+            CachedResult::VertexProperties(ref props) => props
+                .iter()
+                .map(|p| size_of::<VertexProperty>() + json_size(&p.value))
+                .sum(),
+            CachedResult::AllVertexProperties(ref props) => props
+                .iter()
+                .map(|p| size_of::<VertexProperties>() + p.vertex.t.0.len() + named_properties_size(&p.props))
+                .sum(),
+            CachedResult::EdgeProperties(ref props) => props
+                .iter()
+                .map(|p| size_of::<EdgeProperty>() + p.key.t.0.len() + json_size(&p.value))
+                .sum(),
+            CachedResult::AllEdgeProperties(ref props) => props
+                .iter()
+                .map(|p| size_of::<EdgeProperties>() + p.edge.key.t.0.len() + named_properties_size(&p.props))
+                .sum(),
+        }
+    }
+}
+
+struct CacheState {
+    // Bumped on every mutation, so that results computed concurrently with
+    // a mutation are never cached.
+    generation: u64,
+    // Each result is stored with its approximate size.
+    entries: HashMap<String, (CachedResult, usize)>,
+    // Keys in insertion order, for evicting the oldest entries first.
+    order: VecDeque<String>,
+    // The approximate total size of all entries, including their keys.
+    bytes: usize,
+}
+
+/// An in-memory cache of whole query results, keyed by the shape of the
+/// query. Any mutation invalidates the entire cache.
+pub(crate) struct ResultCache {
+    capacity: usize,
+    state: Mutex<CacheState>,
+}
+
+impl ResultCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        ResultCache {
+            capacity,
+            state: Mutex::new(CacheState {
+                generation: 0,
+                entries: HashMap::new(),
+                order: VecDeque::new(),
+                bytes: 0,
+            }),
+        }
+    }
+
+    pub(crate) fn generation(&self) -> u64 {
+        self.state.lock().unwrap().generation
+    }
+
+    pub(crate) fn get<T: Cacheable>(&self, key: &str) -> Option<T> {
+        let state = self.state.lock().unwrap();
+        state
+            .entries
+            .get(key)
+            .and_then(|(result, _)| T::from_cached(result.clone()))
+    }
+
+    /// Caches a result, unless there's been a mutation since `generation`.
+    pub(crate) fn insert<T: Cacheable>(&self, key: String, generation: u64, value: T) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+
+        if state.generation != generation || state.entries.contains_key(&key) {
+            return;
+        }
+
+        while state.entries.len() >= self.capacity {
+            match state.order.pop_front() {
+                Some(oldest) => {
+                    if let Some((_, size)) = state.entries.remove(&oldest) {
+                        state.bytes -= size;
+                    }
+                }
+                None => break,
+            }
+        }
+
+        let result = value.into_cached();
+        let size = 2 * key.len() + result.approximate_size();
+        state.bytes += size;
+        state.order.push_back(key.clone());
+        state.entries.insert(key, (result, size));
+    }
+
+    /// The number of cached results, and their approximate size in bytes.
+    pub(crate) fn usage(&self) -> (usize, usize) {
+        let state = self.state.lock().unwrap();
+        (state.entries.len(), state.bytes)
+    }
+
+    /// Drops all cached results, and releases the memory held by the cache's
+    /// own bookkeeping.
+    pub(crate) fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.entries = HashMap::new();
+        state.order = VecDeque::new();
+        state.bytes = 0;
+    }
+
+    pub(crate) fn invalidate(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.generation += 1;
+        state.entries.clear();
+        state.order.clear();
+        state.bytes = 0;
+    }
+}
+
+/// The result caches of a sled database, one per partition, shared by all
+/// the handles on it so that a write through any handle invalidates the
+/// results that the others have cached.
+#[derive(Default)]
+pub(crate) struct ResultCaches {
+    by_partition: Mutex<HashMap<Option<u32>, Arc<ResultCache>>>,
+}
+
+impl ResultCaches {
+    /// Gets the cache of a partition, creating it on first use.
+    pub(crate) fn get(&self, partition: Option<u32>, capacity: usize) -> Arc<ResultCache> {
+        let mut by_partition = self.by_partition.lock().unwrap();
+        by_partition
+            .entry(partition)
+            .or_insert_with(|| Arc::new(ResultCache::new(capacity)))
+            .clone()
+    }
+
+    /// Invalidates the cache of a partition, if it has one.
+    pub(crate) fn invalidate(&self, partition: Option<u32>) {
+        if let Some(result_cache) = self.by_partition.lock().unwrap().get(&partition) {
+            result_cache.invalidate();
+        }
+    }
+}
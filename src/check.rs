@@ -0,0 +1,117 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::datastore::SledHolder;
+use super::decode::Decoder;
+use super::managers::{EdgeRangeManager, VertexManager};
+use super::rebuild::for_each_parallel;
+
+use chrono::offset::Utc;
+use chrono::DateTime;
+use indradb::{Result, Type};
+use uuid::Uuid;
+
+/// The findings of a quick consistency check, which verifies that every
+/// edge and vertex property is reachable through the indexes and points at
+/// vertices that exist. Property values are not decoded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ConsistencySummary {
+    /// The number of edges checked.
+    pub edges_checked: u64,
+    /// The number of vertex properties checked.
+    pub vertex_properties_checked: u64,
+    /// Edges whose outbound or inbound vertex doesn't exist.
+    pub dangling_edges: u64,
+    /// Edges missing from the edge ranges or, unless indexing is deferred,
+    /// the reversed edge ranges.
+    pub missing_edge_ranges: u64,
+    /// Vertex properties whose vertex doesn't exist.
+    pub orphaned_vertex_properties: u64,
+    /// Records that could not be decoded.
+    pub corrupt_records: u64,
+}
+
+impl ConsistencySummary {
+    /// Whether no problems were found.
+    pub fn is_consistent(&self) -> bool {
+        self.dangling_edges == 0
+            && self.missing_edge_ranges == 0
+            && self.orphaned_vertex_properties == 0
+            && self.corrupt_records == 0
+    }
+}
+
+fn read_edge(holder: &SledHolder, k: &[u8], v: &[u8]) -> Result<(Uuid, Type, Uuid, DateTime<Utc>)> {
+    let mut decoder = Decoder::key(&holder.edges, k);
+    let outbound_id = decoder.read_uuid()?;
+    let t = decoder.read_type()?;
+    let inbound_id = decoder.read_uuid()?;
+    let update_datetime = Decoder::value(&holder.edges, k, v).read_datetime()?;
+    Ok((outbound_id, t, inbound_id, update_datetime))
+}
+
+/// Runs a quick consistency check over the edges and vertex properties.
+pub(crate) fn quick_check(holder: &SledHolder) -> Result<ConsistencySummary> {
+    let vertex_manager = VertexManager::new(holder);
+    let edge_range_manager = EdgeRangeManager::new(holder);
+    let reversed_edge_range_manager = EdgeRangeManager::new_reversed(holder);
+    let check_reversed = !holder.is_indexing_deferred();
+
+    let edges_checked = AtomicU64::new(0);
+    let vertex_properties_checked = AtomicU64::new(0);
+    let dangling_edges = AtomicU64::new(0);
+    let missing_edge_ranges = AtomicU64::new(0);
+    let orphaned_vertex_properties = AtomicU64::new(0);
+    let corrupt_records = AtomicU64::new(0);
+
+    for_each_parallel(&holder.edges, |k, v| {
+        edges_checked.fetch_add(1, Ordering::Relaxed);
+
+        let (outbound_id, t, inbound_id, update_datetime) = match read_edge(holder, k, v) {
+            Ok(decoded) => decoded,
+            Err(_) => {
+                corrupt_records.fetch_add(1, Ordering::Relaxed);
+                return Ok(());
+            }
+        };
+
+        if !vertex_manager.exists(outbound_id)? || !vertex_manager.exists(inbound_id)? {
+            dangling_edges.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let has_ranges = edge_range_manager.contains(outbound_id, &t, update_datetime, inbound_id)?
+            && (!check_reversed
+                || reversed_edge_range_manager.contains(inbound_id, &t, update_datetime, outbound_id)?);
+
+        if !has_ranges {
+            missing_edge_ranges.fetch_add(1, Ordering::Relaxed);
+        }
+
+        Ok(())
+    })?;
+
+    for_each_parallel(&holder.vertex_properties, |k, _| {
+        vertex_properties_checked.fetch_add(1, Ordering::Relaxed);
+
+        match Decoder::key(&holder.vertex_properties, k).read_uuid() {
+            Ok(id) => {
+                if !vertex_manager.exists(id)? {
+                    orphaned_vertex_properties.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            Err(_) => {
+                corrupt_records.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        Ok(())
+    })?;
+
+    Ok(ConsistencySummary {
+        edges_checked: edges_checked.into_inner(),
+        vertex_properties_checked: vertex_properties_checked.into_inner(),
+        dangling_edges: dangling_edges.into_inner(),
+        missing_edge_ranges: missing_edge_ranges.into_inner(),
+        orphaned_vertex_properties: orphaned_vertex_properties.into_inner(),
+        corrupt_records: corrupt_records.into_inner(),
+    })
+}
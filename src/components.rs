@@ -0,0 +1,120 @@
+use super::datastore::SledHolder;
+use super::decode::Decoder;
+use super::errors::map_err;
+use super::managers::VertexPropertyManager;
+
+use indradb::{Result, Type};
+use serde_json::Value as JsonValue;
+use sled::Tree;
+use uuid::Uuid;
+
+/// The number of component IDs written per batch. Writes by other callers
+/// are only held up for the duration of a batch.
+const WRITE_BATCH_SIZE: usize = 1000;
+
+/// The name of the tree the union-find forest is kept in while components
+/// are computed, so that it doesn't have to fit in memory. It's dropped
+/// once they're written.
+const SCRATCH_TREE_NAME: &str = "connected_components";
+
+fn parent(scratch: &Tree, id: Uuid) -> Result<Uuid> {
+    match map_err(scratch.get(id.as_bytes()))? {
+        Some(parent) => Ok(Decoder::value(scratch, id.as_bytes(), &parent).read_uuid()?),
+        None => Ok(id),
+    }
+}
+
+/// Finds the root of a vertex's tree in the forest, halving the path to it
+/// along the way, so that later lookups take fewer reads.
+fn find(scratch: &Tree, mut id: Uuid) -> Result<Uuid> {
+    loop {
+        let parent_id = parent(scratch, id)?;
+
+        if parent_id == id {
+            return Ok(id);
+        }
+
+        let grandparent_id = parent(scratch, parent_id)?;
+
+        if grandparent_id != parent_id {
+            map_err(scratch.insert(id.as_bytes(), grandparent_id.as_bytes()))?;
+        }
+
+        id = grandparent_id;
+    }
+}
+
+/// Merges the trees of two vertices. The larger root is linked to the
+/// smaller one, so that each component's root is its lowest vertex ID.
+fn union(scratch: &Tree, first_id: Uuid, second_id: Uuid) -> Result<()> {
+    let first_root = find(scratch, first_id)?;
+    let second_root = find(scratch, second_id)?;
+
+    if first_root < second_root {
+        map_err(scratch.insert(second_root.as_bytes(), first_root.as_bytes()))?;
+    } else if second_root < first_root {
+        map_err(scratch.insert(first_root.as_bytes(), second_root.as_bytes()))?;
+    }
+
+    Ok(())
+}
+
+fn write_batch(holder: &SledHolder, items: &mut Vec<(Uuid, String, JsonValue)>) -> Result<()> {
+    let _guard = holder.write_guard();
+    VertexPropertyManager::new(holder).set_many(items)?;
+    items.clear();
+    Ok(())
+}
+
+/// Computes the connected components of the graph, ignoring edge
+/// directions, and sets the property `name` of each vertex to the ID of
+/// its component. Returns the number of components.
+///
+/// This makes one pass over the edges, merging the components of their
+/// vertices in a union-find forest kept in a scratch tree, then one pass
+/// over the vertices, writing their components.
+pub(crate) fn tag(holder: &SledHolder, name: &str, t: Option<&Type>) -> Result<u64> {
+    let scratch_name = holder.tree_name(SCRATCH_TREE_NAME);
+    let scratch = map_err(holder.db.open_tree(&scratch_name))?;
+
+    // Leftovers of an interrupted run would merge unrelated components.
+    map_err(scratch.clear())?;
+
+    for item in holder.edges.iter().keys() {
+        let k = map_err(item)?;
+        let mut decoder = Decoder::key(&holder.edges, &k);
+        let outbound_id = decoder.read_uuid()?;
+        let edge_t = decoder.read_type()?;
+        let inbound_id = decoder.read_uuid()?;
+
+        if t.is_none_or(|t| *t == edge_t) {
+            union(&scratch, outbound_id, inbound_id)?;
+        }
+    }
+
+    let mut components = 0;
+    let mut items = Vec::with_capacity(WRITE_BATCH_SIZE);
+
+    for item in holder.vertices.iter().keys() {
+        let k = map_err(item)?;
+        let id = Decoder::key(&holder.vertices, &k).read_uuid()?;
+        let component_id = find(&scratch, id)?;
+
+        if component_id == id {
+            components += 1;
+        }
+
+        items.push((id, name.to_string(), JsonValue::String(component_id.to_string())));
+
+        if items.len() == WRITE_BATCH_SIZE {
+            write_batch(holder, &mut items)?;
+        }
+    }
+
+    if !items.is_empty() {
+        write_batch(holder, &mut items)?;
+    }
+
+    map_err(holder.db.drop_tree(&scratch_name))?;
+    Ok(components)
+}
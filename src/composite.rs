@@ -0,0 +1,242 @@
+use super::datastore::SledHolder;
+use super::deadline::Deadline;
+use super::decode::Decoder;
+use super::errors::{map_err, Error};
+use super::layout::escape;
+use super::managers::{VertexManager, VertexPropertyManager};
+
+use indradb::{Result, Vertex};
+use serde_json::Value as JsonValue;
+use sled::{Batch, Tree};
+use uuid::Uuid;
+
+/// The tag of entries, keyed by `(value..., vertex ID)`.
+const ENTRY_TAG: u8 = 0;
+
+/// The tag of the records of which entry each vertex has, keyed by vertex
+/// ID, so that it can be removed once the values it was built from have
+/// changed.
+const OWNER_TAG: u8 = 1;
+
+/// An index over several vertex properties at once, and the tree it's
+/// stored in. A vertex has an entry if it has every one of the properties,
+/// keyed by their values in order, so that lookups by all of them, or by
+/// the first few, are a single prefix scan. Registered with
+/// `SledConfig::with_composite_index`.
+pub(crate) struct CompositeIndex {
+    pub(crate) name: String,
+    pub(crate) properties: Vec<String>,
+    pub(crate) tree: Tree,
+}
+
+impl CompositeIndex {
+    /// The definition recorded in the metadata tree, so that changing it
+    /// rebuilds the index on the next open.
+    fn definition(&self) -> Vec<u8> {
+        serde_json::to_vec(&self.properties).unwrap()
+    }
+}
+
+/// Builds the prefix of the entries whose first properties have `values`.
+fn entry_prefix(values: &[JsonValue]) -> Result<Vec<u8>> {
+    let mut prefix = vec![ENTRY_TAG];
+
+    for value in values {
+        escape(&serde_json::to_vec(value)?, &mut prefix);
+    }
+
+    Ok(prefix)
+}
+
+fn owner_key(id: Uuid) -> Vec<u8> {
+    let mut key = Vec::with_capacity(17);
+    key.push(OWNER_TAG);
+    key.extend_from_slice(id.as_bytes());
+    key
+}
+
+/// Reads a vertex's values of an index's properties, or returns `None` if
+/// it's missing one.
+fn values(holder: &SledHolder, index: &CompositeIndex, id: Uuid) -> Result<Option<Vec<JsonValue>>> {
+    let vertex_property_manager = VertexPropertyManager::new(holder);
+    let mut values = Vec::with_capacity(index.properties.len());
+
+    for name in &index.properties {
+        match vertex_property_manager.get(id, name)? {
+            Some(value) => values.push(value),
+            None => return Ok(None),
+        }
+    }
+
+    Ok(Some(values))
+}
+
+/// Replaces a vertex's entry with one built from its current values.
+fn refresh(holder: &SledHolder, index: &CompositeIndex, id: Uuid) -> Result<()> {
+    let owner_key = owner_key(id);
+    let old_key = holder.retrier.run(|| index.tree.get(&owner_key))?;
+
+    let new_key = match values(holder, index, id)? {
+        Some(values) => {
+            let mut key = entry_prefix(&values)?;
+            key.extend_from_slice(id.as_bytes());
+            Some(key)
+        }
+        None => None,
+    };
+
+    if old_key.as_ref().map(|old_key| &old_key[..]) == new_key.as_ref().map(|new_key| &new_key[..]) {
+        return Ok(());
+    }
+
+    let mut batch = Batch::default();
+
+    if let Some(old_key) = old_key {
+        batch.remove(old_key);
+    }
+
+    match new_key {
+        Some(new_key) => {
+            batch.insert(new_key.as_slice(), &[]);
+            batch.insert(owner_key, new_key);
+        }
+        None => batch.remove(owner_key),
+    }
+
+    holder.retrier.run(|| index.tree.apply_batch(batch.clone()))?;
+    Ok(())
+}
+
+/// Updates the indexes over a vertex property after it was written. This
+/// has to be called once the write is applied, since the entry is built
+/// from the vertex's current values.
+pub(crate) fn on_property_change(holder: &SledHolder, id: Uuid, name: &str) -> Result<()> {
+    for index in &holder.composite_indexes {
+        if index.properties.iter().any(|property| property == name) {
+            refresh(holder, index, id)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes every entry of the indexes, so that they can be rebuilt with
+/// `rebuild_entries`.
+pub(crate) fn clear(holder: &SledHolder) -> Result<()> {
+    for index in &holder.composite_indexes {
+        map_err(index.tree.clear())?;
+    }
+
+    Ok(())
+}
+
+/// Adds a vertex's entries to the indexes while they're rebuilt, given
+/// the name of one of its properties. Entries are only added for the
+/// indexes whose first property is `name`, since a vertex has an entry
+/// only if it has that property, so calling this for each of a vertex's
+/// properties, in any order, adds each entry once.
+pub(crate) fn rebuild_entries(holder: &SledHolder, id: Uuid, name: &str) -> Result<()> {
+    for index in &holder.composite_indexes {
+        if index.properties.first().map(|first| &first[..]) == Some(name) {
+            refresh(holder, index, id)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn definition_key(holder: &SledHolder, index: &CompositeIndex) -> Vec<u8> {
+    holder.metadata_key(&format!("composite_index:{}", index.name))
+}
+
+/// Rebuilds an index from the vertex properties.
+fn rebuild(holder: &SledHolder, index: &CompositeIndex) -> Result<()> {
+    map_err(index.tree.clear())?;
+    let mut last_id = None;
+
+    // Properties are keyed by vertex ID first, so each vertex is refreshed
+    // once, when its first property is read.
+    for item in holder.vertex_properties.iter().keys() {
+        let k = map_err(item)?;
+        let id = Decoder::key(&holder.vertex_properties, &k).read_uuid()?;
+
+        if last_id != Some(id) {
+            refresh(holder, index, id)?;
+            last_id = Some(id);
+        }
+    }
+
+    map_err(
+        holder
+            .metadata
+            .insert(definition_key(holder, index), index.definition()),
+    )?;
+    Ok(())
+}
+
+/// Rebuilds the indexes that are new, or whose definition has changed,
+/// since the datastore was last opened.
+pub(crate) fn rebuild_stale(holder: &SledHolder) -> Result<()> {
+    for index in &holder.composite_indexes {
+        let recorded = map_err(holder.metadata.get(definition_key(holder, index)))?;
+
+        if recorded.as_ref().map(|recorded| &recorded[..]) != Some(&index.definition()[..]) {
+            rebuild(holder, index)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Gets the vertices whose first properties of the index `name` have
+/// `values`, ordered by the values of the rest of the properties, then
+/// by ID.
+pub(crate) fn lookup(
+    holder: &SledHolder,
+    name: &str,
+    values: &[JsonValue],
+    deadline: &Deadline,
+) -> Result<Vec<Vertex>> {
+    let index = match holder.composite_indexes.iter().find(|index| index.name == name) {
+        Some(index) => index,
+        None => return Err(Error::UnknownCompositeIndex { name: name.to_string() }.into()),
+    };
+
+    if values.len() > index.properties.len() {
+        return Err(Error::TooManyCompositeValues {
+            name: name.to_string(),
+            properties: index.properties.len(),
+        }
+        .into());
+    }
+
+    let vertex_manager = VertexManager::new(holder);
+    let mut vertices = Vec::new();
+
+    for item in index.tree.scan_prefix(entry_prefix(values)?).keys() {
+        let k = map_err(item)?;
+        deadline.tick()?;
+
+        if k.len() < 16 {
+            return Err(Decoder::key(&index.tree, &k).corruption());
+        }
+
+        let mut decoder = Decoder::key(&index.tree, &k);
+        decoder.skip(k.len() - 16)?;
+        let id = decoder.read_uuid()?;
+
+        // Concurrent writes to the same vertex can briefly leave an entry
+        // for values that were replaced, so matches are checked against
+        // the properties themselves.
+        match self::values(holder, index, id)? {
+            Some(ref current) if current[..values.len()] == *values => {}
+            _ => continue,
+        }
+
+        if let Some(t) = vertex_manager.get(id)? {
+            vertices.push(Vertex::with_id(id, t));
+        }
+    }
+
+    Ok(vertices)
+}
@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use uuid::Uuid;
+
+/// The number of counters that vertices are spread across. Vertices that
+/// share a counter look like they conflict, which only costs a retry.
+const STRIPES: u64 = 1024;
+
+fn stripe(id: Uuid) -> usize {
+    let id = id.as_u128();
+    ((id as u64 ^ (id >> 64) as u64) % STRIPES) as usize
+}
+
+/// Counts the writes made to a datastore, so that `SledDatastore::execute`
+/// can tell whether anything it read was written to while it ran. Writes
+/// that say which vertices they touch only count against those vertices;
+/// other writes count against everything.
+pub(crate) struct WriteVersions {
+    all: AtomicU64,
+    untracked: AtomicU64,
+    stripes: Vec<AtomicU64>,
+}
+
+impl Default for WriteVersions {
+    fn default() -> Self {
+        WriteVersions {
+            all: AtomicU64::new(0),
+            untracked: AtomicU64::new(0),
+            stripes: (0..STRIPES).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+}
+
+impl WriteVersions {
+    /// Counts a write that was made to `touched`, or to anything if that's
+    /// `None`. This must be called before the write guard is released.
+    pub(crate) fn record_write(&self, touched: Option<&[Uuid]>) {
+        match touched {
+            Some(ids) => {
+                for &id in ids {
+                    self.stripes[stripe(id)].fetch_add(1, Ordering::AcqRel);
+                }
+            }
+            None => {
+                self.untracked.fetch_add(1, Ordering::AcqRel);
+            }
+        }
+
+        self.all.fetch_add(1, Ordering::AcqRel);
+    }
+}
+
+#[derive(Default)]
+struct Versions {
+    all: Option<u64>,
+    untracked: Option<u64>,
+    stripes: HashMap<usize, u64>,
+}
+
+/// What a transaction run by `SledDatastore::execute` has read, as the
+/// versions it was read at.
+#[derive(Default)]
+pub(crate) struct ReadSet {
+    versions: Mutex<Versions>,
+}
+
+impl ReadSet {
+    /// Records a read of `ids`, or of anything if that's `None`, e.g. for a
+    /// range query. This must be called before the read is made.
+    pub(crate) fn record(&self, write_versions: &WriteVersions, ids: Option<&[Uuid]>) {
+        let mut versions = self.versions.lock().unwrap();
+
+        match ids {
+            Some(ids) => {
+                versions
+                    .untracked
+                    .get_or_insert_with(|| write_versions.untracked.load(Ordering::Acquire));
+
+                for &id in ids {
+                    let stripe = stripe(id);
+                    versions
+                        .stripes
+                        .entry(stripe)
+                        .or_insert_with(|| write_versions.stripes[stripe].load(Ordering::Acquire));
+                }
+            }
+            None => {
+                versions
+                    .all
+                    .get_or_insert_with(|| write_versions.all.load(Ordering::Acquire));
+            }
+        }
+    }
+
+    /// Whether nothing that was read has been written to since. This must
+    /// be called with writes excluded.
+    pub(crate) fn is_current(&self, write_versions: &WriteVersions) -> bool {
+        let versions = self.versions.lock().unwrap();
+        let unchanged = |version: Option<u64>, counter: &AtomicU64| {
+            version.is_none_or(|version| counter.load(Ordering::Acquire) == version)
+        };
+
+        unchanged(versions.all, &write_versions.all)
+            && unchanged(versions.untracked, &write_versions.untracked)
+            && versions
+                .stripes
+                .iter()
+                .all(|(&stripe, &version)| write_versions.stripes[stripe].load(Ordering::Acquire) == version)
+    }
+}
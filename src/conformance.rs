@@ -0,0 +1,982 @@
+//! Checks of sled-specific behavior that every configuration has to agree
+//! on. Config modules run them with `sled_test_impl!`, next to indradb's
+//! `full_test_impl!`, so that features like edge sort keys and deferred
+//! indexing are exercised against compression, untimed edge ranges and so
+//! on, not just the default config.
+
+use std::collections::HashSet;
+use std::thread;
+use std::time::Duration;
+
+use super::{
+    CascadePolicy, EdgeConstraints, EdgeSortKey, Error, Mutation, PropertyLimits, RawRecord, RawTreeAccess, SledConfig,
+    SledDatastore, SledTransaction, TraversalView, TreeKind, WriteValidator,
+};
+
+use chrono::offset::Utc;
+use indradb::{
+    BulkInsertItem, Datastore, EdgeDirection, EdgeKey, EdgePropertyQuery, Error as IndraError, NamedProperty,
+    PipeEdgeQuery, Result, SpecificEdgeQuery, SpecificVertexQuery, Transaction, Type, Vertex, VertexPropertyQuery,
+};
+use serde_json::{json, Value as JsonValue};
+use tempfile::tempdir;
+use uuid::Uuid;
+
+/// Defines a test for each check in this module, run against a datastore
+/// opened with the `SledConfig` that `$code` evaluates to. Checks add the
+/// settings they exercise on top of it.
+macro_rules! sled_test_impl {
+    ($code:expr) => {
+        define_sled_test!(should_order_edges_by_sort_key, $code);
+        define_sled_test!(should_reject_whole_batch_when_validator_vetoes_a_write, $code);
+        define_sled_test!(should_enforce_edge_constraints, $code);
+        define_sled_test!(should_enforce_edge_cardinality, $code);
+        define_sled_test!(should_maintain_materialized_views, $code);
+        define_sled_test!(should_rebuild_indexes_after_deferred_indexing, $code);
+        define_sled_test!(should_rebuild_property_indexes_after_bulk_load, $code);
+        define_sled_test!(should_index_every_edge_in_raw_trees, $code);
+        define_sled_test!(should_list_vertices_by_degree, $code);
+        define_sled_test!(should_delete_edges_in_range, $code);
+        define_sled_test!(should_apply_cascade_policies, $code);
+        define_sled_test!(should_enforce_property_limits, $code);
+        define_sled_test!(should_create_edges_with_properties, $code);
+        define_sled_test!(should_search_vertices_by_words, $code);
+        define_sled_test!(should_count_edge_activity_per_day, $code);
+        define_sled_test!(should_find_nearest_vectors, $code);
+        define_sled_test!(should_find_shortest_paths, $code);
+        define_sled_test!(should_look_up_composite_indexes, $code);
+    };
+}
+
+macro_rules! define_sled_test {
+    ($name:ident, $code:expr) => {
+        #[test]
+        fn $name() {
+            let config = $code;
+            $crate::conformance::$name(config);
+        }
+    };
+}
+
+fn open(config: SledConfig) -> SledDatastore {
+    config.open(tempdir().unwrap().into_path()).unwrap()
+}
+
+fn assert_rejected<T, F>(result: Result<T>, is_expected: F)
+where
+    T: std::fmt::Debug,
+    F: Fn(&Error) -> bool,
+{
+    match result {
+        Err(IndraError::Datastore { inner }) => match inner.downcast_ref::<Error>() {
+            Some(err) if is_expected(err) => {}
+            _ => panic!("unexpected error: {}", inner),
+        },
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
+
+fn outbound_ids(trans: &SledTransaction, id: Uuid, t: &Type) -> Vec<u128> {
+    let q = PipeEdgeQuery {
+        inner: Box::new(SpecificVertexQuery::single(id).into()),
+        direction: EdgeDirection::Outbound,
+        limit: 100,
+        t: Some(t.clone()),
+        high: None,
+        low: None,
+    };
+
+    trans
+        .get_edges(q)
+        .unwrap()
+        .into_iter()
+        .map(|edge| edge.key.inbound_id.as_u128())
+        .collect()
+}
+
+pub(crate) fn should_order_edges_by_sort_key(config: SledConfig) {
+    let t = Type::new("test_edge_type").unwrap();
+    let datastore = open(config.with_edge_sort_key(t.clone(), EdgeSortKey::new("weight").descending()));
+
+    let source_id = Uuid::from_u128(0);
+    let mut items = vec![BulkInsertItem::Vertex(Vertex::with_id(source_id, t.clone()))];
+
+    for (i, weight) in [Some(2.5), None, Some(10.0), Some(-1.0)].iter().enumerate() {
+        let key = EdgeKey::new(source_id, t.clone(), Uuid::from_u128(i as u128 + 1));
+        items.push(BulkInsertItem::Vertex(Vertex::with_id(key.inbound_id, t.clone())));
+        items.push(BulkInsertItem::Edge(key.clone()));
+
+        if let Some(weight) = *weight {
+            items.push(BulkInsertItem::EdgeProperty(
+                key,
+                "weight".to_string(),
+                JsonValue::from(weight),
+            ));
+        }
+    }
+
+    datastore.bulk_insert(items.into_iter()).unwrap();
+
+    let trans = datastore.transaction().unwrap();
+    assert_eq!(outbound_ids(&trans, source_id, &t), vec![3, 1, 4, 2]);
+}
+
+struct RejectEdgeType(Type);
+
+impl WriteValidator for RejectEdgeType {
+    fn validate(&self, mutation: &Mutation) -> std::result::Result<(), String> {
+        match *mutation {
+            Mutation::CreateEdge(key) if key.t == self.0 => Err(format!("`{}` edges are rejected", key.t.0)),
+            _ => Ok(()),
+        }
+    }
+}
+
+pub(crate) fn should_reject_whole_batch_when_validator_vetoes_a_write(config: SledConfig) {
+    let t = Type::new("test_vertex_type").unwrap();
+    let rejected_t = Type::new("rejected_edge_type").unwrap();
+    let datastore = open(config);
+    datastore.add_write_validator(RejectEdgeType(rejected_t.clone()));
+
+    let trans = datastore.transaction().unwrap();
+    let outbound_id = Uuid::from_u128(1);
+    let inbound_id = Uuid::from_u128(2);
+    trans.create_vertex(&Vertex::with_id(outbound_id, t.clone())).unwrap();
+    trans.create_vertex(&Vertex::with_id(inbound_id, t.clone())).unwrap();
+
+    let mut batch = trans.begin_batch();
+    batch.create_edge(&EdgeKey::new(outbound_id, t, inbound_id));
+    batch.create_edge(&EdgeKey::new(outbound_id, rejected_t, inbound_id));
+    assert_rejected(batch.commit(), |err| matches!(*err, Error::WriteRejected { .. }));
+
+    let count = trans
+        .get_edge_count(outbound_id, None, EdgeDirection::Outbound)
+        .unwrap();
+    assert_eq!(count, 0);
+}
+
+pub(crate) fn should_enforce_edge_constraints(config: SledConfig) {
+    let t = Type::new("test_edge_type").unwrap();
+    let constraints = EdgeConstraints::new().max_out_degree(1).forbid_self_loops();
+    let datastore = open(config.with_edge_constraints(t.clone(), constraints));
+    let trans = datastore.transaction().unwrap();
+
+    for i in 1..4 {
+        trans
+            .create_vertex(&Vertex::with_id(Uuid::from_u128(i), t.clone()))
+            .unwrap();
+    }
+
+    let key = EdgeKey::new(Uuid::from_u128(1), t.clone(), Uuid::from_u128(2));
+    assert!(trans.create_edge(&key).unwrap());
+
+    // Refreshing an existing edge doesn't add to the degree.
+    assert!(trans.create_edge(&key).unwrap());
+
+    let key = EdgeKey::new(Uuid::from_u128(1), t.clone(), Uuid::from_u128(3));
+    assert_rejected(trans.create_edge(&key), |err| match *err {
+        Error::MaxOutDegreeExceeded { max, .. } => max == 1,
+        _ => false,
+    });
+
+    let key = EdgeKey::new(Uuid::from_u128(3), t.clone(), Uuid::from_u128(3));
+    assert_rejected(trans.create_edge(&key), |err| {
+        matches!(*err, Error::SelfLoopForbidden { .. })
+    });
+
+    assert_eq!(outbound_ids(&trans, Uuid::from_u128(1), &t), vec![2]);
+}
+
+pub(crate) fn should_enforce_edge_cardinality(config: SledConfig) {
+    let user_t = Type::new("user").unwrap();
+    let address_t = Type::new("address").unwrap();
+    let primary_t = Type::new("primary_address").unwrap();
+    let datastore = open(config.with_edge_cardinality(user_t.clone(), primary_t.clone(), 1));
+    let trans = datastore.transaction().unwrap();
+
+    trans
+        .create_vertex(&Vertex::with_id(Uuid::from_u128(1), user_t))
+        .unwrap();
+
+    for i in 2..5 {
+        trans
+            .create_vertex(&Vertex::with_id(Uuid::from_u128(i), address_t.clone()))
+            .unwrap();
+    }
+
+    let key = EdgeKey::new(Uuid::from_u128(1), primary_t.clone(), Uuid::from_u128(3));
+    assert!(trans.create_edge(&key).unwrap());
+    assert!(trans.create_edge(&key).unwrap());
+
+    let key = EdgeKey::new(Uuid::from_u128(1), primary_t.clone(), Uuid::from_u128(4));
+    assert_rejected(trans.create_edge(&key), |err| match *err {
+        Error::CardinalityExceeded {
+            ref outbound_t, max, ..
+        } => outbound_t == "user" && max == 1,
+        _ => false,
+    });
+
+    // The limit only applies to outbound vertices of the given type.
+    for i in 3..5 {
+        let key = EdgeKey::new(Uuid::from_u128(2), primary_t.clone(), Uuid::from_u128(i));
+        assert!(trans.create_edge(&key).unwrap());
+    }
+
+    assert_eq!(outbound_ids(&trans, Uuid::from_u128(1), &primary_t), vec![3]);
+    let count = trans
+        .get_edge_count(Uuid::from_u128(2), Some(&primary_t), EdgeDirection::Outbound)
+        .unwrap();
+    assert_eq!(count, 2);
+}
+
+pub(crate) fn should_maintain_materialized_views(config: SledConfig) {
+    let user_t = Type::new("user").unwrap();
+    let group_t = Type::new("group").unwrap();
+    let member_t = Type::new("member_of").unwrap();
+    let parent_t = Type::new("part_of").unwrap();
+    let view = TraversalView::new(Some(user_t.clone()))
+        .outbound(member_t.clone())
+        .outbound(parent_t.clone());
+    let datastore = open(config.with_materialized_view("ancestor_groups", view));
+    let trans = datastore.transaction().unwrap();
+
+    let user_id = Uuid::from_u128(1);
+    let group_id = Uuid::from_u128(2);
+    let parent_id = Uuid::from_u128(3);
+    trans.create_vertex(&Vertex::with_id(user_id, user_t)).unwrap();
+    trans
+        .create_vertex(&Vertex::with_id(group_id, group_t.clone()))
+        .unwrap();
+    trans.create_vertex(&Vertex::with_id(parent_id, group_t)).unwrap();
+
+    trans
+        .create_edge(&EdgeKey::new(user_id, member_t.clone(), group_id))
+        .unwrap();
+    assert_eq!(trans.get_view("ancestor_groups", user_id).unwrap(), Vec::<Uuid>::new());
+
+    let parent_key = EdgeKey::new(group_id, parent_t, parent_id);
+    trans.create_edge(&parent_key).unwrap();
+    assert_eq!(trans.get_view("ancestor_groups", user_id).unwrap(), vec![parent_id]);
+
+    trans.delete_edges(SpecificEdgeQuery::single(parent_key)).unwrap();
+    assert!(!trans.view_contains("ancestor_groups", user_id, parent_id).unwrap());
+}
+
+pub(crate) fn should_rebuild_indexes_after_deferred_indexing(config: SledConfig) {
+    let t = Type::new("test_edge_type").unwrap();
+    let view = TraversalView::new(None).inbound(t.clone());
+    let datastore = open(config.with_materialized_view("followers", view));
+    datastore.begin_deferred_indexing().unwrap();
+
+    let target_id = Uuid::from_u128(0);
+    let mut items = vec![BulkInsertItem::Vertex(Vertex::with_id(target_id, t.clone()))];
+
+    for i in 1..11 {
+        let id = Uuid::from_u128(i);
+        items.push(BulkInsertItem::Vertex(Vertex::with_id(id, t.clone())));
+        items.push(BulkInsertItem::Edge(EdgeKey::new(id, t.clone(), target_id)));
+    }
+
+    datastore.bulk_insert(items.into_iter()).unwrap();
+    datastore.finish_deferred_indexing().unwrap();
+    assert!(!datastore.is_indexing_deferred());
+
+    let trans = datastore.transaction().unwrap();
+    let count = trans.get_edge_count(target_id, None, EdgeDirection::Inbound).unwrap();
+    assert_eq!(count, 10);
+    assert_eq!(trans.get_view("followers", target_id).unwrap().len(), 10);
+    assert_eq!(trans.recent_vertices(&t, 100).unwrap().len(), 11);
+}
+
+pub(crate) fn should_rebuild_property_indexes_after_bulk_load(config: SledConfig) {
+    let t = Type::new("test_edge_type").unwrap();
+    let datastore = open(
+        config
+            .with_full_text_index()
+            .with_composite_index("place", &["country", "city"]),
+    );
+    datastore.index_property("country").unwrap();
+    datastore.index_edge_property("weight").unwrap();
+    datastore.begin_bulk_load().unwrap();
+    assert!(datastore.is_bulk_loading());
+    assert!(datastore.is_indexing_deferred());
+
+    let target_id = Uuid::from_u128(0);
+    let mut items = vec![BulkInsertItem::Vertex(Vertex::with_id(target_id, t.clone()))];
+
+    for i in 1..11 {
+        let id = Uuid::from_u128(i);
+        let key = EdgeKey::new(id, t.clone(), target_id);
+        items.push(BulkInsertItem::Vertex(Vertex::with_id(id, t.clone())));
+        items.push(BulkInsertItem::Edge(key.clone()));
+        items.push(BulkInsertItem::EdgeProperty(key, "weight".to_string(), json!(i % 2)));
+        items.push(BulkInsertItem::VertexProperty(id, "country".to_string(), json!("nl")));
+        items.push(BulkInsertItem::VertexProperty(
+            id,
+            "city".to_string(),
+            json!(format!("city {}", i)),
+        ));
+    }
+
+    datastore.bulk_insert(items.into_iter()).unwrap();
+
+    let trans = datastore.transaction().unwrap();
+    assert_rejected(
+        trans.get_vertices_by_property("country", &json!("nl")),
+        |err| match *err {
+            Error::PropertyIndexBuilding { ref name } => name == "country",
+            _ => false,
+        },
+    );
+    assert!(trans.search_vertices("city", "city").unwrap().is_empty());
+    assert!(trans.get_inbound_edge_properties(target_id).unwrap().is_empty());
+
+    datastore.finish_bulk_load().unwrap();
+    assert!(!datastore.is_bulk_loading());
+    assert!(!datastore.is_indexing_deferred());
+
+    assert_eq!(
+        trans.get_vertices_by_property("country", &json!("nl")).unwrap().len(),
+        10
+    );
+    assert_eq!(trans.get_edges_by_property("weight", &json!(1)).unwrap().len(), 5);
+    assert_eq!(trans.search_vertices("city", "city").unwrap().len(), 10);
+    assert_eq!(
+        trans
+            .get_vertices_by_properties("place", &[json!("nl"), json!("city 3")])
+            .unwrap()
+            .len(),
+        1
+    );
+    assert_eq!(trans.get_inbound_edge_properties(target_id).unwrap().len(), 10);
+    assert_eq!(
+        trans.get_edge_count(target_id, None, EdgeDirection::Inbound).unwrap(),
+        10
+    );
+
+    // Writes are indexed as usual once it's finished.
+    trans
+        .set_vertex_properties(
+            VertexPropertyQuery::new(SpecificVertexQuery::single(target_id).into(), "country".to_string()),
+            &json!("nl"),
+        )
+        .unwrap();
+    assert_eq!(
+        trans.get_vertices_by_property("country", &json!("nl")).unwrap().len(),
+        11
+    );
+}
+
+pub(crate) fn should_index_every_edge_in_raw_trees(config: SledConfig) {
+    let t = Type::new("test_edge_type").unwrap();
+    let sorted_t = Type::new("sorted_edge_type").unwrap();
+    let datastore = open(config.with_edge_sort_key(sorted_t.clone(), EdgeSortKey::new("weight")));
+    let trans = datastore.transaction().unwrap();
+
+    for i in 0..3 {
+        trans
+            .create_vertex(&Vertex::with_id(Uuid::from_u128(i), t.clone()))
+            .unwrap();
+    }
+
+    for &(outbound_id, inbound_id) in &[(0, 1), (0, 2), (1, 2), (2, 0)] {
+        let (outbound_id, inbound_id) = (Uuid::from_u128(outbound_id), Uuid::from_u128(inbound_id));
+        trans
+            .create_edge(&EdgeKey::new(outbound_id, t.clone(), inbound_id))
+            .unwrap();
+        trans
+            .create_edge(&EdgeKey::new(outbound_id, sorted_t.clone(), inbound_id))
+            .unwrap();
+    }
+
+    let edge_keys = |kind: TreeKind| -> HashSet<EdgeKey> {
+        datastore
+            .iter_tree_raw::<Vec<u8>, _>(kind, ..)
+            .map(|entry| match entry.unwrap().record {
+                RawRecord::Edge { key, .. } => key,
+                RawRecord::EdgeRange {
+                    first_id, t, second_id, ..
+                } => match kind {
+                    TreeKind::ReversedEdgeRanges => EdgeKey::new(second_id, t, first_id),
+                    _ => EdgeKey::new(first_id, t, second_id),
+                },
+                record => panic!("unexpected record: {:?}", record),
+            })
+            .collect()
+    };
+
+    let edges = edge_keys(TreeKind::Edges);
+    assert_eq!(edges.len(), 8);
+    assert_eq!(edge_keys(TreeKind::EdgeRanges), edges);
+    assert_eq!(edge_keys(TreeKind::ReversedEdgeRanges), edges);
+}
+
+pub(crate) fn should_list_vertices_by_degree(config: SledConfig) {
+    let t = Type::new("test_edge_type").unwrap();
+    let datastore = open(config.with_degree_index());
+    let trans = datastore.transaction().unwrap();
+
+    for i in 0..10 {
+        trans
+            .create_vertex(&Vertex::with_id(Uuid::from_u128(i), t.clone()))
+            .unwrap();
+    }
+
+    // Vertex 0 is linked from every other vertex, and vertex 1 from every
+    // vertex after it.
+    for i in 1..10 {
+        for j in 0..i.min(2) {
+            trans
+                .create_edge(&EdgeKey::new(Uuid::from_u128(i), t.clone(), Uuid::from_u128(j)))
+                .unwrap();
+        }
+    }
+
+    let top = |n| -> Vec<(u128, u64)> {
+        trans
+            .top_vertices_by_degree(n, None)
+            .unwrap()
+            .into_iter()
+            .map(|(vertex, degree)| (vertex.id.as_u128(), degree))
+            .collect()
+    };
+
+    assert_eq!(top(3), vec![(0, 9), (1, 9), (2, 2)]);
+
+    trans
+        .delete_vertices(SpecificVertexQuery::single(Uuid::from_u128(0)))
+        .unwrap();
+    assert_eq!(top(2), vec![(1, 8), (2, 1)]);
+}
+
+pub(crate) fn should_delete_edges_in_range(config: SledConfig) {
+    let t = Type::new("test_edge_type").unwrap();
+    let other_t = Type::new("other_edge_type").unwrap();
+    let datastore = open(config);
+    let trans = datastore.transaction().unwrap();
+
+    for i in 0..7 {
+        trans
+            .create_vertex(&Vertex::with_id(Uuid::from_u128(i), t.clone()))
+            .unwrap();
+    }
+
+    let edge = |i, t: &Type| EdgeKey::new(Uuid::from_u128(0), t.clone(), Uuid::from_u128(i));
+    for i in 1..4 {
+        trans.create_edge(&edge(i, &t)).unwrap();
+    }
+    trans.create_edge(&edge(6, &other_t)).unwrap();
+    trans
+        .set_edge_properties(
+            EdgePropertyQuery::new(SpecificEdgeQuery::single(edge(1, &t)).into(), "name".to_string()),
+            &JsonValue::Bool(true),
+        )
+        .unwrap();
+
+    let cutoff = trans.get_edges(SpecificEdgeQuery::single(edge(3, &t))).unwrap()[0].created_datetime;
+    thread::sleep(Duration::from_millis(2));
+    for i in 4..6 {
+        trans.create_edge(&edge(i, &t)).unwrap();
+    }
+
+    let deleted = trans
+        .delete_edges_in_range(Uuid::from_u128(0), &t, EdgeDirection::Outbound, None, Some(cutoff))
+        .unwrap();
+    assert_eq!(deleted, 3);
+
+    let mut remaining = outbound_ids(&trans, Uuid::from_u128(0), &t);
+    remaining.sort();
+    assert_eq!(remaining, vec![4, 5]);
+    assert_eq!(outbound_ids(&trans, Uuid::from_u128(0), &other_t), vec![6]);
+    assert_eq!(
+        trans
+            .get_edge_count(Uuid::from_u128(1), None, EdgeDirection::Inbound)
+            .unwrap(),
+        0
+    );
+    assert!(trans
+        .get_edge_properties(EdgePropertyQuery::new(
+            SpecificEdgeQuery::single(edge(1, &t)).into(),
+            "name".to_string()
+        ))
+        .unwrap()
+        .is_empty());
+
+    // Inbound edges are deleted from the other side.
+    let deleted = trans
+        .delete_edges_in_range(Uuid::from_u128(4), &t, EdgeDirection::Inbound, None, None)
+        .unwrap();
+    assert_eq!(deleted, 1);
+    assert_eq!(
+        trans
+            .get_edge_count(Uuid::from_u128(0), Some(&t), EdgeDirection::Outbound)
+            .unwrap(),
+        1
+    );
+}
+
+pub(crate) fn should_apply_cascade_policies(config: SledConfig) {
+    let t = Type::new("test_edge_type").unwrap();
+    let restricted_t = Type::new("restricted_vertex_type").unwrap();
+    let datastore = open(config.with_cascade_policy(restricted_t.clone(), CascadePolicy::Restrict));
+    let trans = datastore.transaction().unwrap();
+
+    trans
+        .create_vertex(&Vertex::with_id(Uuid::from_u128(0), restricted_t.clone()))
+        .unwrap();
+    for i in 1..4 {
+        trans
+            .create_vertex(&Vertex::with_id(Uuid::from_u128(i), t.clone()))
+            .unwrap();
+    }
+    for i in 1..4 {
+        trans
+            .create_edge(&EdgeKey::new(Uuid::from_u128(i), t.clone(), Uuid::from_u128(0)))
+            .unwrap();
+    }
+
+    // Vertex 1 would be deleted by default, but vertex 0 fails the whole
+    // call.
+    let q = SpecificVertexQuery::new(vec![Uuid::from_u128(0), Uuid::from_u128(1)]);
+    assert_rejected(trans.delete_vertices(q), |err| match *err {
+        Error::VertexHasEdges { id } => id == Uuid::from_u128(0),
+        _ => false,
+    });
+    assert_eq!(trans.get_vertex_count().unwrap(), 4);
+
+    trans
+        .delete_vertices_with_policy(SpecificVertexQuery::single(Uuid::from_u128(1)), CascadePolicy::Detach)
+        .unwrap();
+    assert_eq!(trans.get_vertex_count().unwrap(), 3);
+    assert_eq!(
+        trans
+            .get_edge_count(Uuid::from_u128(1), None, EdgeDirection::Outbound)
+            .unwrap(),
+        1
+    );
+
+    trans
+        .delete_vertices(SpecificVertexQuery::single(Uuid::from_u128(2)))
+        .unwrap();
+    assert_eq!(
+        trans
+            .get_edge_count(Uuid::from_u128(0), None, EdgeDirection::Inbound)
+            .unwrap(),
+        2
+    );
+
+    trans
+        .delete_vertices_with_policy(SpecificVertexQuery::single(Uuid::from_u128(0)), CascadePolicy::Cascade)
+        .unwrap();
+    assert_eq!(trans.get_vertex_count().unwrap(), 1);
+    assert_eq!(
+        trans
+            .get_edge_count(Uuid::from_u128(3), None, EdgeDirection::Outbound)
+            .unwrap(),
+        0
+    );
+}
+
+pub(crate) fn should_enforce_property_limits(config: SledConfig) {
+    let t = Type::new("test_vertex_type").unwrap();
+    let limits = PropertyLimits::new().max_value_bytes(8).max_properties(2);
+    let datastore = open(config.with_property_limits(limits));
+    let trans = datastore.transaction().unwrap();
+    let id = Uuid::from_u128(1);
+    trans.create_vertex(&Vertex::with_id(id, t.clone())).unwrap();
+
+    let q = |name: &str| VertexPropertyQuery::new(SpecificVertexQuery::single(id).into(), name.to_string());
+    assert_rejected(
+        trans.set_vertex_properties(q("a"), &JsonValue::from("too large")),
+        |err| match *err {
+            Error::PropertyValueTooLarge { size, max, .. } => size == 11 && max == 8,
+            _ => false,
+        },
+    );
+
+    trans.set_vertex_properties(q("a"), &JsonValue::from(1)).unwrap();
+
+    // The batch's two new properties together exceed the maximum, so
+    // neither is set.
+    let mut batch = trans.begin_batch();
+    batch
+        .set_vertex_property(id, "b", &JsonValue::from(2))
+        .set_vertex_property(id, "c", &JsonValue::from(3));
+    assert_rejected(batch.commit(), |err| match *err {
+        Error::TooManyProperties { max, .. } => max == 2,
+        _ => false,
+    });
+    assert_eq!(trans.count_vertex_properties(id).unwrap(), 1);
+
+    trans.set_vertex_properties(q("b"), &JsonValue::from(2)).unwrap();
+    assert_rejected(trans.set_vertex_properties(q("c"), &JsonValue::from(3)), |err| {
+        matches!(*err, Error::TooManyProperties { .. })
+    });
+
+    // Existing properties can still be overwritten.
+    trans.set_vertex_properties(q("a"), &JsonValue::from(4)).unwrap();
+}
+
+pub(crate) fn should_create_edges_with_properties(config: SledConfig) {
+    let t = Type::new("test_edge_type").unwrap();
+    let constraints = EdgeConstraints::new().max_out_degree(2);
+    let config = config
+        .with_edge_sort_key(t.clone(), EdgeSortKey::new("weight"))
+        .with_edge_constraints(t.clone(), constraints);
+    let datastore = open(config);
+    let trans = datastore.transaction().unwrap();
+
+    for i in 0..4 {
+        trans
+            .create_vertex(&Vertex::with_id(Uuid::from_u128(i), t.clone()))
+            .unwrap();
+    }
+
+    let weight = |weight: f64| NamedProperty::new("weight".to_string(), JsonValue::from(weight));
+    let key = |inbound_id: u128| EdgeKey::new(Uuid::from_u128(0), t.clone(), Uuid::from_u128(inbound_id));
+
+    // The last value of a repeated name wins, and the edges are ordered by
+    // the weights they were created with.
+    assert!(trans
+        .create_edge_with_properties(&key(1), &[weight(1.0), weight(3.0)])
+        .unwrap());
+    assert!(trans.create_edge_with_properties(&key(2), &[weight(2.0)]).unwrap());
+    assert_eq!(outbound_ids(&trans, Uuid::from_u128(0), &t), vec![2, 1]);
+
+    let q = EdgePropertyQuery::new(SpecificEdgeQuery::single(key(1)).into(), "weight".to_string());
+    assert_eq!(trans.get_edge_properties(q).unwrap()[0].value, JsonValue::from(3.0));
+
+    // Nothing is written for an edge that violates a constraint.
+    assert_rejected(trans.create_edge_with_properties(&key(3), &[weight(0.0)]), |err| {
+        matches!(*err, Error::MaxOutDegreeExceeded { .. })
+    });
+    assert_eq!(trans.count_edge_properties(&key(3)).unwrap(), 0);
+    assert!(!trans
+        .create_edge_with_properties(&EdgeKey::new(Uuid::from_u128(0), t.clone(), Uuid::from_u128(9)), &[])
+        .unwrap());
+}
+
+pub(crate) fn should_search_vertices_by_words(config: SledConfig) {
+    let t = Type::new("test_vertex_type").unwrap();
+    let datastore = open(config.with_full_text_index());
+    let trans = datastore.transaction().unwrap();
+    let titles = [
+        "The Quick brown fox",
+        "A quick-witted fox, and a dog",
+        "Brown bread",
+        "quickly",
+    ];
+
+    for (i, title) in titles.iter().enumerate() {
+        let id = Uuid::from_u128(i as u128);
+        trans.create_vertex(&Vertex::with_id(id, t.clone())).unwrap();
+        trans
+            .set_vertex_properties(
+                VertexPropertyQuery::new(SpecificVertexQuery::single(id).into(), "title".to_string()),
+                &JsonValue::from(*title),
+            )
+            .unwrap();
+    }
+
+    let search = |query: &str| -> Vec<u128> {
+        trans
+            .search_vertices("title", query)
+            .unwrap()
+            .into_iter()
+            .map(|vertex| vertex.id.as_u128())
+            .collect()
+    };
+
+    // Every word has to match, whole and in any case.
+    assert_eq!(search("quick"), vec![0, 1]);
+    assert_eq!(search("FOX quick"), vec![0, 1]);
+    assert_eq!(search("brown fox"), vec![0]);
+    assert_eq!(search("dog"), vec![1]);
+    assert!(search("").is_empty());
+    assert!(trans.search_vertices("body", "quick").unwrap().is_empty());
+
+    trans
+        .set_vertex_properties(
+            VertexPropertyQuery::new(
+                SpecificVertexQuery::single(Uuid::from_u128(0)).into(),
+                "title".to_string(),
+            ),
+            &JsonValue::from("Slow brown turtle"),
+        )
+        .unwrap();
+    trans
+        .delete_vertices(SpecificVertexQuery::single(Uuid::from_u128(1)))
+        .unwrap();
+    assert!(search("quick").is_empty());
+    assert_eq!(search("brown"), vec![0, 2]);
+
+    let mut batch = trans.begin_batch();
+    batch.set_vertex_property(Uuid::from_u128(3), "title", &JsonValue::from("Quick"));
+    batch.commit().unwrap();
+    assert_eq!(search("quick"), vec![3]);
+}
+
+pub(crate) fn should_count_edge_activity_per_day(config: SledConfig) {
+    let vertex_t = Type::new("test_vertex_type").unwrap();
+    let edge_t = Type::new("test_edge_type").unwrap();
+    let other_t = Type::new("other_edge_type").unwrap();
+    let datastore = open(config.with_edge_activity());
+    let trans = datastore.transaction().unwrap();
+    let ids: Vec<Uuid> = (0..3).map(Uuid::from_u128).collect();
+
+    for id in &ids {
+        trans.create_vertex(&Vertex::with_id(*id, vertex_t.clone())).unwrap();
+    }
+
+    let today = Utc::now().date_naive();
+    trans
+        .create_edge(&EdgeKey::new(ids[0], edge_t.clone(), ids[1]))
+        .unwrap();
+    trans
+        .create_edge(&EdgeKey::new(ids[0], edge_t.clone(), ids[2]))
+        .unwrap();
+    // Setting an existing edge again is another write.
+    trans
+        .create_edge(&EdgeKey::new(ids[0], edge_t.clone(), ids[1]))
+        .unwrap();
+
+    let mut batch = trans.begin_batch();
+    batch.create_edge(&EdgeKey::new(ids[2], edge_t.clone(), ids[1]));
+    batch.commit().unwrap();
+
+    let activity =
+        |id: Uuid, t: &Type, direction: EdgeDirection| trans.get_edge_activity(id, t, direction, None, None).unwrap();
+
+    assert_eq!(activity(ids[0], &edge_t, EdgeDirection::Outbound), vec![(today, 3)]);
+    assert_eq!(activity(ids[1], &edge_t, EdgeDirection::Inbound), vec![(today, 3)]);
+    assert_eq!(activity(ids[2], &edge_t, EdgeDirection::Outbound), vec![(today, 1)]);
+    assert_eq!(activity(ids[2], &edge_t, EdgeDirection::Inbound), vec![(today, 1)]);
+    assert!(activity(ids[0], &edge_t, EdgeDirection::Inbound).is_empty());
+    assert!(activity(ids[0], &other_t, EdgeDirection::Outbound).is_empty());
+
+    // Bounds are inclusive.
+    let bounded = |low, high| {
+        trans
+            .get_edge_activity(ids[0], &edge_t, EdgeDirection::Outbound, low, high)
+            .unwrap()
+    };
+    assert_eq!(bounded(Some(today), Some(today)), vec![(today, 3)]);
+    let (yesterday, tomorrow) = (today.pred_opt().unwrap(), today.succ_opt().unwrap());
+    assert_eq!(bounded(Some(yesterday), Some(tomorrow)), vec![(today, 3)]);
+    assert!(bounded(Some(tomorrow), None).is_empty());
+    assert!(bounded(None, Some(yesterday)).is_empty());
+}
+
+pub(crate) fn should_find_nearest_vectors(config: SledConfig) {
+    let t = Type::new("test_vertex_type").unwrap();
+    let datastore = open(config.with_vector_index("embedding", 3));
+    let trans = datastore.transaction().unwrap();
+    let q = |i: u128| {
+        VertexPropertyQuery::new(
+            SpecificVertexQuery::single(Uuid::from_u128(i)).into(),
+            "embedding".to_string(),
+        )
+    };
+    let vectors = [
+        [1.0, 0.0, 0.0],
+        [0.9, 0.1, 0.0],
+        [0.0, 1.0, 0.0],
+        [-1.0, 0.0, 0.0],
+        [0.0, 0.0, 1.0],
+    ];
+
+    for (i, vector) in vectors.iter().enumerate() {
+        let id = Uuid::from_u128(i as u128 + 1);
+        trans.create_vertex(&Vertex::with_id(id, t.clone())).unwrap();
+        trans.set_vertex_properties(q(i as u128 + 1), &json!(vector)).unwrap();
+    }
+
+    let knn = |query: &[f32], k: usize| -> Vec<u128> {
+        trans
+            .knn("embedding", query, k)
+            .unwrap()
+            .into_iter()
+            .map(|(vertex, _)| vertex.id.as_u128())
+            .collect()
+    };
+
+    // A vector always shares a bucket with itself, and asking for every
+    // vector reads every bucket, so these are exact.
+    let nearest = trans.knn("embedding", &[1.0, 0.0, 0.0], 1).unwrap();
+    assert_eq!(nearest[0].0.id, Uuid::from_u128(1));
+    assert!((nearest[0].1 - 1.0).abs() < 1e-6);
+    assert_eq!(knn(&[1.0, 0.0, 0.0], 5), vec![1, 2, 3, 5, 4]);
+    assert!(knn(&[1.0, 0.0, 0.0], 0).is_empty());
+
+    for value in &[json!([1.0, 0.0]), json!("text")] {
+        assert_rejected(trans.set_vertex_properties(q(1), value), |err| match *err {
+            Error::InvalidVector { ref name, dimensions } => name == "embedding" && dimensions == 3,
+            _ => false,
+        });
+    }
+
+    assert_rejected(trans.knn("embedding", &[1.0], 1), |err| {
+        matches!(*err, Error::InvalidVector { .. })
+    });
+    assert_rejected(trans.knn("title", &[1.0, 0.0, 0.0], 1), |err| match *err {
+        Error::UnknownVectorIndex { ref name } => name == "title",
+        _ => false,
+    });
+
+    trans.set_vertex_properties(q(4), &json!([1.0, 0.0, 0.0])).unwrap();
+    assert_eq!(knn(&[1.0, 0.0, 0.0], 2), vec![1, 4]);
+
+    trans
+        .delete_vertices(SpecificVertexQuery::single(Uuid::from_u128(1)))
+        .unwrap();
+    assert_eq!(knn(&[1.0, 0.0, 0.0], 1), vec![4]);
+}
+
+pub(crate) fn should_find_shortest_paths(config: SledConfig) {
+    let t = Type::new("test_edge_type").unwrap();
+    let other_t = Type::new("other_edge_type").unwrap();
+    let datastore = open(config);
+    let trans = datastore.transaction().unwrap();
+    let key = |t: &Type, outbound: u128, inbound: u128| {
+        EdgeKey::new(Uuid::from_u128(outbound), t.clone(), Uuid::from_u128(inbound))
+    };
+    let set_weight = |key: &EdgeKey, weight: JsonValue| {
+        let q = EdgePropertyQuery::new(SpecificEdgeQuery::single(key.clone()).into(), "weight".to_string());
+        trans.set_edge_properties(q, &weight).unwrap();
+    };
+
+    for i in 1..=5 {
+        trans
+            .create_vertex(&Vertex::with_id(Uuid::from_u128(i), t.clone()))
+            .unwrap();
+    }
+
+    for &(outbound, inbound, weight) in &[(1, 2, 1.0), (2, 3, 1.0), (1, 3, 5.0), (3, 4, 1.0), (1, 4, 10.0)] {
+        trans.create_edge(&key(&t, outbound, inbound)).unwrap();
+        set_weight(&key(&t, outbound, inbound), json!(weight));
+    }
+
+    // Edges without a weight aren't followed.
+    trans.create_edge(&key(&t, 2, 5)).unwrap();
+    trans.create_edge(&key(&t, 5, 4)).unwrap();
+    set_weight(&key(&t, 5, 4), json!(0.0));
+
+    let path = |from: u128, to: u128, t: Option<&Type>, max_depth: u32| {
+        trans
+            .shortest_path(Uuid::from_u128(from), Uuid::from_u128(to), t, "weight", max_depth)
+            .unwrap()
+    };
+
+    assert_eq!(
+        path(1, 4, Some(&t), 10),
+        Some(vec![key(&t, 1, 2), key(&t, 2, 3), key(&t, 3, 4)])
+    );
+    assert_eq!(path(1, 4, Some(&t), 2), Some(vec![key(&t, 1, 3), key(&t, 3, 4)]));
+    assert_eq!(path(1, 4, Some(&t), 1), Some(vec![key(&t, 1, 4)]));
+    assert_eq!(path(1, 4, Some(&t), 0), None);
+    assert_eq!(path(4, 1, Some(&t), 10), None);
+    assert_eq!(path(3, 3, Some(&t), 0), Some(Vec::new()));
+
+    trans.create_edge(&key(&other_t, 1, 4)).unwrap();
+    set_weight(&key(&other_t, 1, 4), json!(2));
+    assert_eq!(path(1, 4, Some(&t), 10).unwrap().len(), 3);
+    assert_eq!(path(1, 4, None, 10), Some(vec![key(&other_t, 1, 4)]));
+
+    set_weight(&key(&t, 3, 4), json!("heavy"));
+    assert_rejected(
+        trans.shortest_path(Uuid::from_u128(1), Uuid::from_u128(4), Some(&t), "weight", 10),
+        |err| match *err {
+            Error::InvalidEdgeWeight {
+                outbound_id, ref name, ..
+            } => outbound_id == Uuid::from_u128(3) && name == "weight",
+            _ => false,
+        },
+    );
+}
+
+pub(crate) fn should_look_up_composite_indexes(config: SledConfig) {
+    let t = Type::new("test_vertex_type").unwrap();
+    let path = tempdir().unwrap().into_path();
+    let places = [
+        ("fr", "paris"),
+        ("fr", "lyon"),
+        ("de", "berlin"),
+        ("fr", "paris"),
+        ("de", "paris"),
+    ];
+
+    // Vertices written before the index is registered are indexed when
+    // it's built.
+    {
+        let datastore = config.clone().open(&path).unwrap();
+        let trans = datastore.transaction().unwrap();
+
+        for (i, &(country, city)) in places.iter().enumerate() {
+            let id = Uuid::from_u128(i as u128 + 1);
+            trans.create_vertex(&Vertex::with_id(id, t.clone())).unwrap();
+            let q = |name: &str| VertexPropertyQuery::new(SpecificVertexQuery::single(id).into(), name.to_string());
+            trans.set_vertex_properties(q("country"), &json!(country)).unwrap();
+            trans.set_vertex_properties(q("city"), &json!(city)).unwrap();
+        }
+    }
+
+    let datastore = config
+        .with_composite_index("place", &["country", "city"])
+        .open(&path)
+        .unwrap();
+    let trans = datastore.transaction().unwrap();
+    let q = |i: u128, name: &str| {
+        VertexPropertyQuery::new(SpecificVertexQuery::single(Uuid::from_u128(i)).into(), name.to_string())
+    };
+    let lookup = |values: &[JsonValue]| -> Vec<u128> {
+        trans
+            .get_vertices_by_properties("place", values)
+            .unwrap()
+            .into_iter()
+            .map(|vertex| vertex.id.as_u128())
+            .collect()
+    };
+
+    assert_eq!(lookup(&[json!("fr"), json!("paris")]), vec![1, 4]);
+    assert_eq!(lookup(&[json!("fr")]), vec![2, 1, 4]);
+    assert_eq!(lookup(&[]), vec![3, 5, 2, 1, 4]);
+    assert!(lookup(&[json!("paris")]).is_empty());
+
+    // Entries follow writes to either property, including ones made in a
+    // single batch or patch.
+    trans.set_vertex_properties(q(2, "city"), &json!("paris")).unwrap();
+    trans.delete_vertex_properties(q(4, "country")).unwrap();
+    assert_eq!(lookup(&[json!("fr"), json!("paris")]), vec![1, 2]);
+
+    datastore
+        .bulk_insert(
+            vec![
+                BulkInsertItem::VertexProperty(Uuid::from_u128(4), "country".to_string(), json!("de")),
+                BulkInsertItem::VertexProperty(Uuid::from_u128(4), "city".to_string(), json!("bonn")),
+            ]
+            .into_iter(),
+        )
+        .unwrap();
+    trans
+        .patch_vertex_properties(Uuid::from_u128(5), &json!({"country": "fr", "city": "nice"}))
+        .unwrap();
+    assert_eq!(lookup(&[json!("de")]), vec![3, 4]);
+    assert_eq!(lookup(&[json!("fr")]), vec![5, 1, 2]);
+
+    trans
+        .delete_vertices(SpecificVertexQuery::single(Uuid::from_u128(1)))
+        .unwrap();
+    assert_eq!(lookup(&[json!("fr"), json!("paris")]), vec![2]);
+
+    assert_rejected(
+        trans.get_vertices_by_properties("place", &[json!("fr"), json!("paris"), json!(1)]),
+        |err| match *err {
+            Error::TooManyCompositeValues { ref name, properties } => name == "place" && properties == 2,
+            _ => false,
+        },
+    );
+    assert_rejected(trans.get_vertices_by_properties("country", &[]), |err| match *err {
+        Error::UnknownCompositeIndex { ref name } => name == "country",
+        _ => false,
+    });
+}
@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use super::datastore::SledHolder;
+use super::deadline::Deadline;
+use super::errors::Error;
+use super::managers::{EdgePropertyManager, EdgeRangeManager, VertexManager};
+
+use indradb::{Result, Type};
+use uuid::Uuid;
+
+/// The rules that edges and vertex deletions are held to, as configured.
+pub(crate) struct Constraints {
+    pub(crate) edges: HashMap<Type, EdgeConstraints>,
+    /// The most edges of a type that a vertex of a type can have going
+    /// out, keyed by `(outbound vertex type, edge type)`.
+    pub(crate) cardinalities: HashMap<(Type, Type), u64>,
+    /// Held while checking and creating edges if there are any
+    /// cardinality limits, so that they're enforced atomically.
+    pub(crate) cardinality_lock: Mutex<()>,
+    pub(crate) cascade_policies: HashMap<Type, CascadePolicy>,
+}
+
+/// Structural rules for the edges of a type, which are checked whenever an
+/// edge of the type is created. Registered with
+/// `SledConfig::with_edge_constraints`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EdgeConstraints {
+    max_out_degree: Option<u64>,
+    forbid_self_loops: bool,
+    allowed_endpoints: Vec<(Type, Type)>,
+}
+
+impl EdgeConstraints {
+    /// Creates a set of constraints that allows any edge.
+    pub fn new() -> Self {
+        EdgeConstraints::default()
+    }
+
+    /// Limits how many edges of the type a vertex can have going out.
+    pub fn max_out_degree(self, max: u64) -> Self {
+        EdgeConstraints {
+            max_out_degree: Some(max),
+            ..self
+        }
+    }
+
+    /// Rejects edges from a vertex to itself.
+    pub fn forbid_self_loops(self) -> Self {
+        EdgeConstraints {
+            forbid_self_loops: true,
+            ..self
+        }
+    }
+
+    /// Allows edges from vertices of type `outbound_t` to vertices of type
+    /// `inbound_t`. Once any pair is allowed, edges between vertices of
+    /// other types are rejected.
+    pub fn allow_endpoints(mut self, outbound_t: Type, inbound_t: Type) -> Self {
+        self.allowed_endpoints.push((outbound_t, inbound_t));
+        self
+    }
+}
+
+/// What happens to the edges of a vertex when it's deleted. Set for a
+/// vertex type with `SledConfig::with_cascade_policy`, or for a single call
+/// with `SledTransaction::delete_vertices_with_policy`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CascadePolicy {
+    /// The vertex's edges are deleted along with it. This is the default.
+    #[default]
+    Cascade,
+    /// Deleting a vertex that has edges fails with
+    /// `Error::VertexHasEdges`. The vertices matched by the query are all
+    /// checked before any are deleted.
+    Restrict,
+    /// The vertex's edges are left in place, pointing at a vertex that no
+    /// longer exists. They're found again if a vertex with the same ID is
+    /// created, and can be deleted with
+    /// `SledTransaction::delete_edges_in_range`. The consistency check
+    /// reports them as dangling.
+    Detach,
+}
+
+/// Checks that a new edge satisfies the constraints of its type.
+/// `pending_outbound` is the number of other edges of the type from the
+/// same outbound vertex that are being created along with it.
+///
+/// The edges of undirected types are limited at both of their vertices, so
+/// for those, the inbound vertex is checked too, with `pending_inbound`
+/// other edges of the type being created along with it. Their endpoint
+/// types may match an allowed pair either way around.
+///
+/// Endpoint types are only checked if both vertices exist, since
+/// `bulk_insert` doesn't require them to.
+pub(crate) fn check_new_edge(
+    holder: &SledHolder,
+    outbound_id: Uuid,
+    t: &Type,
+    inbound_id: Uuid,
+    pending_outbound: u64,
+    pending_inbound: u64,
+) -> Result<()> {
+    let undirected = holder.edge_range_layout.is_undirected(t);
+
+    // The vertices whose degree the edge adds to, along with how many
+    // other edges are being added to it.
+    let mut limited = vec![(outbound_id, pending_outbound)];
+
+    if undirected && inbound_id != outbound_id {
+        limited.push((inbound_id, pending_inbound));
+    }
+
+    for &(id, pending) in &limited {
+        check_cardinality(holder, id, t, pending)?;
+    }
+
+    let constraints = match holder.constraints.edges.get(t) {
+        Some(constraints) => constraints,
+        None => return Ok(()),
+    };
+
+    if constraints.forbid_self_loops && outbound_id == inbound_id {
+        return Err(Error::SelfLoopForbidden {
+            t: t.0.clone(),
+            id: outbound_id,
+        }
+        .into());
+    }
+
+    if !constraints.allowed_endpoints.is_empty() {
+        let vertex_manager = VertexManager::new(holder);
+        let outbound_t = vertex_manager.get(outbound_id)?;
+        let inbound_t = vertex_manager.get(inbound_id)?;
+
+        if let (Some(outbound_t), Some(inbound_t)) = (outbound_t, inbound_t) {
+            let is_allowed = constraints.allowed_endpoints.iter().any(|allowed| {
+                (allowed.0 == outbound_t && allowed.1 == inbound_t)
+                    || (undirected && allowed.0 == inbound_t && allowed.1 == outbound_t)
+            });
+
+            if !is_allowed {
+                return Err(Error::EndpointTypesNotAllowed {
+                    t: t.0.clone(),
+                    outbound_t: outbound_t.0,
+                    inbound_t: inbound_t.0,
+                }
+                .into());
+            }
+        }
+    }
+
+    if let Some(max) = constraints.max_out_degree {
+        let edge_range_manager = EdgeRangeManager::new(holder);
+
+        for &(id, pending) in &limited {
+            let degree = edge_range_manager.count_for_range(id, Some(t), None, None, &Deadline::new(None))?;
+
+            if degree + pending >= max {
+                return Err(Error::MaxOutDegreeExceeded {
+                    t: t.0.clone(),
+                    outbound_id: id,
+                    max,
+                }
+                .into());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that a new edge of type `t` doesn't take `outbound_id` past the
+/// cardinality limit for its type, if there is one. The limit isn't checked
+/// if the vertex doesn't exist.
+fn check_cardinality(holder: &SledHolder, outbound_id: Uuid, t: &Type, pending: u64) -> Result<()> {
+    if holder.constraints.cardinalities.is_empty() {
+        return Ok(());
+    }
+
+    let outbound_t = match VertexManager::new(holder).get(outbound_id)? {
+        Some(outbound_t) => outbound_t,
+        None => return Ok(()),
+    };
+
+    if let Some(&max) = holder.constraints.cardinalities.get(&(outbound_t.clone(), t.clone())) {
+        let edge_range_manager = EdgeRangeManager::new(holder);
+        let count = edge_range_manager.count_for_range(outbound_id, Some(t), None, None, &Deadline::new(None))?;
+
+        if count + pending >= max {
+            return Err(Error::CardinalityExceeded {
+                outbound_t: outbound_t.0,
+                t: t.0.clone(),
+                outbound_id,
+                max,
+            }
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that a vertex has no edges in either direction, for
+/// `CascadePolicy::Restrict`. As when cascading, inbound edges are found
+/// through the reversed edge ranges, or while indexing is deferred, only if
+/// they have properties.
+pub(crate) fn check_no_edges(holder: &SledHolder, id: Uuid) -> Result<()> {
+    let has_edges = EdgeRangeManager::new(holder).iterate_for_owner(id).next().is_some()
+        || EdgeRangeManager::new_reversed(holder)
+            .iterate_for_owner(id)
+            .next()
+            .is_some()
+        || EdgePropertyManager::new(holder)
+            .iterate_for_inbound(id)
+            .next()
+            .is_some();
+
+    if has_edges {
+        return Err(Error::VertexHasEdges { id }.into());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,398 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+
+use super::datastore::{SledConfig, SledDatastore, SledTransaction};
+use super::errors::{map_err, map_io_err, Error};
+use super::recovery::OPEN_MARKER_KEY;
+use super::validate::{Mutation, WriteValidator};
+
+use indradb::{BulkInsertItem, Datastore, Result};
+use sled::Batch;
+
+/// How many published snapshots are kept around, so that readers that are
+/// still copying the previous one when a new one is published don't lose
+/// it from under them.
+const KEPT_SNAPSHOTS: u64 = 2;
+
+/// Distinguishes the snapshot copies opened by readers within a process.
+static NEXT_READER_COPY: AtomicU64 = AtomicU64::new(0);
+
+/// The part a process plays in a `SharedDatastore`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Role {
+    /// Owns the primary database, and is the only process that can write.
+    Writer,
+    /// Reads from a copy of the last snapshot published by the writer.
+    Reader,
+}
+
+/// Rejects every write made to a reader's copy of a snapshot, which would
+/// otherwise be silently lost on the next refresh.
+struct ReadOnly;
+
+impl WriteValidator for ReadOnly {
+    fn validate(&self, _: &Mutation) -> std::result::Result<(), String> {
+        Err("shared datastore readers are read-only".to_string())
+    }
+}
+
+struct State {
+    role: Role,
+    datastore: SledDatastore,
+    // The last generation published by the writer, or opened by the reader.
+    generation: u64,
+    // The reader's copy of the snapshot, removed once it's replaced.
+    copy_path: Option<PathBuf>,
+}
+
+/// A datastore shared by several processes, such as the workers of a web
+/// deployment. Sled databases can only be opened by one process at a time,
+/// so one process is the writer, which owns the primary database, and the
+/// others are readers, which each open their own copy of a snapshot the
+/// writer publishes.
+///
+/// All processes open the same directory, laid out as:
+/// * `primary`: The writer's sled database.
+/// * `snapshots/<generation>`: Published snapshots.
+/// * `CURRENT`: The generation of the latest snapshot.
+/// * `readers`: The readers' copies of snapshots.
+///
+/// Readers only see writes once the writer calls `publish` and they call
+/// `refresh`. Neither happens on its own: deployments call them on their
+/// own schedule, e.g. after each batch of writes or on a timer. Writes to
+/// a reader fail with `Error::WriteRejected`, though writes that aren't
+/// seen by write validators, such as `SledDatastore::partition`, aren't
+/// available through this type to begin with.
+///
+/// Transactions hold on to the database they were started on, so a
+/// transaction started before a refresh keeps reading the old snapshot.
+pub struct SharedDatastore {
+    dir: PathBuf,
+    config: SledConfig,
+    state: RwLock<State>,
+}
+
+impl SharedDatastore {
+    /// Opens a shared datastore as its writer. This fails if another
+    /// process has the primary database open.
+    ///
+    /// # Arguments
+    /// * `config`: The config to open the primary database and snapshots
+    ///   with.
+    /// * `dir`: The directory the datastore is shared through.
+    pub fn open_writer<P: AsRef<Path>>(config: SledConfig, dir: P) -> Result<SharedDatastore> {
+        let dir = dir.as_ref().to_path_buf();
+        map_io_err(fs::create_dir_all(&dir))?;
+        let state = open_writer_state(&config, &dir)?;
+
+        Ok(SharedDatastore {
+            dir,
+            config,
+            state: RwLock::new(state),
+        })
+    }
+
+    /// Opens a shared datastore as a reader, on the latest published
+    /// snapshot. This fails with `Error::NoPublishedSnapshot` if the writer
+    /// hasn't published any yet.
+    ///
+    /// # Arguments
+    /// * `config`: The config to open snapshots with.
+    /// * `dir`: The directory the datastore is shared through.
+    pub fn open_reader<P: AsRef<Path>>(config: SledConfig, dir: P) -> Result<SharedDatastore> {
+        let dir = dir.as_ref().to_path_buf();
+
+        let generation = match read_current(&dir)? {
+            Some(generation) => generation,
+            None => return Err(Error::NoPublishedSnapshot.into()),
+        };
+
+        let state = open_reader_state(&config, &dir, generation)?;
+
+        Ok(SharedDatastore {
+            dir,
+            config,
+            state: RwLock::new(state),
+        })
+    }
+
+    /// The part this process currently plays.
+    pub fn role(&self) -> Role {
+        self.state.read().unwrap().role
+    }
+
+    /// The last generation this process published, as the writer, or
+    /// opened, as a reader. This is 0 if the writer hasn't published yet.
+    pub fn generation(&self) -> u64 {
+        self.state.read().unwrap().generation
+    }
+
+    /// Publishes a snapshot of the primary database for readers to pick up
+    /// with `refresh`, and returns its generation. Writes are blocked while
+    /// the snapshot is taken, so it's consistent.
+    ///
+    /// Only the writer can publish; readers get `Error::NotWriter`.
+    pub fn publish(&self) -> Result<u64> {
+        let mut state = self.state.write().unwrap();
+
+        if state.role != Role::Writer {
+            return Err(Error::NotWriter.into());
+        }
+
+        let generation = publish_snapshot(&self.config, &self.dir, &state.datastore, state.generation + 1)?;
+        state.generation = generation;
+        Ok(generation)
+    }
+
+    /// Switches a reader to the latest published snapshot, if there's a
+    /// newer one than it has open. Returns whether it switched. This is a
+    /// no-op for the writer, which always sees its own writes.
+    pub fn refresh(&self) -> Result<bool> {
+        let generation = match read_current(&self.dir)? {
+            Some(generation) => generation,
+            None => return Ok(false),
+        };
+
+        let mut state = self.state.write().unwrap();
+
+        if state.role != Role::Reader || generation <= state.generation {
+            return Ok(false);
+        }
+
+        let new_state = open_reader_state(&self.config, &self.dir, generation)?;
+        let old_state = std::mem::replace(&mut *state, new_state);
+        remove_reader_copy(old_state);
+        Ok(true)
+    }
+
+    /// Makes a reader the writer, e.g. to fail over when the previous
+    /// writer has gone away. This fails if another process still has the
+    /// primary database open, in which case this stays a reader.
+    pub fn promote(&self) -> Result<()> {
+        let mut state = self.state.write().unwrap();
+
+        if state.role == Role::Writer {
+            return Ok(());
+        }
+
+        let new_state = open_writer_state(&self.config, &self.dir)?;
+        let old_state = std::mem::replace(&mut *state, new_state);
+        remove_reader_copy(old_state);
+        Ok(())
+    }
+
+    /// Makes the writer a reader, releasing the primary database so that
+    /// another process can be promoted. The writes made so far are
+    /// published first, so that they aren't lost to readers.
+    pub fn demote(&self) -> Result<()> {
+        let mut state = self.state.write().unwrap();
+
+        if state.role == Role::Reader {
+            return Ok(());
+        }
+
+        let generation = publish_snapshot(&self.config, &self.dir, &state.datastore, state.generation + 1)?;
+        state.datastore.sync()?;
+
+        // The primary database has to be closed before another process can
+        // open it, which happens once the last transaction on it is gone.
+        let new_state = open_reader_state(&self.config, &self.dir, generation)?;
+        *state = new_state;
+        Ok(())
+    }
+}
+
+impl Datastore for SharedDatastore {
+    type Trans = SledTransaction;
+
+    fn sync(&self) -> Result<()> {
+        self.state.read().unwrap().datastore.sync()
+    }
+
+    fn transaction(&self) -> Result<Self::Trans> {
+        self.state.read().unwrap().datastore.transaction()
+    }
+
+    fn bulk_insert<I>(&self, items: I) -> Result<()>
+    where
+        I: Iterator<Item = BulkInsertItem>,
+    {
+        self.state.read().unwrap().datastore.bulk_insert(items)
+    }
+}
+
+impl Drop for SharedDatastore {
+    fn drop(&mut self) {
+        if let Ok(state) = self.state.get_mut() {
+            if let Some(ref copy_path) = state.copy_path {
+                let _ = fs::remove_dir_all(copy_path);
+            }
+        }
+    }
+}
+
+fn open_writer_state(config: &SledConfig, dir: &Path) -> Result<State> {
+    let datastore = config.clone().open(dir.join("primary"))?;
+
+    Ok(State {
+        role: Role::Writer,
+        datastore,
+        generation: read_current(dir)?.unwrap_or(0),
+        copy_path: None,
+    })
+}
+
+/// Copies a published snapshot for this process, and opens the copy. The
+/// snapshot itself can't be opened, since sled would lock it to this
+/// process.
+fn open_reader_state(config: &SledConfig, dir: &Path, generation: u64) -> Result<State> {
+    let readers_dir = dir.join("readers");
+    map_io_err(fs::create_dir_all(&readers_dir))?;
+
+    let copy_path = readers_dir.join(format!(
+        "{}-{}-{}",
+        process::id(),
+        generation,
+        NEXT_READER_COPY.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let snapshot_path = snapshot_path(dir, generation);
+
+    if let Err(err) = copy_dir(&snapshot_path, &copy_path) {
+        let _ = fs::remove_dir_all(&copy_path);
+        return map_io_err(Err(err));
+    }
+
+    let datastore = match config.clone().open(&copy_path) {
+        Ok(datastore) => datastore,
+        Err(err) => {
+            let _ = fs::remove_dir_all(&copy_path);
+            return Err(err);
+        }
+    };
+
+    datastore.add_write_validator(ReadOnly);
+
+    Ok(State {
+        role: Role::Reader,
+        datastore,
+        generation,
+        copy_path: Some(copy_path),
+    })
+}
+
+fn remove_reader_copy(state: State) {
+    let State {
+        datastore, copy_path, ..
+    } = state;
+
+    drop(datastore);
+
+    if let Some(copy_path) = copy_path {
+        let _ = fs::remove_dir_all(copy_path);
+    }
+}
+
+fn snapshot_path(dir: &Path, generation: u64) -> PathBuf {
+    dir.join("snapshots").join(generation.to_string())
+}
+
+/// Reads the generation of the latest published snapshot.
+fn read_current(dir: &Path) -> Result<Option<u64>> {
+    let contents = match fs::read_to_string(dir.join("CURRENT")) {
+        Ok(contents) => contents,
+        Err(ref err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return map_io_err(Err(err)),
+    };
+
+    match contents.trim().parse() {
+        Ok(generation) => Ok(Some(generation)),
+        Err(_) => Err(Error::Corruption {
+            tree: "CURRENT".to_string(),
+            key: contents.into_bytes(),
+        }
+        .into()),
+    }
+}
+
+/// Writes a consistent copy of the primary database as a new snapshot,
+/// points `CURRENT` at it and removes old snapshots. Returns the new
+/// snapshot's generation.
+fn publish_snapshot(config: &SledConfig, dir: &Path, datastore: &SledDatastore, generation: u64) -> Result<u64> {
+    let snapshots_dir = dir.join("snapshots");
+    map_io_err(fs::create_dir_all(&snapshots_dir))?;
+
+    // The snapshot is written under a temporary name, so that readers
+    // never see one that's partially written.
+    let tmp_path = snapshots_dir.join(format!("{}.tmp", generation));
+    let _ = fs::remove_dir_all(&tmp_path);
+
+    {
+        let holder = &datastore.holder;
+        let _lock = holder.index_lock.write().unwrap();
+        let snapshot = map_err(config.sled_config(&tmp_path).open())?;
+
+        for name in holder.db.tree_names() {
+            let source = map_err(holder.db.open_tree(&name))?;
+            let target = map_err(snapshot.open_tree(&name))?;
+            let mut batch = Batch::default();
+
+            for item in source.iter() {
+                let (k, v) = map_err(item)?;
+                batch.insert(k, v);
+            }
+
+            map_err(target.apply_batch(batch))?;
+        }
+
+        // The primary is open, but readers open the snapshot as if it was
+        // closed cleanly.
+        let metadata = map_err(snapshot.open_tree("metadata"))?;
+        map_err(metadata.remove(OPEN_MARKER_KEY))?;
+        map_err(snapshot.flush())?;
+    }
+
+    let snapshot_path = snapshot_path(dir, generation);
+    let _ = fs::remove_dir_all(&snapshot_path);
+    map_io_err(fs::rename(&tmp_path, &snapshot_path))?;
+
+    let current_tmp_path = dir.join("CURRENT.tmp");
+    map_io_err(fs::write(&current_tmp_path, generation.to_string()))?;
+    map_io_err(fs::rename(&current_tmp_path, dir.join("CURRENT")))?;
+
+    for entry in map_io_err(fs::read_dir(&snapshots_dir))? {
+        let entry = map_io_err(entry)?;
+
+        let is_old = match entry.file_name().to_str().and_then(|name| name.parse::<u64>().ok()) {
+            Some(old_generation) => old_generation + KEPT_SNAPSHOTS <= generation,
+            None => false,
+        };
+
+        if is_old {
+            let _ = fs::remove_dir_all(entry.path());
+        }
+    }
+
+    Ok(generation)
+}
+
+fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(to)?;
+
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+
+    Ok(())
+}
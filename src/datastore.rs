@@ -1,25 +1,232 @@
-use std::path::Path;
-use std::sync::Arc;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fmt::Debug;
+use std::fs::File;
+use std::future::Future;
+use std::io::ErrorKind;
+use std::ops::{Bound, RangeBounds};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::slice;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration as StdDuration, Instant, SystemTime, UNIX_EPOCH};
 use std::{u64, usize};
 
-use super::errors::map_err;
+use super::access::{self, AccessKind, AccessPolicy};
+use super::activity;
+use super::archive::{self, ArchiveTrees};
+use super::atomic::MultiBatch;
+use super::audit::{self, AuditEntry, AuditLog};
+use super::batch::{PendingBytes, PendingWrites, SledBatch, WriteBuffer};
+use super::cache::{Cacheable, ResultCache, ResultCaches};
+use super::components;
+use super::composite::{self, CompositeIndex};
+use super::conflicts::{ReadSet, WriteVersions};
+use super::constraints::{self, CascadePolicy, Constraints, EdgeConstraints};
+use super::deadline::{Deadline, OpContext, TaggedWork};
+use super::decode::{DecodeErrorPolicy, DecodeErrors, PolicyOverride, SkippedRecords};
+use super::dedup::{self, ValueStore};
+use super::degrees::{self, DegreeIndex};
+use super::derived::DerivedProperty;
+use super::errors::{map_err, map_io_err, Error};
+use super::explain::{self, PlannedQuery, QueryPlan};
+use super::export;
+use super::flush::{FlushFuture, FlushSchedule};
+use super::format;
+use super::fulltext;
+use super::history::{HistoryTrees, SledAsOfView};
+use super::import;
+use super::indexed::{self, IndexBuildProgress, IndexedProperties, PropertyOwner};
+use super::layout::{self, EdgeRangeLayout, EdgeSortKey};
+use super::limits::{self, PropertyLimits};
+use super::maintenance::{self, MaintenanceHandle};
 use super::managers::*;
+use super::patch;
+use super::paths;
+use super::precision::{self, DatetimePrecision};
+use super::preflight::{self, ExistingDatastore};
+use super::rebuild;
+use super::reclaim::{self, ReclaimProgress, Reclaimer};
+use super::recovery::{self, RecoveryInfo, Session};
+use super::reindex::{self, Index, IndexTree};
+use super::rename;
+use super::retry::{Retrier, RetryPolicy};
+use super::scrub::{self, ScrubFinding, ScrubHook, ScrubStats, Scrubber};
+use super::stats::{self, EdgeWriteRecorder, EdgeWriteStats, GraphStatistics};
+use super::validate::{self, Mutation, WriteValidator};
+use super::vectors::{self, VectorIndex};
+use super::views::{self, MaterializedView, TraversalView};
+use super::warm;
 
 use chrono::offset::Utc;
+use chrono::{DateTime, Duration, NaiveDate};
 use indradb::util::next_uuid;
 use indradb::{
     BulkInsertItem, Datastore, Edge, EdgeDirection, EdgeKey, EdgeProperties, EdgeProperty, EdgePropertyQuery,
-    EdgeQuery, NamedProperty, Result, Transaction, Type, Vertex, VertexProperties, VertexProperty, VertexPropertyQuery,
-    VertexQuery,
+    EdgeQuery, MemoryDatastore, NamedProperty, Result, Transaction, Type, Vertex, VertexProperties, VertexProperty,
+    VertexPropertyQuery, VertexQuery,
 };
 use serde_json::Value as JsonValue;
-use sled::{Config, Db, Tree};
+use sled::{Config, Db, Error as SledError, Mode, Tree};
 use uuid::Uuid;
 
-#[derive(Copy, Clone, Default, Debug)]
+/// How queries behave when other threads write to the datastore while
+/// they're running.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IteratorStability {
+    /// Queries read from sled lazily, step by step, so they observe writes
+    /// made while they're running. A query may see some of the changes made
+    /// by a concurrent call but not others - e.g. a pipe query may return an
+    /// edge whose outbound vertex is being deleted, but not the vertex
+    /// itself. Every returned item did exist at some point during the
+    /// query. This is the default, and has no overhead.
+    #[default]
+    Live,
+    /// Queries are isolated from concurrent writes: each query's results
+    /// reflect the graph as it was at a single point in time, with every
+    /// mutating call either fully applied or not applied at all. This is
+    /// implemented with a readers-writer lock, so writes wait for running
+    /// queries to finish, and vice versa.
+    Snapshot,
+}
+
+/// How durable a mutating call's writes are by the time it returns. Set for
+/// a datastore with `SledConfig::with_durability`, and for a transaction
+/// with `SledTransaction::with_durability`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Durability {
+    /// Writes are flushed to disk by sled in the background, or per
+    /// `SledConfig::with_flush_interval` and `SledConfig::with_flush_every`,
+    /// so a crash can lose the writes made since the last flush. This is
+    /// the default.
+    #[default]
+    Eventual,
+    /// Each mutating call flushes the datastore before it returns, which
+    /// writes sled's buffers out and fsyncs its files.
+    FlushEachCommit,
+    /// Like `FlushEachCommit`, and the datastore's directory is fsynced
+    /// too, so that files sled has created since it was opened survive a
+    /// crash on filesystems that don't persist new directory entries on
+    /// their own.
+    FsyncEachCommit,
+}
+
+/// How sled trades disk space against write throughput. Set with
+/// `SledConfig::with_storage_mode`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StorageMode {
+    /// Uses less disk space, at the cost of rewriting data more often to
+    /// reduce fragmentation. This is sled's default.
+    LowSpace,
+    /// Maximizes write throughput, at the cost of more disk space.
+    HighThroughput,
+}
+
+/// How the reads of a transaction trade off speed against strictness. Set
+/// with `SledTransaction::with_read_options`, so that e.g. an audit can
+/// read strictly while the rest of the application reads with the
+/// datastore's defaults.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReadOptions {
+    /// Overrides the datastore's `DecodeErrorPolicy` for these reads, e.g.
+    /// `FailFast` to fail on any corrupt record rather than skip it. `None`
+    /// keeps the datastore's. Sled itself checks the checksum of every
+    /// page it reads from disk regardless.
+    pub decode_error_policy: Option<DecodeErrorPolicy>,
+    /// Whether query results may be served from, and added to, the cache
+    /// set up by `SledConfig::with_result_cache`.
+    pub use_cache: bool,
+    /// Whether each read is isolated from concurrent writes, as with
+    /// `IteratorStability::Snapshot`, even if the datastore's iterator
+    /// stability is `Live`. Writes wait for such a read to finish, and it
+    /// waits for running writes and other such reads. Reads are always
+    /// isolated under `IteratorStability::Snapshot`.
+    pub snapshot: bool,
+}
+
+impl Default for ReadOptions {
+    /// The datastore's decode error policy, with result caching and without
+    /// snapshots beyond what the datastore's iterator stability gives.
+    fn default() -> Self {
+        ReadOptions {
+            decode_error_policy: None,
+            use_cache: true,
+            snapshot: false,
+        }
+    }
+}
+
+/// How often the maintenance thread runs, unless otherwise configured.
+const DEFAULT_MAINTENANCE_INTERVAL: StdDuration = StdDuration::from_secs(60);
+
+/// The free disk space `SledConfig::preflight` requires, unless otherwise
+/// configured.
+const DEFAULT_PREFLIGHT_MIN_FREE_SPACE: u64 = 64 * 1024 * 1024;
+
+/// How many edges `delete_edges_in_range` deletes per batch.
+const DELETE_BATCH_SIZE: usize = 1000;
+
+/// The directory of the cold database, within the datastore's. See
+/// `SledConfig::with_cold_property`.
+pub(crate) const COLD_DIR: &str = "cold";
+
+/// How many times `SledDatastore::execute` runs its closure before giving
+/// up with `Error::Conflict`.
+const MAX_EXECUTE_ATTEMPTS: u32 = 64;
+
+/// How many temporary datastores this process has opened, so that each
+/// gets a directory of its own.
+static TEMPORARY_DATASTORES: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone, Default, Debug)]
 pub struct SledConfig {
     use_compression: bool,
     compression_factor: Option<i32>,
+    untimed_edge_ranges: bool,
+    untimed_edge_types: Vec<Type>,
+    edge_sort_keys: Vec<(Type, EdgeSortKey)>,
+    undirected_edge_types: Vec<Type>,
+    datetime_precision: DatetimePrecision,
+    monotonic_edge_datetimes: bool,
+    history: bool,
+    edge_retention: Vec<(Type, Duration)>,
+    edge_constraints: Vec<(Type, EdgeConstraints)>,
+    edge_cardinalities: Vec<(Type, Type, u64)>,
+    cascade_policies: Vec<(Type, CascadePolicy)>,
+    property_limits: PropertyLimits,
+    maintenance_interval: Option<StdDuration>,
+    result_cache_capacity: Option<usize>,
+    audit_log: bool,
+    flush_interval: Option<StdDuration>,
+    flush_every: Option<u64>,
+    sled_flush_every_ms: Option<Option<u64>>,
+    cache_capacity: Option<u64>,
+    storage_mode: Option<StorageMode>,
+    segment_size: Option<usize>,
+    temporary: bool,
+    existing_datastore: ExistingDatastore,
+    durability: Durability,
+    iterator_stability: IteratorStability,
+    retry_policy: RetryPolicy,
+    recovery_check: bool,
+    preflight_min_free_space: Option<u64>,
+    derived_properties: Vec<DerivedProperty>,
+    views: Vec<(String, TraversalView)>,
+    vector_indexes: Vec<(String, usize)>,
+    composite_indexes: Vec<(String, Vec<String>)>,
+    property_compaction_limit: Option<u64>,
+    edge_write_sampling: Option<u64>,
+    degree_index: bool,
+    full_text_index: bool,
+    edge_activity: bool,
+    background_reclaim: bool,
+    value_dedup_min_len: Option<usize>,
+    cold_properties: Vec<String>,
+    decode_error_policy: DecodeErrorPolicy,
+    scrubbing: Option<(StdDuration, u64)>,
+    scrub_hook: Option<ScrubHook>,
 }
 
 impl SledConfig {
@@ -32,133 +239,3806 @@ impl SledConfig {
         SledConfig {
             use_compression: true,
             compression_factor: factor,
+            ..SledConfig::default()
         }
     }
 
-    /// Creates a new sled datastore.
-    pub fn open<P: AsRef<Path>>(self, path: P) -> Result<SledDatastore> {
-        Ok(SledDatastore {
-            holder: Arc::new(SledHolder::new(path, self)?),
-        })
+    /// Omits the update datetime from edge range keys.
+    ///
+    /// Edge ranges are then keyed by `(first_id, type, second_id)`, so
+    /// re-setting an existing edge only rewrites its entry in the edges
+    /// tree, rather than deleting and reinserting both range entries. The
+    /// tradeoff is that edges are no longer ordered by update datetime, so
+    /// `high`/`low` bounds on edge queries are applied by filtering, and the
+    /// datetime of each range entry is looked up from the edges tree.
+    ///
+    /// This changes the on-disk layout, so a datastore must always be opened
+    /// with the same setting it was created with.
+    pub fn with_untimed_edge_ranges(self) -> SledConfig {
+        SledConfig {
+            untimed_edge_ranges: true,
+            ..self
+        }
     }
-}
 
-/// The meat of a Sled datastore
-pub struct SledHolder {
-    pub(crate) db: Arc<Db>, // Derefs to Tree, holds the vertices
-    pub(crate) edges: Tree,
-    pub(crate) edge_ranges: Tree,
-    pub(crate) reversed_edge_ranges: Tree,
-    pub(crate) vertex_properties: Tree,
-    pub(crate) edge_properties: Tree,
-}
+    /// Omits the update datetime from the edge range keys of type `t`, as
+    /// `with_untimed_edge_ranges` does for all types.
+    ///
+    /// The ranges of the type are then ordered by the other vertex's ID,
+    /// which suits types that are mostly checked for existence, or whose
+    /// neighbor sets are intersected, rather than read by recency. The edge
+    /// ranges are rebuilt when the datastore is opened with different
+    /// untimed types than the last time.
+    pub fn with_untimed_edge_type(mut self, t: Type) -> SledConfig {
+        if !self.untimed_edge_types.contains(&t) {
+            self.untimed_edge_types.push(t);
+        }
 
-impl<'ds> SledHolder {
-    /// The meat of a Sled datastore.
+        self
+    }
+
+    /// Orders the edge ranges of type `t` by a property of the edges, so
+    /// that edge queries filtered to the type return edges in that order,
+    /// rather than by update datetime, without sorting them in memory.
+    ///
+    /// The property's value becomes part of the range keys, so setting or
+    /// deleting it rewrites the edge's range entries. As with
+    /// `with_untimed_edge_type`, the edge ranges are rebuilt when the
+    /// datastore is opened with different sort keys than the last time.
+    ///
+    /// # Arguments
+    /// * `t`: The edge type to sort.
+    /// * `sort_key`: The property to sort by, and how.
+    pub fn with_edge_sort_key(mut self, t: Type, sort_key: EdgeSortKey) -> SledConfig {
+        self.edge_sort_keys.retain(|(existing_t, _)| *existing_t != t);
+        self.edge_sort_keys.push((t, sort_key));
+        self
+    }
+
+    /// Treats the edges of type `t` as undirected, e.g. for mutual
+    /// relationships such as friendships.
+    ///
+    /// An edge of the type is stored once, with the lower of its vertex IDs
+    /// as its outbound one, whichever way around it's created. Getting,
+    /// deleting, or reading or writing the properties of the edge by its key
+    /// in either orientation all resolve to that one edge. Querying the
+    /// edges of a vertex in either direction finds the vertex's edges of the
+    /// type whichever end it's on, reported from that vertex's side, so
+    /// traversals step to the other vertex. Edge constraints and
+    /// cardinality limits of the type apply to both of an edge's vertices.
+    ///
+    /// The undirected types are recorded when the datastore is created, and
+    /// opening it with different ones fails with `Error::IncompatibleConfig`,
+    /// since its existing edges are stored in one orientation. Under a write
+    /// buffer, staged edges of the type are only found in the orientation
+    /// they're stored in until they're applied.
+    pub fn with_undirected_edge_type(mut self, t: Type) -> SledConfig {
+        if !self.undirected_edge_types.contains(&t) {
+            self.undirected_edge_types.push(t);
+        }
+
+        self
+    }
+
+    /// Sets the precision that edge update datetimes are stored at.
+    ///
+    /// Lower precisions shrink edge range keys by up to three bytes, at the
+    /// cost of truncating the update datetimes reported for edges, and used
+    /// for `high`/`low` bounds. Defaults to nanosecond precision.
+    ///
+    /// This changes the on-disk layout, so a datastore must always be opened
+    /// with the same precision it was created with.
+    pub fn with_datetime_precision(self, precision: DatetimePrecision) -> SledConfig {
+        SledConfig {
+            datetime_precision: precision,
+            ..self
+        }
+    }
+
+    /// Rejects writes that would move an edge's update datetime backwards,
+    /// with `Error::EdgeDatetimeNotMonotonic`. This matters once callers
+    /// supply their own datetimes with `SledTransaction::create_edge_at`,
+    /// e.g. when replaying changes that may arrive out of order, but
+    /// applies to every write of an edge, including those stamped with the
+    /// current time. Datetimes are compared at the configured precision,
+    /// and writing the same datetime again is allowed.
+    pub fn with_monotonic_edge_datetimes(self) -> SledConfig {
+        SledConfig {
+            monotonic_edge_datetimes: true,
+            ..self
+        }
+    }
+
+    /// Records the history of vertices, edges and properties, so that past
+    /// states of the graph can be read via `SledTransaction::as_of`.
+    ///
+    /// Every mutation additionally writes a record to a history tree, and
+    /// history is never pruned, so this is best suited for auditing and
+    /// debugging rather than write-heavy workloads.
+    pub fn with_history(self) -> SledConfig {
+        SledConfig { history: true, ..self }
+    }
+
+    /// Prunes edges of the given type once their update datetime is older
+    /// than `max_age`.
+    ///
+    /// Pruning is done in batches by a background maintenance thread, so
+    /// expired edges may linger for up to one maintenance interval. Pruned
+    /// edges are deleted along with their properties.
+    ///
+    /// # Arguments
+    /// * `t`: The edge type the rule applies to.
+    /// * `max_age`: How long edges of the type are retained after their
+    ///   last update.
+    pub fn with_edge_retention(mut self, t: Type, max_age: Duration) -> SledConfig {
+        self.edge_retention.push((t, max_age));
+        self
+    }
+
+    /// Enforces structural constraints on edges of type `t`, such as a
+    /// maximum out-degree. Creating an edge that violates them fails with
+    /// the corresponding `Error` variant, and writes nothing. Edges that
+    /// already exist aren't checked, so constraints can be added to a
+    /// datastore that doesn't satisfy them yet.
+    ///
+    /// # Arguments
+    /// * `t`: The edge type the constraints apply to.
+    /// * `constraints`: The constraints. These replace any previously set
+    ///   for the type.
+    pub fn with_edge_constraints(mut self, t: Type, constraints: EdgeConstraints) -> SledConfig {
+        self.edge_constraints.retain(|(existing_t, _)| *existing_t != t);
+        self.edge_constraints.push((t, constraints));
+        self
+    }
+
+    /// Limits how many edges of type `t` a vertex of type `outbound_t` can
+    /// have going out, e.g. at most one `primary_address` edge per `user`.
+    /// Creating an edge past the limit fails with
+    /// `Error::CardinalityExceeded`, and writes nothing. The count and the
+    /// write happen under a lock, so concurrent writers can't both slip in
+    /// under the limit. As with `with_edge_constraints`, existing edges
+    /// aren't checked.
+    ///
+    /// # Arguments
+    /// * `outbound_t`: The type of the outbound vertices.
+    /// * `t`: The edge type.
+    /// * `max`: The maximum number of edges. This replaces any previously
+    ///   set for the pair of types.
+    pub fn with_edge_cardinality(mut self, outbound_t: Type, t: Type, max: u64) -> SledConfig {
+        self.edge_cardinalities
+            .retain(|(existing_outbound_t, existing_t, _)| *existing_outbound_t != outbound_t || *existing_t != t);
+        self.edge_cardinalities.push((outbound_t, t, max));
+        self
+    }
+
+    /// Sets what happens to the edges of vertices of type `t` when they're
+    /// deleted. By default, they're deleted too.
+    ///
+    /// # Arguments
+    /// * `t`: The vertex type the policy applies to.
+    /// * `policy`: The policy. This replaces any previously set for the
+    ///   type.
+    pub fn with_cascade_policy(mut self, t: Type, policy: CascadePolicy) -> SledConfig {
+        self.cascade_policies.retain(|(existing_t, _)| *existing_t != t);
+        self.cascade_policies.push((t, policy));
+        self
+    }
+
+    /// Limits the size of property values and how many properties each
+    /// vertex and edge can have. Setting a property that exceeds them
+    /// fails with the corresponding `Error` variant. Properties that were
+    /// set before the limits were added aren't checked.
+    ///
+    /// # Arguments
+    /// * `limits`: The limits.
+    pub fn with_property_limits(self, limits: PropertyLimits) -> SledConfig {
+        SledConfig {
+            property_limits: limits,
+            ..self
+        }
+    }
+
+    /// Derives a property of vertices of type `t` from their other
+    /// properties. Whenever one of them is set or deleted, `derive` is
+    /// called with the rest of the vertex's properties, and its result is
+    /// stored under `name` like any other property. This keeps common
+    /// denormalizations, e.g. a lowercased name or a geohash of a
+    /// latitude and longitude, next to the data.
+    ///
+    /// `derive` should be a pure function of the properties it's given, as
+    /// it may be called any number of times.
+    ///
+    /// # Arguments
+    /// * `t`: The vertex type the property is derived for.
+    /// * `name`: The name of the derived property.
+    /// * `derive`: Computes the property, or returns `None` to delete it.
+    pub fn with_derived_property<F>(mut self, t: Type, name: &str, derive: F) -> SledConfig
+    where
+        F: Fn(&[NamedProperty]) -> Option<JsonValue> + Send + Sync + 'static,
+    {
+        self.derived_properties.push(DerivedProperty {
+            t,
+            name: name.to_string(),
+            derive: Arc::new(derive),
+        });
+        self
+    }
+
+    /// Materializes a traversal, so that the vertices reachable from each
+    /// source vertex can be read with a prefix scan rather than by running
+    /// the traversal. The view is stored in its own tree, and kept up to
+    /// date as edges and vertices are written, by recomputing the rows of
+    /// the source vertices that a change can affect. It's built when the
+    /// datastore is opened, and rebuilt if its definition changes.
+    ///
+    /// Views are best suited to short traversals over edges that change
+    /// rarely compared to how often they're read.
+    ///
+    /// # Arguments
+    /// * `name`: The name to read the view by, via
+    ///   `SledTransaction::get_view`.
+    /// * `view`: The traversal to materialize.
+    pub fn with_materialized_view(mut self, name: &str, view: TraversalView) -> SledConfig {
+        self.views.push((name.to_string(), view));
+        self
+    }
+
+    /// Makes a vertex property hold vectors of a fixed dimension, e.g.
+    /// embeddings, and indexes them so that the vertices with the vectors
+    /// most similar to a query can be found with `SledTransaction::knn`.
+    ///
+    /// Values of the property have to be arrays of `dimensions` numbers,
+    /// which are stored as JSON like any other property, and as `f32`s in
+    /// the index. The index is approximate: vectors are hashed into
+    /// buckets by random hyperplanes, and only nearby buckets are read. It's
+    /// built when the datastore is opened, and rebuilt if the dimension
+    /// changes.
+    ///
+    /// # Arguments
+    /// * `name`: The property name.
+    /// * `dimensions`: The number of elements in each vector.
+    pub fn with_vector_index(mut self, name: &str, dimensions: usize) -> SledConfig {
+        self.vector_indexes.push((name.to_string(), dimensions));
+        self
+    }
+
+    /// Indexes vertices by the values of several properties at once, e.g.
+    /// `["country", "city"]`, so that lookups by all of them, or by the
+    /// first few, read a single index rather than intersecting one per
+    /// property. Vertices that are missing any of the properties aren't
+    /// indexed. The index is built when the datastore is opened, and
+    /// rebuilt if its properties change.
+    ///
+    /// # Arguments
+    /// * `name`: The name to look the index up by, via
+    ///   `SledTransaction::get_vertices_by_properties`.
+    /// * `properties`: The property names, in the order their values are
+    ///   keyed by.
+    pub fn with_composite_index(mut self, name: &str, properties: &[&str]) -> SledConfig {
+        self.composite_indexes.push((
+            name.to_string(),
+            properties.iter().map(|property| property.to_string()).collect(),
+        ));
+        self
+    }
+
+    /// Rewrites property values into the canonical encoding from the
+    /// background maintenance thread, as `SledDatastore::compact_property_values`
+    /// does, but a bounded number at a time, so that it doesn't compete
+    /// with the datastore's workload. Each run picks up where the last one
+    /// left off, including across restarts.
+    ///
+    /// # Arguments
+    /// * `values_per_run`: The maximum number of property values checked
+    ///   per maintenance run.
+    pub fn with_property_compaction(self, values_per_run: u64) -> SledConfig {
+        SledConfig {
+            property_compaction_limit: Some(values_per_run),
+            ..self
+        }
+    }
+
+    /// Maintains the degree of every vertex, and an index of vertices
+    /// grouped by degree, so that the highest-degree vertices can be found
+    /// with `SledTransaction::top_vertices_by_degree` without scanning the
+    /// graph, e.g. to detect hubs.
+    ///
+    /// Every edge creation and deletion updates the degrees of both of its
+    /// vertices. The index is built when the datastore is first opened
+    /// with this setting, which scans all edges.
+    pub fn with_degree_index(self) -> SledConfig {
+        SledConfig {
+            degree_index: true,
+            ..self
+        }
+    }
+
+    /// Maintains an inverted index from the words of string vertex
+    /// properties to the vertices, so that vertices can be found by the
+    /// words of a property with `SledTransaction::search_vertices`.
+    ///
+    /// Words are split on anything that isn't a letter or digit, and
+    /// lowercased. Every write of a string property updates the entries of
+    /// the words that changed. The index is built when the datastore is
+    /// first opened with this setting, which scans all vertex properties.
+    pub fn with_full_text_index(self) -> SledConfig {
+        SledConfig {
+            full_text_index: true,
+            ..self
+        }
+    }
+
+    /// Tracks how many writes are made to the edges of each type, and how
+    /// many bytes they write to the edges and edge range trees, to find the
+    /// types responsible for most of the write volume. The statistics are
+    /// read via `SledDatastore::edge_write_stats`.
+    ///
+    /// Recording a write updates a stats tree, so rather than recording
+    /// every write, only one in `sample_every` can be recorded, and counted
+    /// `sample_every` times.
+    ///
+    /// # Arguments
+    /// * `sample_every`: Records one in this many edge writes. `1` records
+    ///   all of them.
+    pub fn with_edge_write_stats(self, sample_every: u64) -> SledConfig {
+        SledConfig {
+            edge_write_sampling: Some(sample_every.max(1)),
+            ..self
+        }
+    }
+
+    /// Counts the edge writes of each vertex per edge type and day, in both
+    /// directions, so that activity over time, e.g. messages sent per day,
+    /// can be read with `SledTransaction::get_edge_activity` without
+    /// scanning edge ranges.
+    ///
+    /// Every creation or update of an edge is counted on the UTC day of its
+    /// update datetime, for both of its vertices; deletions aren't
+    /// subtracted. Only writes made while this is enabled are counted.
+    pub fn with_edge_activity(self) -> SledConfig {
+        SledConfig {
+            edge_activity: true,
+            ..self
+        }
+    }
+
+    /// Stores each distinct large property value once, in a value store
+    /// shared by all vertex and edge properties, rather than once per
+    /// property, e.g. when the same schema blob is set on thousands of
+    /// vertices.
+    ///
+    /// Values whose JSON is at least `min_len` bytes long are stored by
+    /// content, with a count of the properties referring to them, and
+    /// removed once nothing does. Smaller values are stored inline, as
+    /// usual, since a reference isn't much smaller than them. Values written
+    /// by `SledDatastore::patch_vertex_properties` are always stored inline.
+    ///
+    /// Values already in the value store are still read when this is
+    /// turned off; only new writes are affected.
+    ///
+    /// # Arguments
+    /// * `min_len`: The length of the smallest values that are deduplicated.
+    pub fn with_value_dedup(self, min_len: usize) -> SledConfig {
+        SledConfig {
+            value_dedup_min_len: Some(min_len),
+            ..self
+        }
+    }
+
+    /// Stores the values of the vertex and edge properties whose names match
+    /// `pattern` in a separate, compressed "cold" database, e.g. large
+    /// payloads that are rarely read. The property trees then only hold
+    /// short references to them, so that the properties read on hot paths
+    /// stay small and uncompressed, and more of them fit in the cache.
+    ///
+    /// A pattern ending in `*` matches the names that start with what comes
+    /// before it, e.g. `payload.*`; other patterns match a name exactly.
+    /// Cold values are kept in the value store, which moves to the cold
+    /// database, in the `cold` directory of the datastore's, along with the
+    /// values deduplicated per `with_value_dedup`. It moves back when the
+    /// datastore is next opened without any cold properties. As with
+    /// deduplicated values, those written by
+    /// `SledDatastore::patch_vertex_properties` are stored inline.
+    ///
+    /// # Arguments
+    /// * `pattern`: The pattern of the names of cold properties. Patterns
+    ///   add up, rather than replacing one another.
+    pub fn with_cold_property<S: Into<String>>(mut self, pattern: S) -> SledConfig {
+        self.cold_properties.push(pattern.into());
+        self
+    }
+
+    /// Reclaims the space of dropped partitions in the background.
+    ///
+    /// `SledDatastore::drop_partition` then returns as soon as the
+    /// partition is marked as dropped, and its trees are emptied a batch at
+    /// a time by a background thread, whose progress is reported by
+    /// `SledDatastore::reclaim_progress`. The tenant can't get a partition
+    /// again until the reclaim is done. Reclaims that are interrupted by the
+    /// datastore being closed resume when it's next opened.
+    pub fn with_background_reclaim(self) -> SledConfig {
+        SledConfig {
+            background_reclaim: true,
+            ..self
+        }
+    }
+
+    /// Sets how often the background maintenance thread runs. Defaults to
+    /// once a minute.
+    pub fn with_maintenance_interval(self, interval: StdDuration) -> SledConfig {
+        SledConfig {
+            maintenance_interval: Some(interval),
+            ..self
+        }
+    }
+
+    /// Caches the results of read queries in memory, keyed by the query.
+    ///
+    /// Any mutation through the datastore invalidates the whole cache, so
+    /// this only pays off for read-mostly workloads where the same queries
+    /// repeat. Each partition has its own cache, shared by all the handles
+    /// returned by `SledDatastore::partition` for it, so a write through any
+    /// of them invalidates the results cached by the others.
+    ///
+    /// # Arguments
+    /// * `capacity`: The maximum number of cached query results. The oldest
+    ///   results are evicted first.
+    pub fn with_result_cache(self, capacity: usize) -> SledConfig {
+        SledConfig {
+            result_cache_capacity: Some(capacity),
+            ..self
+        }
+    }
+
+    /// Records mutating API calls in an append-only audit log, which can be
+    /// read via `SledDatastore::get_audit_entries`.
+    ///
+    /// Entries record when the call completed, what was called, and the
+    /// context set via `SledTransaction::with_audit_context`, if any. Like
+    /// history, the audit log is never pruned.
+    pub fn with_audit_log(self) -> SledConfig {
+        SledConfig {
+            audit_log: true,
+            ..self
+        }
+    }
+
+    /// Flushes the datastore to disk on an interval from a background
+    /// thread, in addition to sled's own periodic flushing. This bounds how
+    /// much can be lost on a crash, regardless of sled's configuration.
+    ///
+    /// # Arguments
+    /// * `interval`: How often to flush.
+    pub fn with_flush_interval(self, interval: StdDuration) -> SledConfig {
+        SledConfig {
+            flush_interval: Some(interval),
+            ..self
+        }
+    }
+
+    /// Flushes the datastore to disk after every `mutations` vertex, edge
+    /// or property writes. The write that reaches the threshold doesn't
+    /// return until the flush completes.
+    ///
+    /// # Arguments
+    /// * `mutations`: The number of writes between flushes.
+    pub fn with_flush_every(self, mutations: u64) -> SledConfig {
+        SledConfig {
+            flush_every: Some(mutations),
+            ..self
+        }
+    }
+
+    /// Sets how often sled flushes its buffers to disk from its own
+    /// background thread. Defaults to sled's default of every 500ms.
+    ///
+    /// Each background flush can briefly stall writes, so latency-sensitive
+    /// callers may want a longer period, or to disable it and flush on
+    /// their own terms via `with_flush_interval`, `with_flush_every`,
+    /// `with_durability` or `SledDatastore::flush`. With it disabled and
+    /// none of those set, writes only reach disk when sled's buffers fill
+    /// up or the datastore is dropped.
+    ///
+    /// # Arguments
+    /// * `flush_every_ms`: How many milliseconds apart sled's background
+    ///   flushes are, or `None` to disable them.
+    pub fn with_sled_flush_every_ms(self, flush_every_ms: Option<u64>) -> SledConfig {
+        SledConfig {
+            sled_flush_every_ms: Some(flush_every_ms),
+            ..self
+        }
+    }
+
+    /// Sets the size of sled's page cache. Defaults to sled's default of
+    /// 1GB. With cold properties configured, the separate database holding
+    /// them gets a cache of the same size.
+    ///
+    /// # Arguments
+    /// * `bytes`: The most memory the cache may use, in bytes.
+    pub fn with_cache_capacity(self, bytes: u64) -> SledConfig {
+        SledConfig {
+            cache_capacity: Some(bytes),
+            ..self
+        }
+    }
+
+    /// Sets whether sled favors less disk space or more write throughput.
+    /// Defaults to `StorageMode::LowSpace`. This can be changed each time
+    /// the datastore is opened.
+    pub fn with_storage_mode(self, storage_mode: StorageMode) -> SledConfig {
+        SledConfig {
+            storage_mode: Some(storage_mode),
+            ..self
+        }
+    }
+
+    /// Sets the size of the segments sled writes its log in. Defaults to
+    /// sled's default of 512KB. Larger segments batch more writes per
+    /// write to disk, at the cost of more space held by partly stale
+    /// segments.
+    ///
+    /// The size is fixed when the datastore is created, so opening an
+    /// existing datastore with a different one fails.
+    ///
+    /// # Arguments
+    /// * `bytes`: The segment size, in bytes. This must be a power of two,
+    ///   at most 16MB.
+    pub fn with_segment_size(self, bytes: usize) -> SledConfig {
+        SledConfig {
+            segment_size: Some(bytes),
+            ..self
+        }
+    }
+
+    /// Sets how durable the writes of each mutating call are by the time it
+    /// returns, unless overridden for a transaction. Defaults to
+    /// `Durability::Eventual`.
+    pub fn with_durability(self, durability: Durability) -> SledConfig {
+        SledConfig { durability, ..self }
+    }
+
+    /// Sets how queries behave when other threads write to the datastore
+    /// while they're running. Defaults to `IteratorStability::Live`.
+    pub fn with_iterator_stability(self, iterator_stability: IteratorStability) -> SledConfig {
+        SledConfig {
+            iterator_stability,
+            ..self
+        }
+    }
+
+    /// Sets what scans do when they come across a record they can't
+    /// decode. Defaults to `DecodeErrorPolicy::FailFast`; skipping lets
+    /// e.g. an export of a damaged datastore get through the rest of it.
+    pub fn with_decode_error_policy(self, decode_error_policy: DecodeErrorPolicy) -> SledConfig {
+        SledConfig {
+            decode_error_policy,
+            ..self
+        }
+    }
+
+    /// Verifies the datastore's records from a background thread, a few at
+    /// a time, so that corruption is found before a query runs into it.
+    /// Each run decodes the next `records_per_run` records, walking every
+    /// tree in turn and starting over once it's done. Sled checks the
+    /// checksum of each page it reads from disk, so damaged pages are found
+    /// along the way too.
+    ///
+    /// Problems are counted in `SledDatastore::scrub_stats`, and passed to
+    /// the hook set via `with_scrub_hook`, if any.
+    ///
+    /// # Arguments
+    /// * `interval`: How often to run.
+    /// * `records_per_run`: The most records to verify per run.
+    pub fn with_scrubbing(self, interval: StdDuration, records_per_run: u64) -> SledConfig {
+        SledConfig {
+            scrubbing: Some((interval, records_per_run)),
+            ..self
+        }
+    }
+
+    /// Calls `hook` with each problem that scrubbing finds, whether it's
+    /// run in the background per `with_scrubbing` or through
+    /// `SledDatastore::scrub`. The hook is called from the scrubbing
+    /// thread, so it should be quick, e.g. to log or raise an alert.
+    pub fn with_scrub_hook<F>(self, hook: F) -> SledConfig
+    where
+        F: Fn(&ScrubFinding) + Send + Sync + 'static,
+    {
+        SledConfig {
+            scrub_hook: Some(ScrubHook(Arc::new(hook))),
+            ..self
+        }
+    }
+
+    /// Sets how reads and writes that fail with transient sled errors are
+    /// retried. By default, they aren't.
+    pub fn with_retry_policy(self, retry_policy: RetryPolicy) -> SledConfig {
+        SledConfig { retry_policy, ..self }
+    }
+
+    /// Runs a quick consistency check when opening a datastore that wasn't
+    /// closed cleanly, and includes its summary in
+    /// `SledDatastore::recovery_info`. This scans all edges and vertex
+    /// properties, so it slows down opening large datastores after a crash.
+    pub fn with_recovery_check(self) -> SledConfig {
+        SledConfig {
+            recovery_check: true,
+            ..self
+        }
+    }
+
+    /// Sets how much free disk space `preflight` requires, in bytes.
+    /// Defaults to 64 MiB.
+    pub fn with_preflight_min_free_space(self, bytes: u64) -> SledConfig {
+        SledConfig {
+            preflight_min_free_space: Some(bytes),
+            ..self
+        }
+    }
+
+    /// Makes `open` fail with `Error::DatastoreExists` if there's already a
+    /// datastore at the path, rather than opening it, e.g. for setup tools
+    /// that mustn't write over existing data. Replaces `with_must_exist`.
+    pub fn with_create_new(self) -> SledConfig {
+        SledConfig {
+            existing_datastore: ExistingDatastore::Reject,
+            ..self
+        }
+    }
+
+    /// Makes `open` fail with `Error::DatastoreNotFound` if there's no
+    /// datastore at the path, rather than creating an empty one, so that a
+    /// mistyped path stops a service from starting with no data. Replaces
+    /// `with_create_new`.
+    pub fn with_must_exist(self) -> SledConfig {
+        SledConfig {
+            existing_datastore: ExistingDatastore::Require,
+            ..self
+        }
+    }
+
+    /// Checks that a datastore can be opened at `path` with this config,
+    /// without opening it, so that services can fail fast at startup with
+    /// a clear reason rather than partway through a request.
+    ///
+    /// This checks, in order, that the path isn't too long, that the
+    /// directory it's in can be written to and synced, that there's enough
+    /// free disk space (see `with_preflight_min_free_space`), that there
+    /// is, or isn't, a datastore there if `with_must_exist` or
+    /// `with_create_new` is set, and, if a datastore already exists there,
+    /// that it isn't open and is in a format this version of the crate
+    /// supports. Checking the format means
+    /// briefly opening the sled database, which recovers it if it wasn't
+    /// closed cleanly.
+    ///
+    /// # Errors
+    /// Returns `Error::PreflightFailed` for the first check that doesn't
+    /// pass, with a description of how to fix it.
     ///
     /// # Arguments
     /// * `path`: The file path to the Sled database.
-    /// * `opts`: Sled options to pass in.
-    pub fn new<P: AsRef<Path>>(path: P, opts: SledConfig) -> Result<SledHolder> {
+    pub fn preflight<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let min_free_space = self
+            .preflight_min_free_space
+            .unwrap_or(DEFAULT_PREFLIGHT_MIN_FREE_SPACE);
+        preflight::preflight(path, self.sled_config(path), min_free_space, self.existing_datastore)
+    }
+
+    /// Builds the config to open the sled database at `path` with.
+    pub(crate) fn sled_config<P: AsRef<Path>>(&self, path: P) -> Config {
         let mut config = Config::default().path(path);
 
-        if opts.use_compression {
-            config = config.use_compression(true);
-        }
+        if self.use_compression {
+            config = config.use_compression(true);
+        }
+
+        if let Some(compression_factor) = self.compression_factor {
+            config = config.compression_factor(compression_factor);
+        }
+
+        if let Some(flush_every_ms) = self.sled_flush_every_ms {
+            config = config.flush_every_ms(flush_every_ms);
+        }
+
+        if let Some(cache_capacity) = self.cache_capacity {
+            config = config.cache_capacity(cache_capacity);
+        }
+
+        if let Some(storage_mode) = self.storage_mode {
+            config = config.mode(match storage_mode {
+                StorageMode::LowSpace => Mode::LowSpace,
+                StorageMode::HighThroughput => Mode::HighThroughput,
+            });
+        }
+
+        if let Some(segment_size) = self.segment_size {
+            config = config.segment_size(segment_size);
+        }
+
+        if self.temporary {
+            config = config.temporary(true);
+        }
+
+        config
+    }
+
+    /// Creates a new sled datastore.
+    pub fn open<P: AsRef<Path>>(self, path: P) -> Result<SledDatastore> {
+        // `with_create_new` is checked as the database is created, by
+        // `SledHolder::new`, so that two processes can't both create it.
+        if self.existing_datastore == ExistingDatastore::Require && !preflight::datastore_exists(path.as_ref()) {
+            let path = path.as_ref().to_path_buf();
+            return Err(Error::DatastoreNotFound { path }.into());
+        }
+
+        let start = Instant::now();
+        let holder = SledHolder::new(path, &self)?;
+        let session = Session::start(&holder, start.elapsed(), self.recovery_check)?;
+        let reclaimer = Arc::new(Reclaimer::default());
+        reclaim::resume(&holder.db, &holder.metadata, &reclaimer)?;
+        let result_caches = Arc::new(ResultCaches::default());
+        Ok(SledDatastore::with_holder(
+            holder,
+            self,
+            Arc::new(session),
+            reclaimer,
+            result_caches,
+        ))
+    }
+
+    /// Creates a new sled datastore in a directory of its own under the
+    /// system's temporary directory, which sled removes once the datastore
+    /// is dropped, e.g. for tests and ephemeral caches. Nothing written to
+    /// it outlives the datastore.
+    pub fn open_temporary(self) -> Result<SledDatastore> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos());
+        let count = TEMPORARY_DATASTORES.fetch_add(1, Ordering::Relaxed);
+        let path = env::temp_dir().join(format!("indradb-sled.{}.{}.{}", process::id(), nanos, count));
+
+        SledConfig {
+            temporary: true,
+            ..self
+        }
+        .open(path)
+    }
+}
+
+/// The meat of a Sled datastore
+pub struct SledHolder {
+    pub(crate) db: Arc<Db>,
+    /// The compressed database holding the value store, if cold properties
+    /// are configured, or were when the datastore was last opened. See
+    /// `SledConfig::with_cold_property`.
+    pub(crate) cold_db: Option<Arc<Db>>,
+    pub(crate) partition: Option<u32>,
+    pub(crate) metadata: Tree,
+    pub(crate) vertices: Tree,
+    pub(crate) edges: Tree,
+    pub(crate) edge_ranges: IndexTree,
+    pub(crate) reversed_edge_ranges: IndexTree,
+    pub(crate) vertex_properties: Tree,
+    pub(crate) edge_properties: Tree,
+    pub(crate) reversed_edge_properties: IndexTree,
+    pub(crate) vertex_edge_properties: IndexTree,
+    pub(crate) vertex_creations: IndexTree,
+    pub(crate) vertex_property_values: IndexTree,
+    pub(crate) vertex_property_numbers: IndexTree,
+    pub(crate) edge_property_values: IndexTree,
+    pub(crate) edge_property_numbers: IndexTree,
+    pub(crate) edges_by_type: IndexTree,
+    pub(crate) history: HistoryTrees,
+    pub(crate) audit: AuditLog,
+    pub(crate) catalog: Tree,
+    pub(crate) edge_writes: EdgeWriteRecorder,
+    pub(crate) archive: ArchiveTrees,
+    pub(crate) values: ValueStore,
+    pub(crate) untimed_edge_ranges: bool,
+    pub(crate) edge_range_layout: EdgeRangeLayout,
+    pub(crate) datetime_precision: DatetimePrecision,
+    pub(crate) monotonic_edge_datetimes: bool,
+    pub(crate) edge_retention: Vec<(Type, Duration)>,
+    pub(crate) constraints: Constraints,
+    pub(crate) property_limits: PropertyLimits,
+    pub(crate) derived_properties: Vec<DerivedProperty>,
+    pub(crate) views: Vec<MaterializedView>,
+    pub(crate) vector_indexes: Vec<VectorIndex>,
+    pub(crate) composite_indexes: Vec<CompositeIndex>,
+    pub(crate) degree_index: Option<DegreeIndex>,
+    /// Vertices by the words of their string properties, keyed by
+    /// `(name, word, vertex ID)`. Enabled with
+    /// `SledConfig::with_full_text_index`.
+    pub(crate) full_text_index: Option<Tree>,
+    /// Edge write counts, keyed by `(vertex ID, type, day)`. Enabled with
+    /// `SledConfig::with_edge_activity`.
+    pub(crate) edge_activity: Option<Tree>,
+    pub(crate) decode_errors: Arc<DecodeErrors>,
+    pub(crate) scrubber: Scrubber,
+    /// The items processed by transaction operations, by the tag of their
+    /// `OpContext`.
+    pub(crate) tagged_work: TaggedWork,
+    pub(crate) indexed: IndexedProperties,
+    pub(crate) validators: RwLock<Vec<Arc<dyn WriteValidator>>>,
+    pub(crate) access_policy: RwLock<Option<Arc<dyn AccessPolicy>>>,
+    pub(crate) deferred_indexing: AtomicBool,
+    /// Whether a bulk load started by `SledDatastore::begin_bulk_load` is
+    /// in progress, so that property indexes aren't maintained.
+    pub(crate) bulk_loading: AtomicBool,
+    pub(crate) result_cache: Option<Arc<ResultCache>>,
+    pub(crate) pending_writes: PendingBytes,
+    pub(crate) flush_schedule: FlushSchedule,
+    /// The directory of the database, which `Durability::FsyncEachCommit`
+    /// fsyncs.
+    pub(crate) directory: Option<PathBuf>,
+    /// Bumped whenever a write guard is released, so that
+    /// `SledDatastore::execute` can tell whether anything its closure read
+    /// was written to while it ran.
+    pub(crate) write_versions: WriteVersions,
+    pub(crate) iterator_stability: IteratorStability,
+    pub(crate) retrier: Retrier,
+    // Isolates queries from writes. With `IteratorStability::Snapshot`,
+    // queries hold it for reading and mutating calls for writing; otherwise,
+    // it's the other way around, for queries with `ReadOptions::snapshot`.
+    snapshot_lock: RwLock<()>,
+    // Held for reading by mutating calls, and for writing by reindexes
+    // while they switch which trees index writes go to.
+    pub(crate) index_lock: RwLock<()>,
+    // Serializes reindexes.
+    pub(crate) reindexing: Mutex<()>,
+}
+
+impl<'ds> SledHolder {
+    /// The meat of a Sled datastore.
+    ///
+    /// # Arguments
+    /// * `path`: The file path to the Sled database.
+    /// * `opts`: Sled options to pass in.
+    pub fn new<P: AsRef<Path>>(path: P, opts: &SledConfig) -> Result<SledHolder> {
+        let directory = path.as_ref().to_path_buf();
+        let reject_existing = opts.existing_datastore == ExistingDatastore::Reject;
+        let db = match opts.sled_config(path).create_new(reject_existing).open() {
+            Err(SledError::Io(ref err)) if reject_existing && err.kind() == ErrorKind::AlreadyExists => {
+                return Err(Error::DatastoreExists { path: directory }.into());
+            }
+            result => map_err(result)?,
+        };
+
+        let result = SledHolder::with_db(db, directory.clone(), opts);
+
+        // The databases are closed by now, but sled releases their locks
+        // from its own threads, which a retried open would otherwise race.
+        if result.is_err() {
+            recovery::wait_until_closed(&directory);
+        }
+
+        result
+    }
+
+    fn with_db(db: Db, directory: PathBuf, opts: &SledConfig) -> Result<SledHolder> {
+        let metadata = map_err(db.open_tree("metadata"))?;
+        reindex::drop_stale_generations(&db, &metadata)?;
+
+        // The cold database is still opened once cold properties are turned
+        // off, so that its values can be moved back.
+        let cold_path = directory.join(COLD_DIR);
+        let cold_db = if !opts.cold_properties.is_empty() || cold_path.exists() {
+            let cold_config = opts.sled_config(cold_path).use_compression(true);
+            Some(Arc::new(map_err(cold_config.open())?))
+        } else {
+            None
+        };
+
+        let mut holder = SledHolder::with_trees(Arc::new(db), cold_db, None, opts)?;
+        holder.directory = Some(directory);
+        format::check_format(&holder)?;
+        Ok(holder)
+    }
+
+    /// Builds the key of a partition-specific entry in the metadata tree.
+    pub(crate) fn metadata_key(&self, name: &str) -> Vec<u8> {
+        self.tree_name(name).into_bytes()
+    }
+
+    /// Gets the full name of one of this partition's trees.
+    pub(crate) fn tree_name(&self, name: &str) -> String {
+        match self.partition {
+            Some(partition) => partition_tree_name(partition, name),
+            None => name.to_string(),
+        }
+    }
+
+    /// Gets the tree of a derived index.
+    pub(crate) fn index_tree(&self, index: Index) -> &IndexTree {
+        match index {
+            Index::EdgeRanges => &self.edge_ranges,
+            Index::ReversedEdgeRanges => &self.reversed_edge_ranges,
+            Index::VertexCreations => &self.vertex_creations,
+            Index::ReversedEdgeProperties => &self.reversed_edge_properties,
+            Index::VertexEdgeProperties => &self.vertex_edge_properties,
+            Index::VertexPropertyValues => &self.vertex_property_values,
+            Index::VertexPropertyNumbers => &self.vertex_property_numbers,
+            Index::EdgePropertyValues => &self.edge_property_values,
+            Index::EdgePropertyNumbers => &self.edge_property_numbers,
+            Index::EdgesByType => &self.edges_by_type,
+        }
+    }
+
+    /// Whether the values of a vertex property are indexed.
+    pub(crate) fn is_property_indexed(&self, name: &str) -> bool {
+        self.indexed.vertex.read().unwrap().contains(name)
+            || self.indexed.building_vertex.read().unwrap().contains(name)
+    }
+
+    /// Whether a property's values are kept in the value store, whatever
+    /// their length, per `SledConfig::with_cold_property`.
+    pub(crate) fn is_property_cold(&self, name: &str) -> bool {
+        self.values.cold_properties.iter().any(|pattern| {
+            if pattern.ends_with('*') {
+                name.starts_with(&pattern[..pattern.len() - 1])
+            } else {
+                name == pattern
+            }
+        })
+    }
+
+    /// Whether a vertex property's values are unique, i.e. held by at most
+    /// one vertex each.
+    pub(crate) fn is_property_unique(&self, name: &str) -> bool {
+        self.indexed.unique.read().unwrap().contains(name)
+    }
+
+    /// Whether the values of an edge property are indexed.
+    pub(crate) fn is_edge_property_indexed(&self, name: &str) -> bool {
+        self.indexed.edge.read().unwrap().contains(name) || self.indexed.building_edge.read().unwrap().contains(name)
+    }
+
+    /// Whether maintenance of derived indexes (reversed edge ranges and the
+    /// vertex creation index) is currently deferred.
+    pub(crate) fn is_indexing_deferred(&self) -> bool {
+        self.deferred_indexing.load(Ordering::Acquire)
+    }
+
+    /// Whether a bulk load is in progress, so that the property value,
+    /// number, full-text, vector and composite indexes, and the inbound-first
+    /// and per-vertex edge property indexes, aren't maintained.
+    pub(crate) fn is_bulk_loading(&self) -> bool {
+        self.bulk_loading.load(Ordering::Acquire)
+    }
+
+    /// Called by the managers after every mutation has been written.
+    pub(crate) fn notify_mutation(&self) -> Result<()> {
+        if let Some(ref result_cache) = self.result_cache {
+            result_cache.invalidate();
+        }
+
+        if let Some(flush_every) = self.flush_schedule.every {
+            if self.flush_schedule.mutations_since_flush.fetch_add(1, Ordering::AcqRel) + 1 >= flush_every {
+                self.flush()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Held for the duration of a query. With snapshot iterator stability,
+    /// this blocks writes until the query is done.
+    pub(crate) fn read_guard(&self) -> Option<RwLockReadGuard<'_, ()>> {
+        match self.iterator_stability {
+            IteratorStability::Live => None,
+            IteratorStability::Snapshot => Some(self.snapshot_lock.read().unwrap()),
+        }
+    }
+
+    /// Held for the duration of a mutating call. With snapshot iterator
+    /// stability, this blocks queries until the call is done. It also keeps
+    /// reindexes from redirecting index writes midway through the call.
+    pub(crate) fn write_guard(&self) -> WriteGuard<'_> {
+        let (snapshot, shared_snapshot) = match self.iterator_stability {
+            IteratorStability::Live => (None, Some(self.snapshot_lock.read().unwrap())),
+            IteratorStability::Snapshot => (Some(self.snapshot_lock.write().unwrap()), None),
+        };
+
+        WriteGuard {
+            _index: self.index_lock.read().unwrap(),
+            _snapshot: snapshot,
+            _shared_snapshot: shared_snapshot,
+            versions: &self.write_versions,
+            touched: RefCell::new(Some(Vec::new())),
+        }
+    }
+
+    /// Like `write_guard`, but excludes other writers whatever the iterator
+    /// stability.
+    pub(crate) fn exclusive_write_guard(&self) -> WriteGuard<'_> {
+        let snapshot = self.snapshot_lock.write().unwrap();
+
+        WriteGuard {
+            _index: self.index_lock.read().unwrap(),
+            _snapshot: Some(snapshot),
+            _shared_snapshot: None,
+            versions: &self.write_versions,
+            touched: RefCell::new(Some(Vec::new())),
+        }
+    }
+
+    /// Serializes edge creation while any edge cardinality limits are set,
+    /// so that an edge's limit can't change between checking and writing it.
+    pub(crate) fn cardinality_guard(&self) -> Option<MutexGuard<'_, ()>> {
+        if self.constraints.cardinalities.is_empty() {
+            None
+        } else {
+            Some(self.constraints.cardinality_lock.lock().unwrap())
+        }
+    }
+
+    /// Flushes the database to disk, recording when it happened.
+    pub(crate) fn flush(&self) -> Result<()> {
+        self.flush_schedule.mutations_since_flush.store(0, Ordering::Release);
+        map_err(self.db.flush())?;
+
+        if let Some(ref cold_db) = self.cold_db {
+            map_err(cold_db.flush())?;
+        }
+
+        *self.flush_schedule.last_flush.lock().unwrap() = Some(Utc::now());
+        Ok(())
+    }
+
+    /// Makes the writes made so far as durable as `durability` requires.
+    pub(crate) fn make_durable(&self, durability: Durability) -> Result<()> {
+        if durability == Durability::Eventual {
+            return Ok(());
+        }
+
+        self.flush()?;
+
+        if durability == Durability::FsyncEachCommit {
+            if let Some(ref directory) = self.directory {
+                map_io_err(File::open(directory).and_then(|directory| directory.sync_all()))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Opens the trees of a partition of an already opened database. The
+    /// unpartitioned data lives in the original tree names, with vertices in
+    /// sled's default tree. The metadata tree is shared by all partitions.
+    ///
+    /// # Arguments
+    /// * `db`: The sled database.
+    /// * `cold_db`: The cold database, if it's been opened.
+    /// * `partition`: The partition to open, if any.
+    /// * `opts`: Sled options to pass in.
+    pub fn with_trees(
+        db: Arc<Db>,
+        cold_db: Option<Arc<Db>>,
+        partition: Option<u32>,
+        opts: &SledConfig,
+    ) -> Result<SledHolder> {
+        let open_tree = |name: &str| match partition {
+            Some(partition) => map_err(db.open_tree(partition_tree_name(partition, name))),
+            None => map_err(db.open_tree(name)),
+        };
+
+        // The value store moves to the cold database when cold properties
+        // are turned on, and back when they're turned off.
+        let value_store = open_tree("value_store")?;
+        let value_store = match cold_db {
+            Some(ref cold_db) => {
+                let cold_value_store = match partition {
+                    Some(partition) => map_err(cold_db.open_tree(partition_tree_name(partition, "value_store")))?,
+                    None => map_err(cold_db.open_tree("value_store"))?,
+                };
+
+                if opts.cold_properties.is_empty() {
+                    dedup::move_values(&cold_value_store, &value_store)?;
+                    value_store
+                } else {
+                    dedup::move_values(&value_store, &cold_value_store)?;
+                    cold_value_store
+                }
+            }
+            None => value_store,
+        };
+
+        let vertices = match partition {
+            Some(_) => open_tree("vertices")?,
+            None => Tree::clone(&db),
+        };
+
+        let metadata = map_err(db.open_tree("metadata"))?;
+
+        let open_index_tree = |name: &str| match partition {
+            Some(partition) => reindex::open_index_tree(&db, &metadata, &partition_tree_name(partition, name)),
+            None => reindex::open_index_tree(&db, &metadata, name),
+        };
+
+        // Deferred indexing survives restarts, so that an interrupted bulk
+        // load can still be finished.
+        let deferred_indexing_key = match partition {
+            Some(partition) => partition_tree_name(partition, DEFERRED_INDEXING_KEY),
+            None => DEFERRED_INDEXING_KEY.to_string(),
+        };
+        let deferred_indexing = map_err(metadata.contains_key(deferred_indexing_key))?;
+        let bulk_load_key = match partition {
+            Some(partition) => partition_tree_name(partition, BULK_LOAD_KEY),
+            None => BULK_LOAD_KEY.to_string(),
+        };
+        let bulk_loading = map_err(metadata.contains_key(bulk_load_key))?;
+
+        let edge_ranges = open_index_tree(Index::EdgeRanges.name())?;
+        let reversed_edge_ranges = open_index_tree(Index::ReversedEdgeRanges.name())?;
+        let reversed_edge_properties = open_index_tree(Index::ReversedEdgeProperties.name())?;
+        let vertex_edge_properties = open_index_tree(Index::VertexEdgeProperties.name())?;
+        let vertex_creations = open_index_tree(Index::VertexCreations.name())?;
+        let vertex_property_values = open_index_tree(Index::VertexPropertyValues.name())?;
+        let vertex_property_numbers = open_index_tree(Index::VertexPropertyNumbers.name())?;
+        let edge_property_values = open_index_tree(Index::EdgePropertyValues.name())?;
+        let edge_property_numbers = open_index_tree(Index::EdgePropertyNumbers.name())?;
+        let edges_by_type = open_index_tree(Index::EdgesByType.name())?;
+
+        let mut views = Vec::with_capacity(opts.views.len());
+        for (name, definition) in &opts.views {
+            views.push(MaterializedView {
+                name: name.clone(),
+                definition: definition.clone(),
+                tree: open_tree(&format!("view:{}", name))?,
+            });
+        }
+
+        let mut vector_indexes = Vec::with_capacity(opts.vector_indexes.len());
+        for &(ref name, dimensions) in &opts.vector_indexes {
+            let tree = open_tree(&format!("vector_index:{}", name))?;
+            vector_indexes.push(VectorIndex::new(name, dimensions, tree));
+        }
+
+        let mut composite_indexes = Vec::with_capacity(opts.composite_indexes.len());
+        for (name, properties) in &opts.composite_indexes {
+            composite_indexes.push(CompositeIndex {
+                name: name.clone(),
+                properties: properties.clone(),
+                tree: open_tree(&format!("composite_index:{}", name))?,
+            });
+        }
+
+        let degree_index = if opts.degree_index {
+            Some(DegreeIndex {
+                counters: open_tree("degrees")?,
+                order: open_tree("degree_order")?,
+            })
+        } else {
+            None
+        };
+
+        let full_text_index = if opts.full_text_index {
+            Some(open_tree("full_text_index")?)
+        } else {
+            None
+        };
+
+        let edge_activity = if opts.edge_activity {
+            Some(open_tree("edge_activity")?)
+        } else {
+            None
+        };
+
+        let holder = SledHolder {
+            partition,
+            metadata,
+            vertices,
+            edges: open_tree("edges")?,
+            edge_ranges,
+            reversed_edge_ranges,
+            vertex_properties: open_tree("vertex_properties")?,
+            edge_properties: open_tree("edge_properties")?,
+            reversed_edge_properties,
+            vertex_edge_properties,
+            vertex_creations,
+            vertex_property_values,
+            vertex_property_numbers,
+            edge_property_values,
+            edge_property_numbers,
+            edges_by_type,
+            history: HistoryTrees {
+                enabled: opts.history,
+                vertices: open_tree("vertex_history")?,
+                edges: open_tree("edge_history")?,
+                vertex_properties: open_tree("vertex_property_history")?,
+                edge_properties: open_tree("edge_property_history")?,
+            },
+            audit: AuditLog {
+                enabled: opts.audit_log,
+                entries: open_tree("audit_log")?,
+            },
+            catalog: open_tree("catalog")?,
+            edge_writes: EdgeWriteRecorder {
+                tree: open_tree("edge_write_stats")?,
+                sampling: opts.edge_write_sampling,
+                seen: AtomicU64::new(0),
+            },
+            archive: ArchiveTrees {
+                vertices: open_tree("archived_vertices")?,
+                vertex_properties: open_tree("archived_vertex_properties")?,
+                edges: open_tree("archived_edges")?,
+                edge_properties: open_tree("archived_edge_properties")?,
+            },
+            values: ValueStore {
+                store: value_store,
+                dedup_min_len: opts.value_dedup_min_len,
+                cold_properties: opts.cold_properties.clone(),
+            },
+            untimed_edge_ranges: opts.untimed_edge_ranges,
+            edge_range_layout: EdgeRangeLayout::new(
+                opts.untimed_edge_ranges,
+                &opts.untimed_edge_types,
+                &opts.edge_sort_keys,
+                &opts.undirected_edge_types,
+            ),
+            datetime_precision: opts.datetime_precision,
+            monotonic_edge_datetimes: opts.monotonic_edge_datetimes,
+            edge_retention: opts.edge_retention.clone(),
+            constraints: Constraints {
+                edges: opts.edge_constraints.iter().cloned().collect(),
+                cardinalities: opts
+                    .edge_cardinalities
+                    .iter()
+                    .map(|&(ref outbound_t, ref t, max)| ((outbound_t.clone(), t.clone()), max))
+                    .collect(),
+                cardinality_lock: Mutex::new(()),
+                cascade_policies: opts.cascade_policies.iter().cloned().collect(),
+            },
+            property_limits: opts.property_limits,
+            derived_properties: opts.derived_properties.clone(),
+            views,
+            vector_indexes,
+            composite_indexes,
+            degree_index,
+            full_text_index,
+            edge_activity,
+            decode_errors: Arc::new(DecodeErrors::new(opts.decode_error_policy)),
+            scrubber: Scrubber::new(opts.scrub_hook.clone()),
+            tagged_work: TaggedWork::default(),
+            indexed: IndexedProperties::new(open_tree("unique_values")?),
+            validators: RwLock::new(Vec::new()),
+            access_policy: RwLock::new(None),
+            deferred_indexing: AtomicBool::new(deferred_indexing),
+            bulk_loading: AtomicBool::new(bulk_loading),
+            result_cache: opts
+                .result_cache_capacity
+                .map(|capacity| Arc::new(ResultCache::new(capacity))),
+            pending_writes: PendingBytes::default(),
+            flush_schedule: FlushSchedule {
+                every: opts.flush_every,
+                mutations_since_flush: AtomicU64::new(0),
+                durability: opts.durability,
+                last_flush: Mutex::new(None),
+            },
+            directory: None,
+            write_versions: WriteVersions::default(),
+            iterator_stability: opts.iterator_stability,
+            retrier: Retrier::new(opts.retry_policy),
+            snapshot_lock: RwLock::new(()),
+            index_lock: RwLock::new(()),
+            reindexing: Mutex::new(()),
+            db,
+            cold_db,
+        };
+
+        format::load_datetime_values(&holder)?;
+        indexed::load(&holder)?;
+        layout::reindex_if_changed(&holder)?;
+        views::rebuild_stale(&holder)?;
+        degrees::rebuild_stale(&holder)?;
+        fulltext::rebuild_stale(&holder)?;
+        vectors::rebuild_stale(&holder)?;
+        composite::rebuild_stale(&holder)?;
+        Ok(holder)
+    }
+}
+
+const DEFERRED_INDEXING_KEY: &str = "deferred_indexing";
+const BULK_LOAD_KEY: &str = "bulk_load";
+
+/// Returned by `SledHolder::write_guard`.
+pub(crate) struct WriteGuard<'a> {
+    _index: RwLockReadGuard<'a, ()>,
+    _snapshot: Option<RwLockWriteGuard<'a, ()>>,
+    _shared_snapshot: Option<RwLockReadGuard<'a, ()>>,
+    versions: &'a WriteVersions,
+    // The vertices written to, or `None` once a write can't be pinned to
+    // any. A guard that's never touched is assumed to write to anything.
+    touched: RefCell<Option<Vec<Uuid>>>,
+}
+
+impl<'a> WriteGuard<'a> {
+    /// Notes that the call writes only to `ids` and their edges, so that
+    /// `SledDatastore::execute` calls reading other vertices needn't retry.
+    pub(crate) fn touch(&self, ids: &[Uuid]) {
+        if let Some(touched) = self.touched.borrow_mut().as_mut() {
+            touched.extend_from_slice(ids);
+        }
+    }
+}
+
+impl<'a> Drop for WriteGuard<'a> {
+    fn drop(&mut self) {
+        // The locks are still held here, since fields are dropped after.
+        let touched = self.touched.get_mut().take().filter(|ids| !ids.is_empty());
+        self.versions.record_write(touched.as_deref());
+    }
+}
+
+/// Held for the duration of a query, with the transaction's read options
+/// applied. See `SledTransaction::read_guard`.
+pub(crate) struct ReadGuard<'a> {
+    _snapshot: Option<RwLockReadGuard<'a, ()>>,
+    _exclusive_snapshot: Option<RwLockWriteGuard<'a, ()>>,
+    _policy: Option<PolicyOverride>,
+}
+
+pub(crate) fn partition_tree_prefix(partition: u32) -> String {
+    format!("partition:{}:", partition)
+}
+
+fn partition_tree_name(partition: u32, name: &str) -> String {
+    format!("{}{}", partition_tree_prefix(partition), name)
+}
+
+/// An approximate breakdown of the memory held by a datastore handle, on
+/// top of sled's own page cache.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// The number of query results in the result cache.
+    pub result_cache_entries: usize,
+    /// The approximate size of the result cache, in bytes.
+    pub result_cache_bytes: usize,
+    /// The approximate size of the writes staged by uncommitted batches and
+    /// write buffers, and of those being applied, in bytes.
+    pub pending_write_bytes: usize,
+}
+
+impl MemoryUsage {
+    /// The approximate total, in bytes.
+    pub fn total_bytes(&self) -> usize {
+        self.result_cache_bytes + self.pending_write_bytes
+    }
+}
+
+/// The edges of one type pointing at a vertex. See
+/// `SledTransaction::inbound_summary`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InboundTypeSummary {
+    /// The edge type.
+    pub t: Type,
+    /// The number of inbound edges of the type.
+    pub count: u64,
+    /// The most recent update datetime among those edges.
+    pub latest_update_datetime: DateTime<Utc>,
+}
+
+/// How often a property name is used.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PropertyNameUsage {
+    /// The property name.
+    pub name: String,
+    /// The number of vertices with a property of this name.
+    pub vertex_count: u64,
+    /// The number of edges with a property of this name.
+    pub edge_count: u64,
+}
+
+/// A datastore that is backed by Sled.
+pub struct SledDatastore {
+    pub(crate) holder: Arc<SledHolder>,
+    config: SledConfig,
+    // Shared with partitions, so that any of them can report on reclaims.
+    reclaimer: Arc<Reclaimer>,
+    // Shared with partitions, so that every handle on a partition uses the
+    // same result cache.
+    result_caches: Arc<ResultCaches>,
+    // Stops the maintenance threads when the datastore is dropped.
+    _maintenance: Option<MaintenanceHandle>,
+    _flusher: Option<MaintenanceHandle>,
+    _scrubber: Option<MaintenanceHandle>,
+    // Shared with partitions, so that the datastore is marked as closed
+    // once the last handle on it is dropped. It's dropped last, since it
+    // waits for the database to be closed.
+    session: Arc<Session>,
+}
+
+impl<'ds> SledDatastore {
+    /// Creates a new Sled datastore.
+    ///
+    /// # Arguments
+    /// * `path`: The file path to the Sled database.
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<SledDatastore> {
+        SledConfig::default().open(path)
+    }
+
+    /// Creates a new Sled datastore that's removed once it's dropped. See
+    /// `SledConfig::open_temporary`.
+    pub fn temporary() -> Result<SledDatastore> {
+        SledConfig::default().open_temporary()
+    }
+
+    fn with_holder(
+        mut holder: SledHolder,
+        config: SledConfig,
+        session: Arc<Session>,
+        reclaimer: Arc<Reclaimer>,
+        result_caches: Arc<ResultCaches>,
+    ) -> SledDatastore {
+        holder.result_cache = config
+            .result_cache_capacity
+            .map(|capacity| result_caches.get(holder.partition, capacity));
+        let holder = Arc::new(holder);
+
+        let maintenance = if holder.edge_retention.is_empty() && config.property_compaction_limit.is_none() {
+            None
+        } else {
+            let interval = config.maintenance_interval.unwrap_or(DEFAULT_MAINTENANCE_INTERVAL);
+            Some(MaintenanceHandle::spawn_maintenance(
+                &holder,
+                interval,
+                config.property_compaction_limit,
+            ))
+        };
+
+        let flusher = config
+            .flush_interval
+            .map(|interval| MaintenanceHandle::spawn_flusher(&holder, interval));
+
+        let scrubber = config
+            .scrubbing
+            .map(|(interval, records_per_run)| MaintenanceHandle::spawn_scrubber(&holder, interval, records_per_run));
+
+        SledDatastore {
+            holder,
+            config,
+            session,
+            reclaimer,
+            result_caches,
+            _maintenance: maintenance,
+            _flusher: flusher,
+            _scrubber: scrubber,
+        }
+    }
+
+    /// Reports the memory held by this partition's caches and pending
+    /// writes, so that embedding applications can account for it. sled's
+    /// page cache is bounded by its own configuration, and is not included.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let mut usage = MemoryUsage {
+            pending_write_bytes: self.holder.pending_writes.get(),
+            ..MemoryUsage::default()
+        };
+
+        if let Some(ref result_cache) = self.holder.result_cache {
+            let (entries, bytes) = result_cache.usage();
+            usage.result_cache_entries = entries;
+            usage.result_cache_bytes = bytes;
+        }
+
+        usage
+    }
+
+    /// Releases the memory held by this partition's caches, e.g. when the
+    /// embedding application is under memory pressure. Caches refill as
+    /// queries are run.
+    pub fn shrink_caches(&self) {
+        if let Some(ref result_cache) = self.holder.result_cache {
+            result_cache.clear();
+        }
+    }
+
+    /// The number of times a read or write has been retried after a
+    /// transient sled error, per `SledConfig::with_retry_policy`.
+    pub fn retried_operations(&self) -> u64 {
+        self.holder.retrier.retries()
+    }
+
+    /// Gets the records that scans have skipped since the datastore was
+    /// opened, per `SledConfig::with_decode_error_policy`.
+    pub fn skipped_records(&self) -> SkippedRecords {
+        self.holder.decode_errors.skipped()
+    }
+
+    /// Gets what scrubbing has verified and found since the datastore was
+    /// opened.
+    pub fn scrub_stats(&self) -> ScrubStats {
+        self.holder.scrubber.stats()
+    }
+
+    /// Immediately verifies up to `max_records` records, picking up where
+    /// the last run of scrubbing left off, rather than waiting for the
+    /// thread set up by `SledConfig::with_scrubbing`. Stops early at the
+    /// end of a pass over every tree. Returns the number of records
+    /// verified; problems are reported as they are in the background.
+    pub fn scrub(&self, max_records: u64) -> u64 {
+        scrub::scrub(&self.holder, max_records)
+    }
+
+    /// Gets the number of items that transaction operations have scanned or
+    /// deleted since the datastore was opened, by the tag of the
+    /// `OpContext` they ran under. Untagged work isn't counted.
+    pub fn work_by_tag(&self) -> HashMap<String, u64> {
+        self.holder.tagged_work.lock().unwrap().clone()
+    }
+
+    /// Gets the write statistics of each edge type that's been written to
+    /// since they were enabled with `SledConfig::with_edge_write_stats`, or
+    /// last reset, in type order. With sampling, the numbers are estimates.
+    pub fn edge_write_stats(&self) -> Result<Vec<EdgeWriteStats>> {
+        stats::edge_write_stats(&self.holder)
+    }
+
+    /// Clears the write statistics of every edge type, e.g. to measure the
+    /// writes of a period of time.
+    pub fn reset_edge_write_stats(&self) -> Result<()> {
+        stats::reset_edge_write_stats(&self.holder)
+    }
+
+    /// Flushes the datastore to disk, and waits until its writes are
+    /// durable, e.g. at a checkpoint in the application. This is the same
+    /// as `Datastore::sync`.
+    pub fn flush(&self) -> Result<()> {
+        self.holder.flush()
+    }
+
+    /// Starts flushing the datastore to disk on a background thread, and
+    /// returns a future that resolves once the writes made before the call
+    /// are durable. The flush happens whether or not the future is polled.
+    pub fn flush_async(&self) -> impl Future<Output = Result<()>> + Send {
+        FlushFuture::spawn(self.holder.clone())
+    }
+
+    /// When the datastore was last flushed to disk through this handle,
+    /// either explicitly or by the flush settings in `SledConfig`. This is
+    /// `None` if it hasn't been flushed since it was opened.
+    pub fn last_flush(&self) -> Option<DateTime<Utc>> {
+        *self.holder.flush_schedule.last_flush.lock().unwrap()
+    }
+
+    /// Describes how the datastore was opened: whether sled recovered it
+    /// after an unclean shutdown, how long that took, and what remains to
+    /// be done to salvage it.
+    pub fn recovery_info(&self) -> &RecoveryInfo {
+        &self.session.info
+    }
+
+    /// Gets the on-disk format version of this datastore. Datastores created
+    /// before format versioning are reported as version 0.
+    pub fn format_version(&self) -> Result<u64> {
+        format::read_format_version(&self.holder.metadata)
+    }
+
+    /// Upgrades the datastore to the current on-disk format version
+    /// (`FORMAT_VERSION`), returning the resulting version.
+    ///
+    /// Opening a datastore already migrates it. Migrations are crash-safe:
+    /// an interrupted one resumes when this is called again, or the next
+    /// time the datastore is opened. Avoid writing to the datastore while
+    /// it's migrating.
+    pub fn migrate_format(&self) -> Result<u64> {
+        format::migrate(&self.holder)
+    }
+
+    /// Rebuilds a derived index from the data it indexes, e.g. to recover
+    /// from a corrupted index, without making the datastore unavailable.
+    ///
+    /// The index is built into a new tree while queries keep reading the
+    /// old one, and writes go to both. Once it's built, the new tree is
+    /// swapped in. Writes are briefly paused as each chunk of the data is
+    /// indexed, but never for the whole rebuild. The old tree's space is
+    /// reclaimed the next time the datastore is opened.
+    ///
+    /// Datastores previously obtained for the same partition via
+    /// `partition` keep using the old tree, and must not be used
+    /// afterwards.
+    ///
+    /// # Arguments
+    /// * `index`: The index to rebuild.
+    pub fn reindex(&self, index: Index) -> Result<()> {
+        reindex::reindex(&self.holder, index)
+    }
+
+    /// Starts indexing the values of a vertex property, so that vertices can
+    /// be looked up by it with `SledTransaction::get_vertices_by_property`
+    /// and `SledTransaction::get_vertices_with_property_in_range`. Returns
+    /// whether it wasn't indexed yet.
+    ///
+    /// The declaration is persisted, and applies to every write from then
+    /// on. The property's existing values are indexed first, which reads
+    /// every vertex property; writes are briefly paused as each chunk of
+    /// them is indexed, and queries of the property fail with
+    /// `Error::PropertyIndexBuilding` until it's done.
+    ///
+    /// # Arguments
+    /// * `name`: The property name.
+    pub fn index_property(&self, name: &str) -> Result<bool> {
+        indexed::index_property(&self.holder, PropertyOwner::Vertex, name, false)
+    }
+
+    /// Starts indexing the values of a vertex property, as `index_property`
+    /// does, and makes them unique: setting the property to a value that
+    /// another vertex has fails with `Error::UniqueValueTaken`. Returns
+    /// whether the property wasn't indexed yet; a property that's already
+    /// indexed has to be dropped with `drop_index` to make it unique.
+    ///
+    /// If two vertices already share a value, this fails with
+    /// `Error::UniqueValueTaken`, and the property is left unindexed.
+    ///
+    /// # Arguments
+    /// * `name`: The property name.
+    pub fn index_unique_property(&self, name: &str) -> Result<bool> {
+        indexed::index_property(&self.holder, PropertyOwner::Vertex, name, true)
+    }
+
+    /// Rebuilds the index entries of a vertex property declared with
+    /// `index_property` from its values, e.g. after they were lost or
+    /// corrupted. Returns how many properties were read and indexed.
+    ///
+    /// As with `index_property`, writes are only paused while each chunk of
+    /// the vertex properties is indexed, and queries of the property fail
+    /// with `Error::PropertyIndexBuilding` until it's done.
+    ///
+    /// # Arguments
+    /// * `name`: The property name.
+    pub fn rebuild_index(&self, name: &str) -> Result<IndexBuildProgress> {
+        indexed::rebuild_index(&self.holder, PropertyOwner::Vertex, name, &mut |_| {})
+    }
+
+    /// Rebuilds the index entries of a vertex property, as
+    /// `rebuild_index` does, calling `on_progress` after each chunk.
+    ///
+    /// # Arguments
+    /// * `name`: The property name.
+    /// * `on_progress`: Called with the progress so far after each chunk,
+    ///   while writes aren't paused.
+    pub fn rebuild_index_with_progress<F>(&self, name: &str, mut on_progress: F) -> Result<IndexBuildProgress>
+    where
+        F: FnMut(&IndexBuildProgress),
+    {
+        indexed::rebuild_index(&self.holder, PropertyOwner::Vertex, name, &mut on_progress)
+    }
+
+    /// Stops indexing the values of a vertex property, and removes its
+    /// index entries. Returns whether it was indexed.
+    ///
+    /// # Arguments
+    /// * `name`: The property name.
+    pub fn drop_index(&self, name: &str) -> Result<bool> {
+        indexed::drop_index(&self.holder, PropertyOwner::Vertex, name)
+    }
+
+    /// Gets the names of the indexed vertex properties, in name order.
+    pub fn indexed_properties(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.holder.indexed.vertex.read().unwrap().iter().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Starts indexing the values of an edge property, so that edges can be
+    /// looked up by it with `SledTransaction::get_edges_by_property` and
+    /// `SledTransaction::get_edges_with_property_in_range`. Returns whether
+    /// it wasn't indexed yet.
+    ///
+    /// As with `index_property`, the declaration is persisted, and the
+    /// property's existing values are indexed first, reading every edge
+    /// property.
+    ///
+    /// # Arguments
+    /// * `name`: The property name.
+    pub fn index_edge_property(&self, name: &str) -> Result<bool> {
+        indexed::index_property(&self.holder, PropertyOwner::Edge, name, false)
+    }
+
+    /// Rebuilds the index entries of an edge property declared with
+    /// `index_edge_property` from its values, as `rebuild_index` does for
+    /// vertex properties.
+    ///
+    /// # Arguments
+    /// * `name`: The property name.
+    pub fn rebuild_edge_index(&self, name: &str) -> Result<IndexBuildProgress> {
+        indexed::rebuild_index(&self.holder, PropertyOwner::Edge, name, &mut |_| {})
+    }
+
+    /// Rebuilds the index entries of an edge property, as
+    /// `rebuild_edge_index` does, calling `on_progress` after each chunk.
+    ///
+    /// # Arguments
+    /// * `name`: The property name.
+    /// * `on_progress`: Called with the progress so far after each chunk,
+    ///   while writes aren't paused.
+    pub fn rebuild_edge_index_with_progress<F>(&self, name: &str, mut on_progress: F) -> Result<IndexBuildProgress>
+    where
+        F: FnMut(&IndexBuildProgress),
+    {
+        indexed::rebuild_index(&self.holder, PropertyOwner::Edge, name, &mut on_progress)
+    }
+
+    /// Stops indexing the values of an edge property, and removes its index
+    /// entries. Returns whether it was indexed.
+    ///
+    /// # Arguments
+    /// * `name`: The property name.
+    pub fn drop_edge_index(&self, name: &str) -> Result<bool> {
+        indexed::drop_index(&self.holder, PropertyOwner::Edge, name)
+    }
+
+    /// Gets the names of the indexed edge properties, in name order.
+    pub fn indexed_edge_properties(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.holder.indexed.edge.read().unwrap().iter().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Stops maintaining the reversed edge ranges, the vertex creation index
+    /// and the edge type index, to speed up bulk loads. They are rebuilt in
+    /// a single pass by `finish_deferred_indexing`.
+    ///
+    /// Until then, inbound edge queries and counts, `recent_vertices`,
+    /// `get_edges_by_type`, and
+    /// the cascading deletion of inbound edges when deleting a vertex won't
+    /// see data written in the meantime. Deferred indexing persists across
+    /// restarts until it's finished.
+    pub fn begin_deferred_indexing(&self) -> Result<()> {
+        let key = self.holder.metadata_key(DEFERRED_INDEXING_KEY);
+        map_err(self.holder.metadata.insert(key, &[]))?;
+        map_err(self.holder.metadata.flush())?;
+        self.holder.deferred_indexing.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    /// Resumes maintaining derived indexes, rebuilding them from the primary
+    /// data in parallel.
+    pub fn finish_deferred_indexing(&self) -> Result<()> {
+        let _guard = self.holder.write_guard();
+        self.finish_deferred_indexing_locked()
+    }
+
+    fn finish_deferred_indexing_locked(&self) -> Result<()> {
+        if !self.holder.is_indexing_deferred() {
+            return Ok(());
+        }
+
+        rebuild::rebuild_reversed_edge_ranges(&self.holder)?;
+        rebuild::rebuild_vertex_creations(&self.holder)?;
+        rebuild::rebuild_edges_by_type(&self.holder)?;
+        views::rebuild_all(&self.holder)?;
+        degrees::rebuild(&self.holder)?;
+        self.holder.notify_mutation()?;
+
+        // Only resume incremental maintenance once the rebuild is durable,
+        // so a crash mid-rebuild leaves indexing deferred.
+        map_err(self.holder.db.flush())?;
+        let key = self.holder.metadata_key(DEFERRED_INDEXING_KEY);
+        map_err(self.holder.metadata.remove(key))?;
+        map_err(self.holder.metadata.flush())?;
+        self.holder.deferred_indexing.store(false, Ordering::Release);
+        Ok(())
+    }
+
+    /// Whether index maintenance is currently deferred.
+    pub fn is_indexing_deferred(&self) -> bool {
+        self.holder.is_indexing_deferred()
+    }
+
+    /// Starts a bulk load, e.g. for an initial import. On top of deferring
+    /// the indexes that `begin_deferred_indexing` does, this stops
+    /// maintaining every secondary index of properties: the value and
+    /// number indexes of vertex and edge properties, the full-text, vector
+    /// and composite indexes, and the inbound-first and per-vertex edge
+    /// property indexes. `finish_bulk_load` rebuilds them all.
+    ///
+    /// Until then, lookups of indexed properties fail with
+    /// `Error::PropertyIndexBuilding`, and full-text, vector and composite
+    /// lookups, and inbound edge property queries, won't see data written
+    /// in the meantime. Unique values are still enforced. The bulk load
+    /// persists across restarts until it's finished.
+    pub fn begin_bulk_load(&self) -> Result<()> {
+        self.begin_deferred_indexing()?;
+        let key = self.holder.metadata_key(BULK_LOAD_KEY);
+        map_err(self.holder.metadata.insert(key, &[]))?;
+        map_err(self.holder.metadata.flush())?;
+        self.holder.bulk_loading.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    /// Finishes a bulk load, rebuilding the property indexes with one pass
+    /// over each of the property trees, then the indexes that were
+    /// deferred, as `finish_deferred_indexing` does. Writes are paused
+    /// until it's done.
+    pub fn finish_bulk_load(&self) -> Result<()> {
+        let _guard = self.holder.write_guard();
+        if !self.holder.is_bulk_loading() {
+            return Ok(());
+        }
+
+        rebuild::rebuild_vertex_property_indexes(&self.holder)?;
+        rebuild::rebuild_edge_property_indexes(&self.holder)?;
+
+        // As with deferred indexing, only resume incremental maintenance
+        // once the rebuild is durable.
+        map_err(self.holder.db.flush())?;
+        let key = self.holder.metadata_key(BULK_LOAD_KEY);
+        map_err(self.holder.metadata.remove(key))?;
+        map_err(self.holder.metadata.flush())?;
+        self.holder.bulk_loading.store(false, Ordering::Release);
+        self.finish_deferred_indexing_locked()
+    }
+
+    /// Whether a bulk load is in progress.
+    pub fn is_bulk_loading(&self) -> bool {
+        self.holder.is_bulk_loading()
+    }
+
+    /// Registers a validator that can veto mutations before they're
+    /// written. Validators run in the order they were added, and apply to
+    /// every transaction, including ones that were already open. Partitions
+    /// created afterwards inherit the validators registered by then.
+    ///
+    /// # Arguments
+    /// * `validator`: The validator to add.
+    pub fn add_write_validator<V: WriteValidator + 'static>(&self, validator: V) {
+        self.holder.validators.write().unwrap().push(Arc::new(validator));
+    }
+
+    /// Sets the policy deciding which operations are allowed, replacing any
+    /// previous one. Like validators, it applies to every transaction,
+    /// including ones that were already open, and partitions created
+    /// afterwards inherit it.
+    ///
+    /// # Arguments
+    /// * `policy`: The policy to set.
+    pub fn set_access_policy<P: AccessPolicy + 'static>(&self, policy: P) {
+        *self.holder.access_policy.write().unwrap() = Some(Arc::new(policy));
+    }
+
+    /// Gets the audit log entries recorded between `low` and `high`
+    /// (inclusive), oldest first.
+    ///
+    /// This requires the datastore to have been opened with
+    /// `SledConfig::with_audit_log`.
+    ///
+    /// # Arguments
+    /// * `low`: The earliest entries to return, if any.
+    /// * `high`: The latest entries to return, if any.
+    pub fn get_audit_entries(
+        &self,
+        low: Option<DateTime<Utc>>,
+        high: Option<DateTime<Utc>>,
+    ) -> Result<Vec<AuditEntry>> {
+        if !self.holder.audit.enabled {
+            return Err(Error::AuditLogDisabled.into());
+        }
+
+        audit::entries(&self.holder, low, high)
+    }
+
+    /// Immediately prunes edges that have outlived their retention rule,
+    /// rather than waiting for the maintenance thread. Returns the number of
+    /// edges pruned.
+    pub fn prune_expired_edges(&self) -> Result<u64> {
+        maintenance::prune_expired_edges(&self.holder)
+    }
+
+    /// Reads the records of the given vertices - their types, properties,
+    /// edges in both directions and outbound edges' properties - so that
+    /// they're in sled's cache before traffic needs them, e.g. right after
+    /// a restart. Returns the number of records read.
+    ///
+    /// # Arguments
+    /// * `ids`: The IDs of the vertices to warm.
+    pub fn warm(&self, ids: &[Uuid]) -> Result<u64> {
+        warm::warm(&self.holder, ids)
+    }
+
+    /// Warms every vertex whose ID is in `range`, as `warm` does, e.g. a
+    /// block of IDs allocated to a hot tenant or a recent import. Returns
+    /// the number of records read.
+    ///
+    /// # Arguments
+    /// * `range`: The range of vertex IDs to warm.
+    pub fn warm_prefix<R: RangeBounds<Uuid>>(&self, range: R) -> Result<u64> {
+        warm::warm_prefix(&self.holder, range)
+    }
+
+    /// Copies the graph into a new `MemoryDatastore`, e.g. for tests or
+    /// short-lived analytical jobs that want a fast, isolated copy. Writes
+    /// to the copy don't touch this datastore.
+    ///
+    /// The memory datastore sets the update datetime of the copied edges
+    /// to when they were copied.
+    pub fn to_memory_datastore(&self) -> Result<MemoryDatastore> {
+        self.to_memory_datastore_filtered(|_| true)
+    }
+
+    /// Copies the vertices accepted by `filter` into a new
+    /// `MemoryDatastore`, as `to_memory_datastore` does, along with their
+    /// properties, the edges between them, and those edges' properties.
+    ///
+    /// # Arguments
+    /// * `filter`: Whether to copy a vertex.
+    pub fn to_memory_datastore_filtered<F>(&self, filter: F) -> Result<MemoryDatastore>
+    where
+        F: Fn(&Vertex) -> bool,
+    {
+        let _guard = self.holder.read_guard();
+        export::to_memory_datastore(&self.holder, filter)
+    }
+
+    /// Rewrites the property values that aren't stored in the canonical
+    /// encoding, i.e. minified JSON, which reclaims the space taken by
+    /// values written with extra whitespace by older versions or other
+    /// tools. Returns the number of values rewritten.
+    ///
+    /// Values are rewritten one by one, and only if they haven't changed
+    /// since they were read, so this can run alongside other writes. It
+    /// resumes from where an interrupted pass, or the maintenance thread
+    /// set up by `SledConfig::with_property_compaction`, left off.
+    pub fn compact_property_values(&self) -> Result<u64> {
+        maintenance::compact_property_values(&self.holder)
+    }
+
+    /// Renames a property on every vertex and edge that has it, e.g. when
+    /// a naming convention changes. A vertex or edge that already has a
+    /// property named `new_name` has it replaced. Returns the number of
+    /// properties renamed.
+    ///
+    /// The property trees are scanned in batches, and each batch is
+    /// written under the new name before it's removed from the old one, so
+    /// an interrupted rename leaves every property under at least one of
+    /// the names; calling this again with the same names resumes it.
+    /// Writes to the old name made while this runs may be lost.
+    ///
+    /// If the old name is indexed with `index_property`,
+    /// `index_unique_property` or `index_edge_property`, the new name is
+    /// indexed the same way, and the old index is dropped once the rename
+    /// is done. Names given to `SledConfig`, e.g. of derived properties or
+    /// composite indexes, aren't changed, and write validators aren't
+    /// called.
+    ///
+    /// # Arguments
+    /// * `old_name`: The current name of the property.
+    /// * `new_name`: The name to rename it to.
+    pub fn rename_property(&self, old_name: &str, new_name: &str) -> Result<u64> {
+        rename::rename_property(&self.holder, old_name, new_name)
+    }
+
+    /// Computes the connected components of the graph, ignoring edge
+    /// directions, and sets a property of every vertex to the ID of its
+    /// component, which is the lowest vertex ID in it. Vertices without
+    /// edges are components of their own. Returns the number of
+    /// components.
+    ///
+    /// The components are computed in a scratch tree rather than in
+    /// memory, so this works on graphs of any size, with one pass over the
+    /// edges and one over the vertices. Vertices and edges written while it
+    /// runs may or may not be accounted for.
+    ///
+    /// # Arguments
+    /// * `name`: The name of the property to write the component IDs to.
+    /// * `t`: If set, only edges of this type connect vertices.
+    pub fn tag_connected_components(&self, name: &str, t: Option<&Type>) -> Result<u64> {
+        components::tag(&self.holder, name, t)
+    }
+
+    /// Moves vertices that haven't been touched since `cutoff` into separate
+    /// archive trees, along with their properties, their edges and the
+    /// edges' properties, to keep the trees that queries read small.
+    /// Returns the number of vertices archived.
+    ///
+    /// A vertex is untouched if it was created before `cutoff`, and none of
+    /// its edges were created or updated since. Archived vertices are
+    /// invisible to queries until they're restored with `unarchive`. With
+    /// history enabled, archiving and restoring are recorded as deletions
+    /// and re-creations. Nothing is archived while indexing is deferred.
+    ///
+    /// # Arguments
+    /// * `cutoff`: Vertices untouched since this datetime are archived.
+    pub fn archive_cold_vertices(&self, cutoff: DateTime<Utc>) -> Result<u64> {
+        archive::archive_cold_vertices(&self.holder, cutoff)
+    }
+
+    /// Restores an archived vertex, along with its properties and those of
+    /// its edges whose other vertex isn't archived. Returns whether the
+    /// vertex was archived.
+    ///
+    /// Edges to vertices that are still archived are restored along with
+    /// those vertices. If a vertex or property with the same key was written
+    /// since the vertex was archived, the newer data is kept.
+    ///
+    /// # Arguments
+    /// * `id`: The ID of the vertex.
+    pub fn unarchive(&self, id: Uuid) -> Result<bool> {
+        archive::unarchive(&self.holder, id)
+    }
+
+    /// Whether a vertex is archived.
+    ///
+    /// # Arguments
+    /// * `id`: The ID of the vertex.
+    pub fn is_archived(&self, id: Uuid) -> Result<bool> {
+        archive::is_archived(&self.holder, id)
+    }
+
+    /// Gets a datastore for a tenant's partition of this sled database.
+    ///
+    /// Each partition has its own set of trees in the same sled file, so
+    /// scans and counts (e.g. `get_vertex_count`) only ever see the
+    /// partition's own data, and edges can't cross partitions. Partitions
+    /// are created on first use, and share this datastore's configuration.
+    ///
+    /// # Errors
+    /// Returns `Error::PartitionReclaiming` if the tenant's partition was
+    /// dropped, and is still being reclaimed in the background.
+    ///
+    /// # Arguments
+    /// * `tenant`: The ID of the tenant.
+    pub fn partition(&self, tenant: u32) -> Result<SledDatastore> {
+        if reclaim::is_reclaiming(&self.holder.metadata, tenant)? {
+            return Err(Error::PartitionReclaiming { tenant }.into());
+        }
+
+        let mut holder = SledHolder::with_trees(
+            self.holder.db.clone(),
+            self.holder.cold_db.clone(),
+            Some(tenant),
+            &self.config,
+        )?;
+        holder.directory = self.holder.directory.clone();
+        *holder.validators.write().unwrap() = self.holder.validators.read().unwrap().clone();
+        *holder.access_policy.write().unwrap() = self.holder.access_policy.read().unwrap().clone();
+        Ok(SledDatastore::with_holder(
+            holder,
+            self.config.clone(),
+            self.session.clone(),
+            self.reclaimer.clone(),
+            self.result_caches.clone(),
+        ))
+    }
+
+    /// The tenant ID of this datastore's partition, or `None` for the
+    /// unpartitioned data.
+    pub fn tenant(&self) -> Option<u32> {
+        self.holder.partition
+    }
+
+    /// Lists the tenants that have a partition in this sled database,
+    /// excluding dropped partitions that are still being reclaimed.
+    pub fn partitions(&self) -> Result<Vec<u32>> {
+        let mut tenants: Vec<u32> = self
+            .holder
+            .db
+            .tree_names()
+            .iter()
+            .filter_map(|name| {
+                let name = std::str::from_utf8(name).ok()?;
+                let rest = name.strip_prefix("partition:")?;
+                rest.split(':').next()?.parse().ok()
+            })
+            .collect();
+
+        tenants.sort_unstable();
+        tenants.dedup();
+
+        let mut live = Vec::with_capacity(tenants.len());
+
+        for tenant in tenants {
+            if !reclaim::is_reclaiming(&self.holder.metadata, tenant)? {
+                live.push(tenant);
+            }
+        }
+
+        Ok(live)
+    }
+
+    /// Drops all of a tenant's data. Returns whether the partition existed.
+    ///
+    /// With `SledConfig::with_background_reclaim`, this returns once the
+    /// partition is marked as dropped, and its space is reclaimed in the
+    /// background. Otherwise, its trees are dropped before this returns.
+    ///
+    /// Datastores previously obtained for the partition must not be used
+    /// afterwards.
+    ///
+    /// # Arguments
+    /// * `tenant`: The ID of the tenant.
+    pub fn drop_partition(&self, tenant: u32) -> Result<bool> {
+        let holder = &self.holder;
+
+        if reclaim::is_reclaiming(&holder.metadata, tenant)? {
+            return Ok(false);
+        }
+
+        if let Some(ref cold_db) = holder.cold_db {
+            reclaim::drop_cold(cold_db, tenant)?;
+        }
+
+        let dropped = if self.config.background_reclaim {
+            reclaim::start(&holder.db, &holder.metadata, &self.reclaimer, tenant)?
+        } else {
+            reclaim::drop_now(&holder.db, &holder.metadata, tenant)?
+        };
+
+        // Handles on the partition may outlive it, and see it recreated.
+        self.result_caches.invalidate(Some(tenant));
+        Ok(dropped)
+    }
+
+    /// Gets the progress of the background reclaims of dropped partitions,
+    /// in tenant order. A reclaim is listed until it's done. See
+    /// `SledConfig::with_background_reclaim`.
+    pub fn reclaim_progress(&self) -> Vec<ReclaimProgress> {
+        self.reclaimer.progress()
+    }
+
+    /// Imports a chunk of items from an external source, such as a page of
+    /// a log or a range of offsets of a message queue, recording the
+    /// chunk's `watermark` for the source, e.g. its last offset. The
+    /// watermark is written in the same sled transaction as the last of
+    /// the chunk's writes, so after a crash, `import_watermark` tells
+    /// exactly which chunk to resume from.
+    ///
+    /// Chunks whose watermark isn't above the source's current one are
+    /// skipped, so a chunk that's imported again, e.g. by an ingest job
+    /// that's replaying its input, isn't applied twice. Returns whether the
+    /// chunk was applied. Items are validated and applied like the writes
+    /// of a `SledBatch`, so items whose vertices or edges don't exist are
+    /// dropped. Each source should be imported by one writer at a time.
+    ///
+    /// # Arguments
+    /// * `source`: The name of the source.
+    /// * `watermark`: The position in the source that the chunk ends at.
+    /// * `items`: The items in the chunk.
+    pub fn import<I>(&self, source: &str, watermark: u64, items: I) -> Result<bool>
+    where
+        I: IntoIterator<Item = BulkInsertItem>,
+    {
+        access::authorize(&self.holder, AccessKind::Write, "import")?;
+        import::import(&SledTransaction::new(self.holder.clone()), source, watermark, items)
+    }
+
+    /// Gets the watermark of the last chunk imported from `source` with
+    /// `import`, or `None` if nothing has been imported from it.
+    pub fn import_watermark(&self, source: &str) -> Result<Option<u64>> {
+        import::watermark(&self.holder, source)
+    }
+
+    /// Runs `f` as an optimistic transaction: its writes are staged in a
+    /// write buffer, with the same limits as
+    /// `SledTransaction::with_write_buffer`, and applied only if nothing it
+    /// read was written to while it ran. Otherwise, the writes are
+    /// discarded and `f` is run again, on a fresh transaction, so that what
+    /// it read is never stale by the time its writes land. This lets
+    /// concurrent read-modify-write cycles over the same vertices be made
+    /// without locking around them.
+    ///
+    /// Conflicts are tracked per vertex for calls scoped to specific
+    /// vertices or edges, such as `get_vertices` with a
+    /// `SpecificVertexQuery` or `get_edges` piped from one, and for writes
+    /// to them. Any other read conflicts with every write, and any other
+    /// write, such as deleting a vertex, conflicts with every read.
+    ///
+    /// `f` may run several times, so it shouldn't have side effects outside
+    /// of the transaction. If it fails, its error is returned without a
+    /// retry. If it keeps conflicting with other writes, this fails with
+    /// `Error::Conflict`.
+    ///
+    /// # Arguments
+    /// * `f`: The reads and writes to make.
+    pub fn execute<T, F>(&self, mut f: F) -> Result<T>
+    where
+        F: FnMut(&SledTransaction) -> Result<T>,
+    {
+        access::authorize(&self.holder, AccessKind::Write, "execute")?;
+
+        for _ in 0..MAX_EXECUTE_ATTEMPTS {
+            let trans = SledTransaction::new(self.holder.clone()).with_write_buffer();
+            let trans = SledTransaction {
+                read_set: Some(ReadSet::default()),
+                ..trans
+            };
+            let value = f(&trans)?;
+            let writes = trans.take_writes();
+
+            if writes.is_empty() {
+                return Ok(value);
+            }
+
+            let guard = self.holder.exclusive_write_guard();
+            let is_current = match trans.read_set {
+                Some(ref read_set) => read_set.is_current(&self.holder.write_versions),
+                None => false,
+            };
+
+            if is_current {
+                if let Some(ids) = writes.touched_vertices() {
+                    guard.touch(&ids);
+                }
+
+                writes.apply_locked(&trans, "execute", MultiBatch::default())?;
+                return Ok(value);
+            }
+        }
+
+        Err(Error::Conflict {
+            attempts: MAX_EXECUTE_ATTEMPTS,
+        }
+        .into())
+    }
+}
+
+impl Datastore for SledDatastore {
+    type Trans = SledTransaction;
+
+    fn sync(&self) -> Result<()> {
+        self.holder.flush()
+    }
+
+    fn transaction(&self) -> Result<Self::Trans> {
+        Ok(SledTransaction::new(self.holder.clone()))
+    }
+
+    fn bulk_insert<I>(&self, items: I) -> Result<()>
+    where
+        I: Iterator<Item = BulkInsertItem>,
+    {
+        access::authorize(&self.holder, AccessKind::Write, "bulk_insert")?;
+        let _guard = self.holder.write_guard();
+        let vertex_manager = VertexManager::new(&self.holder);
+        let edge_manager = EdgeManager::new(&self.holder);
+        let vertex_property_manager = VertexPropertyManager::new(&self.holder);
+        let edge_property_manager = EdgePropertyManager::new(&self.holder);
+
+        let mut count = 0;
+
+        for item in items {
+            count += 1;
+
+            match item {
+                BulkInsertItem::Vertex(ref vertex) => {
+                    validate::check(&self.holder, &Mutation::CreateVertex(vertex))?;
+                    vertex_manager.create(vertex)?;
+                }
+                BulkInsertItem::Edge(ref key) => {
+                    validate::check(&self.holder, &Mutation::CreateEdge(key))?;
+                    edge_manager.set(key.outbound_id, &key.t, key.inbound_id, Utc::now())?;
+                }
+                BulkInsertItem::VertexProperty(id, ref name, ref value) => {
+                    validate::check(&self.holder, &Mutation::SetVertexProperty { id, name, value })?;
+                    limits::check_vertex_property(&self.holder, id, name, value, 0)?;
+                    vertex_property_manager.set(id, name, value)?;
+                }
+                BulkInsertItem::EdgeProperty(ref key, ref name, ref value) => {
+                    validate::check(&self.holder, &Mutation::SetEdgeProperty { key, name, value })?;
+                    limits::check_edge_property(&self.holder, key, name, value, 0)?;
+                    edge_property_manager.set(key.outbound_id, &key.t, key.inbound_id, name, value)?;
+                }
+            }
+        }
+
+        audit::record(&self.holder, None, "bulk_insert", format!("{} items", count))?;
+        self.holder.flush()
+    }
+}
+
+/// A transaction that is backed by Sled.
+pub struct SledTransaction {
+    pub(crate) holder: Arc<SledHolder>,
+    audit_context: Option<String>,
+    timeout: Option<StdDuration>,
+    context: OpContext,
+    read_options: ReadOptions,
+    durability: Option<Durability>,
+    write_buffer: Option<Mutex<WriteBuffer>>,
+    // What's been read, for transactions run by `SledDatastore::execute`.
+    read_set: Option<ReadSet>,
+}
+
+/// The writes that a transaction with a write buffer stages, rather than
+/// applying them right away.
+const BUFFERED_OPERATIONS: &[&str] = &[
+    "create_vertex",
+    "create_edge",
+    "set_vertex_properties",
+    "set_edge_properties",
+    "delete_vertices",
+    "delete_edges",
+    "delete_vertex_properties",
+    "delete_edge_properties",
+];
+
+impl SledTransaction {
+    fn new(holder: Arc<SledHolder>) -> Self {
+        SledTransaction {
+            holder,
+            audit_context: None,
+            timeout: None,
+            context: OpContext::default(),
+            read_options: ReadOptions::default(),
+            durability: None,
+            write_buffer: None,
+            read_set: None,
+        }
+    }
+
+    /// Sets the context recorded in the audit log for mutations made
+    /// through this transaction, e.g. the user or service making them.
+    pub fn with_audit_context<S: Into<String>>(self, context: S) -> Self {
+        SledTransaction {
+            audit_context: Some(context.into()),
+            ..self
+        }
+    }
+
+    /// Limits how long each operation on this transaction may run, to keep
+    /// queries and deletes touching pathologically large vertices from
+    /// tying up the calling thread.
+    ///
+    /// Scans and cascading deletes check the deadline as they go, and fail
+    /// with `Error::Timeout` once it's exceeded. Mutations made before then
+    /// are kept.
+    pub fn with_timeout(self, timeout: StdDuration) -> Self {
+        SledTransaction {
+            timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Runs this transaction's operations on behalf of the request described
+    /// by `context`, so that they stop once its deadline passes or it's
+    /// cancelled, and their work is attributed to its tag.
+    ///
+    /// Like timeouts, these are checked as scans and cascading deletes go.
+    pub fn with_context(self, context: OpContext) -> Self {
+        SledTransaction { context, ..self }
+    }
+
+    /// Sets how this transaction's reads trade off speed against
+    /// strictness. Mutations aren't affected.
+    pub fn with_read_options(self, read_options: ReadOptions) -> Self {
+        SledTransaction { read_options, ..self }
+    }
+
+    /// Overrides the datastore's durability for this transaction's
+    /// mutating calls, e.g. to flush writes that must not be lost while
+    /// leaving the rest of the datastore's writes to be flushed lazily.
+    /// With a write buffer, this applies to `commit`.
+    pub fn with_durability(self, durability: Durability) -> Self {
+        SledTransaction {
+            durability: Some(durability),
+            ..self
+        }
+    }
+
+    /// Stages this transaction's writes in memory, and applies them when
+    /// `commit` is called, grouped as a `SledBatch` groups them. This
+    /// speeds up bursts of small writes, which would otherwise each be
+    /// applied on their own.
+    ///
+    /// Only the `Transaction` methods that create, set and delete are
+    /// staged. Other writes fail with `Error::UnbufferedWrite`. Staged
+    /// writes are validated when they're committed, but the transaction's
+    /// own vertex, edge and property reads see them right away: staged
+    /// vertices and edges are returned alongside committed ones, and
+    /// deleted ones are hidden, along with the edges and properties that
+    /// would be deleted with them. Staged edges report when they were
+    /// staged as their update datetimes. Other reads, such as index
+    /// lookups, only see what's committed, as do other transactions.
+    ///
+    /// Properties set on specific vertices or edges are staged as they are,
+    /// so that they can be set on ones created earlier in the transaction;
+    /// properties set through other queries are staged for the vertices
+    /// and edges that the query returns when they're set. Deletes are
+    /// applied before everything else when the writes are committed, and
+    /// vertices are deleted according to the cascade policies of their
+    /// types. Writes that haven't been committed are discarded when the
+    /// transaction is dropped.
+    pub fn with_write_buffer(self) -> Self {
+        SledTransaction {
+            write_buffer: Some(Mutex::new(WriteBuffer::new(self.holder.clone()))),
+            ..self
+        }
+    }
+
+    /// Applies the writes staged by a transaction with a write buffer. The
+    /// buffer is emptied, even if this fails, and the transaction can go on
+    /// staging writes. As with `SledBatch::commit`, a failure midway
+    /// through can leave some of the writes applied and not others. This
+    /// does nothing on transactions without a write buffer.
+    pub fn commit(&self) -> Result<()> {
+        let writes = self.take_writes();
+
+        if writes.is_empty() {
+            return Ok(());
+        }
+
+        access::authorize(&self.holder, AccessKind::Write, "commit")?;
+        writes.apply(self, "commit")
+    }
+
+    /// Takes the writes staged so far, leaving the write buffer empty.
+    fn take_writes(&self) -> PendingWrites {
+        match self.write_buffer {
+            Some(ref write_buffer) => write_buffer.lock().unwrap().take(),
+            None => PendingWrites::default(),
+        }
+    }
+
+    /// Discards the writes staged by a transaction with a write buffer.
+    pub fn rollback(&self) {
+        if let Some(ref write_buffer) = self.write_buffer {
+            write_buffer.lock().unwrap().take();
+        }
+    }
+
+    /// Gets a copy of the staged writes for reads to be overlaid with, if
+    /// the transaction has a write buffer and anything is staged in it.
+    fn overlay(&self) -> Option<PendingWrites> {
+        let write_buffer = self.write_buffer.as_ref()?.lock().unwrap();
+
+        if write_buffer.writes().is_empty() {
+            None
+        } else {
+            Some(write_buffer.writes().clone())
+        }
+    }
+
+    /// Runs `f` on the write buffer, if the transaction has one. `f` must
+    /// not run queries, which would take the write buffer's lock again to
+    /// overlay it.
+    fn buffered<T, F: FnOnce(&mut PendingWrites) -> Result<T>>(&self, f: F) -> Option<Result<T>> {
+        self.write_buffer
+            .as_ref()
+            .map(|write_buffer| write_buffer.lock().unwrap().update(f))
+    }
+
+    /// Stages writes in the write buffer, if the transaction has one. Unlike
+    /// `buffered`, this is for writes whose queries have already been run.
+    fn stage<F: FnOnce(&mut PendingWrites)>(&self, f: F) {
+        if let Some(ref write_buffer) = self.write_buffer {
+            write_buffer.lock().unwrap().update(f);
+        }
+    }
+
+    /// Gets the IDs of the vertices that a query returns.
+    fn query_vertex_ids(&self, q: &VertexQuery) -> Result<Vec<Uuid>> {
+        let _guard = self.read_guard();
+        let deadline = self.deadline();
+        let iterator = self.vertex_query_to_iterator(q.clone(), &deadline)?;
+        iterator.map(|item| item.map(|(id, _)| id)).collect()
+    }
+
+    /// Gets the keys of the edges that a query returns.
+    fn query_edge_keys(&self, q: &EdgeQuery) -> Result<Vec<EdgeKey>> {
+        let _guard = self.read_guard();
+        let deadline = self.deadline();
+        let iterator = self.edge_query_to_iterator(q.clone(), &deadline)?;
+        iterator
+            .map(|item| item.map(|(outbound_id, t, _, inbound_id)| EdgeKey::new(outbound_id, t, inbound_id)))
+            .collect()
+    }
+
+    /// Held for the duration of a query. On top of what
+    /// `SledHolder::read_guard` does, this applies the read options.
+    pub(crate) fn read_guard(&self) -> ReadGuard<'_> {
+        let holder = &self.holder;
+        let exclusive_snapshot = self.read_options.snapshot && holder.iterator_stability == IteratorStability::Live;
+
+        ReadGuard {
+            _snapshot: holder.read_guard(),
+            _exclusive_snapshot: if exclusive_snapshot {
+                Some(holder.snapshot_lock.write().unwrap())
+            } else {
+                None
+            },
+            _policy: self.read_options.decode_error_policy.map(PolicyOverride::new),
+        }
+    }
+
+    /// Runs an operation past the access policy, and rejects writes that
+    /// can't be staged if the transaction has a write buffer.
+    pub(crate) fn authorize(&self, kind: AccessKind, operation: &str) -> Result<()> {
+        self.authorize_scoped(kind, operation, None)
+    }
+
+    /// Like `authorize`, for operations that only read the vertices in
+    /// `scope`, and their properties and edges, if that's known. This is
+    /// what `SledDatastore::execute` checks for conflicts.
+    fn authorize_scoped(&self, kind: AccessKind, operation: &str, scope: Option<Vec<Uuid>>) -> Result<()> {
+        if kind == AccessKind::Write && self.write_buffer.is_some() && !BUFFERED_OPERATIONS.contains(&operation) {
+            return Err(Error::UnbufferedWrite {
+                operation: operation.to_string(),
+            }
+            .into());
+        }
+
+        access::authorize(&self.holder, kind, operation)?;
+
+        if let Some(ref read_set) = self.read_set {
+            read_set.record(&self.holder.write_versions, scope.as_deref());
+        }
+
+        Ok(())
+    }
+
+    /// Starts the deadline for an operation.
+    fn deadline(&self) -> Deadline {
+        Deadline::with_context(self.timeout, &self.context, &self.holder.tagged_work)
+    }
+
+    /// Records a mutating call in the audit log, if it's enabled. Every
+    /// mutating call ends with this, so it's also where the call's writes
+    /// are made durable.
+    pub(crate) fn audit<D: Debug>(&self, operation: &str, details: D) -> Result<()> {
+        audit::record(
+            &self.holder,
+            self.audit_context.as_deref(),
+            operation,
+            format!("{:?}", details),
+        )?;
+
+        self.holder
+            .make_durable(self.durability.unwrap_or(self.holder.flush_schedule.durability))
+    }
+
+    /// Returns the cached result of a query, or runs it and caches the
+    /// result if result caching is enabled.
+    fn cached<T, Q, F>(&self, kind: &str, q: &Q, f: F) -> Result<T>
+    where
+        T: Cacheable,
+        Q: Debug,
+        F: FnOnce() -> Result<T>,
+    {
+        // Cached results don't reflect staged writes.
+        let result_cache = match self.holder.result_cache {
+            Some(ref result_cache) if self.read_options.use_cache && self.overlay().is_none() => result_cache,
+            _ => return f(),
+        };
+
+        let key = format!("{}:{:?}", kind, q);
+
+        if let Some(result) = result_cache.get(&key) {
+            return Ok(result);
+        }
+
+        let generation = result_cache.generation();
+        let result = f()?;
+        result_cache.insert(key, generation, result.clone());
+        Ok(result)
+    }
+
+    /// Runs a vertex query, and passes `f` an iterator over its results,
+    /// which reads them as it's advanced rather than collecting them first
+    /// as `get_vertices` does. This keeps memory use flat for queries that
+    /// match huge numbers of vertices: range and specific queries hold one
+    /// vertex at a time, and pipe queries hold at most `limit` edges of one
+    /// vertex of their inner query at a time. Results aren't cached.
+    ///
+    /// The query stays open until `f` returns: its timeout and `OpContext`
+    /// are checked as the iterator is advanced, and with
+    /// `IteratorStability::Snapshot` or `ReadOptions::snapshot`, writes
+    /// wait for it.
+    ///
+    /// # Arguments
+    /// * `q`: The query to run.
+    /// * `f`: Consumes as many of the results as it needs.
+    pub fn stream_vertices<Q, T, F>(&self, q: Q, f: F) -> Result<T>
+    where
+        Q: Into<VertexQuery>,
+        F: FnOnce(&mut dyn Iterator<Item = Result<Vertex>>) -> Result<T>,
+    {
+        self.authorize(AccessKind::Read, "stream_vertices")?;
+        let _guard = self.read_guard();
+        let deadline = self.deadline();
+        let iterator = self.vertex_query_to_iterator(q.into(), &deadline)?;
+        let mut vertices = iterator.map(|item| item.map(|(id, t)| Vertex::with_id(id, t)));
+        f(&mut vertices)
+    }
+
+    /// Runs an edge query, and passes `f` an iterator over its results, as
+    /// `stream_vertices` does for vertex queries. Pipe queries hold at most
+    /// `limit` edges of one vertex of their inner query in memory at a
+    /// time.
+    ///
+    /// # Arguments
+    /// * `q`: The query to run.
+    /// * `f`: Consumes as many of the results as it needs.
+    pub fn stream_edges<Q, T, F>(&self, q: Q, f: F) -> Result<T>
+    where
+        Q: Into<EdgeQuery>,
+        F: FnOnce(&mut dyn Iterator<Item = Result<Edge>>) -> Result<T>,
+    {
+        self.authorize(AccessKind::Read, "stream_edges")?;
+        let _guard = self.read_guard();
+        let deadline = self.deadline();
+        let iterator = self.edge_query_to_iterator(q.into(), &deadline)?;
+
+        let mut edges = iterator.map(|item| {
+            item.map(|(outbound_id, t, update_datetime, inbound_id)| {
+                Edge::new(EdgeKey::new(outbound_id, t, inbound_id), update_datetime)
+            })
+        });
+
+        f(&mut edges)
+    }
+
+    /// Gets a read-only view of the graph as it was at `datetime`.
+    ///
+    /// This requires the datastore to have been opened with
+    /// `SledConfig::with_history`; only changes made since then are visible.
+    pub fn as_of(&self, datetime: DateTime<Utc>) -> Result<SledAsOfView> {
+        self.authorize(AccessKind::Read, "as_of")?;
+        if !self.holder.history.enabled {
+            return Err(Error::HistoryDisabled.into());
+        }
+
+        Ok(SledAsOfView::new(self.holder.clone(), datetime))
+    }
+
+    /// Gets how many edges of a type a vertex had written per day, as a
+    /// time series of the days with any writes, in day order. Creations
+    /// and updates are counted on the UTC day of the edge's update
+    /// datetime.
+    ///
+    /// This requires the datastore to have been opened with
+    /// `SledConfig::with_edge_activity`; only writes made since then are
+    /// counted.
+    ///
+    /// # Arguments
+    /// * `id`: The ID of the vertex.
+    /// * `t`: The edge type.
+    /// * `direction`: Whether to count outbound or inbound edges.
+    /// * `low`: Only include days on or after this one, if specified.
+    /// * `high`: Only include days on or before this one, if specified.
+    pub fn get_edge_activity(
+        &self,
+        id: Uuid,
+        t: &Type,
+        direction: EdgeDirection,
+        low: Option<NaiveDate>,
+        high: Option<NaiveDate>,
+    ) -> Result<Vec<(NaiveDate, u64)>> {
+        self.authorize(AccessKind::Read, "get_edge_activity")?;
+        let _guard = self.read_guard();
+
+        match self.holder.edge_activity {
+            Some(ref tree) => activity::get(tree, id, t, direction, low, high),
+            None => Err(Error::EdgeActivityDisabled.into()),
+        }
+    }
+
+    /// Counts the edges of a vertex whose update datetime falls within a
+    /// window, e.g. to find how many interactions happened in the last day.
+    ///
+    /// Unlike fetching the edges, this never decodes or materializes them.
+    ///
+    /// # Arguments
+    /// * `id`: The ID of the vertex.
+    /// * `t`: Only count edges of this type, if specified.
+    /// * `direction`: Whether to count outbound or inbound edges.
+    /// * `low`: Only count edges updated at or after this datetime, if
+    ///   specified.
+    /// * `high`: Only count edges updated at or before this datetime, if
+    ///   specified.
+    pub fn count_edges(
+        &self,
+        id: Uuid,
+        t: Option<&Type>,
+        direction: EdgeDirection,
+        low: Option<DateTime<Utc>>,
+        high: Option<DateTime<Utc>>,
+    ) -> Result<u64> {
+        self.authorize(AccessKind::Read, "count_edges")?;
+        let _guard = self.read_guard();
+        let edge_range_manager = EdgeRangeManager::for_direction(&self.holder, direction);
+
+        edge_range_manager.count_for_range(id, t, low, high, &self.deadline())
+    }
+
+    /// Deletes vertices, handling their edges according to `policy` rather
+    /// than the policy configured for their type.
+    ///
+    /// # Arguments
+    /// * `q`: The query for the vertices to delete.
+    /// * `policy`: What happens to the vertices' edges.
+    pub fn delete_vertices_with_policy<Q: Into<VertexQuery>>(&self, q: Q, policy: CascadePolicy) -> Result<()> {
+        self.authorize(AccessKind::Write, "delete_vertices_with_policy")?;
+        let q = q.into();
+        self.delete_vertices_with(&q, Some(policy))?;
+        self.audit("delete_vertices_with_policy", (q, policy))
+    }
+
+    fn delete_vertices_with(&self, q: &VertexQuery, policy: Option<CascadePolicy>) -> Result<()> {
+        let _guard = self.holder.write_guard();
+        self.delete_vertices_locked(q, policy)
+    }
+
+    /// Like `delete_vertices_with`, for callers that already hold a write
+    /// guard.
+    pub(crate) fn delete_vertices_locked(&self, q: &VertexQuery, policy: Option<CascadePolicy>) -> Result<()> {
+        let deadline = self.deadline();
+        let vertex_manager = VertexManager::new(&self.holder);
+        let policy_for = |t: &Type| {
+            policy
+                .or_else(|| self.holder.constraints.cascade_policies.get(t).cloned())
+                .unwrap_or_default()
+        };
+
+        // Restricted vertices are all checked up front, so that a vertex
+        // with edges leaves every vertex undeleted.
+        let may_restrict = match policy {
+            Some(policy) => policy == CascadePolicy::Restrict,
+            None => self
+                .holder
+                .constraints
+                .cascade_policies
+                .values()
+                .any(|&policy| policy == CascadePolicy::Restrict),
+        };
+
+        if may_restrict {
+            for item in self.vertex_query_to_iterator(q.clone(), &deadline)? {
+                let (id, t) = item?;
+
+                if policy_for(&t) == CascadePolicy::Restrict {
+                    constraints::check_no_edges(&self.holder, id)?;
+                }
+            }
+        }
+
+        for item in self.vertex_query_to_iterator(q.clone(), &deadline)? {
+            let (id, t) = item?;
+            validate::check(&self.holder, &Mutation::DeleteVertex(id))?;
+
+            match policy_for(&t) {
+                CascadePolicy::Detach => vertex_manager.delete_detached(id, &deadline)?,
+                _ => vertex_manager.delete(id, &deadline)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Deletes the edges of a vertex of a type whose update datetime falls
+    /// within a window, e.g. to prune relationships older than a cutoff,
+    /// and returns how many were deleted. Edges are deleted in batches, so
+    /// this is much cheaper than deleting them one by one.
+    ///
+    /// # Arguments
+    /// * `id`: The ID of the vertex.
+    /// * `t`: The type of edges to delete.
+    /// * `direction`: Whether to delete outbound or inbound edges.
+    /// * `low`: Only delete edges updated at or after this datetime, if
+    ///   specified.
+    /// * `high`: Only delete edges updated at or before this datetime, if
+    ///   specified.
+    pub fn delete_edges_in_range(
+        &self,
+        id: Uuid,
+        t: &Type,
+        direction: EdgeDirection,
+        low: Option<DateTime<Utc>>,
+        high: Option<DateTime<Utc>>,
+    ) -> Result<u64> {
+        self.authorize(AccessKind::Write, "delete_edges_in_range")?;
+        let _guard = self.holder.write_guard();
+        let edge_manager = EdgeManager::new(&self.holder);
+        let edge_range_manager = EdgeRangeManager::for_direction(&self.holder, direction);
+        let deadline = self.deadline();
+        let mut count = 0;
+
+        // Deleted edges drop out of the range, so each batch is read from
+        // the start of it.
+        loop {
+            let items = edge_range_manager.query(id, Some(t), low, high, DELETE_BATCH_SIZE, &deadline)?;
+            let mut edges = Vec::with_capacity(items.len());
+
+            for (first_id, t, update_datetime, second_id) in items {
+                let (outbound_id, inbound_id) = match direction {
+                    EdgeDirection::Outbound => (first_id, second_id),
+                    EdgeDirection::Inbound => (second_id, first_id),
+                };
+
+                let key = EdgeKey::new(outbound_id, t, inbound_id);
+                validate::check(&self.holder, &Mutation::DeleteEdge(&key))?;
+                edges.push((outbound_id, key.t, inbound_id, update_datetime));
+            }
+
+            edge_manager.delete_many(&edges)?;
+            count += edges.len() as u64;
+
+            if edges.len() < DELETE_BATCH_SIZE {
+                break;
+            }
+        }
+
+        self.audit("delete_edges_in_range", (id, t, direction, low, high, count))?;
+        Ok(count)
+    }
+
+    /// Summarizes the edges pointing at a vertex: for each edge type, how
+    /// many there are and when the latest one was updated. Types are in the
+    /// order of the edge ranges, where they're length-prefixed, so shorter
+    /// names come first.
+    ///
+    /// This walks the vertex's inbound edge ranges once, without building
+    /// each edge, so it's much cheaper than fetching the edges for "what
+    /// references this" views.
+    ///
+    /// # Arguments
+    /// * `id`: The ID of the vertex.
+    pub fn inbound_summary(&self, id: Uuid) -> Result<Vec<InboundTypeSummary>> {
+        self.authorize(AccessKind::Read, "inbound_summary")?;
+        let _guard = self.read_guard();
+        let deadline = self.deadline();
+        let edge_range_manager = EdgeRangeManager::new_reversed(&self.holder);
+        let mut summaries: Vec<InboundTypeSummary> = Vec::new();
+
+        // Range keys are grouped by type, so each type's edges are
+        // contiguous.
+        for item in edge_range_manager.iterate_for_owner(id) {
+            deadline.tick()?;
+            let (_, t, update_datetime, _) = item?;
+
+            match summaries.last_mut() {
+                Some(summary) if summary.t == t => {
+                    summary.count += 1;
+                    summary.latest_update_datetime = summary.latest_update_datetime.max(update_datetime);
+                }
+                _ => summaries.push(InboundTypeSummary {
+                    t,
+                    count: 1,
+                    latest_update_datetime: update_datetime,
+                }),
+            }
+        }
+
+        Ok(summaries)
+    }
+
+    /// Gets up to `n` of the vertices with the most edges, in both
+    /// directions, along with their degrees, highest first. Ties are
+    /// broken by vertex ID.
+    ///
+    /// This requires the datastore to have been opened with
+    /// `SledConfig::with_degree_index`.
+    ///
+    /// # Arguments
+    /// * `n`: The maximum number of vertices to return.
+    /// * `t`: Only return vertices of this type, if specified.
+    pub fn top_vertices_by_degree(&self, n: usize, t: Option<&Type>) -> Result<Vec<(Vertex, u64)>> {
+        self.authorize(AccessKind::Read, "top_vertices_by_degree")?;
+        let _guard = self.read_guard();
+
+        match self.holder.degree_index {
+            Some(ref index) => degrees::top(&self.holder, index, n, t),
+            None => Err(Error::DegreeIndexDisabled.into()),
+        }
+    }
+
+    /// Gets the vertices whose string property `name` contains every word
+    /// of `query`, in ID order. Words are matched whole and without regard
+    /// to case; an empty query matches nothing.
+    ///
+    /// This requires the datastore to have been opened with
+    /// `SledConfig::with_full_text_index`.
+    ///
+    /// # Arguments
+    /// * `name`: The property name.
+    /// * `query`: The words to look for.
+    pub fn search_vertices(&self, name: &str, query: &str) -> Result<Vec<Vertex>> {
+        self.authorize(AccessKind::Read, "search_vertices")?;
+        let _guard = self.read_guard();
+
+        match self.holder.full_text_index {
+            Some(ref tree) => fulltext::search(&self.holder, tree, name, query, &self.deadline()),
+            None => Err(Error::FullTextIndexDisabled.into()),
+        }
+    }
+
+    /// Gets the `k` vertices whose vector property `name` is most similar
+    /// to `query` by cosine similarity, most similar first, along with
+    /// their similarity. Ties are broken by ID.
+    ///
+    /// The property has to be registered with
+    /// `SledConfig::with_vector_index`. Results are approximate: candidates
+    /// are read from the query's bucket and the buckets nearest it, until
+    /// there are at least `k`, so a similar vector in a farther bucket can
+    /// be missed.
+    ///
+    /// # Arguments
+    /// * `name`: The property name.
+    /// * `query`: The vector to compare against, of the property's
+    ///   dimension.
+    /// * `k`: The maximum number of vertices to return.
+    pub fn knn(&self, name: &str, query: &[f32], k: usize) -> Result<Vec<(Vertex, f32)>> {
+        self.authorize(AccessKind::Read, "knn")?;
+        let _guard = self.read_guard();
+        vectors::knn(&self.holder, name, query, k, &self.deadline())
+    }
+
+    /// Gets the lightest path of outbound edges from one vertex to another,
+    /// as the keys of its edges in order, or `None` if there isn't one. An
+    /// empty path is returned if `from` and `to` are the same vertex.
+    ///
+    /// Edges are weighed by a property, which has to be a non-negative
+    /// number; edges that don't have it aren't followed. Of the equally
+    /// light paths, the one with the fewest edges is returned.
+    ///
+    /// # Arguments
+    /// * `from`: The ID of the vertex to start from.
+    /// * `to`: The ID of the vertex to reach.
+    /// * `t`: If set, only edges of this type are followed.
+    /// * `weight_property`: The name of the edge property holding weights.
+    /// * `max_depth`: The maximum number of edges in the path.
+    pub fn shortest_path(
+        &self,
+        from: Uuid,
+        to: Uuid,
+        t: Option<&Type>,
+        weight_property: &str,
+        max_depth: u32,
+    ) -> Result<Option<Vec<EdgeKey>>> {
+        self.authorize(AccessKind::Read, "shortest_path")?;
+        let _guard = self.read_guard();
+        paths::shortest_path(&self.holder, from, to, t, weight_property, max_depth, &self.deadline())
+    }
+
+    /// Gets when a vertex was created, or `None` if the vertex doesn't
+    /// exist or was created before creation datetimes were tracked.
+    pub fn get_vertex_created_datetime(&self, id: Uuid) -> Result<Option<DateTime<Utc>>> {
+        self.authorize(AccessKind::Read, "get_vertex_created_datetime")?;
+        let _guard = self.read_guard();
+        VertexManager::new(&self.holder).get_created_datetime(id)
+    }
+
+    /// Counts the properties of a vertex, e.g. to show how many attributes
+    /// it has without fetching them. Only keys are scanned, so no property
+    /// values are deserialized.
+    pub fn count_vertex_properties(&self, id: Uuid) -> Result<u64> {
+        self.authorize(AccessKind::Read, "count_vertex_properties")?;
+        let _guard = self.read_guard();
+        VertexPropertyManager::new(&self.holder).count_for_owner(id)
+    }
+
+    /// Counts the properties of an edge. Like `count_vertex_properties`,
+    /// this only scans keys.
+    pub fn count_edge_properties(&self, key: &EdgeKey) -> Result<u64> {
+        self.authorize(AccessKind::Read, "count_edge_properties")?;
+        let _guard = self.read_guard();
+        EdgePropertyManager::new(&self.holder).count_for_owner(key.outbound_id, &key.t, key.inbound_id)
+    }
+
+    /// Lists every property name in use, in name order, with how many
+    /// vertices and edges have a property of that name.
+    ///
+    /// This reads a catalog that's maintained as properties are set and
+    /// deleted, so it doesn't scan any properties. The existing properties
+    /// of datastores created by older versions of this crate are cataloged
+    /// when they're opened.
+    pub fn list_property_names(&self) -> Result<Vec<PropertyNameUsage>> {
+        self.authorize(AccessKind::Read, "list_property_names")?;
+        let _guard = self.read_guard();
+        let catalog_manager = CatalogManager::new(&self.holder);
+        let mut usages: Vec<PropertyNameUsage> = Vec::new();
+
+        for item in catalog_manager.iterate_for_kind(CatalogKind::VertexProperty) {
+            let (name, vertex_count) = item?;
+            usages.push(PropertyNameUsage {
+                name,
+                vertex_count,
+                edge_count: 0,
+            });
+        }
+
+        for item in catalog_manager.iterate_for_kind(CatalogKind::EdgeProperty) {
+            let (name, edge_count) = item?;
+
+            match usages.binary_search_by(|usage| usage.name.cmp(&name)) {
+                Ok(index) => usages[index].edge_count = edge_count,
+                Err(index) => usages.insert(
+                    index,
+                    PropertyNameUsage {
+                        name,
+                        vertex_count: 0,
+                        edge_count,
+                    },
+                ),
+            }
+        }
+
+        Ok(usages)
+    }
+
+    /// Gets the properties of all inbound edges of a vertex, grouped by
+    /// edge. Edges without properties are omitted. Edges are ordered by
+    /// type name, then by outbound vertex ID, and their properties by name.
+    ///
+    /// Edge properties are keyed by outbound vertex, so this reads an
+    /// inbound-first index of them rather than visiting every edge.
+    pub fn get_inbound_edge_properties(&self, id: Uuid) -> Result<Vec<EdgeProperties>> {
+        self.authorize(AccessKind::Read, "get_inbound_edge_properties")?;
+        let _guard = self.read_guard();
+        let edge_manager = EdgeManager::new(&self.holder);
+        let edge_property_manager = EdgePropertyManager::new(&self.holder);
+        let mut results: Vec<EdgeProperties> = Vec::new();
+
+        for item in edge_property_manager.iterate_for_inbound(id) {
+            let ((outbound_id, t, inbound_id, name), value) = item?;
+            let prop = NamedProperty::new(name, value);
+
+            // Properties of the same edge are adjacent.
+            if let Some(last) = results.last_mut() {
+                if last.edge.key.outbound_id == outbound_id && last.edge.key.t == t {
+                    last.props.push(prop);
+                    continue;
+                }
+            }
+
+            if let Some(update_datetime) = edge_manager.get(outbound_id, &t, inbound_id)? {
+                let edge = Edge::new(EdgeKey::new(outbound_id, t, inbound_id), update_datetime);
+                results.push(EdgeProperties::new(edge, vec![prop]));
+            }
+        }
+
+        // The index orders types by length first, as edge ranges do. The
+        // sort is stable, so edges of a type stay in outbound ID order.
+        results.sort_by(|a, b| a.edge.key.t.0.cmp(&b.edge.key.t.0));
+        Ok(results)
+    }
+
+    /// Gets the edges of a vertex in one direction, along with all of their
+    /// properties, in the order they'd be returned by an edge query.
+    ///
+    /// Rather than looking up the properties of each edge separately, this
+    /// reads all of the vertex's edge properties in a single scan, and then
+    /// pairs them up with its edge ranges.
+    ///
+    /// # Arguments
+    /// * `id`: The ID of the vertex.
+    /// * `direction`: Whether to get outbound or inbound edges.
+    pub fn get_all_edge_properties_for_vertex(
+        &self,
+        id: Uuid,
+        direction: EdgeDirection,
+    ) -> Result<Vec<EdgeProperties>> {
+        self.authorize(AccessKind::Read, "get_all_edge_properties_for_vertex")?;
+        let _guard = self.read_guard();
+        let deadline = self.deadline();
+        let edge_property_manager = EdgePropertyManager::new(&self.holder);
+
+        let (edge_range_manager, props_iter): (_, Box<dyn Iterator<Item = Result<EdgePropertyItem>>>) = match direction
+        {
+            EdgeDirection::Outbound => (
+                EdgeRangeManager::new(&self.holder),
+                Box::new(edge_property_manager.iterate_for_outbound(id)),
+            ),
+            EdgeDirection::Inbound => (
+                EdgeRangeManager::new_reversed(&self.holder),
+                Box::new(edge_property_manager.iterate_for_inbound(id)),
+            ),
+        };
+
+        let mut props_by_edge: HashMap<EdgeKey, Vec<NamedProperty>> = HashMap::new();
+
+        for item in deadline.bound(props_iter) {
+            let ((outbound_id, t, inbound_id, name), value) = item?;
+            props_by_edge
+                .entry(EdgeKey::new(outbound_id, t, inbound_id))
+                .or_default()
+                .push(NamedProperty::new(name, value));
+        }
+
+        let mut results = Vec::new();
+
+        for item in deadline.bound(edge_range_manager.iterate_for_range(id, None, None)?) {
+            let (first_id, t, update_datetime, second_id) = item?;
+
+            let key = match direction {
+                EdgeDirection::Outbound => EdgeKey::new(first_id, t, second_id),
+                EdgeDirection::Inbound => EdgeKey::new(second_id, t, first_id),
+            };
+
+            // Undirected edges are reported from `id`'s side, but their
+            // properties are stored under the other orientation if `id` is
+            // the higher of the two.
+            let canonical_key = self.holder.edge_range_layout.canonical_key(&key);
+
+            let props = if canonical_key == key {
+                props_by_edge.remove(&key).unwrap_or_default()
+            } else {
+                let mut props = Vec::new();
+
+                for item in edge_property_manager.iterate_for_owner(key.outbound_id, &key.t, key.inbound_id)? {
+                    let ((_, _, _, name), value) = item?;
+                    props.push(NamedProperty::new(name, value));
+                }
+
+                props
+            };
+
+            results.push(EdgeProperties::new(Edge::new(key, update_datetime), props));
+        }
+
+        Ok(results)
+    }
+
+    /// Checks which of several vertices exist, e.g. to split the entities
+    /// referenced by incoming data into known and unknown ones. Returns one
+    /// result per ID, in the same order.
+    ///
+    /// The IDs are looked up in sorted order, so consecutive lookups mostly
+    /// hit pages of the vertices tree that were just read, and duplicates
+    /// are only looked up once.
+    pub fn filter_existing(&self, ids: &[Uuid]) -> Result<Vec<bool>> {
+        self.authorize(AccessKind::Read, "filter_existing")?;
+        let _guard = self.read_guard();
+        VertexManager::new(&self.holder).exists_many(ids)
+    }
+
+    /// Looks up several edges at once, e.g. to hydrate a list of edge keys
+    /// produced by another system. Returns one result per key, in the same
+    /// order, with `None` for edges that don't exist.
+    ///
+    /// Unlike a `SpecificEdgeQuery`, missing edges keep their position in
+    /// the results, and the lookups are made in key order.
+    pub fn multi_get_edges(&self, keys: &[EdgeKey]) -> Result<Vec<Option<Edge>>> {
+        self.authorize(AccessKind::Read, "multi_get_edges")?;
+        let _guard = self.read_guard();
+        let update_datetimes = EdgeManager::new(&self.holder).get_many(keys)?;
+
+        Ok(keys
+            .iter()
+            .zip(update_datetimes)
+            .map(|(key, update_datetime)| {
+                update_datetime.map(|update_datetime| Edge::new(key.clone(), update_datetime))
+            })
+            .collect())
+    }
+
+    /// Creates several edges at once, all with the same update datetime.
+    /// Returns whether each edge was created, in the order of `keys`; like
+    /// `create_edge`, edges are skipped if either vertex doesn't exist.
+    ///
+    /// This is much cheaper than calling `create_edge` in a loop for
+    /// fan-out writes, since the writes are grouped into a single sled
+    /// transaction.
+    pub fn create_edges(&self, keys: &[EdgeKey]) -> Result<Vec<bool>> {
+        self.authorize(AccessKind::Write, "create_edges")?;
+        self.create_edges_with("create_edges", keys, Utc::now())
+    }
+
+    /// Like `create_edges`, but with the given update datetime rather than
+    /// the current time, as with `create_edge_at`.
+    ///
+    /// # Arguments
+    /// * `keys`: The keys of the edges.
+    /// * `update_datetime`: The update datetime to give the edges.
+    pub fn create_edges_at(&self, keys: &[EdgeKey], update_datetime: DateTime<Utc>) -> Result<Vec<bool>> {
+        self.authorize(AccessKind::Write, "create_edges_at")?;
+        precision::check_range(update_datetime)?;
+        self.create_edges_with("create_edges_at", keys, update_datetime)
+    }
+
+    fn create_edges_with(
+        &self,
+        operation: &str,
+        keys: &[EdgeKey],
+        update_datetime: DateTime<Utc>,
+    ) -> Result<Vec<bool>> {
+        let guard = self.holder.write_guard();
+        let vertex_manager = VertexManager::new(&self.holder);
+        let mut vertex_exists: HashMap<Uuid, bool> = HashMap::new();
+        let mut created = Vec::with_capacity(keys.len());
+        let mut valid_keys = Vec::new();
+        let mut seen_keys = HashSet::new();
+
+        for key in keys {
+            let mut is_valid = true;
+
+            for &id in &[key.outbound_id, key.inbound_id] {
+                let exists = match vertex_exists.get(&id) {
+                    Some(exists) => *exists,
+                    None => {
+                        let exists = vertex_manager.exists(id)?;
+                        vertex_exists.insert(id, exists);
+                        exists
+                    }
+                };
+
+                is_valid &= exists;
+            }
+
+            // The two orientations of an undirected edge are the same edge.
+            if is_valid && seen_keys.insert(self.holder.edge_range_layout.canonical_key(key)) {
+                validate::check(&self.holder, &Mutation::CreateEdge(key))?;
+                valid_keys.push(key.clone());
+            }
+
+            created.push(is_valid);
+        }
+
+        if !valid_keys.is_empty() {
+            for key in &valid_keys {
+                guard.touch(&[key.outbound_id, key.inbound_id]);
+            }
+
+            EdgeManager::new(&self.holder).set_many(&valid_keys, update_datetime)?;
+            self.audit(operation, &valid_keys)?;
+        }
+
+        Ok(created)
+    }
+
+    /// Creates an edge, or updates an existing one, with the given update
+    /// datetime rather than the current time, e.g. to preserve the time of
+    /// the original event when importing or replicating. The datetime is
+    /// truncated to the configured precision. Returns whether the edge was
+    /// written, which it isn't if either vertex doesn't exist.
+    ///
+    /// Edges can be moved back in time this way, unless the datastore is
+    /// configured with `SledConfig::with_monotonic_edge_datetimes`. This
+    /// can't be called on a transaction with a write buffer. Datetimes
+    /// before the Unix epoch or after `indradb::util::MAX_DATETIME` are
+    /// rejected with `Error::DatetimeOutOfRange`.
+    ///
+    /// # Arguments
+    /// * `key`: The key of the edge.
+    /// * `update_datetime`: The update datetime to give the edge.
+    pub fn create_edge_at(&self, key: &EdgeKey, update_datetime: DateTime<Utc>) -> Result<bool> {
+        self.authorize(AccessKind::Write, "create_edge_at")?;
+        precision::check_range(update_datetime)?;
+        self.create_edge_with("create_edge_at", key, true, update_datetime)
+    }
+
+    /// Creates an edge, or refreshes its update datetime. The edge is
+    /// skipped if its outbound vertex doesn't exist, or if `check_inbound`
+    /// is set and its inbound vertex doesn't. `ShardedSledDatastore` unsets
+    /// it for edges whose inbound vertex lives in another shard.
+    pub(crate) fn create_edge_with(
+        &self,
+        operation: &str,
+        key: &EdgeKey,
+        check_inbound: bool,
+        update_datetime: DateTime<Utc>,
+    ) -> Result<bool> {
+        let guard = self.holder.write_guard();
+        guard.touch(&[key.outbound_id, key.inbound_id]);
+        let vertex_manager = VertexManager::new(&self.holder);
+
+        if !vertex_manager.exists(key.outbound_id)? || (check_inbound && !vertex_manager.exists(key.inbound_id)?) {
+            Ok(false)
+        } else {
+            validate::check(&self.holder, &Mutation::CreateEdge(key))?;
+            let edge_manager = EdgeManager::new(&self.holder);
+            edge_manager.set(key.outbound_id, &key.t, key.inbound_id, update_datetime)?;
+            self.audit(operation, key)?;
+            Ok(true)
+        }
+    }
+
+    /// Creates an edge along with its properties, e.g. ones that every edge
+    /// of its type is expected to have. The properties are written in the
+    /// same sled transaction as the edge, so that readers never find the
+    /// edge without them, and nothing is written if it's rejected. If the
+    /// edge already exists, its update datetime is refreshed, and the
+    /// properties replace any of the same names. When a name is given more
+    /// than once, the last value wins.
+    ///
+    /// Returns whether the edge was created or refreshed, which it isn't if
+    /// either vertex doesn't exist. Validators, edge constraints and
+    /// property limits are all checked before anything is written.
+    ///
+    /// # Arguments
+    /// * `key`: The key of the edge.
+    /// * `properties`: The properties to set on the edge.
+    pub fn create_edge_with_properties(&self, key: &EdgeKey, properties: &[NamedProperty]) -> Result<bool> {
+        self.authorize(AccessKind::Write, "create_edge_with_properties")?;
+        let guard = self.holder.write_guard();
+        guard.touch(&[key.outbound_id, key.inbound_id]);
+        let vertex_manager = VertexManager::new(&self.holder);
+
+        if !vertex_manager.exists(key.outbound_id)? || !vertex_manager.exists(key.inbound_id)? {
+            return Ok(false);
+        }
+
+        let mut items: Vec<(EdgeKey, String, JsonValue)> = Vec::with_capacity(properties.len());
+        let mut item_indexes: HashMap<&str, usize> = HashMap::new();
+
+        for property in properties {
+            match item_indexes.get(property.name.as_str()) {
+                Some(&i) => items[i].2 = property.value.clone(),
+                None => {
+                    item_indexes.insert(&property.name, items.len());
+                    items.push((key.clone(), property.name.clone(), property.value.clone()));
+                }
+            }
+        }
+
+        validate::check(&self.holder, &Mutation::CreateEdge(key))?;
+        let mut new_properties = 0;
+
+        for (_, name, value) in &items {
+            validate::check(&self.holder, &Mutation::SetEdgeProperty { key, name, value })?;
+
+            if limits::check_edge_property(&self.holder, key, name, value, new_properties)? {
+                new_properties += 1;
+            }
+        }
+
+        // The edge is checked against the edge constraints and written with
+        // its properties in one sled transaction, under the cardinality
+        // guard, so that nothing is written if the edge is rejected.
+        let edge_manager = EdgeManager::new(&self.holder);
+        let edge_property_manager = EdgePropertyManager::new(&self.holder);
+        let update_datetime = self.holder.datetime_precision.truncate(Utc::now());
+        let mut batch = MultiBatch::default();
+        let _cardinality = self.holder.cardinality_guard();
+        let staged_edges = edge_manager.stage_many(slice::from_ref(key), update_datetime, &mut batch)?;
+        let staged_properties = edge_property_manager.stage_many(&items, Some(update_datetime), &mut batch)?;
+
+        if let Err(err) = batch.apply(&self.holder) {
+            edge_property_manager.abandon_many(staged_properties)?;
+            return Err(err);
+        }
+
+        edge_manager.finish_many(staged_edges)?;
+        edge_property_manager.finish_many(staged_properties)?;
+        self.audit("create_edge_with_properties", (key, properties))?;
+        Ok(true)
+    }
+
+    /// Moves a vertex property from one vertex to another, e.g. when merging
+    /// duplicate entities, replacing the other vertex's property of the
+    /// same name if it has one. Unlike getting, setting and deleting the
+    /// property with separate calls, the property is never on both
+    /// vertices or on neither, and can't be changed by another writer
+    /// midway through. Derived properties of both vertices are updated.
+    ///
+    /// Returns whether the property was moved, which it isn't if either
+    /// vertex doesn't exist, they're the same vertex, or the first one
+    /// doesn't have the property.
+    ///
+    /// # Arguments
+    /// * `from_id`: The ID of the vertex to move the property from.
+    /// * `to_id`: The ID of the vertex to move the property to.
+    /// * `name`: The name of the property.
+    pub fn move_property(&self, from_id: Uuid, to_id: Uuid, name: &str) -> Result<bool> {
+        self.authorize(AccessKind::Write, "move_property")?;
+        let guard = self.holder.write_guard();
+        guard.touch(&[from_id, to_id]);
+        let vertex_manager = VertexManager::new(&self.holder);
+
+        if from_id == to_id || !vertex_manager.exists(from_id)? || !vertex_manager.exists(to_id)? {
+            return Ok(false);
+        }
+
+        let manager = VertexPropertyManager::new(&self.holder);
+
+        let value = match manager.get(from_id, name)? {
+            Some(value) => value,
+            None => return Ok(false),
+        };
+
+        validate::check(&self.holder, &Mutation::DeleteVertexProperty { id: from_id, name })?;
+        let mutation = Mutation::SetVertexProperty {
+            id: to_id,
+            name,
+            value: &value,
+        };
+        validate::check(&self.holder, &mutation)?;
+        limits::check_vertex_property(&self.holder, to_id, name, &value, 0)?;
+
+        if !manager.move_value(from_id, to_id, name)? {
+            return Ok(false);
+        }
+
+        self.audit("move_property", (from_id, to_id, name))?;
+        Ok(true)
+    }
+
+    /// Applies a JSON merge patch (RFC 7396) to the properties of a vertex,
+    /// treated as one JSON object keyed by property name. Each member of
+    /// `patch` is merged into the property of the same name: `null` deletes
+    /// the property, objects are merged recursively, and anything else
+    /// replaces the property's value. This lets clients update part of a
+    /// large property without sending the whole value.
+    ///
+    /// The properties are read, merged and written atomically, and indexes
+    /// and derived properties are updated as for any other property write.
+    /// Validators and property limits are checked against the merged
+    /// values before anything is written.
+    ///
+    /// Returns whether the patch was applied, which it isn't if the vertex
+    /// doesn't exist.
+    ///
+    /// # Arguments
+    /// * `id`: The ID of the vertex.
+    /// * `patch`: The merge patch, which must be a JSON object.
+    pub fn patch_vertex_properties(&self, id: Uuid, patch: &JsonValue) -> Result<bool> {
+        self.authorize(AccessKind::Write, "patch_vertex_properties")?;
+
+        let patch = match *patch {
+            JsonValue::Object(ref patch) => patch,
+            _ => return Err(Error::InvalidMergePatch.into()),
+        };
+
+        let guard = self.holder.write_guard();
+        guard.touch(&[id]);
+
+        if !VertexManager::new(&self.holder).exists(id)? {
+            return Ok(false);
+        }
+
+        let manager = VertexPropertyManager::new(&self.holder);
+        let mut new_properties = 0;
+
+        for (name, patch_value) in patch {
+            let old_value = manager.get(id, name)?;
+
+            match patch::merge(old_value.as_ref(), patch_value) {
+                Some(value) => {
+                    let mutation = Mutation::SetVertexProperty {
+                        id,
+                        name,
+                        value: &value,
+                    };
+                    validate::check(&self.holder, &mutation)?;
+
+                    if limits::check_vertex_property(&self.holder, id, name, &value, new_properties)? {
+                        new_properties += 1;
+                    }
+                }
+                None if old_value.is_some() => {
+                    validate::check(&self.holder, &Mutation::DeleteVertexProperty { id, name })?;
+                }
+                None => {}
+            }
+        }
+
+        manager.patch(id, patch)?;
+        self.audit("patch_vertex_properties", (id, patch))?;
+        Ok(true)
+    }
+
+    /// Starts a batch of writes, which are applied with one sled
+    /// transaction per kind of item when it's committed. This gives control
+    /// over how writes are grouped, without the cost of full transactional
+    /// semantics.
+    pub fn begin_batch(&self) -> SledBatch<'_> {
+        SledBatch::new(self)
+    }
+
+    /// Lists the types of vertices in the datastore, in type order, with how
+    /// many vertices there are of each. Like `list_property_names`, this
+    /// reads the catalog rather than scanning vertices.
+    pub fn list_vertex_types(&self) -> Result<Vec<(Type, u64)>> {
+        self.authorize(AccessKind::Read, "list_vertex_types")?;
+        self.list_types(CatalogKind::VertexType)
+    }
+
+    /// Lists the types of edges in the datastore, in type order, with how
+    /// many edges there are of each.
+    pub fn list_edge_types(&self) -> Result<Vec<(Type, u64)>> {
+        self.authorize(AccessKind::Read, "list_edge_types")?;
+        self.list_types(CatalogKind::EdgeType)
+    }
+
+    fn list_types(&self, kind: CatalogKind) -> Result<Vec<(Type, u64)>> {
+        let _guard = self.read_guard();
+        let catalog_manager = CatalogManager::new(&self.holder);
+        let mut types = Vec::new();
+
+        for item in catalog_manager.iterate_for_kind(kind) {
+            let (name, count) = item?;
+            types.push((Type(name), count));
+        }
+
+        Ok(types)
+    }
+
+    /// Estimates the degree distribution and property density of the graph
+    /// from a sample of vertices and edges, for capacity planning and query
+    /// tuning. This costs a few point reads per sampled vertex and edge,
+    /// plus a scan of each sampled vertex's edge ranges, rather than a scan
+    /// of the whole datastore.
+    ///
+    /// # Arguments
+    /// * `sample_size`: The maximum number of vertices, and of edges, to
+    ///   sample. Larger samples give tighter estimates.
+    pub fn estimate_statistics(&self, sample_size: usize) -> Result<GraphStatistics> {
+        self.authorize(AccessKind::Read, "estimate_statistics")?;
+        let _guard = self.read_guard();
+        stats::estimate(&self.holder, sample_size, &self.deadline())
+    }
+
+    /// Gets the vertices a source vertex maps to in a materialized view, in
+    /// ID order. While indexing is deferred, views aren't maintained, and
+    /// may be stale.
+    ///
+    /// # Arguments
+    /// * `name`: The name the view was registered under.
+    /// * `source_id`: The ID of the source vertex.
+    pub fn get_view(&self, name: &str, source_id: Uuid) -> Result<Vec<Uuid>> {
+        self.authorize(AccessKind::Read, "get_view")?;
+        let _guard = self.read_guard();
+        views::get(&self.holder, name, source_id)
+    }
+
+    /// Whether a materialized view maps a source vertex to a target vertex.
+    ///
+    /// # Arguments
+    /// * `name`: The name the view was registered under.
+    /// * `source_id`: The ID of the source vertex.
+    /// * `target_id`: The ID of the target vertex.
+    pub fn view_contains(&self, name: &str, source_id: Uuid, target_id: Uuid) -> Result<bool> {
+        self.authorize(AccessKind::Read, "view_contains")?;
+        let _guard = self.read_guard();
+        views::contains(&self.holder, name, source_id, target_id)
+    }
+
+    /// Reports which trees and key ranges a query would touch, whether it
+    /// would use an index, and how many items each step is estimated to
+    /// read and pass on, without running the query.
+    ///
+    /// Estimates come from the catalog of type and property name counts,
+    /// and assume that edges and properties are spread evenly across
+    /// vertices. Time bounds on edge queries aren't accounted for, and
+    /// neither is the result cache.
+    ///
+    /// # Arguments
+    /// * `q`: The query to explain.
+    pub fn explain<Q: Into<PlannedQuery>>(&self, q: Q) -> Result<QueryPlan> {
+        self.authorize(AccessKind::Read, "explain")?;
+        let _guard = self.read_guard();
+        explain::explain(&self.holder, &q.into())
+    }
+
+    /// Gets the most recently created vertices of a type, newest first.
+    ///
+    /// # Arguments
+    /// * `t`: The type of vertices to get.
+    /// * `limit`: The maximum number of vertices to return.
+    pub fn recent_vertices(&self, t: &Type, limit: u32) -> Result<Vec<Vertex>> {
+        self.authorize(AccessKind::Read, "recent_vertices")?;
+        let _guard = self.read_guard();
+        let vertex_creation_manager = VertexCreationManager::new(&self.holder);
+        let mut vertices = Vec::new();
+
+        for item in vertex_creation_manager.iterate_for_type(t).take(limit as usize) {
+            vertices.push(Vertex::with_id(item?, t.clone()));
+        }
+
+        Ok(vertices)
+    }
+
+    /// Gets the edges of a type, most recently updated first, without
+    /// scanning the edges of other types.
+    ///
+    /// # Arguments
+    /// * `t`: The type of edges to get.
+    /// * `high`: If set, only edges updated at or before this are returned,
+    ///   e.g. to continue from the update datetime of the last edge of a
+    ///   previous page.
+    /// * `limit`: The maximum number of edges to return.
+    pub fn get_edges_by_type(&self, t: &Type, high: Option<DateTime<Utc>>, limit: u32) -> Result<Vec<Edge>> {
+        self.authorize(AccessKind::Read, "get_edges_by_type")?;
+        let _guard = self.read_guard();
+        let edge_type_manager = EdgeTypeManager::new(&self.holder);
+        let deadline = self.deadline();
+        let mut edges = Vec::new();
+
+        for item in edge_type_manager.iterate_for_type(t, high).take(limit as usize) {
+            let (key, update_datetime) = item?;
+            deadline.tick()?;
+            edges.push(Edge::new(key, update_datetime));
+        }
+
+        Ok(edges)
+    }
+
+    /// Gets the vertices whose first properties of a composite index are
+    /// equal to `values`, ordered by the values of the rest of its
+    /// properties, then by ID.
+    ///
+    /// # Arguments
+    /// * `name`: The name of the index, as registered with
+    ///   `SledConfig::with_composite_index`.
+    /// * `values`: The values of the index's first properties, in order.
+    ///   There can be fewer values than properties, but not more.
+    pub fn get_vertices_by_properties(&self, name: &str, values: &[JsonValue]) -> Result<Vec<Vertex>> {
+        self.authorize(AccessKind::Read, "get_vertices_by_properties")?;
+        let _guard = self.read_guard();
+        composite::lookup(&self.holder, name, values, &self.deadline())
+    }
+
+    /// Gets the vertices whose property `name` is equal to `value`, in ID
+    /// order. This looks them up in the vertex property value index, rather
+    /// than scanning every vertex, so the property has to be indexed with
+    /// `SledDatastore::index_property`.
+    ///
+    /// # Arguments
+    /// * `name`: The name of the property.
+    /// * `value`: The value to look for.
+    pub fn get_vertices_by_property(&self, name: &str, value: &JsonValue) -> Result<Vec<Vertex>> {
+        self.authorize(AccessKind::Read, "get_vertices_by_property")?;
+        indexed::check_queryable(&self.holder, PropertyOwner::Vertex, name)?;
+        let _guard = self.read_guard();
+        let vertex_manager = VertexManager::new(&self.holder);
+        let vertex_property_manager = VertexPropertyManager::new(&self.holder);
+        let deadline = self.deadline();
+        let mut vertices = Vec::new();
+
+        for item in vertex_property_manager.iterate_for_value(name, value)? {
+            let id = item?;
+            deadline.tick()?;
+
+            // Concurrent writes to the same property can briefly leave an
+            // entry for a value that was replaced, so matches are checked
+            // against the property itself.
+            if vertex_property_manager.get(id, name)?.as_ref() != Some(value) {
+                continue;
+            }
+
+            if let Some(t) = vertex_manager.get(id)? {
+                vertices.push(Vertex::with_id(id, t));
+            }
+        }
+
+        Ok(vertices)
+    }
+
+    /// Gets the vertices whose property `name` is a number within `range`,
+    /// ordered by the property's value, then by ID. Datetimes stored as
+    /// numbers, e.g. UNIX timestamps, can be queried the same way. Values
+    /// that aren't numbers are never matched. The property has to be
+    /// indexed with `SledDatastore::index_property`.
+    ///
+    /// # Arguments
+    /// * `name`: The property name.
+    /// * `range`: The range of values, e.g. `18..65`.
+    pub fn get_vertices_with_property_in_range<T, R>(&self, name: &str, range: R) -> Result<Vec<Vertex>>
+    where
+        T: Copy + Into<f64>,
+        R: RangeBounds<T>,
+    {
+        self.authorize(AccessKind::Read, "get_vertices_with_property_in_range")?;
+        let to_f64 = |bound: Bound<&T>| match bound {
+            Bound::Included(&value) => Bound::Included(value.into()),
+            Bound::Excluded(&value) => Bound::Excluded(value.into()),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let range: (Bound<f64>, Bound<f64>) = (to_f64(range.start_bound()), to_f64(range.end_bound()));
+        indexed::check_queryable(&self.holder, PropertyOwner::Vertex, name)?;
+        let _guard = self.read_guard();
+        let vertex_manager = VertexManager::new(&self.holder);
+        let vertex_property_manager = VertexPropertyManager::new(&self.holder);
+        let deadline = self.deadline();
+        let mut vertices = Vec::new();
+
+        for item in vertex_property_manager.iterate_for_number_range(name, range) {
+            let (number, id) = item?;
+            deadline.tick()?;
 
-        if let Some(compression_factor) = opts.compression_factor {
-            config = config.compression_factor(compression_factor);
-        }
+            // As with `get_vertices_by_property`, entries for replaced
+            // values are skipped.
+            let matches = match vertex_property_manager.get(id, name)? {
+                Some(JsonValue::Number(ref value)) => value.as_f64() == Some(number),
+                _ => false,
+            };
 
-        let db = map_err(config.open())?;
+            if !matches {
+                continue;
+            }
 
-        Ok(SledHolder {
-            edges: map_err(db.open_tree("edges"))?,
-            edge_ranges: map_err(db.open_tree("edge_ranges"))?,
-            reversed_edge_ranges: map_err(db.open_tree("reversed_edge_ranges"))?,
-            vertex_properties: map_err(db.open_tree("vertex_properties"))?,
-            edge_properties: map_err(db.open_tree("edge_properties"))?,
-            db: Arc::new(db),
-        })
-    }
-}
+            if let Some(t) = vertex_manager.get(id)? {
+                vertices.push(Vertex::with_id(id, t));
+            }
+        }
 
-/// A datastore that is backed by Sled.
-pub struct SledDatastore {
-    pub(crate) holder: Arc<SledHolder>,
-}
+        Ok(vertices)
+    }
 
-impl<'ds> SledDatastore {
-    /// Creates a new Sled datastore.
+    /// Gets the edges whose property `name` is equal to `value`, in key
+    /// order. This looks them up in the edge property value index, so the
+    /// property has to be indexed with `SledDatastore::index_edge_property`.
     ///
     /// # Arguments
-    /// * `path`: The file path to the Sled database.
-    pub fn new<P: AsRef<Path>>(path: P) -> Result<SledDatastore> {
-        Ok(SledDatastore {
-            holder: Arc::new(SledHolder::new(path, SledConfig::default())?),
-        })
-    }
-}
+    /// * `name`: The name of the property.
+    /// * `value`: The value to look for.
+    pub fn get_edges_by_property(&self, name: &str, value: &JsonValue) -> Result<Vec<Edge>> {
+        self.authorize(AccessKind::Read, "get_edges_by_property")?;
+        indexed::check_queryable(&self.holder, PropertyOwner::Edge, name)?;
+        let _guard = self.read_guard();
+        let edge_manager = EdgeManager::new(&self.holder);
+        let edge_property_manager = EdgePropertyManager::new(&self.holder);
+        let deadline = self.deadline();
+        let mut edges = Vec::new();
 
-impl Datastore for SledDatastore {
-    type Trans = SledTransaction;
+        for item in edge_property_manager.iterate_for_value(name, value)? {
+            let key = item?;
+            deadline.tick()?;
 
-    fn sync(&self) -> Result<()> {
-        let holder = self.holder.clone();
-        let db = holder.db.clone();
-        map_err(db.flush())?;
-        Ok(())
-    }
+            // As with `get_vertices_by_property`, entries for replaced
+            // values are skipped.
+            if edge_property_manager
+                .get(key.outbound_id, &key.t, key.inbound_id, name)?
+                .as_ref()
+                != Some(value)
+            {
+                continue;
+            }
 
-    fn transaction(&self) -> Result<Self::Trans> {
-        Ok(SledTransaction::new(self.holder.clone()))
+            if let Some(update_datetime) = edge_manager.get(key.outbound_id, &key.t, key.inbound_id)? {
+                edges.push(Edge::new(key, update_datetime));
+            }
+        }
+
+        Ok(edges)
     }
 
-    fn bulk_insert<I>(&self, items: I) -> Result<()>
+    /// Gets the edges whose property `name` is a number within `range`,
+    /// ordered by the property's value, then by key. Values that aren't
+    /// numbers are never matched. The property has to be indexed with
+    /// `SledDatastore::index_edge_property`.
+    ///
+    /// # Arguments
+    /// * `name`: The property name.
+    /// * `range`: The range of values, e.g. `0.5..`.
+    pub fn get_edges_with_property_in_range<T, R>(&self, name: &str, range: R) -> Result<Vec<Edge>>
     where
-        I: Iterator<Item = BulkInsertItem>,
+        T: Copy + Into<f64>,
+        R: RangeBounds<T>,
     {
-        let vertex_manager = VertexManager::new(&self.holder);
+        self.authorize(AccessKind::Read, "get_edges_with_property_in_range")?;
+        let to_f64 = |bound: Bound<&T>| match bound {
+            Bound::Included(&value) => Bound::Included(value.into()),
+            Bound::Excluded(&value) => Bound::Excluded(value.into()),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let range: (Bound<f64>, Bound<f64>) = (to_f64(range.start_bound()), to_f64(range.end_bound()));
+        indexed::check_queryable(&self.holder, PropertyOwner::Edge, name)?;
+        let _guard = self.read_guard();
         let edge_manager = EdgeManager::new(&self.holder);
-        let vertex_property_manager = VertexPropertyManager::new(&self.holder.vertex_properties);
-        let edge_property_manager = EdgePropertyManager::new(&self.holder.edge_properties);
+        let edge_property_manager = EdgePropertyManager::new(&self.holder);
+        let deadline = self.deadline();
+        let mut edges = Vec::new();
 
-        for item in items {
-            match item {
-                BulkInsertItem::Vertex(ref vertex) => {
-                    vertex_manager.create(vertex)?;
-                }
-                BulkInsertItem::Edge(ref key) => {
-                    edge_manager.set(key.outbound_id, &key.t, key.inbound_id, Utc::now())?;
-                }
-                BulkInsertItem::VertexProperty(id, ref name, ref value) => {
-                    vertex_property_manager.set(id, name, value)?;
-                }
-                BulkInsertItem::EdgeProperty(ref key, ref name, ref value) => {
-                    edge_property_manager.set(key.outbound_id, &key.t, key.inbound_id, name, value)?;
-                }
-            }
-        }
+        for item in edge_property_manager.iterate_for_number_range(name, range) {
+            let (number, key) = item?;
+            deadline.tick()?;
 
-        map_err(self.holder.db.flush())?;
-        Ok(())
-    }
-}
+            let matches = match edge_property_manager.get(key.outbound_id, &key.t, key.inbound_id, name)? {
+                Some(JsonValue::Number(ref value)) => value.as_f64() == Some(number),
+                _ => false,
+            };
 
-/// A transaction that is backed by Sled.
-pub struct SledTransaction {
-    holder: Arc<SledHolder>,
-}
+            if !matches {
+                continue;
+            }
 
-impl SledTransaction {
-    fn new(holder: Arc<SledHolder>) -> Self {
-        SledTransaction { holder }
+            if let Some(update_datetime) = edge_manager.get(key.outbound_id, &key.t, key.inbound_id)? {
+                edges.push(Edge::new(key, update_datetime));
+            }
+        }
+
+        Ok(edges)
     }
 
-    #[allow(clippy::needless_collect)]
+    /// Runs a vertex query lazily: vertices are read as the iterator is
+    /// advanced, and none are held in memory, except under a write buffer
+    /// with staged writes, where a range query's results are merged with
+    /// them up front.
     fn vertex_query_to_iterator<'iter, 'trans: 'iter>(
         &'trans self,
         q: VertexQuery,
+        deadline: &'iter Deadline,
     ) -> Result<Box<dyn Iterator<Item = Result<VertexItem>> + 'iter>> {
         match q {
             VertexQuery::Range(q) => {
@@ -179,33 +4059,65 @@ impl SledTransaction {
                     None => Uuid::default(),
                 };
 
-                let mut iter: Box<dyn Iterator<Item = Result<VertexItem>>> =
-                    Box::new(vertex_manager.iterate_for_range(next_uuid));
+                let mut iter: Box<dyn Iterator<Item = Result<VertexItem>> + 'iter> =
+                    Box::new(deadline.bound(vertex_manager.iterate_for_range(next_uuid)));
 
-                if let Some(ref t) = q.t {
+                if let Some(t) = q.t.clone() {
                     iter = Box::new(iter.filter(move |item| match item {
-                        Ok((_, v)) => v == t,
+                        Ok((_, v)) => v == &t,
                         Err(_) => true,
                     }));
                 }
 
-                let results: Vec<Result<VertexItem>> = iter.take(q.limit as usize).collect();
-                Ok(Box::new(results.into_iter()))
+                let limit = q.limit as usize;
+
+                match self.overlay() {
+                    Some(writes) => {
+                        let mut vertices = Vec::new();
+
+                        for item in iter {
+                            if vertices.len() == limit {
+                                break;
+                            }
+
+                            let (id, t) = item?;
+
+                            if !writes.creates_vertex(id) && writes.vertex(id, Some(t.clone())).is_some() {
+                                vertices.push((id, t));
+                            }
+                        }
+
+                        // Staged vertices are merged in by ID, which can push
+                        // committed ones past the limit.
+                        for vertex in &writes.vertices {
+                            if vertex.id >= next_uuid && q.t.as_ref().is_none_or(|t| t == &vertex.t) {
+                                vertices.push((vertex.id, vertex.t.clone()));
+                            }
+                        }
+
+                        vertices.sort_by_key(|&(id, _)| id);
+                        vertices.truncate(limit);
+                        Ok(Box::new(vertices.into_iter().map(Ok)))
+                    }
+                    None => Ok(Box::new(iter.take(limit))),
+                }
             }
             VertexQuery::Specific(q) => {
                 let vertex_manager = VertexManager::new(&self.holder);
+                let overlay = self.overlay();
 
-                let iter = q.ids.into_iter().map(move |id| match vertex_manager.get(id)? {
-                    Some(value) => Ok(Some((id, value))),
-                    None => Ok(None),
+                let iter = q.ids.into_iter().map(move |id| {
+                    let t = overlaid_vertex(&overlay, id, vertex_manager.get(id)?);
+                    Ok(t.map(|t| (id, t)))
                 });
 
                 Ok(Box::new(remove_nones_from_iterator(iter)))
             }
             VertexQuery::Pipe(q) => {
                 let vertex_manager = VertexManager::new(&self.holder);
-                let edge_iterator = self.edge_query_to_iterator(*q.inner)?;
+                let edge_iterator = self.edge_query_to_iterator(*q.inner, deadline)?;
                 let direction = q.direction;
+                let overlay = self.overlay();
 
                 let iter = edge_iterator.map(move |item| {
                     let (outbound_id, _, _, inbound_id) = item?;
@@ -215,105 +4127,118 @@ impl SledTransaction {
                         EdgeDirection::Inbound => inbound_id,
                     };
 
-                    match vertex_manager.get(id)? {
-                        Some(value) => Ok(Some((id, value))),
-                        None => Ok(None),
-                    }
+                    let t = overlaid_vertex(&overlay, id, vertex_manager.get(id)?);
+                    Ok(t.map(|t| (id, t)))
                 });
 
-                let mut iter: Box<dyn Iterator<Item = Result<VertexItem>>> = Box::new(remove_nones_from_iterator(iter));
+                let mut iter: Box<dyn Iterator<Item = Result<VertexItem>> + 'iter> =
+                    Box::new(remove_nones_from_iterator(iter));
 
-                if let Some(ref t) = q.t {
+                if let Some(t) = q.t {
                     iter = Box::new(iter.filter(move |item| match item {
-                        Ok((_, v)) => v == t,
+                        Ok((_, v)) => v == &t,
                         Err(_) => true,
                     }));
                 }
 
-                let results: Vec<Result<VertexItem>> = iter.take(q.limit as usize).collect();
-                Ok(Box::new(results.into_iter()))
+                Ok(Box::new(iter.take(q.limit as usize)))
             }
         }
     }
 
+    /// Runs an edge query lazily. Pipe queries read the edges of one vertex
+    /// of the inner query at a time, and hold at most `limit` of them in
+    /// memory; under a write buffer with staged deletes, all of that
+    /// vertex's edges of the queried type are held while they're filtered.
     fn edge_query_to_iterator<'iter, 'trans: 'iter>(
         &'trans self,
         q: EdgeQuery,
+        deadline: &'iter Deadline,
     ) -> Result<Box<dyn Iterator<Item = Result<EdgeRangeItem>> + 'iter>> {
         match q {
             EdgeQuery::Specific(q) => {
                 let edge_manager = EdgeManager::new(&self.holder);
+                let overlay = self.overlay();
 
-                let edges = q.keys.into_iter().map(move |key| {
-                    match edge_manager.get(key.outbound_id, &key.t, key.inbound_id)? {
-                        Some(update_datetime) => {
-                            Ok(Some((key.outbound_id, key.t.clone(), update_datetime, key.inbound_id)))
-                        }
-                        None => Ok(None),
-                    }
-                });
+                let edges =
+                    q.keys.into_iter().map(move |key| {
+                        let update_datetime = edge_manager.get(key.outbound_id, &key.t, key.inbound_id)?;
+                        let update_datetime = match overlay {
+                            Some(ref writes) => writes.edge(&key, update_datetime),
+                            None => update_datetime,
+                        };
+
+                        Ok(update_datetime
+                            .map(|update_datetime| (key.outbound_id, key.t, update_datetime, key.inbound_id)))
+                    });
 
                 let iterator = remove_nones_from_iterator(edges);
                 Ok(Box::new(iterator))
             }
             EdgeQuery::Pipe(q) => {
-                let vertex_iterator = self.vertex_query_to_iterator(*q.inner)?;
+                let vertex_iterator = self.vertex_query_to_iterator(*q.inner, deadline)?;
 
-                let edge_range_manager = match q.direction {
-                    EdgeDirection::Outbound => EdgeRangeManager::new(&self.holder),
-                    EdgeDirection::Inbound => EdgeRangeManager::new_reversed(&self.holder),
-                };
+                let direction = q.direction;
+                let edge_range_manager = EdgeRangeManager::for_direction(&self.holder, direction);
+                let limit = q.limit as usize;
+                let overlay = self.overlay();
+                let t = q.t;
+                let (low, high) = (q.low, q.high);
+
+                // Each vertex's edges are read once the previous vertex's
+                // have all been returned, so `remaining` is known by then.
+                let mut remaining = limit;
+
+                let edges = vertex_iterator.flat_map(move |item| {
+                    let (id, _) = match item {
+                        Ok(item) => item,
+                        Err(err) => return vec![Err(err)],
+                    };
 
-                // Ideally we'd use iterators all the way down, but things
-                // start breaking apart due to conditional expressions not
-                // returning the same type signature, issues with `Result`s
-                // and some of the iterators, etc. So at this point, we'll
-                // just resort to building a vector.
-                let mut edges: Vec<Result<EdgeRangeItem>> = Vec::new();
-
-                for item in vertex_iterator {
-                    let (id, _) = item?;
-                    let edge_iterator = edge_range_manager.iterate_for_range(id, q.t.as_ref(), q.high)?;
-
-                    for item in edge_iterator {
-                        match item {
-                            Ok((
-                                edge_range_first_id,
-                                edge_range_t,
-                                edge_range_update_datetime,
-                                edge_range_second_id,
-                            )) => {
-                                if let Some(low) = q.low {
-                                    if edge_range_update_datetime < low {
-                                        break;
-                                    }
-                                }
-
-                                edges.push(match q.direction {
-                                    EdgeDirection::Outbound => Ok((
-                                        edge_range_first_id,
-                                        edge_range_t,
-                                        edge_range_update_datetime,
-                                        edge_range_second_id,
-                                    )),
-                                    EdgeDirection::Inbound => Ok((
-                                        edge_range_second_id,
-                                        edge_range_t,
-                                        edge_range_update_datetime,
-                                        edge_range_first_id,
-                                    )),
+                    if remaining == 0 {
+                        return Vec::new();
+                    }
+
+                    let items = match overlay {
+                        Some(ref writes) => {
+                            // Staged writes can hide committed edges, so
+                            // enough are fetched to make up for them.
+                            let fetch = if writes.has_deletes() {
+                                usize::MAX
+                            } else {
+                                remaining.saturating_add(writes.edges.len())
+                            };
+
+                            edge_range_manager
+                                .query(id, t.as_ref(), low, high, fetch, deadline)
+                                .map(|items| {
+                                    let mut items = writes.edge_range(id, direction, t.as_ref(), low, high, items);
+                                    items.truncate(remaining);
+                                    items
                                 })
-                            }
-                            Err(_) => edges.push(item),
                         }
+                        None => edge_range_manager.query(id, t.as_ref(), low, high, remaining, deadline),
+                    };
 
-                        if edges.len() == q.limit as usize {
-                            break;
-                        }
-                    }
-                }
+                    let items = match items {
+                        Ok(items) => items,
+                        Err(err) => return vec![Err(err)],
+                    };
+
+                    remaining -= items.len();
+
+                    items
+                        .into_iter()
+                        .map(|(first_id, t, update_datetime, second_id)| {
+                            Ok(match direction {
+                                EdgeDirection::Outbound => (first_id, t, update_datetime, second_id),
+                                EdgeDirection::Inbound => (second_id, t, update_datetime, first_id),
+                            })
+                        })
+                        .collect()
+                });
 
-                Ok(Box::new(edges.into_iter()))
+                Ok(Box::new(edges.take(limit)))
             }
         }
     }
@@ -321,210 +4246,525 @@ impl SledTransaction {
 
 impl Transaction for SledTransaction {
     fn create_vertex(&self, vertex: &Vertex) -> Result<bool> {
+        self.authorize_scoped(AccessKind::Write, "create_vertex", Some(vec![vertex.id]))?;
+
+        let buffered = self.buffered(|writes| {
+            let committed = VertexManager::new(&self.holder).get(vertex.id)?;
+
+            if writes.vertex(vertex.id, committed).is_some() {
+                return Ok(false);
+            }
+
+            writes.vertices.push(vertex.clone());
+            Ok(true)
+        });
+
+        if let Some(result) = buffered {
+            return result;
+        }
+
+        let guard = self.holder.write_guard();
+        guard.touch(&[vertex.id]);
         let vertex_manager = VertexManager::new(&self.holder);
 
         if vertex_manager.exists(vertex.id)? {
             Ok(false)
         } else {
+            validate::check(&self.holder, &Mutation::CreateVertex(vertex))?;
             vertex_manager.create(vertex)?;
+            self.audit("create_vertex", vertex)?;
             Ok(true)
         }
     }
 
     fn get_vertices<Q: Into<VertexQuery>>(&self, q: Q) -> Result<Vec<Vertex>> {
-        let iterator = self.vertex_query_to_iterator(q.into())?;
+        let q = q.into();
+        self.authorize_scoped(AccessKind::Read, "get_vertices", vertex_query_scope(&q))?;
+        let _guard = self.read_guard();
 
-        let mapped = iterator.map(move |item| {
-            let (id, t) = item?;
-            let vertex = Vertex::with_id(id, t);
-            Ok(vertex)
-        });
+        self.cached("vertices", &q, || {
+            let deadline = self.deadline();
+            let iterator = self.vertex_query_to_iterator(q.clone(), &deadline)?;
+
+            let mapped = iterator.map(move |item| {
+                let (id, t) = item?;
+                let vertex = Vertex::with_id(id, t);
+                Ok(vertex)
+            });
 
-        mapped.collect()
+            mapped.collect()
+        })
     }
 
     fn delete_vertices<Q: Into<VertexQuery>>(&self, q: Q) -> Result<()> {
-        let iterator = self.vertex_query_to_iterator(q.into())?;
-        let vertex_manager = VertexManager::new(&self.holder);
+        let q = q.into();
+        self.authorize_scoped(AccessKind::Write, "delete_vertices", vertex_query_scope(&q))?;
 
-        for item in iterator {
-            let (id, _) = item?;
-            vertex_manager.delete(id)?;
+        if self.write_buffer.is_some() {
+            let vertex_manager = VertexManager::new(&self.holder);
+            let mut ids = Vec::new();
+
+            for id in self.query_vertex_ids(&q)? {
+                ids.push((id, vertex_manager.exists(id)?));
+            }
+
+            self.stage(|writes| {
+                for (id, committed) in ids {
+                    writes.delete_vertex(id, committed);
+                }
+            });
+
+            return Ok(());
         }
 
-        Ok(())
+        self.delete_vertices_with(&q, None)?;
+        self.audit("delete_vertices", q)
     }
 
     fn get_vertex_count(&self) -> Result<u64> {
+        self.authorize(AccessKind::Read, "get_vertex_count")?;
+        let _guard = self.read_guard();
+        let deadline = self.deadline();
         let vertex_manager = VertexManager::new(&self.holder);
-        let iterator = vertex_manager.iterate_for_range(Uuid::default());
-        Ok(iterator.count() as u64)
+        let overlay = self.overlay();
+        let mut count = 0;
+
+        for item in deadline.bound(vertex_manager.iterate_for_range(Uuid::default())) {
+            let (id, t) = item?;
+
+            if let Some(ref writes) = overlay {
+                if writes.creates_vertex(id) || writes.vertex(id, Some(t)).is_none() {
+                    continue;
+                }
+            }
+
+            count += 1;
+        }
+
+        if let Some(ref writes) = overlay {
+            count += writes.vertices.len() as u64;
+        }
+
+        Ok(count)
     }
 
     fn create_edge(&self, key: &EdgeKey) -> Result<bool> {
-        let vertex_manager = VertexManager::new(&self.holder);
+        let scope = vec![key.outbound_id, key.inbound_id];
+        self.authorize_scoped(AccessKind::Write, "create_edge", Some(scope))?;
 
-        if !vertex_manager.exists(key.outbound_id)? || !vertex_manager.exists(key.inbound_id)? {
-            Ok(false)
-        } else {
-            let edge_manager = EdgeManager::new(&self.holder);
-            edge_manager.set(key.outbound_id, &key.t, key.inbound_id, Utc::now())?;
+        let buffered = self.buffered(|writes| {
+            let vertex_manager = VertexManager::new(&self.holder);
+
+            for &id in &[key.outbound_id, key.inbound_id] {
+                if writes.vertex(id, vertex_manager.get(id)?).is_none() {
+                    return Ok(false);
+                }
+            }
+
+            let key = &self.holder.edge_range_layout.canonical_key(key);
+
+            if !writes.edges.contains(key) {
+                writes.edges.push(key.clone());
+            }
+
+            let update_datetime = self.holder.datetime_precision.truncate(Utc::now());
+            writes.edge_datetimes.insert(key.clone(), update_datetime);
             Ok(true)
+        });
+
+        match buffered {
+            Some(result) => result,
+            None => self.create_edge_with("create_edge", key, true, Utc::now()),
         }
     }
 
     fn get_edges<Q: Into<EdgeQuery>>(&self, q: Q) -> Result<Vec<Edge>> {
-        let iterator = self.edge_query_to_iterator(q.into())?;
+        let q = q.into();
+        self.authorize_scoped(AccessKind::Read, "get_edges", edge_query_scope(&q))?;
+        let _guard = self.read_guard();
 
-        let mapped = iterator.map(move |item: Result<EdgeRangeItem>| {
-            let (outbound_id, t, update_datetime, inbound_id) = item?;
-            let key = EdgeKey::new(outbound_id, t, inbound_id);
-            let edge = Edge::new(key, update_datetime);
-            Ok(edge)
-        });
+        self.cached("edges", &q, || {
+            let deadline = self.deadline();
+            let iterator = self.edge_query_to_iterator(q.clone(), &deadline)?;
+
+            let mapped = iterator.map(move |item: Result<EdgeRangeItem>| {
+                let (outbound_id, t, update_datetime, inbound_id) = item?;
+                let key = EdgeKey::new(outbound_id, t, inbound_id);
+                let edge = Edge::new(key, update_datetime);
+                Ok(edge)
+            });
 
-        mapped.collect()
+            mapped.collect()
+        })
     }
 
     fn delete_edges<Q: Into<EdgeQuery>>(&self, q: Q) -> Result<()> {
+        let q = q.into();
+        self.authorize_scoped(AccessKind::Write, "delete_edges", edge_query_scope(&q))?;
+
+        if self.write_buffer.is_some() {
+            let edge_manager = EdgeManager::new(&self.holder);
+            let mut keys = Vec::new();
+
+            for key in self.query_edge_keys(&q)? {
+                let committed = edge_manager.get(key.outbound_id, &key.t, key.inbound_id)?.is_some();
+                keys.push((key, committed));
+            }
+
+            self.stage(|writes| {
+                for (key, committed) in keys {
+                    writes.delete_edge(&key, committed);
+                }
+            });
+
+            return Ok(());
+        }
+
+        let guard = self.holder.write_guard();
         let edge_manager = EdgeManager::new(&self.holder);
         let vertex_manager = VertexManager::new(&self.holder);
-        let iterator = self.edge_query_to_iterator(q.into())?;
+        let deadline = self.deadline();
+        let iterator = self.edge_query_to_iterator(q.clone(), &deadline)?;
 
         for item in iterator {
             let (outbound_id, t, update_datetime, inbound_id) = item?;
 
             if vertex_manager.get(outbound_id)?.is_some() {
-                edge_manager.delete(outbound_id, &t, inbound_id, update_datetime)?;
+                guard.touch(&[outbound_id, inbound_id]);
+                let key = EdgeKey::new(outbound_id, t, inbound_id);
+                validate::check(&self.holder, &Mutation::DeleteEdge(&key))?;
+                edge_manager.delete(outbound_id, &key.t, inbound_id, update_datetime)?;
             };
         }
-        Ok(())
+
+        self.audit("delete_edges", q)
     }
 
     fn get_edge_count(&self, id: Uuid, t: Option<&Type>, direction: EdgeDirection) -> Result<u64> {
-        let edge_range_manager = match direction {
-            EdgeDirection::Outbound => EdgeRangeManager::new(&self.holder),
-            EdgeDirection::Inbound => EdgeRangeManager::new_reversed(&self.holder),
-        };
+        self.authorize_scoped(AccessKind::Read, "get_edge_count", Some(vec![id]))?;
+        let _guard = self.read_guard();
+        let edge_range_manager = EdgeRangeManager::for_direction(&self.holder, direction);
+
+        let deadline = self.deadline();
+        let iterator = deadline.bound(edge_range_manager.iterate_for_range(id, t, None)?);
+
+        if let Some(writes) = self.overlay() {
+            let committed = iterator.collect::<Result<Vec<_>>>()?;
+            return Ok(writes.edge_range(id, direction, t, None, None, committed).len() as u64);
+        }
 
-        let iter = edge_range_manager.iterate_for_range(id, t, None)?;
-        let count = iter.count();
+        let mut count = 0;
 
-        Ok(count as u64)
+        for item in iterator {
+            item?;
+            count += 1;
+        }
+
+        Ok(count)
     }
 
     fn get_vertex_properties(&self, q: VertexPropertyQuery) -> Result<Vec<VertexProperty>> {
-        let manager = VertexPropertyManager::new(&self.holder.vertex_properties);
-        let mut properties = Vec::new();
+        self.authorize_scoped(AccessKind::Read, "get_vertex_properties", vertex_query_scope(&q.inner))?;
+        let _guard = self.read_guard();
+        self.cached("vertex_properties", &q, || {
+            let manager = VertexPropertyManager::new(&self.holder);
+            let overlay = self.overlay();
+            let mut properties = Vec::new();
 
-        for item in self.vertex_query_to_iterator(q.inner)? {
-            let (id, _) = item?;
-            let value = manager.get(id, &q.name)?;
+            let deadline = self.deadline();
+            for item in self.vertex_query_to_iterator(q.inner.clone(), &deadline)? {
+                let (id, _) = item?;
+                let mut value = manager.get(id, &q.name)?;
 
-            if let Some(value) = value {
-                properties.push(VertexProperty::new(id, value));
+                if let Some(ref writes) = overlay {
+                    value = writes.vertex_property(id, &q.name, value);
+                }
+
+                if let Some(value) = value {
+                    properties.push(VertexProperty::new(id, value));
+                }
             }
-        }
 
-        Ok(properties)
+            Ok(properties)
+        })
     }
 
     fn get_all_vertex_properties<Q: Into<VertexQuery>>(&self, q: Q) -> Result<Vec<VertexProperties>> {
-        let manager = VertexPropertyManager::new(&self.holder.vertex_properties);
-        let iterator = self.vertex_query_to_iterator(q.into())?;
+        let q = q.into();
+        self.authorize_scoped(AccessKind::Read, "get_all_vertex_properties", vertex_query_scope(&q))?;
+        let _guard = self.read_guard();
 
-        let iter = iterator.map(move |item| {
-            let (id, t) = item?;
-            let vertex = Vertex::with_id(id, t);
+        self.cached("all_vertex_properties", &q, || {
+            let manager = VertexPropertyManager::new(&self.holder);
+            let overlay = self.overlay();
+            let deadline = self.deadline();
+            let iterator = self.vertex_query_to_iterator(q.clone(), &deadline)?;
 
-            let it = manager.iterate_for_owner(id)?;
-            let props: Result<Vec<_>> = it.collect();
-            let props_iter = props?.into_iter();
-            let props = props_iter
-                .map(|((_, name), value)| NamedProperty::new(name, value))
-                .collect();
+            let iter = iterator.map(move |item| {
+                let (id, t) = item?;
+                let vertex = Vertex::with_id(id, t);
 
-            Ok(VertexProperties::new(vertex, props))
-        });
+                let it = manager.iterate_for_owner(id)?;
+                let props: Result<Vec<_>> = it.collect();
+                let mut props: Vec<_> = props?.into_iter().map(|((_, name), value)| (name, value)).collect();
+
+                if let Some(ref writes) = overlay {
+                    props = writes.vertex_properties_of(id, props);
+                }
+
+                let props = props
+                    .into_iter()
+                    .map(|(name, value)| NamedProperty::new(name, value))
+                    .collect();
 
-        iter.collect()
+                Ok(VertexProperties::new(vertex, props))
+            });
+
+            iter.collect()
+        })
     }
 
     fn set_vertex_properties(&self, q: VertexPropertyQuery, value: &JsonValue) -> Result<()> {
-        let manager = VertexPropertyManager::new(&self.holder.vertex_properties);
+        self.authorize_scoped(AccessKind::Write, "set_vertex_properties", vertex_query_scope(&q.inner))?;
 
-        for item in self.vertex_query_to_iterator(q.inner)? {
+        if self.write_buffer.is_some() {
+            let ids = match q.inner {
+                VertexQuery::Specific(ref specific) => specific.ids.clone(),
+                ref inner => self.query_vertex_ids(inner)?,
+            };
+
+            self.stage(|writes| {
+                for id in ids {
+                    writes.vertex_properties.push((id, q.name.clone(), value.clone()));
+                }
+            });
+
+            return Ok(());
+        }
+
+        let guard = self.holder.write_guard();
+        let manager = VertexPropertyManager::new(&self.holder);
+
+        let deadline = self.deadline();
+        for item in self.vertex_query_to_iterator(q.inner.clone(), &deadline)? {
             let (id, _) = item?;
+            guard.touch(&[id]);
+            let mutation = Mutation::SetVertexProperty {
+                id,
+                name: &q.name,
+                value,
+            };
+            validate::check(&self.holder, &mutation)?;
+            limits::check_vertex_property(&self.holder, id, &q.name, value, 0)?;
             manager.set(id, &q.name, value)?;
         }
-        Ok(())
+
+        self.audit("set_vertex_properties", (q, value))
     }
 
     fn delete_vertex_properties(&self, q: VertexPropertyQuery) -> Result<()> {
-        let manager = VertexPropertyManager::new(&self.holder.vertex_properties);
+        self.authorize_scoped(
+            AccessKind::Write,
+            "delete_vertex_properties",
+            vertex_query_scope(&q.inner),
+        )?;
+
+        if self.write_buffer.is_some() {
+            let ids = self.query_vertex_ids(&q.inner)?;
+
+            self.stage(|writes| {
+                for id in ids {
+                    writes.delete_vertex_property(id, &q.name);
+                }
+            });
+
+            return Ok(());
+        }
+
+        let guard = self.holder.write_guard();
+        let manager = VertexPropertyManager::new(&self.holder);
 
-        for item in self.vertex_query_to_iterator(q.inner)? {
+        let deadline = self.deadline();
+        for item in self.vertex_query_to_iterator(q.inner.clone(), &deadline)? {
             let (id, _) = item?;
+            guard.touch(&[id]);
+            validate::check(&self.holder, &Mutation::DeleteVertexProperty { id, name: &q.name })?;
             manager.delete(id, &q.name)?;
         }
-        Ok(())
+
+        self.audit("delete_vertex_properties", q)
     }
 
     fn get_edge_properties(&self, q: EdgePropertyQuery) -> Result<Vec<EdgeProperty>> {
-        let manager = EdgePropertyManager::new(&self.holder.edge_properties);
-        let mut properties = Vec::new();
-
-        for item in self.edge_query_to_iterator(q.inner)? {
-            let (outbound_id, t, _, inbound_id) = item?;
-            let value = manager.get(outbound_id, &t, inbound_id, &q.name)?;
+        self.authorize_scoped(AccessKind::Read, "get_edge_properties", edge_query_scope(&q.inner))?;
+        let _guard = self.read_guard();
+        self.cached("edge_properties", &q, || {
+            let manager = EdgePropertyManager::new(&self.holder);
+            let overlay = self.overlay();
+            let mut properties = Vec::new();
 
-            if let Some(value) = value {
+            let deadline = self.deadline();
+            for item in self.edge_query_to_iterator(q.inner.clone(), &deadline)? {
+                let (outbound_id, t, _, inbound_id) = item?;
                 let key = EdgeKey::new(outbound_id, t, inbound_id);
-                properties.push(EdgeProperty::new(key, value));
+                let mut value = manager.get(outbound_id, &key.t, inbound_id, &q.name)?;
+
+                if let Some(ref writes) = overlay {
+                    value = writes.edge_property(&key, &q.name, value);
+                }
+
+                if let Some(value) = value {
+                    properties.push(EdgeProperty::new(key, value));
+                }
             }
-        }
 
-        Ok(properties)
+            Ok(properties)
+        })
     }
 
     fn get_all_edge_properties<Q: Into<EdgeQuery>>(&self, q: Q) -> Result<Vec<EdgeProperties>> {
-        let manager = EdgePropertyManager::new(&self.holder.edge_properties);
-        let iterator = self.edge_query_to_iterator(q.into())?;
-
-        let iter = iterator.map(move |item| {
-            let (out_id, t, time, in_id) = item?;
-            let edge = Edge::new(EdgeKey::new(out_id, t.clone(), in_id), time);
-            let it = manager.iterate_for_owner(out_id, &t, in_id)?;
-            let props: Result<Vec<_>> = it.collect();
-            let props_iter = props?.into_iter();
-            let props = props_iter
-                .map(|((_, _, _, name), value)| NamedProperty::new(name, value))
-                .collect();
-
-            Ok(EdgeProperties::new(edge, props))
-        });
+        let q = q.into();
+        self.authorize_scoped(AccessKind::Read, "get_all_edge_properties", edge_query_scope(&q))?;
+        let _guard = self.read_guard();
+
+        self.cached("all_edge_properties", &q, || {
+            let manager = EdgePropertyManager::new(&self.holder);
+            let overlay = self.overlay();
+            let deadline = self.deadline();
+            let iterator = self.edge_query_to_iterator(q.clone(), &deadline)?;
+
+            let iter = iterator.map(move |item| {
+                let (out_id, t, time, in_id) = item?;
+                let edge = Edge::new(EdgeKey::new(out_id, t.clone(), in_id), time);
+                let it = manager.iterate_for_owner(out_id, &t, in_id)?;
+                let props: Result<Vec<_>> = it.collect();
+                let mut props: Vec<_> = props?
+                    .into_iter()
+                    .map(|((_, _, _, name), value)| (name, value))
+                    .collect();
+
+                if let Some(ref writes) = overlay {
+                    props = writes.edge_properties_of(&edge.key, props);
+                }
+
+                let props = props
+                    .into_iter()
+                    .map(|(name, value)| NamedProperty::new(name, value))
+                    .collect();
 
-        iter.collect()
+                Ok(EdgeProperties::new(edge, props))
+            });
+
+            iter.collect()
+        })
     }
 
     fn set_edge_properties(&self, q: EdgePropertyQuery, value: &JsonValue) -> Result<()> {
-        let manager = EdgePropertyManager::new(&self.holder.edge_properties);
+        self.authorize_scoped(AccessKind::Write, "set_edge_properties", edge_query_scope(&q.inner))?;
+
+        if self.write_buffer.is_some() {
+            let keys = match q.inner {
+                EdgeQuery::Specific(ref specific) => specific.keys.clone(),
+                ref inner => self.query_edge_keys(inner)?,
+            };
+
+            self.stage(|writes| {
+                for key in keys {
+                    writes.edge_properties.push((key, q.name.clone(), value.clone()));
+                }
+            });
+
+            return Ok(());
+        }
 
-        for item in self.edge_query_to_iterator(q.inner)? {
+        let guard = self.holder.write_guard();
+        let manager = EdgePropertyManager::new(&self.holder);
+
+        let deadline = self.deadline();
+        for item in self.edge_query_to_iterator(q.inner.clone(), &deadline)? {
             let (outbound_id, t, _, inbound_id) = item?;
-            manager.set(outbound_id, &t, inbound_id, &q.name, value)?;
+            guard.touch(&[outbound_id, inbound_id]);
+            let key = EdgeKey::new(outbound_id, t, inbound_id);
+            let mutation = Mutation::SetEdgeProperty {
+                key: &key,
+                name: &q.name,
+                value,
+            };
+            validate::check(&self.holder, &mutation)?;
+            limits::check_edge_property(&self.holder, &key, &q.name, value, 0)?;
+            manager.set(outbound_id, &key.t, inbound_id, &q.name, value)?;
         }
-        Ok(())
+
+        self.audit("set_edge_properties", (q, value))
     }
 
     fn delete_edge_properties(&self, q: EdgePropertyQuery) -> Result<()> {
-        let manager = EdgePropertyManager::new(&self.holder.edge_properties);
+        self.authorize_scoped(AccessKind::Write, "delete_edge_properties", edge_query_scope(&q.inner))?;
+
+        if self.write_buffer.is_some() {
+            let keys = self.query_edge_keys(&q.inner)?;
+
+            self.stage(|writes| {
+                for key in keys {
+                    writes.delete_edge_property(&key, &q.name);
+                }
+            });
 
-        for item in self.edge_query_to_iterator(q.inner)? {
+            return Ok(());
+        }
+
+        let guard = self.holder.write_guard();
+        let manager = EdgePropertyManager::new(&self.holder);
+
+        let deadline = self.deadline();
+        for item in self.edge_query_to_iterator(q.inner.clone(), &deadline)? {
             let (outbound_id, t, _, inbound_id) = item?;
-            manager.delete(outbound_id, &t, inbound_id, &q.name)?;
+            guard.touch(&[outbound_id, inbound_id]);
+            let key = EdgeKey::new(outbound_id, t, inbound_id);
+            validate::check(
+                &self.holder,
+                &Mutation::DeleteEdgeProperty {
+                    key: &key,
+                    name: &q.name,
+                },
+            )?;
+            manager.delete(outbound_id, &key.t, inbound_id, &q.name)?;
         }
-        Ok(())
+
+        self.audit("delete_edge_properties", q)
+    }
+}
+
+/// The vertices that a vertex query reads, if they're known without running
+/// it. Piped queries read the vertices on the other side of edges, which
+/// aren't.
+fn vertex_query_scope(q: &VertexQuery) -> Option<Vec<Uuid>> {
+    match *q {
+        VertexQuery::Specific(ref q) => Some(q.ids.clone()),
+        _ => None,
+    }
+}
+
+/// The vertices whose edges an edge query reads, if they're known without
+/// running it.
+fn edge_query_scope(q: &EdgeQuery) -> Option<Vec<Uuid>> {
+    match *q {
+        EdgeQuery::Specific(ref q) => Some(
+            q.keys
+                .iter()
+                .flat_map(|key| vec![key.outbound_id, key.inbound_id])
+                .collect(),
+        ),
+        EdgeQuery::Pipe(ref q) => vertex_query_scope(&q.inner),
+    }
+}
+
+/// Gets the type of a vertex as a transaction sees it, given its committed
+/// type and the transaction's staged writes, if any.
+fn overlaid_vertex(overlay: &Option<PendingWrites>, id: Uuid, committed: Option<Type>) -> Option<Type> {
+    match *overlay {
+        Some(ref writes) => writes.vertex(id, committed),
+        None => committed,
     }
 }
 
@@ -0,0 +1,177 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::errors::Error;
+
+use indradb::Result;
+
+/// The number of items processed by operations, keyed by the tag of the
+/// `OpContext` they ran under.
+pub(crate) type TaggedWork = Arc<Mutex<HashMap<String, u64>>>;
+
+/// A flag shared between a request handler and the operations it starts,
+/// used to cancel them once the request is abandoned. Clones share the
+/// same flag.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    /// Creates a token that hasn't been cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels the operations watching this token. Ones that are running
+    /// fail with `Error::Cancelled` at their next check.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether the token has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// The context of the request a transaction's operations run on behalf
+/// of. Set with `SledTransaction::with_context`.
+#[derive(Clone, Debug, Default)]
+pub struct OpContext {
+    deadline: Option<Instant>,
+    cancellation: Option<CancellationToken>,
+    tag: Option<String>,
+}
+
+impl OpContext {
+    /// Creates a context without a deadline, cancellation or tag.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fails operations with `Error::Timeout` once `deadline` has passed.
+    /// Unlike `SledTransaction::with_timeout`, this bounds the request as
+    /// a whole rather than each operation.
+    pub fn with_deadline(self, deadline: Instant) -> Self {
+        OpContext {
+            deadline: Some(deadline),
+            ..self
+        }
+    }
+
+    /// Fails operations with `Error::Cancelled` once `token` is cancelled.
+    pub fn with_cancellation(self, token: CancellationToken) -> Self {
+        OpContext {
+            cancellation: Some(token),
+            ..self
+        }
+    }
+
+    /// Attributes the items processed by operations to `tag`, e.g. the
+    /// caller's name, in `SledDatastore::work_by_tag`.
+    pub fn with_tag<S: Into<String>>(self, tag: S) -> Self {
+        OpContext {
+            tag: Some(tag.into()),
+            ..self
+        }
+    }
+}
+
+/// Bounds how long a single transaction operation may run, counting the
+/// items it has processed so that a timeout can report how far it got.
+pub(crate) struct Deadline {
+    start: Instant,
+    timeout: Option<Duration>,
+    expires: Option<Instant>,
+    cancellation: Option<CancellationToken>,
+    tagged: Option<(String, TaggedWork)>,
+    processed: Cell<u64>,
+}
+
+impl Deadline {
+    /// Starts a deadline `timeout` from now, or one that never expires if
+    /// `timeout` is `None`.
+    pub(crate) fn new(timeout: Option<Duration>) -> Self {
+        Deadline {
+            start: Instant::now(),
+            timeout,
+            expires: None,
+            cancellation: None,
+            tagged: None,
+            processed: Cell::new(0),
+        }
+    }
+
+    /// Starts a deadline that also honors `context`, adding the items
+    /// processed to `work` under its tag once the operation is done.
+    pub(crate) fn with_context(timeout: Option<Duration>, context: &OpContext, work: &TaggedWork) -> Self {
+        Deadline {
+            start: Instant::now(),
+            timeout,
+            expires: context.deadline,
+            cancellation: context.cancellation.clone(),
+            tagged: context.tag.clone().map(|tag| (tag, work.clone())),
+            processed: Cell::new(0),
+        }
+    }
+
+    /// Checks the deadline before processing another item.
+    pub(crate) fn tick(&self) -> Result<()> {
+        if let Some(ref token) = self.cancellation {
+            if token.is_cancelled() {
+                return Err(Error::Cancelled {
+                    processed: self.processed.get(),
+                }
+                .into());
+            }
+        }
+
+        let elapsed = self.start.elapsed();
+        let timed_out = self.timeout.is_some_and(|timeout| elapsed > timeout)
+            || self.expires.is_some_and(|expires| Instant::now() > expires);
+
+        if timed_out {
+            return Err(Error::Timeout {
+                elapsed,
+                processed: self.processed.get(),
+            }
+            .into());
+        }
+
+        self.processed.set(self.processed.get() + 1);
+        Ok(())
+    }
+
+    /// Checks the deadline before each item of `iter`. Once it's exceeded,
+    /// a single `Error::Timeout` is yielded and the iterator ends.
+    pub(crate) fn bound<'a, I, T>(&'a self, iter: I) -> impl Iterator<Item = Result<T>> + 'a
+    where
+        I: Iterator<Item = Result<T>> + 'a,
+    {
+        iter.scan(false, move |timed_out, item| {
+            if *timed_out {
+                return None;
+            }
+
+            match self.tick() {
+                Ok(()) => Some(item),
+                Err(err) => {
+                    *timed_out = true;
+                    Some(Err(err))
+                }
+            }
+        })
+    }
+}
+
+impl Drop for Deadline {
+    fn drop(&mut self) {
+        if let Some((ref tag, ref work)) = self.tagged {
+            if let Ok(mut work) = work.lock() {
+                *work.entry(tag.clone()).or_insert(0) += self.processed.get();
+            }
+        }
+    }
+}
@@ -0,0 +1,256 @@
+use std::cell::Cell;
+use std::str;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use super::errors::Error;
+use super::precision::DatetimePrecision;
+
+use chrono::offset::Utc;
+use chrono::DateTime;
+use indradb::{Error as IndraError, Result, Type};
+use sled::Tree;
+use uuid::Uuid;
+
+/// The most record keys a datastore remembers under
+/// `DecodeErrorPolicy::SkipAndReport`, so that a badly damaged datastore
+/// doesn't fill memory with them. Skipped records are still counted past
+/// it.
+const MAX_REPORTED_KEYS: usize = 1000;
+
+/// What scans do when they come across a record they can't decode. Set
+/// with `SledConfig::with_decode_error_policy`.
+///
+/// This covers every iterator over stored records, e.g. vertex and edge
+/// queries, property reads, and exports. Lookups of a single record still
+/// fail if it can't be decoded, and so do storage errors.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DecodeErrorPolicy {
+    /// The scan returns an `Error::Corruption` for the record, which
+    /// usually ends it. This is the default.
+    #[default]
+    FailFast,
+    /// The record is skipped, and counted in
+    /// `SledDatastore::skipped_records`.
+    SkipAndCount,
+    /// The record is skipped, and counted along with its tree and key in
+    /// `SledDatastore::skipped_records`.
+    SkipAndReport,
+}
+
+/// The records skipped by scans since the datastore was opened, under
+/// `DecodeErrorPolicy::SkipAndCount` or `DecodeErrorPolicy::SkipAndReport`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SkippedRecords {
+    /// The number of records skipped. A record is counted each time it's
+    /// skipped, so this may exceed the number of corrupt records.
+    pub count: u64,
+    /// The tree and key of each skipped record, in the order they were
+    /// first skipped, under `DecodeErrorPolicy::SkipAndReport`. At most the
+    /// first 1000 distinct records are kept.
+    pub records: Vec<(String, Vec<u8>)>,
+}
+
+thread_local! {
+    // Set for the duration of a read with `ReadOptions::decode_error_policy`.
+    static POLICY_OVERRIDE: Cell<Option<DecodeErrorPolicy>> = const { Cell::new(None) };
+}
+
+/// Overrides the decode error policy of every datastore for scans run on
+/// the current thread, until it's dropped, when the previous override is
+/// restored.
+pub(crate) struct PolicyOverride {
+    previous: Option<DecodeErrorPolicy>,
+}
+
+impl PolicyOverride {
+    pub(crate) fn new(policy: DecodeErrorPolicy) -> Self {
+        PolicyOverride {
+            previous: POLICY_OVERRIDE.with(|current| current.replace(Some(policy))),
+        }
+    }
+}
+
+impl Drop for PolicyOverride {
+    fn drop(&mut self) {
+        POLICY_OVERRIDE.with(|current| current.set(self.previous));
+    }
+}
+
+/// Applies a datastore's decode error policy to the items of its scans.
+#[derive(Debug, Default)]
+pub(crate) struct DecodeErrors {
+    policy: DecodeErrorPolicy,
+    count: AtomicU64,
+    records: Mutex<Vec<(String, Vec<u8>)>>,
+}
+
+impl DecodeErrors {
+    pub(crate) fn new(policy: DecodeErrorPolicy) -> Self {
+        DecodeErrors {
+            policy,
+            ..DecodeErrors::default()
+        }
+    }
+
+    /// Passes an item of a scan through, unless it's a corrupt record that
+    /// the policy skips, in which case it's recorded and `None` is
+    /// returned. Meant for `Iterator::filter_map`.
+    pub(crate) fn filter<T>(&self, item: Result<T>) -> Option<Result<T>> {
+        let err = match item {
+            Ok(item) => return Some(Ok(item)),
+            Err(err) => err,
+        };
+
+        let policy = POLICY_OVERRIDE.with(|current| current.get()).unwrap_or(self.policy);
+
+        if policy == DecodeErrorPolicy::FailFast {
+            return Some(Err(err));
+        }
+
+        let record = match err {
+            IndraError::Datastore { ref inner } => match inner.downcast_ref::<Error>() {
+                Some(Error::Corruption { tree, key }) => (tree.clone(), key.clone()),
+                _ => return Some(Err(err)),
+            },
+            _ => return Some(Err(err)),
+        };
+
+        self.count.fetch_add(1, Ordering::Relaxed);
+
+        if policy == DecodeErrorPolicy::SkipAndReport {
+            let mut records = self.records.lock().unwrap();
+
+            if records.len() < MAX_REPORTED_KEYS && !records.contains(&record) {
+                records.push(record);
+            }
+        }
+
+        None
+    }
+
+    pub(crate) fn skipped(&self) -> SkippedRecords {
+        SkippedRecords {
+            count: self.count.load(Ordering::Relaxed),
+            records: self.records.lock().unwrap().clone(),
+        }
+    }
+}
+
+/// Builds the error returned when a record of `tree` can't be decoded.
+pub(crate) fn corruption(tree: &Tree, key: &[u8]) -> IndraError {
+    Error::Corruption {
+        tree: String::from_utf8_lossy(&tree.name()).into_owned(),
+        key: key.to_vec(),
+    }
+    .into()
+}
+
+/// Reads the components of a stored key or value, checking the length of
+/// each one first. Unlike the `util::read_*` functions, malformed bytes
+/// yield an `Error::Corruption` naming the record rather than a panic.
+pub(crate) struct Decoder<'a> {
+    tree: &'a Tree,
+    key: &'a [u8],
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Decoder<'a> {
+    /// Decodes a key of `tree`.
+    pub(crate) fn key(tree: &'a Tree, key: &'a [u8]) -> Self {
+        Decoder::value(tree, key, key)
+    }
+
+    /// Decodes the value stored under `key` in `tree`.
+    pub(crate) fn value(tree: &'a Tree, key: &'a [u8], value: &'a [u8]) -> Self {
+        Decoder {
+            tree,
+            key,
+            bytes: value,
+            position: 0,
+        }
+    }
+
+    pub(crate) fn corruption(&self) -> IndraError {
+        corruption(self.tree, self.key)
+    }
+
+    /// Whether all bytes have been read.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.position == self.bytes.len()
+    }
+
+    /// The number of bytes left to read.
+    pub(crate) fn remaining(&self) -> usize {
+        self.bytes.len() - self.position
+    }
+
+    pub(crate) fn read_bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() - self.position < len {
+            return Err(self.corruption());
+        }
+
+        let bytes = &self.bytes[self.position..self.position + len];
+        self.position += len;
+        Ok(bytes)
+    }
+
+    pub(crate) fn skip(&mut self, len: usize) -> Result<()> {
+        self.read_bytes(len).map(|_| ())
+    }
+
+    pub(crate) fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub(crate) fn read_uuid(&mut self) -> Result<Uuid> {
+        let bytes = self.read_bytes(16)?;
+        Uuid::from_slice(bytes).map_err(|_| self.corruption())
+    }
+
+    pub(crate) fn read_type(&mut self) -> Result<Type> {
+        let len = self.read_u8()? as usize;
+        let bytes = self.read_bytes(len)?;
+        let s = str::from_utf8(bytes).map_err(|_| self.corruption())?;
+        Type::new(s).map_err(|_| self.corruption())
+    }
+
+    /// Reads a datetime written by `util::Component::DateTime`.
+    pub(crate) fn read_datetime(&mut self) -> Result<DateTime<Utc>> {
+        DatetimePrecision::Nanos.read(self)
+    }
+
+    /// Reads a string written by `layout::escape`.
+    pub(crate) fn read_escaped_string(&mut self) -> Result<String> {
+        let bytes = self.read_escaped_bytes()?;
+        String::from_utf8(bytes).map_err(|_| self.corruption())
+    }
+
+    /// Reads bytes written by `layout::escape`.
+    pub(crate) fn read_escaped_bytes(&mut self) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+
+        loop {
+            match self.read_u8()? {
+                0 => match self.read_u8()? {
+                    0 => break,
+                    0xff => bytes.push(0),
+                    _ => return Err(self.corruption()),
+                },
+                byte => bytes.push(byte),
+            }
+        }
+
+        Ok(bytes)
+    }
+
+    /// Reads the rest of the bytes as a string, as written by
+    /// `util::Component::FixedLengthString`.
+    pub(crate) fn read_fixed_length_string(&mut self) -> Result<String> {
+        let bytes = self.read_bytes(self.remaining())?;
+        str::from_utf8(bytes)
+            .map(|s| s.to_string())
+            .map_err(|_| self.corruption())
+    }
+}
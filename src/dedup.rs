@@ -0,0 +1,209 @@
+use std::borrow::Cow;
+use std::convert::TryInto;
+
+use super::datastore::SledHolder;
+use super::decode::corruption;
+use super::errors::map_err;
+
+use indradb::{Error as IndradbError, Result};
+use sled::transaction::{ConflictableTransactionError, ConflictableTransactionResult, TransactionalTree};
+use sled::Tree;
+
+/// The first byte of a stored property value that refers to an entry of
+/// the value store, rather than holding the value's JSON. JSON never
+/// starts with it.
+const REFERENCE_TAG: u8 = 0;
+
+/// The length of the reference count that starts each value store entry.
+const COUNT_LEN: usize = 8;
+
+/// The value store, and which property values are moved to it.
+pub(crate) struct ValueStore {
+    /// Deduplicated property values, keyed by their hash and a sequence
+    /// number, with a reference count. See `SledConfig::with_value_dedup`.
+    /// This is in the cold database if cold properties are configured.
+    pub(crate) store: Tree,
+    /// The length of the smallest property values that are deduplicated,
+    /// if any are.
+    pub(crate) dedup_min_len: Option<usize>,
+    /// The patterns of the names of the properties whose values are always
+    /// kept in the value store.
+    pub(crate) cold_properties: Vec<String>,
+}
+
+/// Hashes a value with 64-bit FNV-1a. Hashes are persisted as part of the
+/// value store's keys, so this has to be stable across versions and
+/// platforms, which the standard library's hashers aren't.
+fn hash(bytes: &[u8]) -> [u8; 8] {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+
+    for &byte in bytes {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+
+    hash.to_be_bytes()
+}
+
+/// Whether a stored property value refers to an entry of the value store.
+pub(crate) fn is_reference(stored: &[u8]) -> bool {
+    stored.first() == Some(&REFERENCE_TAG)
+}
+
+fn reference(key: &[u8]) -> Vec<u8> {
+    let mut stored = Vec::with_capacity(key.len() + 1);
+    stored.push(REFERENCE_TAG);
+    stored.extend_from_slice(key);
+    stored
+}
+
+/// Adds `delta` to the reference count of a value store entry. Returns
+/// `None`, removing the entry, once nothing refers to it, and leaves
+/// malformed entries as they are.
+fn adjust_count(entry: &[u8], delta: i64) -> Option<Vec<u8>> {
+    let count = match entry.get(..COUNT_LEN) {
+        Some(count) => u64::from_be_bytes(count.try_into().unwrap()),
+        None => return Some(entry.to_vec()),
+    };
+
+    let count = count as i64 + delta;
+
+    if count <= 0 {
+        return None;
+    }
+
+    let mut entry = entry.to_vec();
+    entry[..COUNT_LEN].copy_from_slice(&(count as u64).to_be_bytes());
+    Some(entry)
+}
+
+/// Gets the bytes to store for a property value, given its name and JSON.
+/// Values that are deduplicated, or are of cold properties, are added to
+/// the value store, or have their
+/// reference count incremented if they're already in it, and a reference
+/// to them is returned; others are returned as they are.
+///
+/// References are taken before the property is written, and released with
+/// `release` once it's been overwritten or removed, so a crash can leak a
+/// value store entry, but never leave a property referring to one that's
+/// gone.
+pub(crate) fn acquire(holder: &SledHolder, name: &str, value_json: &[u8]) -> Result<Vec<u8>> {
+    let dedup = match holder.values.dedup_min_len {
+        Some(min_len) => value_json.len() >= min_len,
+        None => false,
+    };
+
+    if !dedup && !holder.is_property_cold(name) {
+        return Ok(value_json.to_vec());
+    }
+
+    let tree = &holder.values.store;
+    let hash = hash(value_json);
+
+    'scan: loop {
+        // Values with the same hash are told apart by a sequence number.
+        let mut next_sequence: u32 = 0;
+
+        for item in tree.scan_prefix(hash) {
+            let (k, v) = map_err(item)?;
+
+            if k.len() != hash.len() + 4 || v.len() < COUNT_LEN {
+                return Err(corruption(tree, &k));
+            }
+
+            if &v[COUNT_LEN..] == value_json {
+                let updated = holder
+                    .retrier
+                    .run(|| tree.update_and_fetch(&k, |old| old.and_then(|old| adjust_count(old, 1))))?;
+
+                // The entry was removed by a concurrent release since it
+                // was read, so it's added again.
+                if updated.is_none() {
+                    continue 'scan;
+                }
+
+                return Ok(reference(&k));
+            }
+
+            next_sequence = u32::from_be_bytes(k[hash.len()..].try_into().unwrap()) + 1;
+        }
+
+        let mut key = hash.to_vec();
+        key.extend_from_slice(&next_sequence.to_be_bytes());
+        let mut entry = Vec::with_capacity(COUNT_LEN + value_json.len());
+        entry.extend_from_slice(&1u64.to_be_bytes());
+        entry.extend_from_slice(value_json);
+
+        let swapped = holder
+            .retrier
+            .run(|| tree.compare_and_swap(&key, None as Option<&[u8]>, Some(entry.as_slice())))?;
+
+        // Another writer took the sequence number first, possibly for the
+        // same value.
+        if swapped.is_ok() {
+            return Ok(reference(&key));
+        }
+    }
+}
+
+/// Moves the entries of a value store to another one, when it moves
+/// between databases. Entries are removed once they've been copied, so a
+/// move that's interrupted is finished when it's run again.
+pub(crate) fn move_values(from: &Tree, to: &Tree) -> Result<()> {
+    for item in from.iter() {
+        let (k, v) = map_err(item)?;
+        map_err(to.insert(&k, v))?;
+        map_err(from.remove(&k))?;
+    }
+
+    map_err(to.flush())?;
+    Ok(())
+}
+
+/// Releases the reference held by a stored property value that was
+/// overwritten or removed, if it has one, removing the value from the
+/// value store once nothing refers to it.
+pub(crate) fn release(holder: &SledHolder, stored: &[u8]) -> Result<()> {
+    if !is_reference(stored) {
+        return Ok(());
+    }
+
+    let tree = &holder.values.store;
+    holder
+        .retrier
+        .run(|| tree.update_and_fetch(&stored[1..], |old| old.and_then(|old| adjust_count(old, -1))))?;
+    Ok(())
+}
+
+/// Gets the JSON of a stored property value, reading it from the value
+/// store if the property refers to it. `tree` and `key` are those of the
+/// property, to report a reference to a missing entry.
+pub(crate) fn resolve<'a>(value_store: &Tree, tree: &Tree, key: &[u8], stored: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+    if !is_reference(stored) {
+        return Ok(Cow::Borrowed(stored));
+    }
+
+    match map_err(value_store.get(&stored[1..]))? {
+        Some(ref entry) if entry.len() >= COUNT_LEN => Ok(Cow::Owned(entry[COUNT_LEN..].to_vec())),
+        _ => Err(corruption(tree, key)),
+    }
+}
+
+/// Like `resolve`, but reads the value store within a transaction that
+/// includes it. Reading a tree directly from within a sled transaction
+/// deadlocks.
+pub(crate) fn resolve_transactional<'a>(
+    value_store: &TransactionalTree,
+    tree: &Tree,
+    key: &[u8],
+    stored: &'a [u8],
+) -> ConflictableTransactionResult<Cow<'a, [u8]>, IndradbError> {
+    if !is_reference(stored) {
+        return Ok(Cow::Borrowed(stored));
+    }
+
+    match value_store.get(&stored[1..])? {
+        Some(ref entry) if entry.len() >= COUNT_LEN => Ok(Cow::Owned(entry[COUNT_LEN..].to_vec())),
+        _ => Err(ConflictableTransactionError::Abort(corruption(tree, key))),
+    }
+}
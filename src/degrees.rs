@@ -0,0 +1,231 @@
+use std::convert::TryInto;
+
+use super::datastore::SledHolder;
+use super::deadline::Deadline;
+use super::decode::{corruption, Decoder};
+use super::errors::map_err;
+use super::managers::{EdgeRangeManager, VertexManager};
+
+use indradb::{Result, Type, Vertex};
+use sled::transaction::{ConflictableTransactionError, TransactionError, Transactional};
+use sled::Tree;
+use uuid::Uuid;
+
+const DEGREE_INDEX_KEY: &str = "degree_index";
+
+/// The trees of the degree index, enabled with
+/// `SledConfig::with_degree_index`.
+pub(crate) struct DegreeIndex {
+    /// The outbound and inbound degree of each vertex with edges, keyed by
+    /// vertex ID.
+    pub(crate) counters: Tree,
+    /// Vertices with edges, keyed by their degree bucket, highest first,
+    /// followed by their ID.
+    pub(crate) order: Tree,
+}
+
+/// Groups degrees by their bit length, so that a vertex's entry in the
+/// order tree only moves when its degree doubles or halves, rather than on
+/// every edge write. Degree 0 is bucket 0, which isn't indexed.
+fn bucket(degree: u64) -> u8 {
+    (64 - degree.leading_zeros()) as u8
+}
+
+fn order_key(bucket: u8, id: Uuid) -> Vec<u8> {
+    let mut key = Vec::with_capacity(17);
+    key.push(u8::MAX - bucket);
+    key.extend_from_slice(id.as_bytes());
+    key
+}
+
+fn counter_value(outbound: u64, inbound: u64) -> Vec<u8> {
+    let mut value = Vec::with_capacity(16);
+    value.extend_from_slice(&outbound.to_be_bytes());
+    value.extend_from_slice(&inbound.to_be_bytes());
+    value
+}
+
+fn read_counter(tree: &Tree, key: &[u8], value: &[u8]) -> Result<(u64, u64)> {
+    let mut decoder = Decoder::value(tree, key, value);
+    let outbound = u64::from_be_bytes(decoder.read_bytes(8)?.try_into().unwrap());
+    let inbound = u64::from_be_bytes(decoder.read_bytes(8)?.try_into().unwrap());
+
+    if !decoder.is_empty() {
+        return Err(decoder.corruption());
+    }
+
+    Ok((outbound, inbound))
+}
+
+/// Whether the index is maintained as edges change. While indexing is
+/// deferred, it isn't, and it's rebuilt once deferred indexing finishes,
+/// as views are.
+fn is_maintained(holder: &SledHolder) -> bool {
+    holder.degree_index.is_some() && !holder.is_indexing_deferred()
+}
+
+/// Adjusts the degrees of a vertex, moving its entry in the order tree if
+/// its bucket changes. Both trees are updated in one sled transaction, so
+/// concurrent writers can't leave the entry in the wrong bucket.
+fn adjust(index: &DegreeIndex, id: Uuid, outbound_delta: i64, inbound_delta: i64) -> Result<()> {
+    let key = id.as_bytes();
+
+    let result = (&index.counters, &index.order).transaction(|(counters, order)| {
+        let (outbound, inbound) = match counters.get(key)? {
+            Some(value) => read_counter(&index.counters, key, &value)
+                .map_err(|err| ConflictableTransactionError::Abort(err.to_string()))?,
+            None => (0, 0),
+        };
+
+        let new_outbound = (outbound as i64 + outbound_delta).max(0) as u64;
+        let new_inbound = (inbound as i64 + inbound_delta).max(0) as u64;
+        let (old_bucket, new_bucket) = (bucket(outbound + inbound), bucket(new_outbound + new_inbound));
+
+        if new_outbound + new_inbound == 0 {
+            counters.remove(key)?;
+        } else {
+            counters.insert(key, counter_value(new_outbound, new_inbound))?;
+        }
+
+        if old_bucket != new_bucket {
+            if old_bucket > 0 {
+                order.remove(order_key(old_bucket, id))?;
+            }
+
+            if new_bucket > 0 {
+                order.insert(order_key(new_bucket, id), &[])?;
+            }
+        }
+
+        Ok(())
+    });
+
+    match result {
+        Ok(()) => Ok(()),
+        Err(TransactionError::Storage(err)) => map_err(Err(err)),
+        Err(TransactionError::Abort(_)) => Err(corruption(&index.counters, key)),
+    }
+}
+
+/// Updates the index after an edge was created (`delta` of 1) or deleted
+/// (`delta` of -1).
+pub(crate) fn on_edge_change(
+    holder: &SledHolder,
+    outbound_id: Uuid,
+    t: &Type,
+    inbound_id: Uuid,
+    delta: i64,
+) -> Result<()> {
+    if !is_maintained(holder) {
+        return Ok(());
+    }
+
+    let index = holder.degree_index.as_ref().unwrap();
+
+    // An undirected edge has range entries in both directions from each of
+    // its vertices, which is what `rebuild` counts.
+    if outbound_id != inbound_id && holder.edge_range_layout.is_undirected(t) {
+        adjust(index, outbound_id, delta, delta)?;
+        return adjust(index, inbound_id, delta, delta);
+    }
+
+    adjust(index, outbound_id, delta, 0)?;
+    adjust(index, inbound_id, 0, delta)
+}
+
+/// Rebuilds the index from the edge ranges.
+pub(crate) fn rebuild(holder: &SledHolder) -> Result<()> {
+    let index = match holder.degree_index {
+        Some(ref index) => index,
+        None => return Ok(()),
+    };
+
+    map_err(index.counters.clear())?;
+    map_err(index.order.clear())?;
+
+    let edge_range_manager = EdgeRangeManager::new(holder);
+    let reversed_edge_range_manager = EdgeRangeManager::new_reversed(holder);
+    let deadline = Deadline::new(None);
+
+    for item in holder.vertices.iter().keys() {
+        let k = map_err(item)?;
+        let id = Decoder::key(&holder.vertices, &k).read_uuid()?;
+        let outbound = edge_range_manager.count_for_owner(id, &deadline)?;
+        let inbound = reversed_edge_range_manager.count_for_owner(id, &deadline)?;
+
+        if outbound + inbound > 0 {
+            map_err(index.counters.insert(id.as_bytes(), counter_value(outbound, inbound)))?;
+            map_err(index.order.insert(order_key(bucket(outbound + inbound), id), &[]))?;
+        }
+    }
+
+    map_err(holder.metadata.insert(holder.metadata_key(DEGREE_INDEX_KEY), &[]))?;
+    Ok(())
+}
+
+/// Builds the index if it was enabled since the datastore was last
+/// opened. If it's disabled, it's marked as stale instead, since it won't
+/// be maintained.
+pub(crate) fn rebuild_stale(holder: &SledHolder) -> Result<()> {
+    let key = holder.metadata_key(DEGREE_INDEX_KEY);
+
+    if holder.degree_index.is_none() {
+        map_err(holder.metadata.remove(key))?;
+        return Ok(());
+    }
+
+    if is_maintained(holder) && !map_err(holder.metadata.contains_key(key))? {
+        rebuild(holder)?;
+    }
+
+    Ok(())
+}
+
+/// Gets up to `n` vertices with the highest degrees, along with their
+/// degrees, highest first. Ties are broken by vertex ID.
+pub(crate) fn top(holder: &SledHolder, index: &DegreeIndex, n: usize, t: Option<&Type>) -> Result<Vec<(Vertex, u64)>> {
+    let vertex_manager = VertexManager::new(holder);
+    let mut top = Vec::new();
+    let mut current_bucket = None;
+
+    // Vertices are read a bucket at a time, until a bucket has been read
+    // after `n` matching vertices were found, since exact degrees only
+    // order vertices within a bucket.
+    for item in index.order.iter().keys() {
+        let k = map_err(item)?;
+        let mut decoder = Decoder::key(&index.order, &k);
+        let bucket = decoder.read_u8()?;
+        let id = decoder.read_uuid()?;
+
+        if current_bucket != Some(bucket) {
+            if top.len() >= n {
+                break;
+            }
+
+            current_bucket = Some(bucket);
+        }
+
+        let vertex_t = match vertex_manager.get(id)? {
+            Some(vertex_t) => vertex_t,
+            None => continue,
+        };
+
+        if t.is_some_and(|t| *t != vertex_t) {
+            continue;
+        }
+
+        let degree = match map_err(index.counters.get(id.as_bytes()))? {
+            Some(value) => {
+                let (outbound, inbound) = read_counter(&index.counters, id.as_bytes(), &value)?;
+                outbound + inbound
+            }
+            None => continue,
+        };
+
+        top.push((Vertex::with_id(id, vertex_t), degree));
+    }
+
+    top.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.id.cmp(&b.0.id)));
+    top.truncate(n);
+    Ok(top)
+}
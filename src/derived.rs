@@ -0,0 +1,27 @@
+use std::fmt;
+use std::sync::Arc;
+
+use indradb::{NamedProperty, Type};
+use serde_json::Value as JsonValue;
+
+/// Computes a derived property from the other properties of a vertex.
+/// Returning `None` deletes the derived property.
+pub(crate) type DeriveFn = dyn Fn(&[NamedProperty]) -> Option<JsonValue> + Send + Sync;
+
+/// A property of vertices of a type that is computed from their other
+/// properties, as registered via `SledConfig::with_derived_property`.
+#[derive(Clone)]
+pub(crate) struct DerivedProperty {
+    pub(crate) t: Type,
+    pub(crate) name: String,
+    pub(crate) derive: Arc<DeriveFn>,
+}
+
+impl fmt::Debug for DerivedProperty {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DerivedProperty")
+            .field("t", &self.t)
+            .field("name", &self.name)
+            .finish()
+    }
+}
@@ -0,0 +1,287 @@
+use std::cmp::Ordering;
+use std::iter::Peekable;
+
+use super::datastore::SledDatastore;
+use super::decode::Decoder;
+use super::dedup;
+use super::errors::map_err;
+
+use indradb::{Edge, EdgeKey, Result, Vertex};
+use serde_json::Value as JsonValue;
+use sled::{IVec, Iter as DbIterator, Tree};
+use uuid::Uuid;
+
+/// A graph-level difference between two datastores.
+#[derive(Clone, Debug, PartialEq)]
+pub enum GraphChange {
+    VertexCreated(Vertex),
+    VertexDeleted(Vertex),
+    /// The vertex was recreated with a different type.
+    VertexModified {
+        old: Vertex,
+        new: Vertex,
+    },
+    EdgeCreated(Edge),
+    EdgeDeleted(Edge),
+    /// The edge's update datetime changed.
+    EdgeModified {
+        old: Edge,
+        new: Edge,
+    },
+    VertexPropertyCreated {
+        id: Uuid,
+        name: String,
+        value: JsonValue,
+    },
+    VertexPropertyDeleted {
+        id: Uuid,
+        name: String,
+        value: JsonValue,
+    },
+    VertexPropertyModified {
+        id: Uuid,
+        name: String,
+        old: JsonValue,
+        new: JsonValue,
+    },
+    EdgePropertyCreated {
+        key: EdgeKey,
+        name: String,
+        value: JsonValue,
+    },
+    EdgePropertyDeleted {
+        key: EdgeKey,
+        name: String,
+        value: JsonValue,
+    },
+    EdgePropertyModified {
+        key: EdgeKey,
+        name: String,
+        old: JsonValue,
+        new: JsonValue,
+    },
+}
+
+/// A key whose value differs between two trees, along with the old and new
+/// values. A missing value means the key is absent from that tree.
+type TreeChange = (IVec, Option<IVec>, Option<IVec>);
+
+/// Walks two trees in lockstep, yielding the keys whose values differ.
+/// Since both trees are sorted, this never holds more than one entry of
+/// each in memory.
+struct TreeDiff {
+    old: Peekable<DbIterator>,
+    new: Peekable<DbIterator>,
+}
+
+impl TreeDiff {
+    fn new(old: &Tree, new: &Tree) -> Self {
+        TreeDiff {
+            old: old.iter().peekable(),
+            new: new.iter().peekable(),
+        }
+    }
+}
+
+impl Iterator for TreeDiff {
+    type Item = Result<TreeChange>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let ordering = match (self.old.peek(), self.new.peek()) {
+                (None, None) => return None,
+                (Some(Err(_)), _) | (Some(_), None) => Ordering::Less,
+                (_, Some(Err(_))) | (None, Some(_)) => Ordering::Greater,
+                (Some(Ok((old_k, _))), Some(Ok((new_k, _)))) => old_k.cmp(new_k),
+            };
+
+            match ordering {
+                Ordering::Less => {
+                    return self
+                        .old
+                        .next()
+                        .map(|item| map_err(item).map(|(k, v)| (k, Some(v), None)))
+                }
+                Ordering::Greater => {
+                    return self
+                        .new
+                        .next()
+                        .map(|item| map_err(item).map(|(k, v)| (k, None, Some(v))))
+                }
+                Ordering::Equal => {
+                    let (k, old_v) = self.old.next().unwrap().unwrap();
+                    let (_, new_v) = self.new.next().unwrap().unwrap();
+
+                    if old_v != new_v {
+                        return Some(Ok((k, Some(old_v), Some(new_v))));
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn read_vertex(tree: &Tree, k: &[u8], v: &[u8]) -> Result<Vertex> {
+    let id = Decoder::key(tree, k).read_uuid()?;
+    let t = Decoder::value(tree, k, v).read_type()?;
+    Ok(Vertex::with_id(id, t))
+}
+
+fn read_edge(tree: &Tree, k: &[u8], v: &[u8]) -> Result<Edge> {
+    let mut decoder = Decoder::key(tree, k);
+    let outbound_id = decoder.read_uuid()?;
+    let t = decoder.read_type()?;
+    let inbound_id = decoder.read_uuid()?;
+    let update_datetime = Decoder::value(tree, k, v).read_datetime()?;
+    Ok(Edge::new(EdgeKey::new(outbound_id, t, inbound_id), update_datetime))
+}
+
+fn vertex_change(old_tree: &Tree, new_tree: &Tree, change: TreeChange) -> Result<Option<GraphChange>> {
+    Ok(match change {
+        (k, Some(old_v), Some(new_v)) => {
+            let old = read_vertex(old_tree, &k, &old_v)?;
+            let new = read_vertex(new_tree, &k, &new_v)?;
+
+            // The values also differ when a vertex is recreated with the
+            // same type, which isn't a graph-level change.
+            if old.t == new.t {
+                None
+            } else {
+                Some(GraphChange::VertexModified { old, new })
+            }
+        }
+        (k, Some(old_v), None) => Some(GraphChange::VertexDeleted(read_vertex(old_tree, &k, &old_v)?)),
+        (k, None, Some(new_v)) => Some(GraphChange::VertexCreated(read_vertex(new_tree, &k, &new_v)?)),
+        (_, None, None) => None,
+    })
+}
+
+fn edge_change(old_tree: &Tree, new_tree: &Tree, change: TreeChange) -> Result<Option<GraphChange>> {
+    Ok(match change {
+        (k, Some(old_v), Some(new_v)) => Some(GraphChange::EdgeModified {
+            old: read_edge(old_tree, &k, &old_v)?,
+            new: read_edge(new_tree, &k, &new_v)?,
+        }),
+        (k, Some(old_v), None) => Some(GraphChange::EdgeDeleted(read_edge(old_tree, &k, &old_v)?)),
+        (k, None, Some(new_v)) => Some(GraphChange::EdgeCreated(read_edge(new_tree, &k, &new_v)?)),
+        (_, None, None) => None,
+    })
+}
+
+/// Reads a property value, given the value store of the datastore it's
+/// from. Values are compared once read, since the same value may be stored
+/// differently in each datastore.
+fn read_value(value_store: &Tree, tree: &Tree, k: &[u8], v: Option<IVec>) -> Result<Option<JsonValue>> {
+    match v {
+        Some(v) => Ok(Some(serde_json::from_slice(&dedup::resolve(
+            value_store,
+            tree,
+            k,
+            &v,
+        )?)?)),
+        None => Ok(None),
+    }
+}
+
+fn read_values(
+    value_stores: (&Tree, &Tree),
+    old_tree: &Tree,
+    new_tree: &Tree,
+    change: TreeChange,
+) -> Result<(IVec, Option<JsonValue>, Option<JsonValue>)> {
+    let (k, old_v, new_v) = change;
+    let old = read_value(value_stores.0, old_tree, &k, old_v)?;
+    let new = read_value(value_stores.1, new_tree, &k, new_v)?;
+    Ok((k, old, new))
+}
+
+fn vertex_property_change(
+    value_stores: (&Tree, &Tree),
+    old_tree: &Tree,
+    new_tree: &Tree,
+    change: TreeChange,
+) -> Result<Option<GraphChange>> {
+    let (k, old, new) = read_values(value_stores, old_tree, new_tree, change)?;
+    let mut decoder = Decoder::key(new_tree, &k);
+    let id = decoder.read_uuid()?;
+    let name = decoder.read_fixed_length_string()?;
+
+    Ok(match (old, new) {
+        (Some(old), Some(new)) if old != new => Some(GraphChange::VertexPropertyModified { id, name, old, new }),
+        (Some(value), None) => Some(GraphChange::VertexPropertyDeleted { id, name, value }),
+        (None, Some(value)) => Some(GraphChange::VertexPropertyCreated { id, name, value }),
+        _ => None,
+    })
+}
+
+fn edge_property_change(
+    value_stores: (&Tree, &Tree),
+    old_tree: &Tree,
+    new_tree: &Tree,
+    change: TreeChange,
+) -> Result<Option<GraphChange>> {
+    let (k, old, new) = read_values(value_stores, old_tree, new_tree, change)?;
+    let mut decoder = Decoder::key(new_tree, &k);
+    let outbound_id = decoder.read_uuid()?;
+    let t = decoder.read_type()?;
+    let inbound_id = decoder.read_uuid()?;
+    let name = decoder.read_fixed_length_string()?;
+    let key = EdgeKey::new(outbound_id, t, inbound_id);
+
+    Ok(match (old, new) {
+        (Some(old), Some(new)) if old != new => Some(GraphChange::EdgePropertyModified { key, name, old, new }),
+        (Some(value), None) => Some(GraphChange::EdgePropertyDeleted { key, name, value }),
+        (None, Some(value)) => Some(GraphChange::EdgePropertyCreated { key, name, value }),
+        _ => None,
+    })
+}
+
+fn changes<F>(old: &Tree, new: &Tree, f: F) -> impl Iterator<Item = Result<GraphChange>>
+where
+    F: Fn(&Tree, &Tree, TreeChange) -> Result<Option<GraphChange>>,
+{
+    let (old_tree, new_tree) = (old.clone(), new.clone());
+
+    TreeDiff::new(old, new).filter_map(
+        move |item| match item.and_then(|change| f(&old_tree, &new_tree, change)) {
+            Ok(Some(change)) => Some(Ok(change)),
+            Ok(None) => None,
+            Err(err) => Some(Err(err)),
+        },
+    )
+}
+
+/// Compares two datastores - e.g. checkpoints or backups of the same graph
+/// taken at different times - and streams the graph-level changes needed to
+/// get from `a` to `b`.
+///
+/// Changes are yielded for vertices first, then edges, vertex properties
+/// and edge properties, each in key order. Both datastores are walked in
+/// lockstep, so memory use doesn't depend on their size. Neither should be
+/// written to while the diff is being read.
+///
+/// # Arguments
+/// * `a`: The older datastore.
+/// * `b`: The newer datastore.
+pub fn diff_checkpoints(a: &SledDatastore, b: &SledDatastore) -> impl Iterator<Item = Result<GraphChange>> {
+    let (a, b) = (&a.holder, &b.holder);
+    let (a_value_store, b_value_store) = (a.values.store.clone(), b.values.store.clone());
+    let (a_edge_value_store, b_edge_value_store) = (a.values.store.clone(), b.values.store.clone());
+
+    changes(&a.vertices, &b.vertices, vertex_change)
+        .chain(changes(&a.edges, &b.edges, edge_change))
+        .chain(changes(
+            &a.vertex_properties,
+            &b.vertex_properties,
+            move |old_tree, new_tree, change| {
+                vertex_property_change((&a_value_store, &b_value_store), old_tree, new_tree, change)
+            },
+        ))
+        .chain(changes(
+            &a.edge_properties,
+            &b.edge_properties,
+            move |old_tree, new_tree, change| {
+                edge_property_change((&a_edge_value_store, &b_edge_value_store), old_tree, new_tree, change)
+            },
+        ))
+}
@@ -1,6 +1,382 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use super::preflight::PreflightCheck;
+
+use chrono::offset::Utc;
+use chrono::DateTime;
 use indradb::Error as IndraError;
 use sled::Error as SledError;
+use uuid::Uuid;
+
+/// Errors specific to the sled datastore.
+///
+/// These are surfaced to callers wrapped in `indradb::Error::Datastore`.
+#[derive(Debug)]
+pub enum Error {
+    /// A history-based read was attempted on a datastore that was not
+    /// configured to record history.
+    HistoryDisabled,
+
+    /// The audit log was read on a datastore that was not configured to
+    /// record one.
+    AuditLogDisabled,
+
+    /// Vertices were listed by degree on a datastore that was not
+    /// configured to maintain a degree index.
+    DegreeIndexDisabled,
+
+    /// Vertices were searched on a datastore that was not configured to
+    /// maintain a full-text index.
+    FullTextIndexDisabled,
+
+    /// Edge activity was read on a datastore that was not configured to
+    /// count it.
+    EdgeActivityDisabled,
+
+    /// The datastore was written in a newer on-disk format than this
+    /// version of the crate supports.
+    UnsupportedFormat { found: u64, supported: u64 },
+
+    /// The datastore was opened with a config that conflicts with how it
+    /// was created.
+    IncompatibleConfig { reason: String },
+
+    /// A datastore was opened at `path`, which already holds one, with a
+    /// config set with `SledConfig::with_create_new`.
+    DatastoreExists { path: PathBuf },
+
+    /// A datastore was opened at `path`, which doesn't hold one, with a
+    /// config set with `SledConfig::with_must_exist`.
+    DatastoreNotFound { path: PathBuf },
+
+    /// An entry in the metadata tree could not be decoded.
+    CorruptMetadata { key: String },
+
+    /// A record could not be decoded. `key` is the raw key of the record
+    /// in `tree`.
+    Corruption { tree: String, key: Vec<u8> },
+
+    /// A transaction operation ran past the timeout set via
+    /// `SledTransaction::with_timeout`. `processed` is the number of items
+    /// it had scanned or deleted by then; mutations made before the timeout
+    /// are not rolled back.
+    Timeout { elapsed: Duration, processed: u64 },
+
+    /// A transaction operation was cancelled through the `OpContext` set
+    /// with `SledTransaction::with_context`, after processing `processed`
+    /// items. As with timeouts, mutations made before then are kept.
+    Cancelled { processed: u64 },
+
+    /// `SledDatastore::execute` gave up after its closure's writes
+    /// conflicted with other writes `attempts` times in a row.
+    Conflict { attempts: u32 },
+
+    /// A materialized view was read that wasn't registered with
+    /// `SledConfig::with_materialized_view`.
+    UnknownView { name: String },
+
+    /// Vertices were looked up by similarity to a vector property that
+    /// wasn't registered with `SledConfig::with_vector_index`.
+    UnknownVectorIndex { name: String },
+
+    /// Vertices were looked up by a composite index that wasn't registered
+    /// with `SledConfig::with_composite_index`.
+    UnknownCompositeIndex { name: String },
+
+    /// A composite index was looked up by more values than it has
+    /// properties.
+    TooManyCompositeValues { name: String, properties: usize },
+
+    /// A vector property was set, or queried, with a value that isn't an
+    /// array of `dimensions` finite numbers.
+    InvalidVector { name: String, dimensions: usize },
+
+    /// A shortest path was looked up over an edge whose weight property
+    /// `name` isn't a non-negative number.
+    InvalidEdgeWeight {
+        outbound_id: Uuid,
+        t: String,
+        inbound_id: Uuid,
+        name: String,
+    },
+
+    /// An edge was created that would give its outbound vertex more edges
+    /// of type `t` than `EdgeConstraints::max_out_degree` allows.
+    MaxOutDegreeExceeded { t: String, outbound_id: Uuid, max: u64 },
+
+    /// An edge was created that would give its outbound vertex, of type
+    /// `outbound_t`, more edges of type `t` than the limit set with
+    /// `SledConfig::with_edge_cardinality`.
+    CardinalityExceeded {
+        outbound_t: String,
+        t: String,
+        outbound_id: Uuid,
+        max: u64,
+    },
+
+    /// An edge was given an update datetime older than its current one, on
+    /// a datastore configured with
+    /// `SledConfig::with_monotonic_edge_datetimes`.
+    EdgeDatetimeNotMonotonic {
+        outbound_id: Uuid,
+        t: String,
+        inbound_id: Uuid,
+        current: DateTime<Utc>,
+        given: DateTime<Utc>,
+    },
+
+    /// An edge was given an update datetime that can't be stored in an
+    /// edge range key, which only covers the Unix epoch through
+    /// `indradb::util::MAX_DATETIME`.
+    DatetimeOutOfRange { datetime: DateTime<Utc> },
+
+    /// An edge of type `t` was created from a vertex to itself, which the
+    /// type's `EdgeConstraints` forbid.
+    SelfLoopForbidden { t: String, id: Uuid },
+
+    /// An edge of type `t` was created between vertices whose types aren't
+    /// among those allowed by the type's `EdgeConstraints`.
+    EndpointTypesNotAllowed {
+        t: String,
+        outbound_t: String,
+        inbound_t: String,
+    },
+
+    /// A mutation was vetoed by a `WriteValidator`. Mutations made earlier
+    /// in the same call are not rolled back.
+    WriteRejected { reason: String },
+
+    /// An operation was denied by the datastore's `AccessPolicy`.
+    AccessDenied {
+        tenant: Option<u32>,
+        operation: String,
+        reason: String,
+    },
+
+    /// A property value was set that's larger than
+    /// `PropertyLimits::max_value_bytes` allows.
+    PropertyValueTooLarge { name: String, size: usize, max: usize },
+
+    /// A property was set that would give its vertex or edge more
+    /// properties than `PropertyLimits::max_properties` allows.
+    TooManyProperties { name: String, max: u64 },
+
+    /// A vertex with edges was deleted under `CascadePolicy::Restrict`.
+    VertexHasEdges { id: Uuid },
+
+    /// A merge patch of vertex properties wasn't a JSON object.
+    InvalidMergePatch,
+
+    /// Vertices or edges were looked up by the value of a property that
+    /// isn't indexed with `SledDatastore::index_property` or
+    /// `SledDatastore::index_edge_property`.
+    PropertyNotIndexed { name: String },
+
+    /// A property declared unique with `SledDatastore::index_unique_property`
+    /// was set to a value that another vertex, `vertex_id`, already has.
+    /// This is also returned when declaring it, if two vertices share a
+    /// value.
+    UniqueValueTaken { name: String, vertex_id: Uuid },
+
+    /// Vertices or edges were looked up by the value of a property whose
+    /// index is still being built, e.g. by `SledDatastore::index_property`
+    /// or `SledDatastore::rebuild_index`, or will be once a bulk load is
+    /// finished.
+    PropertyIndexBuilding { name: String },
+
+    /// A partition was requested for a tenant whose partition was dropped,
+    /// and is still being reclaimed in the background.
+    PartitionReclaiming { tenant: u32 },
+
+    /// A check made by `SledConfig::preflight` didn't pass. `reason`
+    /// describes the problem and how to fix it.
+    PreflightFailed { check: PreflightCheck, reason: String },
+
+    /// A `SharedDatastore` call that only the writer can make was made on
+    /// a reader.
+    NotWriter,
+
+    /// A `SharedDatastore` reader was opened before the writer published
+    /// any snapshots.
+    NoPublishedSnapshot,
+
+    /// A write that can't be staged was made on a transaction with a write
+    /// buffer, set with `SledTransaction::with_write_buffer`.
+    UnbufferedWrite { operation: String },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::HistoryDisabled => write!(f, "history is not enabled for this datastore"),
+            Error::AuditLogDisabled => write!(f, "the audit log is not enabled for this datastore"),
+            Error::DegreeIndexDisabled => write!(f, "the degree index is not enabled for this datastore"),
+            Error::FullTextIndexDisabled => write!(f, "the full-text index is not enabled for this datastore"),
+            Error::EdgeActivityDisabled => write!(f, "edge activity is not enabled for this datastore"),
+            Error::UnsupportedFormat { found, supported } => write!(
+                f,
+                "datastore has format version {}, but at most version {} is supported",
+                found, supported
+            ),
+            Error::IncompatibleConfig { ref reason } => write!(f, "incompatible config: {}", reason),
+            Error::DatastoreExists { ref path } => write!(f, "a datastore already exists at `{}`", path.display()),
+            Error::DatastoreNotFound { ref path } => write!(f, "no datastore exists at `{}`", path.display()),
+            Error::CorruptMetadata { ref key } => write!(f, "corrupt metadata entry `{}`", key),
+            Error::Corruption { ref tree, ref key } => {
+                write!(f, "corrupt record in tree `{}` at key ", tree)?;
+                for byte in key {
+                    write!(f, "{:02x}", byte)?;
+                }
+                Ok(())
+            }
+            Error::Timeout { elapsed, processed } => write!(
+                f,
+                "operation timed out after {:?}, having processed {} items",
+                elapsed, processed
+            ),
+            Error::Cancelled { processed } => {
+                write!(f, "operation cancelled, having processed {} items", processed)
+            }
+            Error::Conflict { attempts } => write!(
+                f,
+                "transaction conflicted with other writes {} times in a row",
+                attempts
+            ),
+            Error::UnknownView { ref name } => write!(f, "no materialized view named `{}`", name),
+            Error::UnknownVectorIndex { ref name } => write!(f, "no vector index named `{}`", name),
+            Error::UnknownCompositeIndex { ref name } => write!(f, "no composite index named `{}`", name),
+            Error::TooManyCompositeValues { ref name, properties } => write!(
+                f,
+                "composite index `{}` can be looked up by at most {} values",
+                name, properties
+            ),
+            Error::InvalidVector { ref name, dimensions } => write!(
+                f,
+                "vector property `{}` must be an array of {} finite numbers",
+                name, dimensions
+            ),
+            Error::InvalidEdgeWeight {
+                outbound_id,
+                ref t,
+                inbound_id,
+                ref name,
+            } => write!(
+                f,
+                "edge ({}, {}, {}) has a weight `{}` that isn't a non-negative number",
+                outbound_id, t, inbound_id, name
+            ),
+            Error::MaxOutDegreeExceeded {
+                ref t,
+                outbound_id,
+                max,
+            } => write!(
+                f,
+                "vertex {} already has the maximum of {} outbound `{}` edges",
+                outbound_id, max, t
+            ),
+            Error::CardinalityExceeded {
+                ref outbound_t,
+                ref t,
+                outbound_id,
+                max,
+            } => write!(
+                f,
+                "`{}` vertex {} already has the maximum of {} outbound `{}` edges",
+                outbound_t, outbound_id, max, t
+            ),
+            Error::EdgeDatetimeNotMonotonic {
+                outbound_id,
+                ref t,
+                inbound_id,
+                current,
+                given,
+            } => write!(
+                f,
+                "edge ({}, {}, {}) was updated at {}, which is before its current update datetime {}",
+                outbound_id, t, inbound_id, given, current
+            ),
+            Error::DatetimeOutOfRange { datetime } => {
+                write!(f, "datetime {} is outside the range edge keys can store", datetime)
+            }
+            Error::SelfLoopForbidden { ref t, id } => {
+                write!(f, "`{}` edges can't be self-loops, as on vertex {}", t, id)
+            }
+            Error::EndpointTypesNotAllowed {
+                ref t,
+                ref outbound_t,
+                ref inbound_t,
+            } => write!(
+                f,
+                "`{}` edges aren't allowed from `{}` vertices to `{}` vertices",
+                t, outbound_t, inbound_t
+            ),
+            Error::WriteRejected { ref reason } => write!(f, "write rejected: {}", reason),
+            Error::AccessDenied {
+                tenant: Some(tenant),
+                ref operation,
+                ref reason,
+            } => write!(f, "`{}` denied for tenant {}: {}", operation, tenant, reason),
+            Error::AccessDenied {
+                tenant: None,
+                ref operation,
+                ref reason,
+            } => write!(f, "`{}` denied: {}", operation, reason),
+            Error::PropertyValueTooLarge { ref name, size, max } => write!(
+                f,
+                "value of property `{}` is {} bytes, but at most {} bytes are allowed",
+                name, size, max
+            ),
+            Error::TooManyProperties { ref name, max } => write!(
+                f,
+                "can't set property `{}`, since the maximum of {} properties has been reached",
+                name, max
+            ),
+            Error::VertexHasEdges { id } => write!(f, "vertex {} can't be deleted while it has edges", id),
+            Error::InvalidMergePatch => write!(f, "a merge patch of vertex properties must be a JSON object"),
+            Error::PropertyNotIndexed { ref name } => write!(f, "property `{}` is not indexed", name),
+            Error::UniqueValueTaken { ref name, vertex_id } => write!(
+                f,
+                "vertex {} already has this value of unique property `{}`",
+                vertex_id, name
+            ),
+            Error::PropertyIndexBuilding { ref name } => write!(f, "property `{}` is still being indexed", name),
+            Error::PartitionReclaiming { tenant } => write!(
+                f,
+                "the partition of tenant {} was dropped, and is still being reclaimed",
+                tenant
+            ),
+            Error::PreflightFailed { check, ref reason } => {
+                write!(f, "preflight check {:?} failed: {}", check, reason)
+            }
+            Error::NotWriter => write!(f, "only the writer of a shared datastore can do this"),
+            Error::NoPublishedSnapshot => write!(f, "the shared datastore has no published snapshots"),
+            Error::UnbufferedWrite { ref operation } => {
+                write!(
+                    f,
+                    "`{}` can't be called on a transaction with a write buffer",
+                    operation
+                )
+            }
+        }
+    }
+}
+
+impl StdError for Error {}
+
+impl From<Error> for IndraError {
+    fn from(err: Error) -> IndraError {
+        IndraError::Datastore { inner: Box::new(err) }
+    }
+}
 
 pub(crate) fn map_err<T>(result: Result<T, SledError>) -> Result<T, IndraError> {
     result.map_err(|err| IndraError::Datastore { inner: Box::new(err) })
 }
+
+pub(crate) fn map_io_err<T>(result: io::Result<T>) -> Result<T, IndraError> {
+    result.map_err(|err| IndraError::Datastore { inner: Box::new(err) })
+}
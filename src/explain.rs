@@ -0,0 +1,333 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::fmt;
+
+use super::datastore::SledHolder;
+use super::managers::{CatalogKind, CatalogManager};
+
+use indradb::{util, EdgeDirection, EdgePropertyQuery, EdgeQuery, Result, Type, VertexPropertyQuery, VertexQuery};
+use sled::Tree;
+
+/// A query that can be explained with `SledTransaction::explain`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PlannedQuery {
+    Vertex(VertexQuery),
+    Edge(EdgeQuery),
+    VertexProperty(VertexPropertyQuery),
+    EdgeProperty(EdgePropertyQuery),
+}
+
+impl From<VertexQuery> for PlannedQuery {
+    fn from(q: VertexQuery) -> Self {
+        PlannedQuery::Vertex(q)
+    }
+}
+
+impl From<EdgeQuery> for PlannedQuery {
+    fn from(q: EdgeQuery) -> Self {
+        PlannedQuery::Edge(q)
+    }
+}
+
+impl From<VertexPropertyQuery> for PlannedQuery {
+    fn from(q: VertexPropertyQuery) -> Self {
+        PlannedQuery::VertexProperty(q)
+    }
+}
+
+impl From<EdgePropertyQuery> for PlannedQuery {
+    fn from(q: EdgePropertyQuery) -> Self {
+        PlannedQuery::EdgeProperty(q)
+    }
+}
+
+/// How a step of a query plan reads its tree.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Access {
+    /// Gets one key per input item, or per ID or key of a specific query.
+    PointLookups,
+
+    /// Scans the tree in key order from `start`, until the query's limit is
+    /// reached or the tree runs out.
+    Scan { start: Vec<u8> },
+
+    /// Scans one key range per input vertex: the keys prefixed by its ID,
+    /// and by `t` if it's set. If `seeks_time_range` is set, keys outside
+    /// the query's time range are skipped by seeking past them, rather
+    /// than read and discarded.
+    PrefixScans { t: Option<Type>, seeks_time_range: bool },
+}
+
+/// A step of a query plan, which reads one tree.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlanStep {
+    /// The name of the tree, including any partition prefix.
+    pub tree: String,
+    pub access: Access,
+    /// Whether the tree is a derived index, rather than primary data.
+    pub uses_index: bool,
+    /// Whether some of the items read are discarded after decoding, e.g.
+    /// because they're of the wrong type, rather than being excluded by
+    /// the key range.
+    pub filtered: bool,
+    /// The estimated number of keys read.
+    pub estimated_reads: u64,
+    /// The estimated number of items passed on to the next step.
+    pub estimated_items: u64,
+}
+
+/// Which trees and key ranges a query touches, in the order they're read.
+/// See `SledTransaction::explain`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueryPlan {
+    pub steps: Vec<PlanStep>,
+    /// The estimated number of results.
+    pub estimated_items: u64,
+}
+
+impl QueryPlan {
+    /// The estimated number of keys read across all steps.
+    pub fn estimated_reads(&self) -> u64 {
+        self.steps.iter().map(|step| step.estimated_reads).sum()
+    }
+}
+
+impl fmt::Display for QueryPlan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, step) in self.steps.iter().enumerate() {
+            write!(f, "{}. ", i + 1)?;
+
+            match step.access {
+                Access::PointLookups => write!(f, "point lookups in `{}`", step.tree)?,
+                Access::Scan { ref start } => {
+                    write!(f, "scan `{}` from ", step.tree)?;
+                    for byte in start {
+                        write!(f, "{:02x}", byte)?;
+                    }
+                }
+                Access::PrefixScans {
+                    ref t,
+                    seeks_time_range,
+                } => {
+                    write!(f, "prefix scans of `{}` by vertex", step.tree)?;
+                    if let Some(ref t) = *t {
+                        write!(f, " and type `{}`", t.0)?;
+                    }
+                    if seeks_time_range {
+                        write!(f, ", seeking to the time range")?;
+                    }
+                }
+            }
+
+            if step.uses_index {
+                write!(f, " (index)")?;
+            }
+
+            if step.filtered {
+                write!(f, ", filtered")?;
+            }
+
+            writeln!(f, ": ~{} reads, ~{} items", step.estimated_reads, step.estimated_items)?;
+        }
+
+        write!(f, "~{} results", self.estimated_items)
+    }
+}
+
+fn tree_name(tree: &Tree) -> String {
+    String::from_utf8_lossy(&tree.name()).into_owned()
+}
+
+/// Scales `count` by `part / whole`, rounding up so that anything that
+/// might match is estimated as at least one item.
+fn scale(count: u64, part: u64, whole: u64) -> u64 {
+    if whole == 0 {
+        0
+    } else {
+        (u128::from(count) * u128::from(part)).div_ceil(u128::from(whole)) as u64
+    }
+}
+
+/// Builds query plans, estimating item counts from the catalog.
+struct Planner<'a> {
+    holder: &'a SledHolder,
+    catalog: HashMap<(CatalogKind, String), u64>,
+    vertex_count: u64,
+    edge_count: u64,
+    steps: Vec<PlanStep>,
+}
+
+impl<'a> Planner<'a> {
+    fn new(holder: &'a SledHolder) -> Result<Self> {
+        let catalog_manager = CatalogManager::new(holder);
+        let mut catalog = HashMap::new();
+
+        for kind in &[
+            CatalogKind::VertexType,
+            CatalogKind::EdgeType,
+            CatalogKind::VertexProperty,
+            CatalogKind::EdgeProperty,
+        ] {
+            for item in catalog_manager.iterate_for_kind(*kind) {
+                let (name, count) = item?;
+                catalog.insert((*kind, name), count);
+            }
+        }
+
+        let total = |kind| -> u64 {
+            catalog
+                .iter()
+                .filter(|&(&(k, _), _)| k == kind)
+                .map(|(_, count)| count)
+                .sum()
+        };
+
+        Ok(Planner {
+            holder,
+            vertex_count: total(CatalogKind::VertexType),
+            edge_count: total(CatalogKind::EdgeType),
+            catalog,
+            steps: Vec::new(),
+        })
+    }
+
+    fn count(&self, kind: CatalogKind, name: &str) -> u64 {
+        self.catalog.get(&(kind, name.to_string())).cloned().unwrap_or(0)
+    }
+
+    fn vertex_type_count(&self, t: &Option<Type>) -> u64 {
+        match *t {
+            Some(ref t) => self.count(CatalogKind::VertexType, &t.0),
+            None => self.vertex_count,
+        }
+    }
+
+    fn edge_type_count(&self, t: &Option<Type>) -> u64 {
+        match *t {
+            Some(ref t) => self.count(CatalogKind::EdgeType, &t.0),
+            None => self.edge_count,
+        }
+    }
+
+    fn push(&mut self, tree: &Tree, access: Access, uses_index: bool, filtered: bool, reads: u64, items: u64) -> u64 {
+        self.steps.push(PlanStep {
+            tree: tree_name(tree),
+            access,
+            uses_index,
+            filtered,
+            estimated_reads: reads,
+            estimated_items: items,
+        });
+
+        items
+    }
+
+    fn vertex_query(&mut self, q: &VertexQuery) -> u64 {
+        let vertices = &self.holder.vertices;
+
+        match *q {
+            VertexQuery::Range(ref q) => {
+                let start = util::build(&[util::Component::Uuid(q.start_id.unwrap_or_default())]);
+                let matching = self.vertex_type_count(&q.t);
+                let items = cmp::min(u64::from(q.limit), matching);
+
+                // Matches are assumed to be spread evenly through the tree.
+                let reads = if items < matching {
+                    scale(items, self.vertex_count, matching)
+                } else {
+                    self.vertex_count
+                };
+
+                self.push(vertices, Access::Scan { start }, false, q.t.is_some(), reads, items)
+            }
+            VertexQuery::Specific(ref q) => {
+                let reads = q.ids.len() as u64;
+                let items = cmp::min(reads, self.vertex_count);
+                self.push(vertices, Access::PointLookups, false, false, reads, items)
+            }
+            VertexQuery::Pipe(ref q) => {
+                let reads = self.edge_query(&q.inner);
+                let matching = scale(reads, self.vertex_type_count(&q.t), self.vertex_count);
+                let items = cmp::min(u64::from(q.limit), matching);
+                self.push(vertices, Access::PointLookups, false, q.t.is_some(), reads, items)
+            }
+        }
+    }
+
+    fn edge_query(&mut self, q: &EdgeQuery) -> u64 {
+        match *q {
+            EdgeQuery::Specific(ref q) => {
+                let reads = q.keys.len() as u64;
+                let items = cmp::min(reads, self.edge_count);
+                self.push(&self.holder.edges, Access::PointLookups, false, false, reads, items)
+            }
+            EdgeQuery::Pipe(ref q) => {
+                let vertices = self.vertex_query(&q.inner);
+                let untimed = match q.t {
+                    Some(ref t) => !self.holder.edge_range_layout.is_timed(t),
+                    None => !self.holder.edge_range_layout.is_all_timed(),
+                };
+                let time_bounded = q.low.is_some() || q.high.is_some();
+
+                let tree = match q.direction {
+                    EdgeDirection::Outbound => self.holder.edge_ranges.writer(),
+                    EdgeDirection::Inbound => self.holder.reversed_edge_ranges.writer(),
+                };
+
+                // Time bounds aren't accounted for, since there are no
+                // statistics on update datetimes.
+                let reads = scale(vertices, self.edge_type_count(&q.t), self.vertex_count);
+                let items = cmp::min(u64::from(q.limit), reads);
+
+                let access = Access::PrefixScans {
+                    t: q.t.clone(),
+                    seeks_time_range: time_bounded && !untimed,
+                };
+
+                self.push(&tree, access, true, time_bounded && untimed, reads, items);
+
+                // Untimed edge ranges don't include update datetimes, so
+                // each one is followed by a read of its edge. Ranges of mixed
+                // types are estimated as if they were all untimed.
+                if untimed {
+                    self.push(&self.holder.edges, Access::PointLookups, false, false, items, items)
+                } else {
+                    items
+                }
+            }
+        }
+    }
+
+    fn query(mut self, q: &PlannedQuery) -> QueryPlan {
+        let estimated_items = match *q {
+            PlannedQuery::Vertex(ref q) => self.vertex_query(q),
+            PlannedQuery::Edge(ref q) => self.edge_query(q),
+            PlannedQuery::VertexProperty(ref q) => {
+                let reads = self.vertex_query(&q.inner);
+                let items = scale(
+                    reads,
+                    self.count(CatalogKind::VertexProperty, &q.name),
+                    self.vertex_count,
+                );
+                let tree = &self.holder.vertex_properties;
+                self.push(tree, Access::PointLookups, false, false, reads, cmp::min(items, reads))
+            }
+            PlannedQuery::EdgeProperty(ref q) => {
+                let reads = self.edge_query(&q.inner);
+                let items = scale(reads, self.count(CatalogKind::EdgeProperty, &q.name), self.edge_count);
+                let tree = &self.holder.edge_properties;
+                self.push(tree, Access::PointLookups, false, false, reads, cmp::min(items, reads))
+            }
+        };
+
+        QueryPlan {
+            steps: self.steps,
+            estimated_items,
+        }
+    }
+}
+
+/// Plans a query without running it.
+pub(crate) fn explain(holder: &SledHolder, q: &PlannedQuery) -> Result<QueryPlan> {
+    Ok(Planner::new(holder)?.query(q))
+}
@@ -0,0 +1,99 @@
+use std::collections::HashSet;
+use std::mem;
+
+use super::datastore::SledHolder;
+use super::managers::{EdgePropertyManager, EdgeRangeManager, VertexManager, VertexPropertyManager};
+
+use indradb::{BulkInsertItem, Datastore, EdgeKey, MemoryDatastore, Result, Vertex};
+use uuid::Uuid;
+
+/// How many items are bulk inserted into the memory datastore at a time.
+const EXPORT_BATCH_SIZE: usize = 1000;
+
+/// Buffers items for the memory datastore, bulk inserting them a batch at
+/// a time.
+struct Exporter {
+    datastore: MemoryDatastore,
+    items: Vec<BulkInsertItem>,
+}
+
+impl Exporter {
+    fn push(&mut self, item: BulkInsertItem) -> Result<()> {
+        self.items.push(item);
+
+        if self.items.len() == EXPORT_BATCH_SIZE {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let items = mem::replace(&mut self.items, Vec::with_capacity(EXPORT_BATCH_SIZE));
+        self.datastore.bulk_insert(items.into_iter())
+    }
+}
+
+/// Copies the vertices accepted by `filter` into a new memory datastore,
+/// along with their properties, the edges between them, and those edges'
+/// properties.
+pub(crate) fn to_memory_datastore<F>(holder: &SledHolder, filter: F) -> Result<MemoryDatastore>
+where
+    F: Fn(&Vertex) -> bool,
+{
+    let vertex_property_manager = VertexPropertyManager::new(holder);
+    let edge_range_manager = EdgeRangeManager::new(holder);
+    let edge_property_manager = EdgePropertyManager::new(holder);
+    let mut ids = HashSet::new();
+    let mut exporter = Exporter {
+        datastore: MemoryDatastore::default(),
+        items: Vec::with_capacity(EXPORT_BATCH_SIZE),
+    };
+
+    for item in VertexManager::new(holder).iterate_for_range(Uuid::default()) {
+        let (id, t) = item?;
+        let vertex = Vertex::with_id(id, t);
+
+        if !filter(&vertex) {
+            continue;
+        }
+
+        ids.insert(id);
+        exporter.push(BulkInsertItem::Vertex(vertex))?;
+
+        for item in vertex_property_manager.iterate_for_owner(id)? {
+            let ((_, name), value) = item?;
+            exporter.push(BulkInsertItem::VertexProperty(id, name, value))?;
+        }
+    }
+
+    // Edges are only copied once every vertex has been filtered, since
+    // both of their vertices have to be accepted.
+    for &id in &ids {
+        for item in edge_range_manager.iterate_for_owner(id) {
+            let (outbound_id, t, _, inbound_id) = item?;
+
+            // Undirected edges also have a range entry from their inbound
+            // vertex, which is skipped so that they're copied once.
+            if holder.edge_range_layout.canonical_ids(outbound_id, &t, inbound_id) != (outbound_id, inbound_id) {
+                continue;
+            }
+
+            if ids.contains(&inbound_id) {
+                exporter.push(BulkInsertItem::Edge(EdgeKey::new(outbound_id, t, inbound_id)))?;
+            }
+        }
+
+        for item in edge_property_manager.iterate_for_outbound(id) {
+            let ((outbound_id, t, inbound_id, name), value) = item?;
+
+            if ids.contains(&inbound_id) {
+                let key = EdgeKey::new(outbound_id, t, inbound_id);
+                exporter.push(BulkInsertItem::EdgeProperty(key, name, value))?;
+            }
+        }
+    }
+
+    exporter.flush()?;
+    Ok(exporter.datastore)
+}
@@ -0,0 +1,70 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::AtomicU64;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+use super::datastore::{Durability, SledHolder};
+
+use chrono::offset::Utc;
+use chrono::DateTime;
+use indradb::Result;
+
+/// When a holder flushes its writes, and when it last did.
+pub(crate) struct FlushSchedule {
+    /// How many mutations are made between flushes, if they're counted.
+    /// See `SledConfig::with_flush_every`.
+    pub(crate) every: Option<u64>,
+    pub(crate) mutations_since_flush: AtomicU64,
+    pub(crate) durability: Durability,
+    pub(crate) last_flush: Mutex<Option<DateTime<Utc>>>,
+}
+
+#[derive(Default)]
+struct FlushState {
+    result: Option<Result<()>>,
+    waker: Option<Waker>,
+}
+
+/// Resolves once a flush started by `SledDatastore::flush_async` completes.
+/// The flush runs on its own thread, so it goes ahead whether or not this
+/// is polled.
+pub(crate) struct FlushFuture {
+    state: Arc<Mutex<FlushState>>,
+}
+
+impl FlushFuture {
+    pub(crate) fn spawn(holder: Arc<SledHolder>) -> Self {
+        let state = Arc::new(Mutex::new(FlushState::default()));
+        let thread_state = state.clone();
+
+        thread::spawn(move || {
+            let result = holder.flush();
+            let mut state = thread_state.lock().unwrap();
+            state.result = Some(result);
+
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        });
+
+        FlushFuture { state }
+    }
+}
+
+impl Future for FlushFuture {
+    type Output = Result<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<()>> {
+        let mut state = self.state.lock().unwrap();
+
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
@@ -0,0 +1,294 @@
+use std::convert::TryInto;
+
+use super::datastore::SledHolder;
+use super::decode::Decoder;
+use super::errors::{map_err, Error};
+use super::precision::DatetimePrecision;
+use super::rebuild;
+
+use indradb::{util, Result, Type};
+use sled::Tree;
+
+/// The on-disk format version written by this version of the crate.
+///
+/// Version history:
+/// * `0`: Datastores created before format versioning. These have no
+///   metadata tree entries.
+/// * `1`: Records the format version and the edge range layout in the
+///   metadata tree. Datastores of this version may also record the
+///   datetime precision of edge range keys; if they don't, it's full
+///   precision. Likewise for the undirected edge types; if they aren't
+///   recorded, there are none.
+/// * `2`: Adds the catalog of property names.
+/// * `3`: Adds vertex and edge types to the catalog.
+/// * `4`: Adds the inbound-first edge property index.
+/// * `5`: Stores the update datetime as the value of untimed edge range
+///   entries.
+/// * `6`: Adds the vertex property value index.
+/// * `7`: Adds the vertex property number index.
+/// * `8`: Only indexes the values of properties declared with
+///   `SledDatastore::index_property`.
+/// * `9`: Adds the edge property value and number indexes.
+/// * `10`: Adds the edge type index.
+/// * `11`: Adds the value store. Property values may refer to it, rather
+///   than holding their JSON.
+/// * `12`: Adds the per-vertex edge property index.
+pub const FORMAT_VERSION: u64 = 12;
+
+/// The first format version whose untimed edge range entries hold the
+/// update datetime.
+const DATETIME_VALUES_VERSION: u64 = 5;
+
+const FORMAT_VERSION_KEY: &[u8] = b"format_version";
+const UNTIMED_EDGE_RANGES_KEY: &[u8] = b"untimed_edge_ranges";
+const DATETIME_PRECISION_KEY: &[u8] = b"datetime_precision";
+const UNDIRECTED_EDGE_TYPES_KEY: &[u8] = b"undirected_edge_types";
+
+/// A step that migrates a datastore from the format version at its index
+/// in `MIGRATIONS` to the next version.
+///
+/// Migrations must be idempotent, since a crash before the new version is
+/// recorded causes them to run again.
+type Migration = fn(&SledHolder) -> Result<()>;
+
+const MIGRATIONS: &[Migration] = &[
+    migrate_v0_to_v1,
+    migrate_v1_to_v2,
+    migrate_v2_to_v3,
+    migrate_v3_to_v4,
+    migrate_v4_to_v5,
+    migrate_v5_to_v6,
+    migrate_v6_to_v7,
+    migrate_v7_to_v8,
+    migrate_v8_to_v9,
+    migrate_v9_to_v10,
+    migrate_v10_to_v11,
+    migrate_v11_to_v12,
+];
+
+fn migrate_v0_to_v1(holder: &SledHolder) -> Result<()> {
+    write_layout(holder)
+}
+
+fn migrate_v1_to_v2(holder: &SledHolder) -> Result<()> {
+    rebuild::rebuild_catalog(holder)
+}
+
+fn migrate_v2_to_v3(holder: &SledHolder) -> Result<()> {
+    rebuild::rebuild_catalog(holder)
+}
+
+fn migrate_v3_to_v4(holder: &SledHolder) -> Result<()> {
+    rebuild::rebuild_reversed_edge_properties(holder)
+}
+
+fn migrate_v4_to_v5(holder: &SledHolder) -> Result<()> {
+    // Entries written from here on hold the datetime, and those that don't
+    // yet are still read through the edges tree, so the flag is set first.
+    holder.edge_range_layout.set_datetime_values(true);
+    rebuild::rebuild_edge_range_datetimes(holder)
+}
+
+fn migrate_v5_to_v6(holder: &SledHolder) -> Result<()> {
+    rebuild::rebuild_vertex_property_values(holder)
+}
+
+fn migrate_v6_to_v7(holder: &SledHolder) -> Result<()> {
+    rebuild::rebuild_vertex_property_numbers(holder)
+}
+
+fn migrate_v7_to_v8(holder: &SledHolder) -> Result<()> {
+    // Drops the entries of properties that aren't declared.
+    rebuild::rebuild_vertex_property_values(holder)?;
+    rebuild::rebuild_vertex_property_numbers(holder)
+}
+
+fn migrate_v8_to_v9(holder: &SledHolder) -> Result<()> {
+    rebuild::rebuild_edge_property_values(holder)?;
+    rebuild::rebuild_edge_property_numbers(holder)
+}
+
+fn migrate_v9_to_v10(holder: &SledHolder) -> Result<()> {
+    rebuild::rebuild_edges_by_type(holder)
+}
+
+fn migrate_v10_to_v11(_: &SledHolder) -> Result<()> {
+    // Existing values stay inline, and the value store is opened empty.
+    // The version only changes so that older versions, which can't read
+    // references, refuse to open the datastore.
+    Ok(())
+}
+
+fn migrate_v11_to_v12(holder: &SledHolder) -> Result<()> {
+    rebuild::rebuild_vertex_edge_properties(holder)
+}
+
+fn write_layout(holder: &SledHolder) -> Result<()> {
+    let untimed_edge_ranges: &[u8] = if holder.untimed_edge_ranges { &[1] } else { &[0] };
+    map_err(holder.metadata.insert(UNTIMED_EDGE_RANGES_KEY, untimed_edge_ranges))?;
+    map_err(
+        holder
+            .metadata
+            .insert(DATETIME_PRECISION_KEY, &[holder.datetime_precision.to_byte()]),
+    )?;
+
+    let undirected_edge_types = holder.edge_range_layout.undirected_types();
+    let components: Vec<_> = undirected_edge_types.iter().map(util::Component::Type).collect();
+    map_err(
+        holder
+            .metadata
+            .insert(UNDIRECTED_EDGE_TYPES_KEY, util::build(&components)),
+    )?;
+    Ok(())
+}
+
+fn read_undirected_edge_types(metadata: &Tree) -> Result<Vec<Type>> {
+    let value = match map_err(metadata.get(UNDIRECTED_EDGE_TYPES_KEY))? {
+        Some(value) => value,
+        None => return Ok(Vec::new()),
+    };
+
+    let mut decoder = Decoder::value(metadata, UNDIRECTED_EDGE_TYPES_KEY, &value);
+    let mut undirected_edge_types = Vec::new();
+
+    while !decoder.is_empty() {
+        undirected_edge_types.push(decoder.read_type()?);
+    }
+
+    Ok(undirected_edge_types)
+}
+
+/// Gets the format version of a datastore. Unversioned datastores are
+/// reported as version 0.
+pub(crate) fn read_format_version(metadata: &Tree) -> Result<u64> {
+    match map_err(metadata.get(FORMAT_VERSION_KEY))? {
+        Some(value) => {
+            let bytes: [u8; 8] = value.as_ref().try_into().map_err(|_| Error::CorruptMetadata {
+                key: String::from_utf8_lossy(FORMAT_VERSION_KEY).into_owned(),
+            })?;
+            Ok(u64::from_be_bytes(bytes))
+        }
+        None => Ok(0),
+    }
+}
+
+fn write_format_version(metadata: &Tree, version: u64) -> Result<()> {
+    map_err(metadata.insert(FORMAT_VERSION_KEY, &version.to_be_bytes()))?;
+    Ok(())
+}
+
+/// Enables reading and writing datetimes in the values of untimed edge
+/// range entries, if the datastore's format version has them.
+pub(crate) fn load_datetime_values(holder: &SledHolder) -> Result<()> {
+    let version = read_format_version(&holder.metadata)?;
+    holder
+        .edge_range_layout
+        .set_datetime_values(version >= DATETIME_VALUES_VERSION);
+    Ok(())
+}
+
+/// Validates the format of a datastore as it's opened. New datastores are
+/// stamped with the current format version, datastores written by a newer
+/// version of this crate are rejected, and older ones are migrated once
+/// they're known to match the config.
+pub(crate) fn check_format(holder: &SledHolder) -> Result<()> {
+    let version = read_format_version(&holder.metadata)?;
+
+    if version > FORMAT_VERSION {
+        return Err(Error::UnsupportedFormat {
+            found: version,
+            supported: FORMAT_VERSION,
+        }
+        .into());
+    }
+
+    if version == 0 {
+        let is_new = holder.vertices.is_empty() && holder.edges.is_empty() && holder.vertex_properties.is_empty();
+
+        if is_new {
+            write_layout(holder)?;
+            write_format_version(&holder.metadata, FORMAT_VERSION)?;
+            holder.edge_range_layout.set_datetime_values(true);
+            return Ok(());
+        }
+    }
+
+    // Unversioned datastores predate untimed edge ranges.
+    let untimed_edge_ranges = match map_err(holder.metadata.get(UNTIMED_EDGE_RANGES_KEY))? {
+        Some(value) => value.as_ref() == [1],
+        None => false,
+    };
+
+    if untimed_edge_ranges != holder.untimed_edge_ranges {
+        return Err(Error::IncompatibleConfig {
+            reason: if untimed_edge_ranges {
+                "the datastore was created with untimed edge ranges".to_string()
+            } else {
+                "the datastore was created without untimed edge ranges".to_string()
+            },
+        }
+        .into());
+    }
+
+    // Datastores created before the precision was configurable use full
+    // precision.
+    let datetime_precision = match map_err(holder.metadata.get(DATETIME_PRECISION_KEY))? {
+        Some(value) => value
+            .first()
+            .and_then(|byte| DatetimePrecision::from_byte(*byte))
+            .ok_or_else(|| Error::CorruptMetadata {
+                key: String::from_utf8_lossy(DATETIME_PRECISION_KEY).into_owned(),
+            })?,
+        None => DatetimePrecision::Nanos,
+    };
+
+    if datetime_precision != holder.datetime_precision {
+        return Err(Error::IncompatibleConfig {
+            reason: format!(
+                "the datastore was created with {:?} datetime precision",
+                datetime_precision
+            ),
+        }
+        .into());
+    }
+
+    // Edges of undirected types are stored in one orientation, so changing
+    // which types are undirected would strand or duplicate them.
+    let undirected_edge_types = read_undirected_edge_types(&holder.metadata)?;
+
+    if undirected_edge_types != holder.edge_range_layout.undirected_types() {
+        let names: Vec<_> = undirected_edge_types.iter().map(|t| &t.0).collect();
+        return Err(Error::IncompatibleConfig {
+            reason: if names.is_empty() {
+                "the datastore was created without undirected edge types".to_string()
+            } else {
+                format!("the datastore was created with the undirected edge types {:?}", names)
+            },
+        }
+        .into());
+    }
+
+    if version < FORMAT_VERSION {
+        migrate(holder)?;
+    }
+
+    Ok(())
+}
+
+/// Upgrades a datastore to the current format version, one version at a
+/// time. Each step is flushed before the next begins, so an interrupted
+/// migration resumes where it left off. Returns the resulting version.
+pub(crate) fn migrate(holder: &SledHolder) -> Result<u64> {
+    let mut version = read_format_version(&holder.metadata)?;
+
+    while version < FORMAT_VERSION {
+        MIGRATIONS[version as usize](holder)?;
+        map_err(holder.db.flush())?;
+
+        version += 1;
+        write_format_version(&holder.metadata, version)?;
+        map_err(holder.metadata.flush())?;
+    }
+
+    Ok(version)
+}
@@ -0,0 +1,192 @@
+use std::collections::BTreeSet;
+
+use super::datastore::SledHolder;
+use super::deadline::Deadline;
+use super::decode::Decoder;
+use super::dedup;
+use super::errors::map_err;
+use super::layout::escape;
+use super::managers::{VertexManager, VertexPropertyManager};
+
+use indradb::{Result, Vertex};
+use serde_json::Value as JsonValue;
+use sled::{Batch, Tree};
+use uuid::Uuid;
+
+const FULL_TEXT_INDEX_KEY: &str = "full_text_index";
+
+/// Splits text into lowercase words, breaking on anything that isn't a
+/// letter or digit.
+pub(crate) fn tokenize(text: &str) -> BTreeSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+/// The words of a property value, given the value as stored. Only strings
+/// are indexed.
+fn tokenize_value(value_json: &[u8]) -> BTreeSet<String> {
+    if value_json.first() != Some(&b'"') {
+        return BTreeSet::new();
+    }
+
+    match serde_json::from_slice::<String>(value_json) {
+        Ok(text) => tokenize(&text),
+        Err(_) => BTreeSet::new(),
+    }
+}
+
+/// Builds the prefix shared by the entries of a word in a property. Both
+/// are escaped, since each is followed by more of the key.
+fn word_prefix(name: &str, word: &str) -> Vec<u8> {
+    let mut prefix = Vec::with_capacity(name.len() + word.len() + 4);
+    escape(name.as_bytes(), &mut prefix);
+    escape(word.as_bytes(), &mut prefix);
+    prefix
+}
+
+fn key(name: &str, word: &str, id: Uuid) -> Vec<u8> {
+    let mut key = word_prefix(name, word);
+    key.extend_from_slice(id.as_bytes());
+    key
+}
+
+/// Updates the index after a vertex property changed, given its old and
+/// new values as stored. `None` means the vertex doesn't have the
+/// property. Only the entries of words that were added or removed are
+/// written.
+pub(crate) fn on_property_change(
+    holder: &SledHolder,
+    id: Uuid,
+    name: &str,
+    old_value_json: Option<&[u8]>,
+    new_value_json: Option<&[u8]>,
+) -> Result<()> {
+    let tree = match holder.full_text_index {
+        Some(ref tree) => tree,
+        None => return Ok(()),
+    };
+
+    let old_words = old_value_json.map(tokenize_value).unwrap_or_default();
+    let new_words = new_value_json.map(tokenize_value).unwrap_or_default();
+
+    if old_words == new_words {
+        return Ok(());
+    }
+
+    let mut batch = Batch::default();
+
+    for word in old_words.difference(&new_words) {
+        batch.remove(key(name, word, id));
+    }
+
+    for word in new_words.difference(&old_words) {
+        batch.insert(key(name, word, id), &[]);
+    }
+
+    holder.retrier.run(|| tree.apply_batch(batch.clone()))?;
+    Ok(())
+}
+
+/// Removes every entry of the index, so that it can be rebuilt with
+/// `on_property_change`.
+pub(crate) fn clear(holder: &SledHolder) -> Result<()> {
+    if let Some(ref tree) = holder.full_text_index {
+        map_err(tree.clear())?;
+    }
+
+    Ok(())
+}
+
+/// Rebuilds the index from the vertex properties.
+pub(crate) fn rebuild(holder: &SledHolder) -> Result<()> {
+    let tree = match holder.full_text_index {
+        Some(ref tree) => tree,
+        None => return Ok(()),
+    };
+
+    map_err(tree.clear())?;
+
+    for item in holder.vertex_properties.iter() {
+        let (k, v) = map_err(item)?;
+        let mut decoder = Decoder::key(&holder.vertex_properties, &k);
+        let id = decoder.read_uuid()?;
+        let name = decoder.read_fixed_length_string()?;
+        let value_json = dedup::resolve(&holder.values.store, &holder.vertex_properties, &k, &v)?;
+
+        for word in tokenize_value(&value_json) {
+            map_err(tree.insert(key(&name, &word, id), &[]))?;
+        }
+    }
+
+    map_err(holder.metadata.insert(holder.metadata_key(FULL_TEXT_INDEX_KEY), &[]))?;
+    Ok(())
+}
+
+/// Builds the index if it was enabled since the datastore was last
+/// opened. If it's disabled, it's marked as stale instead, since it won't
+/// be maintained.
+pub(crate) fn rebuild_stale(holder: &SledHolder) -> Result<()> {
+    let key = holder.metadata_key(FULL_TEXT_INDEX_KEY);
+
+    if holder.full_text_index.is_none() {
+        map_err(holder.metadata.remove(key))?;
+        return Ok(());
+    }
+
+    if !map_err(holder.metadata.contains_key(key))? {
+        rebuild(holder)?;
+    }
+
+    Ok(())
+}
+
+/// Gets the vertices whose property `name` contains every word of
+/// `query`, in ID order.
+pub(crate) fn search(
+    holder: &SledHolder,
+    tree: &Tree,
+    name: &str,
+    query: &str,
+    deadline: &Deadline,
+) -> Result<Vec<Vertex>> {
+    let words = tokenize(query);
+
+    // Candidates are read from the entries of the longest word, which
+    // tends to be the rarest, and checked against the rest by reading the
+    // property, which also skips entries of values that were just
+    // replaced.
+    let longest = match words.iter().max_by_key(|word| word.len()) {
+        Some(word) => word,
+        None => return Ok(Vec::new()),
+    };
+
+    let vertex_manager = VertexManager::new(holder);
+    let vertex_property_manager = VertexPropertyManager::new(holder);
+    let prefix = word_prefix(name, longest);
+    let mut vertices = Vec::new();
+
+    for item in tree.scan_prefix(&prefix).keys() {
+        let k = map_err(item)?;
+        deadline.tick()?;
+        let mut decoder = Decoder::key(tree, &k);
+        decoder.skip(prefix.len())?;
+        let id = decoder.read_uuid()?;
+
+        let matches = match vertex_property_manager.get(id, name)? {
+            Some(JsonValue::String(ref text)) => tokenize(text).is_superset(&words),
+            _ => false,
+        };
+
+        if !matches {
+            continue;
+        }
+
+        if let Some(t) = vertex_manager.get(id)? {
+            vertices.push(Vertex::with_id(id, t));
+        }
+    }
+
+    Ok(vertices)
+}
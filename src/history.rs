@@ -0,0 +1,279 @@
+use std::cmp::Reverse;
+use std::sync::Arc;
+
+use super::datastore::SledHolder;
+use super::decode::Decoder;
+use super::managers::{history_property_key, EdgeManager, HistoryManager};
+
+use chrono::offset::Utc;
+use chrono::DateTime;
+use indradb::{
+    util, Edge, EdgeDirection, EdgeKey, EdgeProperty, EdgePropertyQuery, EdgeQuery, Result, Vertex, VertexProperty,
+    VertexPropertyQuery, VertexQuery,
+};
+use serde_json::Value as JsonValue;
+use sled::Tree;
+use uuid::Uuid;
+
+/// The trees that every version of the graph is recorded in, keyed by the
+/// entity and the datetime it was written at. Written to if
+/// `SledConfig::with_history` is set.
+pub(crate) struct HistoryTrees {
+    pub(crate) enabled: bool,
+    pub(crate) vertices: Tree,
+    pub(crate) edges: Tree,
+    pub(crate) vertex_properties: Tree,
+    pub(crate) edge_properties: Tree,
+}
+
+/// A read-only view of a sled datastore as it was at a point in time, built
+/// from the history trees.
+pub struct SledAsOfView {
+    holder: Arc<SledHolder>,
+    datetime: DateTime<Utc>,
+}
+
+impl SledAsOfView {
+    pub(crate) fn new(holder: Arc<SledHolder>, datetime: DateTime<Utc>) -> Self {
+        SledAsOfView { holder, datetime }
+    }
+
+    /// The point in time this view reflects.
+    pub fn datetime(&self) -> DateTime<Utc> {
+        self.datetime
+    }
+
+    /// Gets the vertices that a query returns at the time of this view,
+    /// with the same semantics as `Transaction::get_vertices`.
+    pub fn get_vertices<Q: Into<VertexQuery>>(&self, q: Q) -> Result<Vec<Vertex>> {
+        self.query_vertices(q.into())
+    }
+
+    /// Gets the edges that a query returns at the time of this view, with
+    /// the same semantics as `Transaction::get_edges`. Piped queries return
+    /// each vertex's edges by type, most recently updated first, and
+    /// inbound ones scan the edge history of every vertex.
+    pub fn get_edges<Q: Into<EdgeQuery>>(&self, q: Q) -> Result<Vec<Edge>> {
+        self.query_edges(q.into())
+    }
+
+    /// Gets a property of the vertices that a query returns at the time of
+    /// this view, for those that had it set then.
+    pub fn get_vertex_properties(&self, q: VertexPropertyQuery) -> Result<Vec<VertexProperty>> {
+        let mut properties = Vec::new();
+
+        for vertex in self.query_vertices(q.inner)? {
+            if let Some(value) = self.get_vertex_property(vertex.id, &q.name)? {
+                properties.push(VertexProperty::new(vertex.id, value));
+            }
+        }
+
+        Ok(properties)
+    }
+
+    /// Gets a property of the edges that a query returns at the time of this
+    /// view, for those that had it set then.
+    pub fn get_edge_properties(&self, q: EdgePropertyQuery) -> Result<Vec<EdgeProperty>> {
+        let mut properties = Vec::new();
+
+        for edge in self.query_edges(q.inner)? {
+            if let Some(value) = self.get_edge_property(&edge.key, &q.name)? {
+                properties.push(EdgeProperty::new(edge.key, value));
+            }
+        }
+
+        Ok(properties)
+    }
+
+    /// Gets a vertex, if it existed at the time of this view.
+    pub fn get_vertex(&self, id: Uuid) -> Result<Option<Vertex>> {
+        let manager = HistoryManager::new(&self.holder.retrier, &self.holder.history.vertices);
+        let key = util::build(&[util::Component::Uuid(id)]);
+
+        match manager.get_as_of(&key, self.datetime)? {
+            Some(value) => {
+                let t = Decoder::value(manager.tree, &key, &value).read_type()?;
+                Ok(Some(Vertex::with_id(id, t)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Gets an edge, if it existed at the time of this view.
+    pub fn get_edge(&self, key: &EdgeKey) -> Result<Option<Edge>> {
+        let manager = HistoryManager::new(&self.holder.retrier, &self.holder.history.edges);
+        let entity_key = self.edge_entity_key(key);
+
+        match manager.get_as_of(&entity_key, self.datetime)? {
+            Some(value) => {
+                let update_datetime = Decoder::value(manager.tree, &entity_key, &value).read_datetime()?;
+                Ok(Some(Edge::new(key.clone(), update_datetime)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Gets the outbound edges of a vertex that existed at the time of this
+    /// view.
+    pub fn get_outbound_edges(&self, id: Uuid) -> Result<Vec<Edge>> {
+        self.edges_with_prefix(&util::build(&[util::Component::Uuid(id)]))
+    }
+
+    /// Gets the inbound edges of a vertex that existed at the time of this
+    /// view. Edge history is keyed by outbound vertex, so this scans the
+    /// edge history of every vertex.
+    pub fn get_inbound_edges(&self, id: Uuid) -> Result<Vec<Edge>> {
+        let mut edges = self.edges_with_prefix(&[])?;
+        edges.retain(|edge| edge.key.inbound_id == id);
+        Ok(edges)
+    }
+
+    /// Gets a vertex property, if it was set at the time of this view.
+    pub fn get_vertex_property(&self, id: Uuid, name: &str) -> Result<Option<JsonValue>> {
+        let manager = HistoryManager::new(&self.holder.retrier, &self.holder.history.vertex_properties);
+        let entity_key = history_property_key(&util::build(&[util::Component::Uuid(id)]), name);
+
+        match manager.get_as_of(&entity_key, self.datetime)? {
+            Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Gets an edge property, if it was set at the time of this view.
+    pub fn get_edge_property(&self, key: &EdgeKey, name: &str) -> Result<Option<JsonValue>> {
+        let manager = HistoryManager::new(&self.holder.retrier, &self.holder.history.edge_properties);
+        let entity_key = history_property_key(&self.edge_entity_key(key), name);
+
+        match manager.get_as_of(&entity_key, self.datetime)? {
+            Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn query_vertices(&self, q: VertexQuery) -> Result<Vec<Vertex>> {
+        match q {
+            VertexQuery::Range(q) => {
+                let manager = HistoryManager::new(&self.holder.retrier, &self.holder.history.vertices);
+                let mut vertices = Vec::new();
+
+                // Vertex history is keyed by ID, so vertices come in ID order.
+                for (entity_key, value) in manager.iterate_as_of(&[], self.datetime)? {
+                    if vertices.len() == q.limit as usize {
+                        break;
+                    }
+
+                    let id = Decoder::key(manager.tree, &entity_key).read_uuid()?;
+                    let t = Decoder::value(manager.tree, &entity_key, &value).read_type()?;
+
+                    if q.start_id.is_none_or(|start_id| id > start_id) && q.t.as_ref().is_none_or(|q_t| *q_t == t) {
+                        vertices.push(Vertex::with_id(id, t));
+                    }
+                }
+
+                Ok(vertices)
+            }
+            VertexQuery::Specific(q) => {
+                let mut vertices = Vec::new();
+
+                for id in q.ids {
+                    if let Some(vertex) = self.get_vertex(id)? {
+                        vertices.push(vertex);
+                    }
+                }
+
+                Ok(vertices)
+            }
+            VertexQuery::Pipe(q) => {
+                let mut vertices = Vec::new();
+
+                for edge in self.query_edges(*q.inner)? {
+                    if vertices.len() == q.limit as usize {
+                        break;
+                    }
+
+                    let id = match q.direction {
+                        EdgeDirection::Outbound => edge.key.outbound_id,
+                        EdgeDirection::Inbound => edge.key.inbound_id,
+                    };
+
+                    if let Some(vertex) = self.get_vertex(id)? {
+                        if q.t.as_ref().is_none_or(|t| *t == vertex.t) {
+                            vertices.push(vertex);
+                        }
+                    }
+                }
+
+                Ok(vertices)
+            }
+        }
+    }
+
+    fn query_edges(&self, q: EdgeQuery) -> Result<Vec<Edge>> {
+        match q {
+            EdgeQuery::Specific(q) => {
+                let mut edges = Vec::new();
+
+                for key in &q.keys {
+                    if let Some(edge) = self.get_edge(key)? {
+                        edges.push(edge);
+                    }
+                }
+
+                Ok(edges)
+            }
+            EdgeQuery::Pipe(q) => {
+                let (t, low, high, limit) = (q.t, q.low, q.high, q.limit as usize);
+                let mut edges = Vec::new();
+
+                for vertex in self.query_vertices(*q.inner)? {
+                    if edges.len() == limit {
+                        break;
+                    }
+
+                    let mut vertex_edges = match q.direction {
+                        EdgeDirection::Outbound => self.get_outbound_edges(vertex.id)?,
+                        EdgeDirection::Inbound => self.get_inbound_edges(vertex.id)?,
+                    };
+
+                    vertex_edges.retain(|edge| {
+                        t.as_ref().is_none_or(|t| *t == edge.key.t)
+                            && low.is_none_or(|low| edge.created_datetime >= low)
+                            && high.is_none_or(|high| edge.created_datetime <= high)
+                    });
+                    vertex_edges.sort_by_key(|edge| (edge.key.t.clone(), Reverse(edge.created_datetime)));
+                    edges.extend(vertex_edges.into_iter().take(limit - edges.len()));
+                }
+
+                Ok(edges)
+            }
+        }
+    }
+
+    /// Builds the history key of an edge, which is recorded under its
+    /// canonical orientation if its type is undirected.
+    fn edge_entity_key(&self, key: &EdgeKey) -> Vec<u8> {
+        let (outbound_id, inbound_id) =
+            self.holder
+                .edge_range_layout
+                .canonical_ids(key.outbound_id, &key.t, key.inbound_id);
+        EdgeManager::build_key(outbound_id, &key.t, inbound_id)
+    }
+
+    /// Gets the edges whose history keys start with `prefix` that existed at
+    /// the time of this view.
+    fn edges_with_prefix(&self, prefix: &[u8]) -> Result<Vec<Edge>> {
+        let manager = HistoryManager::new(&self.holder.retrier, &self.holder.history.edges);
+        let mut edges = Vec::new();
+
+        for (entity_key, value) in manager.iterate_as_of(prefix, self.datetime)? {
+            let mut decoder = Decoder::key(manager.tree, &entity_key);
+            let outbound_id = decoder.read_uuid()?;
+            let t = decoder.read_type()?;
+            let inbound_id = decoder.read_uuid()?;
+            let update_datetime = Decoder::value(manager.tree, &entity_key, &value).read_datetime()?;
+            edges.push(Edge::new(EdgeKey::new(outbound_id, t, inbound_id), update_datetime));
+        }
+
+        Ok(edges)
+    }
+}
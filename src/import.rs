@@ -0,0 +1,70 @@
+use std::convert::TryInto;
+
+use super::atomic::MultiBatch;
+use super::batch::PendingWrites;
+use super::datastore::{SledHolder, SledTransaction};
+use super::errors::{map_err, Error};
+
+use indradb::{BulkInsertItem, Result};
+
+/// The prefix of the names of the metadata entries holding import
+/// watermarks, which is followed by the name of the source.
+const WATERMARK_PREFIX: &str = "import_watermark/";
+
+fn watermark_name(source: &str) -> String {
+    format!("{}{}", WATERMARK_PREFIX, source)
+}
+
+/// Gets the watermark of the last chunk imported from `source`, if any.
+pub(crate) fn watermark(holder: &SledHolder, source: &str) -> Result<Option<u64>> {
+    let name = watermark_name(source);
+
+    match map_err(holder.metadata.get(holder.metadata_key(&name)))? {
+        Some(value) => {
+            let bytes: [u8; 8] = value
+                .as_ref()
+                .try_into()
+                .map_err(|_| Error::CorruptMetadata { key: name })?;
+            Ok(Some(u64::from_be_bytes(bytes)))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Applies a chunk of items imported from `source`, unless its watermark
+/// shows it was already imported. The new watermark is written in the same
+/// sled transaction as the last of the chunk's writes. Returns whether the
+/// chunk was applied.
+pub(crate) fn import<I>(trans: &SledTransaction, source: &str, watermark: u64, items: I) -> Result<bool>
+where
+    I: IntoIterator<Item = BulkInsertItem>,
+{
+    let holder = &trans.holder;
+    let mut writes = PendingWrites::default();
+
+    for item in items {
+        match item {
+            BulkInsertItem::Vertex(vertex) => writes.vertices.push(vertex),
+            BulkInsertItem::Edge(key) => writes.edges.push(key),
+            BulkInsertItem::VertexProperty(id, name, value) => writes.vertex_properties.push((id, name, value)),
+            BulkInsertItem::EdgeProperty(key, name, value) => writes.edge_properties.push((key, name, value)),
+        }
+    }
+
+    let _guard = holder.write_guard();
+
+    if let Some(last) = self::watermark(holder, source)? {
+        if last >= watermark {
+            return Ok(false);
+        }
+    }
+
+    let mut tail = MultiBatch::default();
+    tail.insert(
+        &holder.metadata,
+        holder.metadata_key(&watermark_name(source)),
+        &watermark.to_be_bytes()[..],
+    );
+    writes.apply_locked(trans, "import", tail)?;
+    Ok(true)
+}
@@ -0,0 +1,447 @@
+use std::collections::HashSet;
+use std::ops::Bound;
+use std::sync::RwLock;
+
+use super::datastore::SledHolder;
+use super::decode::Decoder;
+use super::dedup;
+use super::errors::{map_err, Error};
+use super::managers::{EdgePropertyManager, VertexPropertyManager};
+use super::reindex::IndexWriter;
+
+use indradb::Result;
+use sled::{Batch, Tree};
+
+/// The number of properties read per step of a backfill. Writes are paused
+/// while each step runs, so this bounds how long they may stall.
+const BUILD_CHUNK_SIZE: usize = 1000;
+
+/// The value of the definition of a unique property. Other properties'
+/// definitions are empty.
+const UNIQUE_DEFINITION: &[u8] = &[1];
+
+/// The value index key of a property record, and its number index key if
+/// its value is a number.
+type EntryKeys = (Vec<u8>, Option<Vec<u8>>);
+
+/// Which properties are indexed, and the tree that enforces the uniqueness
+/// of those declared unique.
+pub(crate) struct IndexedProperties {
+    /// The vertex properties whose values are indexed, as declared with
+    /// `SledDatastore::index_property`.
+    pub(crate) vertex: RwLock<HashSet<String>>,
+    /// The vertex properties whose index entries are being backfilled.
+    /// Writes index them, but they can't be queried until it's done.
+    pub(crate) building_vertex: RwLock<HashSet<String>>,
+    /// The indexed vertex properties that are unique, as declared with
+    /// `SledDatastore::index_unique_property`, including ones whose index
+    /// is being built.
+    pub(crate) unique: RwLock<HashSet<String>>,
+    /// The edge properties whose values are indexed, as declared with
+    /// `SledDatastore::index_edge_property`.
+    pub(crate) edge: RwLock<HashSet<String>>,
+    /// The edge properties whose index entries are being backfilled.
+    pub(crate) building_edge: RwLock<HashSet<String>>,
+    /// The vertex holding each value of the unique properties, keyed by
+    /// `(name, value)`.
+    pub(crate) unique_values: Tree,
+}
+
+impl IndexedProperties {
+    /// Starts with no properties indexed; `load` reads them back.
+    pub(crate) fn new(unique_values: Tree) -> Self {
+        IndexedProperties {
+            vertex: RwLock::new(HashSet::new()),
+            building_vertex: RwLock::new(HashSet::new()),
+            unique: RwLock::new(HashSet::new()),
+            edge: RwLock::new(HashSet::new()),
+            building_edge: RwLock::new(HashSet::new()),
+            unique_values,
+        }
+    }
+}
+
+/// Whether an indexed property is a vertex or an edge property.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum PropertyOwner {
+    Vertex,
+    Edge,
+}
+
+impl PropertyOwner {
+    /// The metadata key prefix of the owner's index definitions, which are
+    /// followed by the property name.
+    fn definition_prefix(self) -> &'static str {
+        match self {
+            PropertyOwner::Vertex => "indexed_property:",
+            PropertyOwner::Edge => "indexed_edge_property:",
+        }
+    }
+
+    fn definition_key(self, holder: &SledHolder, name: &str) -> Vec<u8> {
+        holder.metadata_key(&format!("{}{}", self.definition_prefix(), name))
+    }
+
+    /// The names of the owner's indexed properties.
+    pub(crate) fn indexed(self, holder: &SledHolder) -> &RwLock<HashSet<String>> {
+        match self {
+            PropertyOwner::Vertex => &holder.indexed.vertex,
+            PropertyOwner::Edge => &holder.indexed.edge,
+        }
+    }
+
+    /// The names of the owner's properties whose entries are being
+    /// backfilled.
+    fn building(self, holder: &SledHolder) -> &RwLock<HashSet<String>> {
+        match self {
+            PropertyOwner::Vertex => &holder.indexed.building_vertex,
+            PropertyOwner::Edge => &holder.indexed.building_edge,
+        }
+    }
+
+    /// Whether writes index a property, i.e. it's indexed or being
+    /// backfilled.
+    fn is_indexed(self, holder: &SledHolder, name: &str) -> bool {
+        match self {
+            PropertyOwner::Vertex => holder.is_property_indexed(name),
+            PropertyOwner::Edge => holder.is_edge_property_indexed(name),
+        }
+    }
+
+    fn source(self, holder: &SledHolder) -> &Tree {
+        match self {
+            PropertyOwner::Vertex => &holder.vertex_properties,
+            PropertyOwner::Edge => &holder.edge_properties,
+        }
+    }
+
+    /// The value and number index trees.
+    fn index_trees(self, holder: &SledHolder) -> (IndexWriter, IndexWriter) {
+        match self {
+            PropertyOwner::Vertex => {
+                let manager = VertexPropertyManager::new(holder);
+                (manager.value_tree, manager.number_tree)
+            }
+            PropertyOwner::Edge => {
+                let manager = EdgePropertyManager::new(holder);
+                (manager.value_tree, manager.number_tree)
+            }
+        }
+    }
+
+    /// Builds the value and number index keys of a property record, or
+    /// returns `None` if it isn't of the property `name`.
+    fn entry_keys(self, holder: &SledHolder, k: &[u8], v: &[u8], name: &str) -> Result<Option<EntryKeys>> {
+        let source = self.source(holder);
+        let mut decoder = Decoder::key(source, k);
+
+        match self {
+            PropertyOwner::Vertex => {
+                let id = decoder.read_uuid()?;
+
+                if decoder.read_fixed_length_string()? != name {
+                    return Ok(None);
+                }
+
+                let value_json = dedup::resolve(&holder.values.store, source, k, v)?;
+                Ok(Some((
+                    VertexPropertyManager::value_key(id, name, &value_json),
+                    VertexPropertyManager::number_key(id, name, &value_json),
+                )))
+            }
+            PropertyOwner::Edge => {
+                let outbound_id = decoder.read_uuid()?;
+                let t = decoder.read_type()?;
+                let inbound_id = decoder.read_uuid()?;
+
+                if decoder.read_fixed_length_string()? != name {
+                    return Ok(None);
+                }
+
+                let value_json = dedup::resolve(&holder.values.store, source, k, v)?;
+                Ok(Some((
+                    EdgePropertyManager::value_key(outbound_id, &t, inbound_id, name, &value_json),
+                    EdgePropertyManager::number_key(outbound_id, &t, inbound_id, name, &value_json),
+                )))
+            }
+        }
+    }
+}
+
+/// Loads the names of the indexed properties, when the datastore is
+/// opened.
+pub(crate) fn load(holder: &SledHolder) -> Result<()> {
+    for &owner in &[PropertyOwner::Vertex, PropertyOwner::Edge] {
+        let prefix = holder.metadata_key(owner.definition_prefix());
+        let mut indexed = owner.indexed(holder).write().unwrap();
+
+        for item in holder.metadata.scan_prefix(&prefix) {
+            let (k, v) = map_err(item)?;
+            let mut decoder = Decoder::key(&holder.metadata, &k);
+            decoder.skip(prefix.len())?;
+            let name = decoder.read_fixed_length_string()?;
+
+            if owner == PropertyOwner::Vertex && v == UNIQUE_DEFINITION {
+                holder.indexed.unique.write().unwrap().insert(name.clone());
+            }
+
+            indexed.insert(name);
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that a property can be queried through its index.
+/// While a bulk load is in progress, no index can be, since they're only
+/// rebuilt once it's finished.
+pub(crate) fn check_queryable(holder: &SledHolder, owner: PropertyOwner, name: &str) -> Result<()> {
+    if holder.is_bulk_loading() || owner.building(holder).read().unwrap().contains(name) {
+        Err(Error::PropertyIndexBuilding { name: name.to_string() }.into())
+    } else if owner.indexed(holder).read().unwrap().contains(name) {
+        Ok(())
+    } else {
+        Err(Error::PropertyNotIndexed { name: name.to_string() }.into())
+    }
+}
+
+/// Removes the value and number index entries of a property.
+fn clear(holder: &SledHolder, owner: PropertyOwner, name: &str) -> Result<()> {
+    let (value_tree, number_tree) = owner.index_trees(holder);
+    clear_prefixed(holder, &[&value_tree, &number_tree], name)
+}
+
+/// Removes the records of which vertex holds each value of a unique
+/// property.
+fn clear_claims(holder: &SledHolder, name: &str) -> Result<()> {
+    clear_prefixed(holder, &[&holder.indexed.unique_values], name)
+}
+
+fn clear_prefixed(holder: &SledHolder, trees: &[&Tree], name: &str) -> Result<()> {
+    let prefix = VertexPropertyManager::name_prefix(name);
+
+    for tree in trees {
+        let mut batch = Batch::default();
+
+        for item in tree.scan_prefix(&prefix).keys() {
+            batch.remove(map_err(item)?);
+        }
+
+        holder.retrier.run(|| tree.apply_batch(batch.clone()))?;
+    }
+
+    Ok(())
+}
+
+/// How far along the backfill of a property index is. See
+/// `SledDatastore::rebuild_index_with_progress`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IndexBuildProgress {
+    /// The number of properties read so far, of any name.
+    pub scanned: u64,
+    /// The number of values of the property indexed so far.
+    pub indexed: u64,
+}
+
+/// Records that a vertex holds a value of a unique property, given its
+/// value index key, or fails if another vertex already does.
+fn claim(holder: &SledHolder, name: &str, value_key: &[u8]) -> Result<()> {
+    let (claim_key, vertex_id) = value_key.split_at(value_key.len() - 16);
+
+    match map_err(holder.indexed.unique_values.get(claim_key))? {
+        Some(ref holder_id) if &holder_id[..] != vertex_id => Err(Error::UniqueValueTaken {
+            name: name.to_string(),
+            vertex_id: Decoder::value(&holder.indexed.unique_values, claim_key, holder_id).read_uuid()?,
+        }
+        .into()),
+        _ => {
+            map_err(holder.indexed.unique_values.insert(claim_key, vertex_id))?;
+            Ok(())
+        }
+    }
+}
+
+/// Indexes the existing values of a property, a chunk of the properties
+/// at a time. Writes are paused while each chunk is indexed, so that none
+/// of them can replace a value between it being read and indexed; between
+/// chunks, they index the property themselves.
+fn backfill(
+    holder: &SledHolder,
+    owner: PropertyOwner,
+    name: &str,
+    unique: bool,
+    on_progress: &mut dyn FnMut(&IndexBuildProgress),
+) -> Result<IndexBuildProgress> {
+    let source = owner.source(holder);
+    let (value_tree, number_tree) = owner.index_trees(holder);
+    let mut progress = IndexBuildProgress::default();
+    let mut start = Bound::Unbounded;
+
+    loop {
+        let last = {
+            let _paused = holder.index_lock.write().unwrap();
+            let mut value_batch = Batch::default();
+            let mut number_batch = Batch::default();
+            let mut last = None;
+            let mut count = 0;
+
+            // Properties are keyed by their owner first, so every one has to
+            // be read.
+            for item in source.range::<Vec<u8>, _>((start.clone(), Bound::Unbounded)) {
+                let (k, v) = map_err(item)?;
+
+                if let Some((value_key, number_key)) = owner.entry_keys(holder, &k, &v, name)? {
+                    if unique {
+                        claim(holder, name, &value_key)?;
+                    }
+
+                    value_batch.insert(value_key, &[]);
+
+                    if let Some(number_key) = number_key {
+                        number_batch.insert(number_key, &[]);
+                    }
+
+                    progress.indexed += 1;
+                }
+
+                progress.scanned += 1;
+                count += 1;
+                if count == BUILD_CHUNK_SIZE {
+                    last = Some(k.to_vec());
+                    break;
+                }
+            }
+
+            holder.retrier.run(|| value_tree.apply_batch(value_batch.clone()))?;
+            holder.retrier.run(|| number_tree.apply_batch(number_batch.clone()))?;
+            last
+        };
+
+        on_progress(&progress);
+
+        match last {
+            Some(last) => start = Bound::Excluded(last),
+            None => return Ok(progress),
+        }
+    }
+}
+
+/// Clears and backfills the entries of a property, then runs `finish`
+/// with writes paused. Until then, writes index the property, but it
+/// can't be queried. If `unique` is set, writes also enforce its
+/// uniqueness from the start, and the backfill fails if two vertices
+/// share a value.
+fn build<F>(
+    holder: &SledHolder,
+    owner: PropertyOwner,
+    name: &str,
+    unique: bool,
+    on_progress: &mut dyn FnMut(&IndexBuildProgress),
+    finish: F,
+) -> Result<IndexBuildProgress>
+where
+    F: FnOnce() -> Result<()>,
+{
+    // Entries are cleared with writes paused, so that none written from
+    // then on are lost.
+    let cleared = {
+        let _paused = holder.index_lock.write().unwrap();
+        owner.building(holder).write().unwrap().insert(name.to_string());
+
+        if unique {
+            holder.indexed.unique.write().unwrap().insert(name.to_string());
+        }
+
+        clear(holder, owner, name)
+    };
+
+    let result = cleared
+        .and_then(|_| backfill(holder, owner, name, unique, on_progress))
+        .and_then(|progress| {
+            let _paused = holder.index_lock.write().unwrap();
+            finish()?;
+            Ok(progress)
+        });
+
+    owner.building(holder).write().unwrap().remove(name);
+    result
+}
+
+/// Starts indexing the values of a property, indexing the values it
+/// already has. Returns whether it wasn't indexed yet. Only vertex
+/// properties can be `unique`.
+///
+/// The definition is recorded last, so if this is interrupted, the
+/// property stays unindexed, and its partial entries are cleared when it's
+/// next indexed.
+pub(crate) fn index_property(holder: &SledHolder, owner: PropertyOwner, name: &str, unique: bool) -> Result<bool> {
+    let _reindexing = holder.reindexing.lock().unwrap();
+
+    if owner.is_indexed(holder, name) {
+        return Ok(false);
+    }
+
+    let definition: &[u8] = if unique { UNIQUE_DEFINITION } else { &[] };
+
+    // Left behind if an earlier attempt was interrupted. Writes don't read
+    // them until the property is marked unique, at the start of the build.
+    if unique {
+        clear_claims(holder, name)?;
+    }
+
+    let result = build(holder, owner, name, unique, &mut |_| {}, || {
+        map_err(holder.metadata.insert(owner.definition_key(holder, name), definition))?;
+        owner.indexed(holder).write().unwrap().insert(name.to_string());
+        Ok(())
+    });
+
+    if result.is_err() && unique {
+        holder.indexed.unique.write().unwrap().remove(name);
+    }
+
+    result.map(|_| true)
+}
+
+/// Rebuilds the entries of an indexed property from its values, e.g.
+/// after they were lost or corrupted. The records of which vertex holds
+/// each value of a unique property are re-recorded rather than cleared,
+/// so that writes can't take a value while its record is missing.
+pub(crate) fn rebuild_index(
+    holder: &SledHolder,
+    owner: PropertyOwner,
+    name: &str,
+    on_progress: &mut dyn FnMut(&IndexBuildProgress),
+) -> Result<IndexBuildProgress> {
+    let _reindexing = holder.reindexing.lock().unwrap();
+
+    if !owner.is_indexed(holder, name) {
+        return Err(Error::PropertyNotIndexed { name: name.to_string() }.into());
+    }
+
+    let unique = owner == PropertyOwner::Vertex && holder.is_property_unique(name);
+    build(holder, owner, name, unique, on_progress, || Ok(()))
+}
+
+/// Stops indexing the values of a property, removing its entries. Returns
+/// whether it was indexed.
+pub(crate) fn drop_index(holder: &SledHolder, owner: PropertyOwner, name: &str) -> Result<bool> {
+    let _reindexing = holder.reindexing.lock().unwrap();
+    let _paused = holder.index_lock.write().unwrap();
+
+    if !owner.is_indexed(holder, name) {
+        return Ok(false);
+    }
+
+    // The definition goes first, so that if this is interrupted, the
+    // leftover entries are never read.
+    map_err(holder.metadata.remove(owner.definition_key(holder, name)))?;
+    owner.indexed(holder).write().unwrap().remove(name);
+
+    clear(holder, owner, name)?;
+
+    if owner == PropertyOwner::Vertex && holder.indexed.unique.write().unwrap().remove(name) {
+        clear_claims(holder, name)?;
+    }
+
+    Ok(true)
+}
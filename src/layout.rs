@@ -0,0 +1,263 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use super::datastore::SledHolder;
+use super::errors::map_err;
+use super::reindex::{self, Index};
+
+use indradb::{EdgeKey, Result, Type};
+use serde_json::Value as JsonValue;
+use uuid::Uuid;
+
+/// Orders the edge ranges of a type by a property of the edges, so that
+/// edge queries return them in that order, e.g. by weight or rank. Set with
+/// `SledConfig::with_edge_sort_key`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EdgeSortKey {
+    pub(crate) property: String,
+    descending: bool,
+    after_datetime: bool,
+}
+
+impl EdgeSortKey {
+    /// Sorts edges by the property `property`, in ascending order. Null
+    /// sorts before booleans, which sort before numbers, then strings, then
+    /// arrays and objects. Edges without the property sort last.
+    ///
+    /// By default, the property replaces the update datetime in the range
+    /// keys, so `high`/`low` bounds on queries of the type are applied by
+    /// filtering, as with untimed edge ranges.
+    pub fn new(property: &str) -> Self {
+        EdgeSortKey {
+            property: property.to_string(),
+            descending: false,
+            after_datetime: false,
+        }
+    }
+
+    /// Sorts in descending order instead. Edges without the property still
+    /// sort last.
+    pub fn descending(self) -> Self {
+        EdgeSortKey {
+            descending: true,
+            ..self
+        }
+    }
+
+    /// Keeps the update datetime in the range keys, and only sorts edges
+    /// with the same datetime by the property. Has no effect with untimed
+    /// edge ranges.
+    pub fn after_datetime(self) -> Self {
+        EdgeSortKey {
+            after_datetime: true,
+            ..self
+        }
+    }
+
+    /// Encodes a value of the property such that the encodings sort in the
+    /// same order as the values. No encoding is a prefix of another, so
+    /// whatever follows it in a key doesn't affect the order.
+    pub(crate) fn encode(&self, value: Option<&JsonValue>) -> Vec<u8> {
+        let value = match value {
+            Some(value) => value,
+            None => return vec![0xff],
+        };
+
+        let mut bytes = Vec::new();
+
+        match *value {
+            JsonValue::Null => bytes.push(1),
+            JsonValue::Bool(b) => bytes.extend_from_slice(&[2, b as u8]),
+            JsonValue::Number(ref n) => {
+                bytes.push(3);
+                bytes.extend_from_slice(&encode_number(n.as_f64().unwrap_or(0.0)).to_be_bytes());
+            }
+            JsonValue::String(ref s) => {
+                bytes.push(4);
+                escape(s.as_bytes(), &mut bytes);
+            }
+            JsonValue::Array(_) | JsonValue::Object(_) => {
+                bytes.push(5);
+                escape(value.to_string().as_bytes(), &mut bytes);
+            }
+        }
+
+        if self.descending {
+            for byte in &mut bytes {
+                *byte = !*byte;
+            }
+        }
+
+        bytes
+    }
+}
+
+/// Maps a number to an integer that sorts in the same order, by flipping
+/// the sign bit of positive numbers and every bit of negative ones.
+pub(crate) fn encode_number(f: f64) -> u64 {
+    if f.is_sign_negative() {
+        !f.to_bits()
+    } else {
+        f.to_bits() ^ (1 << 63)
+    }
+}
+
+/// Reverses `encode_number`.
+pub(crate) fn decode_number(bits: u64) -> f64 {
+    if bits & (1 << 63) != 0 {
+        f64::from_bits(bits ^ (1 << 63))
+    } else {
+        f64::from_bits(!bits)
+    }
+}
+
+/// Writes `s` terminated by two zero bytes, escaping zero bytes within it
+/// so that shorter strings sort first. Read back with
+/// `Decoder::read_escaped_string`.
+pub(crate) fn escape(s: &[u8], bytes: &mut Vec<u8>) {
+    for &byte in s {
+        if byte == 0 {
+            bytes.extend_from_slice(&[0, 0xff]);
+        } else {
+            bytes.push(byte);
+        }
+    }
+
+    bytes.extend_from_slice(&[0, 0]);
+}
+
+/// What the edge range keys of each type are made up of: the first vertex
+/// ID and the type, then the update datetime unless the type's ranges are
+/// untimed, then the encoded value of the type's sort key if it has one,
+/// and finally the second vertex ID.
+///
+/// Keeping the datetime out of the keys lets a vertex's keys of the same
+/// type share everything up to the second ID, so untimed range entries
+/// hold the datetime as their value instead, as of format version 5.
+///
+/// Edges of undirected types get a range entry from each of their vertices
+/// in both trees, so that either vertex finds them in either direction.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct EdgeRangeLayout {
+    untimed: bool,
+    untimed_types: Arc<HashSet<Type>>,
+    sort_keys: Arc<HashMap<Type, EdgeSortKey>>,
+    undirected_types: Arc<HashSet<Type>>,
+    datetime_values: Arc<AtomicBool>,
+}
+
+impl EdgeRangeLayout {
+    pub(crate) fn new(
+        untimed: bool,
+        untimed_types: &[Type],
+        sort_keys: &[(Type, EdgeSortKey)],
+        undirected_types: &[Type],
+    ) -> Self {
+        EdgeRangeLayout {
+            untimed,
+            untimed_types: Arc::new(untimed_types.iter().cloned().collect()),
+            sort_keys: Arc::new(sort_keys.iter().cloned().collect()),
+            undirected_types: Arc::new(undirected_types.iter().cloned().collect()),
+            datetime_values: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Whether untimed range entries hold the update datetime as their
+    /// value. Entries written before the datastore was upgraded to format
+    /// version 5 don't, so until then, the datetime is always looked up
+    /// from the edges tree.
+    pub(crate) fn has_datetime_values(&self) -> bool {
+        self.datetime_values.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn set_datetime_values(&self, datetime_values: bool) {
+        self.datetime_values.store(datetime_values, Ordering::Release);
+    }
+
+    pub(crate) fn sort_key(&self, t: &Type) -> Option<&EdgeSortKey> {
+        self.sort_keys.get(t)
+    }
+
+    /// Whether the range keys of a type include the update datetime.
+    pub(crate) fn is_timed(&self, t: &Type) -> bool {
+        !self.untimed
+            && !self.untimed_types.contains(t)
+            && self.sort_key(t).is_none_or(|sort_key| sort_key.after_datetime)
+    }
+
+    /// Whether the range keys of every type include the update datetime.
+    pub(crate) fn is_all_timed(&self) -> bool {
+        !self.untimed
+            && self.untimed_types.is_empty()
+            && self.sort_keys.values().all(|sort_key| sort_key.after_datetime)
+    }
+
+    /// The undirected types, in order.
+    pub(crate) fn undirected_types(&self) -> Vec<Type> {
+        let mut undirected_types: Vec<_> = self.undirected_types.iter().cloned().collect();
+        undirected_types.sort();
+        undirected_types
+    }
+
+    /// Whether edges of a type are undirected.
+    pub(crate) fn is_undirected(&self, t: &Type) -> bool {
+        self.undirected_types.contains(t)
+    }
+
+    /// Orders the vertex IDs of an edge the way it's stored. An undirected
+    /// edge is stored once, with the lower of its IDs as the outbound one,
+    /// whichever way around it's given.
+    pub(crate) fn canonical_ids(&self, outbound_id: Uuid, t: &Type, inbound_id: Uuid) -> (Uuid, Uuid) {
+        if inbound_id < outbound_id && self.is_undirected(t) {
+            (inbound_id, outbound_id)
+        } else {
+            (outbound_id, inbound_id)
+        }
+    }
+
+    /// Like `canonical_ids`, for a whole key.
+    pub(crate) fn canonical_key(&self, key: &EdgeKey) -> EdgeKey {
+        let (outbound_id, inbound_id) = self.canonical_ids(key.outbound_id, &key.t, key.inbound_id);
+        EdgeKey::new(outbound_id, key.t.clone(), inbound_id)
+    }
+
+    /// Whether every type's range keys are laid out the default way.
+    fn is_default(&self) -> bool {
+        self.untimed_types.is_empty() && self.sort_keys.is_empty() && self.undirected_types.is_empty()
+    }
+
+    /// Describes the per-type layouts, in a form that's stable across
+    /// opens. Whether all ranges are untimed and which types are undirected
+    /// aren't included, since those can't change.
+    fn describe_types(&self) -> String {
+        let mut untimed_types: Vec<_> = self.untimed_types.iter().map(|t| &t.0).collect();
+        untimed_types.sort();
+        let mut sort_keys: Vec<_> = self.sort_keys.iter().map(|(t, sort_key)| (&t.0, sort_key)).collect();
+        sort_keys.sort_by(|a, b| a.0.cmp(b.0));
+        format!("untimed: {:?}, sorted: {:?}", untimed_types, sort_keys)
+    }
+}
+
+/// Rebuilds the edge ranges if the per-type layouts have changed since the
+/// datastore was last opened.
+pub(crate) fn reindex_if_changed(holder: &SledHolder) -> Result<()> {
+    let key = holder.metadata_key("edge_range_layout");
+    let description = holder.edge_range_layout.describe_types();
+    let recorded = map_err(holder.metadata.get(&key))?;
+
+    // Datastores opened before per-type layouts existed only have default
+    // ones.
+    let unchanged = match recorded {
+        Some(ref recorded) => &recorded[..] == description.as_bytes(),
+        None => holder.edge_range_layout.is_default(),
+    };
+
+    if !unchanged {
+        reindex::reindex(holder, Index::EdgeRanges)?;
+        reindex::reindex(holder, Index::ReversedEdgeRanges)?;
+        map_err(holder.metadata.insert(key, description.as_bytes()))?;
+    }
+
+    Ok(())
+}
@@ -1,8 +1,28 @@
 //! The Sled datastore implementation.
+//!
+//! # Ordering
+//!
+//! Queries return their results in a fixed order, so that they can be
+//! paginated, and exports reproduced:
+//!
+//! * Range vertex queries return vertices by ID. Specific vertex and edge
+//!   queries return them in the order of the IDs or keys given, skipping
+//!   ones that don't exist.
+//! * Edge queries piped from vertices return each vertex's edges in turn,
+//!   in the order of the vertices. A vertex's edges are ordered by type
+//!   name, then by update datetime, newest first, then by the other
+//!   vertex's ID, whether or not the query is filtered to a type. Types
+//!   with untimed edge ranges skip the datetime, and types with a sort key
+//!   are ordered by it instead of the datetime, or after it.
+//! * Vertex queries piped from edges return vertices in the order of the
+//!   edges, once for each edge.
+//! * The properties of a vertex or edge are ordered by name, and property
+//!   queries return them in the order of their vertices or edges.
 
 #![cfg_attr(feature = "bench-suite", feature(test))]
 
 extern crate chrono;
+extern crate fs2;
 
 #[cfg(any(feature = "bench-suite", feature = "test-suite"))]
 #[macro_use]
@@ -16,11 +36,99 @@ extern crate sled;
 extern crate tempfile;
 extern crate uuid;
 
+mod access;
+mod activity;
+mod archive;
+mod atomic;
+mod audit;
+mod batch;
+mod cache;
+mod check;
+mod components;
+mod composite;
+mod conflicts;
+#[cfg(all(test, feature = "test-suite"))]
+#[macro_use]
+mod conformance;
+mod constraints;
+mod coordination;
 mod datastore;
+mod deadline;
+mod decode;
+mod dedup;
+mod degrees;
+mod derived;
+mod diff;
 mod errors;
+mod explain;
+mod export;
+mod flush;
+mod format;
+mod fulltext;
+mod history;
+mod import;
+mod indexed;
+mod layout;
+mod limits;
+mod maintenance;
 mod managers;
+mod patch;
+mod paths;
+mod precision;
+mod preflight;
+mod raw;
+mod rebuild;
+mod reclaim;
+mod recovery;
+mod reindex;
+mod rename;
+mod retry;
+mod scrub;
+mod shadow;
+mod shard;
+mod stats;
+#[cfg(all(test, feature = "test-suite"))]
+mod tests;
+mod union;
+mod validate;
+mod vectors;
+mod views;
+mod warm;
 
-pub use self::datastore::{SledConfig, SledDatastore, SledTransaction};
+pub use self::access::{AccessKind, AccessPolicy};
+pub use self::audit::AuditEntry;
+pub use self::batch::SledBatch;
+pub use self::check::ConsistencySummary;
+pub use self::constraints::{CascadePolicy, EdgeConstraints};
+pub use self::coordination::{Role, SharedDatastore};
+pub use self::datastore::{
+    Durability, InboundTypeSummary, IteratorStability, MemoryUsage, PropertyNameUsage, ReadOptions, SledConfig,
+    SledDatastore, SledTransaction, StorageMode,
+};
+pub use self::deadline::{CancellationToken, OpContext};
+pub use self::decode::{DecodeErrorPolicy, SkippedRecords};
+pub use self::diff::{diff_checkpoints, GraphChange};
+pub use self::errors::Error;
+pub use self::explain::{Access, PlanStep, PlannedQuery, QueryPlan};
+pub use self::format::FORMAT_VERSION;
+pub use self::history::SledAsOfView;
+pub use self::indexed::IndexBuildProgress;
+pub use self::layout::EdgeSortKey;
+pub use self::limits::PropertyLimits;
+pub use self::precision::DatetimePrecision;
+pub use self::preflight::PreflightCheck;
+pub use self::raw::{RawEntry, RawRecord, RawTreeAccess, RawTreeIter, TreeKind};
+pub use self::reclaim::ReclaimProgress;
+pub use self::recovery::{RecoveryInfo, SalvageAction};
+pub use self::reindex::Index;
+pub use self::retry::RetryPolicy;
+pub use self::scrub::{ScrubFinding, ScrubStats};
+pub use self::shadow::{ShadowDatastore, ShadowMismatch, ShadowReport, ShadowTransaction};
+pub use self::shard::{ShardedSledDatastore, ShardedTransaction};
+pub use self::stats::{DegreePercentiles, EdgeWriteStats, GraphStatistics};
+pub use self::union::{UnionDatastore, UnionTransaction};
+pub use self::validate::{Mutation, WriteValidator};
+pub use self::views::TraversalView;
 
 mod normal_config {
     #[cfg(feature = "bench-suite")]
@@ -38,6 +146,12 @@ mod normal_config {
         let path = tempdir().unwrap().into_path();
         SledDatastore::new(path).unwrap()
     });
+
+    #[cfg(all(test, feature = "test-suite"))]
+    sled_test_impl!({
+        use super::SledConfig;
+        SledConfig::default()
+    });
 }
 
 mod compression_config {
@@ -56,4 +170,112 @@ mod compression_config {
         let path = tempdir().unwrap().into_path();
         SledConfig::with_compression(None).open(path).unwrap()
     });
+
+    #[cfg(all(test, feature = "test-suite"))]
+    sled_test_impl!({
+        use super::SledConfig;
+        SledConfig::with_compression(None)
+    });
+}
+
+mod untimed_edge_ranges_config {
+    #[cfg(feature = "bench-suite")]
+    full_bench_impl!({
+        use super::SledConfig;
+        use tempfile::tempdir;
+        let path = tempdir().unwrap().into_path();
+        SledConfig::default().with_untimed_edge_ranges().open(path).unwrap()
+    });
+
+    #[cfg(feature = "test-suite")]
+    full_test_impl!({
+        use super::SledConfig;
+        use tempfile::tempdir;
+        let path = tempdir().unwrap().into_path();
+        SledConfig::default().with_untimed_edge_ranges().open(path).unwrap()
+    });
+
+    #[cfg(all(test, feature = "test-suite"))]
+    sled_test_impl!({
+        use super::SledConfig;
+        SledConfig::default().with_untimed_edge_ranges()
+    });
+}
+
+mod history_config {
+    #[cfg(feature = "bench-suite")]
+    full_bench_impl!({
+        use super::SledConfig;
+        use tempfile::tempdir;
+        let path = tempdir().unwrap().into_path();
+        SledConfig::default().with_history().open(path).unwrap()
+    });
+
+    #[cfg(feature = "test-suite")]
+    full_test_impl!({
+        use super::SledConfig;
+        use tempfile::tempdir;
+        let path = tempdir().unwrap().into_path();
+        SledConfig::default().with_history().open(path).unwrap()
+    });
+
+    #[cfg(all(test, feature = "test-suite"))]
+    sled_test_impl!({
+        use super::SledConfig;
+        SledConfig::default().with_history()
+    });
+}
+
+mod result_cache_config {
+    #[cfg(feature = "bench-suite")]
+    full_bench_impl!({
+        use super::SledConfig;
+        use tempfile::tempdir;
+        let path = tempdir().unwrap().into_path();
+        SledConfig::default().with_result_cache(1000).open(path).unwrap()
+    });
+
+    #[cfg(feature = "test-suite")]
+    full_test_impl!({
+        use super::SledConfig;
+        use tempfile::tempdir;
+        let path = tempdir().unwrap().into_path();
+        SledConfig::default().with_result_cache(1000).open(path).unwrap()
+    });
+
+    #[cfg(all(test, feature = "test-suite"))]
+    sled_test_impl!({
+        use super::SledConfig;
+        SledConfig::default().with_result_cache(1000)
+    });
+}
+
+mod snapshot_iterator_stability_config {
+    #[cfg(feature = "bench-suite")]
+    full_bench_impl!({
+        use super::{IteratorStability, SledConfig};
+        use tempfile::tempdir;
+        let path = tempdir().unwrap().into_path();
+        SledConfig::default()
+            .with_iterator_stability(IteratorStability::Snapshot)
+            .open(path)
+            .unwrap()
+    });
+
+    #[cfg(feature = "test-suite")]
+    full_test_impl!({
+        use super::{IteratorStability, SledConfig};
+        use tempfile::tempdir;
+        let path = tempdir().unwrap().into_path();
+        SledConfig::default()
+            .with_iterator_stability(IteratorStability::Snapshot)
+            .open(path)
+            .unwrap()
+    });
+
+    #[cfg(all(test, feature = "test-suite"))]
+    sled_test_impl!({
+        use super::{IteratorStability, SledConfig};
+        SledConfig::default().with_iterator_stability(IteratorStability::Snapshot)
+    });
 }
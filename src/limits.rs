@@ -0,0 +1,132 @@
+use super::datastore::SledHolder;
+use super::errors::Error;
+use super::managers::{EdgePropertyManager, VertexPropertyManager};
+use super::vectors;
+
+use indradb::{EdgeKey, Result};
+use serde_json::Value as JsonValue;
+use uuid::Uuid;
+
+/// Limits on the properties of each vertex and edge, which are checked
+/// whenever a property is set. Registered with
+/// `SledConfig::with_property_limits`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PropertyLimits {
+    max_value_bytes: Option<usize>,
+    max_properties: Option<u64>,
+}
+
+impl PropertyLimits {
+    /// Creates a set of limits that allows any property.
+    pub fn new() -> Self {
+        PropertyLimits::default()
+    }
+
+    /// Limits the size of a property value, as stored, i.e. encoded as
+    /// minified JSON.
+    pub fn max_value_bytes(self, max: usize) -> Self {
+        PropertyLimits {
+            max_value_bytes: Some(max),
+            ..self
+        }
+    }
+
+    /// Limits how many properties a single vertex or edge can have.
+    pub fn max_properties(self, max: u64) -> Self {
+        PropertyLimits {
+            max_properties: Some(max),
+            ..self
+        }
+    }
+}
+
+/// Checks the size of a value, and if the owner doesn't have the property
+/// yet, that adding it stays within the maximum number of properties.
+/// `pending` is the number of other properties being added to the owner
+/// along with this one.
+///
+/// Returns whether the property is new to the owner. That's only looked up,
+/// through `exists` and `count`, if there's a maximum; otherwise this
+/// returns `false`.
+fn check<C, E>(holder: &SledHolder, name: &str, value: &JsonValue, pending: u64, count: C, exists: E) -> Result<bool>
+where
+    C: FnOnce() -> Result<u64>,
+    E: FnOnce() -> Result<bool>,
+{
+    let limits = &holder.property_limits;
+
+    if let Some(max) = limits.max_value_bytes {
+        let size = serde_json::to_vec(value)?.len();
+
+        if size > max {
+            return Err(Error::PropertyValueTooLarge {
+                name: name.to_string(),
+                size,
+                max,
+            }
+            .into());
+        }
+    }
+
+    let max = match limits.max_properties {
+        Some(max) => max,
+        None => return Ok(false),
+    };
+
+    if exists()? {
+        return Ok(false);
+    }
+
+    if count()? + pending >= max {
+        return Err(Error::TooManyProperties {
+            name: name.to_string(),
+            max,
+        }
+        .into());
+    }
+
+    Ok(true)
+}
+
+/// Checks that setting a vertex property stays within the limits. See
+/// `check` for `pending` and the returned value.
+pub(crate) fn check_vertex_property(
+    holder: &SledHolder,
+    id: Uuid,
+    name: &str,
+    value: &JsonValue,
+    pending: u64,
+) -> Result<bool> {
+    vectors::check_value(holder, name, value)?;
+    let manager = VertexPropertyManager::new(holder);
+
+    check(
+        holder,
+        name,
+        value,
+        pending,
+        || manager.count_for_owner(id),
+        || Ok(manager.get(id, name)?.is_some()),
+    )
+}
+
+/// Checks that setting an edge property stays within the limits. See
+/// `check` for `pending` and the returned value.
+pub(crate) fn check_edge_property(
+    holder: &SledHolder,
+    key: &EdgeKey,
+    name: &str,
+    value: &JsonValue,
+    pending: u64,
+) -> Result<bool> {
+    let manager = EdgePropertyManager::new(holder);
+
+    check(
+        holder,
+        name,
+        value,
+        pending,
+        || manager.count_for_owner(key.outbound_id, &key.t, key.inbound_id),
+        || Ok(manager.get(key.outbound_id, &key.t, key.inbound_id, name)?.is_some()),
+    )
+}
@@ -0,0 +1,270 @@
+use std::ops::Bound;
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::thread;
+use std::time::Duration;
+
+use super::datastore::SledHolder;
+use super::dedup;
+use super::errors::map_err;
+use super::managers::{EdgeManager, EdgeTypeManager};
+use super::scrub;
+
+use chrono::offset::Utc;
+use chrono::DateTime;
+use indradb::{Result, Type};
+use serde_json::Value as JsonValue;
+use uuid::Uuid;
+
+/// The maximum number of edges deleted per retention batch.
+const RETENTION_BATCH_SIZE: usize = 1000;
+
+/// How many property values are compacted between saves of the cursor.
+const COMPACTION_BATCH_SIZE: u64 = 1000;
+
+/// Where an interrupted pass of property compaction resumes: the index of
+/// the tree in `property_trees`, followed by the last key compacted.
+const COMPACTION_CURSOR_KEY: &str = "property_compaction_cursor";
+
+/// Owns a background maintenance thread, which runs a task on an interval.
+/// Dropping the handle signals the thread to stop after its current batch.
+pub(crate) struct MaintenanceHandle {
+    shutdown: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl MaintenanceHandle {
+    /// Spawns a thread that runs `task` every `interval`. The task is passed
+    /// a function that returns whether it should stop early.
+    pub(crate) fn spawn<F>(holder: &Arc<SledHolder>, interval: Duration, task: F) -> Self
+    where
+        F: Fn(&SledHolder, &dyn Fn() -> bool) + Send + 'static,
+    {
+        let shutdown = Arc::new((Mutex::new(false), Condvar::new()));
+        let thread_shutdown = shutdown.clone();
+        let thread_holder = Arc::downgrade(holder);
+
+        thread::spawn(move || run(thread_holder, thread_shutdown, interval, task));
+
+        MaintenanceHandle { shutdown }
+    }
+
+    /// Spawns a thread that prunes expired edges, and compacts up to
+    /// `compaction_limit` property values per run, if it's set.
+    pub(crate) fn spawn_maintenance(
+        holder: &Arc<SledHolder>,
+        interval: Duration,
+        compaction_limit: Option<u64>,
+    ) -> Self {
+        // Errors are not fatal to the thread; whatever failed will be
+        // retried on the next run.
+        Self::spawn(holder, interval, move |holder, should_stop| {
+            let _ = prune_expired_edges_until(holder, should_stop);
+
+            if let Some(limit) = compaction_limit {
+                if !should_stop() {
+                    let _ = compact_property_values_until(holder, limit, should_stop);
+                }
+            }
+        })
+    }
+
+    /// Spawns a thread that verifies up to `records_per_run` records per
+    /// run.
+    pub(crate) fn spawn_scrubber(holder: &Arc<SledHolder>, interval: Duration, records_per_run: u64) -> Self {
+        Self::spawn(holder, interval, move |holder, should_stop| {
+            scrub::scrub_until(holder, records_per_run, should_stop);
+        })
+    }
+
+    /// Spawns a thread that flushes the database.
+    pub(crate) fn spawn_flusher(holder: &Arc<SledHolder>, interval: Duration) -> Self {
+        Self::spawn(holder, interval, |holder, _| {
+            let _ = holder.flush();
+        })
+    }
+}
+
+impl Drop for MaintenanceHandle {
+    fn drop(&mut self) {
+        let (lock, cvar) = &*self.shutdown;
+        *lock.lock().unwrap() = true;
+        cvar.notify_all();
+    }
+}
+
+fn is_shut_down(shutdown: &(Mutex<bool>, Condvar)) -> bool {
+    *shutdown.0.lock().unwrap()
+}
+
+fn run<F>(holder: Weak<SledHolder>, shutdown: Arc<(Mutex<bool>, Condvar)>, interval: Duration, task: F)
+where
+    F: Fn(&SledHolder, &dyn Fn() -> bool),
+{
+    loop {
+        {
+            let (lock, cvar) = &*shutdown;
+            let guard = lock.lock().unwrap();
+            let (guard, _) = cvar.wait_timeout_while(guard, interval, |stopped| !*stopped).unwrap();
+            if *guard {
+                return;
+            }
+        }
+
+        // Only hold a strong reference while working, so that the
+        // maintenance thread never keeps a dropped datastore open.
+        let holder = match holder.upgrade() {
+            Some(holder) => holder,
+            None => return,
+        };
+
+        task(&holder, &|| is_shut_down(&shutdown));
+    }
+}
+
+/// Deletes edges whose update datetime is older than the retention rule for
+/// their type allows. Returns the number of edges deleted. Edges written
+/// while indexing is deferred aren't found until it's finished.
+pub(crate) fn prune_expired_edges(holder: &SledHolder) -> Result<u64> {
+    prune_expired_edges_until(holder, &|| false)
+}
+
+fn prune_expired_edges_until(holder: &SledHolder, should_stop: &dyn Fn() -> bool) -> Result<u64> {
+    if holder.edge_retention.is_empty() {
+        return Ok(0);
+    }
+
+    let now = Utc::now();
+    let edge_manager = EdgeManager::new(holder);
+    let edge_type_manager = EdgeTypeManager::new(holder);
+    let mut batch: Vec<(Uuid, Type, Uuid)> = Vec::with_capacity(RETENTION_BATCH_SIZE);
+    let mut pruned = 0;
+
+    for (t, max_age) in &holder.edge_retention {
+        let cutoff = now - *max_age;
+
+        // The edge type index lists the edges of a type newest first, so
+        // the expired ones are those from the cutoff on. Deleting as we go
+        // is fine, since sled iterators don't hold locks.
+        for item in edge_type_manager.iterate_for_type(t, Some(cutoff)) {
+            let (key, update_datetime) = item?;
+
+            if update_datetime < cutoff {
+                batch.push((key.outbound_id, key.t, key.inbound_id));
+            }
+
+            if batch.len() == RETENTION_BATCH_SIZE {
+                pruned += delete_batch(&edge_manager, cutoff, &mut batch)?;
+
+                if should_stop() {
+                    return Ok(pruned);
+                }
+            }
+        }
+
+        pruned += delete_batch(&edge_manager, cutoff, &mut batch)?;
+    }
+
+    Ok(pruned)
+}
+
+/// Deletes the edges in `batch` that are still older than `cutoff`. Their
+/// update datetimes are read again under the write guard, since an edge may
+/// have been refreshed or deleted since it was found.
+fn delete_batch(edge_manager: &EdgeManager, cutoff: DateTime<Utc>, batch: &mut Vec<(Uuid, Type, Uuid)>) -> Result<u64> {
+    if batch.is_empty() {
+        return Ok(0);
+    }
+
+    let guard = edge_manager.holder.write_guard();
+    let mut count = 0;
+
+    for (outbound_id, t, inbound_id) in batch.drain(..) {
+        match edge_manager.get(outbound_id, &t, inbound_id)? {
+            Some(update_datetime) if update_datetime < cutoff => {
+                guard.touch(&[outbound_id, inbound_id]);
+                edge_manager.delete(outbound_id, &t, inbound_id, update_datetime)?;
+                count += 1;
+            }
+            _ => {}
+        }
+    }
+
+    Ok(count)
+}
+
+fn property_trees(holder: &SledHolder) -> [&sled::Tree; 2] {
+    [&holder.vertex_properties, &holder.edge_properties]
+}
+
+/// Rewrites property values that aren't stored in the canonical encoding -
+/// minified JSON, as `serde_json` writes it - e.g. because they were
+/// written by an older version or another tool. Returns the number of
+/// values rewritten.
+pub(crate) fn compact_property_values(holder: &SledHolder) -> Result<u64> {
+    compact_property_values_until(holder, u64::MAX, &|| false)
+}
+
+/// Compacts property values, starting from where the last pass left off,
+/// until `limit` values have been checked or `should_stop` says so.
+fn compact_property_values_until(holder: &SledHolder, limit: u64, should_stop: &dyn Fn() -> bool) -> Result<u64> {
+    let cursor_key = holder.metadata_key(COMPACTION_CURSOR_KEY);
+
+    let (mut tree_index, mut last_key) = match map_err(holder.metadata.get(&cursor_key))? {
+        Some(cursor) => match cursor.split_first() {
+            Some((&tree_index, last_key)) => (tree_index as usize, Some(last_key.to_vec())),
+            None => (0, None),
+        },
+        None => (0, None),
+    };
+
+    let trees = property_trees(holder);
+    let mut checked = 0;
+    let mut rewritten = 0;
+
+    while tree_index < trees.len() {
+        let tree = trees[tree_index];
+
+        let start = match last_key.take() {
+            Some(key) => Bound::Excluded(key),
+            None => Bound::Unbounded,
+        };
+
+        for item in tree.range::<Vec<u8>, _>((start, Bound::Unbounded)) {
+            let (k, v) = map_err(item)?;
+
+            // Values in the value store were added by this crate, so only
+            // those stored inline may need rewriting.
+            if !dedup::is_reference(&v) {
+                let value: JsonValue = serde_json::from_slice(&v)?;
+                let canonical = serde_json::to_vec(&value)?;
+
+                // A value that was changed since it was read was written by
+                // this crate, so it's already canonical.
+                if canonical != v.as_ref()
+                    && holder
+                        .retrier
+                        .run(|| tree.compare_and_swap(&k, Some(&v), Some(canonical.as_slice())))?
+                        .is_ok()
+                {
+                    rewritten += 1;
+                }
+            }
+
+            checked += 1;
+
+            if checked % COMPACTION_BATCH_SIZE == 0 || checked == limit {
+                let mut cursor = vec![tree_index as u8];
+                cursor.extend_from_slice(&k);
+                map_err(holder.metadata.insert(&cursor_key, cursor))?;
+
+                if checked == limit || should_stop() {
+                    return Ok(rewritten);
+                }
+            }
+        }
+
+        tree_index += 1;
+    }
+
+    map_err(holder.metadata.remove(&cursor_key))?;
+    Ok(rewritten)
+}
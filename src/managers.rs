@@ -1,22 +1,74 @@
-use std::io::Cursor;
-use std::ops::Deref;
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
 use std::u8;
 
-use super::errors::map_err;
+use super::activity;
+use super::atomic::MultiBatch;
+use super::composite;
+use super::constraints;
+use super::deadline::Deadline;
+use super::decode::{corruption, DecodeErrors, Decoder};
+use super::dedup;
+use super::degrees;
+use super::errors::{map_err, Error};
+use super::fulltext;
+use super::layout::{decode_number, encode_number, escape, EdgeRangeLayout};
+use super::patch;
+use super::precision::DatetimePrecision;
+use super::reindex::IndexWriter;
+use super::retry::Retrier;
+use super::stats;
+use super::vectors;
+use super::views;
 use crate::datastore::SledHolder;
 
 use chrono::offset::Utc;
 use chrono::DateTime;
-use indradb::{util, Result, Type, Vertex};
-use serde_json::Value as JsonValue;
+use indradb::{util, EdgeDirection, EdgeKey, Error as IndradbError, NamedProperty, Result, Type, Vertex};
+use serde_json::{Map as JsonMap, Value as JsonValue};
+use sled::transaction::{
+    ConflictableTransactionError, ConflictableTransactionResult, TransactionError, Transactional, TransactionalTree,
+};
 use sled::Result as SledResult;
-use sled::{IVec, Iter as DbIterator, Tree};
+use sled::{CompareAndSwapError, IVec, Iter as DbIterator, Tree};
 use uuid::Uuid;
 
 pub type OwnedPropertyItem = ((Uuid, String), JsonValue);
 pub type VertexItem = (Uuid, Type);
 pub type EdgeRangeItem = (Uuid, Type, DateTime<Utc>, Uuid);
 pub type EdgePropertyItem = ((Uuid, Type, Uuid, String), JsonValue);
+type PatchChanges<'p> = Vec<(&'p String, Option<IVec>, Option<Vec<u8>>, Option<Vec<u8>>)>;
+type ResolvedReferences = HashMap<Vec<u8>, (IVec, Vec<u8>)>;
+
+/// Encodes a number for the number index. Zero and negative zero are
+/// equal, so they share an encoding.
+fn encode_indexed_number(number: f64) -> u64 {
+    encode_number(if number == 0.0 { 0.0 } else { number })
+}
+
+/// Converts a range of numbers to the inclusive range of their number
+/// index encodings, or `None` if the range is empty.
+fn encoded_number_bounds<R: RangeBounds<f64>>(range: &R) -> Option<(u64, u64)> {
+    let low = match range.start_bound() {
+        Bound::Included(&low) => Some(encode_indexed_number(low)),
+        Bound::Excluded(&low) => encode_indexed_number(low).checked_add(1),
+        Bound::Unbounded => Some(0),
+    };
+    let high = match range.end_bound() {
+        Bound::Included(&high) => Some(encode_indexed_number(high)),
+        Bound::Excluded(&high) => encode_indexed_number(high).checked_sub(1),
+        Bound::Unbounded => Some(u64::MAX),
+    };
+
+    match (low, high) {
+        (Some(low), Some(high)) if low <= high => Some((low, high)),
+        _ => None,
+    }
+}
 
 fn take_while_prefixed(iterator: DbIterator, prefix: Vec<u8>) -> impl Iterator<Item = SledResult<(IVec, IVec)>> {
     iterator.take_while(move |item| -> bool {
@@ -27,6 +79,21 @@ fn take_while_prefixed(iterator: DbIterator, prefix: Vec<u8>) -> impl Iterator<I
     })
 }
 
+/// Gets the smallest key that's greater than every key starting with
+/// `prefix`, or `None` if there isn't one.
+fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+
+    while let Some(byte) = end.pop() {
+        if byte < u8::MAX {
+            end.push(byte + 1);
+            return Some(end);
+        }
+    }
+
+    None
+}
+
 pub struct VertexManager<'db: 'tree, 'tree> {
     pub holder: &'db SledHolder,
     pub tree: &'tree Tree,
@@ -36,7 +103,7 @@ impl<'db: 'tree, 'tree> VertexManager<'db, 'tree> {
     pub fn new(ds: &'db SledHolder) -> Self {
         VertexManager {
             holder: ds,
-            tree: ds.db.deref(),
+            tree: &ds.vertices,
         }
     }
 
@@ -45,65 +112,285 @@ impl<'db: 'tree, 'tree> VertexManager<'db, 'tree> {
     }
 
     pub fn exists(&self, id: Uuid) -> Result<bool> {
-        Ok(map_err(self.tree.get(&self.key(id)))?.is_some())
+        Ok(self.holder.retrier.run(|| self.tree.get(self.key(id)))?.is_some())
+    }
+
+    /// Checks whether several vertices exist, in the order of `ids`. As
+    /// with `EdgeManager::get_many`, the lookups are made in key order.
+    pub fn exists_many(&self, ids: &[Uuid]) -> Result<Vec<bool>> {
+        let mut sorted: Vec<(Uuid, usize)> = ids.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+        sorted.sort();
+
+        let mut results = vec![false; ids.len()];
+        let mut last: Option<(Uuid, bool)> = None;
+
+        for (id, i) in sorted {
+            let exists = match last {
+                // Duplicate IDs are only looked up once.
+                Some((last_id, exists)) if last_id == id => exists,
+                _ => self.holder.retrier.run(|| self.tree.contains_key(self.key(id)))?,
+            };
+
+            results[i] = exists;
+            last = Some((id, exists));
+        }
+
+        Ok(results)
     }
 
     pub fn get(&self, id: Uuid) -> Result<Option<Type>> {
-        match map_err(self.tree.get(&self.key(id)))? {
-            Some(value_bytes) => {
-                let mut cursor = Cursor::new(value_bytes.deref());
-                Ok(Some(util::read_type(&mut cursor)))
-            }
+        let key = self.key(id);
+
+        match self.holder.retrier.run(|| self.tree.get(&key))? {
+            Some(value_bytes) => Ok(Some(Decoder::value(self.tree, &key, &value_bytes).read_type()?)),
             None => Ok(None),
         }
     }
 
-    fn iterate(&self, iterator: DbIterator) -> impl Iterator<Item = Result<VertexItem>> + '_ {
-        iterator.map(move |item| -> Result<VertexItem> {
-            let (k, v) = map_err(item)?;
+    /// Gets when a vertex was created. This is `None` for vertices that
+    /// don't exist, and for those created before creation datetimes were
+    /// tracked.
+    pub fn get_created_datetime(&self, id: Uuid) -> Result<Option<DateTime<Utc>>> {
+        let key = self.key(id);
 
-            let id = {
-                debug_assert_eq!(k.len(), 16);
-                let mut cursor = Cursor::new(k);
-                util::read_uuid(&mut cursor)
-            };
+        match self.holder.retrier.run(|| self.tree.get(&key))? {
+            Some(value_bytes) => Ok(read_vertex_value(self.tree, &key, &value_bytes)?.1),
+            None => Ok(None),
+        }
+    }
 
-            let mut cursor = Cursor::new(v);
-            let t = util::read_type(&mut cursor);
-            Ok((id, t))
-        })
+    // The iterators only borrow the tree and holder, not the manager, so
+    // that they can outlive it.
+    fn iterate(&self, iterator: DbIterator) -> impl Iterator<Item = Result<VertexItem>> + 'tree {
+        let (holder, tree) = (self.holder, self.tree);
+
+        iterator
+            .map(move |item| -> Result<VertexItem> {
+                let (k, v) = map_err(item)?;
+
+                let mut decoder = Decoder::key(tree, &k);
+                let id = decoder.read_uuid()?;
+                if !decoder.is_empty() {
+                    return Err(decoder.corruption());
+                }
+
+                let t = Decoder::value(tree, &k, &v).read_type()?;
+                Ok((id, t))
+            })
+            .filter_map(move |item| holder.decode_errors.filter(item))
     }
 
-    pub fn iterate_for_range(&self, id: Uuid) -> impl Iterator<Item = Result<VertexItem>> + '_ {
+    pub fn iterate_for_range(&self, id: Uuid) -> impl Iterator<Item = Result<VertexItem>> + 'tree {
         let low_key = util::build(&[util::Component::Uuid(id)]);
         let low_key_bytes: &[u8] = low_key.as_ref();
         let iter = self.tree.range(low_key_bytes..);
         self.iterate(iter)
     }
 
+    fn value(t: &Type, created_datetime: DateTime<Utc>) -> Vec<u8> {
+        // The creation datetime trails the type, so readers that only care
+        // about the type can ignore it.
+        util::build(&[util::Component::Type(t), util::Component::DateTime(created_datetime)])
+    }
+
     pub fn create(&self, vertex: &Vertex) -> Result<()> {
+        self.create_at(vertex, Utc::now())
+    }
+
+    /// Creates a vertex with the given creation datetime, e.g. to restore
+    /// one that was archived.
+    pub fn create_at(&self, vertex: &Vertex, created_datetime: DateTime<Utc>) -> Result<()> {
         let key = self.key(vertex.id);
-        map_err(self.tree.insert(&key, util::build(&[util::Component::Type(&vertex.t)])))?;
+        let value = Self::value(&vertex.t, created_datetime);
+        let old_value = self.holder.retrier.run(|| self.tree.get(&key))?;
+
+        // The vertex is written in a single transaction with its creation
+        // index entry and history.
+        let mut batch = MultiBatch::default();
+        batch.insert(self.tree, key.as_slice(), value.as_slice());
+
+        // The creation index is rebuilt wholesale once deferred indexing
+        // finishes.
+        if !self.holder.is_indexing_deferred() {
+            let vertex_creation_manager = VertexCreationManager::new(self.holder);
+
+            // Overwriting an existing vertex (e.g. via bulk inserts) must
+            // not leave its old creation entry behind.
+            if let Some(ref old_value) = old_value {
+                if let Some(old_key) = vertex_creation_manager.key_for_value(vertex.id, old_value)? {
+                    batch.remove_index(&vertex_creation_manager.tree, old_key);
+                }
+            }
+
+            batch.insert_index(
+                &vertex_creation_manager.tree,
+                vertex_creation_manager.key(&vertex.t, created_datetime, vertex.id),
+                &[],
+            );
+        }
+
+        if self.holder.history.enabled {
+            HistoryManager::new(&self.holder.retrier, &self.holder.history.vertices).stage_record(
+                &mut batch,
+                &key,
+                Utc::now(),
+                Some(&value),
+            );
+        }
+
+        batch.apply(self.holder)?;
+        let catalog_manager = CatalogManager::new(self.holder);
+
+        if let Some(ref old_value) = old_value {
+            let (old_t, _) = read_vertex_value(self.tree, &key, old_value)?;
+            catalog_manager.decrement(CatalogKind::VertexType, old_t.0.as_bytes())?;
+        }
+
+        catalog_manager.increment(CatalogKind::VertexType, vertex.t.0.as_bytes())?;
+
+        views::on_vertex_change(self.holder, vertex.id)?;
+        self.holder.notify_mutation()?;
+
+        Ok(())
+    }
+
+    /// Creates several vertices. Unlike calling `create` for each vertex,
+    /// the writes are grouped into a single sled transaction, along with
+    /// those already in `batch`. None of the vertices may exist yet, and
+    /// `vertices` must not contain duplicates.
+    pub(crate) fn create_many(&self, vertices: &[Vertex], mut batch: MultiBatch) -> Result<()> {
+        let created_datetime = Utc::now();
+        let vertex_creation_manager = VertexCreationManager::new(self.holder);
+        let history_manager = HistoryManager::new(&self.holder.retrier, &self.holder.history.vertices);
+        let update_creations = !self.holder.is_indexing_deferred();
+
+        let mut new_vertices_per_type: HashMap<&Type, i64> = HashMap::new();
+
+        for vertex in vertices {
+            let key = self.key(vertex.id);
+            let value = Self::value(&vertex.t, created_datetime);
+            batch.insert(self.tree, key.as_slice(), value.as_slice());
+
+            if update_creations {
+                batch.insert_index(
+                    &vertex_creation_manager.tree,
+                    vertex_creation_manager.key(&vertex.t, created_datetime, vertex.id),
+                    &[],
+                );
+            }
+
+            if self.holder.history.enabled {
+                history_manager.stage_record(&mut batch, &key, created_datetime, Some(&value));
+            }
+
+            *new_vertices_per_type.entry(&vertex.t).or_insert(0) += 1;
+        }
+
+        batch.apply(self.holder)?;
+
+        let catalog_manager = CatalogManager::new(self.holder);
+        for (t, count) in new_vertices_per_type {
+            catalog_manager.adjust(CatalogKind::VertexType, t.0.as_bytes(), count)?;
+        }
+
+        for vertex in vertices {
+            views::on_vertex_change(self.holder, vertex.id)?;
+        }
+
+        self.holder.notify_mutation()?;
+
         Ok(())
     }
 
-    pub fn delete(&self, id: Uuid) -> Result<()> {
-        map_err(self.tree.remove(&self.key(id)))?;
+    /// Deletes a vertex along with its properties and edges. The deadline
+    /// is checked before each property and edge is deleted.
+    pub fn delete(&self, id: Uuid, deadline: &Deadline) -> Result<()> {
+        self.delete_with(id, true, deadline)
+    }
+
+    /// Deletes a vertex along with its properties, leaving its edges in
+    /// place, for `CascadePolicy::Detach`.
+    pub fn delete_detached(&self, id: Uuid, deadline: &Deadline) -> Result<()> {
+        self.delete_with(id, false, deadline)
+    }
+
+    fn delete_with(&self, id: Uuid, cascade: bool, deadline: &Deadline) -> Result<()> {
+        let key = self.key(id);
+        let old_value = self.holder.retrier.run(|| self.tree.get(&key))?;
+
+        // The vertex is removed in a single transaction with its creation
+        // index entry and history. Its properties and edges are then
+        // removed one at a time, each in a transaction of its own.
+        let mut batch = MultiBatch::default();
+
+        if let Some(ref old_value) = old_value {
+            let vertex_creation_manager = VertexCreationManager::new(self.holder);
+            batch.remove(self.tree, key.as_slice());
+
+            if let Some(old_key) = vertex_creation_manager.key_for_value(id, old_value)? {
+                batch.remove_index(&vertex_creation_manager.tree, old_key);
+            }
+        }
+
+        if self.holder.history.enabled {
+            HistoryManager::new(&self.holder.retrier, &self.holder.history.vertices).stage_record(
+                &mut batch,
+                &key,
+                Utc::now(),
+                None,
+            );
+        }
+
+        batch.apply(self.holder)?;
+
+        if let Some(ref old_value) = old_value {
+            let (old_t, _) = read_vertex_value(self.tree, &key, old_value)?;
+            CatalogManager::new(self.holder).decrement(CatalogKind::VertexType, old_t.0.as_bytes())?;
+        }
 
-        let vertex_property_manager = VertexPropertyManager::new(&self.holder.vertex_properties);
+        let vertex_property_manager = VertexPropertyManager::new(self.holder);
         for item in vertex_property_manager.iterate_for_owner(id)? {
             let ((vertex_property_owner_id, vertex_property_name), _) = item?;
+            deadline.tick()?;
             vertex_property_manager.delete(vertex_property_owner_id, &vertex_property_name[..])?;
         }
 
+        if !cascade {
+            views::on_vertex_change(self.holder, id)?;
+            return self.holder.notify_mutation();
+        }
+
         let edge_manager = EdgeManager::new(self.holder);
+        let edge_property_manager = EdgePropertyManager::new(self.holder);
+
+        // The properties of the vertex's edges are found up front with the
+        // per-vertex index, so that each edge can be deleted without
+        // scanning for its properties. The index isn't maintained during a
+        // bulk load, in which case each edge is scanned as it's deleted.
+        let mut edge_properties = if self.holder.is_bulk_loading() {
+            None
+        } else {
+            Some(edge_property_manager.properties_of_vertex(id)?)
+        };
+
+        let mut delete_edge =
+            |outbound_id: Uuid, t: &Type, inbound_id: Uuid, update_datetime: DateTime<Utc>| match edge_properties {
+                Some(ref mut edge_properties) => {
+                    let key = EdgeKey::new(outbound_id, t.clone(), inbound_id);
+                    let names = edge_properties
+                        .remove(&self.holder.edge_range_layout.canonical_key(&key))
+                        .unwrap_or_default();
+                    edge_manager.delete_with_properties(outbound_id, t, inbound_id, update_datetime, &names)
+                }
+                None => edge_manager.delete(outbound_id, t, inbound_id, update_datetime),
+            };
 
         {
             let edge_range_manager = EdgeRangeManager::new(self.holder);
             for item in edge_range_manager.iterate_for_owner(id) {
                 let (edge_range_outbound_id, edge_range_t, edge_range_update_datetime, edge_range_inbound_id) = item?;
-                debug_assert_eq!(edge_range_outbound_id, id);
-                edge_manager.delete(
+                deadline.tick()?;
+                delete_edge(
                     edge_range_outbound_id,
                     &edge_range_t,
                     edge_range_inbound_id,
@@ -121,8 +408,8 @@ impl<'db: 'tree, 'tree> VertexManager<'db, 'tree> {
                     reversed_edge_range_update_datetime,
                     reversed_edge_range_outbound_id,
                 ) = item?;
-                debug_assert_eq!(reversed_edge_range_inbound_id, id);
-                edge_manager.delete(
+                deadline.tick()?;
+                delete_edge(
                     reversed_edge_range_outbound_id,
                     &reversed_edge_range_t,
                     reversed_edge_range_inbound_id,
@@ -130,319 +417,3293 @@ impl<'db: 'tree, 'tree> VertexManager<'db, 'tree> {
                 )?;
             }
         }
+
+        // Inbound edges are normally found through the reversed edge ranges,
+        // but those aren't maintained while indexing is deferred. Any that
+        // were missed but have properties are still left in the per-vertex
+        // index, or, during a bulk load, can be found through the inbound
+        // edge property index.
+        match edge_properties {
+            Some(edge_properties) => {
+                for (key, names) in edge_properties {
+                    deadline.tick()?;
+
+                    match edge_manager.get(key.outbound_id, &key.t, key.inbound_id)? {
+                        Some(update_datetime) => edge_manager.delete_with_properties(
+                            key.outbound_id,
+                            &key.t,
+                            key.inbound_id,
+                            update_datetime,
+                            &names,
+                        )?,
+                        None => {
+                            for name in names {
+                                edge_property_manager.delete(key.outbound_id, &key.t, key.inbound_id, &name)?;
+                            }
+                        }
+                    }
+                }
+            }
+            None => {
+                for item in edge_property_manager.iterate_for_inbound(id) {
+                    let ((outbound_id, t, inbound_id, name), _) = item?;
+                    deadline.tick()?;
+
+                    match edge_manager.get(outbound_id, &t, inbound_id)? {
+                        Some(update_datetime) => edge_manager.delete(outbound_id, &t, inbound_id, update_datetime)?,
+                        None => edge_property_manager.delete(outbound_id, &t, inbound_id, &name)?,
+                    }
+                }
+            }
+        }
+
+        views::on_vertex_change(self.holder, id)?;
+        self.holder.notify_mutation()?;
         Ok(())
     }
 }
 
-pub struct EdgeManager<'db: 'tree, 'tree> {
-    pub holder: &'db SledHolder,
-    pub tree: &'tree Tree,
+/// Reads the type of a vertex value, along with the creation datetime that
+/// trails it, if there is one.
+pub(crate) fn read_vertex_value(tree: &Tree, key: &[u8], value_bytes: &[u8]) -> Result<(Type, Option<DateTime<Utc>>)> {
+    let mut decoder = Decoder::value(tree, key, value_bytes);
+    let t = decoder.read_type()?;
+
+    if decoder.is_empty() {
+        Ok((t, None))
+    } else {
+        Ok((t, Some(decoder.read_datetime()?)))
+    }
 }
 
-impl<'db, 'tree> EdgeManager<'db, 'tree> {
-    pub fn new(ds: &'db SledHolder) -> Self {
-        EdgeManager {
-            holder: ds,
-            tree: &ds.edges,
+/// Indexes vertices by `(type, created datetime, id)`, so the most recently
+/// created vertices of a type can be found without a full scan.
+pub struct VertexCreationManager<'tree> {
+    pub tree: IndexWriter,
+    vertices: &'tree Tree,
+    retrier: &'tree Retrier,
+    decode_errors: Arc<DecodeErrors>,
+}
+
+impl<'tree> VertexCreationManager<'tree> {
+    pub fn new<'db: 'tree>(ds: &'db SledHolder) -> Self {
+        VertexCreationManager {
+            tree: ds.vertex_creations.writer(),
+            vertices: &ds.vertices,
+            retrier: &ds.retrier,
+            decode_errors: ds.decode_errors.clone(),
         }
     }
 
-    fn key(&self, outbound_id: Uuid, t: &Type, inbound_id: Uuid) -> Vec<u8> {
+    fn key(&self, t: &Type, created_datetime: DateTime<Utc>, id: Uuid) -> Vec<u8> {
         util::build(&[
-            util::Component::Uuid(outbound_id),
             util::Component::Type(t),
-            util::Component::Uuid(inbound_id),
+            util::Component::DateTime(created_datetime),
+            util::Component::Uuid(id),
         ])
     }
 
-    pub fn get(&self, outbound_id: Uuid, t: &Type, inbound_id: Uuid) -> Result<Option<DateTime<Utc>>> {
-        match map_err(self.tree.get(self.key(outbound_id, t, inbound_id)))? {
-            Some(value_bytes) => {
-                let mut cursor = Cursor::new(value_bytes.deref());
-                Ok(Some(util::read_datetime(&mut cursor)))
-            }
-            None => Ok(None),
-        }
+    /// Iterates over the IDs of vertices of the given type, most recently
+    /// created first.
+    pub fn iterate_for_type(&self, t: &Type) -> impl Iterator<Item = Result<Uuid>> {
+        let prefix = util::build(&[util::Component::Type(t)]);
+        let prefix_len = prefix.len();
+        let tree = self.tree.clone();
+        let decode_errors = self.decode_errors.clone();
+
+        self.tree
+            .scan_prefix(&prefix)
+            .map(move |item| -> Result<Uuid> {
+                let (k, _) = map_err(item)?;
+                let mut decoder = Decoder::key(&tree, &k);
+                decoder.skip(prefix_len + 8)?;
+                decoder.read_uuid()
+            })
+            .filter_map(move |item| decode_errors.filter(item))
     }
 
-    pub fn set(&self, outbound_id: Uuid, t: &Type, inbound_id: Uuid, new_update_datetime: DateTime<Utc>) -> Result<()> {
-        let edge_range_manager = EdgeRangeManager::new(self.holder);
-        let reversed_edge_range_manager = EdgeRangeManager::new_reversed(self.holder);
+    pub fn set(&self, t: &Type, created_datetime: DateTime<Utc>, id: Uuid) -> Result<()> {
+        self.retrier
+            .run(|| self.tree.insert(self.key(t, created_datetime, id), &[]))?;
+        Ok(())
+    }
 
-        if let Some(update_datetime) = self.get(outbound_id, t, inbound_id)? {
-            edge_range_manager.delete(outbound_id, t, update_datetime, inbound_id)?;
-            reversed_edge_range_manager.delete(inbound_id, t, update_datetime, outbound_id)?;
+    /// Sets the entry for a vertex, given its value in the vertices tree.
+    /// Vertices without a recorded creation datetime aren't indexed.
+    pub fn set_for_value(&self, id: Uuid, value_bytes: &[u8]) -> Result<()> {
+        if let (t, Some(created_datetime)) = read_vertex_value(self.vertices, id.as_bytes(), value_bytes)? {
+            self.set(&t, created_datetime, id)?;
         }
 
-        let key = self.key(outbound_id, t, inbound_id);
-        map_err(
-            self.tree
-                .insert(key, util::build(&[util::Component::DateTime(new_update_datetime)])),
-        )?;
-        edge_range_manager.set(outbound_id, t, new_update_datetime, inbound_id)?;
-        reversed_edge_range_manager.set(inbound_id, t, new_update_datetime, outbound_id)?;
         Ok(())
     }
 
-    pub fn delete(&self, outbound_id: Uuid, t: &Type, inbound_id: Uuid, update_datetime: DateTime<Utc>) -> Result<()> {
-        map_err(self.tree.remove(&self.key(outbound_id, t, inbound_id)))?;
-
-        let edge_range_manager = EdgeRangeManager::new(self.holder);
-        edge_range_manager.delete(outbound_id, t, update_datetime, inbound_id)?;
-
-        let reversed_edge_range_manager = EdgeRangeManager::new_reversed(self.holder);
-        reversed_edge_range_manager.delete(inbound_id, t, update_datetime, outbound_id)?;
-
-        let edge_property_manager = EdgePropertyManager::new(&self.holder.edge_properties);
-        for item in edge_property_manager.iterate_for_owner(outbound_id, t, inbound_id)? {
-            let ((edge_property_outbound_id, edge_property_t, edge_property_inbound_id, edge_property_name), _) = item?;
-            edge_property_manager.delete(
-                edge_property_outbound_id,
-                &edge_property_t,
-                edge_property_inbound_id,
-                &edge_property_name[..],
-            )?;
+    /// Gets the key of the entry for a vertex, given its value in the
+    /// vertices tree, or `None` if it isn't indexed.
+    pub(crate) fn key_for_value(&self, id: Uuid, value_bytes: &[u8]) -> Result<Option<Vec<u8>>> {
+        match read_vertex_value(self.vertices, id.as_bytes(), value_bytes)? {
+            (t, Some(created_datetime)) => Ok(Some(self.key(&t, created_datetime, id))),
+            (_, None) => Ok(None),
         }
-        Ok(())
     }
 }
 
-pub struct EdgeRangeManager<'tree> {
-    pub tree: &'tree Tree,
+/// Indexes edges by `(type, update datetime, outbound ID, inbound ID)`, so
+/// the edges of a type can be found without knowing their vertices.
+pub struct EdgeTypeManager<'tree> {
+    pub tree: IndexWriter,
+    retrier: &'tree Retrier,
+    decode_errors: Arc<DecodeErrors>,
 }
 
-impl<'tree> EdgeRangeManager<'tree> {
+impl<'tree> EdgeTypeManager<'tree> {
     pub fn new<'db: 'tree>(ds: &'db SledHolder) -> Self {
-        EdgeRangeManager { tree: &ds.edge_ranges }
-    }
-
-    pub fn new_reversed<'db: 'tree>(ds: &'db SledHolder) -> Self {
-        EdgeRangeManager {
-            tree: &ds.reversed_edge_ranges,
+        EdgeTypeManager {
+            tree: ds.edges_by_type.writer(),
+            retrier: &ds.retrier,
+            decode_errors: ds.decode_errors.clone(),
         }
     }
 
-    fn key(&self, first_id: Uuid, t: &Type, update_datetime: DateTime<Utc>, second_id: Uuid) -> Vec<u8> {
+    pub(crate) fn key(t: &Type, update_datetime: DateTime<Utc>, outbound_id: Uuid, inbound_id: Uuid) -> Vec<u8> {
         util::build(&[
-            util::Component::Uuid(first_id),
             util::Component::Type(t),
             util::Component::DateTime(update_datetime),
-            util::Component::Uuid(second_id),
+            util::Component::Uuid(outbound_id),
+            util::Component::Uuid(inbound_id),
         ])
     }
 
-    fn iterate<'it>(&self, iterator: DbIterator, prefix: Vec<u8>) -> impl Iterator<Item = Result<EdgeRangeItem>> + 'it {
-        let filtered = take_while_prefixed(iterator, prefix);
-        filtered.map(move |item| -> Result<EdgeRangeItem> {
-            let (k, _) = map_err(item)?;
-            let mut cursor = Cursor::new(k);
-            let first_id = util::read_uuid(&mut cursor);
-            let t = util::read_type(&mut cursor);
-            let update_datetime = util::read_datetime(&mut cursor);
-            let second_id = util::read_uuid(&mut cursor);
-            Ok((first_id, t, update_datetime, second_id))
-        })
-    }
-
-    pub fn iterate_for_range<'iter, 'trans: 'iter>(
-        &'trans self,
-        id: Uuid,
-        t: Option<&Type>,
+    /// Iterates over the edges of the given type, most recently updated
+    /// first, starting from those updated at or before `high`, if it's set.
+    pub fn iterate_for_type(
+        &self,
+        t: &Type,
         high: Option<DateTime<Utc>>,
-    ) -> Result<Box<dyn Iterator<Item = Result<EdgeRangeItem>> + 'iter>> {
-        match t {
-            Some(t) => {
-                let high = high.unwrap_or_else(|| *util::MAX_DATETIME);
-                let prefix = util::build(&[util::Component::Uuid(id), util::Component::Type(t)]);
-                let low_key = util::build(&[
-                    util::Component::Uuid(id),
-                    util::Component::Type(t),
-                    util::Component::DateTime(high),
-                ]);
-                let low_key_bytes: &[u8] = low_key.as_ref();
-                let iterator = self.tree.range(low_key_bytes..);
-                Ok(Box::new(self.iterate(iterator, prefix)))
-            }
-            None => {
-                let prefix = util::build(&[util::Component::Uuid(id)]);
-                let prefix_bytes: &[u8] = prefix.as_ref();
-                let iterator = self.tree.range(prefix_bytes..);
-                let mapped = self.iterate(iterator, prefix);
-
-                if let Some(high) = high {
-                    // We can filter out `update_datetime`s greater than
-                    // `high` via key prefix filtering, so instead we handle
-                    // it here - after the key has been deserialized.
-                    let filtered = mapped.filter(move |item| {
-                        if let Ok((_, _, update_datetime, _)) = *item {
-                            update_datetime <= high
-                        } else {
-                            true
-                        }
-                    });
-
-                    Ok(Box::new(filtered))
-                } else {
-                    Ok(Box::new(mapped))
-                }
-            }
-        }
+    ) -> impl Iterator<Item = Result<(EdgeKey, DateTime<Utc>)>> {
+        let prefix = util::build(&[util::Component::Type(t)]);
+        let start = util::build(&[
+            util::Component::Type(t),
+            util::Component::DateTime(high.unwrap_or_else(|| *util::MAX_DATETIME)),
+        ]);
+        let tree = self.tree.clone();
+        let decode_errors = self.decode_errors.clone();
+        let t = t.clone();
+
+        take_while_prefixed(self.tree.range(start..), prefix)
+            .map(move |item| -> Result<(EdgeKey, DateTime<Utc>)> {
+                let (k, _) = map_err(item)?;
+                let mut decoder = Decoder::key(&tree, &k);
+                decoder.read_type()?;
+                let update_datetime = decoder.read_datetime()?;
+                let outbound_id = decoder.read_uuid()?;
+                let inbound_id = decoder.read_uuid()?;
+                Ok((EdgeKey::new(outbound_id, t.clone(), inbound_id), update_datetime))
+            })
+            .filter_map(move |item| decode_errors.filter(item))
     }
 
-    pub fn iterate_for_owner<'iter, 'trans: 'iter>(
-        &'trans self,
-        id: Uuid,
-    ) -> impl Iterator<Item = Result<EdgeRangeItem>> + 'iter {
-        let prefix: Vec<u8> = util::build(&[util::Component::Uuid(id)]);
-        let iterator = self.tree.scan_prefix(&prefix);
-        self.iterate(iterator, prefix)
+    pub fn set(&self, t: &Type, update_datetime: DateTime<Utc>, outbound_id: Uuid, inbound_id: Uuid) -> Result<()> {
+        let key = Self::key(t, update_datetime, outbound_id, inbound_id);
+        self.retrier.run(|| self.tree.insert(key.as_slice(), &[]))?;
+        Ok(())
     }
 
-    pub fn set(&self, first_id: Uuid, t: &Type, update_datetime: DateTime<Utc>, second_id: Uuid) -> Result<()> {
-        let key = self.key(first_id, t, update_datetime, second_id);
-        map_err(self.tree.insert(&key, &[]))?;
-        Ok(())
+    /// Adds an entry to `batch`, rather than writing it right away.
+    pub(crate) fn stage_set(
+        &self,
+        batch: &mut MultiBatch,
+        t: &Type,
+        update_datetime: DateTime<Utc>,
+        outbound_id: Uuid,
+        inbound_id: Uuid,
+    ) {
+        batch.insert_index(&self.tree, Self::key(t, update_datetime, outbound_id, inbound_id), &[]);
     }
 
-    pub fn delete(&self, first_id: Uuid, t: &Type, update_datetime: DateTime<Utc>, second_id: Uuid) -> Result<()> {
-        map_err(self.tree.remove(&self.key(first_id, t, update_datetime, second_id)))?;
-        Ok(())
+    /// Adds the removal of an entry to `batch`, rather than removing it
+    /// right away.
+    pub(crate) fn stage_delete(
+        &self,
+        batch: &mut MultiBatch,
+        t: &Type,
+        update_datetime: DateTime<Utc>,
+        outbound_id: Uuid,
+        inbound_id: Uuid,
+    ) {
+        batch.remove_index(&self.tree, Self::key(t, update_datetime, outbound_id, inbound_id));
     }
 }
 
-pub struct VertexPropertyManager<'tree> {
+/// Edges staged by `EdgeManager::stage_many`, canonicalized.
+pub(crate) struct StagedEdges {
+    keys: Vec<EdgeKey>,
+    update_datetime: DateTime<Utc>,
+    // Whether each edge is new, and the number of range entries written
+    // for it.
+    new: Vec<bool>,
+    range_writes: Vec<u64>,
+}
+
+pub struct EdgeManager<'db: 'tree, 'tree> {
+    pub holder: &'db SledHolder,
     pub tree: &'tree Tree,
 }
 
-impl<'tree> VertexPropertyManager<'tree> {
-    pub fn new(tree: &'tree Tree) -> Self {
-        VertexPropertyManager { tree }
+impl<'db, 'tree> EdgeManager<'db, 'tree> {
+    pub fn new(ds: &'db SledHolder) -> Self {
+        EdgeManager {
+            holder: ds,
+            tree: &ds.edges,
+        }
     }
 
-    fn key(&self, vertex_id: Uuid, name: &str) -> Vec<u8> {
+    pub(crate) fn build_key(outbound_id: Uuid, t: &Type, inbound_id: Uuid) -> Vec<u8> {
         util::build(&[
-            util::Component::Uuid(vertex_id),
-            util::Component::FixedLengthString(name),
+            util::Component::Uuid(outbound_id),
+            util::Component::Type(t),
+            util::Component::Uuid(inbound_id),
         ])
     }
 
-    pub fn iterate_for_owner(&self, vertex_id: Uuid) -> Result<impl Iterator<Item = Result<OwnedPropertyItem>> + '_> {
-        let prefix = util::build(&[util::Component::Uuid(vertex_id)]);
-        let iterator = self.tree.scan_prefix(&prefix);
-
-        Ok(iterator.map(move |item| -> Result<OwnedPropertyItem> {
-            let (k, v) = map_err(item)?;
-            let mut cursor = Cursor::new(k);
-            let owner_id = util::read_uuid(&mut cursor);
-            debug_assert_eq!(vertex_id, owner_id);
-            let name = util::read_fixed_length_string(&mut cursor);
-            let value = serde_json::from_slice(&v)?;
-            Ok(((owner_id, name), value))
-        }))
+    /// Builds the key of an edge as it's stored, which for undirected
+    /// types may have its vertex IDs swapped.
+    fn key(&self, outbound_id: Uuid, t: &Type, inbound_id: Uuid) -> Vec<u8> {
+        let (outbound_id, inbound_id) = self.holder.edge_range_layout.canonical_ids(outbound_id, t, inbound_id);
+        Self::build_key(outbound_id, t, inbound_id)
     }
 
-    pub fn get(&self, vertex_id: Uuid, name: &str) -> Result<Option<JsonValue>> {
-        let key = self.key(vertex_id, name);
+    pub fn get(&self, outbound_id: Uuid, t: &Type, inbound_id: Uuid) -> Result<Option<DateTime<Utc>>> {
+        let key = self.key(outbound_id, t, inbound_id);
 
-        match map_err(self.tree.get(&key))? {
-            Some(value_bytes) => Ok(Some(serde_json::from_slice(&value_bytes)?)),
+        match self.holder.retrier.run(|| self.tree.get(&key))? {
+            Some(value_bytes) => Ok(Some(Decoder::value(self.tree, &key, &value_bytes).read_datetime()?)),
             None => Ok(None),
         }
     }
 
-    pub fn set(&self, vertex_id: Uuid, name: &str, value: &JsonValue) -> Result<()> {
-        let key = self.key(vertex_id, name);
-        let value_json = serde_json::to_vec(value)?;
-        map_err(self.tree.insert(key.as_slice(), value_json.as_slice()))?;
-        Ok(())
-    }
+    /// Gets the update datetimes of several edges, in the order of `keys`.
+    /// The lookups are made in key order, so that they walk the edges tree
+    /// in a single pass.
+    pub fn get_many(&self, keys: &[EdgeKey]) -> Result<Vec<Option<DateTime<Utc>>>> {
+        let mut encoded: Vec<(Vec<u8>, usize)> = keys
+            .iter()
+            .enumerate()
+            .map(|(i, key)| (self.key(key.outbound_id, &key.t, key.inbound_id), i))
+            .collect();
+        encoded.sort();
+
+        let mut results = vec![None; keys.len()];
+        let mut last: Option<(&[u8], Option<DateTime<Utc>>)> = None;
+
+        for &(ref key, i) in &encoded {
+            let update_datetime = match last {
+                // Duplicate keys are only looked up once.
+                Some((last_key, update_datetime)) if last_key == &key[..] => update_datetime,
+                _ => match self.holder.retrier.run(|| self.tree.get(key))? {
+                    Some(value_bytes) => Some(Decoder::value(self.tree, key, &value_bytes).read_datetime()?),
+                    None => None,
+                },
+            };
 
-    pub fn delete(&self, vertex_id: Uuid, name: &str) -> Result<()> {
-        map_err(self.tree.remove(&self.key(vertex_id, name)))?;
-        Ok(())
+            results[i] = update_datetime;
+            last = Some((key, update_datetime));
+        }
+
+        Ok(results)
     }
-}
 
-pub struct EdgePropertyManager<'tree> {
-    pub tree: &'tree Tree,
-}
+    pub fn set(&self, outbound_id: Uuid, t: &Type, inbound_id: Uuid, new_update_datetime: DateTime<Utc>) -> Result<()> {
+        let (outbound_id, inbound_id) = self.holder.edge_range_layout.canonical_ids(outbound_id, t, inbound_id);
 
-impl<'tree> EdgePropertyManager<'tree> {
-    pub fn new(tree: &'tree Tree) -> Self {
-        EdgePropertyManager { tree }
-    }
+        // Datetimes are stored at the configured precision everywhere, so
+        // that the edges tree agrees with the edge range keys.
+        let new_update_datetime = self.holder.datetime_precision.truncate(new_update_datetime);
+        let edge_range_manager = EdgeRangeManager::new(self.holder);
+        let reversed_edge_range_manager = EdgeRangeManager::new_reversed(self.holder);
 
-    fn key(&self, outbound_id: Uuid, t: &Type, inbound_id: Uuid, name: &str) -> Vec<u8> {
-        util::build(&[
-            util::Component::Uuid(outbound_id),
-            util::Component::Type(t),
-            util::Component::Uuid(inbound_id),
-            util::Component::FixedLengthString(name),
-        ])
-    }
+        let _cardinality = self.holder.cardinality_guard();
+        let existing_update_datetime = self.get(outbound_id, t, inbound_id)?;
+        self.check_monotonic(
+            outbound_id,
+            t,
+            inbound_id,
+            existing_update_datetime,
+            new_update_datetime,
+        )?;
 
-    pub fn iterate_for_owner<'a>(
-        &'a self,
-        outbound_id: Uuid,
-        t: &'a Type,
-        inbound_id: Uuid,
-    ) -> Result<Box<dyn Iterator<Item = Result<EdgePropertyItem>> + 'a>> {
-        let prefix = util::build(&[
-            util::Component::Uuid(outbound_id),
-            util::Component::Type(t),
-            util::Component::Uuid(inbound_id),
-        ]);
+        if existing_update_datetime.is_none() {
+            constraints::check_new_edge(self.holder, outbound_id, t, inbound_id, 0, 0)?;
+        }
 
-        let iterator = self.tree.scan_prefix(&prefix);
+        // If the type's ranges are untimed, the range keys of an existing
+        // edge don't depend on its update datetime, so they're left
+        // untouched, and only rewritten to update their value if it holds
+        // the datetime.
+        let timed = edge_range_manager.is_timed(t);
+        let update_ranges = existing_update_datetime.is_none() || timed || edge_range_manager.has_datetime_value(t);
+
+        // Reversed ranges are rebuilt from the forward ranges once deferred
+        // indexing finishes.
+        let update_reversed_ranges = update_ranges && !self.holder.is_indexing_deferred();
+
+        // The edge, its range and type index entries and its history are
+        // written in a single transaction, so that a crash can't leave
+        // range entries behind for an edge that was never written.
+        let mut batch = MultiBatch::default();
+
+        if update_ranges && timed {
+            if let Some(update_datetime) = existing_update_datetime {
+                edge_range_manager.stage_delete(&mut batch, outbound_id, t, update_datetime, inbound_id)?;
+
+                if update_reversed_ranges {
+                    reversed_edge_range_manager.stage_delete(
+                        &mut batch,
+                        inbound_id,
+                        t,
+                        update_datetime,
+                        outbound_id,
+                    )?;
+                }
+            }
+        }
 
-        let mapped = iterator.map(move |item| -> Result<EdgePropertyItem> {
-            let (k, v) = map_err(item)?;
-            let mut cursor = Cursor::new(k);
+        let key = self.key(outbound_id, t, inbound_id);
+        let value = util::build(&[util::Component::DateTime(new_update_datetime)]);
+        batch.insert(self.tree, key.as_slice(), value.as_slice());
 
-            let edge_property_outbound_id = util::read_uuid(&mut cursor);
-            debug_assert_eq!(edge_property_outbound_id, outbound_id);
+        // The type index is rebuilt wholesale once deferred indexing
+        // finishes.
+        if !self.holder.is_indexing_deferred() {
+            let edge_type_manager = EdgeTypeManager::new(self.holder);
 
-            let edge_property_t = util::read_type(&mut cursor);
-            debug_assert_eq!(&edge_property_t, t);
+            if let Some(update_datetime) = existing_update_datetime {
+                edge_type_manager.stage_delete(&mut batch, t, update_datetime, outbound_id, inbound_id);
+            }
 
-            let edge_property_inbound_id = util::read_uuid(&mut cursor);
-            debug_assert_eq!(edge_property_inbound_id, inbound_id);
+            edge_type_manager.stage_set(&mut batch, t, new_update_datetime, outbound_id, inbound_id);
+        }
 
-            let edge_property_name = util::read_fixed_length_string(&mut cursor);
+        if self.holder.history.enabled {
+            HistoryManager::new(&self.holder.retrier, &self.holder.history.edges).stage_record(
+                &mut batch,
+                &key,
+                Utc::now(),
+                Some(&value),
+            );
+        }
 
-            let value = serde_json::from_slice(&v)?;
-            Ok((
-                (
-                    edge_property_outbound_id,
-                    edge_property_t,
-                    edge_property_inbound_id,
-                    edge_property_name,
-                ),
-                value,
-            ))
-        });
+        if update_ranges {
+            edge_range_manager.stage_set(&mut batch, outbound_id, t, new_update_datetime, inbound_id)?;
+        }
 
-        Ok(Box::new(mapped))
-    }
+        if update_reversed_ranges {
+            reversed_edge_range_manager.stage_set(&mut batch, inbound_id, t, new_update_datetime, outbound_id)?;
+        }
 
-    pub fn get(&self, outbound_id: Uuid, t: &Type, inbound_id: Uuid, name: &str) -> Result<Option<JsonValue>> {
-        let key = self.key(outbound_id, t, inbound_id, name);
+        batch.apply(self.holder)?;
 
-        match map_err(self.tree.get(&key))? {
-            Some(ref value_bytes) => Ok(Some(serde_json::from_slice(value_bytes)?)),
-            None => Ok(None),
+        if existing_update_datetime.is_none() {
+            CatalogManager::new(self.holder).increment(CatalogKind::EdgeType, t.0.as_bytes())?;
         }
-    }
 
-    pub fn set(&self, outbound_id: Uuid, t: &Type, inbound_id: Uuid, name: &str, value: &JsonValue) -> Result<()> {
-        let key = self.key(outbound_id, t, inbound_id, name);
+        if existing_update_datetime.is_none() {
+            views::on_edge_change(self.holder, outbound_id, t, inbound_id)?;
+            degrees::on_edge_change(self.holder, outbound_id, t, inbound_id, 1)?;
+        }
+
+        // Range entries are written and, for existing edges whose range keys
+        // include the datetime, deleted.
+        let range_writes_per_tree = if existing_update_datetime.is_some() && timed {
+            2
+        } else {
+            1
+        };
+        let range_writes = range_writes_per_tree * (update_ranges as u64 + update_reversed_ranges as u64);
+        stats::record_edge_write(
+            self.holder,
+            outbound_id,
+            t,
+            inbound_id,
+            new_update_datetime,
+            range_writes,
+        )?;
+        activity::record_edge_write(self.holder, outbound_id, t, inbound_id, new_update_datetime)?;
+
+        self.holder.notify_mutation()?;
+
+        Ok(())
+    }
+
+    /// Sets several edges to the same update datetime. Unlike calling `set`
+    /// for each edge, the writes are grouped into a single sled
+    /// transaction. `keys` must not contain duplicates, including the two
+    /// orientations of an undirected edge.
+    pub fn set_many(&self, keys: &[EdgeKey], new_update_datetime: DateTime<Utc>) -> Result<()> {
+        self.set_many_with(keys, new_update_datetime, MultiBatch::default())
+    }
+
+    /// Like `set_many`, with the writes in `batch` applied in the same sled
+    /// transaction.
+    pub(crate) fn set_many_with(
+        &self,
+        keys: &[EdgeKey],
+        new_update_datetime: DateTime<Utc>,
+        mut batch: MultiBatch,
+    ) -> Result<()> {
+        let _cardinality = self.holder.cardinality_guard();
+        let staged = self.stage_many(keys, new_update_datetime, &mut batch)?;
+        batch.apply(self.holder)?;
+        self.finish_many(staged)
+    }
+
+    /// Adds the writes of `set_many` to `batch`, once the edges have been
+    /// checked against the edge constraints, for `finish_many` to complete
+    /// once the batch is applied. Callers hold the cardinality guard until
+    /// then, so that no other edge can be checked in the meantime.
+    pub(crate) fn stage_many(
+        &self,
+        keys: &[EdgeKey],
+        new_update_datetime: DateTime<Utc>,
+        batch: &mut MultiBatch,
+    ) -> Result<StagedEdges> {
+        let keys: Vec<EdgeKey> = keys
+            .iter()
+            .map(|key| self.holder.edge_range_layout.canonical_key(key))
+            .collect();
+        let new_update_datetime = self.holder.datetime_precision.truncate(new_update_datetime);
+        let edge_range_manager = EdgeRangeManager::new(self.holder);
+        let reversed_edge_range_manager = EdgeRangeManager::new_reversed(self.holder);
+        let update_reversed_ranges = !self.holder.is_indexing_deferred();
+        let value = util::build(&[util::Component::DateTime(new_update_datetime)]);
+
+        let edge_type_manager = EdgeTypeManager::new(self.holder);
+        let update_edge_types = !self.holder.is_indexing_deferred();
+        let history_manager = HistoryManager::new(&self.holder.retrier, &self.holder.history.edges);
+        let now = Utc::now();
+
+        let mut new = Vec::with_capacity(keys.len());
+        let mut new_edges_per_vertex: HashMap<(Uuid, &Type), u64> = HashMap::new();
+        let mut range_writes = Vec::with_capacity(keys.len());
+
+        for (key, existing_update_datetime) in keys.iter().zip(self.get_many(&keys)?) {
+            let (outbound_id, t, inbound_id) = (key.outbound_id, &key.t, key.inbound_id);
+
+            // Nothing's been written yet, so a violation leaves all of the
+            // edges uncreated.
+            self.check_monotonic(
+                outbound_id,
+                t,
+                inbound_id,
+                existing_update_datetime,
+                new_update_datetime,
+            )?;
+
+            if existing_update_datetime.is_none() {
+                let pending_outbound = new_edges_per_vertex.get(&(outbound_id, t)).cloned().unwrap_or(0);
+                let pending_inbound = new_edges_per_vertex.get(&(inbound_id, t)).cloned().unwrap_or(0);
+                constraints::check_new_edge(
+                    self.holder,
+                    outbound_id,
+                    t,
+                    inbound_id,
+                    pending_outbound,
+                    pending_inbound,
+                )?;
+                *new_edges_per_vertex.entry((outbound_id, t)).or_insert(0) += 1;
+
+                // Undirected edges count against the limits of both of
+                // their vertices.
+                if inbound_id != outbound_id && self.holder.edge_range_layout.is_undirected(t) {
+                    *new_edges_per_vertex.entry((inbound_id, t)).or_insert(0) += 1;
+                }
+            }
+            let edge_key = self.key(outbound_id, t, inbound_id);
+            batch.insert(self.tree, edge_key.as_slice(), value.as_slice());
+
+            if self.holder.history.enabled {
+                history_manager.stage_record(batch, &edge_key, now, Some(&value));
+            }
+
+            if update_edge_types {
+                if let Some(update_datetime) = existing_update_datetime {
+                    edge_type_manager.stage_delete(batch, t, update_datetime, outbound_id, inbound_id);
+                }
+
+                edge_type_manager.stage_set(batch, t, new_update_datetime, outbound_id, inbound_id);
+            }
+
+            // As in `set`, the range entries of existing edges are only
+            // rewritten if they include the update datetime, in their keys or
+            // their values.
+            let timed = edge_range_manager.is_timed(t);
+            let update_ranges = match existing_update_datetime {
+                Some(update_datetime) => {
+                    if timed {
+                        edge_range_manager.stage_delete(batch, outbound_id, t, update_datetime, inbound_id)?;
+
+                        if update_reversed_ranges {
+                            reversed_edge_range_manager.stage_delete(
+                                batch,
+                                inbound_id,
+                                t,
+                                update_datetime,
+                                outbound_id,
+                            )?;
+                        }
+
+                        true
+                    } else {
+                        edge_range_manager.has_datetime_value(t)
+                    }
+                }
+                None => true,
+            };
+
+            new.push(existing_update_datetime.is_none());
+            let range_writes_per_tree = match existing_update_datetime {
+                Some(_) if update_ranges && timed => 2,
+                Some(_) if update_ranges => 1,
+                Some(_) => 0,
+                None => 1,
+            };
+            range_writes.push(range_writes_per_tree * (1 + update_reversed_ranges as u64));
+
+            if update_ranges {
+                edge_range_manager.stage_set(batch, outbound_id, t, new_update_datetime, inbound_id)?;
+
+                if update_reversed_ranges {
+                    reversed_edge_range_manager.stage_set(batch, inbound_id, t, new_update_datetime, outbound_id)?;
+                }
+            }
+        }
+
+        Ok(StagedEdges {
+            keys,
+            update_datetime: new_update_datetime,
+            new,
+            range_writes,
+        })
+    }
+
+    /// Updates the catalog, views and statistics for edges staged by
+    /// `stage_many`, once their batch is applied.
+    pub(crate) fn finish_many(&self, staged: StagedEdges) -> Result<()> {
+        let new_keys: Vec<&EdgeKey> = staged
+            .keys
+            .iter()
+            .zip(&staged.new)
+            .filter(|&(_, &new)| new)
+            .map(|(key, _)| key)
+            .collect();
+
+        let mut new_edges_per_type: HashMap<&Type, i64> = HashMap::new();
+        for key in &new_keys {
+            *new_edges_per_type.entry(&key.t).or_insert(0) += 1;
+        }
+
+        let catalog_manager = CatalogManager::new(self.holder);
+        for (t, count) in new_edges_per_type {
+            catalog_manager.adjust(CatalogKind::EdgeType, t.0.as_bytes(), count)?;
+        }
+
+        for key in new_keys {
+            views::on_edge_change(self.holder, key.outbound_id, &key.t, key.inbound_id)?;
+            degrees::on_edge_change(self.holder, key.outbound_id, &key.t, key.inbound_id, 1)?;
+        }
+
+        for (key, range_writes) in staged.keys.iter().zip(staged.range_writes) {
+            stats::record_edge_write(
+                self.holder,
+                key.outbound_id,
+                &key.t,
+                key.inbound_id,
+                staged.update_datetime,
+                range_writes,
+            )?;
+            activity::record_edge_write(
+                self.holder,
+                key.outbound_id,
+                &key.t,
+                key.inbound_id,
+                staged.update_datetime,
+            )?;
+        }
+
+        self.holder.notify_mutation()?;
+
+        Ok(())
+    }
+
+    /// Fails if an edge would be given an update datetime older than its
+    /// current one, and the datastore is configured to reject that.
+    fn check_monotonic(
+        &self,
+        outbound_id: Uuid,
+        t: &Type,
+        inbound_id: Uuid,
+        current: Option<DateTime<Utc>>,
+        given: DateTime<Utc>,
+    ) -> Result<()> {
+        match current {
+            Some(current) if self.holder.monotonic_edge_datetimes && given < current => {
+                Err(Error::EdgeDatetimeNotMonotonic {
+                    outbound_id,
+                    t: t.0.clone(),
+                    inbound_id,
+                    current,
+                    given,
+                }
+                .into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn delete(&self, outbound_id: Uuid, t: &Type, inbound_id: Uuid, update_datetime: DateTime<Utc>) -> Result<()> {
+        self.delete_many(&[(outbound_id, t.clone(), inbound_id, update_datetime)])
+    }
+
+    /// Deletes several edges, given their update datetimes, along with
+    /// their properties. Everything is removed in a single sled
+    /// transaction, so that a crash can't leave range entries or
+    /// properties behind for an edge that's gone. `edges` must not contain
+    /// duplicates.
+    pub fn delete_many(&self, edges: &[(Uuid, Type, Uuid, DateTime<Utc>)]) -> Result<()> {
+        self.delete_many_with(edges, None)
+    }
+
+    /// Like `delete`, for an edge whose property names are already known,
+    /// as given by `EdgePropertyManager::properties_of_vertex`, so that
+    /// they don't have to be scanned for.
+    pub(crate) fn delete_with_properties(
+        &self,
+        outbound_id: Uuid,
+        t: &Type,
+        inbound_id: Uuid,
+        update_datetime: DateTime<Utc>,
+        property_names: &[String],
+    ) -> Result<()> {
+        let edge = (outbound_id, t.clone(), inbound_id, update_datetime);
+        self.delete_many_with(&[edge], Some(property_names))
+    }
+
+    /// Deletes edges as with `delete_many`. If `property_names` is set, it
+    /// names the properties of the single edge in `edges`.
+    fn delete_many_with(
+        &self,
+        edges: &[(Uuid, Type, Uuid, DateTime<Utc>)],
+        property_names: Option<&[String]>,
+    ) -> Result<()> {
+        // Undirected edges are found from either of their vertices, so the
+        // same edge may be given in both orientations.
+        let mut seen = HashSet::new();
+        let edges: Vec<(Uuid, Type, Uuid, DateTime<Utc>)> = edges
+            .iter()
+            .map(|&(outbound_id, ref t, inbound_id, update_datetime)| {
+                let (outbound_id, inbound_id) = self.holder.edge_range_layout.canonical_ids(outbound_id, t, inbound_id);
+                (outbound_id, t.clone(), inbound_id, update_datetime)
+            })
+            .filter(|&(outbound_id, ref t, inbound_id, _)| seen.insert((outbound_id, t.clone(), inbound_id)))
+            .collect();
+
+        let edge_range_manager = EdgeRangeManager::new(self.holder);
+        let reversed_edge_range_manager = EdgeRangeManager::new_reversed(self.holder);
+        let edge_type_manager = EdgeTypeManager::new(self.holder);
+        let edge_property_manager = EdgePropertyManager::new(self.holder);
+        let history_manager = HistoryManager::new(&self.holder.retrier, &self.holder.history.edges);
+        let keys: Vec<EdgeKey> = edges
+            .iter()
+            .map(|&(outbound_id, ref t, inbound_id, _)| EdgeKey::new(outbound_id, t.clone(), inbound_id))
+            .collect();
+
+        let mut batch = MultiBatch::default();
+        let mut deleted = Vec::new();
+        let mut deleted_properties_per_name: HashMap<String, i64> = HashMap::new();
+        let mut deleted_values = Vec::new();
+        let now = Utc::now();
+
+        // Range keys may include a sort key read from the edge's
+        // properties, so they're built before the properties are deleted.
+        for (edge, existing_update_datetime) in edges.iter().zip(self.get_many(&keys)?) {
+            let &(outbound_id, ref t, inbound_id, update_datetime) = edge;
+            let key = self.key(outbound_id, t, inbound_id);
+            batch.remove(self.tree, key.as_slice());
+            edge_range_manager.stage_delete(&mut batch, outbound_id, t, update_datetime, inbound_id)?;
+            reversed_edge_range_manager.stage_delete(&mut batch, inbound_id, t, update_datetime, outbound_id)?;
+            edge_type_manager.stage_delete(&mut batch, t, update_datetime, outbound_id, inbound_id);
+
+            if self.holder.history.enabled && existing_update_datetime.is_some() {
+                history_manager.stage_record(&mut batch, &key, now, None);
+            }
+
+            let deleted_properties = match property_names {
+                Some(names) => {
+                    edge_property_manager.stage_delete_named(&mut batch, outbound_id, t, inbound_id, names, now)?
+                }
+                None => edge_property_manager.stage_delete_for_owner(&mut batch, outbound_id, t, inbound_id, now)?,
+            };
+
+            for (name, stored) in deleted_properties {
+                *deleted_properties_per_name.entry(name).or_insert(0) -= 1;
+                deleted_values.push(stored);
+            }
+
+            if existing_update_datetime.is_some() {
+                deleted.push(edge);
+            }
+        }
+
+        batch.apply(self.holder)?;
+
+        for stored in deleted_values {
+            dedup::release(self.holder, &stored)?;
+        }
+
+        let catalog_manager = CatalogManager::new(self.holder);
+        for (name, count) in deleted_properties_per_name {
+            catalog_manager.adjust(CatalogKind::EdgeProperty, name.as_bytes(), count)?;
+        }
+
+        for &&(outbound_id, ref t, inbound_id, update_datetime) in &deleted {
+            catalog_manager.decrement(CatalogKind::EdgeType, t.0.as_bytes())?;
+            views::on_edge_change(self.holder, outbound_id, t, inbound_id)?;
+            degrees::on_edge_change(self.holder, outbound_id, t, inbound_id, -1)?;
+            stats::record_edge_write(self.holder, outbound_id, t, inbound_id, update_datetime, 2)?;
+        }
+
+        self.holder.notify_mutation()?;
+        Ok(())
+    }
+}
+
+/// Adds the rewrite of an edge's range entries, in both directions, to
+/// `batch`, for when the value of its sort property is about to change to
+/// `sort_value`. The old entries are found from the current value, so this
+/// has to be called before the property is written. Reversed ranges are
+/// rebuilt from the forward ranges once deferred indexing finishes, so
+/// new ones are skipped until then.
+fn stage_resorted_edge_ranges(
+    holder: &SledHolder,
+    batch: &mut MultiBatch,
+    outbound_id: Uuid,
+    t: &Type,
+    inbound_id: Uuid,
+    update_datetime: DateTime<Utc>,
+    sort_value: Option<&JsonValue>,
+) -> Result<()> {
+    let edge_range_manager = EdgeRangeManager::new(holder);
+    let reversed_edge_range_manager = EdgeRangeManager::new_reversed(holder);
+
+    edge_range_manager.stage_delete(batch, outbound_id, t, update_datetime, inbound_id)?;
+    reversed_edge_range_manager.stage_delete(batch, inbound_id, t, update_datetime, outbound_id)?;
+
+    for key in edge_range_manager.keys_with_sort_value(outbound_id, t, update_datetime, inbound_id, sort_value) {
+        batch.insert_index(
+            &edge_range_manager.tree,
+            key,
+            edge_range_manager.value(t, update_datetime),
+        );
+    }
+
+    if !holder.is_indexing_deferred() {
+        let keys =
+            reversed_edge_range_manager.keys_with_sort_value(inbound_id, t, update_datetime, outbound_id, sort_value);
+
+        for key in keys {
+            batch.insert_index(
+                &reversed_edge_range_manager.tree,
+                key,
+                reversed_edge_range_manager.value(t, update_datetime),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+pub struct EdgeRangeManager<'tree> {
+    pub tree: IndexWriter,
+    edges: &'tree Tree,
+    edge_properties: &'tree Tree,
+    value_store: &'tree Tree,
+    reversed: bool,
+    layout: EdgeRangeLayout,
+    precision: DatetimePrecision,
+    retrier: &'tree Retrier,
+    decode_errors: Arc<DecodeErrors>,
+}
+
+/// Decodes an edge range entry. Returns `None` if the entry's datetime has
+/// to be looked up from the edges tree, and the edge has since disappeared.
+fn decode_edge_range(
+    tree: &Tree,
+    edges: &Tree,
+    reversed: bool,
+    layout: &EdgeRangeLayout,
+    precision: DatetimePrecision,
+    k: &[u8],
+    v: &[u8],
+) -> Result<Option<EdgeRangeItem>> {
+    let mut decoder = Decoder::key(tree, k);
+    let first_id = decoder.read_uuid()?;
+    let t = decoder.read_type()?;
+
+    let update_datetime = if layout.is_timed(&t) {
+        Some(precision.read(&mut decoder)?)
+    } else {
+        None
+    };
+
+    // The second ID is at the end of the key, after the sort key if the
+    // type has one.
+    let sort_key_len = decoder.remaining().saturating_sub(16);
+    decoder.skip(sort_key_len)?;
+    let second_id = decoder.read_uuid()?;
+
+    let update_datetime = match update_datetime {
+        Some(update_datetime) => update_datetime,
+        None if layout.has_datetime_values() && !v.is_empty() => {
+            let mut decoder = Decoder::value(tree, k, v);
+            let update_datetime = precision.read(&mut decoder)?;
+
+            if !decoder.is_empty() {
+                return Err(decoder.corruption());
+            }
+
+            update_datetime
+        }
+        None => {
+            let (outbound_id, inbound_id) = if reversed {
+                layout.canonical_ids(second_id, &t, first_id)
+            } else {
+                layout.canonical_ids(first_id, &t, second_id)
+            };
+            let edge_key = EdgeManager::build_key(outbound_id, &t, inbound_id);
+
+            // The range entry and the edge are not written atomically, so
+            // skip range entries whose edge has since disappeared.
+            match map_err(edges.get(&edge_key))? {
+                Some(value_bytes) => Decoder::value(edges, &edge_key, &value_bytes).read_datetime()?,
+                None => return Ok(None),
+            }
+        }
+    };
+
+    Ok(Some((first_id, t, update_datetime, second_id)))
+}
+
+impl<'tree> EdgeRangeManager<'tree> {
+    pub fn new<'db: 'tree>(ds: &'db SledHolder) -> Self {
+        EdgeRangeManager {
+            tree: ds.edge_ranges.writer(),
+            edges: &ds.edges,
+            edge_properties: &ds.edge_properties,
+            value_store: &ds.values.store,
+            reversed: false,
+            layout: ds.edge_range_layout.clone(),
+            precision: ds.datetime_precision,
+            retrier: &ds.retrier,
+            decode_errors: ds.decode_errors.clone(),
+        }
+    }
+
+    pub fn new_reversed<'db: 'tree>(ds: &'db SledHolder) -> Self {
+        EdgeRangeManager {
+            tree: ds.reversed_edge_ranges.writer(),
+            edges: &ds.edges,
+            edge_properties: &ds.edge_properties,
+            value_store: &ds.values.store,
+            reversed: true,
+            layout: ds.edge_range_layout.clone(),
+            precision: ds.datetime_precision,
+            retrier: &ds.retrier,
+            decode_errors: ds.decode_errors.clone(),
+        }
+    }
+
+    /// Gets the manager for edge ranges keyed by the outbound vertex, or
+    /// by the inbound vertex for `EdgeDirection::Inbound`.
+    pub fn for_direction<'db: 'tree>(ds: &'db SledHolder, direction: EdgeDirection) -> Self {
+        match direction {
+            EdgeDirection::Outbound => EdgeRangeManager::new(ds),
+            EdgeDirection::Inbound => EdgeRangeManager::new_reversed(ds),
+        }
+    }
+
+    pub(crate) fn key(
+        &self,
+        first_id: Uuid,
+        t: &Type,
+        update_datetime: DateTime<Utc>,
+        second_id: Uuid,
+    ) -> Result<Vec<u8>> {
+        let sort_value = self.sort_value(first_id, t, second_id)?;
+        Ok(self.key_with_sort_value(first_id, t, update_datetime, second_id, sort_value.as_ref()))
+    }
+
+    /// Builds the keys of all of an edge's range entries in this tree: the
+    /// one from `first_id`, plus the one from `second_id` if the edge is
+    /// undirected and not a loop.
+    pub(crate) fn keys(
+        &self,
+        first_id: Uuid,
+        t: &Type,
+        update_datetime: DateTime<Utc>,
+        second_id: Uuid,
+    ) -> Result<Vec<Vec<u8>>> {
+        let sort_value = self.sort_value(first_id, t, second_id)?;
+        Ok(self.keys_with_sort_value(first_id, t, update_datetime, second_id, sort_value.as_ref()))
+    }
+
+    /// Reads the value of the type's sort property from the edge, if the
+    /// type has a sort key.
+    fn sort_value(&self, first_id: Uuid, t: &Type, second_id: Uuid) -> Result<Option<JsonValue>> {
+        let sort_key = match self.layout.sort_key(t) {
+            Some(sort_key) => sort_key,
+            None => return Ok(None),
+        };
+
+        let (outbound_id, inbound_id) = if self.reversed {
+            self.layout.canonical_ids(second_id, t, first_id)
+        } else {
+            self.layout.canonical_ids(first_id, t, second_id)
+        };
+        let property_key = EdgePropertyManager::build_key(outbound_id, t, inbound_id, &sort_key.property);
+
+        match self.retrier.run(|| self.edge_properties.get(&property_key))? {
+            Some(stored) => {
+                let value_json = dedup::resolve(self.value_store, self.edge_properties, &property_key, &stored)?;
+                Ok(Some(serde_json::from_slice(&value_json)?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Builds a range key given the value of the type's sort property,
+    /// rather than reading it, e.g. because it's about to change. The value
+    /// is ignored if the type doesn't have a sort key.
+    pub(crate) fn key_with_sort_value(
+        &self,
+        first_id: Uuid,
+        t: &Type,
+        update_datetime: DateTime<Utc>,
+        second_id: Uuid,
+        sort_value: Option<&JsonValue>,
+    ) -> Vec<u8> {
+        let mut key = util::build(&[util::Component::Uuid(first_id), util::Component::Type(t)]);
+
+        if self.layout.is_timed(t) {
+            key.extend(self.precision.encode(update_datetime));
+        }
+
+        if let Some(sort_key) = self.layout.sort_key(t) {
+            key.extend(sort_key.encode(sort_value));
+        }
+
+        key.extend(util::build(&[util::Component::Uuid(second_id)]));
+        key
+    }
+
+    /// Like `keys`, given the value of the type's sort property, as with
+    /// `key_with_sort_value`.
+    pub(crate) fn keys_with_sort_value(
+        &self,
+        first_id: Uuid,
+        t: &Type,
+        update_datetime: DateTime<Utc>,
+        second_id: Uuid,
+        sort_value: Option<&JsonValue>,
+    ) -> Vec<Vec<u8>> {
+        let mut keys = vec![self.key_with_sort_value(first_id, t, update_datetime, second_id, sort_value)];
+
+        if first_id != second_id && self.layout.is_undirected(t) {
+            keys.push(self.key_with_sort_value(second_id, t, update_datetime, first_id, sort_value));
+        }
+
+        keys
+    }
+
+    /// Whether the range keys of a type include the update datetime.
+    pub(crate) fn is_timed(&self, t: &Type) -> bool {
+        self.layout.is_timed(t)
+    }
+
+    /// Whether the range entries of a type hold the update datetime as
+    /// their value, which is the case if their keys don't include it.
+    pub(crate) fn has_datetime_value(&self, t: &Type) -> bool {
+        !self.is_timed(t) && self.layout.has_datetime_values()
+    }
+
+    /// Gets the value of a range entry.
+    pub(crate) fn value(&self, t: &Type, update_datetime: DateTime<Utc>) -> Vec<u8> {
+        if self.has_datetime_value(t) {
+            self.precision.encode(update_datetime)
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn iterate<'it>(&self, iterator: DbIterator, prefix: Vec<u8>) -> impl Iterator<Item = Result<EdgeRangeItem>> + 'it {
+        let tree = self.tree.clone();
+        let edges = self.edges.clone();
+        let reversed = self.reversed;
+        let layout = self.layout.clone();
+        let precision = self.precision;
+        let decode_errors = self.decode_errors.clone();
+        let filtered = take_while_prefixed(iterator, prefix);
+
+        let mapped = filtered.map(move |item| -> Result<Option<EdgeRangeItem>> {
+            let (k, v) = map_err(item)?;
+            decode_edge_range(&tree, &edges, reversed, &layout, precision, &k, &v)
+        });
+
+        mapped.filter_map(move |item| match item {
+            Err(err) => decode_errors.filter(Err(err)),
+            Ok(Some(value)) => Some(Ok(value)),
+            Ok(None) => None,
+        })
+    }
+
+    /// Gets the types of the edge ranges of `id`, in name order. Range keys
+    /// are ordered by the type's length first, since types are stored
+    /// length-prefixed, so this seeks past each type's keys in turn, and
+    /// reads one key per type.
+    fn types_for(&self, id: Uuid, deadline: &Deadline) -> Result<Vec<Type>> {
+        let prefix = util::build(&[util::Component::Uuid(id)]);
+        let mut start = prefix.clone();
+        let mut types = Vec::new();
+
+        loop {
+            let k = match self.tree.range(start..).keys().next() {
+                Some(item) => map_err(item)?,
+                None => break,
+            };
+
+            if !k.starts_with(&prefix) {
+                break;
+            }
+
+            deadline.tick()?;
+            let mut decoder = Decoder::key(&self.tree, &k);
+            decoder.skip(prefix.len())?;
+
+            let t = match self.decode_errors.filter(decoder.read_type()) {
+                Some(t) => t?,
+                None => {
+                    start = k.to_vec();
+                    start.push(0);
+                    continue;
+                }
+            };
+
+            let type_prefix = util::build(&[util::Component::Uuid(id), util::Component::Type(&t)]);
+            types.push(t);
+
+            start = match prefix_end(&type_prefix) {
+                Some(end) => end,
+                None => break,
+            };
+        }
+
+        types.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(types)
+    }
+
+    /// Iterates over the edge ranges of `id` whose update datetime is at
+    /// most `high`, optionally filtered to a type, in the order described
+    /// by `query`.
+    pub fn iterate_for_range<'iter, 'trans: 'iter>(
+        &'trans self,
+        id: Uuid,
+        t: Option<&Type>,
+        high: Option<DateTime<Utc>>,
+    ) -> Result<Box<dyn Iterator<Item = Result<EdgeRangeItem>> + 'iter>> {
+        let t = match t {
+            Some(t) => t,
+            None => {
+                let mut iterators = Vec::new();
+
+                for t in self.types_for(id, &Deadline::new(None))? {
+                    iterators.push(self.iterate_for_range(id, Some(&t), high)?);
+                }
+
+                return Ok(Box::new(iterators.into_iter().flatten()));
+            }
+        };
+
+        let prefix = util::build(&[util::Component::Uuid(id), util::Component::Type(t)]);
+
+        if self.is_timed(t) {
+            let high = high.unwrap_or_else(|| *util::MAX_DATETIME);
+            let mut low_key = prefix.clone();
+            low_key.extend(self.precision.encode(high));
+            let low_key_bytes: &[u8] = low_key.as_ref();
+            let iterator = self.tree.range(low_key_bytes..);
+            return Ok(Box::new(self.iterate(iterator, prefix)));
+        }
+
+        let prefix_bytes: &[u8] = prefix.as_ref();
+        let iterator = self.tree.range(prefix_bytes..);
+        let mapped = self.iterate(iterator, prefix);
+
+        if let Some(high) = high {
+            // We can filter out `update_datetime`s greater than
+            // `high` via key prefix filtering, so instead we handle
+            // it here - after the key has been deserialized.
+            let filtered = mapped.filter(move |item| {
+                if let Ok((_, _, update_datetime, _)) = *item {
+                    update_datetime <= high
+                } else {
+                    true
+                }
+            });
+
+            Ok(Box::new(filtered))
+        } else {
+            Ok(Box::new(mapped))
+        }
+    }
+
+    /// Gets up to `limit` edge ranges of `id` whose update datetime is
+    /// between `low` and `high` (both inclusive), optionally filtered to a
+    /// type. Results are ordered by type name, then within a type by the
+    /// update datetime (newest first) unless the type's ranges are
+    /// untimed, then by the type's sort key if it has one, and finally by
+    /// the other vertex's ID.
+    ///
+    /// For types whose ranges are timed, the bounds are pushed down into
+    /// the scan: the type's range is entered at `high`, and left as soon as
+    /// it passes `low`. So only keys that match, plus at most two per type,
+    /// are ever read. For other types, the bounds are applied by filtering.
+    /// The deadline is checked before each key.
+    pub fn query(
+        &self,
+        id: Uuid,
+        t: Option<&Type>,
+        low: Option<DateTime<Utc>>,
+        high: Option<DateTime<Utc>>,
+        limit: usize,
+        deadline: &Deadline,
+    ) -> Result<Vec<EdgeRangeItem>> {
+        let mut results = Vec::new();
+
+        if limit == 0 {
+            return Ok(results);
+        }
+
+        let t = match t {
+            Some(t) => t,
+            None => {
+                for t in self.types_for(id, deadline)? {
+                    results.extend(self.query(id, Some(&t), low, high, limit - results.len(), deadline)?);
+
+                    if results.len() == limit {
+                        break;
+                    }
+                }
+
+                return Ok(results);
+            }
+        };
+
+        // As in `count_for_range`, bounds are compared as encoded bytes, in
+        // which later datetimes are smaller.
+        let precision = self.precision;
+        let width = precision.width();
+        let timed = self.is_timed(t);
+        let low_bytes = low.map(|low| precision.encode(precision.round_up(low)));
+        let high_bytes = high.map(|high| precision.encode(high));
+
+        let prefix = util::build(&[util::Component::Uuid(id), util::Component::Type(t)]);
+        let mut start = prefix.clone();
+
+        if let (true, Some(ref high_bytes)) = (timed, &high_bytes) {
+            start.extend_from_slice(high_bytes);
+        }
+
+        for item in take_while_prefixed(self.tree.range(start..), prefix.clone()) {
+            let (k, v) = map_err(item)?;
+            deadline.tick()?;
+
+            if timed {
+                let mut decoder = Decoder::key(&self.tree, &k);
+                decoder.skip(prefix.len())?;
+                let datetime_bytes = decoder.read_bytes(width)?;
+
+                // Older than `low`, as is everything after it.
+                if low_bytes
+                    .as_ref()
+                    .is_some_and(|low_bytes| datetime_bytes > &low_bytes[..])
+                {
+                    break;
+                }
+            }
+
+            let item = match decode_edge_range(&self.tree, self.edges, self.reversed, &self.layout, precision, &k, &v)?
+            {
+                Some(item) => item,
+                None => continue,
+            };
+
+            if !timed {
+                let (_, _, update_datetime, _) = item;
+
+                if low.is_some_and(|low| update_datetime < low) || high.is_some_and(|high| update_datetime > high) {
+                    continue;
+                }
+            }
+
+            results.push(item);
+
+            if results.len() == limit {
+                break;
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Counts the edge ranges of `id` whose update datetime is between
+    /// `low` and `high` (both inclusive), optionally filtered to a type.
+    ///
+    /// If the ranges counted are all timed, this only ever inspects keys:
+    /// datetimes are encoded such that later datetimes have smaller byte
+    /// representations, so the bounds are checked by comparing raw key
+    /// bytes, and a typed count stops as soon as it passes `low`. The
+    /// deadline is checked before each key.
+    pub fn count_for_range(
+        &self,
+        id: Uuid,
+        t: Option<&Type>,
+        low: Option<DateTime<Utc>>,
+        high: Option<DateTime<Utc>>,
+        deadline: &Deadline,
+    ) -> Result<u64> {
+        let timed = match t {
+            Some(t) => self.is_timed(t),
+            None => self.layout.is_all_timed(),
+        };
+
+        if !timed {
+            let mut count = 0;
+
+            for item in deadline.bound(self.iterate_for_range(id, t, high)?) {
+                let (_, _, update_datetime, _) = item?;
+                if low.is_none_or(|low| update_datetime >= low) {
+                    count += 1;
+                }
+            }
+
+            return Ok(count);
+        }
+
+        // Stored datetimes are truncated to the configured precision, so
+        // `low` is rounded up to keep it inclusive of exactly those that are
+        // after it.
+        let precision = self.precision;
+        let width = precision.width();
+        let low_bytes = low.map(|low| precision.encode(precision.round_up(low)));
+        let high_bytes = high.map(|high| precision.encode(high));
+        let mut count = 0;
+
+        match t {
+            Some(t) => {
+                let prefix = util::build(&[util::Component::Uuid(id), util::Component::Type(t)]);
+                let datetime_offset = prefix.len();
+
+                let iterator = match high_bytes {
+                    Some(ref high_bytes) => {
+                        let mut low_key = prefix.clone();
+                        low_key.extend_from_slice(high_bytes);
+                        self.tree.range(low_key..)
+                    }
+                    None => self.tree.range(prefix.clone()..),
+                };
+
+                for item in take_while_prefixed(iterator, prefix) {
+                    let (k, _) = map_err(item)?;
+                    deadline.tick()?;
+                    let mut decoder = Decoder::key(&self.tree, &k);
+                    decoder.skip(datetime_offset)?;
+                    let datetime_bytes = decoder.read_bytes(width)?;
+
+                    if let Some(ref low_bytes) = low_bytes {
+                        if datetime_bytes > &low_bytes[..] {
+                            break;
+                        }
+                    }
+
+                    count += 1;
+                }
+            }
+            None => {
+                let prefix = util::build(&[util::Component::Uuid(id)]);
+
+                for item in self.tree.scan_prefix(&prefix) {
+                    let (k, _) = map_err(item)?;
+                    deadline.tick()?;
+                    // Skip past the ID and the length-prefixed type.
+                    let mut decoder = Decoder::key(&self.tree, &k);
+                    decoder.skip(prefix.len())?;
+                    let t_len = decoder.read_u8()? as usize;
+                    decoder.skip(t_len)?;
+                    let datetime_bytes = decoder.read_bytes(width)?;
+
+                    let after_low = low_bytes.as_ref().is_none_or(|b| datetime_bytes <= &b[..]);
+                    let before_high = high_bytes.as_ref().is_none_or(|b| datetime_bytes >= &b[..]);
+
+                    if after_low && before_high {
+                        count += 1;
+                    }
+                }
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Counts all edge ranges of `id`, without decoding them.
+    pub fn count_for_owner(&self, id: Uuid, deadline: &Deadline) -> Result<u64> {
+        let prefix = util::build(&[util::Component::Uuid(id)]);
+        let mut count = 0;
+
+        for item in self.tree.scan_prefix(&prefix).keys() {
+            map_err(item)?;
+            deadline.tick()?;
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    pub fn iterate_for_owner<'iter, 'trans: 'iter>(
+        &'trans self,
+        id: Uuid,
+    ) -> impl Iterator<Item = Result<EdgeRangeItem>> + 'iter {
+        let prefix: Vec<u8> = util::build(&[util::Component::Uuid(id)]);
+        let iterator = self.tree.scan_prefix(&prefix);
+        self.iterate(iterator, prefix)
+    }
+
+    pub fn contains(&self, first_id: Uuid, t: &Type, update_datetime: DateTime<Utc>, second_id: Uuid) -> Result<bool> {
+        let key = self.key(first_id, t, update_datetime, second_id)?;
+        self.retrier.run(|| self.tree.contains_key(&key))
+    }
+
+    /// Writes an edge's range entries, of which undirected edges have one
+    /// from each vertex.
+    pub fn set(&self, first_id: Uuid, t: &Type, update_datetime: DateTime<Utc>, second_id: Uuid) -> Result<()> {
+        let value = self.value(t, update_datetime);
+
+        for key in self.keys(first_id, t, update_datetime, second_id)? {
+            self.retrier.run(|| self.tree.insert(&key, value.as_slice()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Adds an edge's range entries to `batch`, rather than writing them
+    /// right away.
+    pub(crate) fn stage_set(
+        &self,
+        batch: &mut MultiBatch,
+        first_id: Uuid,
+        t: &Type,
+        update_datetime: DateTime<Utc>,
+        second_id: Uuid,
+    ) -> Result<()> {
+        for key in self.keys(first_id, t, update_datetime, second_id)? {
+            batch.insert_index(&self.tree, key, self.value(t, update_datetime));
+        }
+
+        Ok(())
+    }
+
+    /// Adds the removal of an edge's range entries to `batch`, rather than
+    /// removing them right away.
+    pub(crate) fn stage_delete(
+        &self,
+        batch: &mut MultiBatch,
+        first_id: Uuid,
+        t: &Type,
+        update_datetime: DateTime<Utc>,
+        second_id: Uuid,
+    ) -> Result<()> {
+        for key in self.keys(first_id, t, update_datetime, second_id)? {
+            batch.remove_index(&self.tree, key);
+        }
+
+        Ok(())
+    }
+}
+
+pub struct VertexPropertyManager<'db: 'tree, 'tree> {
+    pub holder: &'db SledHolder,
+    pub tree: &'tree Tree,
+    /// Indexes vertex properties by `(name, value, vertex_id)`, so the
+    /// vertices with a given property value can be found with a prefix
+    /// scan. The value is the property's JSON, as stored.
+    pub value_tree: IndexWriter,
+    /// Indexes numeric vertex properties by `(name, number, vertex_id)`,
+    /// with the number encoded so that keys sort in numeric order, so the
+    /// vertices with a property in a range can be found with a range scan.
+    pub number_tree: IndexWriter,
+}
+
+impl<'db: 'tree, 'tree> VertexPropertyManager<'db, 'tree> {
+    pub fn new(ds: &'db SledHolder) -> Self {
+        VertexPropertyManager {
+            holder: ds,
+            tree: &ds.vertex_properties,
+            value_tree: ds.vertex_property_values.writer(),
+            number_tree: ds.vertex_property_numbers.writer(),
+        }
+    }
+
+    /// Builds the prefix shared by the value index entries of a property
+    /// value, given the value as stored.
+    fn value_prefix(name: &str, value_json: &[u8]) -> Vec<u8> {
+        let mut prefix = Self::name_prefix(name);
+        prefix.extend_from_slice(value_json);
+        prefix
+    }
+
+    /// Builds the key of a value index entry, given the value as stored.
+    pub(crate) fn value_key(vertex_id: Uuid, name: &str, value_json: &[u8]) -> Vec<u8> {
+        let mut key = Self::value_prefix(name, value_json);
+        key.extend_from_slice(vertex_id.as_bytes());
+        key
+    }
+
+    /// Iterates over the IDs of the vertices whose property `name` is
+    /// `value`, in ID order, using the value index.
+    pub fn iterate_for_value(&self, name: &str, value: &JsonValue) -> Result<impl Iterator<Item = Result<Uuid>> + '_> {
+        let prefix = Self::value_prefix(name, &serde_json::to_vec(value)?);
+        let prefix_len = prefix.len();
+
+        Ok(self.value_tree.scan_prefix(&prefix).keys().filter_map(move |item| {
+            let k = match map_err(item) {
+                Ok(k) => k,
+                Err(err) => return Some(Err(err)),
+            };
+
+            // Values that start with the bytes of this one share the
+            // prefix, but their keys are longer.
+            if k.len() != prefix_len + 16 {
+                return None;
+            }
+
+            let mut decoder = Decoder::key(&self.value_tree, &k);
+            let item = decoder.skip(prefix_len).and_then(|()| decoder.read_uuid());
+            self.holder.decode_errors.filter(item)
+        }))
+    }
+
+    /// Builds the prefix shared by the index entries of a property. The
+    /// name is escaped, since it's followed by the value, and one name
+    /// mustn't be a prefix of another's entries.
+    pub(crate) fn name_prefix(name: &str) -> Vec<u8> {
+        let mut prefix = Vec::with_capacity(name.len() + 2);
+        escape(name.as_bytes(), &mut prefix);
+        prefix
+    }
+
+    /// Builds the key of a number index entry, given the value as stored,
+    /// or returns `None` if the value isn't a number. Integers beyond 2^53
+    /// are indexed by their nearest float.
+    pub(crate) fn number_key(vertex_id: Uuid, name: &str, value_json: &[u8]) -> Option<Vec<u8>> {
+        match value_json.first() {
+            Some(b'-') | Some(b'0'..=b'9') => {}
+            _ => return None,
+        }
+
+        let number: f64 = serde_json::from_slice(value_json).ok()?;
+        let mut key = Self::name_prefix(name);
+        key.extend_from_slice(&encode_indexed_number(number).to_be_bytes());
+        key.extend_from_slice(vertex_id.as_bytes());
+        Some(key)
+    }
+
+    /// Iterates over the IDs of the vertices whose property `name` is a
+    /// number within `range`, in numeric order, then ID order, using the
+    /// number index.
+    pub fn iterate_for_number_range<R: RangeBounds<f64>>(
+        &self,
+        name: &str,
+        range: R,
+    ) -> impl Iterator<Item = Result<(f64, Uuid)>> + '_ {
+        let prefix = Self::name_prefix(name);
+        let prefix_len = prefix.len();
+        let (low, high) = encoded_number_bounds(&range).unwrap_or((1, 0));
+
+        let mut start = prefix.clone();
+        start.extend_from_slice(&low.to_be_bytes());
+        let tree = &self.number_tree;
+
+        tree.range(start..)
+            .keys()
+            .take_while(move |item| match *item {
+                Ok(ref k) => k.starts_with(&prefix),
+                Err(_) => true,
+            })
+            .map(move |item| {
+                let k = map_err(item)?;
+                let mut decoder = Decoder::key(tree, &k);
+                decoder.skip(prefix_len)?;
+                let bits = u64::from_be_bytes(decoder.read_bytes(8)?.try_into().unwrap());
+                let id = decoder.read_uuid()?;
+                Ok((bits, id))
+            })
+            .filter_map(move |item| self.holder.decode_errors.filter(item))
+            .take_while(move |item| match *item {
+                Ok((bits, _)) => bits <= high,
+                Err(_) => true,
+            })
+            .map(|item| item.map(|(bits, id)| (decode_number(bits), id)))
+    }
+
+    /// Records that a vertex holds a value of a unique property, or fails
+    /// with `Error::UniqueValueTaken` if another vertex already does. The
+    /// record is taken with a compare-and-swap, so of two vertices racing
+    /// for a value, only one gets it. Returns whether the record is new.
+    fn claim(&self, vertex_id: Uuid, name: &str, value_json: &[u8]) -> Result<bool> {
+        let tree = &self.holder.indexed.unique_values;
+        let key = Self::value_prefix(name, value_json);
+        let result = self
+            .holder
+            .retrier
+            .run(|| tree.compare_and_swap(&key, None as Option<&[u8]>, Some(vertex_id.as_bytes())))?;
+
+        match result {
+            Ok(()) => Ok(true),
+            Err(CompareAndSwapError {
+                current: Some(current), ..
+            }) => {
+                if current == vertex_id.as_bytes() {
+                    return Ok(false);
+                }
+
+                Err(Error::UniqueValueTaken {
+                    name: name.to_string(),
+                    vertex_id: Decoder::value(tree, &key, &current).read_uuid()?,
+                }
+                .into())
+            }
+            Err(CompareAndSwapError { current: None, .. }) => unreachable!(),
+        }
+    }
+
+    /// Removes the record that a vertex holds a value of a unique property,
+    /// unless another vertex took the value since.
+    fn release(&self, vertex_id: Uuid, name: &str, value_json: &[u8]) -> Result<()> {
+        let tree = &self.holder.indexed.unique_values;
+        let key = Self::value_prefix(name, value_json);
+        self.holder
+            .retrier
+            .run(|| tree.compare_and_swap(&key, Some(vertex_id.as_bytes()), None as Option<&[u8]>))?
+            .ok();
+        Ok(())
+    }
+
+    /// Moves a vertex's value, number, full-text, vector and composite
+    /// index entries for a property from its old value to its new one,
+    /// given the values as stored. `None` means the vertex doesn't have the
+    /// property. The new value has to be written already, for the
+    /// composite indexes.
+    fn update_value_index(
+        &self,
+        vertex_id: Uuid,
+        name: &str,
+        old_value_json: Option<&[u8]>,
+        new_value_json: Option<&[u8]>,
+    ) -> Result<()> {
+        let mut batch = MultiBatch::default();
+        self.stage_value_index(&mut batch, vertex_id, name, old_value_json, new_value_json);
+        batch.apply(self.holder)?;
+        self.update_search_indexes(vertex_id, name, old_value_json, new_value_json)
+    }
+
+    /// Adds the changes to a vertex's value and number index entries for a
+    /// property to `batch`, so that they're applied along with the property
+    /// itself. Entries are only kept for indexed properties.
+    fn stage_value_index(
+        &self,
+        batch: &mut MultiBatch,
+        vertex_id: Uuid,
+        name: &str,
+        old_value_json: Option<&[u8]>,
+        new_value_json: Option<&[u8]>,
+    ) {
+        // Bulk loads rebuild these indexes once they're finished.
+        if old_value_json == new_value_json || self.holder.is_bulk_loading() || !self.holder.is_property_indexed(name) {
+            return;
+        }
+
+        if let Some(old_value_json) = old_value_json {
+            batch.remove_index(&self.value_tree, Self::value_key(vertex_id, name, old_value_json));
+
+            if let Some(key) = Self::number_key(vertex_id, name, old_value_json) {
+                batch.remove_index(&self.number_tree, key);
+            }
+        }
+
+        if let Some(new_value_json) = new_value_json {
+            batch.insert_index(&self.value_tree, Self::value_key(vertex_id, name, new_value_json), &[]);
+
+            if let Some(key) = Self::number_key(vertex_id, name, new_value_json) {
+                batch.insert_index(&self.number_tree, key, &[]);
+            }
+        }
+    }
+
+    /// Updates the full-text, vector and composite indexes after a property
+    /// was written. These are kept in trees of their own, outside of the
+    /// transaction the property is written in, and are rebuilt when a bulk
+    /// load finishes.
+    fn update_search_indexes(
+        &self,
+        vertex_id: Uuid,
+        name: &str,
+        old_value_json: Option<&[u8]>,
+        new_value_json: Option<&[u8]>,
+    ) -> Result<()> {
+        if old_value_json == new_value_json || self.holder.is_bulk_loading() {
+            return Ok(());
+        }
+
+        fulltext::on_property_change(self.holder, vertex_id, name, old_value_json, new_value_json)?;
+        vectors::on_property_change(self.holder, vertex_id, name, old_value_json, new_value_json)?;
+        composite::on_property_change(self.holder, vertex_id, name)
+    }
+
+    fn key(&self, vertex_id: Uuid, name: &str) -> Vec<u8> {
+        util::build(&[
+            util::Component::Uuid(vertex_id),
+            util::Component::FixedLengthString(name),
+        ])
+    }
+
+    /// Gets the JSON of a property value as stored under `key`.
+    fn resolve<'a>(&self, key: &[u8], stored: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+        dedup::resolve(&self.holder.values.store, self.tree, key, stored)
+    }
+
+    pub fn iterate_for_owner(&self, vertex_id: Uuid) -> Result<impl Iterator<Item = Result<OwnedPropertyItem>> + '_> {
+        let prefix = util::build(&[util::Component::Uuid(vertex_id)]);
+        let iterator = self.tree.scan_prefix(&prefix);
+
+        Ok(iterator
+            .map(move |item| -> Result<OwnedPropertyItem> {
+                let (k, v) = map_err(item)?;
+                let mut decoder = Decoder::key(self.tree, &k);
+                let owner_id = decoder.read_uuid()?;
+                let name = decoder.read_fixed_length_string()?;
+                let value = serde_json::from_slice(&self.resolve(&k, &v)?).map_err(|_| corruption(self.tree, &k))?;
+                Ok(((owner_id, name), value))
+            })
+            .filter_map(move |item| self.holder.decode_errors.filter(item)))
+    }
+
+    /// Counts the properties of a vertex, without reading their values.
+    pub fn count_for_owner(&self, vertex_id: Uuid) -> Result<u64> {
+        let prefix = util::build(&[util::Component::Uuid(vertex_id)]);
+        let mut count = 0;
+
+        for item in self.tree.scan_prefix(&prefix).keys() {
+            map_err(item)?;
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    pub fn get(&self, vertex_id: Uuid, name: &str) -> Result<Option<JsonValue>> {
+        let key = self.key(vertex_id, name);
+
+        match self.holder.retrier.run(|| self.tree.get(&key))? {
+            Some(stored) => Ok(Some(serde_json::from_slice(&self.resolve(&key, &stored)?)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn set(&self, vertex_id: Uuid, name: &str, value: &JsonValue) -> Result<()> {
+        let key = self.key(vertex_id, name);
+        let value_json = serde_json::to_vec(value)?;
+        let unique = self.holder.is_property_unique(name);
+        let claimed = unique && self.claim(vertex_id, name, &value_json)?;
+
+        // A new claim is released if the property isn't written, so that
+        // the value isn't left taken by a vertex that doesn't hold it.
+        let (old_value, old_value_json) = match self.write(vertex_id, name, &key, &value_json) {
+            Ok(written) => written,
+            Err(err) => {
+                if claimed {
+                    self.release(vertex_id, name, &value_json)?;
+                }
+
+                return Err(err);
+            }
+        };
+        let old_value_json = old_value_json.as_deref();
+
+        if let Some(ref old_value) = old_value {
+            dedup::release(self.holder, old_value)?;
+        }
+
+        if let Some(old_value_json) = old_value_json {
+            if unique && old_value_json != &value_json[..] {
+                self.release(vertex_id, name, old_value_json)?;
+            }
+        }
+
+        if old_value.is_none() {
+            CatalogManager::new(self.holder).increment(CatalogKind::VertexProperty, name.as_bytes())?;
+        }
+
+        self.update_search_indexes(vertex_id, name, old_value_json, Some(&value_json))?;
+        self.holder.notify_mutation()?;
+        self.update_derived(vertex_id, name)
+    }
+
+    /// Writes a property for `set`, in a single transaction with its value
+    /// and number index entries and its history. Returns the value it
+    /// replaced, as stored and as JSON. Nothing is left acquired from the
+    /// value store if this fails.
+    fn write(
+        &self,
+        vertex_id: Uuid,
+        name: &str,
+        key: &[u8],
+        value_json: &[u8],
+    ) -> Result<(Option<IVec>, Option<Vec<u8>>)> {
+        let stored = dedup::acquire(self.holder, name, value_json)?;
+        let result = self.write_stored(vertex_id, name, key, value_json, &stored);
+
+        if result.is_err() {
+            dedup::release(self.holder, &stored)?;
+        }
+
+        result
+    }
+
+    fn write_stored(
+        &self,
+        vertex_id: Uuid,
+        name: &str,
+        key: &[u8],
+        value_json: &[u8],
+        stored: &[u8],
+    ) -> Result<(Option<IVec>, Option<Vec<u8>>)> {
+        let old_value = self.holder.retrier.run(|| self.tree.get(key))?;
+        let old_value_json = match old_value {
+            Some(ref old_value) => Some(self.resolve(key, old_value)?.into_owned()),
+            None => None,
+        };
+
+        let mut batch = MultiBatch::default();
+        batch.insert(self.tree, key, stored);
+        self.stage_value_index(&mut batch, vertex_id, name, old_value_json.as_deref(), Some(value_json));
+
+        if self.holder.history.enabled {
+            let history_key = history_property_key(&util::build(&[util::Component::Uuid(vertex_id)]), name);
+            HistoryManager::new(&self.holder.retrier, &self.holder.history.vertex_properties).stage_record(
+                &mut batch,
+                &history_key,
+                Utc::now(),
+                Some(value_json),
+            );
+        }
+
+        batch.apply(self.holder)?;
+        Ok((old_value, old_value_json))
+    }
+
+    pub fn delete(&self, vertex_id: Uuid, name: &str) -> Result<()> {
+        let key = self.key(vertex_id, name);
+        let old_value = self.holder.retrier.run(|| self.tree.get(&key))?;
+        let old_value_json = match old_value {
+            Some(ref old_value) => Some(self.resolve(&key, old_value)?),
+            None => None,
+        };
+        let old_value_json = old_value_json.as_ref().map(|old_value_json| &old_value_json[..]);
+        let mut batch = MultiBatch::default();
+
+        if old_value_json.is_some() {
+            batch.remove(self.tree, key.as_slice());
+            self.stage_value_index(&mut batch, vertex_id, name, old_value_json, None);
+        }
+
+        if self.holder.history.enabled {
+            let history_key = history_property_key(&util::build(&[util::Component::Uuid(vertex_id)]), name);
+            HistoryManager::new(&self.holder.retrier, &self.holder.history.vertex_properties).stage_record(
+                &mut batch,
+                &history_key,
+                Utc::now(),
+                None,
+            );
+        }
+
+        batch.apply(self.holder)?;
+
+        if let Some(ref old_value) = old_value {
+            dedup::release(self.holder, old_value)?;
+        }
+
+        if let Some(old_value_json) = old_value_json {
+            CatalogManager::new(self.holder).decrement(CatalogKind::VertexProperty, name.as_bytes())?;
+
+            if self.holder.is_property_unique(name) {
+                self.release(vertex_id, name, old_value_json)?;
+            }
+        }
+
+        self.update_search_indexes(vertex_id, name, old_value_json, None)?;
+        self.holder.notify_mutation()?;
+        self.update_derived(vertex_id, name)
+    }
+
+    /// Sets several vertex properties. Unlike calling `set` for each
+    /// property, the writes are grouped into a single sled transaction.
+    /// `items` must not contain duplicate `(vertex_id, name)` pairs.
+    pub fn set_many(&self, items: &[(Uuid, String, JsonValue)]) -> Result<()> {
+        self.set_many_with(items, MultiBatch::default())
+    }
+
+    /// Like `set_many`, with the writes in `batch` applied in the same sled
+    /// transaction.
+    pub(crate) fn set_many_with(&self, items: &[(Uuid, String, JsonValue)], mut batch: MultiBatch) -> Result<()> {
+        let mut new_properties_per_name: HashMap<&str, i64> = HashMap::new();
+        let mut changes = Vec::new();
+        let mut replaced_unique_values = Vec::new();
+        let mut replaced_values = Vec::new();
+        let history_manager = HistoryManager::new(&self.holder.retrier, &self.holder.history.vertex_properties);
+        let now = Utc::now();
+
+        // Unique values are claimed before anything is written, so that if
+        // one is taken, the properties are left as they were.
+        let mut claimed = Vec::new();
+        for &(vertex_id, ref name, ref value) in items {
+            if !self.holder.is_property_unique(name) {
+                continue;
+            }
+
+            let value_json = serde_json::to_vec(value)?;
+
+            match self.claim(vertex_id, name, &value_json) {
+                Ok(true) => claimed.push((vertex_id, name, value_json)),
+                Ok(false) => {}
+                Err(err) => {
+                    for (vertex_id, name, value_json) in claimed {
+                        self.release(vertex_id, name, &value_json)?;
+                    }
+
+                    return Err(err);
+                }
+            }
+        }
+
+        // As with a taken value, the claims and the values acquired from the
+        // value store are released if the properties aren't written.
+        let mut acquired = Vec::new();
+        let mut write = |batch: &mut MultiBatch| -> Result<()> {
+            for &(vertex_id, ref name, ref value) in items {
+                let key = self.key(vertex_id, name);
+                let value_json = serde_json::to_vec(value)?;
+                let stored = dedup::acquire(self.holder, name, &value_json)?;
+                acquired.push(stored.clone());
+
+                let old_stored = self.holder.retrier.run(|| self.tree.get(&key))?;
+                let old_value = match old_stored {
+                    Some(ref old_stored) => Some(self.resolve(&key, old_stored)?.into_owned()),
+                    None => None,
+                };
+                let old_value = old_value.as_ref().map(|old_value| &old_value[..]);
+
+                if let Some(old_stored) = old_stored {
+                    replaced_values.push(old_stored);
+                }
+
+                if old_value != Some(&value_json[..]) {
+                    match old_value {
+                        None => *new_properties_per_name.entry(name).or_insert(0) += 1,
+                        Some(old_value) if self.holder.is_property_unique(name) => {
+                            replaced_unique_values.push((vertex_id, name, old_value.to_vec()))
+                        }
+                        Some(_) => {}
+                    }
+
+                    changes.push((
+                        vertex_id,
+                        name,
+                        old_value.map(|old_value| old_value.to_vec()),
+                        value_json.clone(),
+                    ));
+                }
+
+                self.stage_value_index(batch, vertex_id, name, old_value, Some(&value_json));
+
+                if self.holder.history.enabled {
+                    let history_key = history_property_key(&util::build(&[util::Component::Uuid(vertex_id)]), name);
+                    history_manager.stage_record(batch, &history_key, now, Some(&value_json));
+                }
+
+                batch.insert(self.tree, key, stored);
+            }
+
+            batch.apply(self.holder)
+        };
+
+        if let Err(err) = write(&mut batch) {
+            for (vertex_id, name, value_json) in claimed {
+                self.release(vertex_id, name, &value_json)?;
+            }
+
+            for stored in acquired {
+                dedup::release(self.holder, &stored)?;
+            }
+
+            return Err(err);
+        }
+
+        for old_stored in replaced_values {
+            dedup::release(self.holder, &old_stored)?;
+        }
+
+        // Composite entries are built from the current values, so these
+        // indexes are updated once the transaction is applied.
+        for (vertex_id, name, old_value_json, value_json) in changes {
+            self.update_search_indexes(
+                vertex_id,
+                name,
+                old_value_json.as_ref().map(|v| &v[..]),
+                Some(&value_json),
+            )?;
+        }
+
+        for (vertex_id, name, old_value_json) in replaced_unique_values {
+            self.release(vertex_id, name, &old_value_json)?;
+        }
+
+        let catalog_manager = CatalogManager::new(self.holder);
+        for (name, count) in new_properties_per_name {
+            catalog_manager.adjust(CatalogKind::VertexProperty, name.as_bytes(), count)?;
+        }
+
+        self.holder.notify_mutation()?;
+
+        // Derived properties are recomputed once per vertex, rather than
+        // once per property written.
+        let mut updated = HashSet::new();
+        for &(vertex_id, ref name, _) in items {
+            if updated.insert(vertex_id) {
+                self.update_derived(vertex_id, name)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Applies a JSON merge patch (RFC 7396) to the properties of a vertex,
+    /// with each member of `patch` patching the property of the same name.
+    /// The properties are read, merged and written in a single sled
+    /// transaction, along with the records of their unique values, so
+    /// concurrent writes to them can't be lost. Patched values are stored
+    /// inline, rather than in the value store.
+    pub fn patch(&self, vertex_id: Uuid, patch: &JsonMap<String, JsonValue>) -> Result<()> {
+        // The value store can only be read within the transaction if it's
+        // part of it, which it can't be once it's in the cold database.
+        // Reading it directly from within the transaction would deadlock,
+        // so its values are read beforehand, and the patch starts over if
+        // any of them were replaced in the meantime.
+        let result = match self.holder.cold_db {
+            Some(_) => loop {
+                let resolved = self.resolve_references(vertex_id, patch)?;
+                let stale = Cell::new(false);
+
+                let result = (self.tree, &self.holder.indexed.unique_values).transaction(|(tx, unique_tx)| {
+                    self.patch_in(tx, unique_tx, vertex_id, patch, |key, stored| {
+                        if !dedup::is_reference(stored) {
+                            return Ok(stored.to_vec());
+                        }
+
+                        match resolved.get(key) {
+                            Some((resolved_stored, value_json)) if resolved_stored == stored => Ok(value_json.clone()),
+                            _ => {
+                                stale.set(true);
+                                Err(ConflictableTransactionError::Abort(corruption(self.tree, key)))
+                            }
+                        }
+                    })
+                });
+
+                if !stale.get() {
+                    break result;
+                }
+            },
+            None => (self.tree, &self.holder.indexed.unique_values, &self.holder.values.store).transaction(
+                |(tx, unique_tx, value_store_tx)| {
+                    self.patch_in(tx, unique_tx, vertex_id, patch, |key, stored| {
+                        dedup::resolve_transactional(value_store_tx, self.tree, key, stored).map(Cow::into_owned)
+                    })
+                },
+            ),
+        };
+
+        let changes = match result {
+            Ok(changes) => changes,
+            Err(TransactionError::Storage(err)) => return map_err(Err(err)),
+            Err(TransactionError::Abort(err)) => return Err(err),
+        };
+
+        let catalog_manager = CatalogManager::new(self.holder);
+        let mut changed_name = None;
+        let now = Utc::now();
+
+        for (name, old_stored, old_value_json, new_value_json) in changes {
+            if let Some(ref old_stored) = old_stored {
+                dedup::release(self.holder, old_stored)?;
+            }
+
+            let (old_value_json, new_value_json) = (
+                old_value_json.as_ref().map(|v| &v[..]),
+                new_value_json.as_ref().map(|v| &v[..]),
+            );
+
+            if old_value_json == new_value_json {
+                continue;
+            }
+
+            match (old_value_json, new_value_json) {
+                (None, Some(_)) => catalog_manager.increment(CatalogKind::VertexProperty, name.as_bytes())?,
+                (Some(_), None) => catalog_manager.decrement(CatalogKind::VertexProperty, name.as_bytes())?,
+                _ => {}
+            }
+
+            self.update_value_index(vertex_id, name, old_value_json, new_value_json)?;
+
+            if self.holder.history.enabled {
+                let history_key = history_property_key(&util::build(&[util::Component::Uuid(vertex_id)]), name);
+                HistoryManager::new(&self.holder.retrier, &self.holder.history.vertex_properties).record(
+                    &history_key,
+                    now,
+                    new_value_json,
+                )?;
+            }
+
+            changed_name = changed_name.or(Some(name));
+        }
+
+        self.holder.notify_mutation()?;
+
+        // As with `set_many`, derived properties are recomputed once.
+        match changed_name {
+            Some(name) => self.update_derived(vertex_id, name),
+            None => Ok(()),
+        }
+    }
+
+    /// Reads the value store entries referred to by the properties a patch
+    /// touches, keyed by property key, along with the stored references.
+    fn resolve_references(&self, vertex_id: Uuid, patch: &JsonMap<String, JsonValue>) -> Result<ResolvedReferences> {
+        let mut resolved = HashMap::new();
+
+        for name in patch.keys() {
+            let key = self.key(vertex_id, name);
+
+            if let Some(stored) = self.holder.retrier.run(|| self.tree.get(&key))? {
+                if dedup::is_reference(&stored) {
+                    let value_json = dedup::resolve(&self.holder.values.store, self.tree, &key, &stored)?.into_owned();
+                    resolved.insert(key, (stored, value_json));
+                }
+            }
+        }
+
+        Ok(resolved)
+    }
+
+    /// Patches the properties of a vertex within a transaction, returning
+    /// each patched property's name, old stored bytes, old JSON and new
+    /// JSON. `resolve` gets the JSON of a stored value, given its key.
+    fn patch_in<'p, F>(
+        &self,
+        tx: &TransactionalTree,
+        unique_tx: &TransactionalTree,
+        vertex_id: Uuid,
+        patch: &'p JsonMap<String, JsonValue>,
+        resolve: F,
+    ) -> ConflictableTransactionResult<PatchChanges<'p>, IndradbError>
+    where
+        F: Fn(&[u8], &[u8]) -> ConflictableTransactionResult<Vec<u8>, IndradbError>,
+    {
+        let mut changes = Vec::with_capacity(patch.len());
+
+        for (name, patch_value) in patch {
+            let key = self.key(vertex_id, name);
+            let old_stored = tx.get(key.as_slice())?;
+            let old_value_json = match old_stored {
+                Some(ref old_stored) => Some(resolve(&key, old_stored)?),
+                None => None,
+            };
+
+            let old_value: Option<JsonValue> = match old_value_json {
+                Some(ref old_value_json) => Some(
+                    serde_json::from_slice(old_value_json)
+                        .map_err(|_| ConflictableTransactionError::Abort(corruption(self.tree, &key)))?,
+                ),
+                None => None,
+            };
+
+            let new_value_json = match patch::merge(old_value.as_ref(), patch_value) {
+                Some(new_value) => {
+                    let new_value_json = serde_json::to_vec(&new_value).unwrap();
+                    tx.insert(key.as_slice(), new_value_json.as_slice())?;
+                    Some(new_value_json)
+                }
+                None => {
+                    tx.remove(key.as_slice())?;
+                    None
+                }
+            };
+
+            let unique_changed = self.holder.is_property_unique(name)
+                && old_value_json.as_ref().map(|v| &v[..]) != new_value_json.as_ref().map(|v| &v[..]);
+
+            if unique_changed {
+                if let Some(ref new_value_json) = new_value_json {
+                    let unique_key = Self::value_prefix(name, new_value_json);
+
+                    match unique_tx.get(unique_key.as_slice())? {
+                        Some(ref current) if current != vertex_id.as_bytes() => {
+                            let err = match Decoder::value(&self.holder.indexed.unique_values, &unique_key, current)
+                                .read_uuid()
+                            {
+                                Ok(other_id) => Error::UniqueValueTaken {
+                                    name: name.to_string(),
+                                    vertex_id: other_id,
+                                }
+                                .into(),
+                                Err(err) => err,
+                            };
+                            return Err(ConflictableTransactionError::Abort(err));
+                        }
+                        Some(_) => {}
+                        None => {
+                            unique_tx.insert(unique_key.as_slice(), vertex_id.as_bytes())?;
+                        }
+                    }
+                }
+
+                if let Some(ref old_value_json) = old_value_json {
+                    let unique_key = Self::value_prefix(name, old_value_json);
+
+                    if unique_tx.get(unique_key.as_slice())?.as_ref().map(|v| &v[..]) == Some(vertex_id.as_bytes()) {
+                        unique_tx.remove(unique_key.as_slice())?;
+                    }
+                }
+            }
+
+            changes.push((name, old_stored, old_value_json, new_value_json));
+        }
+
+        Ok(changes)
+    }
+
+    /// Moves a property from one vertex to another, replacing the second
+    /// vertex's property of the same name, if any. The removal and the
+    /// insertion are applied in a single sled transaction, so concurrent
+    /// readers and writers never see the property on both vertices, or on
+    /// neither. Returns whether the first vertex had the property.
+    pub fn move_value(&self, from_id: Uuid, to_id: Uuid, name: &str) -> Result<bool> {
+        let from_key = self.key(from_id, name);
+        let to_key = self.key(to_id, name);
+
+        let result = self.tree.transaction(|tx| {
+            let value = match tx.remove(from_key.as_slice())? {
+                Some(value) => value,
+                None => return Ok(None),
+            };
+
+            let replaced = tx.insert(to_key.as_slice(), value.clone())?;
+            Ok(Some((value, replaced)))
+        });
+
+        // The stored value moves as it is, so any reference it holds moves
+        // with it, while the replaced value's is released.
+        let (stored, replaced_stored) = match result {
+            Ok(Some(moved)) => moved,
+            Ok(None) => return Ok(false),
+            Err(TransactionError::Storage(err)) => return map_err(Err(err)),
+            Err(TransactionError::Abort(())) => unreachable!(),
+        };
+
+        let value_json = self.resolve(&to_key, &stored)?.into_owned();
+        let replaced = match replaced_stored {
+            Some(ref replaced_stored) => {
+                let replaced = self.resolve(&to_key, replaced_stored)?.into_owned();
+                dedup::release(self.holder, replaced_stored)?;
+                Some(replaced)
+            }
+            None => None,
+        };
+
+        // One vertex fewer has the property, unless the other vertex
+        // already had one.
+        if replaced.is_some() {
+            CatalogManager::new(self.holder).decrement(CatalogKind::VertexProperty, name.as_bytes())?;
+        }
+
+        // A unique value stays unique, since it's no longer on the first
+        // vertex, so its record just moves.
+        if self.holder.is_property_unique(name) {
+            let tree = &self.holder.indexed.unique_values;
+            let key = Self::value_prefix(name, &value_json);
+            self.holder
+                .retrier
+                .run(|| tree.compare_and_swap(&key, Some(from_id.as_bytes()), Some(to_id.as_bytes())))?
+                .ok();
+
+            if let Some(ref replaced) = replaced {
+                if replaced != &value_json {
+                    self.release(to_id, name, replaced)?;
+                }
+            }
+        }
+
+        self.update_value_index(from_id, name, Some(&value_json), None)?;
+        self.update_value_index(
+            to_id,
+            name,
+            replaced.as_ref().map(|replaced| &replaced[..]),
+            Some(&value_json),
+        )?;
+
+        if self.holder.history.enabled {
+            let history_manager = HistoryManager::new(&self.holder.retrier, &self.holder.history.vertex_properties);
+            let now = Utc::now();
+            let from_history_key = history_property_key(&util::build(&[util::Component::Uuid(from_id)]), name);
+            let to_history_key = history_property_key(&util::build(&[util::Component::Uuid(to_id)]), name);
+            history_manager.record(&from_history_key, now, None)?;
+            history_manager.record(&to_history_key, now, Some(&value_json))?;
+        }
+
+        self.holder.notify_mutation()?;
+        self.update_derived(from_id, name)?;
+        self.update_derived(to_id, name)?;
+        Ok(true)
+    }
+
+    /// Recomputes the derived properties of a vertex after its property
+    /// `name` was written. Writes to derived properties themselves, and to
+    /// vertices that no longer exist, don't trigger this.
+    fn update_derived(&self, vertex_id: Uuid, name: &str) -> Result<()> {
+        let derived_properties = &self.holder.derived_properties;
+
+        if derived_properties.is_empty() || derived_properties.iter().any(|derived| derived.name == name) {
+            return Ok(());
+        }
+
+        let t = match VertexManager::new(self.holder).get(vertex_id)? {
+            Some(t) => t,
+            None => return Ok(()),
+        };
+
+        let mut properties: Option<Vec<NamedProperty>> = None;
+
+        for derived in derived_properties.iter().filter(|derived| derived.t == t) {
+            if properties.is_none() {
+                let mut source_properties = Vec::new();
+
+                for item in self.iterate_for_owner(vertex_id)? {
+                    let ((_, name), value) = item?;
+                    if !derived_properties.iter().any(|derived| derived.name == name) {
+                        source_properties.push(NamedProperty::new(name, value));
+                    }
+                }
+
+                properties = Some(source_properties);
+            }
+
+            let value = (derived.derive)(properties.as_ref().unwrap());
+
+            if value != self.get(vertex_id, &derived.name)? {
+                match value {
+                    Some(value) => self.set(vertex_id, &derived.name, &value)?,
+                    None => self.delete(vertex_id, &derived.name)?,
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Edge properties staged by `EdgePropertyManager::stage_many`.
+#[derive(Default)]
+pub(crate) struct StagedEdgeProperties {
+    // The stored values, which hold references to the value store.
+    acquired: Vec<Vec<u8>>,
+    replaced: Vec<IVec>,
+    new_properties_per_name: HashMap<String, i64>,
+}
+
+pub struct EdgePropertyManager<'db: 'tree, 'tree> {
+    pub holder: &'db SledHolder,
+    pub tree: &'tree Tree,
+    /// Indexes edge properties by `(inbound_id, type, outbound_id, name)`,
+    /// so the properties of a vertex's inbound edges can be found with a
+    /// prefix scan.
+    pub reversed_tree: IndexWriter,
+    /// Indexes edge properties by `(vertex_id, outbound_id, type,
+    /// inbound_id, name)` for each of the edge's vertices, so that the
+    /// properties of all of a vertex's edges can be found with a single
+    /// prefix scan when the vertex is deleted.
+    pub vertex_tree: IndexWriter,
+    /// Indexes edge properties by `(name, value, outbound_id, type,
+    /// inbound_id)`, so the edges with a given property value can be found
+    /// with a prefix scan. The value is the property's JSON, as stored,
+    /// escaped since the edge's variable-length key follows it.
+    pub value_tree: IndexWriter,
+    /// Indexes numeric edge properties by `(name, number, outbound_id,
+    /// type, inbound_id)`, as the vertex property number index does.
+    pub number_tree: IndexWriter,
+}
+
+impl<'db: 'tree, 'tree> EdgePropertyManager<'db, 'tree> {
+    pub fn new(ds: &'db SledHolder) -> Self {
+        EdgePropertyManager {
+            holder: ds,
+            tree: &ds.edge_properties,
+            reversed_tree: ds.reversed_edge_properties.writer(),
+            vertex_tree: ds.vertex_edge_properties.writer(),
+            value_tree: ds.edge_property_values.writer(),
+            number_tree: ds.edge_property_numbers.writer(),
+        }
+    }
+
+    pub(crate) fn build_key(outbound_id: Uuid, t: &Type, inbound_id: Uuid, name: &str) -> Vec<u8> {
+        util::build(&[
+            util::Component::Uuid(outbound_id),
+            util::Component::Type(t),
+            util::Component::Uuid(inbound_id),
+            util::Component::FixedLengthString(name),
+        ])
+    }
+
+    /// Gets the JSON of a property value as stored under `key`.
+    fn resolve<'a>(&self, key: &[u8], stored: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+        dedup::resolve(&self.holder.values.store, self.tree, key, stored)
+    }
+
+    fn key(&self, outbound_id: Uuid, t: &Type, inbound_id: Uuid, name: &str) -> Vec<u8> {
+        Self::build_key(outbound_id, t, inbound_id, name)
+    }
+
+    /// Gets the update datetime of an edge if its type is sorted by the
+    /// property `name`, in which case its range entries are keyed by the
+    /// property's value, and have to be rewritten around writes to it.
+    fn get_sorted_edge(
+        &self,
+        outbound_id: Uuid,
+        t: &Type,
+        inbound_id: Uuid,
+        name: &str,
+    ) -> Result<Option<DateTime<Utc>>> {
+        if self.is_sort_property(t, name) {
+            EdgeManager::new(self.holder).get(outbound_id, t, inbound_id)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn is_sort_property(&self, t: &Type, name: &str) -> bool {
+        matches!(self.holder.edge_range_layout.sort_key(t), Some(sort_key) if sort_key.property == name)
+    }
+
+    pub(crate) fn reversed_key(outbound_id: Uuid, t: &Type, inbound_id: Uuid, name: &str) -> Vec<u8> {
+        util::build(&[
+            util::Component::Uuid(inbound_id),
+            util::Component::Type(t),
+            util::Component::Uuid(outbound_id),
+            util::Component::FixedLengthString(name),
+        ])
+    }
+
+    /// Builds the keys of the per-vertex index entries of a property: one
+    /// for each of the edge's vertices, or just one for a loop.
+    pub(crate) fn vertex_keys(outbound_id: Uuid, t: &Type, inbound_id: Uuid, name: &str) -> Vec<Vec<u8>> {
+        let property_key = Self::build_key(outbound_id, t, inbound_id, name);
+        let mut vertex_ids = vec![outbound_id];
+
+        if inbound_id != outbound_id {
+            vertex_ids.push(inbound_id);
+        }
+
+        vertex_ids
+            .into_iter()
+            .map(|vertex_id| [&vertex_id.as_bytes()[..], &property_key[..]].concat())
+            .collect()
+    }
+
+    /// Builds the prefix shared by the value index entries of a property
+    /// value, given the value as stored.
+    fn value_prefix(name: &str, value_json: &[u8]) -> Vec<u8> {
+        let mut prefix = VertexPropertyManager::name_prefix(name);
+        escape(value_json, &mut prefix);
+        prefix
+    }
+
+    /// Builds the key of a value index entry, given the value as stored.
+    pub(crate) fn value_key(outbound_id: Uuid, t: &Type, inbound_id: Uuid, name: &str, value_json: &[u8]) -> Vec<u8> {
+        let mut key = Self::value_prefix(name, value_json);
+        key.extend_from_slice(&EdgeManager::build_key(outbound_id, t, inbound_id));
+        key
+    }
+
+    /// Builds the key of a number index entry, given the value as stored,
+    /// or returns `None` if the value isn't a number.
+    pub(crate) fn number_key(
+        outbound_id: Uuid,
+        t: &Type,
+        inbound_id: Uuid,
+        name: &str,
+        value_json: &[u8],
+    ) -> Option<Vec<u8>> {
+        match value_json.first() {
+            Some(b'-') | Some(b'0'..=b'9') => {}
+            _ => return None,
+        }
+
+        let number: f64 = serde_json::from_slice(value_json).ok()?;
+        let mut key = VertexPropertyManager::name_prefix(name);
+        key.extend_from_slice(&encode_indexed_number(number).to_be_bytes());
+        key.extend_from_slice(&EdgeManager::build_key(outbound_id, t, inbound_id));
+        Some(key)
+    }
+
+    /// Iterates over the keys of the edges whose property `name` is
+    /// `value`, in key order, using the value index.
+    pub fn iterate_for_value(
+        &self,
+        name: &str,
+        value: &JsonValue,
+    ) -> Result<impl Iterator<Item = Result<EdgeKey>> + '_> {
+        let prefix = Self::value_prefix(name, &serde_json::to_vec(value)?);
+        let prefix_len = prefix.len();
+
+        Ok(self.value_tree.scan_prefix(&prefix).keys().filter_map(move |item| {
+            let item = map_err(item).and_then(|k| {
+                let mut decoder = Decoder::key(&self.value_tree, &k);
+                decoder.skip(prefix_len)?;
+                let outbound_id = decoder.read_uuid()?;
+                let t = decoder.read_type()?;
+                let inbound_id = decoder.read_uuid()?;
+                Ok(EdgeKey::new(outbound_id, t, inbound_id))
+            });
+
+            self.holder.decode_errors.filter(item)
+        }))
+    }
+
+    /// Iterates over the keys of the edges whose property `name` is a
+    /// number within `range`, in numeric order, then key order, using the
+    /// number index.
+    pub fn iterate_for_number_range<R: RangeBounds<f64>>(
+        &self,
+        name: &str,
+        range: R,
+    ) -> impl Iterator<Item = Result<(f64, EdgeKey)>> + '_ {
+        let prefix = VertexPropertyManager::name_prefix(name);
+        let prefix_len = prefix.len();
+        let (low, high) = encoded_number_bounds(&range).unwrap_or((1, 0));
+
+        let mut start = prefix.clone();
+        start.extend_from_slice(&low.to_be_bytes());
+        let tree = &self.number_tree;
+
+        tree.range(start..)
+            .keys()
+            .take_while(move |item| match *item {
+                Ok(ref k) => k.starts_with(&prefix),
+                Err(_) => true,
+            })
+            .map(move |item| {
+                let k = map_err(item)?;
+                let mut decoder = Decoder::key(tree, &k);
+                decoder.skip(prefix_len)?;
+                let bits = u64::from_be_bytes(decoder.read_bytes(8)?.try_into().unwrap());
+                let outbound_id = decoder.read_uuid()?;
+                let t = decoder.read_type()?;
+                let inbound_id = decoder.read_uuid()?;
+                Ok((bits, EdgeKey::new(outbound_id, t, inbound_id)))
+            })
+            .filter_map(move |item| self.holder.decode_errors.filter(item))
+            .take_while(move |item| match *item {
+                Ok((bits, _)) => bits <= high,
+                Err(_) => true,
+            })
+            .map(|item| item.map(|(bits, key)| (decode_number(bits), key)))
+    }
+
+    /// Adds what comes with a write of a property - `None` removing it -
+    /// to `batch`: the changes to its reversed and value index entries,
+    /// and its history. The property itself is written by the caller,
+    /// since what's stored may refer to the value store. Values are given
+    /// as JSON, and `old_value_json` is the current one, if any. Index
+    /// entries aren't kept while bulk loading, and value and number
+    /// entries are only kept for indexed properties.
+    #[allow(clippy::too_many_arguments)]
+    fn stage_entries(
+        &self,
+        batch: &mut MultiBatch,
+        outbound_id: Uuid,
+        t: &Type,
+        inbound_id: Uuid,
+        name: &str,
+        old_value_json: Option<&[u8]>,
+        new_value_json: Option<&[u8]>,
+        datetime: DateTime<Utc>,
+    ) {
+        if self.holder.history.enabled {
+            let history_key = history_property_key(&EdgeManager::build_key(outbound_id, t, inbound_id), name);
+            HistoryManager::new(&self.holder.retrier, &self.holder.history.edge_properties).stage_record(
+                batch,
+                &history_key,
+                datetime,
+                new_value_json,
+            );
+        }
+
+        if old_value_json == new_value_json || self.holder.is_bulk_loading() {
+            return;
+        }
+
+        let reversed_key = Self::reversed_key(outbound_id, t, inbound_id, name);
+
+        let vertex_keys = Self::vertex_keys(outbound_id, t, inbound_id, name);
+
+        match (old_value_json, new_value_json) {
+            (None, Some(_)) => {
+                batch.insert_index(&self.reversed_tree, reversed_key, &[]);
+
+                for vertex_key in vertex_keys {
+                    batch.insert_index(&self.vertex_tree, vertex_key, &[]);
+                }
+            }
+            (Some(_), None) => {
+                batch.remove_index(&self.reversed_tree, reversed_key);
+
+                for vertex_key in vertex_keys {
+                    batch.remove_index(&self.vertex_tree, vertex_key);
+                }
+            }
+            _ => {}
+        }
+
+        if !self.holder.is_edge_property_indexed(name) {
+            return;
+        }
+
+        if let Some(old_value_json) = old_value_json {
+            batch.remove_index(
+                &self.value_tree,
+                Self::value_key(outbound_id, t, inbound_id, name, old_value_json),
+            );
+
+            if let Some(key) = Self::number_key(outbound_id, t, inbound_id, name, old_value_json) {
+                batch.remove_index(&self.number_tree, key);
+            }
+        }
+
+        if let Some(new_value_json) = new_value_json {
+            batch.insert_index(
+                &self.value_tree,
+                Self::value_key(outbound_id, t, inbound_id, name, new_value_json),
+                &[],
+            );
+
+            if let Some(key) = Self::number_key(outbound_id, t, inbound_id, name, new_value_json) {
+                batch.insert_index(&self.number_tree, key, &[]);
+            }
+        }
+    }
+
+    /// Adds the removal of all of an edge's properties to `batch`, as with
+    /// `delete`. Returns their names and values as stored, so that the
+    /// catalog can be updated, and the values released, once the batch is
+    /// applied.
+    pub(crate) fn stage_delete_for_owner(
+        &self,
+        batch: &mut MultiBatch,
+        outbound_id: Uuid,
+        t: &Type,
+        inbound_id: Uuid,
+        datetime: DateTime<Utc>,
+    ) -> Result<Vec<(String, IVec)>> {
+        let (outbound_id, inbound_id) = self.holder.edge_range_layout.canonical_ids(outbound_id, t, inbound_id);
+        let prefix = util::build(&[
+            util::Component::Uuid(outbound_id),
+            util::Component::Type(t),
+            util::Component::Uuid(inbound_id),
+        ]);
+        let mut deleted = Vec::new();
+
+        let items = self
+            .tree
+            .scan_prefix(&prefix)
+            .map(|item| -> Result<(IVec, String, IVec)> {
+                let (k, v) = map_err(item)?;
+                let mut decoder = Decoder::key(self.tree, &k);
+                decoder.skip(prefix.len())?;
+                let name = decoder.read_fixed_length_string()?;
+                Ok((k, name, v))
+            })
+            .filter_map(|item| self.holder.decode_errors.filter(item));
+
+        for item in items {
+            let (key, name, stored) = item?;
+            self.stage_delete_stored(batch, outbound_id, t, inbound_id, &key, &stored, &name, datetime)?;
+            deleted.push((name, stored));
+        }
+
+        Ok(deleted)
+    }
+
+    /// Like `stage_delete_for_owner`, but for an edge whose property names
+    /// are already known, e.g. from `properties_of_vertex`, so that they're
+    /// looked up directly rather than scanned for. Names that aren't set
+    /// are skipped.
+    pub(crate) fn stage_delete_named(
+        &self,
+        batch: &mut MultiBatch,
+        outbound_id: Uuid,
+        t: &Type,
+        inbound_id: Uuid,
+        names: &[String],
+        datetime: DateTime<Utc>,
+    ) -> Result<Vec<(String, IVec)>> {
+        let (outbound_id, inbound_id) = self.holder.edge_range_layout.canonical_ids(outbound_id, t, inbound_id);
+        let mut deleted = Vec::new();
+
+        for name in names {
+            let key = self.key(outbound_id, t, inbound_id, name);
+
+            if let Some(stored) = self.holder.retrier.run(|| self.tree.get(&key))? {
+                self.stage_delete_stored(batch, outbound_id, t, inbound_id, &key, &stored, name, datetime)?;
+                deleted.push((name.clone(), stored));
+            }
+        }
+
+        Ok(deleted)
+    }
+
+    /// Adds the removal of a property, given its key and what's stored
+    /// under it, to `batch`.
+    #[allow(clippy::too_many_arguments)]
+    fn stage_delete_stored(
+        &self,
+        batch: &mut MultiBatch,
+        outbound_id: Uuid,
+        t: &Type,
+        inbound_id: Uuid,
+        key: &[u8],
+        stored: &[u8],
+        name: &str,
+        datetime: DateTime<Utc>,
+    ) -> Result<()> {
+        let value_json = self.resolve(key, stored)?;
+        self.stage_entries(
+            batch,
+            outbound_id,
+            t,
+            inbound_id,
+            name,
+            Some(&value_json),
+            None,
+            datetime,
+        );
+        batch.remove(self.tree, key);
+        Ok(())
+    }
+
+    /// Gets the names of the properties of every edge of a vertex, in
+    /// either direction, grouped by edge, with one scan of the per-vertex
+    /// index. The index isn't maintained during a bulk load.
+    pub(crate) fn properties_of_vertex(&self, vertex_id: Uuid) -> Result<HashMap<EdgeKey, Vec<String>>> {
+        let mut properties: HashMap<EdgeKey, Vec<String>> = HashMap::new();
+
+        let items = self
+            .vertex_tree
+            .scan_prefix(vertex_id.as_bytes())
+            .keys()
+            .map(|item| -> Result<(EdgeKey, String)> {
+                let k = map_err(item)?;
+                let mut decoder = Decoder::key(&self.vertex_tree, &k);
+                decoder.read_uuid()?;
+                let outbound_id = decoder.read_uuid()?;
+                let t = decoder.read_type()?;
+                let inbound_id = decoder.read_uuid()?;
+                let name = decoder.read_fixed_length_string()?;
+                Ok((EdgeKey::new(outbound_id, t, inbound_id), name))
+            })
+            .filter_map(|item| self.holder.decode_errors.filter(item));
+
+        for item in items {
+            let (key, name) = item?;
+            properties.entry(key).or_default().push(name);
+        }
+
+        Ok(properties)
+    }
+
+    /// Iterates over the properties of all inbound edges of a vertex,
+    /// ordered by edge type, then outbound ID, then name.
+    pub fn iterate_for_inbound(&self, inbound_id: Uuid) -> impl Iterator<Item = Result<EdgePropertyItem>> + '_ {
+        let prefix = util::build(&[util::Component::Uuid(inbound_id)]);
+
+        let mapped = self
+            .reversed_tree
+            .scan_prefix(&prefix)
+            .map(move |item| -> Result<Option<EdgePropertyItem>> {
+                let (k, _) = map_err(item)?;
+                let mut decoder = Decoder::key(&self.reversed_tree, &k);
+                let inbound_id = decoder.read_uuid()?;
+                let t = decoder.read_type()?;
+                let outbound_id = decoder.read_uuid()?;
+                let name = decoder.read_fixed_length_string()?;
+
+                // The index entry and the property are not written
+                // atomically, so skip entries whose property has since
+                // disappeared.
+                match self.get(outbound_id, &t, inbound_id, &name)? {
+                    Some(value) => Ok(Some(((outbound_id, t, inbound_id, name), value))),
+                    None => Ok(None),
+                }
+            });
+
+        mapped.filter_map(move |item| match item {
+            Err(err) => self.holder.decode_errors.filter(Err(err)),
+            Ok(Some(value)) => Some(Ok(value)),
+            Ok(None) => None,
+        })
+    }
+
+    pub fn iterate_for_owner<'a>(
+        &'a self,
+        outbound_id: Uuid,
+        t: &'a Type,
+        inbound_id: Uuid,
+    ) -> Result<Box<dyn Iterator<Item = Result<EdgePropertyItem>> + 'a>> {
+        let (outbound_id, inbound_id) = self.holder.edge_range_layout.canonical_ids(outbound_id, t, inbound_id);
+        let prefix = util::build(&[
+            util::Component::Uuid(outbound_id),
+            util::Component::Type(t),
+            util::Component::Uuid(inbound_id),
+        ]);
+
+        Ok(Box::new(self.iterate(&prefix)))
+    }
+
+    /// Iterates over the properties of all outbound edges of a vertex,
+    /// ordered by edge type, then inbound ID, then name.
+    pub fn iterate_for_outbound(&self, outbound_id: Uuid) -> impl Iterator<Item = Result<EdgePropertyItem>> + '_ {
+        self.iterate(&util::build(&[util::Component::Uuid(outbound_id)]))
+    }
+
+    fn iterate(&self, prefix: &[u8]) -> impl Iterator<Item = Result<EdgePropertyItem>> + '_ {
+        let iterator = self.tree.scan_prefix(prefix);
+
+        iterator
+            .map(move |item| -> Result<EdgePropertyItem> {
+                let (k, v) = map_err(item)?;
+                let mut decoder = Decoder::key(self.tree, &k);
+                let edge_property_outbound_id = decoder.read_uuid()?;
+                let edge_property_t = decoder.read_type()?;
+                let edge_property_inbound_id = decoder.read_uuid()?;
+                let edge_property_name = decoder.read_fixed_length_string()?;
+
+                let value = serde_json::from_slice(&self.resolve(&k, &v)?).map_err(|_| corruption(self.tree, &k))?;
+                Ok((
+                    (
+                        edge_property_outbound_id,
+                        edge_property_t,
+                        edge_property_inbound_id,
+                        edge_property_name,
+                    ),
+                    value,
+                ))
+            })
+            .filter_map(move |item| self.holder.decode_errors.filter(item))
+    }
+
+    /// Counts the properties of an edge, without reading their values.
+    pub fn count_for_owner(&self, outbound_id: Uuid, t: &Type, inbound_id: Uuid) -> Result<u64> {
+        let (outbound_id, inbound_id) = self.holder.edge_range_layout.canonical_ids(outbound_id, t, inbound_id);
+        let prefix = EdgeManager::build_key(outbound_id, t, inbound_id);
+        let mut count = 0;
+
+        for item in self.tree.scan_prefix(&prefix).keys() {
+            map_err(item)?;
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    pub fn get(&self, outbound_id: Uuid, t: &Type, inbound_id: Uuid, name: &str) -> Result<Option<JsonValue>> {
+        let (outbound_id, inbound_id) = self.holder.edge_range_layout.canonical_ids(outbound_id, t, inbound_id);
+        let key = self.key(outbound_id, t, inbound_id, name);
+
+        match self.holder.retrier.run(|| self.tree.get(&key))? {
+            Some(ref stored) => Ok(Some(serde_json::from_slice(&self.resolve(&key, stored)?)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn set(&self, outbound_id: Uuid, t: &Type, inbound_id: Uuid, name: &str, value: &JsonValue) -> Result<()> {
+        let (outbound_id, inbound_id) = self.holder.edge_range_layout.canonical_ids(outbound_id, t, inbound_id);
+        let key = self.key(outbound_id, t, inbound_id, name);
         let value_json = serde_json::to_vec(value)?;
-        map_err(self.tree.insert(key.as_slice(), value_json.as_slice()))?;
+        let stored = dedup::acquire(self.holder, name, &value_json)?;
+        let old_value = self.holder.retrier.run(|| self.tree.get(&key))?;
+        let old_value_json = match old_value {
+            Some(ref old_value) => Some(self.resolve(&key, old_value)?),
+            None => None,
+        };
+        let mut batch = MultiBatch::default();
+
+        if let Some(update_datetime) = self.get_sorted_edge(outbound_id, t, inbound_id, name)? {
+            stage_resorted_edge_ranges(
+                self.holder,
+                &mut batch,
+                outbound_id,
+                t,
+                inbound_id,
+                update_datetime,
+                Some(value),
+            )?;
+        }
+
+        self.stage_entries(
+            &mut batch,
+            outbound_id,
+            t,
+            inbound_id,
+            name,
+            old_value_json.as_ref().map(|v| v.as_ref()),
+            Some(&value_json),
+            Utc::now(),
+        );
+        batch.insert(self.tree, key, stored);
+        batch.apply(self.holder)?;
+
+        if let Some(ref old_value) = old_value {
+            dedup::release(self.holder, old_value)?;
+        }
+
+        if old_value.is_none() {
+            CatalogManager::new(self.holder).increment(CatalogKind::EdgeProperty, name.as_bytes())?;
+        }
+
+        self.holder.notify_mutation()?;
+
+        Ok(())
+    }
+
+    /// Sets several edge properties. Unlike calling `set` for each property,
+    /// the writes are grouped into a single sled transaction. `items` must
+    /// not contain duplicate `(key, name)` pairs.
+    pub fn set_many(&self, items: &[(EdgeKey, String, JsonValue)]) -> Result<()> {
+        self.set_many_with(items, MultiBatch::default())
+    }
+
+    /// Like `set_many`, with the writes in `batch` applied in the same sled
+    /// transaction.
+    pub(crate) fn set_many_with(&self, items: &[(EdgeKey, String, JsonValue)], mut batch: MultiBatch) -> Result<()> {
+        let staged = self.stage_many(items, None, &mut batch)?;
+
+        if let Err(err) = batch.apply(self.holder) {
+            self.abandon_many(staged)?;
+            return Err(err);
+        }
+
+        self.finish_many(staged)
+    }
+
+    /// Adds the writes of `set_many` to `batch`, for `finish_many` to
+    /// complete once the batch is applied, or `abandon_many` to undo if it
+    /// isn't. `update_datetime` is the update datetime that the batch gives
+    /// the items' edge, if it writes the edge too, so that the edge's range
+    /// entries are sorted by the new values.
+    pub(crate) fn stage_many(
+        &self,
+        items: &[(EdgeKey, String, JsonValue)],
+        update_datetime: Option<DateTime<Utc>>,
+        batch: &mut MultiBatch,
+    ) -> Result<StagedEdgeProperties> {
+        let mut staged = StagedEdgeProperties::default();
+
+        if let Err(err) = self.stage_many_into(items, update_datetime, batch, &mut staged) {
+            self.abandon_many(staged)?;
+            return Err(err);
+        }
+
+        Ok(staged)
+    }
+
+    fn stage_many_into(
+        &self,
+        items: &[(EdgeKey, String, JsonValue)],
+        update_datetime: Option<DateTime<Utc>>,
+        batch: &mut MultiBatch,
+        staged: &mut StagedEdgeProperties,
+    ) -> Result<()> {
+        let now = Utc::now();
+
+        for (edge_key, name, value) in items {
+            let (outbound_id, t, inbound_id) = (edge_key.outbound_id, &edge_key.t, edge_key.inbound_id);
+            let (outbound_id, inbound_id) = self.holder.edge_range_layout.canonical_ids(outbound_id, t, inbound_id);
+            let key = self.key(outbound_id, t, inbound_id, name);
+            let value_json = serde_json::to_vec(value)?;
+            let sorted_update_datetime = match update_datetime {
+                Some(update_datetime) => Some(update_datetime).filter(|_| self.is_sort_property(t, name)),
+                None => self.get_sorted_edge(outbound_id, t, inbound_id, name)?,
+            };
+
+            // Each edge has a single sort property, so its range entries
+            // are rewritten at most once.
+            if let Some(update_datetime) = sorted_update_datetime {
+                stage_resorted_edge_ranges(
+                    self.holder,
+                    batch,
+                    outbound_id,
+                    t,
+                    inbound_id,
+                    update_datetime,
+                    Some(value),
+                )?;
+            }
+
+            let stored = dedup::acquire(self.holder, name, &value_json)?;
+            staged.acquired.push(stored.clone());
+            let old_value = self.holder.retrier.run(|| self.tree.get(&key))?;
+            let old_value_json = match old_value {
+                Some(ref old_value) => Some(self.resolve(&key, old_value)?.into_owned()),
+                None => None,
+            };
+
+            match old_value {
+                Some(old_value) => staged.replaced.push(old_value),
+                None => *staged.new_properties_per_name.entry(name.clone()).or_insert(0) += 1,
+            }
+
+            self.stage_entries(
+                batch,
+                outbound_id,
+                t,
+                inbound_id,
+                name,
+                old_value_json.as_ref().map(|v| v.as_ref()),
+                Some(&value_json),
+                now,
+            );
+            batch.insert(self.tree, key, stored);
+        }
+
+        Ok(())
+    }
+
+    /// Releases the values replaced by properties staged by `stage_many`,
+    /// and counts the new ones, once their batch is applied.
+    pub(crate) fn finish_many(&self, staged: StagedEdgeProperties) -> Result<()> {
+        for old_value in staged.replaced {
+            dedup::release(self.holder, &old_value)?;
+        }
+
+        let catalog_manager = CatalogManager::new(self.holder);
+        for (name, count) in staged.new_properties_per_name {
+            catalog_manager.adjust(CatalogKind::EdgeProperty, name.as_bytes(), count)?;
+        }
+
+        self.holder.notify_mutation()?;
+
+        Ok(())
+    }
+
+    /// Releases the values acquired for properties staged by `stage_many`,
+    /// if their batch isn't applied.
+    pub(crate) fn abandon_many(&self, staged: StagedEdgeProperties) -> Result<()> {
+        for stored in staged.acquired {
+            dedup::release(self.holder, &stored)?;
+        }
+
         Ok(())
     }
 
     pub fn delete(&self, outbound_id: Uuid, t: &Type, inbound_id: Uuid, name: &str) -> Result<()> {
-        map_err(self.tree.remove(&self.key(outbound_id, t, inbound_id, name)))?;
+        let (outbound_id, inbound_id) = self.holder.edge_range_layout.canonical_ids(outbound_id, t, inbound_id);
+        let key = self.key(outbound_id, t, inbound_id, name);
+        let old_value = self.holder.retrier.run(|| self.tree.get(&key))?;
+        let old_value_json = match old_value {
+            Some(ref old_value) => Some(self.resolve(&key, old_value)?),
+            None => None,
+        };
+        let mut batch = MultiBatch::default();
+
+        if let Some(update_datetime) = self.get_sorted_edge(outbound_id, t, inbound_id, name)? {
+            stage_resorted_edge_ranges(
+                self.holder,
+                &mut batch,
+                outbound_id,
+                t,
+                inbound_id,
+                update_datetime,
+                None,
+            )?;
+        }
+
+        self.stage_entries(
+            &mut batch,
+            outbound_id,
+            t,
+            inbound_id,
+            name,
+            old_value_json.as_ref().map(|v| v.as_ref()),
+            None,
+            Utc::now(),
+        );
+        batch.remove(self.tree, key);
+        batch.apply(self.holder)?;
+
+        if let Some(ref old_value) = old_value {
+            dedup::release(self.holder, old_value)?;
+            CatalogManager::new(self.holder).decrement(CatalogKind::EdgeProperty, name.as_bytes())?;
+        }
+
+        self.holder.notify_mutation()?;
+
+        Ok(())
+    }
+}
+
+/// The kinds of names tracked by the catalog.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CatalogKind {
+    VertexProperty,
+    EdgeProperty,
+    VertexType,
+    EdgeType,
+}
+
+impl CatalogKind {
+    fn to_byte(self) -> u8 {
+        match self {
+            CatalogKind::VertexProperty => 0,
+            CatalogKind::EdgeProperty => 1,
+            CatalogKind::VertexType => 2,
+            CatalogKind::EdgeType => 3,
+        }
+    }
+}
+
+/// Counts the uses of each distinct name, keyed by `(kind, name)`. Names
+/// are removed once their count drops to zero.
+pub struct CatalogManager<'tree> {
+    pub tree: &'tree Tree,
+    retrier: &'tree Retrier,
+}
+
+impl<'tree> CatalogManager<'tree> {
+    pub fn new<'db: 'tree>(ds: &'db SledHolder) -> Self {
+        CatalogManager {
+            tree: &ds.catalog,
+            retrier: &ds.retrier,
+        }
+    }
+
+    fn key(&self, kind: CatalogKind, name: &[u8]) -> Vec<u8> {
+        let mut key = Vec::with_capacity(name.len() + 1);
+        key.push(kind.to_byte());
+        key.extend_from_slice(name);
+        key
+    }
+
+    pub fn adjust(&self, kind: CatalogKind, name: &[u8], delta: i64) -> Result<()> {
+        let key = self.key(kind, name);
+
+        // This is atomic, so concurrent writers don't lose updates. A
+        // malformed count can't be reported from here, so it's reset.
+        self.retrier.run(|| {
+            self.tree.update_and_fetch(&key, |old| {
+                let old_count = old.and_then(|old| old.try_into().ok()).map_or(0, u64::from_be_bytes);
+                let count = old_count as i64 + delta;
+
+                if count > 0 {
+                    Some((count as u64).to_be_bytes().to_vec())
+                } else {
+                    None
+                }
+            })
+        })?;
+
+        Ok(())
+    }
+
+    pub fn increment(&self, kind: CatalogKind, name: &[u8]) -> Result<()> {
+        self.adjust(kind, name, 1)
+    }
+
+    pub fn decrement(&self, kind: CatalogKind, name: &[u8]) -> Result<()> {
+        self.adjust(kind, name, -1)
+    }
+
+    /// Iterates over the names of a kind and their counts, in name order.
+    pub fn iterate_for_kind(&self, kind: CatalogKind) -> impl Iterator<Item = Result<(String, u64)>> + '_ {
+        self.tree
+            .scan_prefix([kind.to_byte()])
+            .map(move |item| -> Result<(String, u64)> {
+                let (k, v) = map_err(item)?;
+                let mut key_decoder = Decoder::key(self.tree, &k);
+                key_decoder.skip(1)?;
+                let name = key_decoder.read_fixed_length_string()?;
+
+                let mut value_decoder = Decoder::value(self.tree, &k, &v);
+                let count = value_decoder.read_bytes(8)?;
+                if !value_decoder.is_empty() {
+                    return Err(value_decoder.corruption());
+                }
+
+                Ok((name, u64::from_be_bytes(count.try_into().unwrap())))
+            })
+    }
+}
+
+/// Builds the entity key of a property in the history trees. Property names
+/// are not length-prefixed in the property trees, so they're terminated
+/// with `0xFF` here - a byte that never appears in UTF-8 - to keep entity
+/// keys prefix-free.
+pub(crate) fn history_property_key(owner_key: &[u8], name: &str) -> Vec<u8> {
+    let mut key = Vec::with_capacity(owner_key.len() + name.len() + 1);
+    key.extend_from_slice(owner_key);
+    key.extend_from_slice(name.as_bytes());
+    key.push(u8::MAX);
+    key
+}
+
+/// Records the successive states of entities, keyed by the entity key
+/// followed by the datetime of the change. Since datetimes are encoded so
+/// that later datetimes sort first, the state of an entity at a given time
+/// is the first record at or after `(entity key, datetime)`.
+pub struct HistoryManager<'tree> {
+    pub tree: &'tree Tree,
+    retrier: &'tree Retrier,
+}
+
+impl<'tree> HistoryManager<'tree> {
+    pub fn new(retrier: &'tree Retrier, tree: &'tree Tree) -> Self {
+        HistoryManager { tree, retrier }
+    }
+
+    fn key(&self, entity_key: &[u8], datetime: DateTime<Utc>) -> Vec<u8> {
+        let mut key = entity_key.to_vec();
+        key.extend(util::build(&[util::Component::DateTime(datetime)]));
+        key
+    }
+
+    fn record_value(value: Option<&[u8]>) -> Vec<u8> {
+        match value {
+            Some(value) => {
+                let mut record = Vec::with_capacity(value.len() + 1);
+                record.push(1);
+                record.extend_from_slice(value);
+                record
+            }
+            None => vec![0],
+        }
+    }
+
+    /// Records the state of an entity as of `datetime`. A value of `None`
+    /// records that the entity was deleted.
+    ///
+    /// `datetime` is always the time of the write, never a datetime given
+    /// by the caller such as an edge's update datetime, so that an entity's
+    /// records are in the order they were written.
+    pub fn record(&self, entity_key: &[u8], datetime: DateTime<Utc>, value: Option<&[u8]>) -> Result<()> {
+        let record = Self::record_value(value);
+        let key = self.key(entity_key, datetime);
+        self.retrier.run(|| self.tree.insert(&key, record.as_slice()))?;
         Ok(())
     }
+
+    /// Adds a record to `batch`, rather than writing it right away, so that
+    /// it's applied along with the change it records.
+    pub(crate) fn stage_record(
+        &self,
+        batch: &mut MultiBatch,
+        entity_key: &[u8],
+        datetime: DateTime<Utc>,
+        value: Option<&[u8]>,
+    ) {
+        batch.insert(self.tree, self.key(entity_key, datetime), Self::record_value(value));
+    }
+
+    /// Gets the value of an entity as of `datetime`, or `None` if it didn't
+    /// exist at that time.
+    pub fn get_as_of(&self, entity_key: &[u8], datetime: DateTime<Utc>) -> Result<Option<Vec<u8>>> {
+        let low_key = self.key(entity_key, datetime);
+
+        match self.tree.range(low_key..).next() {
+            Some(item) => {
+                let (k, v) = map_err(item)?;
+
+                if !k.starts_with(entity_key) {
+                    return Ok(None);
+                }
+
+                match v.split_first() {
+                    Some((1, value)) => Ok(Some(value.to_vec())),
+                    Some((0, _)) => Ok(None),
+                    _ => Err(corruption(self.tree, &k)),
+                }
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Gets the entity keys and values of all entities under `prefix` that
+    /// existed as of `datetime`.
+    pub fn iterate_as_of(&self, prefix: &[u8], datetime: DateTime<Utc>) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut results = Vec::new();
+        let mut last_entity_key: Option<Vec<u8>> = None;
+
+        for item in self.tree.scan_prefix(prefix) {
+            let (k, v) = map_err(item)?;
+
+            if k.len() < 8 {
+                return Err(corruption(self.tree, &k));
+            }
+
+            let (entity_key, datetime_bytes) = k.split_at(k.len() - 8);
+
+            if last_entity_key.as_deref() == Some(entity_key) {
+                // The state of this entity was already settled by a more
+                // recent record.
+                continue;
+            }
+
+            if Decoder::value(self.tree, &k, datetime_bytes).read_datetime()? > datetime {
+                continue;
+            }
+
+            match v.split_first() {
+                Some((1, value)) => results.push((entity_key.to_vec(), value.to_vec())),
+                Some((0, _)) => {}
+                _ => return Err(corruption(self.tree, &k)),
+            }
+
+            last_entity_key = Some(entity_key.to_vec());
+        }
+
+        Ok(results)
+    }
 }
@@ -0,0 +1,26 @@
+use serde_json::{Map as JsonMap, Value as JsonValue};
+
+/// Applies a JSON merge patch, as defined by RFC 7396, to a value. `None`
+/// stands for a missing value, both as the target and as the result, which
+/// is what a `null` patch produces.
+pub(crate) fn merge(target: Option<&JsonValue>, patch: &JsonValue) -> Option<JsonValue> {
+    let patch = match *patch {
+        JsonValue::Object(ref patch) => patch,
+        JsonValue::Null => return None,
+        ref patch => return Some(patch.clone()),
+    };
+
+    let mut merged = match target {
+        Some(JsonValue::Object(target)) => target.clone(),
+        _ => JsonMap::new(),
+    };
+
+    for (name, patch_value) in patch {
+        match merge(merged.get(name), patch_value) {
+            Some(value) => merged.insert(name.clone(), value),
+            None => merged.remove(name),
+        };
+    }
+
+    Some(JsonValue::Object(merged))
+}
@@ -0,0 +1,169 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+use super::datastore::SledHolder;
+use super::deadline::Deadline;
+use super::errors::Error;
+use super::managers::{EdgePropertyManager, EdgeRangeManager};
+
+use indradb::{EdgeKey, Result, Type};
+use uuid::Uuid;
+
+/// A vertex reached by a path, and the edge the path reached it by.
+struct Step {
+    edge: Option<EdgeKey>,
+    previous: Option<usize>,
+}
+
+/// A vertex waiting to be visited, along with the total weight and length
+/// of the path that reached it. Ordered so that a `BinaryHeap` pops the
+/// lightest first, then the shortest, then the lowest ID, so that ties are
+/// broken the same way every time.
+struct Candidate {
+    distance: f64,
+    depth: u32,
+    id: Uuid,
+    step: usize,
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .distance
+            .total_cmp(&self.distance)
+            .then(other.depth.cmp(&self.depth))
+            .then(other.id.cmp(&self.id))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+/// Reads the weight of an edge, or returns `None` if it doesn't have one.
+fn weight(
+    edge_property_manager: &EdgePropertyManager,
+    outbound_id: Uuid,
+    t: &Type,
+    inbound_id: Uuid,
+    name: &str,
+) -> Result<Option<f64>> {
+    match edge_property_manager.get(outbound_id, t, inbound_id, name)? {
+        None => Ok(None),
+        Some(value) => match value.as_f64() {
+            Some(weight) if weight >= 0.0 && weight.is_finite() => Ok(Some(weight)),
+            _ => Err(Error::InvalidEdgeWeight {
+                outbound_id,
+                t: t.0.clone(),
+                inbound_id,
+                name: name.to_string(),
+            }
+            .into()),
+        },
+    }
+}
+
+/// Finds the lightest path of at most `max_depth` outbound edges from
+/// `from` to `to`, using Dijkstra's algorithm over the edge ranges. Returns
+/// `None` if there isn't one.
+///
+/// Vertices are visited once per path length, rather than once, since a
+/// heavier path can still lead to `to` when a lighter one is too long to.
+/// A vertex is skipped when it was already visited by a path that's no
+/// heavier and no longer.
+pub(crate) fn shortest_path(
+    holder: &SledHolder,
+    from: Uuid,
+    to: Uuid,
+    t: Option<&Type>,
+    weight_property: &str,
+    max_depth: u32,
+    deadline: &Deadline,
+) -> Result<Option<Vec<EdgeKey>>> {
+    let edge_range_manager = EdgeRangeManager::new(holder);
+    let edge_property_manager = EdgePropertyManager::new(holder);
+    let mut steps = vec![Step {
+        edge: None,
+        previous: None,
+    }];
+    let mut visited_depths: HashMap<Uuid, u32> = HashMap::new();
+    let mut candidates = BinaryHeap::new();
+    candidates.push(Candidate {
+        distance: 0.0,
+        depth: 0,
+        id: from,
+        step: 0,
+    });
+
+    while let Some(candidate) = candidates.pop() {
+        if visited_depths
+            .get(&candidate.id)
+            .is_some_and(|&depth| depth <= candidate.depth)
+        {
+            continue;
+        }
+
+        visited_depths.insert(candidate.id, candidate.depth);
+
+        if candidate.id == to {
+            let mut path = Vec::with_capacity(candidate.depth as usize);
+            let mut step = Some(candidate.step);
+
+            while let Some(i) = step {
+                path.extend(steps[i].edge.clone());
+                step = steps[i].previous;
+            }
+
+            path.reverse();
+            return Ok(Some(path));
+        }
+
+        if candidate.depth == max_depth {
+            continue;
+        }
+
+        for item in edge_range_manager.iterate_for_range(candidate.id, t, None)? {
+            let (outbound_id, edge_t, _, inbound_id) = item?;
+            deadline.tick()?;
+            let depth = candidate.depth + 1;
+
+            if visited_depths.get(&inbound_id).is_some_and(|&visited| visited <= depth) {
+                continue;
+            }
+
+            let weight = match weight(
+                &edge_property_manager,
+                outbound_id,
+                &edge_t,
+                inbound_id,
+                weight_property,
+            )? {
+                Some(weight) => weight,
+                None => continue,
+            };
+
+            steps.push(Step {
+                edge: Some(EdgeKey::new(outbound_id, edge_t, inbound_id)),
+                previous: Some(candidate.step),
+            });
+            candidates.push(Candidate {
+                distance: candidate.distance + weight,
+                depth,
+                id: inbound_id,
+                step: steps.len() - 1,
+            });
+        }
+    }
+
+    Ok(None)
+}
@@ -0,0 +1,147 @@
+use std::convert::TryFrom;
+
+use super::decode::Decoder;
+use super::errors::Error;
+
+use chrono::offset::Utc;
+use chrono::DateTime;
+use indradb::{util, Result};
+
+/// The precision of edge update datetimes, which determines how many bytes
+/// they take up in edge range keys.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DatetimePrecision {
+    /// Nanosecond precision, encoded in 8 bytes. This is the default.
+    #[default]
+    Nanos,
+    /// Microsecond precision, encoded in 7 bytes.
+    Micros,
+    /// Millisecond precision, encoded in 6 bytes.
+    Millis,
+    /// Second precision, encoded in 5 bytes.
+    Seconds,
+}
+
+impl DatetimePrecision {
+    fn nanos_per_unit(self) -> u64 {
+        match self {
+            DatetimePrecision::Nanos => 1,
+            DatetimePrecision::Micros => 1_000,
+            DatetimePrecision::Millis => 1_000_000,
+            DatetimePrecision::Seconds => 1_000_000_000,
+        }
+    }
+
+    /// The number of bytes an encoded datetime takes up.
+    pub(crate) fn width(self) -> usize {
+        match self {
+            DatetimePrecision::Nanos => 8,
+            DatetimePrecision::Micros => 7,
+            DatetimePrecision::Millis => 6,
+            DatetimePrecision::Seconds => 5,
+        }
+    }
+
+    /// The byte stored in the metadata tree to record the precision.
+    pub(crate) fn to_byte(self) -> u8 {
+        match self {
+            DatetimePrecision::Nanos => 0,
+            DatetimePrecision::Micros => 1,
+            DatetimePrecision::Millis => 2,
+            DatetimePrecision::Seconds => 3,
+        }
+    }
+
+    pub(crate) fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(DatetimePrecision::Nanos),
+            1 => Some(DatetimePrecision::Micros),
+            2 => Some(DatetimePrecision::Millis),
+            3 => Some(DatetimePrecision::Seconds),
+            _ => None,
+        }
+    }
+
+    /// Returns `None` for datetimes before the Unix epoch, or too far
+    /// after it to count in nanoseconds.
+    fn units_since_epoch(self, datetime: DateTime<Utc>) -> Option<u64> {
+        let seconds = u64::try_from(datetime.timestamp()).ok()?;
+        let nanos = seconds
+            .checked_mul(1_000_000_000)?
+            .checked_add(u64::from(datetime.timestamp_subsec_nanos()))?;
+        Some(nanos / self.nanos_per_unit())
+    }
+
+    /// Like `units_since_epoch`, but for a datetime clamped to the range
+    /// that can be encoded, e.g. an open-ended query bound.
+    fn clamped_units_since_epoch(self, datetime: DateTime<Utc>) -> u64 {
+        let datetime = datetime.clamp(epoch(), *util::MAX_DATETIME);
+        // Everything in the encodable range is countable.
+        self.units_since_epoch(datetime).unwrap()
+    }
+
+    fn datetime_from_units(self, units: u64) -> DateTime<Utc> {
+        let nanos = units * self.nanos_per_unit();
+        // Any `u64` of nanoseconds is well within the range chrono supports.
+        DateTime::from_timestamp((nanos / 1_000_000_000) as i64, (nanos % 1_000_000_000) as u32).unwrap()
+    }
+
+    /// Rounds a datetime down to this precision.
+    pub(crate) fn truncate(self, datetime: DateTime<Utc>) -> DateTime<Utc> {
+        self.datetime_from_units(self.clamped_units_since_epoch(datetime))
+    }
+
+    /// Rounds a datetime up to this precision.
+    pub(crate) fn round_up(self, datetime: DateTime<Utc>) -> DateTime<Utc> {
+        let truncated = self.truncate(datetime);
+
+        if truncated == datetime {
+            truncated
+        } else {
+            self.datetime_from_units(self.clamped_units_since_epoch(datetime) + 1)
+        }
+    }
+
+    /// Encodes a datetime, truncated to this precision. Like
+    /// `util::Component::DateTime`, later datetimes have smaller byte
+    /// representations, so that ranges are iterated newest first.
+    pub(crate) fn encode(self, datetime: DateTime<Utc>) -> Vec<u8> {
+        if self == DatetimePrecision::Nanos {
+            let datetime = datetime.clamp(epoch(), *util::MAX_DATETIME);
+            return util::build(&[util::Component::DateTime(datetime)]);
+        }
+
+        let time_to_end =
+            self.clamped_units_since_epoch(*util::MAX_DATETIME) - self.clamped_units_since_epoch(datetime);
+        time_to_end.to_be_bytes()[8 - self.width()..].to_vec()
+    }
+
+    /// Decodes a datetime written by `encode`.
+    pub(crate) fn read(self, decoder: &mut Decoder) -> Result<DateTime<Utc>> {
+        let width = self.width();
+        let mut buf = [0; 8];
+        buf[8 - width..].copy_from_slice(decoder.read_bytes(width)?);
+
+        let time_to_end = u64::from_be_bytes(buf);
+        match self
+            .clamped_units_since_epoch(*util::MAX_DATETIME)
+            .checked_sub(time_to_end)
+        {
+            Some(units) => Ok(self.datetime_from_units(units)),
+            None => Err(decoder.corruption()),
+        }
+    }
+}
+
+fn epoch() -> DateTime<Utc> {
+    DateTime::from_timestamp(0, 0).unwrap()
+}
+
+/// Checks that an update datetime given for an edge can be stored in its
+/// range keys, before any of them are built.
+pub(crate) fn check_range(datetime: DateTime<Utc>) -> Result<()> {
+    match DatetimePrecision::Nanos.units_since_epoch(datetime) {
+        Some(_) if datetime <= *util::MAX_DATETIME => Ok(()),
+        _ => Err(Error::DatetimeOutOfRange { datetime }.into()),
+    }
+}
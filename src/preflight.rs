@@ -0,0 +1,315 @@
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::errors::{map_err, map_io_err, Error};
+use super::format::{self, FORMAT_VERSION};
+
+use fs2::FileExt;
+use indradb::Result;
+use sled::Config;
+
+/// The longest path the platform's filesystem APIs accept, in bytes.
+const MAX_PATH_BYTES: usize = if cfg!(windows) { 260 } else { 4096 };
+
+/// The longest file or directory name most filesystems accept, in bytes.
+const MAX_NAME_BYTES: usize = 255;
+
+/// Room left for the names sled gives its files within the datastore
+/// directory, e.g. `blobs/<id>` and `snap.<lsn>.generating`.
+const SLED_FILE_NAME_BYTES: usize = 48;
+
+/// The name of the file written to check that the directory is writable.
+const PROBE_FILE_NAME: &str = ".indradb-sled-preflight";
+
+/// A check made by `SledConfig::preflight`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PreflightCheck {
+    /// The path, or one of its components, is too long.
+    PathLength,
+    /// The datastore's directory, or the one it would be created in, isn't
+    /// a writable directory.
+    Permissions,
+    /// The filesystem doesn't support syncing files to disk.
+    Fsync,
+    /// There's less free disk space than required.
+    DiskSpace,
+    /// The datastore is already open, in this process or another one.
+    Lock,
+    /// The existing datastore can't be opened by this version of the crate,
+    /// or with this config.
+    Format,
+    /// There's already a datastore at the path, but the config was set
+    /// with `SledConfig::with_create_new`, or there's none, but it was set
+    /// with `SledConfig::with_must_exist`.
+    Existence,
+}
+
+/// Whether `SledConfig::open` accepts a path that already holds a
+/// datastore, or one that doesn't.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum ExistingDatastore {
+    /// Opens the datastore if there is one, and creates one otherwise.
+    #[default]
+    Allow,
+    /// Only creates a new datastore. Set by `SledConfig::with_create_new`.
+    Reject,
+    /// Only opens an existing datastore. Set by
+    /// `SledConfig::with_must_exist`.
+    Require,
+}
+
+/// Whether there's a datastore at `path`.
+pub(crate) fn datastore_exists(path: &Path) -> bool {
+    path.join("db").is_file()
+}
+
+fn fail<T>(check: PreflightCheck, reason: String) -> Result<T> {
+    Err(Error::PreflightFailed { check, reason }.into())
+}
+
+fn check_path_length(path: &Path) -> Result<()> {
+    for component in path.components() {
+        if let Component::Normal(name) = component {
+            if name.len() > MAX_NAME_BYTES {
+                return fail(
+                    PreflightCheck::PathLength,
+                    format!(
+                        "the path component `{}` is {} bytes long, but most filesystems allow at most {}; \
+                         use a shorter name",
+                        name.to_string_lossy(),
+                        name.len(),
+                        MAX_NAME_BYTES
+                    ),
+                );
+            }
+        }
+    }
+
+    let len = path.as_os_str().len() + SLED_FILE_NAME_BYTES;
+
+    if len > MAX_PATH_BYTES {
+        return fail(
+            PreflightCheck::PathLength,
+            format!(
+                "the paths of the datastore's files would be up to {} bytes long, but at most {} are allowed; \
+                 move the datastore to a shorter path",
+                len, MAX_PATH_BYTES
+            ),
+        );
+    }
+
+    Ok(())
+}
+
+/// Finds the directory the datastore is in, or the closest existing
+/// ancestor that it would be created in.
+fn existing_dir(path: &Path) -> Result<PathBuf> {
+    let mut dir = path;
+
+    loop {
+        if dir.is_dir() {
+            return Ok(dir.to_path_buf());
+        }
+
+        if dir.exists() {
+            return fail(
+                PreflightCheck::Permissions,
+                format!(
+                    "`{}` isn't a directory; the datastore path has to be a directory, or not exist yet",
+                    dir.display()
+                ),
+            );
+        }
+
+        dir = match dir.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => return Ok(PathBuf::from(".")),
+        };
+    }
+}
+
+/// Writes, syncs and removes a file in `dir`.
+fn check_writable(dir: &Path) -> Result<()> {
+    let probe_path = dir.join(PROBE_FILE_NAME);
+
+    let mut probe = match OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&probe_path)
+    {
+        Ok(probe) => probe,
+        Err(err) => {
+            return fail(
+                PreflightCheck::Permissions,
+                format!(
+                    "can't create files in `{}` ({}); check its permissions",
+                    dir.display(),
+                    err
+                ),
+            )
+        }
+    };
+
+    let written = probe.write_all(b"preflight").and_then(|_| probe.sync_all());
+    drop(probe);
+    let _ = fs::remove_file(&probe_path);
+
+    if let Err(err) = written {
+        return fail(
+            PreflightCheck::Fsync,
+            format!(
+                "can't write and sync files in `{}` ({}); use a filesystem that supports fsync",
+                dir.display(),
+                err
+            ),
+        );
+    }
+
+    Ok(())
+}
+
+fn check_disk_space(dir: &Path, min_free_bytes: u64) -> Result<()> {
+    let available = map_io_err(fs2::available_space(dir))?;
+
+    if available < min_free_bytes {
+        return fail(
+            PreflightCheck::DiskSpace,
+            format!(
+                "only {} bytes are free in `{}`, but at least {} are required; free up space, or lower the \
+                 minimum with `SledConfig::with_preflight_min_free_space`",
+                available,
+                dir.display(),
+                min_free_bytes
+            ),
+        );
+    }
+
+    Ok(())
+}
+
+/// Waits up to `timeout` for sled to release its lock on the database at
+/// `path`. sled gives up the lock from its own threads, a moment after the
+/// last handle on the database is dropped. Returns straight away if the
+/// database is gone, e.g. because it was temporary.
+pub(crate) fn wait_until_unlocked(path: &Path, timeout: Duration) {
+    let file = match OpenOptions::new().read(true).write(true).open(path.join("db")) {
+        Ok(file) => file,
+        Err(_) => return,
+    };
+    let start = Instant::now();
+
+    while file.try_lock_exclusive().is_err() {
+        if start.elapsed() >= timeout {
+            return;
+        }
+
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    let _ = file.unlock();
+}
+
+/// Checks that the datastore at `path`, which exists, isn't locked.
+fn check_unlocked(path: &Path) -> Result<()> {
+    let db_path = path.join("db");
+    let file = map_io_err(OpenOptions::new().read(true).write(true).open(&db_path))?;
+
+    if file.try_lock_exclusive().is_err() {
+        return fail(
+            PreflightCheck::Lock,
+            format!(
+                "`{}` is locked, so the datastore is already open; close it, or stop the process that has it open",
+                db_path.display()
+            ),
+        );
+    }
+
+    map_io_err(file.unlock())?;
+    Ok(())
+}
+
+/// Checks that the datastore at `path`, which exists, can be opened with
+/// `config` and is in a supported format.
+fn check_format(path: &Path, config: Config) -> Result<()> {
+    let db = match config.open() {
+        Ok(db) => db,
+        Err(err) => {
+            return fail(
+                PreflightCheck::Format,
+                format!("the datastore at `{}` can't be opened: {}", path.display(), err),
+            )
+        }
+    };
+
+    // Datastores that predate the metadata tree are at version 0.
+    if !db.tree_names().iter().any(|name| name.as_ref() == b"metadata") {
+        return Ok(());
+    }
+
+    let version = format::read_format_version(&map_err(db.open_tree("metadata"))?)?;
+
+    if version > FORMAT_VERSION {
+        return fail(
+            PreflightCheck::Format,
+            format!(
+                "the datastore at `{}` has format version {}, but this version of the crate supports at most {}; \
+                 upgrade the crate",
+                path.display(),
+                version,
+                FORMAT_VERSION
+            ),
+        );
+    }
+
+    Ok(())
+}
+
+/// Checks that there is, or isn't, a datastore at `path`, as `existing`
+/// requires.
+fn check_existence(path: &Path, existing: ExistingDatastore) -> Result<()> {
+    match existing {
+        ExistingDatastore::Reject if datastore_exists(path) => fail(
+            PreflightCheck::Existence,
+            format!(
+                "a datastore already exists at `{}`; remove it, or open it without `with_create_new`",
+                path.display()
+            ),
+        ),
+        ExistingDatastore::Require if !datastore_exists(path) => fail(
+            PreflightCheck::Existence,
+            format!(
+                "there's no datastore at `{}`; check the path, or open it without `with_must_exist`",
+                path.display()
+            ),
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// Runs the checks of `SledConfig::preflight`, in order, failing on the
+/// first one that doesn't pass.
+pub(crate) fn preflight(path: &Path, config: Config, min_free_bytes: u64, existing: ExistingDatastore) -> Result<()> {
+    let path = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        map_io_err(env::current_dir())?.join(path)
+    };
+
+    check_path_length(&path)?;
+    let dir = existing_dir(&path)?;
+    check_writable(&dir)?;
+    check_disk_space(&dir, min_free_bytes)?;
+    check_existence(&path, existing)?;
+
+    if datastore_exists(&path) {
+        check_unlocked(&path)?;
+        check_format(&path, config)?;
+    }
+
+    Ok(())
+}
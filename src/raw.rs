@@ -0,0 +1,383 @@
+use std::convert::TryInto;
+use std::ops::RangeBounds;
+
+use super::datastore::{SledDatastore, SledHolder};
+use super::decode::Decoder;
+use super::dedup;
+use super::errors::map_err;
+use super::layout::{decode_number, EdgeRangeLayout};
+use super::managers::read_vertex_value;
+use super::precision::DatetimePrecision;
+
+use chrono::offset::Utc;
+use chrono::DateTime;
+use indradb::{EdgeKey, Result, Type};
+use serde_json::Value as JsonValue;
+use sled::{IVec, Iter, Tree};
+use uuid::Uuid;
+
+/// The trees that can be read with `RawTreeAccess::iter_tree_raw`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TreeKind {
+    /// Vertex types, keyed by vertex ID.
+    Vertices,
+    /// Edge update datetimes, keyed by `(outbound ID, type, inbound ID)`.
+    Edges,
+    /// Edges by outbound vertex.
+    EdgeRanges,
+    /// Edges by inbound vertex.
+    ReversedEdgeRanges,
+    /// Vertex property values, keyed by `(vertex ID, name)`.
+    VertexProperties,
+    /// Edge property values, keyed by `(outbound ID, type, inbound ID,
+    /// name)`.
+    EdgeProperties,
+    /// Edge property names by inbound vertex.
+    ReversedEdgeProperties,
+    /// Edge property names by each of their edge's vertices.
+    VertexEdgeProperties,
+    /// Vertices by type and creation datetime.
+    VertexCreations,
+    /// Vertices by property name and value.
+    VertexPropertyValues,
+    /// Vertices by property name and numeric value.
+    VertexPropertyNumbers,
+    /// Edges by property name and value.
+    EdgePropertyValues,
+    /// Edges by property name and numeric value.
+    EdgePropertyNumbers,
+    /// Edges by type and update datetime.
+    EdgesByType,
+}
+
+/// The interpretation of a raw record.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RawRecord {
+    /// A record of `TreeKind::Vertices`. Vertices created by old versions
+    /// of the crate have no recorded creation datetime.
+    Vertex {
+        id: Uuid,
+        t: Type,
+        created_datetime: Option<DateTime<Utc>>,
+    },
+    /// A record of `TreeKind::Edges`.
+    Edge {
+        key: EdgeKey,
+        update_datetime: DateTime<Utc>,
+    },
+    /// A record of `TreeKind::EdgeRanges` or `TreeKind::ReversedEdgeRanges`.
+    /// `first_id` is the outbound ID of the former and the inbound ID of
+    /// the latter. `update_datetime` is `None` if the type's ranges are
+    /// untimed, in which case it's recorded in `TreeKind::Edges` and, as of
+    /// format version 5, the entry's value.
+    EdgeRange {
+        first_id: Uuid,
+        t: Type,
+        update_datetime: Option<DateTime<Utc>>,
+        second_id: Uuid,
+    },
+    /// A record of `TreeKind::VertexProperties`.
+    VertexProperty { id: Uuid, name: String, value: JsonValue },
+    /// A record of `TreeKind::EdgeProperties`.
+    EdgeProperty {
+        key: EdgeKey,
+        name: String,
+        value: JsonValue,
+    },
+    /// A record of `TreeKind::ReversedEdgeProperties`. The value is in
+    /// `TreeKind::EdgeProperties`.
+    ReversedEdgeProperty { key: EdgeKey, name: String },
+    /// A record of `TreeKind::VertexEdgeProperties`, for one of the edge's
+    /// vertices. The value is in `TreeKind::EdgeProperties`.
+    VertexEdgeProperty {
+        vertex_id: Uuid,
+        key: EdgeKey,
+        name: String,
+    },
+    /// A record of `TreeKind::VertexCreations`.
+    VertexCreation {
+        t: Type,
+        created_datetime: DateTime<Utc>,
+        id: Uuid,
+    },
+    /// A record of `TreeKind::VertexPropertyValues`.
+    VertexPropertyValue { name: String, value: JsonValue, id: Uuid },
+    /// A record of `TreeKind::VertexPropertyNumbers`.
+    VertexPropertyNumber { name: String, value: f64, id: Uuid },
+    /// A record of `TreeKind::EdgePropertyValues`.
+    EdgePropertyValue {
+        name: String,
+        value: JsonValue,
+        key: EdgeKey,
+    },
+    /// A record of `TreeKind::EdgePropertyNumbers`.
+    EdgePropertyNumber { name: String, value: f64, key: EdgeKey },
+    /// A record of `TreeKind::EdgesByType`.
+    EdgeByType {
+        key: EdgeKey,
+        update_datetime: DateTime<Utc>,
+    },
+}
+
+/// A record as it's stored in sled, along with its interpretation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RawEntry {
+    pub key: IVec,
+    pub value: IVec,
+    pub record: RawRecord,
+}
+
+/// Low-level access to the trees backing a datastore, for tools such as
+/// replicators and verifiers that need more than the `Datastore` API
+/// offers, without reimplementing the on-disk encoding. The encoding isn't
+/// covered by semver, so this is kept out of the way in an extension
+/// trait.
+///
+/// Raw iteration is read-only, and reads the trees of this handle's
+/// partition directly: writes made while it runs may or may not be seen,
+/// and indexes may briefly disagree with the records they're derived from.
+pub trait RawTreeAccess {
+    /// Iterates over the records of a tree whose keys are in `range`, in
+    /// key order. Records that can't be decoded yield
+    /// `Error::Corruption`.
+    ///
+    /// # Arguments
+    /// * `kind`: The tree to read.
+    /// * `range`: The range of raw keys to read, e.g. `..` for all of them.
+    fn iter_tree_raw<K, R>(&self, kind: TreeKind, range: R) -> RawTreeIter
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K>;
+}
+
+impl RawTreeAccess for SledDatastore {
+    fn iter_tree_raw<K, R>(&self, kind: TreeKind, range: R) -> RawTreeIter
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K>,
+    {
+        iter(&self.holder, kind, range)
+    }
+}
+
+/// Iterates over the records of one of a holder's trees whose keys are in
+/// `range`. See `RawTreeAccess::iter_tree_raw`.
+pub(crate) fn iter<K, R>(holder: &SledHolder, kind: TreeKind, range: R) -> RawTreeIter
+where
+    K: AsRef<[u8]>,
+    R: RangeBounds<K>,
+{
+    let tree = match kind {
+        TreeKind::Vertices => holder.vertices.clone(),
+        TreeKind::Edges => holder.edges.clone(),
+        TreeKind::EdgeRanges => (*holder.edge_ranges.writer()).clone(),
+        TreeKind::ReversedEdgeRanges => (*holder.reversed_edge_ranges.writer()).clone(),
+        TreeKind::VertexProperties => holder.vertex_properties.clone(),
+        TreeKind::EdgeProperties => holder.edge_properties.clone(),
+        TreeKind::ReversedEdgeProperties => (*holder.reversed_edge_properties.writer()).clone(),
+        TreeKind::VertexEdgeProperties => (*holder.vertex_edge_properties.writer()).clone(),
+        TreeKind::VertexCreations => (*holder.vertex_creations.writer()).clone(),
+        TreeKind::VertexPropertyValues => (*holder.vertex_property_values.writer()).clone(),
+        TreeKind::VertexPropertyNumbers => (*holder.vertex_property_numbers.writer()).clone(),
+        TreeKind::EdgePropertyValues => (*holder.edge_property_values.writer()).clone(),
+        TreeKind::EdgePropertyNumbers => (*holder.edge_property_numbers.writer()).clone(),
+        TreeKind::EdgesByType => (*holder.edges_by_type.writer()).clone(),
+    };
+
+    RawTreeIter {
+        iter: tree.range(range),
+        tree,
+        kind,
+        value_store: holder.values.store.clone(),
+        layout: holder.edge_range_layout.clone(),
+        precision: holder.datetime_precision,
+    }
+}
+
+/// Iterates over raw records. Returned by `RawTreeAccess::iter_tree_raw`.
+pub struct RawTreeIter {
+    iter: Iter,
+    tree: Tree,
+    kind: TreeKind,
+    value_store: Tree,
+    layout: EdgeRangeLayout,
+    precision: DatetimePrecision,
+}
+
+impl RawTreeIter {
+    /// Like `next`, but keeps the key of a record that can't be decoded, so
+    /// that it can be reported. Errors reading the tree itself are still
+    /// returned as they are.
+    pub(crate) fn next_keyed(&mut self) -> Option<Result<(IVec, Result<RawRecord>)>> {
+        let item = self.iter.next()?;
+
+        Some(map_err(item).map(|(key, value)| {
+            let record = self.decode(&key, &value);
+            (key, record)
+        }))
+    }
+
+    fn decode(&self, k: &[u8], v: &[u8]) -> Result<RawRecord> {
+        let mut decoder = Decoder::key(&self.tree, k);
+
+        let record = match self.kind {
+            TreeKind::Vertices => {
+                let id = decoder.read_uuid()?;
+                let (t, created_datetime) = read_vertex_value(&self.tree, k, v)?;
+                RawRecord::Vertex {
+                    id,
+                    t,
+                    created_datetime,
+                }
+            }
+            TreeKind::Edges => {
+                let outbound_id = decoder.read_uuid()?;
+                let t = decoder.read_type()?;
+                let inbound_id = decoder.read_uuid()?;
+                let update_datetime = Decoder::value(&self.tree, k, v).read_datetime()?;
+                RawRecord::Edge {
+                    key: EdgeKey::new(outbound_id, t, inbound_id),
+                    update_datetime,
+                }
+            }
+            TreeKind::EdgeRanges | TreeKind::ReversedEdgeRanges => {
+                let first_id = decoder.read_uuid()?;
+                let t = decoder.read_type()?;
+
+                let update_datetime = if self.layout.is_timed(&t) {
+                    Some(self.precision.read(&mut decoder)?)
+                } else {
+                    None
+                };
+
+                // Skip the sort key, if the type has one.
+                let sort_key_len = decoder.remaining().saturating_sub(16);
+                decoder.skip(sort_key_len)?;
+
+                RawRecord::EdgeRange {
+                    first_id,
+                    t,
+                    update_datetime,
+                    second_id: decoder.read_uuid()?,
+                }
+            }
+            TreeKind::VertexProperties => {
+                let id = decoder.read_uuid()?;
+                let name = decoder.read_fixed_length_string()?;
+                let value = serde_json::from_slice(&dedup::resolve(&self.value_store, &self.tree, k, v)?)?;
+                RawRecord::VertexProperty { id, name, value }
+            }
+            TreeKind::EdgeProperties => {
+                let outbound_id = decoder.read_uuid()?;
+                let t = decoder.read_type()?;
+                let inbound_id = decoder.read_uuid()?;
+                let name = decoder.read_fixed_length_string()?;
+                let value = serde_json::from_slice(&dedup::resolve(&self.value_store, &self.tree, k, v)?)?;
+                RawRecord::EdgeProperty {
+                    key: EdgeKey::new(outbound_id, t, inbound_id),
+                    name,
+                    value,
+                }
+            }
+            TreeKind::ReversedEdgeProperties => {
+                let inbound_id = decoder.read_uuid()?;
+                let t = decoder.read_type()?;
+                let outbound_id = decoder.read_uuid()?;
+                let name = decoder.read_fixed_length_string()?;
+                RawRecord::ReversedEdgeProperty {
+                    key: EdgeKey::new(outbound_id, t, inbound_id),
+                    name,
+                }
+            }
+            TreeKind::VertexEdgeProperties => {
+                let vertex_id = decoder.read_uuid()?;
+                let outbound_id = decoder.read_uuid()?;
+                let t = decoder.read_type()?;
+                let inbound_id = decoder.read_uuid()?;
+                RawRecord::VertexEdgeProperty {
+                    vertex_id,
+                    key: EdgeKey::new(outbound_id, t, inbound_id),
+                    name: decoder.read_fixed_length_string()?,
+                }
+            }
+            TreeKind::VertexCreations => {
+                let t = decoder.read_type()?;
+                let created_datetime = decoder.read_datetime()?;
+                RawRecord::VertexCreation {
+                    t,
+                    created_datetime,
+                    id: decoder.read_uuid()?,
+                }
+            }
+            TreeKind::VertexPropertyValues => {
+                let name = decoder.read_escaped_string()?;
+                let value_len = decoder.remaining().saturating_sub(16);
+                let value = serde_json::from_slice(decoder.read_bytes(value_len)?)?;
+                RawRecord::VertexPropertyValue {
+                    name,
+                    value,
+                    id: decoder.read_uuid()?,
+                }
+            }
+            TreeKind::VertexPropertyNumbers => {
+                let name = decoder.read_escaped_string()?;
+                let bits = u64::from_be_bytes(decoder.read_bytes(8)?.try_into().unwrap());
+                RawRecord::VertexPropertyNumber {
+                    name,
+                    value: decode_number(bits),
+                    id: decoder.read_uuid()?,
+                }
+            }
+            TreeKind::EdgePropertyValues => {
+                let name = decoder.read_escaped_string()?;
+                let value = serde_json::from_slice(&decoder.read_escaped_bytes()?)?;
+                let outbound_id = decoder.read_uuid()?;
+                let t = decoder.read_type()?;
+                RawRecord::EdgePropertyValue {
+                    name,
+                    value,
+                    key: EdgeKey::new(outbound_id, t, decoder.read_uuid()?),
+                }
+            }
+            TreeKind::EdgePropertyNumbers => {
+                let name = decoder.read_escaped_string()?;
+                let bits = u64::from_be_bytes(decoder.read_bytes(8)?.try_into().unwrap());
+                let outbound_id = decoder.read_uuid()?;
+                let t = decoder.read_type()?;
+                RawRecord::EdgePropertyNumber {
+                    name,
+                    value: decode_number(bits),
+                    key: EdgeKey::new(outbound_id, t, decoder.read_uuid()?),
+                }
+            }
+            TreeKind::EdgesByType => {
+                let t = decoder.read_type()?;
+                let update_datetime = decoder.read_datetime()?;
+                let outbound_id = decoder.read_uuid()?;
+                RawRecord::EdgeByType {
+                    key: EdgeKey::new(outbound_id, t, decoder.read_uuid()?),
+                    update_datetime,
+                }
+            }
+        };
+
+        if !decoder.is_empty() {
+            return Err(decoder.corruption());
+        }
+
+        Ok(record)
+    }
+}
+
+impl Iterator for RawTreeIter {
+    type Item = Result<RawEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.iter.next()?;
+
+        Some(map_err(item).and_then(|(key, value)| {
+            let record = self.decode(&key, &value)?;
+            Ok(RawEntry { key, value, record })
+        }))
+    }
+}
@@ -0,0 +1,384 @@
+use std::thread;
+
+use super::composite;
+use super::datastore::SledHolder;
+use super::decode::Decoder;
+use super::dedup;
+use super::errors::map_err;
+use super::fulltext;
+use super::managers::{
+    CatalogKind, CatalogManager, EdgePropertyManager, EdgeRangeManager, EdgeTypeManager, VertexCreationManager,
+    VertexPropertyManager,
+};
+use super::vectors;
+
+use indradb::Result;
+use sled::Tree;
+
+/// The number of threads used to rebuild an index. Keys are UUID-prefixed,
+/// so splitting the key space on the first byte spreads the work evenly.
+const REBUILD_SHARDS: u16 = 8;
+
+/// Calls `f` on every entry of `tree`, splitting the key space across
+/// `REBUILD_SHARDS` threads.
+pub(crate) fn for_each_parallel<F>(tree: &Tree, f: F) -> Result<()>
+where
+    F: Fn(&[u8], &[u8]) -> Result<()> + Sync,
+{
+    let shard_width = 256 / REBUILD_SHARDS;
+
+    thread::scope(|scope| {
+        let handles: Vec<_> = (0..REBUILD_SHARDS)
+            .map(|shard| {
+                let f = &f;
+                scope.spawn(move || -> Result<()> {
+                    let start = vec![(shard * shard_width) as u8];
+                    let items = if shard == REBUILD_SHARDS - 1 {
+                        tree.range(start..)
+                    } else {
+                        tree.range(start..vec![((shard + 1) * shard_width) as u8])
+                    };
+
+                    for item in items {
+                        let (k, v) = map_err(item)?;
+                        f(&k, &v)?;
+                    }
+
+                    Ok(())
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().expect("index rebuild thread panicked")?;
+        }
+
+        Ok(())
+    })
+}
+
+/// Rebuilds the reversed edge ranges from the edges tree.
+pub(crate) fn rebuild_reversed_edge_ranges(holder: &SledHolder) -> Result<()> {
+    let reversed_edge_range_manager = EdgeRangeManager::new_reversed(holder);
+    map_err(reversed_edge_range_manager.tree.clear())?;
+
+    for_each_parallel(&holder.edges, |k, v| {
+        let mut decoder = Decoder::key(&holder.edges, k);
+        let outbound_id = decoder.read_uuid()?;
+        let t = decoder.read_type()?;
+        let inbound_id = decoder.read_uuid()?;
+        let update_datetime = Decoder::value(&holder.edges, k, v).read_datetime()?;
+        reversed_edge_range_manager.set(inbound_id, &t, update_datetime, outbound_id)
+    })
+}
+
+/// Rewrites the untimed edge range entries so that they hold the update
+/// datetimes of their edges. Entries that are missing are added.
+pub(crate) fn rebuild_edge_range_datetimes(holder: &SledHolder) -> Result<()> {
+    let edge_range_manager = EdgeRangeManager::new(holder);
+    let reversed_edge_range_manager = EdgeRangeManager::new_reversed(holder);
+    let update_reversed_ranges = !holder.is_indexing_deferred();
+
+    for_each_parallel(&holder.edges, |k, v| {
+        let mut decoder = Decoder::key(&holder.edges, k);
+        let outbound_id = decoder.read_uuid()?;
+        let t = decoder.read_type()?;
+        let inbound_id = decoder.read_uuid()?;
+
+        if !edge_range_manager.has_datetime_value(&t) {
+            return Ok(());
+        }
+
+        let update_datetime = Decoder::value(&holder.edges, k, v).read_datetime()?;
+        edge_range_manager.set(outbound_id, &t, update_datetime, inbound_id)?;
+
+        if update_reversed_ranges {
+            reversed_edge_range_manager.set(inbound_id, &t, update_datetime, outbound_id)?;
+        }
+
+        Ok(())
+    })
+}
+
+/// Rebuilds the vertex creation index from the vertices tree.
+pub(crate) fn rebuild_vertex_creations(holder: &SledHolder) -> Result<()> {
+    let vertex_creation_manager = VertexCreationManager::new(holder);
+    map_err(vertex_creation_manager.tree.clear())?;
+
+    for_each_parallel(&holder.vertices, |k, v| {
+        let id = Decoder::key(&holder.vertices, k).read_uuid()?;
+        vertex_creation_manager.set_for_value(id, v)
+    })
+}
+
+/// Rebuilds the edge type index from the edges tree.
+pub(crate) fn rebuild_edges_by_type(holder: &SledHolder) -> Result<()> {
+    let edge_type_manager = EdgeTypeManager::new(holder);
+    map_err(edge_type_manager.tree.clear())?;
+
+    for_each_parallel(&holder.edges, |k, v| {
+        let mut decoder = Decoder::key(&holder.edges, k);
+        let outbound_id = decoder.read_uuid()?;
+        let t = decoder.read_type()?;
+        let inbound_id = decoder.read_uuid()?;
+        let update_datetime = Decoder::value(&holder.edges, k, v).read_datetime()?;
+        edge_type_manager.set(&t, update_datetime, outbound_id, inbound_id)
+    })
+}
+
+/// Rebuilds the catalog from the vertices, edges and property trees.
+pub(crate) fn rebuild_catalog(holder: &SledHolder) -> Result<()> {
+    let catalog_manager = CatalogManager::new(holder);
+    map_err(catalog_manager.tree.clear())?;
+
+    for_each_parallel(&holder.vertices, |k, v| {
+        let t = Decoder::value(&holder.vertices, k, v).read_type()?;
+        catalog_manager.increment(CatalogKind::VertexType, t.0.as_bytes())
+    })?;
+
+    for_each_parallel(&holder.edges, |k, _| {
+        let mut decoder = Decoder::key(&holder.edges, k);
+        decoder.read_uuid()?;
+        let t = decoder.read_type()?;
+        catalog_manager.increment(CatalogKind::EdgeType, t.0.as_bytes())
+    })?;
+
+    for_each_parallel(&holder.vertex_properties, |k, _| {
+        let mut decoder = Decoder::key(&holder.vertex_properties, k);
+        decoder.skip(16)?;
+        catalog_manager.increment(
+            CatalogKind::VertexProperty,
+            decoder.read_fixed_length_string()?.as_bytes(),
+        )
+    })?;
+
+    for_each_parallel(&holder.edge_properties, |k, _| {
+        let mut decoder = Decoder::key(&holder.edge_properties, k);
+        decoder.read_uuid()?;
+        decoder.read_type()?;
+        decoder.read_uuid()?;
+        catalog_manager.increment(
+            CatalogKind::EdgeProperty,
+            decoder.read_fixed_length_string()?.as_bytes(),
+        )
+    })
+}
+
+/// Rebuilds the inbound-first edge property index from the edge properties
+/// tree.
+pub(crate) fn rebuild_reversed_edge_properties(holder: &SledHolder) -> Result<()> {
+    let reversed_tree = holder.reversed_edge_properties.writer();
+    map_err(reversed_tree.clear())?;
+
+    for_each_parallel(&holder.edge_properties, |k, _| {
+        let mut decoder = Decoder::key(&holder.edge_properties, k);
+        let outbound_id = decoder.read_uuid()?;
+        let t = decoder.read_type()?;
+        let inbound_id = decoder.read_uuid()?;
+        let name = decoder.read_fixed_length_string()?;
+        let reversed_key = EdgePropertyManager::reversed_key(outbound_id, &t, inbound_id, &name);
+        holder
+            .retrier
+            .run(|| reversed_tree.insert(reversed_key.as_slice(), &[]))?;
+        Ok(())
+    })
+}
+
+/// Rebuilds the per-vertex edge property index from the edge properties
+/// tree.
+pub(crate) fn rebuild_vertex_edge_properties(holder: &SledHolder) -> Result<()> {
+    let vertex_tree = holder.vertex_edge_properties.writer();
+    map_err(vertex_tree.clear())?;
+
+    for_each_parallel(&holder.edge_properties, |k, _| {
+        let mut decoder = Decoder::key(&holder.edge_properties, k);
+        let outbound_id = decoder.read_uuid()?;
+        let t = decoder.read_type()?;
+        let inbound_id = decoder.read_uuid()?;
+        let name = decoder.read_fixed_length_string()?;
+
+        for vertex_key in EdgePropertyManager::vertex_keys(outbound_id, &t, inbound_id, &name) {
+            holder.retrier.run(|| vertex_tree.insert(vertex_key.as_slice(), &[]))?;
+        }
+
+        Ok(())
+    })
+}
+
+/// Rebuilds the vertex property value index from the vertex properties
+/// tree, for the indexed properties.
+pub(crate) fn rebuild_vertex_property_values(holder: &SledHolder) -> Result<()> {
+    let value_tree = holder.vertex_property_values.writer();
+    map_err(value_tree.clear())?;
+
+    for_each_parallel(&holder.vertex_properties, |k, v| {
+        let mut decoder = Decoder::key(&holder.vertex_properties, k);
+        let id = decoder.read_uuid()?;
+        let name = decoder.read_fixed_length_string()?;
+
+        let value_json = dedup::resolve(&holder.values.store, &holder.vertex_properties, k, v)?;
+
+        if holder.is_property_indexed(&name) {
+            let value_key = VertexPropertyManager::value_key(id, &name, &value_json);
+            holder.retrier.run(|| value_tree.insert(value_key.as_slice(), &[]))?;
+        }
+
+        Ok(())
+    })
+}
+
+/// Rebuilds the vertex property number index from the vertex properties
+/// tree, for the indexed properties.
+pub(crate) fn rebuild_vertex_property_numbers(holder: &SledHolder) -> Result<()> {
+    let number_tree = holder.vertex_property_numbers.writer();
+    map_err(number_tree.clear())?;
+
+    for_each_parallel(&holder.vertex_properties, |k, v| {
+        let mut decoder = Decoder::key(&holder.vertex_properties, k);
+        let id = decoder.read_uuid()?;
+        let name = decoder.read_fixed_length_string()?;
+
+        let value_json = dedup::resolve(&holder.values.store, &holder.vertex_properties, k, v)?;
+
+        if !holder.is_property_indexed(&name) {
+            return Ok(());
+        }
+
+        if let Some(number_key) = VertexPropertyManager::number_key(id, &name, &value_json) {
+            holder.retrier.run(|| number_tree.insert(number_key.as_slice(), &[]))?;
+        }
+
+        Ok(())
+    })
+}
+
+/// Rebuilds the edge property value index from the edge properties tree,
+/// for the indexed properties.
+pub(crate) fn rebuild_edge_property_values(holder: &SledHolder) -> Result<()> {
+    let value_tree = holder.edge_property_values.writer();
+    map_err(value_tree.clear())?;
+
+    for_each_parallel(&holder.edge_properties, |k, v| {
+        let mut decoder = Decoder::key(&holder.edge_properties, k);
+        let outbound_id = decoder.read_uuid()?;
+        let t = decoder.read_type()?;
+        let inbound_id = decoder.read_uuid()?;
+        let name = decoder.read_fixed_length_string()?;
+
+        let value_json = dedup::resolve(&holder.values.store, &holder.edge_properties, k, v)?;
+
+        if holder.is_edge_property_indexed(&name) {
+            let value_key = EdgePropertyManager::value_key(outbound_id, &t, inbound_id, &name, &value_json);
+            holder.retrier.run(|| value_tree.insert(value_key.as_slice(), &[]))?;
+        }
+
+        Ok(())
+    })
+}
+
+/// Rebuilds the edge property number index from the edge properties tree,
+/// for the indexed properties.
+pub(crate) fn rebuild_edge_property_numbers(holder: &SledHolder) -> Result<()> {
+    let number_tree = holder.edge_property_numbers.writer();
+    map_err(number_tree.clear())?;
+
+    for_each_parallel(&holder.edge_properties, |k, v| {
+        let mut decoder = Decoder::key(&holder.edge_properties, k);
+        let outbound_id = decoder.read_uuid()?;
+        let t = decoder.read_type()?;
+        let inbound_id = decoder.read_uuid()?;
+        let name = decoder.read_fixed_length_string()?;
+
+        let value_json = dedup::resolve(&holder.values.store, &holder.edge_properties, k, v)?;
+
+        if !holder.is_edge_property_indexed(&name) {
+            return Ok(());
+        }
+
+        if let Some(number_key) = EdgePropertyManager::number_key(outbound_id, &t, inbound_id, &name, &value_json) {
+            holder.retrier.run(|| number_tree.insert(number_key.as_slice(), &[]))?;
+        }
+
+        Ok(())
+    })
+}
+
+/// Rebuilds every index of vertex properties once a bulk load finishes,
+/// with one pass over the vertex properties tree: the value and number
+/// indexes of the indexed properties, and the full-text, vector and
+/// composite indexes.
+pub(crate) fn rebuild_vertex_property_indexes(holder: &SledHolder) -> Result<()> {
+    let value_tree = holder.vertex_property_values.writer();
+    let number_tree = holder.vertex_property_numbers.writer();
+    map_err(value_tree.clear())?;
+    map_err(number_tree.clear())?;
+    fulltext::clear(holder)?;
+    vectors::clear(holder)?;
+    composite::clear(holder)?;
+
+    for_each_parallel(&holder.vertex_properties, |k, v| {
+        let mut decoder = Decoder::key(&holder.vertex_properties, k);
+        let id = decoder.read_uuid()?;
+        let name = decoder.read_fixed_length_string()?;
+
+        let value_json = dedup::resolve(&holder.values.store, &holder.vertex_properties, k, v)?;
+
+        if holder.is_property_indexed(&name) {
+            let value_key = VertexPropertyManager::value_key(id, &name, &value_json);
+            holder.retrier.run(|| value_tree.insert(value_key.as_slice(), &[]))?;
+
+            if let Some(number_key) = VertexPropertyManager::number_key(id, &name, &value_json) {
+                holder.retrier.run(|| number_tree.insert(number_key.as_slice(), &[]))?;
+            }
+        }
+
+        fulltext::on_property_change(holder, id, &name, None, Some(&value_json))?;
+        vectors::on_property_change(holder, id, &name, None, Some(&value_json))?;
+        composite::rebuild_entries(holder, id, &name)
+    })
+}
+
+/// Rebuilds every index of edge properties once a bulk load finishes, with
+/// one pass over the edge properties tree: the inbound-first and
+/// per-vertex indexes, and the value and number indexes of the indexed
+/// properties.
+pub(crate) fn rebuild_edge_property_indexes(holder: &SledHolder) -> Result<()> {
+    let reversed_tree = holder.reversed_edge_properties.writer();
+    let vertex_tree = holder.vertex_edge_properties.writer();
+    let value_tree = holder.edge_property_values.writer();
+    let number_tree = holder.edge_property_numbers.writer();
+    map_err(reversed_tree.clear())?;
+    map_err(vertex_tree.clear())?;
+    map_err(value_tree.clear())?;
+    map_err(number_tree.clear())?;
+
+    for_each_parallel(&holder.edge_properties, |k, v| {
+        let mut decoder = Decoder::key(&holder.edge_properties, k);
+        let outbound_id = decoder.read_uuid()?;
+        let t = decoder.read_type()?;
+        let inbound_id = decoder.read_uuid()?;
+        let name = decoder.read_fixed_length_string()?;
+
+        let value_json = dedup::resolve(&holder.values.store, &holder.edge_properties, k, v)?;
+
+        let reversed_key = EdgePropertyManager::reversed_key(outbound_id, &t, inbound_id, &name);
+        holder
+            .retrier
+            .run(|| reversed_tree.insert(reversed_key.as_slice(), &[]))?;
+
+        for vertex_key in EdgePropertyManager::vertex_keys(outbound_id, &t, inbound_id, &name) {
+            holder.retrier.run(|| vertex_tree.insert(vertex_key.as_slice(), &[]))?;
+        }
+
+        if holder.is_edge_property_indexed(&name) {
+            let value_key = EdgePropertyManager::value_key(outbound_id, &t, inbound_id, &name, &value_json);
+            holder.retrier.run(|| value_tree.insert(value_key.as_slice(), &[]))?;
+
+            if let Some(number_key) = EdgePropertyManager::number_key(outbound_id, &t, inbound_id, &name, &value_json) {
+                holder.retrier.run(|| number_tree.insert(number_key.as_slice(), &[]))?;
+            }
+        }
+
+        Ok(())
+    })
+}
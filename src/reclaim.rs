@@ -0,0 +1,204 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+
+use super::datastore::partition_tree_prefix;
+use super::errors::map_err;
+use super::reindex;
+
+use indradb::Result;
+use sled::{Batch, Db, IVec, Tree};
+
+/// The maximum number of records removed per step of a reclaim.
+const RECLAIM_BATCH_SIZE: usize = 1000;
+
+/// The metadata key prefix of the partitions being reclaimed, which are
+/// followed by the tenant ID.
+const RECLAIMING_PREFIX: &str = "reclaiming_partition:";
+
+fn marker_key(tenant: u32) -> String {
+    format!("{}{}", RECLAIMING_PREFIX, tenant)
+}
+
+/// How far along the reclaim of a dropped partition is. See
+/// `SledConfig::with_background_reclaim`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReclaimProgress {
+    /// The tenant whose partition was dropped.
+    pub tenant: u32,
+    /// The number of the partition's trees that are left to drop.
+    pub trees_remaining: u64,
+    /// The number of records removed so far.
+    pub records_removed: u64,
+}
+
+/// Tracks the reclaims running in the background. Shared by a datastore
+/// and its partitions.
+#[derive(Default)]
+pub(crate) struct Reclaimer {
+    progress: Mutex<BTreeMap<u32, ReclaimProgress>>,
+}
+
+impl Reclaimer {
+    /// Gets the progress of the running reclaims, in tenant order.
+    pub(crate) fn progress(&self) -> Vec<ReclaimProgress> {
+        self.progress.lock().unwrap().values().cloned().collect()
+    }
+
+    fn update<F: FnOnce(&mut ReclaimProgress)>(&self, tenant: u32, f: F) {
+        if let Some(progress) = self.progress.lock().unwrap().get_mut(&tenant) {
+            f(progress);
+        }
+    }
+}
+
+/// Whether a tenant's partition was dropped, but hasn't been reclaimed
+/// yet.
+pub(crate) fn is_reclaiming(metadata: &Tree, tenant: u32) -> Result<bool> {
+    map_err(metadata.contains_key(marker_key(tenant)))
+}
+
+fn partition_trees(db: &Db, tenant: u32) -> Vec<IVec> {
+    let prefix = partition_tree_prefix(tenant);
+
+    db.tree_names()
+        .into_iter()
+        .filter(|name| name.starts_with(prefix.as_bytes()))
+        .collect()
+}
+
+/// Removes a partition's entries from the metadata tree, so that a new
+/// partition for the same tenant starts afresh.
+fn clear_metadata(metadata: &Tree, tenant: u32) -> Result<()> {
+    let prefix = partition_tree_prefix(tenant);
+    let mut batch = Batch::default();
+
+    for key_prefix in &[prefix.clone(), reindex::generation_key(&prefix)] {
+        for item in metadata.scan_prefix(key_prefix).keys() {
+            batch.remove(map_err(item)?);
+        }
+    }
+
+    map_err(metadata.apply_batch(batch))
+}
+
+/// Drops a partition's trees before returning. Returns whether it had any.
+pub(crate) fn drop_now(db: &Db, metadata: &Tree, tenant: u32) -> Result<bool> {
+    let mut dropped = false;
+
+    for name in partition_trees(db, tenant) {
+        dropped |= map_err(db.drop_tree(&name))?;
+    }
+
+    clear_metadata(metadata, tenant)?;
+    Ok(dropped)
+}
+
+/// Drops a partition's trees in the cold database. These only hold the
+/// partition's value store, so they're dropped right away even with
+/// background reclaims.
+pub(crate) fn drop_cold(cold_db: &Db, tenant: u32) -> Result<()> {
+    for name in partition_trees(cold_db, tenant) {
+        map_err(cold_db.drop_tree(&name))?;
+    }
+
+    Ok(())
+}
+
+/// Marks a partition as dropped, and starts reclaiming its trees in the
+/// background. Returns whether it had any.
+pub(crate) fn start(db: &Arc<Db>, metadata: &Tree, reclaimer: &Arc<Reclaimer>, tenant: u32) -> Result<bool> {
+    let trees = partition_trees(db, tenant).len();
+
+    if trees == 0 {
+        clear_metadata(metadata, tenant)?;
+        return Ok(false);
+    }
+
+    map_err(metadata.insert(marker_key(tenant), &[]))?;
+    spawn(db, reclaimer, tenant, trees);
+    Ok(true)
+}
+
+/// Restarts the reclaims that were interrupted by the datastore being
+/// closed. This must only be called when the database is first opened.
+pub(crate) fn resume(db: &Arc<Db>, metadata: &Tree, reclaimer: &Arc<Reclaimer>) -> Result<()> {
+    for item in metadata.scan_prefix(RECLAIMING_PREFIX).keys() {
+        let key = map_err(item)?;
+        let tenant = std::str::from_utf8(&key[RECLAIMING_PREFIX.len()..])
+            .ok()
+            .and_then(|tenant| tenant.parse().ok());
+
+        // A marker that doesn't name a tenant can't have been written by
+        // `start`, so it's left alone.
+        if let Some(tenant) = tenant {
+            spawn(db, reclaimer, tenant, partition_trees(db, tenant).len());
+        }
+    }
+
+    Ok(())
+}
+
+fn spawn(db: &Arc<Db>, reclaimer: &Arc<Reclaimer>, tenant: u32, trees: usize) {
+    reclaimer.progress.lock().unwrap().insert(
+        tenant,
+        ReclaimProgress {
+            tenant,
+            trees_remaining: trees as u64,
+            records_removed: 0,
+        },
+    );
+
+    let db = Arc::downgrade(db);
+    let reclaimer = reclaimer.clone();
+
+    thread::spawn(move || {
+        // If this fails, or the datastore is closed first, the marker is
+        // left in place, so the reclaim is resumed when it's next opened.
+        let _ = reclaim(&db, &reclaimer, tenant);
+        reclaimer.progress.lock().unwrap().remove(&tenant);
+    });
+}
+
+/// Empties a partition's trees a batch at a time, dropping each once it's
+/// empty, so that no single step holds up the rest of the database for
+/// long.
+fn reclaim(db: &Weak<Db>, reclaimer: &Reclaimer, tenant: u32) -> Result<()> {
+    loop {
+        // Only hold a strong reference while working, so that the reclaim
+        // never keeps a dropped datastore open.
+        let db = match db.upgrade() {
+            Some(db) => db,
+            None => return Ok(()),
+        };
+
+        let name = match partition_trees(&db, tenant).into_iter().next() {
+            Some(name) => name,
+            None => {
+                let metadata = map_err(db.open_tree("metadata"))?;
+                clear_metadata(&metadata, tenant)?;
+                map_err(metadata.remove(marker_key(tenant)))?;
+                return Ok(());
+            }
+        };
+
+        let tree = map_err(db.open_tree(&name))?;
+        let mut batch = Batch::default();
+        let mut removed = 0;
+
+        for item in tree.iter().keys().take(RECLAIM_BATCH_SIZE) {
+            batch.remove(map_err(item)?);
+            removed += 1;
+        }
+
+        if removed == 0 {
+            map_err(db.drop_tree(&name))?;
+            reclaimer.update(tenant, |progress| {
+                progress.trees_remaining = progress.trees_remaining.saturating_sub(1)
+            });
+        } else {
+            map_err(tree.apply_batch(batch))?;
+            reclaimer.update(tenant, |progress| progress.records_removed += removed);
+        }
+    }
+}
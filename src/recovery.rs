@@ -0,0 +1,144 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use super::check::{self, ConsistencySummary};
+use super::datastore::{SledHolder, COLD_DIR};
+use super::errors::map_err;
+use super::preflight;
+
+use indradb::Result;
+use sled::{Db, Tree};
+
+/// Present in the metadata tree while a process has the datastore open. If
+/// it's there when the datastore is opened, the last process to open it
+/// didn't close it cleanly.
+pub(crate) const OPEN_MARKER_KEY: &[u8] = b"open";
+
+/// How long closing the datastore waits for sled to release its file locks.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Something that needs to be done to finish salvaging a datastore that
+/// wasn't closed cleanly, or was left mid-way through an operation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SalvageAction {
+    /// A bulk load deferred index maintenance and never finished. Call
+    /// `SledDatastore::finish_deferred_indexing` to rebuild the indexes.
+    FinishDeferredIndexing,
+
+    /// A bulk load stopped maintaining the property indexes and never
+    /// finished. Call `SledDatastore::finish_bulk_load` to rebuild them,
+    /// along with the deferred indexes.
+    FinishBulkLoad,
+}
+
+/// What happened when a datastore was opened, so that operators notice
+/// unclean shutdowns.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecoveryInfo {
+    /// Whether sled recovered existing data from disk, rather than creating
+    /// a new database.
+    pub recovered: bool,
+    /// Whether the last process to open the datastore didn't close it, e.g.
+    /// because it crashed. Writes it made after its last flush may have
+    /// been lost.
+    pub unclean_shutdown: bool,
+    /// How long it took to open the datastore, including sled's recovery.
+    pub duration: Duration,
+    /// What remains to be done to salvage the datastore.
+    pub salvage_actions: Vec<SalvageAction>,
+    /// The result of the quick consistency check, if it ran. See
+    /// `SledConfig::with_recovery_check`.
+    pub consistency: Option<ConsistencySummary>,
+}
+
+/// Marks the datastore as open for as long as this is alive, and records
+/// how it was opened.
+pub(crate) struct Session {
+    metadata: Option<Tree>,
+    // The databases, along with their directories, whose locks are waited
+    // for once the datastore is closed, so that it can be reopened straight
+    // away.
+    databases: Vec<(Weak<Db>, PathBuf)>,
+    pub(crate) info: RecoveryInfo,
+}
+
+impl Session {
+    /// Starts a session on a newly opened datastore.
+    ///
+    /// # Arguments
+    /// * `holder`: The unpartitioned datastore.
+    /// * `duration`: How long it took to open.
+    /// * `check`: Whether to run a quick consistency check after an unclean
+    ///   shutdown.
+    pub(crate) fn start(holder: &SledHolder, duration: Duration, check: bool) -> Result<Session> {
+        let recovered = holder.db.was_recovered();
+        let unclean_shutdown = recovered && map_err(holder.metadata.contains_key(OPEN_MARKER_KEY))?;
+
+        let mut salvage_actions = Vec::new();
+
+        if holder.is_bulk_loading() {
+            salvage_actions.push(SalvageAction::FinishBulkLoad);
+        } else if holder.is_indexing_deferred() {
+            salvage_actions.push(SalvageAction::FinishDeferredIndexing);
+        }
+
+        let consistency = if check && unclean_shutdown {
+            Some(check::quick_check(holder)?)
+        } else {
+            None
+        };
+
+        map_err(holder.metadata.insert(OPEN_MARKER_KEY, &[]))?;
+        map_err(holder.metadata.flush())?;
+
+        let mut databases = Vec::new();
+
+        if let Some(ref directory) = holder.directory {
+            databases.push((Arc::downgrade(&holder.db), directory.clone()));
+
+            if let Some(ref cold_db) = holder.cold_db {
+                databases.push((Arc::downgrade(cold_db), directory.join(COLD_DIR)));
+            }
+        }
+
+        Ok(Session {
+            metadata: Some(holder.metadata.clone()),
+            databases,
+            info: RecoveryInfo {
+                recovered,
+                unclean_shutdown,
+                duration,
+                salvage_actions,
+                consistency,
+            },
+        })
+    }
+}
+
+/// Waits for sled to release the locks of the databases in `directory`,
+/// once they've been closed without a session, e.g. by a failed open.
+pub(crate) fn wait_until_closed(directory: &Path) {
+    preflight::wait_until_unlocked(directory, CLOSE_TIMEOUT);
+    preflight::wait_until_unlocked(&directory.join(COLD_DIR), CLOSE_TIMEOUT);
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        // If this fails, the next open reports an unclean shutdown, which is
+        // the safe direction to be wrong in.
+        if let Some(metadata) = self.metadata.take() {
+            if metadata.remove(OPEN_MARKER_KEY).is_ok() {
+                let _ = metadata.flush();
+            }
+        }
+
+        // A database that's still referenced, e.g. by a transaction that
+        // outlived the datastore, stays open, so there's nothing to wait for.
+        for (db, directory) in self.databases.drain(..) {
+            if db.strong_count() == 0 {
+                preflight::wait_until_unlocked(&directory, CLOSE_TIMEOUT);
+            }
+        }
+    }
+}
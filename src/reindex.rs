@@ -0,0 +1,430 @@
+use std::convert::TryInto;
+use std::ops::{Bound, Deref};
+use std::str;
+use std::sync::RwLock;
+
+use super::datastore::SledHolder;
+use super::decode::Decoder;
+use super::dedup;
+use super::errors::{map_err, Error};
+use super::managers::{
+    EdgePropertyManager, EdgeRangeManager, EdgeTypeManager, VertexCreationManager, VertexPropertyManager,
+};
+
+use indradb::Result;
+use sled::{Batch, Db, IVec, Result as SledResult, Tree};
+
+/// The number of primary records indexed per step of a reindex. Writes are
+/// paused while each step runs, so this bounds how long they may stall.
+const REINDEX_CHUNK_SIZE: usize = 1000;
+
+/// The indexes that are derived from other trees, and so can be rebuilt
+/// with `SledDatastore::reindex`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Index {
+    /// Edges by outbound vertex, derived from the edges.
+    EdgeRanges,
+    /// Edges by inbound vertex, derived from the edges.
+    ReversedEdgeRanges,
+    /// Vertices by type and creation datetime, derived from the vertices.
+    VertexCreations,
+    /// Edge properties by inbound vertex, derived from the edge properties.
+    ReversedEdgeProperties,
+    /// Edge properties by each of their edge's vertices, derived from the
+    /// edge properties.
+    VertexEdgeProperties,
+    /// Vertices by property value, derived from the vertex properties
+    /// declared with `SledDatastore::index_property`.
+    VertexPropertyValues,
+    /// Vertices by numeric property value, derived from the vertex
+    /// properties declared with `SledDatastore::index_property`.
+    VertexPropertyNumbers,
+    /// Edges by property value, derived from the edge properties declared
+    /// with `SledDatastore::index_edge_property`.
+    EdgePropertyValues,
+    /// Edges by numeric property value, derived from the edge properties
+    /// declared with `SledDatastore::index_edge_property`.
+    EdgePropertyNumbers,
+    /// Edges by type and update datetime, derived from the edges.
+    EdgesByType,
+}
+
+impl Index {
+    const ALL: [Index; 10] = [
+        Index::EdgeRanges,
+        Index::ReversedEdgeRanges,
+        Index::VertexCreations,
+        Index::ReversedEdgeProperties,
+        Index::VertexEdgeProperties,
+        Index::VertexPropertyValues,
+        Index::VertexPropertyNumbers,
+        Index::EdgePropertyValues,
+        Index::EdgePropertyNumbers,
+        Index::EdgesByType,
+    ];
+
+    /// The name of the index's tree as of its first generation.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Index::EdgeRanges => "edge_ranges",
+            Index::ReversedEdgeRanges => "reversed_edge_ranges",
+            Index::VertexCreations => "vertex_creations",
+            Index::ReversedEdgeProperties => "reversed_edge_properties",
+            Index::VertexEdgeProperties => "vertex_edge_properties",
+            Index::VertexPropertyValues => "vertex_property_values",
+            Index::VertexPropertyNumbers => "vertex_property_numbers",
+            Index::EdgePropertyValues => "edge_property_values",
+            Index::EdgePropertyNumbers => "edge_property_numbers",
+            Index::EdgesByType => "edges_by_type",
+        }
+    }
+}
+
+/// The metadata key recording which generation of an index is live, given
+/// the name of its tree, including any partition prefix.
+pub(crate) fn generation_key(name: &str) -> String {
+    format!("index_generation:{}", name)
+}
+
+/// The name of the tree holding a generation of an index. Generation 0 is
+/// the original tree, so datastores that were never reindexed keep their
+/// tree names.
+fn generation_tree_name(name: &str, generation: u64) -> String {
+    if generation == 0 {
+        name.to_string()
+    } else {
+        format!("{}#{}", name, generation)
+    }
+}
+
+/// Opens the live generation of an index's tree. `name` is the name of
+/// the tree, including any partition prefix.
+pub(crate) fn open_index_tree(db: &Db, metadata: &Tree, name: &str) -> Result<IndexTree> {
+    let generation = read_generation(metadata, name)?;
+    let tree = map_err(db.open_tree(generation_tree_name(name, generation)))?;
+    Ok(IndexTree::new(tree))
+}
+
+/// Drops the trees of index generations that have been swapped out, or
+/// were left behind by an interrupted reindex. This must only be called
+/// when the database is first opened, so that no reindex is in progress.
+pub(crate) fn drop_stale_generations(db: &Db, metadata: &Tree) -> Result<()> {
+    for tree_name in db.tree_names() {
+        let tree_name = match str::from_utf8(&tree_name) {
+            Ok(tree_name) => tree_name,
+            Err(_) => continue,
+        };
+
+        let (name, generation) = match tree_name.rfind('#') {
+            Some(i) => match tree_name[i + 1..].parse::<u64>() {
+                Ok(generation) => (&tree_name[..i], generation),
+                Err(_) => continue,
+            },
+            None => (tree_name, 0),
+        };
+
+        // Index trees are named after the index, possibly with a partition
+        // prefix that ends in a colon.
+        let is_index = Index::ALL.iter().any(|index| {
+            name.ends_with(index.name()) && {
+                let prefix = &name[..name.len() - index.name().len()];
+                prefix.is_empty() || prefix.ends_with(':')
+            }
+        });
+
+        if is_index && generation != read_generation(metadata, name)? {
+            map_err(db.drop_tree(tree_name))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The tree of a derived index, which can be swapped for a rebuilt one
+/// while the datastore is in use. While a rebuild is in progress, writes
+/// go to both trees.
+pub(crate) struct IndexTree {
+    trees: RwLock<(Tree, Option<Tree>)>,
+}
+
+impl IndexTree {
+    fn new(tree: Tree) -> Self {
+        IndexTree {
+            trees: RwLock::new((tree, None)),
+        }
+    }
+
+    /// Gets a handle for reading and writing the index. Writers must hold
+    /// `SledHolder::write_guard` from before getting the handle until they
+    /// are done with it, so that they can't miss the start of a rebuild.
+    pub(crate) fn writer(&self) -> IndexWriter {
+        let trees = self.trees.read().unwrap();
+
+        IndexWriter {
+            tree: trees.0.clone(),
+            building: trees.1.clone(),
+        }
+    }
+
+    fn start_building(&self, tree: Tree) {
+        self.trees.write().unwrap().1 = Some(tree);
+    }
+
+    fn swap(&self) {
+        let mut trees = self.trees.write().unwrap();
+        if let Some(building) = trees.1.take() {
+            trees.0 = building;
+        }
+    }
+}
+
+/// A handle on a derived index. Reads go to the live tree, which this
+/// dereferences to, while writes also go to the tree being rebuilt, if
+/// any.
+#[derive(Clone)]
+pub(crate) struct IndexWriter {
+    tree: Tree,
+    building: Option<Tree>,
+}
+
+impl IndexWriter {
+    /// The live tree, followed by the tree being rebuilt, if any.
+    pub(crate) fn trees(&self) -> impl Iterator<Item = &Tree> {
+        Some(&self.tree).into_iter().chain(self.building.as_ref())
+    }
+
+    pub(crate) fn insert<K: AsRef<[u8]>, V: Into<IVec>>(&self, key: K, value: V) -> SledResult<Option<IVec>> {
+        let value = value.into();
+
+        if let Some(ref building) = self.building {
+            building.insert(key.as_ref(), value.clone())?;
+        }
+
+        self.tree.insert(key, value)
+    }
+
+    pub(crate) fn apply_batch(&self, batch: Batch) -> SledResult<()> {
+        if let Some(ref building) = self.building {
+            building.apply_batch(batch.clone())?;
+        }
+
+        self.tree.apply_batch(batch)
+    }
+
+    pub(crate) fn clear(&self) -> SledResult<()> {
+        if let Some(ref building) = self.building {
+            building.clear()?;
+        }
+
+        self.tree.clear()
+    }
+}
+
+impl Deref for IndexWriter {
+    type Target = Tree;
+
+    fn deref(&self) -> &Tree {
+        &self.tree
+    }
+}
+
+/// Computes the keys and values of the index entries for a record of the
+/// index's source tree. Only the per-vertex edge property index has more
+/// than one entry per record.
+fn index_entries(holder: &SledHolder, index: Index, k: &[u8], v: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    match index {
+        Index::EdgeRanges | Index::ReversedEdgeRanges => {
+            let mut decoder = Decoder::key(&holder.edges, k);
+            let outbound_id = decoder.read_uuid()?;
+            let t = decoder.read_type()?;
+            let inbound_id = decoder.read_uuid()?;
+            let update_datetime = Decoder::value(&holder.edges, k, v).read_datetime()?;
+
+            let (edge_range_manager, first_id, second_id) = if index == Index::EdgeRanges {
+                (EdgeRangeManager::new(holder), outbound_id, inbound_id)
+            } else {
+                (EdgeRangeManager::new_reversed(holder), inbound_id, outbound_id)
+            };
+
+            let keys = edge_range_manager.keys(first_id, &t, update_datetime, second_id)?;
+            let value = edge_range_manager.value(&t, update_datetime);
+            Ok(keys.into_iter().map(|key| (key, value.clone())).collect())
+        }
+        Index::EdgesByType => {
+            let mut decoder = Decoder::key(&holder.edges, k);
+            let outbound_id = decoder.read_uuid()?;
+            let t = decoder.read_type()?;
+            let inbound_id = decoder.read_uuid()?;
+            let update_datetime = Decoder::value(&holder.edges, k, v).read_datetime()?;
+            let key = EdgeTypeManager::key(&t, update_datetime, outbound_id, inbound_id);
+            Ok(vec![(key, Vec::new())])
+        }
+        Index::VertexCreations => {
+            let id = Decoder::key(&holder.vertices, k).read_uuid()?;
+            let key = VertexCreationManager::new(holder).key_for_value(id, v)?;
+            Ok(key.into_iter().map(|key| (key, Vec::new())).collect())
+        }
+        Index::ReversedEdgeProperties => {
+            let mut decoder = Decoder::key(&holder.edge_properties, k);
+            let outbound_id = decoder.read_uuid()?;
+            let t = decoder.read_type()?;
+            let inbound_id = decoder.read_uuid()?;
+            let name = decoder.read_fixed_length_string()?;
+            let key = EdgePropertyManager::reversed_key(outbound_id, &t, inbound_id, &name);
+            Ok(vec![(key, Vec::new())])
+        }
+        Index::VertexEdgeProperties => {
+            let mut decoder = Decoder::key(&holder.edge_properties, k);
+            let outbound_id = decoder.read_uuid()?;
+            let t = decoder.read_type()?;
+            let inbound_id = decoder.read_uuid()?;
+            let name = decoder.read_fixed_length_string()?;
+            let keys = EdgePropertyManager::vertex_keys(outbound_id, &t, inbound_id, &name);
+            Ok(keys.into_iter().map(|key| (key, Vec::new())).collect())
+        }
+        Index::VertexPropertyValues => {
+            let mut decoder = Decoder::key(&holder.vertex_properties, k);
+            let id = decoder.read_uuid()?;
+            let name = decoder.read_fixed_length_string()?;
+
+            let value_json = dedup::resolve(&holder.values.store, &holder.vertex_properties, k, v)?;
+
+            if !holder.is_property_indexed(&name) {
+                return Ok(Vec::new());
+            }
+
+            Ok(vec![(
+                VertexPropertyManager::value_key(id, &name, &value_json),
+                Vec::new(),
+            )])
+        }
+        Index::VertexPropertyNumbers => {
+            let mut decoder = Decoder::key(&holder.vertex_properties, k);
+            let id = decoder.read_uuid()?;
+            let name = decoder.read_fixed_length_string()?;
+
+            let value_json = dedup::resolve(&holder.values.store, &holder.vertex_properties, k, v)?;
+
+            if !holder.is_property_indexed(&name) {
+                return Ok(Vec::new());
+            }
+
+            let key = VertexPropertyManager::number_key(id, &name, &value_json);
+            Ok(key.into_iter().map(|key| (key, Vec::new())).collect())
+        }
+        Index::EdgePropertyValues | Index::EdgePropertyNumbers => {
+            let mut decoder = Decoder::key(&holder.edge_properties, k);
+            let outbound_id = decoder.read_uuid()?;
+            let t = decoder.read_type()?;
+            let inbound_id = decoder.read_uuid()?;
+            let name = decoder.read_fixed_length_string()?;
+
+            let value_json = dedup::resolve(&holder.values.store, &holder.edge_properties, k, v)?;
+
+            if !holder.is_edge_property_indexed(&name) {
+                return Ok(Vec::new());
+            }
+
+            let key = if index == Index::EdgePropertyValues {
+                Some(EdgePropertyManager::value_key(
+                    outbound_id,
+                    &t,
+                    inbound_id,
+                    &name,
+                    &value_json,
+                ))
+            } else {
+                EdgePropertyManager::number_key(outbound_id, &t, inbound_id, &name, &value_json)
+            };
+
+            Ok(key.into_iter().map(|key| (key, Vec::new())).collect())
+        }
+    }
+}
+
+/// Rebuilds an index into a new tree while the datastore stays available,
+/// then swaps it in.
+///
+/// The new tree is filled from the source tree in chunks. Writes are paused
+/// while each chunk is indexed, and otherwise go to both the old and the new
+/// tree, so the new tree is complete once the scan finishes. Swapping is a
+/// matter of recording the new generation and pointing the index at it.
+/// Queries that were already running keep reading the old tree, which is
+/// dropped the next time the datastore is opened.
+pub(crate) fn reindex(holder: &SledHolder, index: Index) -> Result<()> {
+    let _reindexing = holder.reindexing.lock().unwrap();
+    let index_tree = holder.index_tree(index);
+    let source = match index {
+        Index::EdgeRanges | Index::ReversedEdgeRanges | Index::EdgesByType => &holder.edges,
+        Index::VertexCreations => &holder.vertices,
+        Index::ReversedEdgeProperties
+        | Index::VertexEdgeProperties
+        | Index::EdgePropertyValues
+        | Index::EdgePropertyNumbers => &holder.edge_properties,
+        Index::VertexPropertyValues | Index::VertexPropertyNumbers => &holder.vertex_properties,
+    };
+
+    let name = holder.tree_name(index.name());
+    let generation = read_generation(&holder.metadata, &name)? + 1;
+    let building = map_err(holder.db.open_tree(generation_tree_name(&name, generation)))?;
+    // Left over from an interrupted reindex, if it's not empty.
+    map_err(building.clear())?;
+
+    {
+        let _paused = holder.index_lock.write().unwrap();
+        index_tree.start_building(building.clone());
+    }
+
+    let mut start = Bound::Unbounded;
+
+    loop {
+        let _paused = holder.index_lock.write().unwrap();
+        let mut batch = Batch::default();
+        let mut last = None;
+        let mut count = 0;
+
+        for item in source.range::<Vec<u8>, _>((start.clone(), Bound::Unbounded)) {
+            let (k, v) = map_err(item)?;
+
+            for (key, value) in index_entries(holder, index, &k, &v)? {
+                batch.insert(key, value);
+            }
+
+            count += 1;
+            if count == REINDEX_CHUNK_SIZE {
+                last = Some(k.to_vec());
+                break;
+            }
+        }
+
+        holder.retrier.run(|| building.apply_batch(batch.clone()))?;
+
+        match last {
+            Some(last) => start = Bound::Excluded(last),
+            None => break,
+        }
+    }
+
+    let _paused = holder.index_lock.write().unwrap();
+    map_err(building.flush())?;
+    map_err(holder.metadata.insert(generation_key(&name), &generation.to_be_bytes()))?;
+    map_err(holder.metadata.flush())?;
+    index_tree.swap();
+    holder.notify_mutation()
+}
+
+/// Gets the live generation of an index, given the name of its tree.
+fn read_generation(metadata: &Tree, name: &str) -> Result<u64> {
+    let key = generation_key(name);
+
+    match map_err(metadata.get(&key))? {
+        Some(value) => {
+            let bytes: [u8; 8] = value
+                .as_ref()
+                .try_into()
+                .map_err(|_| Error::CorruptMetadata { key: key.clone() })?;
+            Ok(u64::from_be_bytes(bytes))
+        }
+        None => Ok(0),
+    }
+}
@@ -0,0 +1,195 @@
+use std::ops::Bound;
+
+use super::datastore::SledHolder;
+use super::decode::Decoder;
+use super::dedup;
+use super::errors::map_err;
+use super::indexed::{self, PropertyOwner};
+use super::managers::{EdgePropertyManager, VertexPropertyManager};
+
+use indradb::{EdgeKey, Result};
+use serde_json::Value as JsonValue;
+use sled::Tree;
+use uuid::Uuid;
+
+/// The most properties moved to their new name per batch. The cursor is
+/// saved after each batch.
+const RENAME_BATCH_SIZE: usize = 500;
+
+/// The metadata key prefix of the cursors of interrupted renames, which is
+/// followed by the old name.
+const RENAME_CURSOR_PREFIX: &str = "rename_cursor:";
+
+/// The owners whose properties are renamed, in order. The cursor records
+/// the index of the one being renamed.
+const OWNERS: [PropertyOwner; 2] = [PropertyOwner::Vertex, PropertyOwner::Edge];
+
+/// A property to rename, found by `read_batch`.
+enum Found {
+    Vertex(Uuid, JsonValue),
+    Edge(EdgeKey, JsonValue),
+}
+
+fn source(holder: &SledHolder, owner: PropertyOwner) -> &Tree {
+    match owner {
+        PropertyOwner::Vertex => &holder.vertex_properties,
+        PropertyOwner::Edge => &holder.edge_properties,
+    }
+}
+
+/// Reads up to `RENAME_BATCH_SIZE` properties named `name`, from after
+/// `last_key`. Returns them along with the last key read, or `None` for it
+/// if the end of the tree was reached.
+fn read_batch(
+    holder: &SledHolder,
+    owner: PropertyOwner,
+    name: &str,
+    last_key: Option<Vec<u8>>,
+) -> Result<(Vec<Found>, Option<Vec<u8>>)> {
+    let tree = source(holder, owner);
+    let start = match last_key {
+        Some(key) => Bound::Excluded(key),
+        None => Bound::Unbounded,
+    };
+    let mut found = Vec::new();
+
+    let items = tree
+        .range::<Vec<u8>, _>((start, Bound::Unbounded))
+        .map(|item| -> Result<(Vec<u8>, Option<Found>)> {
+            let (k, v) = map_err(item)?;
+            let mut decoder = Decoder::key(tree, &k);
+
+            let found = match owner {
+                PropertyOwner::Vertex => {
+                    let id = decoder.read_uuid()?;
+
+                    if decoder.read_fixed_length_string()? != name {
+                        None
+                    } else {
+                        let value = serde_json::from_slice(&dedup::resolve(&holder.values.store, tree, &k, &v)?)?;
+                        Some(Found::Vertex(id, value))
+                    }
+                }
+                PropertyOwner::Edge => {
+                    let outbound_id = decoder.read_uuid()?;
+                    let t = decoder.read_type()?;
+                    let inbound_id = decoder.read_uuid()?;
+
+                    if decoder.read_fixed_length_string()? != name {
+                        None
+                    } else {
+                        let value = serde_json::from_slice(&dedup::resolve(&holder.values.store, tree, &k, &v)?)?;
+                        Some(Found::Edge(EdgeKey::new(outbound_id, t, inbound_id), value))
+                    }
+                }
+            };
+
+            Ok((k.to_vec(), found))
+        })
+        .filter_map(|item| holder.decode_errors.filter(item));
+
+    for item in items {
+        let (key, item) = item?;
+
+        if let Some(item) = item {
+            found.push(item);
+
+            if found.len() == RENAME_BATCH_SIZE {
+                return Ok((found, Some(key)));
+            }
+        }
+    }
+
+    Ok((found, None))
+}
+
+/// Writes a batch of properties under `new_name`, then removes them from
+/// `old_name`, so that an interrupted batch leaves each property under at
+/// least one of the names.
+fn move_batch(holder: &SledHolder, batch: Vec<Found>, old_name: &str, new_name: &str) -> Result<()> {
+    let _guard = holder.write_guard();
+    let mut vertex_items = Vec::new();
+    let mut edge_items = Vec::new();
+
+    for item in batch {
+        match item {
+            Found::Vertex(id, value) => vertex_items.push((id, new_name.to_string(), value)),
+            Found::Edge(key, value) => edge_items.push((key, new_name.to_string(), value)),
+        }
+    }
+
+    if !vertex_items.is_empty() {
+        let vertex_property_manager = VertexPropertyManager::new(holder);
+        vertex_property_manager.set_many(&vertex_items)?;
+
+        for &(id, _, _) in &vertex_items {
+            vertex_property_manager.delete(id, old_name)?;
+        }
+    }
+
+    if !edge_items.is_empty() {
+        let edge_property_manager = EdgePropertyManager::new(holder);
+        edge_property_manager.set_many(&edge_items)?;
+
+        for (key, _, _) in &edge_items {
+            edge_property_manager.delete(key.outbound_id, &key.t, key.inbound_id, old_name)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Renames every vertex and edge property named `old_name` to `new_name`,
+/// resuming an interrupted rename of `old_name`, and returns the number of
+/// properties renamed. See `SledDatastore::rename_property`.
+pub(crate) fn rename_property(holder: &SledHolder, old_name: &str, new_name: &str) -> Result<u64> {
+    if old_name == new_name {
+        return Ok(0);
+    }
+
+    let cursor_key = holder.metadata_key(&format!("{}{}", RENAME_CURSOR_PREFIX, old_name));
+
+    let (owner_index, mut last_key) = match map_err(holder.metadata.get(&cursor_key))? {
+        Some(cursor) => match cursor.split_first() {
+            Some((&owner_index, last_key)) => (owner_index as usize, Some(last_key.to_vec())),
+            None => (0, None),
+        },
+        None => (0, None),
+    };
+
+    // Index declarations are carried over before anything is moved, so
+    // that values are indexed under the new name as they're written.
+    for &owner in &OWNERS {
+        if owner.indexed(holder).read().unwrap().contains(old_name) {
+            let unique = owner == PropertyOwner::Vertex && holder.is_property_unique(old_name);
+            indexed::index_property(holder, owner, new_name, unique)?;
+        }
+    }
+
+    let mut renamed = 0;
+
+    for (i, &owner) in OWNERS.iter().enumerate().skip(owner_index) {
+        loop {
+            let (batch, next_key) = read_batch(holder, owner, old_name, last_key.take())?;
+            renamed += batch.len() as u64;
+            move_batch(holder, batch, old_name, new_name)?;
+
+            match next_key {
+                Some(key) => {
+                    let mut cursor = vec![i as u8];
+                    cursor.extend_from_slice(&key);
+                    map_err(holder.metadata.insert(&cursor_key, cursor))?;
+                    last_key = Some(key);
+                }
+                None => break,
+            }
+        }
+    }
+
+    for &owner in &OWNERS {
+        indexed::drop_index(holder, owner, old_name)?;
+    }
+
+    map_err(holder.metadata.remove(&cursor_key))?;
+    Ok(renamed)
+}
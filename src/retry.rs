@@ -0,0 +1,103 @@
+use std::io::ErrorKind;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::errors::map_err;
+
+use indradb::Result;
+use sled::{Error as SledError, Result as SledResult};
+
+/// How sled operations that fail with transient errors are retried.
+///
+/// Only I/O errors that are expected to go away on their own - e.g.
+/// interrupted system calls or timeouts - are retried. Failed attempts are
+/// followed by an exponential backoff, with random jitter so that threads
+/// contending for the same resource don't retry in lockstep.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// The maximum number of times a failed operation is retried. `0`
+    /// disables retries.
+    pub max_retries: u32,
+    /// The backoff after the first failed attempt.
+    pub initial_backoff: Duration,
+    /// The upper bound of the backoff, which otherwise doubles after every
+    /// failed attempt.
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    /// No retries, with a backoff from 10ms to 1s if `max_retries` is
+    /// raised.
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 0,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+fn is_transient(err: &SledError) -> bool {
+    match *err {
+        SledError::Io(ref err) => matches!(
+            err.kind(),
+            ErrorKind::Interrupted | ErrorKind::WouldBlock | ErrorKind::TimedOut
+        ),
+        _ => false,
+    }
+}
+
+/// Scales a backoff to between 50% and 100% of its value. This doesn't need
+/// to be random in any strong sense, so the clock is used rather than
+/// pulling in a dependency.
+fn jitter(backoff: Duration) -> Duration {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    backoff / 2 + backoff * (nanos % 1000) / 2000
+}
+
+/// Runs sled operations under a retry policy, counting the retries.
+pub(crate) struct Retrier {
+    policy: RetryPolicy,
+    retries: AtomicU64,
+}
+
+impl Retrier {
+    pub(crate) fn new(policy: RetryPolicy) -> Self {
+        Retrier {
+            policy,
+            retries: AtomicU64::new(0),
+        }
+    }
+
+    /// Runs `f`, retrying it if it fails with a transient error. Since it
+    /// may be run several times, `f` should be a single point read or
+    /// write.
+    pub(crate) fn run<T, F>(&self, mut f: F) -> Result<T>
+    where
+        F: FnMut() -> SledResult<T>,
+    {
+        let mut attempt = 0;
+        let mut backoff = self.policy.initial_backoff;
+
+        loop {
+            match f() {
+                Err(ref err) if attempt < self.policy.max_retries && is_transient(err) => {
+                    attempt += 1;
+                    self.retries.fetch_add(1, Ordering::Relaxed);
+                    thread::sleep(jitter(backoff));
+                    backoff = (backoff * 2).min(self.policy.max_backoff);
+                }
+                result => return map_err(result),
+            }
+        }
+    }
+
+    /// The total number of retries so far.
+    pub(crate) fn retries(&self) -> u64 {
+        self.retries.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,184 @@
+use std::fmt;
+use std::ops::Bound;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use super::datastore::SledHolder;
+use super::raw::{self, TreeKind};
+
+use sled::IVec;
+
+/// The trees that the scrubber walks, in order.
+const SCRUBBED_TREES: &[TreeKind] = &[
+    TreeKind::Vertices,
+    TreeKind::Edges,
+    TreeKind::EdgeRanges,
+    TreeKind::ReversedEdgeRanges,
+    TreeKind::VertexProperties,
+    TreeKind::EdgeProperties,
+    TreeKind::ReversedEdgeProperties,
+    TreeKind::VertexEdgeProperties,
+    TreeKind::VertexCreations,
+    TreeKind::VertexPropertyValues,
+    TreeKind::VertexPropertyNumbers,
+    TreeKind::EdgePropertyValues,
+    TreeKind::EdgePropertyNumbers,
+    TreeKind::EdgesByType,
+];
+
+/// A problem found by the scrubber.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScrubFinding {
+    /// The tree the problem was found in.
+    pub tree: TreeKind,
+    /// The raw key of the record that couldn't be decoded, or `None` if
+    /// sled failed to read the tree, e.g. because a page's checksum didn't
+    /// match. The rest of such a tree is skipped until the next pass.
+    pub key: Option<Vec<u8>>,
+    /// What went wrong.
+    pub reason: String,
+}
+
+/// What the scrubber has done since the datastore was opened. Returned by
+/// `SledDatastore::scrub_stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ScrubStats {
+    /// The number of records verified.
+    pub records_checked: u64,
+    /// The number of problems found. A corrupt record is counted on each
+    /// pass that finds it.
+    pub problems_found: u64,
+    /// The number of passes completed over every tree.
+    pub passes_completed: u64,
+}
+
+/// Called with each problem the scrubber finds.
+pub(crate) type ScrubHookFn = dyn Fn(&ScrubFinding) + Send + Sync;
+
+/// The hook registered via `SledConfig::with_scrub_hook`.
+#[derive(Clone)]
+pub(crate) struct ScrubHook(pub(crate) Arc<ScrubHookFn>);
+
+impl fmt::Debug for ScrubHook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("ScrubHook")
+    }
+}
+
+/// Where the next run of the scrubber picks up: the index of the tree in
+/// `SCRUBBED_TREES`, and the last key verified in it.
+#[derive(Default)]
+struct ScrubCursor {
+    tree: usize,
+    last_key: Option<IVec>,
+}
+
+/// The state of a holder's scrubber. Progress isn't persisted, so each time
+/// the datastore is opened, scrubbing starts over from the first tree.
+pub(crate) struct Scrubber {
+    cursor: Mutex<ScrubCursor>,
+    records_checked: AtomicU64,
+    problems_found: AtomicU64,
+    passes_completed: AtomicU64,
+    hook: Option<ScrubHook>,
+}
+
+impl Scrubber {
+    pub(crate) fn new(hook: Option<ScrubHook>) -> Self {
+        Scrubber {
+            cursor: Mutex::new(ScrubCursor::default()),
+            records_checked: AtomicU64::new(0),
+            problems_found: AtomicU64::new(0),
+            passes_completed: AtomicU64::new(0),
+            hook,
+        }
+    }
+
+    pub(crate) fn stats(&self) -> ScrubStats {
+        ScrubStats {
+            records_checked: self.records_checked.load(Ordering::Relaxed),
+            problems_found: self.problems_found.load(Ordering::Relaxed),
+            passes_completed: self.passes_completed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+fn report(holder: &SledHolder, finding: ScrubFinding) {
+    holder.scrubber.problems_found.fetch_add(1, Ordering::Relaxed);
+
+    if let Some(ref hook) = holder.scrubber.hook {
+        (hook.0)(&finding);
+    }
+}
+
+/// Verifies up to `limit` records, picking up where the last run left off,
+/// and returns the number verified. A run stops early at the end of a pass,
+/// so that an empty or small datastore isn't walked over and over.
+pub(crate) fn scrub(holder: &SledHolder, limit: u64) -> u64 {
+    scrub_until(holder, limit, &|| false)
+}
+
+/// Like `scrub`, but stops early once `should_stop` returns true.
+pub(crate) fn scrub_until(holder: &SledHolder, limit: u64, should_stop: &dyn Fn() -> bool) -> u64 {
+    let scrubber = &holder.scrubber;
+    let mut cursor = scrubber.cursor.lock().unwrap();
+    let mut checked = 0;
+
+    while checked < limit && !should_stop() {
+        let kind = SCRUBBED_TREES[cursor.tree];
+
+        let mut iter = match cursor.last_key.clone() {
+            Some(last_key) => raw::iter(holder, kind, (Bound::Excluded(last_key), Bound::Unbounded)),
+            None => raw::iter::<IVec, _>(holder, kind, ..),
+        };
+
+        let finished_tree = loop {
+            if checked == limit || should_stop() {
+                break false;
+            }
+
+            match iter.next_keyed() {
+                Some(Ok((key, record))) => {
+                    checked += 1;
+
+                    if let Err(err) = record {
+                        let finding = ScrubFinding {
+                            tree: kind,
+                            key: Some(key.to_vec()),
+                            reason: err.to_string(),
+                        };
+                        report(holder, finding);
+                    }
+
+                    cursor.last_key = Some(key);
+                }
+                Some(Err(err)) => {
+                    let finding = ScrubFinding {
+                        tree: kind,
+                        key: None,
+                        reason: err.to_string(),
+                    };
+                    report(holder, finding);
+                    break true;
+                }
+                None => break true,
+            }
+        };
+
+        if !finished_tree {
+            break;
+        }
+
+        cursor.last_key = None;
+        cursor.tree += 1;
+
+        if cursor.tree == SCRUBBED_TREES.len() {
+            cursor.tree = 0;
+            scrubber.passes_completed.fetch_add(1, Ordering::Relaxed);
+            break;
+        }
+    }
+
+    scrubber.records_checked.fetch_add(checked, Ordering::Relaxed);
+    checked
+}
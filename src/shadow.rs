@@ -0,0 +1,354 @@
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::datastore::{SledDatastore, SledTransaction};
+
+use indradb::{
+    BulkInsertItem, Datastore, Edge, EdgeDirection, EdgeKey, EdgeProperties, EdgeProperty, EdgePropertyQuery,
+    EdgeQuery, Result, Transaction, Type, Vertex, VertexProperties, VertexProperty, VertexPropertyQuery, VertexQuery,
+};
+use serde_json::Value as JsonValue;
+use uuid::Uuid;
+
+/// A read whose result differed between the primary and the shadow
+/// datastore.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShadowMismatch {
+    /// The name of the transaction method, e.g. `"get_vertices"`.
+    pub operation: &'static str,
+    /// The primary's result, formatted with `Debug`.
+    pub primary: String,
+    /// The shadow's result, formatted with `Debug`.
+    pub shadow: String,
+}
+
+/// What a `ShadowDatastore` has observed so far.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ShadowReport {
+    /// The number of reads mirrored to the shadow.
+    pub sampled_reads: u64,
+    /// The number of mirrored reads whose results differed.
+    pub mismatches: u64,
+    /// The number of mirrored reads that failed on the shadow, but not on
+    /// the primary.
+    pub shadow_errors: u64,
+    /// The number of writes that failed on the shadow, after succeeding on
+    /// the primary. Reads touching the written data may mismatch after
+    /// one.
+    pub shadow_write_errors: u64,
+    /// The total time the primary spent on the sampled reads.
+    pub primary_latency: Duration,
+    /// The total time the shadow spent on the sampled reads.
+    pub shadow_latency: Duration,
+}
+
+#[derive(Default)]
+struct ShadowCounters {
+    reads_seen: AtomicU64,
+    sampled_reads: AtomicU64,
+    mismatches: AtomicU64,
+    shadow_errors: AtomicU64,
+    shadow_write_errors: AtomicU64,
+    primary_nanos: AtomicU64,
+    shadow_nanos: AtomicU64,
+}
+
+type MismatchHandler = Arc<dyn Fn(&ShadowMismatch) + Send + Sync>;
+
+/// A datastore that serves everything from a primary datastore, and
+/// mirrors a sample of reads to a shadow datastore, comparing results and
+/// latencies. This is for trying out a new format or configuration, e.g. a
+/// different key encoding or set of indexes, on production traffic before
+/// migrating to it.
+///
+/// Writes are applied to the primary, then to the shadow, so that the two
+/// hold the same graph as long as the shadow starts as a copy of the
+/// primary. Only the primary's results and errors are returned; the
+/// shadow's failures are counted in the report instead. Edges are compared
+/// by key, since each datastore stamps its own update datetimes.
+pub struct ShadowDatastore {
+    primary: SledDatastore,
+    shadow: SledDatastore,
+    sample_every: u64,
+    counters: Arc<ShadowCounters>,
+    mismatch_handler: Option<MismatchHandler>,
+}
+
+impl ShadowDatastore {
+    /// Creates a shadowed datastore.
+    ///
+    /// # Arguments
+    /// * `primary`: The datastore that serves reads and writes.
+    /// * `shadow`: The datastore to compare against.
+    /// * `sample_every`: Mirrors one in this many reads to the shadow. `1`
+    ///   mirrors all of them.
+    pub fn new(primary: SledDatastore, shadow: SledDatastore, sample_every: u64) -> ShadowDatastore {
+        ShadowDatastore {
+            primary,
+            shadow,
+            sample_every: sample_every.max(1),
+            counters: Arc::new(ShadowCounters::default()),
+            mismatch_handler: None,
+        }
+    }
+
+    /// Calls `handler` with every mismatch found, e.g. to log it. It's
+    /// called on the thread that made the read.
+    pub fn with_mismatch_handler<F>(self, handler: F) -> ShadowDatastore
+    where
+        F: Fn(&ShadowMismatch) + Send + Sync + 'static,
+    {
+        ShadowDatastore {
+            mismatch_handler: Some(Arc::new(handler)),
+            ..self
+        }
+    }
+
+    /// The datastore that serves reads and writes.
+    pub fn primary(&self) -> &SledDatastore {
+        &self.primary
+    }
+
+    /// The datastore that sampled reads are compared against.
+    pub fn shadow(&self) -> &SledDatastore {
+        &self.shadow
+    }
+
+    /// Gets what has been observed since the datastore was created.
+    pub fn report(&self) -> ShadowReport {
+        let counters = &self.counters;
+
+        ShadowReport {
+            sampled_reads: counters.sampled_reads.load(Ordering::Relaxed),
+            mismatches: counters.mismatches.load(Ordering::Relaxed),
+            shadow_errors: counters.shadow_errors.load(Ordering::Relaxed),
+            shadow_write_errors: counters.shadow_write_errors.load(Ordering::Relaxed),
+            primary_latency: Duration::from_nanos(counters.primary_nanos.load(Ordering::Relaxed)),
+            shadow_latency: Duration::from_nanos(counters.shadow_nanos.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl Datastore for ShadowDatastore {
+    type Trans = ShadowTransaction;
+
+    fn sync(&self) -> Result<()> {
+        self.primary.sync()?;
+
+        if self.shadow.sync().is_err() {
+            self.counters.shadow_write_errors.fetch_add(1, Ordering::Relaxed);
+        }
+
+        Ok(())
+    }
+
+    fn transaction(&self) -> Result<Self::Trans> {
+        Ok(ShadowTransaction {
+            primary: self.primary.transaction()?,
+            shadow: self.shadow.transaction()?,
+            sample_every: self.sample_every,
+            counters: self.counters.clone(),
+            mismatch_handler: self.mismatch_handler.clone(),
+        })
+    }
+
+    fn bulk_insert<I>(&self, items: I) -> Result<()>
+    where
+        I: Iterator<Item = BulkInsertItem>,
+    {
+        let items: Vec<BulkInsertItem> = items.collect();
+        self.primary.bulk_insert(items.clone().into_iter())?;
+
+        if self.shadow.bulk_insert(items.into_iter()).is_err() {
+            self.counters.shadow_write_errors.fetch_add(1, Ordering::Relaxed);
+        }
+
+        Ok(())
+    }
+}
+
+/// A transaction over a `ShadowDatastore`.
+pub struct ShadowTransaction {
+    primary: SledTransaction,
+    shadow: SledTransaction,
+    sample_every: u64,
+    counters: Arc<ShadowCounters>,
+    mismatch_handler: Option<MismatchHandler>,
+}
+
+impl ShadowTransaction {
+    /// Runs a read on the primary and, if it's sampled, on the shadow,
+    /// comparing the results as projected by `compared`.
+    fn read<T, K, R, P>(&self, operation: &'static str, read: R, compared: P) -> Result<T>
+    where
+        R: Fn(&SledTransaction) -> Result<T>,
+        P: Fn(&T) -> K,
+        K: PartialEq + Debug,
+    {
+        let counters = &self.counters;
+
+        if !counters
+            .reads_seen
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(self.sample_every)
+        {
+            return read(&self.primary);
+        }
+
+        let start = Instant::now();
+        let primary_result = read(&self.primary)?;
+        let primary_elapsed = start.elapsed();
+
+        let start = Instant::now();
+        let shadow_result = read(&self.shadow);
+        let shadow_elapsed = start.elapsed();
+
+        counters.sampled_reads.fetch_add(1, Ordering::Relaxed);
+        counters
+            .primary_nanos
+            .fetch_add(primary_elapsed.as_nanos() as u64, Ordering::Relaxed);
+        counters
+            .shadow_nanos
+            .fetch_add(shadow_elapsed.as_nanos() as u64, Ordering::Relaxed);
+
+        match shadow_result {
+            Ok(shadow_result) => {
+                let (primary, shadow) = (compared(&primary_result), compared(&shadow_result));
+
+                if primary != shadow {
+                    counters.mismatches.fetch_add(1, Ordering::Relaxed);
+
+                    if let Some(ref handler) = self.mismatch_handler {
+                        handler(&ShadowMismatch {
+                            operation,
+                            primary: format!("{:?}", primary),
+                            shadow: format!("{:?}", shadow),
+                        });
+                    }
+                }
+            }
+            Err(_) => {
+                counters.shadow_errors.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        Ok(primary_result)
+    }
+
+    /// Runs a write on the primary, then on the shadow.
+    fn write<T, W>(&self, write: W) -> Result<T>
+    where
+        W: Fn(&SledTransaction) -> Result<T>,
+    {
+        let result = write(&self.primary)?;
+
+        if write(&self.shadow).is_err() {
+            self.counters.shadow_write_errors.fetch_add(1, Ordering::Relaxed);
+        }
+
+        Ok(result)
+    }
+}
+
+impl Transaction for ShadowTransaction {
+    fn create_vertex(&self, vertex: &Vertex) -> Result<bool> {
+        self.write(|trans| trans.create_vertex(vertex))
+    }
+
+    fn get_vertices<Q: Into<VertexQuery>>(&self, q: Q) -> Result<Vec<Vertex>> {
+        let q = q.into();
+        self.read("get_vertices", |trans| trans.get_vertices(q.clone()), Clone::clone)
+    }
+
+    fn delete_vertices<Q: Into<VertexQuery>>(&self, q: Q) -> Result<()> {
+        let q = q.into();
+        self.write(|trans| trans.delete_vertices(q.clone()))
+    }
+
+    fn get_vertex_count(&self) -> Result<u64> {
+        self.read("get_vertex_count", |trans| trans.get_vertex_count(), Clone::clone)
+    }
+
+    fn create_edge(&self, key: &EdgeKey) -> Result<bool> {
+        self.write(|trans| trans.create_edge(key))
+    }
+
+    fn get_edges<Q: Into<EdgeQuery>>(&self, q: Q) -> Result<Vec<Edge>> {
+        let q = q.into();
+        self.read(
+            "get_edges",
+            |trans| trans.get_edges(q.clone()),
+            |edges: &Vec<Edge>| edges.iter().map(|edge| edge.key.clone()).collect::<Vec<_>>(),
+        )
+    }
+
+    fn delete_edges<Q: Into<EdgeQuery>>(&self, q: Q) -> Result<()> {
+        let q = q.into();
+        self.write(|trans| trans.delete_edges(q.clone()))
+    }
+
+    fn get_edge_count(&self, id: Uuid, t: Option<&Type>, direction: EdgeDirection) -> Result<u64> {
+        self.read(
+            "get_edge_count",
+            |trans| trans.get_edge_count(id, t, direction),
+            Clone::clone,
+        )
+    }
+
+    fn get_vertex_properties(&self, q: VertexPropertyQuery) -> Result<Vec<VertexProperty>> {
+        self.read(
+            "get_vertex_properties",
+            |trans| trans.get_vertex_properties(q.clone()),
+            Clone::clone,
+        )
+    }
+
+    fn get_all_vertex_properties<Q: Into<VertexQuery>>(&self, q: Q) -> Result<Vec<VertexProperties>> {
+        let q = q.into();
+        self.read(
+            "get_all_vertex_properties",
+            |trans| trans.get_all_vertex_properties(q.clone()),
+            Clone::clone,
+        )
+    }
+
+    fn set_vertex_properties(&self, q: VertexPropertyQuery, value: &JsonValue) -> Result<()> {
+        self.write(|trans| trans.set_vertex_properties(q.clone(), value))
+    }
+
+    fn delete_vertex_properties(&self, q: VertexPropertyQuery) -> Result<()> {
+        self.write(|trans| trans.delete_vertex_properties(q.clone()))
+    }
+
+    fn get_edge_properties(&self, q: EdgePropertyQuery) -> Result<Vec<EdgeProperty>> {
+        self.read(
+            "get_edge_properties",
+            |trans| trans.get_edge_properties(q.clone()),
+            Clone::clone,
+        )
+    }
+
+    fn get_all_edge_properties<Q: Into<EdgeQuery>>(&self, q: Q) -> Result<Vec<EdgeProperties>> {
+        let q = q.into();
+        self.read(
+            "get_all_edge_properties",
+            |trans| trans.get_all_edge_properties(q.clone()),
+            |properties: &Vec<EdgeProperties>| {
+                properties
+                    .iter()
+                    .map(|properties| (properties.edge.key.clone(), properties.props.clone()))
+                    .collect::<Vec<_>>()
+            },
+        )
+    }
+
+    fn set_edge_properties(&self, q: EdgePropertyQuery, value: &JsonValue) -> Result<()> {
+        self.write(|trans| trans.set_edge_properties(q.clone(), value))
+    }
+
+    fn delete_edge_properties(&self, q: EdgePropertyQuery) -> Result<()> {
+        self.write(|trans| trans.delete_edge_properties(q.clone()))
+    }
+}
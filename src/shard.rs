@@ -0,0 +1,557 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+use super::access::{self, AccessKind};
+use super::datastore::{SledConfig, SledDatastore, SledTransaction};
+use super::deadline::Deadline;
+use super::errors::{map_err, Error};
+use super::managers::{EdgeManager, EdgeRangeItem, EdgeRangeManager};
+
+use chrono::offset::Utc;
+use chrono::DateTime;
+use indradb::{
+    BulkInsertItem, Datastore, Edge, EdgeDirection, EdgeKey, EdgeProperties, EdgeProperty, EdgePropertyQuery,
+    EdgeQuery, Result, SpecificEdgeQuery, SpecificVertexQuery, Transaction, Type, Vertex, VertexProperties,
+    VertexProperty, VertexPropertyQuery, VertexQuery,
+};
+use serde_json::Value as JsonValue;
+use uuid::Uuid;
+
+/// Picks the shard of a vertex. This uses FNV-1a rather than the standard
+/// library's hasher, whose output isn't guaranteed to be stable across
+/// releases, since placements have to stay put for the life of the data.
+fn shard_index(id: Uuid, count: usize) -> usize {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+
+    for &byte in id.as_bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+
+    (hash % count as u64) as usize
+}
+
+/// Records which shard a datastore is, or checks that it's opened as the
+/// same shard it was created as, since anything else would misplace
+/// vertices.
+fn check_placement(shard: &SledDatastore, index: usize, count: usize) -> Result<()> {
+    let holder = &shard.holder;
+    let key = holder.metadata_key("shard");
+    let placement = format!("{} of {}", index, count);
+
+    match map_err(holder.metadata.get(&key))? {
+        Some(recorded) if &recorded[..] != placement.as_bytes() => Err(Error::IncompatibleConfig {
+            reason: format!(
+                "datastore was created as shard {}, but is opened as shard {}",
+                String::from_utf8_lossy(&recorded),
+                placement
+            ),
+        }
+        .into()),
+        Some(_) => Ok(()),
+        None => {
+            map_err(holder.metadata.insert(key, placement.as_bytes()))?;
+            map_err(holder.metadata.flush())?;
+            Ok(())
+        }
+    }
+}
+
+/// A datastore that spreads a graph across several sled databases, for
+/// graphs that outgrow what a single sled file handles well.
+///
+/// Each vertex lives in one shard, picked by hashing its ID, along with its
+/// properties, its outbound edges and their properties. Queries fan out to
+/// the shards that can hold matching data and merge the results, in the
+/// same order a single datastore would return them. An edge whose inbound
+/// vertex lives in another shard is only found through that shard when
+/// looking up inbound edges, so inbound queries read every shard.
+///
+/// Each call is applied shard by shard, so a call spanning several shards
+/// isn't atomic, and `IteratorStability::Snapshot` only isolates queries
+/// within each shard. Deleting a vertex deletes the edges pointing at it
+/// from other shards before the vertex itself, so if that fails midway,
+/// the vertex is left in place and deleting it again finishes the job.
+/// Features evaluated within a datastore, such as materialized views, edge
+/// constraints on endpoint types, and `SledTransaction`'s extensions, only
+/// see each shard's own data.
+pub struct ShardedSledDatastore {
+    shards: Vec<SledDatastore>,
+    // One per shard. Held for reading while an edge is written to another
+    // shard than its inbound vertex's, and for writing while a vertex is
+    // deleted along with the edges pointing at it from other shards, so
+    // that no edge is written after its inbound vertex's deletion scanned
+    // for it.
+    inbound_locks: Arc<Vec<RwLock<()>>>,
+}
+
+impl ShardedSledDatastore {
+    /// Creates a sharded datastore. The shards must always be passed in the
+    /// same order, and their number can't change once data is written.
+    ///
+    /// # Arguments
+    /// * `shards`: The datastores to spread the graph across.
+    ///
+    /// # Panics
+    /// Panics if `shards` is empty.
+    pub fn new(shards: Vec<SledDatastore>) -> Result<ShardedSledDatastore> {
+        assert!(!shards.is_empty(), "a sharded datastore needs at least one shard");
+
+        for (index, shard) in shards.iter().enumerate() {
+            check_placement(shard, index, shards.len())?;
+        }
+
+        let inbound_locks = Arc::new(shards.iter().map(|_| RwLock::new(())).collect());
+        Ok(ShardedSledDatastore { shards, inbound_locks })
+    }
+
+    /// Opens a sharded datastore with one sled database per path, all with
+    /// the same config.
+    ///
+    /// # Arguments
+    /// * `config`: The config to open each shard with.
+    /// * `paths`: The file paths to the shards' sled databases.
+    pub fn open<P: AsRef<Path>>(config: &SledConfig, paths: &[P]) -> Result<ShardedSledDatastore> {
+        let shards: Result<Vec<SledDatastore>> = paths.iter().map(|path| config.clone().open(path)).collect();
+        ShardedSledDatastore::new(shards?)
+    }
+
+    /// The shards, in order.
+    pub fn shards(&self) -> &[SledDatastore] {
+        &self.shards
+    }
+
+    /// The index of the shard a vertex lives in.
+    ///
+    /// # Arguments
+    /// * `id`: The ID of the vertex.
+    pub fn shard_of(&self, id: Uuid) -> usize {
+        shard_index(id, self.shards.len())
+    }
+}
+
+impl Datastore for ShardedSledDatastore {
+    type Trans = ShardedTransaction;
+
+    fn sync(&self) -> Result<()> {
+        for shard in &self.shards {
+            shard.sync()?;
+        }
+
+        Ok(())
+    }
+
+    fn transaction(&self) -> Result<Self::Trans> {
+        let shards: Result<Vec<SledTransaction>> = self.shards.iter().map(|shard| shard.transaction()).collect();
+        Ok(ShardedTransaction {
+            shards: shards?,
+            inbound_locks: self.inbound_locks.clone(),
+        })
+    }
+
+    fn bulk_insert<I>(&self, items: I) -> Result<()>
+    where
+        I: Iterator<Item = BulkInsertItem>,
+    {
+        let mut shard_items: Vec<Vec<BulkInsertItem>> = self.shards.iter().map(|_| Vec::new()).collect();
+
+        for item in items {
+            let id = match item {
+                BulkInsertItem::Vertex(ref vertex) => vertex.id,
+                BulkInsertItem::Edge(ref key) => key.outbound_id,
+                BulkInsertItem::VertexProperty(id, _, _) => id,
+                BulkInsertItem::EdgeProperty(ref key, _, _) => key.outbound_id,
+            };
+
+            shard_items[self.shard_of(id)].push(item);
+        }
+
+        for (shard, items) in self.shards.iter().zip(shard_items) {
+            if !items.is_empty() {
+                shard.bulk_insert(items.into_iter())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A transaction over a `ShardedSledDatastore`.
+pub struct ShardedTransaction {
+    shards: Vec<SledTransaction>,
+    inbound_locks: Arc<Vec<RwLock<()>>>,
+}
+
+impl ShardedTransaction {
+    fn shard(&self, id: Uuid) -> &SledTransaction {
+        &self.shards[shard_index(id, self.shards.len())]
+    }
+
+    /// Splits items up by the shard of the vertex that `id` picks out of
+    /// them, keeping their order within each shard.
+    fn group<T, F>(&self, items: Vec<T>, id: F) -> Vec<Vec<T>>
+    where
+        F: Fn(&T) -> Uuid,
+    {
+        let mut groups: Vec<Vec<T>> = self.shards.iter().map(|_| Vec::new()).collect();
+
+        for item in items {
+            groups[shard_index(id(&item), self.shards.len())].push(item);
+        }
+
+        groups
+    }
+
+    fn vertex_ids<Q: Into<VertexQuery>>(&self, q: Q) -> Result<Vec<Uuid>> {
+        Ok(self.get_vertices(q)?.into_iter().map(|vertex| vertex.id).collect())
+    }
+
+    fn edge_keys<Q: Into<EdgeQuery>>(&self, q: Q) -> Result<Vec<EdgeKey>> {
+        Ok(self.get_edges(q)?.into_iter().map(|edge| edge.key).collect())
+    }
+
+    /// Looks vertices up by ID, in the order of `ids`.
+    fn get_specific_vertices(&self, ids: Vec<Uuid>) -> Result<Vec<Vertex>> {
+        let mut found = HashMap::new();
+
+        for (shard, shard_ids) in self.shards.iter().zip(self.group(ids.clone(), |id| *id)) {
+            if !shard_ids.is_empty() {
+                for vertex in shard.get_vertices(SpecificVertexQuery::new(shard_ids))? {
+                    found.insert(vertex.id, vertex);
+                }
+            }
+        }
+
+        Ok(ids.into_iter().filter_map(|id| found.get(&id).cloned()).collect())
+    }
+
+    /// Gets the edge ranges of a vertex in one direction, ordered as a
+    /// single datastore would order them. Outbound edges all live in the
+    /// vertex's shard, but inbound edges may live in any shard, so those
+    /// are merged by their range keys.
+    fn edge_ranges(
+        &self,
+        id: Uuid,
+        direction: EdgeDirection,
+        t: Option<&Type>,
+        low: Option<DateTime<Utc>>,
+        high: Option<DateTime<Utc>>,
+        limit: usize,
+    ) -> Result<Vec<EdgeRangeItem>> {
+        let deadline = Deadline::new(None);
+
+        if let EdgeDirection::Outbound = direction {
+            let holder = &self.shard(id).holder;
+            let _guard = holder.read_guard();
+            return EdgeRangeManager::new(holder).query(id, t, low, high, limit, &deadline);
+        }
+
+        let mut keyed = Vec::new();
+
+        for shard in &self.shards {
+            let holder = &shard.holder;
+            let _guard = holder.read_guard();
+            let manager = EdgeRangeManager::new_reversed(holder);
+
+            for item in manager.query(id, t, low, high, limit, &deadline)? {
+                let key = manager.key(item.0, &item.1, item.2, item.3)?;
+                keyed.push((key, item));
+            }
+        }
+
+        keyed.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(keyed.into_iter().take(limit).map(|(_, item)| item).collect())
+    }
+
+    /// Deletes the edges pointing at a vertex from shards other than its
+    /// own. This runs before the vertex itself is deleted, and each shard's
+    /// access policy is checked, so that a failure leaves the vertex to be
+    /// deleted again.
+    fn delete_foreign_inbound_edges(&self, id: Uuid) -> Result<()> {
+        let home = shard_index(id, self.shards.len());
+
+        for (index, shard) in self.shards.iter().enumerate() {
+            if index == home {
+                continue;
+            }
+
+            let holder = &shard.holder;
+            access::authorize(holder, AccessKind::Write, "delete_vertices")?;
+            let _guard = holder.write_guard();
+            let edge_manager = EdgeManager::new(holder);
+            let items: Result<Vec<EdgeRangeItem>> =
+                EdgeRangeManager::new_reversed(holder).iterate_for_owner(id).collect();
+
+            for (inbound_id, t, update_datetime, outbound_id) in items? {
+                edge_manager.delete(outbound_id, &t, inbound_id, update_datetime)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Transaction for ShardedTransaction {
+    fn create_vertex(&self, vertex: &Vertex) -> Result<bool> {
+        self.shard(vertex.id).create_vertex(vertex)
+    }
+
+    fn get_vertices<Q: Into<VertexQuery>>(&self, q: Q) -> Result<Vec<Vertex>> {
+        match q.into() {
+            VertexQuery::Range(q) => {
+                // Each shard's first `limit` matches contains the first
+                // `limit` matches overall that live in the shard.
+                let mut vertices = Vec::new();
+
+                for shard in &self.shards {
+                    vertices.extend(shard.get_vertices(q.clone())?);
+                }
+
+                vertices.sort_by_key(|vertex| vertex.id);
+                vertices.truncate(q.limit as usize);
+                Ok(vertices)
+            }
+            VertexQuery::Specific(q) => self.get_specific_vertices(q.ids),
+            VertexQuery::Pipe(q) => {
+                let direction = q.direction;
+                let ids = self.get_edges(*q.inner)?.into_iter().map(|edge| match direction {
+                    EdgeDirection::Outbound => edge.key.outbound_id,
+                    EdgeDirection::Inbound => edge.key.inbound_id,
+                });
+
+                let t = q.t;
+                let vertices = self.get_specific_vertices(ids.collect())?.into_iter();
+                Ok(vertices
+                    .filter(|vertex| t.as_ref().is_none_or(|t| &vertex.t == t))
+                    .take(q.limit as usize)
+                    .collect())
+            }
+        }
+    }
+
+    fn delete_vertices<Q: Into<VertexQuery>>(&self, q: Q) -> Result<()> {
+        for id in self.vertex_ids(q)? {
+            let _inbound_lock = self.inbound_locks[shard_index(id, self.shards.len())].write().unwrap();
+            self.delete_foreign_inbound_edges(id)?;
+            self.shard(id).delete_vertices(SpecificVertexQuery::single(id))?;
+        }
+
+        Ok(())
+    }
+
+    fn get_vertex_count(&self) -> Result<u64> {
+        let mut count = 0;
+
+        for shard in &self.shards {
+            count += shard.get_vertex_count()?;
+        }
+
+        Ok(count)
+    }
+
+    fn create_edge(&self, key: &EdgeKey) -> Result<bool> {
+        let outbound_index = shard_index(key.outbound_id, self.shards.len());
+        let inbound_index = shard_index(key.inbound_id, self.shards.len());
+
+        if outbound_index == inbound_index {
+            return self.shards[outbound_index].create_edge(key);
+        }
+
+        let _inbound_lock = self.inbound_locks[inbound_index].read().unwrap();
+        let inbound_q = SpecificVertexQuery::single(key.inbound_id);
+        if self.shards[inbound_index].get_vertices(inbound_q)?.is_empty() {
+            return Ok(false);
+        }
+
+        self.shards[outbound_index].create_edge_with("create_edge", key, false, Utc::now())
+    }
+
+    fn get_edges<Q: Into<EdgeQuery>>(&self, q: Q) -> Result<Vec<Edge>> {
+        match q.into() {
+            EdgeQuery::Specific(q) => {
+                let mut found = HashMap::new();
+
+                for (shard, keys) in self
+                    .shards
+                    .iter()
+                    .zip(self.group(q.keys.clone(), |key| key.outbound_id))
+                {
+                    if !keys.is_empty() {
+                        for edge in shard.get_edges(SpecificEdgeQuery::new(keys))? {
+                            found.insert(edge.key.clone(), edge);
+                        }
+                    }
+                }
+
+                Ok(q.keys.into_iter().filter_map(|key| found.get(&key).cloned()).collect())
+            }
+            EdgeQuery::Pipe(q) => {
+                let limit = q.limit as usize;
+                let direction = q.direction;
+                let mut edges = Vec::new();
+
+                for id in self.vertex_ids(*q.inner)? {
+                    if edges.len() == limit {
+                        break;
+                    }
+
+                    let items = self.edge_ranges(id, direction, q.t.as_ref(), q.low, q.high, limit - edges.len())?;
+
+                    edges.extend(items.into_iter().map(|(first_id, t, update_datetime, second_id)| {
+                        let key = match direction {
+                            EdgeDirection::Outbound => EdgeKey::new(first_id, t, second_id),
+                            EdgeDirection::Inbound => EdgeKey::new(second_id, t, first_id),
+                        };
+
+                        Edge::new(key, update_datetime)
+                    }));
+                }
+
+                Ok(edges)
+            }
+        }
+    }
+
+    fn delete_edges<Q: Into<EdgeQuery>>(&self, q: Q) -> Result<()> {
+        let keys = self.edge_keys(q)?;
+
+        for (shard, keys) in self.shards.iter().zip(self.group(keys, |key| key.outbound_id)) {
+            if !keys.is_empty() {
+                shard.delete_edges(SpecificEdgeQuery::new(keys))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn get_edge_count(&self, id: Uuid, t: Option<&Type>, direction: EdgeDirection) -> Result<u64> {
+        if let EdgeDirection::Outbound = direction {
+            return self.shard(id).get_edge_count(id, t, direction);
+        }
+
+        let mut count = 0;
+
+        for shard in &self.shards {
+            count += shard.get_edge_count(id, t, direction)?;
+        }
+
+        Ok(count)
+    }
+
+    fn get_vertex_properties(&self, q: VertexPropertyQuery) -> Result<Vec<VertexProperty>> {
+        let ids = self.vertex_ids(q.inner)?;
+        let mut found = HashMap::new();
+
+        for (shard, shard_ids) in self.shards.iter().zip(self.group(ids.clone(), |id| *id)) {
+            if !shard_ids.is_empty() {
+                let shard_q = VertexPropertyQuery::new(SpecificVertexQuery::new(shard_ids).into(), q.name.clone());
+
+                for property in shard.get_vertex_properties(shard_q)? {
+                    found.insert(property.id, property);
+                }
+            }
+        }
+
+        Ok(ids.into_iter().filter_map(|id| found.get(&id).cloned()).collect())
+    }
+
+    fn get_all_vertex_properties<Q: Into<VertexQuery>>(&self, q: Q) -> Result<Vec<VertexProperties>> {
+        let ids = self.vertex_ids(q)?;
+        let mut found = HashMap::new();
+
+        for (shard, shard_ids) in self.shards.iter().zip(self.group(ids.clone(), |id| *id)) {
+            if !shard_ids.is_empty() {
+                for properties in shard.get_all_vertex_properties(SpecificVertexQuery::new(shard_ids))? {
+                    found.insert(properties.vertex.id, properties);
+                }
+            }
+        }
+
+        Ok(ids.into_iter().filter_map(|id| found.get(&id).cloned()).collect())
+    }
+
+    fn set_vertex_properties(&self, q: VertexPropertyQuery, value: &JsonValue) -> Result<()> {
+        let ids = self.vertex_ids(q.inner)?;
+
+        for (shard, shard_ids) in self.shards.iter().zip(self.group(ids, |id| *id)) {
+            if !shard_ids.is_empty() {
+                let shard_q = VertexPropertyQuery::new(SpecificVertexQuery::new(shard_ids).into(), q.name.clone());
+                shard.set_vertex_properties(shard_q, value)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn delete_vertex_properties(&self, q: VertexPropertyQuery) -> Result<()> {
+        let ids = self.vertex_ids(q.inner)?;
+
+        for (shard, shard_ids) in self.shards.iter().zip(self.group(ids, |id| *id)) {
+            if !shard_ids.is_empty() {
+                let shard_q = VertexPropertyQuery::new(SpecificVertexQuery::new(shard_ids).into(), q.name.clone());
+                shard.delete_vertex_properties(shard_q)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn get_edge_properties(&self, q: EdgePropertyQuery) -> Result<Vec<EdgeProperty>> {
+        let keys = self.edge_keys(q.inner)?;
+        let mut found = HashMap::new();
+
+        for (shard, shard_keys) in self.shards.iter().zip(self.group(keys.clone(), |key| key.outbound_id)) {
+            if !shard_keys.is_empty() {
+                let shard_q = EdgePropertyQuery::new(SpecificEdgeQuery::new(shard_keys).into(), q.name.clone());
+
+                for property in shard.get_edge_properties(shard_q)? {
+                    found.insert(property.key.clone(), property);
+                }
+            }
+        }
+
+        Ok(keys.into_iter().filter_map(|key| found.get(&key).cloned()).collect())
+    }
+
+    fn get_all_edge_properties<Q: Into<EdgeQuery>>(&self, q: Q) -> Result<Vec<EdgeProperties>> {
+        let keys = self.edge_keys(q)?;
+        let mut found = HashMap::new();
+
+        for (shard, shard_keys) in self.shards.iter().zip(self.group(keys.clone(), |key| key.outbound_id)) {
+            if !shard_keys.is_empty() {
+                for properties in shard.get_all_edge_properties(SpecificEdgeQuery::new(shard_keys))? {
+                    found.insert(properties.edge.key.clone(), properties);
+                }
+            }
+        }
+
+        Ok(keys.into_iter().filter_map(|key| found.get(&key).cloned()).collect())
+    }
+
+    fn set_edge_properties(&self, q: EdgePropertyQuery, value: &JsonValue) -> Result<()> {
+        let keys = self.edge_keys(q.inner)?;
+
+        for (shard, shard_keys) in self.shards.iter().zip(self.group(keys, |key| key.outbound_id)) {
+            if !shard_keys.is_empty() {
+                let shard_q = EdgePropertyQuery::new(SpecificEdgeQuery::new(shard_keys).into(), q.name.clone());
+                shard.set_edge_properties(shard_q, value)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn delete_edge_properties(&self, q: EdgePropertyQuery) -> Result<()> {
+        let keys = self.edge_keys(q.inner)?;
+
+        for (shard, shard_keys) in self.shards.iter().zip(self.group(keys, |key| key.outbound_id)) {
+            if !shard_keys.is_empty() {
+                let shard_q = EdgePropertyQuery::new(SpecificEdgeQuery::new(shard_keys).into(), q.name.clone());
+                shard.delete_edge_properties(shard_q)?;
+            }
+        }
+
+        Ok(())
+    }
+}
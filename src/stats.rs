@@ -0,0 +1,269 @@
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::datastore::SledHolder;
+use super::deadline::Deadline;
+use super::decode::{corruption, Decoder};
+use super::errors::map_err;
+use super::managers::{EdgeManager, EdgePropertyManager, EdgeRangeManager, VertexPropertyManager};
+
+use chrono::offset::Utc;
+use chrono::DateTime;
+use indradb::{Result, Type};
+use sled::{IVec, Tree};
+use uuid::Uuid;
+
+/// Percentiles of a sampled degree distribution, by nearest rank.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DegreePercentiles {
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub max: u64,
+}
+
+impl DegreePercentiles {
+    fn from_degrees(mut degrees: Vec<u64>) -> Self {
+        if degrees.is_empty() {
+            return DegreePercentiles::default();
+        }
+
+        degrees.sort_unstable();
+
+        let percentile = |p: f64| {
+            let rank = (p * degrees.len() as f64).ceil() as usize;
+            degrees[rank.max(1) - 1]
+        };
+
+        DegreePercentiles {
+            p50: percentile(0.5),
+            p90: percentile(0.9),
+            p99: percentile(0.99),
+            max: degrees[degrees.len() - 1],
+        }
+    }
+}
+
+/// Graph statistics estimated from a sample of vertices and edges. See
+/// `SledTransaction::estimate_statistics`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GraphStatistics {
+    /// The number of distinct vertices sampled.
+    pub sampled_vertices: u64,
+    /// The number of distinct edges sampled.
+    pub sampled_edges: u64,
+    /// The mean number of outbound edges per vertex.
+    pub average_outbound_degree: f64,
+    /// The mean number of inbound edges per vertex.
+    pub average_inbound_degree: f64,
+    pub outbound_degree_percentiles: DegreePercentiles,
+    pub inbound_degree_percentiles: DegreePercentiles,
+    /// The mean number of properties per vertex.
+    pub vertex_property_density: f64,
+    /// The mean number of properties per edge.
+    pub edge_property_density: f64,
+}
+
+/// The writes made to the edges of a type since write statistics were
+/// enabled or last reset. See `SledDatastore::edge_write_stats`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EdgeWriteStats {
+    /// The edge type.
+    pub t: Type,
+    /// The number of times an edge of the type was created, updated or
+    /// deleted.
+    pub writes: u64,
+    /// The number of bytes of keys and values those writes inserted into,
+    /// or removed from, the edges tree and the edge range trees.
+    pub bytes: u64,
+}
+
+/// Where sampled edge writes are counted. See
+/// `SledConfig::with_edge_write_stats`.
+pub(crate) struct EdgeWriteRecorder {
+    /// Write counts, keyed by edge type.
+    pub(crate) tree: Tree,
+    /// Every how many writes one is sampled, if writes are recorded.
+    pub(crate) sampling: Option<u64>,
+    pub(crate) seen: AtomicU64,
+}
+
+/// Records a write to an edge, if write statistics are enabled and the
+/// write is sampled. Sampled writes are weighted by the sampling interval,
+/// so that the totals estimate the unsampled ones.
+///
+/// # Arguments
+/// * `range_writes`: The number of range entries inserted or removed.
+pub(crate) fn record_edge_write(
+    holder: &SledHolder,
+    outbound_id: Uuid,
+    t: &Type,
+    inbound_id: Uuid,
+    update_datetime: DateTime<Utc>,
+    range_writes: u64,
+) -> Result<()> {
+    let sample_every = match holder.edge_writes.sampling {
+        Some(sample_every) => sample_every,
+        None => return Ok(()),
+    };
+
+    if !holder
+        .edge_writes
+        .seen
+        .fetch_add(1, Ordering::Relaxed)
+        .is_multiple_of(sample_every)
+    {
+        return Ok(());
+    }
+
+    // Every edge record has an 8-byte datetime value, and range entries
+    // have empty values.
+    let edge_bytes = EdgeManager::build_key(outbound_id, t, inbound_id).len() as u64 + 8;
+    let range_key = EdgeRangeManager::new(holder).key(outbound_id, t, update_datetime, inbound_id)?;
+    let bytes = edge_bytes + range_writes * range_key.len() as u64;
+
+    holder.retrier.run(|| {
+        holder.edge_writes.tree.update_and_fetch(t.0.as_bytes(), |old| {
+            // A malformed value is reported when it's read, and replaced on
+            // the next write.
+            let (writes, total_bytes) = old.and_then(decode_edge_write_stats).unwrap_or((0, 0));
+            let mut value = Vec::with_capacity(16);
+            value.extend_from_slice(&(writes + sample_every).to_be_bytes());
+            value.extend_from_slice(&(total_bytes + bytes * sample_every).to_be_bytes());
+            Some(value)
+        })
+    })?;
+
+    Ok(())
+}
+
+/// Decodes the write count and byte volume of an edge type.
+fn decode_edge_write_stats(value: &[u8]) -> Option<(u64, u64)> {
+    if value.len() != 16 {
+        return None;
+    }
+
+    let writes = u64::from_be_bytes(value[..8].try_into().unwrap());
+    let bytes = u64::from_be_bytes(value[8..].try_into().unwrap());
+    Some((writes, bytes))
+}
+
+/// Gets the write statistics of every edge type that's been written to, in
+/// type order.
+pub(crate) fn edge_write_stats(holder: &SledHolder) -> Result<Vec<EdgeWriteStats>> {
+    let mut stats = Vec::new();
+
+    for item in holder.edge_writes.tree.iter() {
+        let (k, v) = map_err(item)?;
+        let tree = &holder.edge_writes.tree;
+        let name = Decoder::key(tree, &k).read_fixed_length_string()?;
+        let t = Type::new(name).map_err(|_| corruption(tree, &k))?;
+        let (writes, bytes) = decode_edge_write_stats(&v).ok_or_else(|| corruption(tree, &k))?;
+        stats.push(EdgeWriteStats { t, writes, bytes });
+    }
+
+    Ok(stats)
+}
+
+/// Picks up to `sample_size` distinct keys of `tree`, by seeking to evenly
+/// spaced points of the UUID-prefixed key space from a varying offset.
+/// Keys that follow large gaps are more likely to be picked, so this is
+/// only unbiased for evenly spread keys - which UUID-prefixed ones mostly
+/// are.
+fn sample_keys(tree: &Tree, sample_size: usize, deadline: &Deadline) -> Result<Vec<IVec>> {
+    if sample_size == 0 {
+        return Ok(Vec::new());
+    }
+
+    // This doesn't need to be random in any strong sense, so the clock is
+    // used rather than pulling in a dependency.
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+
+    let step = u128::MAX / sample_size as u128;
+    let offset = step / 1_000_000_000 * u128::from(nanos);
+    let mut seen = HashSet::new();
+    let mut keys = Vec::new();
+
+    for i in 0..sample_size as u128 {
+        deadline.tick()?;
+        let probe = Uuid::from_u128(offset + step * i);
+
+        let item = match map_err(tree.range(&probe.as_bytes()[..]..).next().transpose())? {
+            Some(item) => Some(item),
+            // Wrap around to the start of the key space.
+            None => map_err(tree.first())?,
+        };
+
+        match item {
+            Some((k, _)) => {
+                if seen.insert(k.clone()) {
+                    keys.push(k);
+                }
+            }
+            None => break,
+        }
+    }
+
+    Ok(keys)
+}
+
+fn mean(total: u64, count: usize) -> f64 {
+    if count == 0 {
+        0.0
+    } else {
+        total as f64 / count as f64
+    }
+}
+
+/// Estimates graph statistics from up to `sample_size` vertices and
+/// `sample_size` edges.
+pub(crate) fn estimate(holder: &SledHolder, sample_size: usize, deadline: &Deadline) -> Result<GraphStatistics> {
+    let edge_range_manager = EdgeRangeManager::new(holder);
+    let reversed_edge_range_manager = EdgeRangeManager::new_reversed(holder);
+    let vertex_property_manager = VertexPropertyManager::new(holder);
+    let edge_property_manager = EdgePropertyManager::new(holder);
+
+    let vertex_keys = sample_keys(&holder.vertices, sample_size, deadline)?;
+    let mut outbound_degrees = Vec::with_capacity(vertex_keys.len());
+    let mut inbound_degrees = Vec::with_capacity(vertex_keys.len());
+    let mut vertex_properties = 0;
+
+    for k in &vertex_keys {
+        let id = Decoder::key(&holder.vertices, k).read_uuid()?;
+        outbound_degrees.push(edge_range_manager.count_for_owner(id, deadline)?);
+        inbound_degrees.push(reversed_edge_range_manager.count_for_owner(id, deadline)?);
+        vertex_properties += vertex_property_manager.count_for_owner(id)?;
+    }
+
+    let edge_keys = sample_keys(&holder.edges, sample_size, deadline)?;
+    let mut edge_properties = 0;
+
+    for k in &edge_keys {
+        let mut decoder = Decoder::key(&holder.edges, k);
+        let outbound_id = decoder.read_uuid()?;
+        let t = decoder.read_type()?;
+        let inbound_id = decoder.read_uuid()?;
+        edge_properties += edge_property_manager.count_for_owner(outbound_id, &t, inbound_id)?;
+    }
+
+    Ok(GraphStatistics {
+        sampled_vertices: vertex_keys.len() as u64,
+        sampled_edges: edge_keys.len() as u64,
+        average_outbound_degree: mean(outbound_degrees.iter().sum(), outbound_degrees.len()),
+        average_inbound_degree: mean(inbound_degrees.iter().sum(), inbound_degrees.len()),
+        outbound_degree_percentiles: DegreePercentiles::from_degrees(outbound_degrees),
+        inbound_degree_percentiles: DegreePercentiles::from_degrees(inbound_degrees),
+        vertex_property_density: mean(vertex_properties, vertex_keys.len()),
+        edge_property_density: mean(edge_properties, edge_keys.len()),
+    })
+}
+
+/// Clears the write statistics of every edge type.
+pub(crate) fn reset_edge_write_stats(holder: &SledHolder) -> Result<()> {
+    map_err(holder.edge_writes.tree.clear())
+}
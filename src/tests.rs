@@ -0,0 +1,4462 @@
+use std::cell::Cell;
+use std::future::Future;
+use std::io::{Error as IoError, ErrorKind};
+use std::ops::Bound;
+use std::slice;
+use std::sync::{Arc, Barrier, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread;
+use std::time::Duration;
+
+use super::preflight::wait_until_unlocked;
+use super::recovery::OPEN_MARKER_KEY;
+use super::retry::Retrier;
+use super::{
+    diff_checkpoints, Access, AccessKind, AccessPolicy, CancellationToken, CascadePolicy, DatetimePrecision,
+    DecodeErrorPolicy, DegreePercentiles, Durability, Error, GraphChange, GraphStatistics, InboundTypeSummary, Index,
+    IteratorStability, OpContext, PlanStep, PreflightCheck, RawRecord, RawTreeAccess, ReadOptions, RetryPolicy, Role,
+    ScrubFinding, ShadowDatastore, ShardedSledDatastore, SharedDatastore, SledConfig, SledDatastore, SledTransaction,
+    StorageMode, TreeKind, UnionDatastore, FORMAT_VERSION,
+};
+
+use chrono::offset::Utc;
+use chrono::{DateTime, Duration as ChronoDuration, TimeZone};
+use indradb::{
+    BulkInsertItem, Datastore, Edge, EdgeDirection, EdgeKey, EdgePropertyQuery, EdgeQuery, EdgeQueryExt,
+    Error as IndraError, NamedProperty, PipeEdgeQuery, RangeVertexQuery, Result, SpecificEdgeQuery,
+    SpecificVertexQuery, Transaction, Type, Vertex, VertexPropertyQuery, VertexQuery, VertexQueryExt,
+};
+use serde_json::{json, Value as JsonValue};
+use sled::{Error as SledError, Tree};
+use tempfile::tempdir;
+use uuid::Uuid;
+
+const BATCHES: u128 = 50;
+const BATCH_SIZE: u128 = 20;
+
+fn datastore(iterator_stability: IteratorStability) -> Arc<SledDatastore> {
+    let path = tempdir().unwrap().into_path();
+    let datastore = SledConfig::default()
+        .with_iterator_stability(iterator_stability)
+        .open(path)
+        .unwrap();
+    Arc::new(datastore)
+}
+
+/// Creates a source vertex, then spawns a thread that links it to batches
+/// of new vertices, with one `bulk_insert` call per batch.
+fn spawn_writer(datastore: &Arc<SledDatastore>) -> (Uuid, thread::JoinHandle<()>) {
+    let t = Type::new("test_edge_type").unwrap();
+    let source_id = Uuid::from_u128(u128::MAX);
+    let trans = datastore.transaction().unwrap();
+    trans.create_vertex(&Vertex::with_id(source_id, t.clone())).unwrap();
+
+    let datastore = datastore.clone();
+    let handle = thread::spawn(move || {
+        for batch in 0..BATCHES {
+            let mut items = Vec::new();
+
+            for i in 0..BATCH_SIZE {
+                let id = Uuid::from_u128(batch * BATCH_SIZE + i + 1);
+                items.push(BulkInsertItem::Vertex(Vertex::with_id(id, t.clone())));
+                items.push(BulkInsertItem::Edge(EdgeKey::new(source_id, t.clone(), id)));
+            }
+
+            datastore.bulk_insert(items.into_iter()).unwrap();
+        }
+    });
+
+    (source_id, handle)
+}
+
+#[test]
+fn should_isolate_queries_from_writes_with_snapshot_stability() {
+    let datastore = datastore(IteratorStability::Snapshot);
+    let (source_id, writer) = spawn_writer(&datastore);
+    let trans = datastore.transaction().unwrap();
+
+    loop {
+        let finished = writer.is_finished();
+        let count = trans.get_edge_count(source_id, None, EdgeDirection::Outbound).unwrap();
+
+        // Writes are never partially visible, so the count is always a
+        // whole number of batches.
+        assert_eq!(count % BATCH_SIZE as u64, 0);
+
+        if finished {
+            assert_eq!(count, (BATCHES * BATCH_SIZE) as u64);
+            break;
+        }
+    }
+
+    writer.join().unwrap();
+}
+
+#[test]
+fn should_observe_writes_while_running_with_live_stability() {
+    let datastore = datastore(IteratorStability::Live);
+    let (source_id, writer) = spawn_writer(&datastore);
+    let trans = datastore.transaction().unwrap();
+    let mut last_count = 0;
+
+    loop {
+        let finished = writer.is_finished();
+        let count = trans.get_edge_count(source_id, None, EdgeDirection::Outbound).unwrap();
+
+        // Batches may be partially visible, but since edges are only ever
+        // added, what's visible only grows.
+        assert!(count >= last_count);
+        last_count = count;
+
+        if finished {
+            assert_eq!(count, (BATCHES * BATCH_SIZE) as u64);
+            break;
+        }
+    }
+
+    writer.join().unwrap();
+}
+
+fn assert_corruption(err: IndraError, expected_tree: &Tree, expected_key: &[u8]) {
+    match err {
+        IndraError::Datastore { inner } => match inner.downcast_ref::<Error>() {
+            Some(Error::Corruption { tree, key }) => {
+                assert_eq!(tree.as_bytes(), &expected_tree.name()[..]);
+                assert_eq!(&key[..], expected_key);
+            }
+            _ => panic!("unexpected error: {}", inner),
+        },
+        err => panic!("unexpected error: {}", err),
+    }
+}
+
+#[test]
+fn should_return_corruption_error_for_malformed_value() {
+    let datastore = datastore(IteratorStability::Live);
+    let id = Uuid::from_u128(1);
+    let trans = datastore.transaction().unwrap();
+    trans
+        .create_vertex(&Vertex::with_id(id, Type::new("test_vertex_type").unwrap()))
+        .unwrap();
+
+    // The type claims to be longer than the rest of the value.
+    let vertices = &datastore.holder.vertices;
+    vertices.insert(id.as_bytes(), &[200, b'a']).unwrap();
+
+    let err = trans.get_vertices(SpecificVertexQuery::single(id)).unwrap_err();
+    assert_corruption(err, vertices, id.as_bytes());
+}
+
+#[test]
+fn should_skip_corrupt_records_per_decode_error_policy() {
+    let path = tempdir().unwrap().into_path();
+    let datastore = SledConfig::default()
+        .with_decode_error_policy(DecodeErrorPolicy::SkipAndReport)
+        .open(path)
+        .unwrap();
+    let t = Type::new("test_vertex_type").unwrap();
+    let trans = datastore.transaction().unwrap();
+
+    for i in 1..4 {
+        let id = Uuid::from_u128(i);
+        trans.create_vertex(&Vertex::with_id(id, t.clone())).unwrap();
+        trans
+            .set_vertex_properties(
+                VertexPropertyQuery::new(SpecificVertexQuery::single(id).into(), "name".to_string()),
+                &JsonValue::from("value"),
+            )
+            .unwrap();
+    }
+
+    // Vertex 2's type is truncated, and one of vertex 3's properties isn't
+    // JSON.
+    let vertex_key = Uuid::from_u128(2).as_bytes().to_vec();
+    datastore.holder.vertices.insert(&vertex_key, &[200, b'a']).unwrap();
+    let mut property_key = Uuid::from_u128(3).as_bytes().to_vec();
+    property_key.extend_from_slice(b"broken");
+    datastore
+        .holder
+        .vertex_properties
+        .insert(&property_key, &b"{"[..])
+        .unwrap();
+
+    let vertices = trans.get_vertices(RangeVertexQuery::new().limit(10)).unwrap();
+    assert_eq!(
+        vertices.iter().map(|vertex| vertex.id.as_u128()).collect::<Vec<_>>(),
+        vec![1, 3]
+    );
+
+    let properties = trans
+        .get_all_vertex_properties(SpecificVertexQuery::single(Uuid::from_u128(3)))
+        .unwrap();
+    assert_eq!(properties[0].props.len(), 1);
+
+    // Records skipped again are counted again, but reported once.
+    trans.get_vertices(RangeVertexQuery::new().limit(10)).unwrap();
+    let skipped = datastore.skipped_records();
+    assert_eq!(skipped.count, 3);
+    assert_eq!(
+        skipped.records,
+        vec![
+            (
+                String::from_utf8_lossy(&datastore.holder.vertices.name()).into_owned(),
+                vertex_key
+            ),
+            ("vertex_properties".to_string(), property_key),
+        ]
+    );
+}
+
+#[test]
+fn should_scrub_records_and_report_corruption() {
+    let findings = Arc::new(Mutex::new(Vec::new()));
+    let hook_findings = findings.clone();
+    let datastore = SledConfig::default()
+        .with_scrub_hook(move |finding: &ScrubFinding| hook_findings.lock().unwrap().push(finding.clone()))
+        .open(tempdir().unwrap().into_path())
+        .unwrap();
+    let t = Type::new("test_vertex_type").unwrap();
+    let trans = datastore.transaction().unwrap();
+
+    for i in 1..4 {
+        trans
+            .create_vertex(&Vertex::with_id(Uuid::from_u128(i), t.clone()))
+            .unwrap();
+    }
+
+    let vertex_key = Uuid::from_u128(2).as_bytes().to_vec();
+    datastore.holder.vertices.insert(&vertex_key, &[200, b'a']).unwrap();
+
+    assert_eq!(datastore.scrub(2), 2);
+    let stats = datastore.scrub_stats();
+    assert_eq!(
+        (stats.records_checked, stats.problems_found, stats.passes_completed),
+        (2, 1, 0)
+    );
+
+    {
+        let findings = findings.lock().unwrap();
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].tree, TreeKind::Vertices);
+        assert_eq!(findings[0].key, Some(vertex_key));
+    }
+
+    // The next run picks up after the corrupt record, and stops at the end
+    // of the pass.
+    let checked = datastore.scrub(1000);
+    assert!(checked > 1 && checked < 1000);
+    let stats = datastore.scrub_stats();
+    assert_eq!((stats.problems_found, stats.passes_completed), (1, 1));
+    assert_eq!(stats.records_checked, checked + 2);
+
+    // Scrubbing in the background finds corruption on its own.
+    let datastore = SledConfig::default()
+        .with_scrubbing(Duration::from_millis(10), 100)
+        .open(tempdir().unwrap().into_path())
+        .unwrap();
+    datastore
+        .transaction()
+        .unwrap()
+        .create_vertex(&Vertex::with_id(Uuid::from_u128(1), t))
+        .unwrap();
+    datastore
+        .holder
+        .vertices
+        .insert(Uuid::from_u128(1).as_bytes(), &[200, b'a'])
+        .unwrap();
+
+    for _ in 0..500 {
+        if datastore.scrub_stats().problems_found > 0 {
+            break;
+        }
+
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    assert!(datastore.scrub_stats().problems_found > 0);
+}
+
+#[test]
+fn should_return_corruption_error_for_truncated_key() {
+    let datastore = datastore(IteratorStability::Live);
+    let id = Uuid::from_u128(1);
+    let trans = datastore.transaction().unwrap();
+
+    let mut key = id.as_bytes().to_vec();
+    key.extend_from_slice(&[5, b'a']);
+    let edge_ranges = datastore.holder.edge_ranges.writer();
+    edge_ranges.insert(&key, &[]).unwrap();
+
+    let err = trans.get_edge_count(id, None, EdgeDirection::Outbound).unwrap_err();
+    assert_corruption(err, &edge_ranges, &key);
+}
+
+#[test]
+fn should_time_out_scans_past_the_deadline() {
+    let datastore = datastore(IteratorStability::Live);
+    let (source_id, writer) = spawn_writer(&datastore);
+    writer.join().unwrap();
+
+    let trans = datastore.transaction().unwrap().with_timeout(Duration::from_secs(0));
+
+    match trans.get_edge_count(source_id, None, EdgeDirection::Outbound) {
+        Err(IndraError::Datastore { inner }) => match inner.downcast_ref::<Error>() {
+            Some(Error::Timeout { processed, .. }) => assert!(*processed < (BATCHES * BATCH_SIZE) as u64),
+            _ => panic!("unexpected error: {}", inner),
+        },
+        result => panic!("unexpected result: {:?}", result),
+    }
+
+    let trans = datastore.transaction().unwrap().with_timeout(Duration::from_secs(60));
+    let count = trans.get_edge_count(source_id, None, EdgeDirection::Outbound).unwrap();
+    assert_eq!(count, (BATCHES * BATCH_SIZE) as u64);
+}
+
+#[test]
+fn should_stop_scans_once_cancelled() {
+    let datastore = datastore(IteratorStability::Live);
+    let (source_id, writer) = spawn_writer(&datastore);
+    writer.join().unwrap();
+
+    let token = CancellationToken::new();
+    let context = OpContext::new().with_cancellation(token.clone()).with_tag("caller");
+    let trans = datastore.transaction().unwrap().with_context(context);
+    let count = trans.get_edge_count(source_id, None, EdgeDirection::Outbound).unwrap();
+    assert_eq!(count, (BATCHES * BATCH_SIZE) as u64);
+    assert_eq!(datastore.work_by_tag()["caller"], count);
+
+    token.cancel();
+    match trans.get_edge_count(source_id, None, EdgeDirection::Outbound) {
+        Err(IndraError::Datastore { inner }) => match inner.downcast_ref::<Error>() {
+            Some(Error::Cancelled { processed }) => assert_eq!(*processed, 0),
+            _ => panic!("unexpected error: {}", inner),
+        },
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
+
+#[test]
+fn should_keep_writes_made_during_a_reindex() {
+    let datastore = datastore(IteratorStability::Live);
+    let (source_id, writer) = spawn_writer(&datastore);
+
+    while !writer.is_finished() {
+        datastore.reindex(Index::EdgeRanges).unwrap();
+        datastore.reindex(Index::ReversedEdgeRanges).unwrap();
+    }
+
+    writer.join().unwrap();
+
+    let trans = datastore.transaction().unwrap();
+    let count = trans.get_edge_count(source_id, None, EdgeDirection::Outbound).unwrap();
+    assert_eq!(count, (BATCHES * BATCH_SIZE) as u64);
+    let count = trans
+        .get_edge_count(Uuid::from_u128(1), None, EdgeDirection::Inbound)
+        .unwrap();
+    assert_eq!(count, 1);
+}
+
+#[test]
+fn should_rename_properties_of_vertices_and_edges() {
+    let datastore = datastore(IteratorStability::Live);
+    datastore.index_property("colour").unwrap();
+    let t = Type::new("test_vertex_type").unwrap();
+    let trans = datastore.transaction().unwrap();
+    let ids: Vec<Uuid> = (1..=3).map(Uuid::from_u128).collect();
+    for &id in &ids {
+        trans.create_vertex(&Vertex::with_id(id, t.clone())).unwrap();
+    }
+
+    let vertex_q =
+        |id: Uuid, name: &str| VertexPropertyQuery::new(SpecificVertexQuery::single(id).into(), name.to_string());
+    trans
+        .set_vertex_properties(vertex_q(ids[0], "colour"), &json!("red"))
+        .unwrap();
+    trans
+        .set_vertex_properties(vertex_q(ids[1], "colour"), &json!("blue"))
+        .unwrap();
+    trans
+        .set_vertex_properties(vertex_q(ids[1], "color"), &json!("stale"))
+        .unwrap();
+    trans
+        .set_vertex_properties(vertex_q(ids[2], "size"), &json!(3))
+        .unwrap();
+
+    let key = EdgeKey::new(ids[0], t, ids[2]);
+    trans.create_edge(&key).unwrap();
+    let edge_q = |name: &str| EdgePropertyQuery::new(SpecificEdgeQuery::single(key.clone()).into(), name.to_string());
+    trans.set_edge_properties(edge_q("colour"), &json!("green")).unwrap();
+
+    assert_eq!(datastore.rename_property("colour", "color").unwrap(), 3);
+    assert!(trans
+        .get_vertex_properties(vertex_q(ids[0], "colour"))
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        trans.get_vertex_properties(vertex_q(ids[1], "color")).unwrap()[0].value,
+        json!("blue")
+    );
+    assert_eq!(
+        trans.get_vertex_properties(vertex_q(ids[2], "size")).unwrap()[0].value,
+        json!(3)
+    );
+    assert!(trans.get_edge_properties(edge_q("colour")).unwrap().is_empty());
+    assert_eq!(
+        trans.get_edge_properties(edge_q("color")).unwrap()[0].value,
+        json!("green")
+    );
+
+    assert_eq!(datastore.indexed_properties(), vec!["color".to_string()]);
+    let red = trans.get_vertices_by_property("color", &json!("red")).unwrap();
+    assert_eq!(red.iter().map(|v| v.id).collect::<Vec<_>>(), vec![ids[0]]);
+    assert_eq!(datastore.rename_property("colour", "color").unwrap(), 0);
+}
+
+#[test]
+fn should_create_edges_at_given_datetimes() {
+    let t = Type::new("test_edge_type").unwrap();
+    let monotonic = SledConfig::default()
+        .with_monotonic_edge_datetimes()
+        .open(tempdir().unwrap().into_path())
+        .unwrap();
+    let trans = monotonic.transaction().unwrap();
+    for id in 1..=3 {
+        trans
+            .create_vertex(&Vertex::with_id(Uuid::from_u128(id), t.clone()))
+            .unwrap();
+    }
+
+    let key = EdgeKey::new(Uuid::from_u128(1), t.clone(), Uuid::from_u128(2));
+    let update_datetime = |key: &EdgeKey| -> DateTime<Utc> {
+        let edges = trans.get_edges(SpecificEdgeQuery::single(key.clone())).unwrap();
+        edges[0].created_datetime
+    };
+    let earlier = Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap();
+    let later = Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap();
+
+    assert!(trans.create_edge_at(&key, later).unwrap());
+    assert_eq!(update_datetime(&key), later);
+    assert!(trans.create_edge_at(&key, later).unwrap());
+
+    match trans.create_edge_at(&key, earlier) {
+        Err(IndraError::Datastore { inner }) => match inner.downcast_ref::<Error>() {
+            Some(Error::EdgeDatetimeNotMonotonic { current, given, .. }) => {
+                assert_eq!((*current, *given), (later, earlier));
+            }
+            _ => panic!("unexpected error: {}", inner),
+        },
+        result => panic!("unexpected result: {:?}", result),
+    }
+    assert_eq!(update_datetime(&key), later);
+
+    let other_key = EdgeKey::new(Uuid::from_u128(1), t.clone(), Uuid::from_u128(3));
+    let missing_key = EdgeKey::new(Uuid::from_u128(1), t, Uuid::from_u128(4));
+    assert_eq!(
+        trans
+            .create_edges_at(&[other_key.clone(), missing_key], earlier)
+            .unwrap(),
+        vec![true, false]
+    );
+    assert_eq!(update_datetime(&other_key), earlier);
+    assert!(trans.create_edge(&key).unwrap());
+    assert!(update_datetime(&key) > later);
+
+    // Without the check, edges can be moved back in time.
+    let datastore = datastore(IteratorStability::Live);
+    let trans = datastore.transaction().unwrap();
+    let t = Type::new("test_edge_type").unwrap();
+    trans
+        .create_vertex(&Vertex::with_id(Uuid::from_u128(1), t.clone()))
+        .unwrap();
+    trans.create_vertex(&Vertex::with_id(Uuid::from_u128(2), t)).unwrap();
+    assert!(trans.create_edge(&key).unwrap());
+    assert!(trans.create_edge_at(&key, earlier).unwrap());
+    assert_eq!(
+        trans.get_edges(SpecificEdgeQuery::single(key)).unwrap()[0].created_datetime,
+        earlier
+    );
+}
+
+#[test]
+fn should_read_the_graph_as_of_a_point_in_time() {
+    let datastore = SledConfig::default()
+        .with_history()
+        .open(tempdir().unwrap().into_path())
+        .unwrap();
+    let trans = datastore.transaction().unwrap();
+    let (user_t, follows_t) = (Type::new("user").unwrap(), Type::new("follows").unwrap());
+    let ids: Vec<Uuid> = (1..=3).map(Uuid::from_u128).collect();
+    let before = Utc::now();
+    thread::sleep(Duration::from_millis(2));
+
+    for id in &ids {
+        trans.create_vertex(&Vertex::with_id(*id, user_t.clone())).unwrap();
+    }
+
+    let first_edge = EdgeKey::new(ids[0], follows_t.clone(), ids[1]);
+    let second_edge = EdgeKey::new(ids[2], follows_t.clone(), ids[1]);
+    let name_q = SpecificVertexQuery::single(ids[0]).property("name");
+    let weight_q = SpecificVertexQuery::single(ids[0]).outbound().property("weight");
+    trans.create_edge(&first_edge).unwrap();
+    trans.set_vertex_properties(name_q.clone(), &json!("a")).unwrap();
+    trans.set_edge_properties(weight_q.clone(), &json!(1)).unwrap();
+
+    thread::sleep(Duration::from_millis(2));
+    let first = Utc::now();
+    thread::sleep(Duration::from_millis(2));
+
+    // History is recorded when edges are written, not at the update
+    // datetime they're given.
+    let long_ago = Utc.with_ymd_and_hms(2000, 1, 1, 0, 0, 0).unwrap();
+    trans.create_edge_at(&second_edge, long_ago).unwrap();
+    trans
+        .delete_edges(SpecificEdgeQuery::single(first_edge.clone()))
+        .unwrap();
+    trans.set_vertex_properties(name_q.clone(), &json!("b")).unwrap();
+    trans.delete_vertices(SpecificVertexQuery::single(ids[0])).unwrap();
+    let second = Utc::now();
+
+    let keys = |edges: Vec<Edge>| edges.into_iter().map(|edge| edge.key).collect::<Vec<_>>();
+    let vertex_ids = |vertices: Vec<Vertex>| vertices.into_iter().map(|vertex| vertex.id).collect::<Vec<_>>();
+
+    let view = trans.as_of(before).unwrap();
+    assert!(view.get_vertices(RangeVertexQuery::new()).unwrap().is_empty());
+
+    let view = trans.as_of(first).unwrap();
+    assert_eq!(vertex_ids(view.get_vertices(RangeVertexQuery::new()).unwrap()), ids);
+    assert_eq!(
+        vertex_ids(
+            view.get_vertices(RangeVertexQuery::new().start_id(ids[0]).limit(1))
+                .unwrap()
+        ),
+        vec![ids[1]]
+    );
+    assert_eq!(keys(view.get_inbound_edges(ids[1]).unwrap()), vec![first_edge.clone()]);
+    assert_eq!(
+        keys(view.get_edges(SpecificVertexQuery::single(ids[1]).inbound()).unwrap()),
+        vec![first_edge.clone()]
+    );
+    assert!(view.get_edge(&second_edge).unwrap().is_none());
+    assert_eq!(
+        vertex_ids(
+            view.get_vertices(SpecificVertexQuery::single(ids[0]).outbound().inbound())
+                .unwrap()
+        ),
+        vec![ids[1]]
+    );
+    assert_eq!(view.get_vertex_properties(name_q.clone()).unwrap()[0].value, json!("a"));
+    assert_eq!(view.get_edge_properties(weight_q.clone()).unwrap()[0].value, json!(1));
+
+    let view = trans.as_of(second).unwrap();
+    assert_eq!(
+        vertex_ids(view.get_vertices(RangeVertexQuery::new()).unwrap()),
+        ids[1..].to_vec()
+    );
+    let inbound = view.get_edges(SpecificVertexQuery::single(ids[1]).inbound()).unwrap();
+    assert_eq!(keys(inbound.clone()), vec![second_edge]);
+    assert_eq!(inbound[0].created_datetime, long_ago);
+    assert!(view.get_outbound_edges(ids[0]).unwrap().is_empty());
+    assert!(view.get_vertex_properties(name_q).unwrap().is_empty());
+    assert!(view.get_edge_properties(weight_q).unwrap().is_empty());
+}
+
+#[test]
+fn should_record_audit_entries_of_mutating_calls() {
+    let datastore = SledConfig::default()
+        .with_audit_log()
+        .open(tempdir().unwrap().into_path())
+        .unwrap();
+    let trans = datastore.transaction().unwrap().with_audit_context("alice");
+    let before = Utc::now();
+    trans
+        .create_vertex(&Vertex::with_id(Uuid::from_u128(1), Type::new("user").unwrap()))
+        .unwrap();
+    trans.get_vertex_count().unwrap();
+
+    let entries = datastore.get_audit_entries(None, None).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].operation, "create_vertex");
+    assert_eq!(entries[0].context, Some("alice".to_string()));
+    assert!(entries[0].datetime >= before);
+
+    // Bounds past the range of nanosecond timestamps are clamped to it.
+    let ancient = Utc.with_ymd_and_hms(1000, 1, 1, 0, 0, 0).unwrap();
+    let distant = Utc.with_ymd_and_hms(3000, 1, 1, 0, 0, 0).unwrap();
+    assert_eq!(
+        datastore.get_audit_entries(Some(ancient), Some(distant)).unwrap().len(),
+        1
+    );
+    assert!(datastore.get_audit_entries(Some(distant), None).unwrap().is_empty());
+    assert!(datastore.get_audit_entries(None, Some(ancient)).unwrap().is_empty());
+}
+
+#[test]
+fn should_prune_expired_edges() {
+    let expiring_t = Type::new("expiring").unwrap();
+    let kept_t = Type::new("kept").unwrap();
+    let datastore = SledConfig::default()
+        .with_edge_retention(expiring_t.clone(), ChronoDuration::days(1))
+        .open(tempdir().unwrap().into_path())
+        .unwrap();
+    let trans = datastore.transaction().unwrap();
+
+    for id in 1..=3 {
+        trans
+            .create_vertex(&Vertex::with_id(Uuid::from_u128(id), kept_t.clone()))
+            .unwrap();
+    }
+
+    let edge = |t: &Type, inbound_id| EdgeKey::new(Uuid::from_u128(1), t.clone(), Uuid::from_u128(inbound_id));
+    let (expired, fresh, refreshed) = (edge(&expiring_t, 2), edge(&expiring_t, 3), edge(&expiring_t, 1));
+    let kept = edge(&kept_t, 2);
+    let two_days_ago = Utc::now() - ChronoDuration::days(2);
+
+    for key in &[&expired, &refreshed, &kept] {
+        assert!(trans.create_edge_at(key, two_days_ago).unwrap());
+    }
+
+    assert!(trans.create_edge(&fresh).unwrap());
+    assert!(trans.create_edge(&refreshed).unwrap());
+    let weight_q = EdgePropertyQuery::new(SpecificEdgeQuery::single(expired.clone()).into(), "weight".to_string());
+    trans.set_edge_properties(weight_q.clone(), &json!(1)).unwrap();
+
+    assert_eq!(datastore.prune_expired_edges().unwrap(), 1);
+    assert_eq!(datastore.prune_expired_edges().unwrap(), 0);
+    assert!(trans
+        .get_edges(SpecificEdgeQuery::single(expired.clone()))
+        .unwrap()
+        .is_empty());
+    assert!(trans.get_edge_properties(weight_q).unwrap().is_empty());
+    assert!(trans
+        .get_edges_by_type(&expiring_t, None, 10)
+        .unwrap()
+        .iter()
+        .all(|edge| edge.key != expired));
+
+    for key in [fresh, refreshed, kept] {
+        assert_eq!(trans.get_edges(SpecificEdgeQuery::single(key)).unwrap().len(), 1);
+    }
+}
+
+#[test]
+fn should_cascade_edge_properties_through_the_vertex_index() {
+    let datastore = datastore(IteratorStability::Live);
+    let t = Type::new("test_edge_type").unwrap();
+    let trans = datastore.transaction().unwrap();
+    let ids: Vec<Uuid> = (1..=4).map(Uuid::from_u128).collect();
+    for &id in &ids {
+        trans.create_vertex(&Vertex::with_id(id, t.clone())).unwrap();
+    }
+
+    let set_weight = |key: EdgeKey| {
+        trans.create_edge(&key).unwrap();
+        let q = EdgePropertyQuery::new(SpecificEdgeQuery::single(key).into(), "weight".to_string());
+        trans.set_edge_properties(q, &json!(1)).unwrap();
+    };
+    set_weight(EdgeKey::new(ids[0], t.clone(), ids[1]));
+    set_weight(EdgeKey::new(ids[2], t.clone(), ids[0]));
+    set_weight(EdgeKey::new(ids[0], t.clone(), ids[0]));
+
+    let holder = &datastore.holder;
+    assert_eq!(holder.vertex_edge_properties.writer().len(), 5);
+    datastore.reindex(Index::VertexEdgeProperties).unwrap();
+    assert_eq!(holder.vertex_edge_properties.writer().len(), 5);
+
+    // Inbound edges written while indexing is deferred can't be found
+    // through the reversed edge ranges, but their properties are indexed.
+    datastore.begin_deferred_indexing().unwrap();
+    set_weight(EdgeKey::new(ids[3], t.clone(), ids[0]));
+    trans.delete_vertices(SpecificVertexQuery::single(ids[0])).unwrap();
+    datastore.finish_deferred_indexing().unwrap();
+
+    assert!(holder.edges.is_empty());
+    assert!(holder.edge_properties.is_empty());
+    assert!(holder.reversed_edge_properties.writer().is_empty());
+    assert!(holder.vertex_edge_properties.writer().is_empty());
+    assert_eq!(trans.get_vertex_count().unwrap(), 3);
+}
+
+#[test]
+fn should_delete_edges_with_their_index_entries_and_properties() {
+    let datastore = datastore(IteratorStability::Live);
+    datastore.index_edge_property("weight").unwrap();
+    let t = Type::new("test_edge_type").unwrap();
+    let trans = datastore.transaction().unwrap();
+    let outbound_id = Uuid::from_u128(1);
+    let inbound_id = Uuid::from_u128(2);
+    trans.create_vertex(&Vertex::with_id(outbound_id, t.clone())).unwrap();
+    trans.create_vertex(&Vertex::with_id(inbound_id, t.clone())).unwrap();
+
+    let key = EdgeKey::new(outbound_id, t.clone(), inbound_id);
+    trans.create_edge(&key).unwrap();
+    let q = |name: &str| EdgePropertyQuery::new(SpecificEdgeQuery::single(key.clone()).into(), name.to_string());
+    trans.set_edge_properties(q("weight"), &json!(1)).unwrap();
+    trans.set_edge_properties(q("label"), &json!("a")).unwrap();
+
+    let holder = &datastore.holder;
+    assert_eq!(holder.edge_properties.len(), 2);
+    assert_eq!(holder.reversed_edge_properties.writer().len(), 2);
+    assert_eq!(holder.edge_property_values.writer().len(), 1);
+
+    trans.delete_edges(SpecificEdgeQuery::single(key)).unwrap();
+
+    assert!(holder.edges.is_empty());
+    assert!(holder.edge_ranges.writer().is_empty());
+    assert!(holder.reversed_edge_ranges.writer().is_empty());
+    assert!(holder.edges_by_type.writer().is_empty());
+    assert!(holder.edge_properties.is_empty());
+    assert!(holder.reversed_edge_properties.writer().is_empty());
+    assert!(holder.edge_property_values.writer().is_empty());
+}
+
+#[test]
+fn should_store_duplicate_property_values_once() {
+    let path = tempdir().unwrap().into_path();
+    let datastore = SledConfig::default().with_value_dedup(16).open(path).unwrap();
+    let t = Type::new("test_vertex_type").unwrap();
+    let trans = datastore.transaction().unwrap();
+    let schema = json!({"fields": ["name", "email", "created_at"]});
+    let q = |id: u128| {
+        VertexPropertyQuery::new(
+            SpecificVertexQuery::single(Uuid::from_u128(id)).into(),
+            "schema".to_string(),
+        )
+    };
+
+    for id in 1..4 {
+        trans
+            .create_vertex(&Vertex::with_id(Uuid::from_u128(id), t.clone()))
+            .unwrap();
+        trans.set_vertex_properties(q(id), &schema).unwrap();
+    }
+
+    // Short values are stored inline.
+    trans.set_vertex_properties(q(4), &json!("short")).unwrap();
+
+    let holder = &datastore.holder;
+    assert_eq!(holder.values.store.len(), 1);
+    assert_eq!(trans.get_vertex_properties(q(2)).unwrap()[0].value, schema);
+
+    // Patched values are stored inline, so the old one is released.
+    assert!(trans
+        .patch_vertex_properties(Uuid::from_u128(1), &json!({"schema": {"version": 2}}))
+        .unwrap());
+    assert_eq!(
+        trans.get_vertex_properties(q(1)).unwrap()[0].value,
+        json!({"fields": ["name", "email", "created_at"], "version": 2})
+    );
+
+    trans.delete_vertex_properties(q(2)).unwrap();
+    assert_eq!(holder.values.store.len(), 1);
+    trans.set_vertex_properties(q(3), &json!({"fields": []})).unwrap();
+    assert!(holder.values.store.is_empty());
+}
+
+#[test]
+fn should_keep_cold_property_values_in_the_cold_database() {
+    let path = tempdir().unwrap().into_path();
+    let datastore = SledConfig::default()
+        .with_cold_property("payload.*")
+        .open(&path)
+        .unwrap();
+    let id = Uuid::from_u128(1);
+    let q = |name: &str| VertexPropertyQuery::new(SpecificVertexQuery::single(id).into(), name.to_string());
+    let trans = datastore.transaction().unwrap();
+    trans
+        .create_vertex(&Vertex::with_id(id, Type::new("test_vertex_type").unwrap()))
+        .unwrap();
+    trans.set_vertex_properties(q("payload.body"), &json!("abc")).unwrap();
+    trans.set_vertex_properties(q("payload"), &json!("def")).unwrap();
+
+    let holder = &datastore.holder;
+    assert_eq!(holder.values.store.len(), 1);
+    assert!(holder
+        .cold_db
+        .as_ref()
+        .unwrap()
+        .tree_names()
+        .contains(&"value_store".into()));
+    assert_eq!(
+        trans.get_vertex_properties(q("payload.body")).unwrap()[0].value,
+        json!("abc")
+    );
+    drop(trans);
+    drop(datastore);
+
+    // Once cold properties are turned off, the values move back.
+    let datastore = SledConfig::default().open(&path).unwrap();
+    let trans = datastore.transaction().unwrap();
+    assert_eq!(datastore.holder.values.store.len(), 1);
+    assert!(datastore
+        .holder
+        .cold_db
+        .as_ref()
+        .unwrap()
+        .open_tree("value_store")
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        trans.get_vertex_properties(q("payload.body")).unwrap()[0].value,
+        json!("abc")
+    );
+}
+
+#[test]
+fn should_patch_cold_property_values() {
+    let datastore = SledConfig::default()
+        .with_cold_property("payload")
+        .open(tempdir().unwrap().into_path())
+        .unwrap();
+    let id = Uuid::from_u128(1);
+    let q = VertexPropertyQuery::new(SpecificVertexQuery::single(id).into(), "payload".to_string());
+    let trans = datastore.transaction().unwrap();
+    trans
+        .create_vertex(&Vertex::with_id(id, Type::new("test_vertex_type").unwrap()))
+        .unwrap();
+    trans
+        .set_vertex_properties(q.clone(), &json!({"a": 1, "b": 2}))
+        .unwrap();
+
+    // The stored value refers to the cold database, which used to deadlock
+    // when read from within the patch's transaction.
+    assert!(trans
+        .patch_vertex_properties(id, &json!({"payload": {"b": null, "c": 3}}))
+        .unwrap());
+    assert_eq!(
+        trans.get_vertex_properties(q).unwrap()[0].value,
+        json!({"a": 1, "c": 3})
+    );
+}
+
+#[test]
+fn should_stage_writes_until_commit() {
+    let datastore = datastore(IteratorStability::Live);
+    let t = Type::new("test_edge_type").unwrap();
+    let trans = datastore.transaction().unwrap().with_write_buffer();
+    let reader = datastore.transaction().unwrap();
+    let (outbound_id, inbound_id) = (Uuid::from_u128(1), Uuid::from_u128(2));
+    let key = EdgeKey::new(outbound_id, t.clone(), inbound_id);
+    let q = VertexPropertyQuery::new(SpecificVertexQuery::single(outbound_id).into(), "name".to_string());
+
+    assert!(trans.create_vertex(&Vertex::with_id(outbound_id, t.clone())).unwrap());
+    assert!(!trans.create_vertex(&Vertex::with_id(outbound_id, t.clone())).unwrap());
+    assert!(!trans.create_edge(&key).unwrap());
+    assert!(trans.create_vertex(&Vertex::with_id(inbound_id, t.clone())).unwrap());
+    assert!(trans.create_edge(&key).unwrap());
+    trans.set_vertex_properties(q.clone(), &json!("a")).unwrap();
+    trans
+        .set_edge_properties(
+            EdgePropertyQuery::new(SpecificEdgeQuery::single(key.clone()).into(), "weight".to_string()),
+            &json!(1),
+        )
+        .unwrap();
+
+    match trans.delete_vertices_with_policy(SpecificVertexQuery::single(outbound_id), CascadePolicy::Detach) {
+        Err(IndraError::Datastore { inner }) => match inner.downcast_ref::<Error>() {
+            Some(Error::UnbufferedWrite { operation }) if operation == "delete_vertices_with_policy" => {}
+            _ => panic!("unexpected error: {}", inner),
+        },
+        result => panic!("unexpected result: {:?}", result),
+    }
+
+    assert_eq!(reader.get_vertex_count().unwrap(), 0);
+    trans.commit().unwrap();
+    assert_eq!(reader.get_vertex_count().unwrap(), 2);
+    assert_eq!(
+        reader.get_edges(SpecificEdgeQuery::single(key.clone())).unwrap().len(),
+        1
+    );
+    assert_eq!(reader.get_vertex_properties(q.clone()).unwrap()[0].value, json!("a"));
+    assert_eq!(datastore.holder.edge_properties.len(), 1);
+
+    trans.set_vertex_properties(q.clone(), &json!("b")).unwrap();
+    trans.rollback();
+    trans.commit().unwrap();
+    assert_eq!(reader.get_vertex_properties(q).unwrap()[0].value, json!("a"));
+}
+
+/// Wakes the thread that's blocked on a future.
+struct ThreadWaker(thread::Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = Box::pin(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+#[test]
+fn should_stream_query_results() {
+    let datastore = datastore(IteratorStability::Live);
+    let trans = datastore.transaction().unwrap();
+    let t = Type::new("test_edge_type").unwrap();
+    let source_id = Uuid::from_u128(1);
+    trans.create_vertex(&Vertex::with_id(source_id, t.clone())).unwrap();
+
+    for i in 2..52 {
+        let id = Uuid::from_u128(i);
+        trans.create_vertex(&Vertex::with_id(id, t.clone())).unwrap();
+        trans.create_edge(&EdgeKey::new(source_id, t.clone(), id)).unwrap();
+    }
+
+    let q = PipeEdgeQuery {
+        inner: Box::new(RangeVertexQuery::new().limit(10).into()),
+        direction: EdgeDirection::Outbound,
+        limit: 40,
+        t: None,
+        high: None,
+        low: None,
+    };
+
+    let streamed = trans
+        .stream_edges(q.clone(), |edges| edges.collect::<Result<Vec<_>>>())
+        .unwrap();
+    assert_eq!(streamed, trans.get_edges(q.clone()).unwrap());
+    assert_eq!(streamed.len(), 40);
+
+    let first = trans
+        .stream_edges(q, |edges| edges.take(3).collect::<Result<Vec<_>>>())
+        .unwrap();
+    assert_eq!(first, streamed[..3].to_vec());
+
+    let count = trans
+        .stream_vertices(RangeVertexQuery::new().limit(100), |vertices| {
+            let mut count = 0;
+
+            for vertex in vertices {
+                vertex?;
+                count += 1;
+            }
+
+            Ok(count)
+        })
+        .unwrap();
+    assert_eq!(count, 51);
+}
+
+#[test]
+fn should_flush_explicitly() {
+    let datastore = datastore(IteratorStability::Live);
+    let trans = datastore.transaction().unwrap();
+    let t = Type::new("test_vertex_type").unwrap();
+    assert!(datastore.last_flush().is_none());
+
+    trans
+        .create_vertex(&Vertex::with_id(Uuid::from_u128(1), t.clone()))
+        .unwrap();
+    datastore.flush().unwrap();
+    let flushed = datastore.last_flush();
+    assert!(flushed.is_some());
+
+    trans.create_vertex(&Vertex::with_id(Uuid::from_u128(2), t)).unwrap();
+    block_on(datastore.flush_async()).unwrap();
+    assert!(datastore.last_flush() > flushed);
+}
+
+#[test]
+fn should_pass_tuning_through_to_sled() {
+    let path = tempdir().unwrap().into_path();
+    assert_eq!(SledConfig::default().sled_config(&path).flush_every_ms, Some(500));
+    let config = SledConfig::default().with_sled_flush_every_ms(Some(2000));
+    assert_eq!(config.sled_config(&path).flush_every_ms, Some(2000));
+    let config = SledConfig::default().with_sled_flush_every_ms(None);
+    assert_eq!(config.sled_config(&path).flush_every_ms, None);
+    let config = config.with_cache_capacity(64 * 1024 * 1024);
+    assert_eq!(config.sled_config(&path).cache_capacity, 64 * 1024 * 1024);
+    let config = config
+        .with_storage_mode(StorageMode::HighThroughput)
+        .with_segment_size(1024 * 1024);
+    assert!(matches!(config.sled_config(&path).mode, sled::Mode::HighThroughput));
+    assert_eq!(config.sled_config(&path).segment_size, 1024 * 1024);
+
+    let unflushed = config.open(path).unwrap();
+    let trans = unflushed.transaction().unwrap();
+    let t = Type::new("test_vertex_type").unwrap();
+    assert!(trans.create_vertex(&Vertex::with_id(Uuid::from_u128(1), t)).unwrap());
+    unflushed.flush().unwrap();
+    assert_eq!(trans.get_vertex_count().unwrap(), 1);
+}
+
+#[test]
+fn should_remove_temporary_datastores_when_dropped() {
+    let t = Type::new("test_vertex_type").unwrap();
+    let vertex = Vertex::with_id(Uuid::from_u128(1), t);
+    let datastore = SledDatastore::temporary().unwrap();
+    let directory = datastore.holder.directory.clone().unwrap();
+    assert!(datastore.transaction().unwrap().create_vertex(&vertex).unwrap());
+    assert!(directory.exists());
+
+    // Each temporary datastore starts out empty.
+    let compressed = SledConfig::with_compression(None).open_temporary().unwrap();
+    assert_ne!(compressed.holder.directory.clone().unwrap(), directory);
+    assert!(compressed.transaction().unwrap().create_vertex(&vertex).unwrap());
+
+    drop(datastore);
+    assert!(!directory.exists());
+}
+
+#[test]
+fn should_flush_per_durability() {
+    let t = Type::new("test_vertex_type").unwrap();
+    let flushing = SledConfig::default()
+        .with_durability(Durability::FlushEachCommit)
+        .open(tempdir().unwrap().into_path())
+        .unwrap();
+    let trans = flushing.transaction().unwrap();
+    assert!(trans
+        .create_vertex(&Vertex::with_id(Uuid::from_u128(1), t.clone()))
+        .unwrap());
+    assert!(flushing.last_flush().is_some());
+
+    let datastore = datastore(IteratorStability::Live);
+    let trans = datastore.transaction().unwrap();
+    assert!(trans
+        .create_vertex(&Vertex::with_id(Uuid::from_u128(1), t.clone()))
+        .unwrap());
+    assert!(datastore.last_flush().is_none());
+
+    let trans = trans.with_durability(Durability::FsyncEachCommit);
+    assert!(trans
+        .create_vertex(&Vertex::with_id(Uuid::from_u128(2), t.clone()))
+        .unwrap());
+    assert!(datastore.last_flush().is_some());
+
+    // Under a write buffer, it's the commit that's flushed.
+    let trans = datastore
+        .transaction()
+        .unwrap()
+        .with_write_buffer()
+        .with_durability(Durability::FlushEachCommit);
+    let before = datastore.last_flush();
+    assert!(trans.create_vertex(&Vertex::with_id(Uuid::from_u128(3), t)).unwrap());
+    assert_eq!(datastore.last_flush(), before);
+    trans.commit().unwrap();
+    assert!(datastore.last_flush() > before);
+}
+
+#[test]
+fn should_read_staged_writes_within_the_transaction() {
+    let datastore = datastore(IteratorStability::Live);
+    let t = Type::new("test_edge_type").unwrap();
+    let setup = datastore.transaction().unwrap();
+    let (first_id, second_id, third_id) = (Uuid::from_u128(1), Uuid::from_u128(2), Uuid::from_u128(3));
+    let first_key = EdgeKey::new(first_id, t.clone(), second_id);
+    let second_key = EdgeKey::new(first_id, t.clone(), third_id);
+    let name_q = |id| VertexPropertyQuery::new(SpecificVertexQuery::single(id).into(), "name".to_string());
+    let outbound_q = PipeEdgeQuery {
+        inner: Box::new(SpecificVertexQuery::single(first_id).into()),
+        direction: EdgeDirection::Outbound,
+        limit: 10,
+        t: None,
+        high: None,
+        low: None,
+    };
+
+    assert!(setup.create_vertex(&Vertex::with_id(first_id, t.clone())).unwrap());
+    assert!(setup.create_vertex(&Vertex::with_id(second_id, t.clone())).unwrap());
+    assert!(setup.create_edge(&first_key).unwrap());
+    setup.set_vertex_properties(name_q(first_id), &json!("a")).unwrap();
+
+    let trans = datastore.transaction().unwrap().with_write_buffer();
+    assert!(trans.create_vertex(&Vertex::with_id(third_id, t.clone())).unwrap());
+    assert!(trans.create_edge(&second_key).unwrap());
+    trans.set_vertex_properties(name_q(third_id), &json!("c")).unwrap();
+    trans.delete_vertex_properties(name_q(first_id)).unwrap();
+
+    assert_eq!(trans.get_vertex_count().unwrap(), 3);
+    assert_eq!(trans.get_vertices(RangeVertexQuery::new().limit(10)).unwrap().len(), 3);
+    assert_eq!(
+        trans.get_edge_count(first_id, None, EdgeDirection::Outbound).unwrap(),
+        2
+    );
+    assert_eq!(trans.get_edges(outbound_q.clone()).unwrap()[0].key, second_key);
+    assert!(trans.get_vertex_properties(name_q(first_id)).unwrap().is_empty());
+    assert_eq!(
+        trans.get_vertex_properties(name_q(third_id)).unwrap()[0].value,
+        json!("c")
+    );
+    assert_eq!(setup.get_vertex_count().unwrap(), 2);
+
+    // Deleting a vertex hides its edges, as the cascade would delete them.
+    trans.delete_vertices(SpecificVertexQuery::single(second_id)).unwrap();
+    assert_eq!(trans.get_vertex_count().unwrap(), 2);
+    assert_eq!(
+        trans.get_edge_count(first_id, None, EdgeDirection::Outbound).unwrap(),
+        1
+    );
+    assert!(trans
+        .get_edges(SpecificEdgeQuery::single(first_key.clone()))
+        .unwrap()
+        .is_empty());
+    assert!(!trans.create_edge(&first_key).unwrap());
+
+    trans
+        .delete_edges(SpecificEdgeQuery::single(second_key.clone()))
+        .unwrap();
+    assert!(trans.get_edges(outbound_q.clone()).unwrap().is_empty());
+    assert_eq!(
+        setup.get_edge_count(first_id, None, EdgeDirection::Outbound).unwrap(),
+        1
+    );
+
+    trans.commit().unwrap();
+    let vertices = setup.get_vertices(RangeVertexQuery::new().limit(10)).unwrap();
+    assert_eq!(
+        vertices.iter().map(|vertex| vertex.id).collect::<Vec<_>>(),
+        vec![first_id, third_id]
+    );
+    assert!(setup.get_edges(outbound_q).unwrap().is_empty());
+    assert!(setup.get_vertex_properties(name_q(first_id)).unwrap().is_empty());
+    assert_eq!(
+        setup.get_vertex_properties(name_q(third_id)).unwrap()[0].value,
+        json!("c")
+    );
+}
+
+#[test]
+fn should_retry_conflicting_executes() {
+    const THREADS: u64 = 4;
+    const INCREMENTS: u64 = 25;
+
+    let datastore = datastore(IteratorStability::Live);
+    let id = Uuid::from_u128(1);
+    let q = VertexPropertyQuery::new(SpecificVertexQuery::single(id).into(), "count".to_string());
+    let trans = datastore.transaction().unwrap();
+    trans
+        .create_vertex(&Vertex::with_id(id, Type::new("counter").unwrap()))
+        .unwrap();
+    trans.set_vertex_properties(q.clone(), &json!(0)).unwrap();
+
+    let threads: Vec<_> = (0..THREADS)
+        .map(|_| {
+            let datastore = datastore.clone();
+            let q = q.clone();
+
+            thread::spawn(move || {
+                for _ in 0..INCREMENTS {
+                    datastore
+                        .execute(|trans| {
+                            let count = trans.get_vertex_properties(q.clone())?[0].value.as_u64().unwrap();
+                            trans.set_vertex_properties(q.clone(), &json!(count + 1))
+                        })
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    let count = trans.get_vertex_properties(q).unwrap()[0].value.clone();
+    assert_eq!(count, json!(THREADS * INCREMENTS));
+}
+
+#[test]
+fn should_only_retry_executes_on_writes_to_what_they_read() {
+    let datastore = datastore(IteratorStability::Live);
+    let t = Type::new("counter").unwrap();
+    let (read_id, other_id) = (Uuid::from_u128(1), Uuid::from_u128(2));
+    let q = |id| VertexPropertyQuery::new(SpecificVertexQuery::single(id).into(), "count".to_string());
+    let trans = datastore.transaction().unwrap();
+
+    for &id in &[read_id, other_id] {
+        trans.create_vertex(&Vertex::with_id(id, t.clone())).unwrap();
+        trans.set_vertex_properties(q(id), &json!(0)).unwrap();
+    }
+
+    // Runs an execute that increments the count of `read_id`, with a write
+    // to `written_id` landing midway through its first attempt.
+    let increment = |written_id| {
+        let attempts = Cell::new(0);
+
+        datastore
+            .execute(|execute_trans| {
+                attempts.set(attempts.get() + 1);
+                let count = execute_trans.get_vertex_properties(q(read_id))?[0]
+                    .value
+                    .as_u64()
+                    .unwrap();
+
+                if attempts.get() == 1 {
+                    trans.set_vertex_properties(q(written_id), &json!(100)).unwrap();
+                }
+
+                execute_trans.set_vertex_properties(q(read_id), &json!(count + 1))
+            })
+            .unwrap();
+
+        attempts.get()
+    };
+
+    assert_eq!(increment(other_id), 1);
+    assert_eq!(increment(read_id), 2);
+    assert_eq!(trans.get_vertex_properties(q(read_id)).unwrap()[0].value, json!(101));
+
+    // Reads that aren't scoped to specific vertices conflict with any write.
+    let attempts = Cell::new(0);
+    datastore
+        .execute(|execute_trans| {
+            attempts.set(attempts.get() + 1);
+            execute_trans.get_vertex_count()?;
+
+            if attempts.get() == 1 {
+                trans.set_vertex_properties(q(other_id), &json!(0)).unwrap();
+            }
+
+            execute_trans.set_vertex_properties(q(read_id), &json!(0))
+        })
+        .unwrap();
+    assert_eq!(attempts.get(), 2);
+}
+
+#[test]
+fn should_resume_imports_from_their_watermarks() {
+    let datastore = datastore(IteratorStability::Live);
+    let t = Type::new("test_vertex_type").unwrap();
+    let (outbound_id, inbound_id) = (Uuid::from_u128(1), Uuid::from_u128(2));
+    let key = EdgeKey::new(outbound_id, t.clone(), inbound_id);
+    let chunk = vec![
+        BulkInsertItem::Vertex(Vertex::with_id(outbound_id, t.clone())),
+        BulkInsertItem::Vertex(Vertex::with_id(inbound_id, t.clone())),
+        BulkInsertItem::Edge(key.clone()),
+        BulkInsertItem::EdgeProperty(key.clone(), "weight".to_string(), json!(1)),
+    ];
+
+    assert_eq!(datastore.import_watermark("log").unwrap(), None);
+    assert!(datastore.import("log", 10, chunk.clone()).unwrap());
+    assert_eq!(datastore.import_watermark("log").unwrap(), Some(10));
+
+    // Chunks at or below the watermark were already imported.
+    assert!(!datastore.import("log", 10, chunk.clone()).unwrap());
+    assert!(!datastore.import("log", 5, chunk.clone()).unwrap());
+
+    // The watermark advances even if the chunk's items are all no-ops, and
+    // sources are tracked separately.
+    assert!(datastore.import("log", 20, chunk[..2].to_vec()).unwrap());
+    assert_eq!(datastore.import_watermark("log").unwrap(), Some(20));
+    assert_eq!(datastore.import_watermark("other").unwrap(), None);
+
+    let trans = datastore.transaction().unwrap();
+    assert_eq!(trans.get_vertex_count().unwrap(), 2);
+    let q = EdgePropertyQuery::new(SpecificEdgeQuery::single(key).into(), "weight".to_string());
+    assert_eq!(trans.get_edge_properties(q).unwrap()[0].value, json!(1));
+}
+
+#[test]
+fn should_apply_read_options() {
+    let path = tempdir().unwrap().into_path();
+    let datastore = SledConfig::default()
+        .with_decode_error_policy(DecodeErrorPolicy::SkipAndCount)
+        .with_result_cache(100)
+        .open(path)
+        .unwrap();
+    let t = Type::new("test_vertex_type").unwrap();
+    let trans = datastore.transaction().unwrap();
+
+    for i in 1..4 {
+        trans
+            .create_vertex(&Vertex::with_id(Uuid::from_u128(i), t.clone()))
+            .unwrap();
+    }
+
+    let vertex_key = Uuid::from_u128(2).as_bytes().to_vec();
+    datastore.holder.vertices.insert(&vertex_key, &[200, b'a']).unwrap();
+
+    let strict_trans = datastore.transaction().unwrap().with_read_options(ReadOptions {
+        decode_error_policy: Some(DecodeErrorPolicy::FailFast),
+        use_cache: false,
+        snapshot: true,
+    });
+    let err = strict_trans
+        .get_vertices(RangeVertexQuery::new().limit(10))
+        .unwrap_err();
+    assert_corruption(err, &datastore.holder.vertices, &vertex_key);
+    assert_eq!(datastore.memory_usage().result_cache_entries, 0);
+
+    // Other transactions still read with the datastore's policy, and cache
+    // their results.
+    assert_eq!(trans.get_vertices(RangeVertexQuery::new().limit(10)).unwrap().len(), 2);
+    assert_eq!(datastore.memory_usage().result_cache_entries, 1);
+    assert_eq!(datastore.skipped_records().count, 1);
+
+    // Snapshot reads only hold writes off while they run.
+    assert!(trans.create_vertex(&Vertex::with_id(Uuid::from_u128(4), t)).unwrap());
+
+    let lenient_trans = strict_trans.with_read_options(ReadOptions {
+        decode_error_policy: None,
+        ..ReadOptions::default()
+    });
+    assert_eq!(
+        lenient_trans
+            .get_vertices(RangeVertexQuery::new().limit(10))
+            .unwrap()
+            .len(),
+        3
+    );
+}
+
+#[test]
+fn should_compact_non_canonical_property_values() {
+    let datastore = datastore(IteratorStability::Live);
+    let id = Uuid::from_u128(1);
+    let trans = datastore.transaction().unwrap();
+    trans
+        .create_vertex(&Vertex::with_id(id, Type::new("test_vertex_type").unwrap()))
+        .unwrap();
+
+    for name in &["a", "b"] {
+        let q = VertexPropertyQuery::new(SpecificVertexQuery::single(id).into(), name.to_string());
+        trans.set_vertex_properties(q, &JsonValue::from(vec![1, 2])).unwrap();
+    }
+
+    // Written the way another tool might have.
+    let vertex_properties = &datastore.holder.vertex_properties;
+    let (key, _) = vertex_properties.iter().next().unwrap().unwrap();
+    vertex_properties.insert(&key, &b"[ 1, 2 ]"[..]).unwrap();
+
+    assert_eq!(datastore.compact_property_values().unwrap(), 1);
+    assert_eq!(vertex_properties.get(&key).unwrap().unwrap(), &b"[1,2]"[..]);
+    assert_eq!(datastore.compact_property_values().unwrap(), 0);
+}
+
+#[test]
+fn should_store_datetimes_in_untimed_edge_range_values() {
+    let path = tempdir().unwrap().into_path();
+    let datastore = SledConfig::default().with_untimed_edge_ranges().open(path).unwrap();
+    let t = Type::new("test_edge_type").unwrap();
+    let key = EdgeKey::new(Uuid::from_u128(1), t.clone(), Uuid::from_u128(2));
+    let trans = datastore.transaction().unwrap();
+    trans
+        .create_vertex(&Vertex::with_id(key.outbound_id, t.clone()))
+        .unwrap();
+    trans.create_vertex(&Vertex::with_id(key.inbound_id, t)).unwrap();
+    trans.create_edge(&key).unwrap();
+    trans.create_edge(&key).unwrap();
+
+    let update_datetime = trans.get_edges(SpecificEdgeQuery::single(key.clone())).unwrap()[0].created_datetime;
+    let edge_ranges = datastore.holder.edge_ranges.writer();
+    let (range_key, value) = edge_ranges.iter().next().unwrap().unwrap();
+    assert_eq!(value.len(), 8);
+    let count = |low| trans.count_edges(key.outbound_id, None, EdgeDirection::Outbound, Some(low), None);
+    assert_eq!(count(update_datetime).unwrap(), 1);
+
+    // Written the way version 4 of the format would have.
+    edge_ranges.insert(&range_key, &[]).unwrap();
+    datastore
+        .holder
+        .metadata
+        .insert("format_version", &4u64.to_be_bytes())
+        .unwrap();
+    datastore.holder.edge_range_layout.set_datetime_values(false);
+    assert_eq!(count(update_datetime).unwrap(), 1);
+
+    assert_eq!(datastore.migrate_format().unwrap(), FORMAT_VERSION);
+    assert_eq!(edge_ranges.get(&range_key).unwrap().unwrap(), value);
+    assert_eq!(count(update_datetime).unwrap(), 1);
+}
+
+fn assert_incompatible_config(result: Result<SledDatastore>) {
+    match result {
+        Err(IndraError::Datastore { inner }) => match inner.downcast_ref::<Error>() {
+            Some(Error::IncompatibleConfig { .. }) => (),
+            _ => panic!("unexpected error: {}", inner),
+        },
+        _ => panic!("expected the config to be rejected"),
+    }
+}
+
+#[test]
+fn should_migrate_older_formats_when_opening() {
+    let path = tempdir().unwrap().into_path();
+    let t = Type::new("test_edge_type").unwrap();
+    let key = EdgeKey::new(Uuid::from_u128(1), t.clone(), Uuid::from_u128(2));
+
+    {
+        let datastore = SledConfig::default().open(&path).unwrap();
+        let trans = datastore.transaction().unwrap();
+        trans
+            .create_vertex(&Vertex::with_id(key.outbound_id, t.clone()))
+            .unwrap();
+        trans.create_vertex(&Vertex::with_id(key.inbound_id, t)).unwrap();
+        trans.create_edge(&key).unwrap();
+
+        // Unversioned datastores have no layout metadata.
+        let metadata = &datastore.holder.metadata;
+        for name in &[
+            "format_version",
+            "untimed_edge_ranges",
+            "datetime_precision",
+            "undirected_edge_types",
+        ] {
+            metadata.remove(name).unwrap();
+        }
+        datastore.sync().unwrap();
+    }
+
+    // Their layout can't have differed from the defaults.
+    assert_incompatible_config(SledConfig::default().with_untimed_edge_ranges().open(&path));
+    assert_incompatible_config(
+        SledConfig::default()
+            .with_datetime_precision(DatetimePrecision::Millis)
+            .open(&path),
+    );
+    assert_incompatible_config(
+        SledConfig::default()
+            .with_undirected_edge_type(key.t.clone())
+            .open(&path),
+    );
+
+    let datastore = SledConfig::default().open(&path).unwrap();
+    assert_eq!(datastore.format_version().unwrap(), FORMAT_VERSION);
+    assert!(datastore.recovery_info().salvage_actions.is_empty());
+    let trans = datastore.transaction().unwrap();
+    assert_eq!(
+        trans.get_edges(SpecificEdgeQuery::single(key.clone())).unwrap().len(),
+        1
+    );
+    assert_eq!(
+        trans
+            .get_edge_count(key.inbound_id, None, EdgeDirection::Inbound)
+            .unwrap(),
+        1
+    );
+}
+
+#[test]
+fn should_treat_undirected_edges_as_one_edge() {
+    let t = Type::new("test_edge_type").unwrap();
+    let datastore = SledConfig::default()
+        .with_undirected_edge_type(t.clone())
+        .open(tempdir().unwrap().into_path())
+        .unwrap();
+    let trans = datastore.transaction().unwrap();
+    let (low_id, high_id) = (Uuid::from_u128(1), Uuid::from_u128(2));
+    trans.create_vertex(&Vertex::with_id(low_id, t.clone())).unwrap();
+    trans.create_vertex(&Vertex::with_id(high_id, t.clone())).unwrap();
+
+    let forward = EdgeKey::new(high_id, t.clone(), low_id);
+    let backward = EdgeKey::new(low_id, t.clone(), high_id);
+    let count = |id, direction| trans.get_edge_count(id, Some(&t), direction).unwrap();
+    let all_counts = || {
+        [low_id, high_id]
+            .iter()
+            .flat_map(|&id| vec![count(id, EdgeDirection::Outbound), count(id, EdgeDirection::Inbound)])
+            .collect::<Vec<_>>()
+    };
+
+    assert!(trans.create_edge(&forward).unwrap());
+    assert!(trans.create_edge(&backward).unwrap());
+    assert_eq!(all_counts(), vec![1, 1, 1, 1]);
+
+    for key in &[&forward, &backward] {
+        let edges = trans.get_edges(SpecificEdgeQuery::single((*key).clone())).unwrap();
+        assert_eq!(edges.len(), 1);
+    }
+
+    // Each vertex sees the edge from its own side.
+    let edges = trans
+        .get_edges(PipeEdgeQuery {
+            inner: Box::new(SpecificVertexQuery::single(high_id).into()),
+            direction: EdgeDirection::Outbound,
+            limit: 10,
+            t: Some(t.clone()),
+            high: None,
+            low: None,
+        })
+        .unwrap();
+    assert_eq!(edges.len(), 1);
+    assert_eq!(edges[0].key, forward);
+
+    let q = |key: &EdgeKey| EdgePropertyQuery::new(SpecificEdgeQuery::single(key.clone()).into(), "since".to_string());
+    trans.set_edge_properties(q(&forward), &json!(2020)).unwrap();
+    assert_eq!(trans.get_edge_properties(q(&backward)).unwrap()[0].value, json!(2020));
+
+    trans.delete_edges(SpecificEdgeQuery::single(forward.clone())).unwrap();
+    assert_eq!(all_counts(), vec![0, 0, 0, 0]);
+    assert!(trans.get_edge_properties(q(&backward)).unwrap().is_empty());
+
+    // Deleting either vertex takes the edge with it.
+    assert!(trans.create_edge(&forward).unwrap());
+    trans.delete_vertices(SpecificVertexQuery::single(high_id)).unwrap();
+    assert_eq!(count(low_id, EdgeDirection::Outbound), 0);
+    assert_eq!(count(low_id, EdgeDirection::Inbound), 0);
+}
+
+#[test]
+fn should_refuse_to_change_undirected_edge_types() {
+    let path = tempdir().unwrap().into_path();
+    let (friends_t, follows_t) = (Type::new("friends").unwrap(), Type::new("follows").unwrap());
+    let config = SledConfig::default()
+        .with_undirected_edge_type(friends_t.clone())
+        .with_undirected_edge_type(follows_t.clone());
+    drop(config.clone().open(&path).unwrap());
+
+    assert_incompatible_config(SledConfig::default().open(&path));
+    assert_incompatible_config(
+        SledConfig::default()
+            .with_undirected_edge_type(friends_t.clone())
+            .open(&path),
+    );
+
+    // The order the types are given in doesn't matter.
+    SledConfig::default()
+        .with_undirected_edge_type(follows_t)
+        .with_undirected_edge_type(friends_t)
+        .open(&path)
+        .unwrap();
+
+    let path = tempdir().unwrap().into_path();
+    drop(SledConfig::default().open(&path).unwrap());
+    assert_incompatible_config(config.open(&path));
+}
+
+#[test]
+fn should_move_property_between_vertices() {
+    let datastore = datastore(IteratorStability::Live);
+    let t = Type::new("test_vertex_type").unwrap();
+    let (from_id, to_id) = (Uuid::from_u128(1), Uuid::from_u128(2));
+    let trans = datastore.transaction().unwrap();
+    trans.create_vertex(&Vertex::with_id(from_id, t.clone())).unwrap();
+    trans.create_vertex(&Vertex::with_id(to_id, t)).unwrap();
+
+    let q = |id| VertexPropertyQuery::new(SpecificVertexQuery::single(id).into(), "name".to_string());
+    trans.set_vertex_properties(q(from_id), &JsonValue::from("a")).unwrap();
+    trans.set_vertex_properties(q(to_id), &JsonValue::from("b")).unwrap();
+
+    assert!(trans.move_property(from_id, to_id, "name").unwrap());
+    assert!(trans.get_vertex_properties(q(from_id)).unwrap().is_empty());
+    let properties = trans.get_vertex_properties(q(to_id)).unwrap();
+    assert_eq!(properties[0].value, JsonValue::from("a"));
+    assert_eq!(trans.list_property_names().unwrap()[0].vertex_count, 1);
+
+    assert!(!trans.move_property(from_id, to_id, "name").unwrap());
+}
+
+#[test]
+fn should_copy_filtered_graph_into_memory_datastore() {
+    let datastore = datastore(IteratorStability::Live);
+    let (t, other_t) = (
+        Type::new("test_vertex_type").unwrap(),
+        Type::new("other_vertex_type").unwrap(),
+    );
+    let trans = datastore.transaction().unwrap();
+    trans
+        .create_vertex(&Vertex::with_id(Uuid::from_u128(1), t.clone()))
+        .unwrap();
+    trans
+        .create_vertex(&Vertex::with_id(Uuid::from_u128(2), t.clone()))
+        .unwrap();
+    trans
+        .create_vertex(&Vertex::with_id(Uuid::from_u128(3), other_t))
+        .unwrap();
+    trans
+        .create_edge(&EdgeKey::new(Uuid::from_u128(1), t.clone(), Uuid::from_u128(2)))
+        .unwrap();
+    trans
+        .create_edge(&EdgeKey::new(Uuid::from_u128(1), t.clone(), Uuid::from_u128(3)))
+        .unwrap();
+
+    let q = VertexPropertyQuery::new(
+        SpecificVertexQuery::single(Uuid::from_u128(2)).into(),
+        "name".to_string(),
+    );
+    trans.set_vertex_properties(q.clone(), &JsonValue::from("a")).unwrap();
+
+    let memory = datastore.to_memory_datastore_filtered(|vertex| vertex.t == t).unwrap();
+    let memory_trans = memory.transaction().unwrap();
+    assert_eq!(memory_trans.get_vertex_count().unwrap(), 2);
+    assert_eq!(
+        memory_trans
+            .get_edge_count(Uuid::from_u128(1), None, EdgeDirection::Outbound)
+            .unwrap(),
+        1
+    );
+    assert_eq!(
+        memory_trans.get_vertex_properties(q).unwrap()[0].value,
+        JsonValue::from("a")
+    );
+
+    // The copy is independent of the datastore.
+    trans
+        .delete_vertices(SpecificVertexQuery::single(Uuid::from_u128(1)))
+        .unwrap();
+    assert_eq!(memory_trans.get_vertex_count().unwrap(), 2);
+    assert_eq!(
+        datastore
+            .to_memory_datastore()
+            .unwrap()
+            .transaction()
+            .unwrap()
+            .get_vertex_count()
+            .unwrap(),
+        2
+    );
+}
+
+#[test]
+fn should_follow_edges_across_shards() {
+    let t = Type::new("test_edge_type").unwrap();
+    let paths: Vec<_> = (0..4).map(|_| tempdir().unwrap().into_path()).collect();
+    let datastore = ShardedSledDatastore::open(&SledConfig::default(), &paths).unwrap();
+    let trans = datastore.transaction().unwrap();
+
+    let ids: Vec<Uuid> = (0..16).map(Uuid::from_u128).collect();
+    for &id in &ids {
+        trans.create_vertex(&Vertex::with_id(id, t.clone())).unwrap();
+    }
+
+    let target_id = ids[0];
+    for &id in &ids[1..] {
+        assert!(trans.create_edge(&EdgeKey::new(id, t.clone(), target_id)).unwrap());
+    }
+
+    let count = trans.get_edge_count(target_id, None, EdgeDirection::Inbound).unwrap();
+    assert_eq!(count, 15);
+
+    trans.delete_vertices(SpecificVertexQuery::single(target_id)).unwrap();
+
+    for &id in &ids[1..] {
+        let count = trans.get_edge_count(id, None, EdgeDirection::Outbound).unwrap();
+        assert_eq!(count, 0);
+    }
+}
+
+#[test]
+fn should_share_datastore_between_writer_and_readers() {
+    let t = Type::new("test_vertex_type").unwrap();
+    let dir = tempdir().unwrap();
+    let writer = SharedDatastore::open_writer(SledConfig::default(), dir.path()).unwrap();
+    let trans = writer.transaction().unwrap();
+    trans
+        .create_vertex(&Vertex::with_id(Uuid::from_u128(1), t.clone()))
+        .unwrap();
+    writer.publish().unwrap();
+
+    let reader = SharedDatastore::open_reader(SledConfig::default(), dir.path()).unwrap();
+    assert_eq!(reader.transaction().unwrap().get_vertex_count().unwrap(), 1);
+    let result = reader
+        .transaction()
+        .unwrap()
+        .create_vertex(&Vertex::with_id(Uuid::from_u128(2), t.clone()));
+    assert!(result.is_err());
+
+    trans.create_vertex(&Vertex::with_id(Uuid::from_u128(2), t)).unwrap();
+    assert!(!reader.refresh().unwrap());
+    writer.publish().unwrap();
+    assert!(reader.refresh().unwrap());
+    assert_eq!(reader.transaction().unwrap().get_vertex_count().unwrap(), 2);
+
+    // The primary stays open until the writer's last transaction is gone.
+    assert!(reader.promote().is_err());
+    drop(trans);
+    writer.demote().unwrap();
+    reader.promote().unwrap();
+    assert_eq!(writer.role(), Role::Reader);
+    assert_eq!(reader.role(), Role::Writer);
+}
+
+#[test]
+fn should_merge_union_layers_in_order_of_precedence() {
+    let a = Type::new("a").unwrap();
+    let b = Type::new("b").unwrap();
+    let base_path = tempdir().unwrap().into_path();
+    let delta_path = tempdir().unwrap().into_path();
+    let base = SledConfig::default().open(&base_path).unwrap();
+    let delta = SledConfig::default().open(&delta_path).unwrap();
+    let id = Uuid::from_u128;
+    let edge = EdgeKey::new(id(1), a.clone(), id(2));
+
+    {
+        let trans = base.transaction().unwrap();
+        for i in 1..=3 {
+            trans.create_vertex(&Vertex::with_id(id(i), a.clone())).unwrap();
+        }
+        trans.create_edge(&edge).unwrap();
+        trans
+            .set_vertex_properties(
+                VertexPropertyQuery::new(SpecificVertexQuery::new(vec![id(1), id(2)]).into(), "layer"),
+                &json!("base"),
+            )
+            .unwrap();
+        trans
+            .set_edge_properties(
+                EdgePropertyQuery::new(SpecificEdgeQuery::single(edge.clone()).into(), "layer"),
+                &json!("base"),
+            )
+            .unwrap();
+    }
+
+    // The delta shadows vertex 2 with another type, and the properties of
+    // vertex 1 and the edge.
+    {
+        let trans = delta.transaction().unwrap();
+        trans.create_vertex(&Vertex::with_id(id(1), a.clone())).unwrap();
+        trans.create_vertex(&Vertex::with_id(id(2), b.clone())).unwrap();
+        trans.create_vertex(&Vertex::with_id(id(4), a.clone())).unwrap();
+        trans.create_edge(&edge).unwrap();
+        trans
+            .set_vertex_properties(
+                VertexPropertyQuery::new(SpecificVertexQuery::single(id(1)).into(), "layer"),
+                &json!("delta"),
+            )
+            .unwrap();
+        trans
+            .set_edge_properties(
+                EdgePropertyQuery::new(SpecificEdgeQuery::single(edge.clone()).into(), "layer"),
+                &json!("delta"),
+            )
+            .unwrap();
+    }
+
+    let union = UnionDatastore::new(vec![delta, base]);
+    let trans = union.transaction().unwrap();
+    let types = |vertices: Vec<Vertex>| -> Vec<(u128, Type)> {
+        vertices
+            .into_iter()
+            .map(|vertex| (vertex.id.as_u128(), vertex.t))
+            .collect()
+    };
+
+    assert_eq!(trans.get_vertex_count().unwrap(), 4);
+    assert_eq!(
+        types(trans.get_vertices(RangeVertexQuery::new().limit(3)).unwrap()),
+        vec![(1, a.clone()), (2, b.clone()), (3, a.clone())]
+    );
+    assert_eq!(
+        types(
+            trans
+                .get_vertices(SpecificVertexQuery::new(vec![id(4), id(2), id(4)]))
+                .unwrap()
+        ),
+        vec![(4, a.clone()), (2, b.clone()), (4, a.clone())]
+    );
+
+    let values: Vec<(u128, JsonValue)> = trans
+        .get_vertex_properties(VertexPropertyQuery::new(
+            SpecificVertexQuery::new(vec![id(1), id(2), id(3)]).into(),
+            "layer",
+        ))
+        .unwrap()
+        .into_iter()
+        .map(|property| (property.id.as_u128(), property.value))
+        .collect();
+    assert_eq!(values, vec![(1, json!("delta")), (2, json!("base"))]);
+
+    // The edge is in both layers, but is only returned once, with the
+    // delta's property.
+    let outbound = PipeEdgeQuery {
+        inner: Box::new(SpecificVertexQuery::single(id(1)).into()),
+        direction: EdgeDirection::Outbound,
+        t: None,
+        high: None,
+        low: None,
+        limit: 10,
+    };
+    assert_eq!(trans.get_edges(outbound.clone()).unwrap().len(), 1);
+    assert_eq!(trans.get_edge_count(id(1), None, EdgeDirection::Outbound).unwrap(), 1);
+    let properties = trans
+        .get_edge_properties(EdgePropertyQuery::new(outbound.into(), "layer"))
+        .unwrap();
+    assert_eq!(properties.len(), 1);
+    assert_eq!(properties[0].value, json!("delta"));
+
+    // Putting the base first makes it win instead.
+    drop(trans);
+    drop(union);
+    let union = UnionDatastore::new(vec![
+        SledConfig::default().open(&base_path).unwrap(),
+        SledConfig::default().open(&delta_path).unwrap(),
+    ]);
+    let trans = union.transaction().unwrap();
+    assert_eq!(trans.get_vertex_count().unwrap(), 4);
+    assert_eq!(
+        types(trans.get_vertices(SpecificVertexQuery::single(id(2))).unwrap()),
+        vec![(2, a)]
+    );
+}
+
+#[test]
+fn should_compare_sampled_reads_with_shadow() {
+    let t = Type::new("test_vertex_type").unwrap();
+    let primary = SledConfig::default().open(tempdir().unwrap().into_path()).unwrap();
+    let shadow = SledConfig::default().open(tempdir().unwrap().into_path()).unwrap();
+    let mismatches = Arc::new(Mutex::new(Vec::new()));
+    let handler_mismatches = mismatches.clone();
+    let datastore = ShadowDatastore::new(primary, shadow, 2)
+        .with_mismatch_handler(move |mismatch| handler_mismatches.lock().unwrap().push(mismatch.clone()));
+
+    // Writes reach both datastores, so their reads agree.
+    let trans = datastore.transaction().unwrap();
+    trans
+        .create_vertex(&Vertex::with_id(Uuid::from_u128(1), t.clone()))
+        .unwrap();
+    trans
+        .create_vertex(&Vertex::with_id(Uuid::from_u128(2), t.clone()))
+        .unwrap();
+    trans
+        .create_edge(&EdgeKey::new(Uuid::from_u128(1), t.clone(), Uuid::from_u128(2)))
+        .unwrap();
+
+    for _ in 0..4 {
+        assert_eq!(trans.get_vertex_count().unwrap(), 2);
+        let edges = trans
+            .get_edges(SpecificEdgeQuery::single(EdgeKey::new(
+                Uuid::from_u128(1),
+                t.clone(),
+                Uuid::from_u128(2),
+            )))
+            .unwrap();
+        assert_eq!(edges.len(), 1);
+    }
+
+    let report = datastore.report();
+    assert_eq!(report.sampled_reads, 4);
+    assert_eq!(report.mismatches, 0);
+
+    // A vertex only in the primary makes sampled counts mismatch, but the
+    // primary's result is still returned.
+    datastore
+        .primary()
+        .transaction()
+        .unwrap()
+        .create_vertex(&Vertex::with_id(Uuid::from_u128(3), t))
+        .unwrap();
+
+    for _ in 0..2 {
+        assert_eq!(trans.get_vertex_count().unwrap(), 3);
+    }
+
+    assert_eq!(datastore.report().mismatches, 1);
+    let mismatches = mismatches.lock().unwrap();
+    assert_eq!(mismatches.len(), 1);
+    assert_eq!(mismatches[0].operation, "get_vertex_count");
+    assert_eq!(mismatches[0].primary, "3");
+    assert_eq!(mismatches[0].shadow, "2");
+}
+
+/// Makes tenant 1 read-only.
+struct ReadOnlyTenant;
+
+impl AccessPolicy for ReadOnlyTenant {
+    fn authorize(&self, tenant: Option<u32>, kind: AccessKind, _operation: &str) -> std::result::Result<(), String> {
+        if tenant == Some(1) && kind == AccessKind::Write {
+            Err("tenant is read-only".to_string())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[test]
+fn should_enforce_access_policy_per_tenant() {
+    let t = Type::new("test_vertex_type").unwrap();
+    let datastore = datastore(IteratorStability::Live);
+    datastore.set_access_policy(ReadOnlyTenant);
+
+    let read_only = datastore.partition(1).unwrap();
+    let read_write = datastore.partition(2).unwrap();
+    let vertex = Vertex::with_id(Uuid::from_u128(1), t);
+
+    assert!(read_write.transaction().unwrap().create_vertex(&vertex).unwrap());
+
+    let trans = read_only.transaction().unwrap();
+    assert_eq!(trans.get_vertex_count().unwrap(), 0);
+
+    for result in [
+        trans.create_vertex(&vertex).map(|_| ()),
+        read_only.bulk_insert(vec![BulkInsertItem::Vertex(vertex.clone())].into_iter()),
+    ] {
+        match result {
+            Err(IndraError::Datastore { inner }) => match inner.downcast_ref::<Error>() {
+                Some(Error::AccessDenied { tenant, reason, .. }) => {
+                    assert_eq!(*tenant, Some(1));
+                    assert_eq!(reason, "tenant is read-only");
+                }
+                _ => panic!("unexpected error: {}", inner),
+            },
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    assert_eq!(trans.get_vertex_count().unwrap(), 0);
+}
+
+#[test]
+fn should_reclaim_dropped_partitions_in_background() {
+    let t = Type::new("test_vertex_type").unwrap();
+    let path = tempdir().unwrap().into_path();
+    let datastore = SledConfig::default().with_background_reclaim().open(path).unwrap();
+    let dropped = datastore.partition(1).unwrap();
+    let kept = datastore.partition(2).unwrap();
+
+    // Enough records to take several batches.
+    dropped
+        .bulk_insert((0..2500).map(|i| BulkInsertItem::Vertex(Vertex::with_id(Uuid::from_u128(i), t.clone()))))
+        .unwrap();
+    dropped.index_property("n").unwrap();
+    kept.transaction()
+        .unwrap()
+        .create_vertex(&Vertex::with_id(Uuid::from_u128(1), t.clone()))
+        .unwrap();
+    drop(dropped);
+
+    assert!(datastore.drop_partition(1).unwrap());
+    assert!(!datastore.drop_partition(1).unwrap());
+    assert_eq!(datastore.partitions().unwrap(), vec![2]);
+
+    match datastore.partition(1) {
+        Err(IndraError::Datastore { inner }) => match inner.downcast_ref::<Error>() {
+            Some(Error::PartitionReclaiming { tenant: 1 }) => {}
+            _ => panic!("unexpected error: {}", inner),
+        },
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+
+    let mut waited = 0;
+
+    loop {
+        let progress = datastore.reclaim_progress();
+
+        if progress.is_empty() {
+            break;
+        }
+
+        assert!(progress.iter().all(|progress| progress.tenant == 1));
+        waited += 1;
+        assert!(waited < 1000, "reclaim didn't finish");
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    assert!(!datastore
+        .holder
+        .db
+        .tree_names()
+        .iter()
+        .any(|name| name.starts_with(b"partition:1:")));
+
+    // The tenant starts afresh, without the dropped partition's index
+    // definitions.
+    let recreated = datastore.partition(1).unwrap();
+    assert_eq!(recreated.transaction().unwrap().get_vertex_count().unwrap(), 0);
+    assert!(recreated.indexed_properties().is_empty());
+    assert_eq!(kept.transaction().unwrap().get_vertex_count().unwrap(), 1);
+}
+
+fn preflight_failure(result: Result<()>) -> PreflightCheck {
+    match result {
+        Err(IndraError::Datastore { inner }) => match inner.downcast_ref::<Error>() {
+            Some(Error::PreflightFailed { check, .. }) => *check,
+            _ => panic!("unexpected error: {}", inner),
+        },
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
+
+#[test]
+fn should_check_datastore_path_before_opening() {
+    let dir = tempdir().unwrap().into_path();
+    let path = dir.join("nested").join("datastore");
+    let config = SledConfig::default();
+
+    // A datastore that doesn't exist yet only needs a writable directory.
+    config.preflight(&path).unwrap();
+    assert!(!path.exists());
+
+    assert_eq!(
+        preflight_failure(config.preflight(dir.join("x".repeat(300)))),
+        PreflightCheck::PathLength
+    );
+
+    let file = dir.join("file");
+    std::fs::write(&file, b"").unwrap();
+    assert_eq!(
+        preflight_failure(config.preflight(file.join("datastore"))),
+        PreflightCheck::Permissions
+    );
+
+    assert_eq!(
+        preflight_failure(config.clone().with_preflight_min_free_space(u64::MAX).preflight(&path)),
+        PreflightCheck::DiskSpace
+    );
+
+    {
+        let datastore = config.clone().open(&path).unwrap();
+        assert_eq!(preflight_failure(config.preflight(&path)), PreflightCheck::Lock);
+
+        datastore
+            .holder
+            .metadata
+            .insert("format_version", &(FORMAT_VERSION + 1).to_be_bytes())
+            .unwrap();
+        datastore.sync().unwrap();
+    }
+
+    assert_eq!(preflight_failure(config.preflight(&path)), PreflightCheck::Format);
+}
+
+#[test]
+fn should_check_whether_datastore_exists_when_opening() {
+    let path = tempdir().unwrap().into_path().join("datastore");
+
+    match SledConfig::default().with_must_exist().open(&path) {
+        Err(IndraError::Datastore { inner }) => match inner.downcast_ref::<Error>() {
+            Some(Error::DatastoreNotFound { path: ref not_found }) => assert_eq!(not_found, &path),
+            _ => panic!("unexpected error: {}", inner),
+        },
+        _ => panic!("expected a missing datastore to be rejected"),
+    }
+
+    assert_eq!(
+        preflight_failure(SledConfig::default().with_must_exist().preflight(&path)),
+        PreflightCheck::Existence
+    );
+
+    SledConfig::default().with_create_new().preflight(&path).unwrap();
+    drop(SledConfig::default().with_create_new().open(&path).unwrap());
+
+    match SledConfig::default().with_create_new().open(&path) {
+        Err(IndraError::Datastore { inner }) => match inner.downcast_ref::<Error>() {
+            Some(Error::DatastoreExists { path: ref existing }) => assert_eq!(existing, &path),
+            _ => panic!("unexpected error: {}", inner),
+        },
+        _ => panic!("expected an existing datastore to be rejected"),
+    }
+
+    assert_eq!(
+        preflight_failure(SledConfig::default().with_create_new().preflight(&path)),
+        PreflightCheck::Existence
+    );
+
+    // The last flag set wins.
+    SledConfig::default()
+        .with_create_new()
+        .with_must_exist()
+        .open(&path)
+        .unwrap();
+}
+
+#[test]
+fn should_only_create_a_new_datastore_once_when_racing() {
+    let path = tempdir().unwrap().into_path().join("datastore");
+    let barrier = Arc::new(Barrier::new(8));
+
+    let handles: Vec<_> = (0..8)
+        .map(|_| {
+            let (path, barrier) = (path.clone(), barrier.clone());
+            thread::spawn(move || {
+                barrier.wait();
+                SledConfig::default().with_create_new().open(&path)
+            })
+        })
+        .collect();
+
+    let mut created = Vec::new();
+
+    for handle in handles {
+        match handle.join().unwrap() {
+            Ok(datastore) => created.push(datastore),
+            Err(IndraError::Datastore { inner }) => match inner.downcast_ref::<Error>() {
+                Some(Error::DatastoreExists { .. }) => {}
+                _ => panic!("unexpected error: {}", inner),
+            },
+            Err(err) => panic!("unexpected error: {}", err),
+        }
+    }
+
+    assert_eq!(created.len(), 1);
+}
+
+#[test]
+fn should_find_vertices_by_property_value() {
+    let t = Type::new("test_vertex_type").unwrap();
+    let datastore = datastore(IteratorStability::Live);
+    let trans = datastore.transaction().unwrap();
+    datastore.index_property("n").unwrap();
+    datastore.index_property("n1").unwrap();
+
+    for i in 1..5 {
+        trans
+            .create_vertex(&Vertex::with_id(Uuid::from_u128(i), t.clone()))
+            .unwrap();
+    }
+
+    let q = |i: u128| VertexPropertyQuery::new(SpecificVertexQuery::single(Uuid::from_u128(i)).into(), "n".to_string());
+    let ids = |value: JsonValue| -> Vec<u128> {
+        trans
+            .get_vertices_by_property("n", &value)
+            .unwrap()
+            .into_iter()
+            .map(|vertex| vertex.id.as_u128())
+            .collect()
+    };
+
+    // 12's value starts with the bytes of 1's, but doesn't match it.
+    trans.set_vertex_properties(q(1), &JsonValue::from(1)).unwrap();
+    trans.set_vertex_properties(q(2), &JsonValue::from(12)).unwrap();
+    trans.set_vertex_properties(q(3), &JsonValue::from(1)).unwrap();
+    assert_eq!(ids(JsonValue::from(1)), vec![1, 3]);
+    assert_eq!(ids(JsonValue::from(12)), vec![2]);
+
+    trans.set_vertex_properties(q(3), &JsonValue::from(12)).unwrap();
+    trans.delete_vertex_properties(q(1)).unwrap();
+    assert!(ids(JsonValue::from(1)).is_empty());
+    assert_eq!(ids(JsonValue::from(12)), vec![2, 3]);
+
+    let mut batch = trans.begin_batch();
+    batch
+        .set_vertex_property(Uuid::from_u128(2), "n", &JsonValue::from(1))
+        .set_vertex_property(Uuid::from_u128(4), "n", &JsonValue::from(1));
+    batch.commit().unwrap();
+    assert_eq!(ids(JsonValue::from(1)), vec![2, 4]);
+
+    assert!(trans
+        .move_property(Uuid::from_u128(3), Uuid::from_u128(4), "n")
+        .unwrap());
+    trans
+        .delete_vertices(SpecificVertexQuery::single(Uuid::from_u128(2)))
+        .unwrap();
+    assert!(ids(JsonValue::from(1)).is_empty());
+    assert_eq!(ids(JsonValue::from(12)), vec![4]);
+
+    datastore.reindex(Index::VertexPropertyValues).unwrap();
+    assert_eq!(ids(JsonValue::from(12)), vec![4]);
+    assert_eq!(datastore.holder.vertex_property_values.writer().len(), 1);
+
+    // A name followed by the start of a value isn't another name.
+    trans
+        .create_vertex(&Vertex::with_id(Uuid::from_u128(5), t.clone()))
+        .unwrap();
+    trans
+        .set_vertex_properties(
+            VertexPropertyQuery::new(SpecificVertexQuery::single(Uuid::from_u128(5)).into(), "n1".to_string()),
+            &JsonValue::from(2),
+        )
+        .unwrap();
+    assert_eq!(ids(JsonValue::from(12)), vec![4]);
+    assert!(trans
+        .get_vertices_with_property_in_range::<f64, _>("n", ..)
+        .unwrap()
+        .iter()
+        .all(|vertex| vertex.id != Uuid::from_u128(5)));
+
+    let names = |kind: TreeKind| -> Vec<String> {
+        datastore
+            .iter_tree_raw::<Vec<u8>, _>(kind, ..)
+            .map(|entry| match entry.unwrap().record {
+                RawRecord::VertexPropertyValue { name, .. } | RawRecord::VertexPropertyNumber { name, .. } => name,
+                record => panic!("unexpected record: {:?}", record),
+            })
+            .collect()
+    };
+    assert_eq!(names(TreeKind::VertexPropertyValues), vec!["n", "n1"]);
+    assert_eq!(names(TreeKind::VertexPropertyNumbers), vec!["n", "n1"]);
+}
+
+#[test]
+fn should_require_full_text_index_to_search() {
+    let datastore = datastore(IteratorStability::Live);
+    let trans = datastore.transaction().unwrap();
+
+    match trans.search_vertices("title", "quick") {
+        Err(IndraError::Datastore { inner }) => match inner.downcast_ref::<Error>() {
+            Some(Error::FullTextIndexDisabled) => {}
+            _ => panic!("unexpected error: {}", inner),
+        },
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
+
+#[test]
+fn should_persist_declared_property_indexes() {
+    let path = tempdir().unwrap().into_path();
+    let t = Type::new("test_vertex_type").unwrap();
+    let id = Uuid::from_u128(1);
+    let q = |name: &str| VertexPropertyQuery::new(SpecificVertexQuery::single(id).into(), name.to_string());
+
+    {
+        let datastore = SledConfig::default().open(&path).unwrap();
+        let trans = datastore.transaction().unwrap();
+        trans.create_vertex(&Vertex::with_id(id, t)).unwrap();
+        assert!(datastore.index_property("email").unwrap());
+        trans
+            .set_vertex_properties(q("email"), &json!("a@example.com"))
+            .unwrap();
+        trans.set_vertex_properties(q("bio"), &json!("unindexed")).unwrap();
+
+        // Only the declared property has entries.
+        assert_eq!(datastore.holder.vertex_property_values.writer().len(), 1);
+    }
+
+    let datastore = SledConfig::default().open(&path).unwrap();
+    let trans = datastore.transaction().unwrap();
+    assert_eq!(datastore.indexed_properties(), vec!["email".to_string()]);
+    trans
+        .set_vertex_properties(q("email"), &json!("b@example.com"))
+        .unwrap();
+    assert_eq!(
+        trans
+            .get_vertices_by_property("email", &json!("b@example.com"))
+            .unwrap()[0]
+            .id,
+        id
+    );
+
+    match trans.get_vertices_by_property("bio", &json!("unindexed")) {
+        Err(IndraError::Datastore { inner }) => match inner.downcast_ref::<Error>() {
+            Some(Error::PropertyNotIndexed { name }) => assert_eq!(name, "bio"),
+            _ => panic!("unexpected error: {}", inner),
+        },
+        result => panic!("unexpected result: {:?}", result),
+    }
+
+    assert!(datastore.drop_index("email").unwrap());
+    assert!(!datastore.drop_index("email").unwrap());
+    assert!(datastore.indexed_properties().is_empty());
+    assert_eq!(datastore.holder.vertex_property_values.writer().len(), 0);
+    assert!(trans
+        .get_vertices_by_property("email", &json!("b@example.com"))
+        .is_err());
+}
+
+#[test]
+fn should_rebuild_property_index_in_chunks() {
+    let t = Type::new("test_vertex_type").unwrap();
+    let datastore = datastore(IteratorStability::Live);
+    let trans = datastore.transaction().unwrap();
+    datastore.index_property("n").unwrap();
+
+    let mut batch = trans.begin_batch();
+
+    for i in 0..1250 {
+        let id = Uuid::from_u128(i);
+        batch.create_vertex(&Vertex::with_id(id, t.clone()));
+        batch.set_vertex_property(id, "n", &json!(i % 2));
+        batch.set_vertex_property(id, "m", &json!(i));
+    }
+
+    batch.commit().unwrap();
+    assert_eq!(trans.get_vertices_by_property("n", &json!(1)).unwrap().len(), 625);
+
+    // Simulate losing the index.
+    datastore.holder.vertex_property_values.writer().clear().unwrap();
+    datastore.holder.vertex_property_numbers.writer().clear().unwrap();
+    assert!(trans.get_vertices_by_property("n", &json!(1)).unwrap().is_empty());
+
+    let mut reports = Vec::new();
+    let progress = datastore
+        .rebuild_index_with_progress("n", |progress| {
+            // The property can't be queried while it's being rebuilt.
+            match trans.get_vertices_by_property("n", &json!(1)) {
+                Err(IndraError::Datastore { inner }) => match inner.downcast_ref::<Error>() {
+                    Some(Error::PropertyIndexBuilding { name }) => assert_eq!(name, "n"),
+                    _ => panic!("unexpected error: {}", inner),
+                },
+                result => panic!("unexpected result: {:?}", result),
+            }
+
+            reports.push(*progress);
+        })
+        .unwrap();
+
+    assert_eq!(progress.scanned, 2500);
+    assert_eq!(progress.indexed, 1250);
+    assert_eq!(reports.len(), 3);
+    assert_eq!(reports.last(), Some(&progress));
+    assert_eq!(trans.get_vertices_by_property("n", &json!(1)).unwrap().len(), 625);
+    assert_eq!(
+        trans
+            .get_vertices_with_property_in_range::<f64, _>("n", 1.0..)
+            .unwrap()
+            .len(),
+        625
+    );
+
+    match datastore.rebuild_index("m") {
+        Err(IndraError::Datastore { inner }) => match inner.downcast_ref::<Error>() {
+            Some(Error::PropertyNotIndexed { name }) => assert_eq!(name, "m"),
+            _ => panic!("unexpected error: {}", inner),
+        },
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
+
+#[test]
+fn should_find_vertices_with_property_in_range() {
+    let t = Type::new("test_vertex_type").unwrap();
+    let datastore = datastore(IteratorStability::Live);
+    let trans = datastore.transaction().unwrap();
+    let values = [json!(30), json!(-2.5), json!(65), json!(18), json!("20"), json!(0)];
+
+    for (i, value) in values.iter().enumerate() {
+        let id = Uuid::from_u128(i as u128 + 1);
+        trans.create_vertex(&Vertex::with_id(id, t.clone())).unwrap();
+        trans
+            .set_vertex_properties(
+                VertexPropertyQuery::new(SpecificVertexQuery::single(id).into(), "age".to_string()),
+                value,
+            )
+            .unwrap();
+    }
+
+    // Existing values are indexed when the property is declared.
+    assert!(datastore.index_property("age").unwrap());
+    assert!(!datastore.index_property("age").unwrap());
+
+    let ids = |vertices: Vec<Vertex>| -> Vec<u128> { vertices.into_iter().map(|vertex| vertex.id.as_u128()).collect() };
+
+    // Results are in value order, and strings aren't matched.
+    assert_eq!(
+        ids(trans.get_vertices_with_property_in_range("age", 18..65).unwrap()),
+        vec![4, 1]
+    );
+    assert_eq!(
+        ids(trans.get_vertices_with_property_in_range("age", 18..=65).unwrap()),
+        vec![4, 1, 3]
+    );
+    assert_eq!(
+        ids(trans.get_vertices_with_property_in_range("age", ..0.0).unwrap()),
+        vec![2]
+    );
+    assert_eq!(
+        ids(trans.get_vertices_with_property_in_range("age", -0.0..=0.0).unwrap()),
+        vec![6]
+    );
+    assert_eq!(
+        ids(trans.get_vertices_with_property_in_range("age", 40.0..).unwrap()),
+        vec![3]
+    );
+    assert!(trans
+        .get_vertices_with_property_in_range("age", (Bound::Included(65), Bound::Excluded(18)))
+        .unwrap()
+        .is_empty());
+
+    trans
+        .set_vertex_properties(
+            VertexPropertyQuery::new(
+                SpecificVertexQuery::single(Uuid::from_u128(1)).into(),
+                "age".to_string(),
+            ),
+            &json!(70),
+        )
+        .unwrap();
+    assert_eq!(
+        ids(trans.get_vertices_with_property_in_range("age", 18..65).unwrap()),
+        vec![4]
+    );
+
+    datastore.reindex(Index::VertexPropertyNumbers).unwrap();
+    assert_eq!(
+        ids(trans.get_vertices_with_property_in_range("age", 60..).unwrap()),
+        vec![3, 1]
+    );
+}
+
+#[test]
+fn should_find_edges_by_property() {
+    let t = Type::new("test_edge_type").unwrap();
+    let datastore = datastore(IteratorStability::Live);
+    let trans = datastore.transaction().unwrap();
+    let key = |i: u128| EdgeKey::new(Uuid::from_u128(1), t.clone(), Uuid::from_u128(i));
+    let q = |i: u128| EdgePropertyQuery::new(SpecificEdgeQuery::single(key(i)).into(), "weight".to_string());
+    let values = [json!(0.9), json!(0.5), json!("0.7"), json!(0.2), json!(0.9)];
+
+    for i in 1..=values.len() as u128 + 1 {
+        trans
+            .create_vertex(&Vertex::with_id(Uuid::from_u128(i), t.clone()))
+            .unwrap();
+    }
+
+    for (i, value) in values.iter().enumerate() {
+        trans.create_edge(&key(i as u128 + 2)).unwrap();
+        trans.set_edge_properties(q(i as u128 + 2), value).unwrap();
+    }
+
+    match trans.get_edges_by_property("weight", &json!(0.9)) {
+        Err(IndraError::Datastore { inner }) => match inner.downcast_ref::<Error>() {
+            Some(Error::PropertyNotIndexed { name }) => assert_eq!(name, "weight"),
+            _ => panic!("unexpected error: {}", inner),
+        },
+        result => panic!("unexpected result: {:?}", result),
+    }
+
+    // Existing values are indexed when the property is declared.
+    assert!(datastore.index_edge_property("weight").unwrap());
+    assert!(!datastore.index_edge_property("weight").unwrap());
+    assert_eq!(datastore.indexed_edge_properties(), vec!["weight".to_string()]);
+    assert!(datastore.indexed_properties().is_empty());
+
+    let ids = |edges: Vec<Edge>| -> Vec<u128> { edges.into_iter().map(|edge| edge.key.inbound_id.as_u128()).collect() };
+    let heavy = || trans.get_edges_with_property_in_range("weight", (Bound::Excluded(0.5), Bound::Unbounded));
+
+    assert_eq!(
+        ids(trans.get_edges_by_property("weight", &json!(0.9)).unwrap()),
+        vec![2, 6]
+    );
+    assert_eq!(
+        ids(trans.get_edges_by_property("weight", &json!("0.7")).unwrap()),
+        vec![4]
+    );
+    assert_eq!(ids(heavy().unwrap()), vec![2, 6]);
+    assert_eq!(
+        ids(trans.get_edges_with_property_in_range("weight", ..=0.5).unwrap()),
+        vec![5, 3]
+    );
+
+    trans.set_edge_properties(q(5), &json!(0.6)).unwrap();
+    trans.delete_edges(SpecificEdgeQuery::single(key(2))).unwrap();
+    assert_eq!(ids(heavy().unwrap()), vec![5, 6]);
+    assert_eq!(
+        ids(trans.get_edges_by_property("weight", &json!(0.9)).unwrap()),
+        vec![6]
+    );
+
+    datastore.reindex(Index::EdgePropertyNumbers).unwrap();
+    assert_eq!(ids(heavy().unwrap()), vec![5, 6]);
+    assert_eq!(datastore.rebuild_edge_index("weight").unwrap().indexed, 4);
+
+    let records: Vec<RawRecord> = datastore
+        .iter_tree_raw::<Vec<u8>, _>(TreeKind::EdgePropertyValues, ..)
+        .map(|entry| entry.unwrap().record)
+        .collect();
+    assert_eq!(records.len(), 4);
+    assert_eq!(
+        records[0],
+        RawRecord::EdgePropertyValue {
+            name: "weight".to_string(),
+            value: json!("0.7"),
+            key: key(4),
+        }
+    );
+
+    assert!(datastore.drop_edge_index("weight").unwrap());
+    assert_eq!(datastore.holder.edge_property_values.writer().len(), 0);
+    assert_eq!(datastore.holder.edge_property_numbers.writer().len(), 0);
+    assert!(heavy().is_err());
+}
+
+#[test]
+fn should_list_edges_by_type() {
+    let t = Type::new("test_edge_type").unwrap();
+    let other_t = Type::new("other_edge_type").unwrap();
+    let datastore = datastore(IteratorStability::Live);
+    let trans = datastore.transaction().unwrap();
+    let key = |t: &Type, i: u128| EdgeKey::new(Uuid::from_u128(1), t.clone(), Uuid::from_u128(i));
+
+    for i in 1..=5 {
+        trans
+            .create_vertex(&Vertex::with_id(Uuid::from_u128(i), t.clone()))
+            .unwrap();
+    }
+
+    for i in 2..=5 {
+        trans.create_edge(&key(&t, i)).unwrap();
+        trans.create_edge(&key(&other_t, i)).unwrap();
+        thread::sleep(Duration::from_millis(2));
+    }
+
+    let ids = |edges: Vec<Edge>| -> Vec<u128> { edges.into_iter().map(|edge| edge.key.inbound_id.as_u128()).collect() };
+    assert_eq!(ids(trans.get_edges_by_type(&t, None, 10).unwrap()), vec![5, 4, 3, 2]);
+    assert_eq!(ids(trans.get_edges_by_type(&t, None, 2).unwrap()), vec![5, 4]);
+
+    // Pages continue from the update datetime of the last edge.
+    let page = trans.get_edges_by_type(&t, None, 2).unwrap();
+    let high = page[1].created_datetime - ChronoDuration::nanoseconds(1);
+    assert_eq!(ids(trans.get_edges_by_type(&t, Some(high), 10).unwrap()), vec![3, 2]);
+
+    // Updating an edge moves its entry, and deleting one removes it.
+    trans.create_edge(&key(&t, 2)).unwrap();
+    trans.delete_edges(SpecificEdgeQuery::single(key(&t, 4))).unwrap();
+    assert_eq!(ids(trans.get_edges_by_type(&t, None, 10).unwrap()), vec![2, 5, 3]);
+    assert_eq!(
+        ids(trans.get_edges_by_type(&other_t, None, 10).unwrap()),
+        vec![5, 4, 3, 2]
+    );
+
+    datastore.reindex(Index::EdgesByType).unwrap();
+    assert_eq!(ids(trans.get_edges_by_type(&t, None, 10).unwrap()), vec![2, 5, 3]);
+
+    let records: Vec<RawRecord> = datastore
+        .iter_tree_raw::<Vec<u8>, _>(TreeKind::EdgesByType, ..)
+        .map(|entry| entry.unwrap().record)
+        .collect();
+    assert_eq!(records.len(), 7);
+}
+
+#[test]
+fn should_tag_connected_components() {
+    let t = Type::new("test_edge_type").unwrap();
+    let other_t = Type::new("other_edge_type").unwrap();
+    let datastore = datastore(IteratorStability::Live);
+    let trans = datastore.transaction().unwrap();
+    let id = Uuid::from_u128;
+
+    for i in 1..=6 {
+        trans.create_vertex(&Vertex::with_id(id(i), t.clone())).unwrap();
+    }
+
+    trans.create_edge(&EdgeKey::new(id(1), t.clone(), id(2))).unwrap();
+    trans.create_edge(&EdgeKey::new(id(3), t.clone(), id(2))).unwrap();
+    trans.create_edge(&EdgeKey::new(id(5), other_t, id(4))).unwrap();
+    datastore.index_property("component").unwrap();
+
+    let component = |i: u128| -> Vec<u128> {
+        let value = trans
+            .get_vertex_properties(VertexPropertyQuery::new(
+                SpecificVertexQuery::single(id(i)).into(),
+                "component".to_string(),
+            ))
+            .unwrap()[0]
+            .value
+            .clone();
+        trans
+            .get_vertices_by_property("component", &value)
+            .unwrap()
+            .into_iter()
+            .map(|vertex| vertex.id.as_u128())
+            .collect()
+    };
+
+    assert_eq!(datastore.tag_connected_components("component", None).unwrap(), 3);
+    assert_eq!(component(2), vec![1, 2, 3]);
+    assert_eq!(component(5), vec![4, 5]);
+    assert_eq!(component(6), vec![6]);
+
+    assert_eq!(datastore.tag_connected_components("component", Some(&t)).unwrap(), 4);
+    assert_eq!(component(3), vec![1, 2, 3]);
+    assert_eq!(component(5), vec![5]);
+    assert!(!datastore
+        .holder
+        .db
+        .tree_names()
+        .iter()
+        .any(|name| &name[..] == b"connected_components"));
+}
+
+#[test]
+fn should_apply_merge_patch_to_vertex_properties() {
+    let t = Type::new("test_vertex_type").unwrap();
+    let datastore = datastore(IteratorStability::Live);
+    let trans = datastore.transaction().unwrap();
+    let id = Uuid::from_u128(1);
+    trans.create_vertex(&Vertex::with_id(id, t)).unwrap();
+    datastore.index_property("score").unwrap();
+    datastore.index_property("status").unwrap();
+
+    let q = |name: &str| VertexPropertyQuery::new(SpecificVertexQuery::single(id).into(), name.to_string());
+    let get = |name: &str| {
+        trans
+            .get_vertex_properties(q(name))
+            .unwrap()
+            .into_iter()
+            .next()
+            .map(|property| property.value)
+    };
+
+    trans
+        .set_vertex_properties(
+            q("profile"),
+            &json!({"name": "a", "tags": ["x"], "address": {"city": "b"}}),
+        )
+        .unwrap();
+    trans.set_vertex_properties(q("status"), &json!("active")).unwrap();
+
+    let patch = json!({
+        "profile": {"name": "c", "tags": null, "address": {"zip": "1"}},
+        "status": null,
+        "score": 2
+    });
+    assert!(trans.patch_vertex_properties(id, &patch).unwrap());
+
+    assert_eq!(
+        get("profile"),
+        Some(json!({"name": "c", "address": {"city": "b", "zip": "1"}}))
+    );
+    assert_eq!(get("status"), None);
+    assert_eq!(get("score"), Some(json!(2)));
+    assert_eq!(trans.get_vertices_by_property("score", &json!(2)).unwrap().len(), 1);
+    assert!(trans
+        .get_vertices_by_property("status", &json!("active"))
+        .unwrap()
+        .is_empty());
+
+    assert!(!trans.patch_vertex_properties(Uuid::from_u128(2), &patch).unwrap());
+
+    match trans.patch_vertex_properties(id, &json!([1])) {
+        Err(IndraError::Datastore { inner }) => match inner.downcast_ref::<Error>() {
+            Some(Error::InvalidMergePatch) => {}
+            _ => panic!("unexpected error: {}", inner),
+        },
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
+
+#[test]
+fn should_enforce_unique_properties() {
+    let t = Type::new("test_vertex_type").unwrap();
+    let path = tempdir().unwrap().into_path();
+    let datastore = SledConfig::default().open(&path).unwrap();
+    let trans = datastore.transaction().unwrap();
+    let ids: Vec<Uuid> = (1..=4).map(Uuid::from_u128).collect();
+
+    for &id in &ids {
+        trans.create_vertex(&Vertex::with_id(id, t.clone())).unwrap();
+    }
+
+    let q = |id: Uuid| VertexPropertyQuery::new(SpecificVertexQuery::single(id).into(), "email".to_string());
+    let get = |trans: &SledTransaction, id: Uuid| {
+        trans
+            .get_vertex_properties(q(id))
+            .unwrap()
+            .into_iter()
+            .next()
+            .map(|property| property.value)
+    };
+    let assert_taken = |result: Result<()>, owner: Uuid| match result {
+        Err(IndraError::Datastore { inner }) => match inner.downcast_ref::<Error>() {
+            Some(Error::UniqueValueTaken { name, vertex_id }) if name == "email" && *vertex_id == owner => {}
+            _ => panic!("unexpected error: {}", inner),
+        },
+        result => panic!("unexpected result: {:?}", result),
+    };
+
+    // Existing duplicates keep the property from being indexed.
+    trans.set_vertex_properties(q(ids[0]), &json!("a")).unwrap();
+    trans.set_vertex_properties(q(ids[1]), &json!("a")).unwrap();
+    assert!(datastore.index_unique_property("email").is_err());
+    assert!(datastore.indexed_properties().is_empty());
+
+    trans.set_vertex_properties(q(ids[1]), &json!("b")).unwrap();
+    assert!(datastore.index_unique_property("email").unwrap());
+
+    // Setting a vertex's own value again is fine, taking another's isn't.
+    trans.set_vertex_properties(q(ids[0]), &json!("a")).unwrap();
+    assert_taken(trans.set_vertex_properties(q(ids[2]), &json!("a")), ids[0]);
+    assert_eq!(get(&trans, ids[2]), None);
+
+    // Changing or deleting a value frees it up.
+    trans.set_vertex_properties(q(ids[0]), &json!("c")).unwrap();
+    trans.set_vertex_properties(q(ids[2]), &json!("a")).unwrap();
+    trans.delete_vertex_properties(q(ids[2])).unwrap();
+    trans.set_vertex_properties(q(ids[3]), &json!("a")).unwrap();
+
+    // A batch with a taken value writes nothing.
+    let mut batch = trans.begin_batch();
+    batch
+        .set_vertex_property(ids[2], "email", &json!("d"))
+        .set_vertex_property(ids[2], "name", &json!("x"))
+        .set_vertex_property(ids[1], "email", &json!("c"));
+    assert_taken(batch.commit(), ids[0]);
+    assert_eq!(get(&trans, ids[1]), Some(json!("b")));
+    // The value it claimed first is released again.
+    trans.set_vertex_properties(q(ids[3]), &json!("d")).unwrap();
+    trans.set_vertex_properties(q(ids[3]), &json!("a")).unwrap();
+
+    // Patches are checked too.
+    assert_taken(
+        trans
+            .patch_vertex_properties(ids[1], &json!({"email": "c"}))
+            .map(|_| ()),
+        ids[0],
+    );
+    assert!(trans.patch_vertex_properties(ids[1], &json!({"email": "e"})).unwrap());
+    trans.set_vertex_properties(q(ids[2]), &json!("b")).unwrap();
+
+    // Moving a value moves its ownership, and frees the replaced one.
+    assert!(trans.move_property(ids[0], ids[1], "email").unwrap());
+    assert_taken(trans.set_vertex_properties(q(ids[2]), &json!("c")), ids[1]);
+    trans.set_vertex_properties(q(ids[2]), &json!("e")).unwrap();
+
+    // Uniqueness survives rebuilds and reopening, and ends with the index.
+    datastore.rebuild_index("email").unwrap();
+    assert_taken(trans.set_vertex_properties(q(ids[0]), &json!("a")), ids[3]);
+    drop(trans);
+    drop(datastore);
+
+    let datastore = SledConfig::default().open(&path).unwrap();
+    let trans = datastore.transaction().unwrap();
+    assert_taken(trans.set_vertex_properties(q(ids[0]), &json!("a")), ids[3]);
+    assert!(datastore.drop_index("email").unwrap());
+    trans.set_vertex_properties(q(ids[0]), &json!("a")).unwrap();
+}
+
+#[test]
+fn should_release_lock_when_dropped() {
+    let t = Type::new("test_vertex_type").unwrap();
+    let path = tempdir().unwrap().into_path();
+    let config = SledConfig::default().with_cold_property("payload");
+
+    // sled lets go of its lock from its own threads, so reopening straight
+    // away used to fail now and then.
+    for i in 1..=20 {
+        let datastore = config.clone().open(&path).unwrap();
+        let trans = datastore.transaction().unwrap();
+        let id = Uuid::from_u128(i);
+        trans.create_vertex(&Vertex::with_id(id, t.clone())).unwrap();
+        trans
+            .set_vertex_properties(
+                VertexPropertyQuery::new(SpecificVertexQuery::single(id).into(), "payload".to_string()),
+                &json!("x".repeat(100)),
+            )
+            .unwrap();
+        assert_eq!(trans.get_vertex_count().unwrap(), i as u64);
+    }
+}
+
+#[test]
+fn should_return_query_results_in_a_total_order() {
+    let vertex_t = Type::new("test_vertex_type").unwrap();
+    // Stored length-prefixed, types sort by length first, so "bb" would
+    // otherwise come after "c".
+    let types: Vec<Type> = ["c", "bb", "a"].iter().map(|t| Type::new(*t).unwrap()).collect();
+    let datastore = datastore(IteratorStability::Live);
+    let trans = datastore.transaction().unwrap();
+    let source_id = Uuid::from_u128(1);
+
+    for i in [4, 1, 3, 2, 5].iter() {
+        trans
+            .create_vertex(&Vertex::with_id(Uuid::from_u128(*i), vertex_t.clone()))
+            .unwrap();
+    }
+
+    let vertex_ids: Vec<u128> = trans
+        .get_vertices(RangeVertexQuery::new().limit(100))
+        .unwrap()
+        .into_iter()
+        .map(|vertex| vertex.id.as_u128())
+        .collect();
+    assert_eq!(vertex_ids, vec![1, 2, 3, 4, 5]);
+
+    for t in &types {
+        for i in [3, 2, 4, 5].iter() {
+            trans
+                .create_edge(&EdgeKey::new(source_id, t.clone(), Uuid::from_u128(*i)))
+                .unwrap();
+            thread::sleep(Duration::from_millis(2));
+        }
+    }
+
+    let q = |direction: EdgeDirection, limit: u32| PipeEdgeQuery {
+        inner: Box::new(SpecificVertexQuery::single(source_id).into()),
+        direction,
+        limit,
+        t: None,
+        high: None,
+        low: None,
+    };
+    let keys = |edges: Vec<Edge>| -> Vec<(String, u128)> {
+        edges
+            .into_iter()
+            .map(|edge| (edge.key.t.0, edge.key.inbound_id.as_u128()))
+            .collect()
+    };
+
+    let expected: Vec<(String, u128)> = ["a", "bb", "c"]
+        .iter()
+        .flat_map(|t| [5, 4, 2, 3].iter().map(move |i| (t.to_string(), *i)))
+        .collect();
+    let edges = trans.get_edges(q(EdgeDirection::Outbound, 100)).unwrap();
+    assert_eq!(keys(edges.clone()), expected);
+    assert!(edges
+        .windows(2)
+        .all(|pair| pair[0].key.t != pair[1].key.t || pair[0].created_datetime > pair[1].created_datetime));
+
+    // Pages are prefixes of the full order, however they cross types.
+    for limit in 1..12 {
+        let page = keys(trans.get_edges(q(EdgeDirection::Outbound, limit)).unwrap());
+        assert_eq!(page[..], expected[..limit as usize]);
+    }
+
+    let all_props = trans
+        .get_all_edge_properties_for_vertex(source_id, EdgeDirection::Outbound)
+        .unwrap();
+    let all_keys: Vec<(String, u128)> = all_props
+        .into_iter()
+        .map(|props| (props.edge.key.t.0, props.edge.key.inbound_id.as_u128()))
+        .collect();
+    assert_eq!(all_keys, expected);
+
+    // Inbound edges are ordered the same way, by type, then datetime.
+    let inbound: Vec<(String, u128)> = trans
+        .get_edges(PipeEdgeQuery {
+            inner: Box::new(SpecificVertexQuery::single(Uuid::from_u128(4)).into()),
+            ..q(EdgeDirection::Inbound, 100)
+        })
+        .unwrap()
+        .into_iter()
+        .map(|edge| (edge.key.t.0, edge.key.outbound_id.as_u128()))
+        .collect();
+    assert_eq!(
+        inbound,
+        vec![("a".to_string(), 1), ("bb".to_string(), 1), ("c".to_string(), 1)]
+    );
+
+    for t in &types {
+        let key = EdgeKey::new(source_id, t.clone(), Uuid::from_u128(4));
+        trans
+            .set_edge_properties(
+                EdgePropertyQuery::new(SpecificEdgeQuery::single(key).into(), "weight".to_string()),
+                &json!(1),
+            )
+            .unwrap();
+    }
+
+    let inbound_types: Vec<String> = trans
+        .get_inbound_edge_properties(Uuid::from_u128(4))
+        .unwrap()
+        .into_iter()
+        .map(|props| props.edge.key.t.0)
+        .collect();
+    assert_eq!(inbound_types, vec!["a", "bb", "c"]);
+
+    // Properties are ordered by name.
+    for name in ["b", "c", "a"].iter() {
+        trans
+            .set_vertex_properties(
+                VertexPropertyQuery::new(SpecificVertexQuery::single(source_id).into(), name.to_string()),
+                &json!(name),
+            )
+            .unwrap();
+    }
+
+    let names: Vec<String> = trans
+        .get_all_vertex_properties(SpecificVertexQuery::single(source_id))
+        .unwrap()
+        .remove(0)
+        .props
+        .into_iter()
+        .map(|prop| prop.name)
+        .collect();
+    assert_eq!(names, vec!["a", "b", "c"]);
+}
+
+#[test]
+fn should_count_edges_within_a_time_window() {
+    let (likes_t, follows_t) = (Type::new("likes").unwrap(), Type::new("follows").unwrap());
+    let day = |d| Utc.with_ymd_and_hms(2020, 1, d, 0, 0, 0).unwrap();
+
+    for config in [SledConfig::default(), SledConfig::default().with_untimed_edge_ranges()] {
+        let datastore = config.open(tempdir().unwrap().into_path()).unwrap();
+        let trans = datastore.transaction().unwrap();
+        let ids: Vec<Uuid> = (0..6).map(Uuid::from_u128).collect();
+
+        for id in &ids {
+            trans.create_vertex(&Vertex::with_id(*id, likes_t.clone())).unwrap();
+        }
+
+        for (i, id) in ids[1..5].iter().enumerate() {
+            let key = EdgeKey::new(ids[0], likes_t.clone(), *id);
+            trans.create_edge_at(&key, day(i as u32 + 1)).unwrap();
+        }
+
+        let key = EdgeKey::new(ids[0], follows_t.clone(), ids[5]);
+        trans.create_edge_at(&key, day(2)).unwrap();
+
+        let count = |id, t, direction, low, high| trans.count_edges(id, t, direction, low, high).unwrap();
+        assert_eq!(count(ids[0], None, EdgeDirection::Outbound, None, None), 5);
+        assert_eq!(count(ids[0], Some(&likes_t), EdgeDirection::Outbound, None, None), 4);
+        assert_eq!(
+            count(
+                ids[0],
+                Some(&likes_t),
+                EdgeDirection::Outbound,
+                Some(day(2)),
+                Some(day(3))
+            ),
+            2
+        );
+        assert_eq!(count(ids[0], None, EdgeDirection::Outbound, Some(day(2)), None), 4);
+        assert_eq!(count(ids[0], None, EdgeDirection::Outbound, None, Some(day(1))), 1);
+        assert_eq!(count(ids[0], None, EdgeDirection::Inbound, None, None), 0);
+        assert_eq!(count(ids[1], Some(&likes_t), EdgeDirection::Inbound, None, None), 1);
+        assert_eq!(count(ids[5], Some(&likes_t), EdgeDirection::Inbound, None, None), 0);
+    }
+}
+
+#[test]
+fn should_isolate_tenant_partitions() {
+    let datastore = SledConfig::default().open(tempdir().unwrap().into_path()).unwrap();
+    let (first, second) = (datastore.partition(1).unwrap(), datastore.partition(2).unwrap());
+    assert_eq!((datastore.tenant(), first.tenant()), (None, Some(1)));
+
+    let id = Uuid::from_u128(1);
+    let name_q = || VertexPropertyQuery::new(SpecificVertexQuery::single(id).into(), "name".to_string());
+    let first_trans = first.transaction().unwrap();
+    let second_trans = second.transaction().unwrap();
+    first_trans
+        .create_vertex(&Vertex::with_id(id, Type::new("user").unwrap()))
+        .unwrap();
+    second_trans
+        .create_vertex(&Vertex::with_id(id, Type::new("account").unwrap()))
+        .unwrap();
+    first_trans.set_vertex_properties(name_q(), &json!("first")).unwrap();
+
+    // The same ID is a different vertex in each partition, and neither is
+    // in the unpartitioned data.
+    assert_eq!(datastore.transaction().unwrap().get_vertex_count().unwrap(), 0);
+    assert_eq!(first_trans.get_vertex_count().unwrap(), 1);
+    let vertices = second_trans.get_vertices(SpecificVertexQuery::single(id)).unwrap();
+    assert_eq!(vertices[0].t.0, "account");
+    assert!(second_trans.get_vertex_properties(name_q()).unwrap().is_empty());
+    assert_eq!(datastore.partitions().unwrap(), vec![1, 2]);
+
+    assert!(datastore.drop_partition(1).unwrap());
+    assert!(!datastore.drop_partition(3).unwrap());
+    assert_eq!(datastore.partitions().unwrap(), vec![2]);
+    assert_eq!(second_trans.get_vertex_count().unwrap(), 1);
+
+    // A dropped partition starts out empty once it's used again.
+    let first = datastore.partition(1).unwrap();
+    let first_trans = first.transaction().unwrap();
+    assert_eq!(first_trans.get_vertex_count().unwrap(), 0);
+    assert!(first_trans.get_vertex_properties(name_q()).unwrap().is_empty());
+}
+
+#[test]
+fn should_cache_query_results_until_a_mutation() {
+    let datastore = SledConfig::default()
+        .with_result_cache(2)
+        .open(tempdir().unwrap().into_path())
+        .unwrap();
+    let t = Type::new("test_vertex_type").unwrap();
+    let trans = datastore.transaction().unwrap();
+    let ids: Vec<Uuid> = (1..=3).map(Uuid::from_u128).collect();
+    trans.create_vertex(&Vertex::with_id(ids[0], t.clone())).unwrap();
+    trans.create_vertex(&Vertex::with_id(ids[1], t.clone())).unwrap();
+
+    let vertex_ids = || {
+        trans
+            .get_vertices(RangeVertexQuery::new().limit(10))
+            .unwrap()
+            .into_iter()
+            .map(|vertex| vertex.id)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(vertex_ids(), ids[..2].to_vec());
+    assert_eq!(datastore.memory_usage().result_cache_entries, 1);
+    assert!(datastore.memory_usage().total_bytes() > 0);
+
+    // Writes that bypass the datastore aren't seen until the cache is
+    // invalidated.
+    datastore.holder.vertices.remove(ids[1].as_bytes()).unwrap();
+    assert_eq!(vertex_ids(), ids[..2].to_vec());
+
+    trans.create_vertex(&Vertex::with_id(ids[2], t)).unwrap();
+    assert_eq!(datastore.memory_usage().result_cache_entries, 0);
+    assert_eq!(vertex_ids(), vec![ids[0], ids[2]]);
+
+    // The oldest results are evicted past the capacity.
+    for id in &ids {
+        trans.get_vertices(SpecificVertexQuery::single(*id)).unwrap();
+    }
+
+    assert_eq!(datastore.memory_usage().result_cache_entries, 2);
+}
+
+#[test]
+fn should_diff_checkpoints() {
+    let (user_t, admin_t, follows_t) = (
+        Type::new("user").unwrap(),
+        Type::new("admin").unwrap(),
+        Type::new("follows").unwrap(),
+    );
+    let (day1, day2) = (
+        Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap(),
+        Utc.with_ymd_and_hms(2020, 1, 2, 0, 0, 0).unwrap(),
+    );
+    let id = Uuid::from_u128;
+    let vertex_q =
+        |i, name: &str| VertexPropertyQuery::new(SpecificVertexQuery::single(id(i)).into(), name.to_string());
+    let edge_key = |inbound| EdgeKey::new(id(1), follows_t.clone(), id(inbound));
+    let edge_q = |inbound| EdgePropertyQuery::new(SpecificEdgeQuery::single(edge_key(inbound)).into(), "w".to_string());
+
+    let old = SledConfig::default().open(tempdir().unwrap().into_path()).unwrap();
+    let trans = old.transaction().unwrap();
+    for i in 1..=3 {
+        trans.create_vertex(&Vertex::with_id(id(i), user_t.clone())).unwrap();
+    }
+    trans.create_edge_at(&edge_key(2), day1).unwrap();
+    trans.set_vertex_properties(vertex_q(1, "name"), &json!("a")).unwrap();
+    trans.set_vertex_properties(vertex_q(2, "age"), &json!(1)).unwrap();
+    trans.set_edge_properties(edge_q(2), &json!(1)).unwrap();
+
+    let new = SledConfig::default().open(tempdir().unwrap().into_path()).unwrap();
+    let trans = new.transaction().unwrap();
+    trans.create_vertex(&Vertex::with_id(id(1), user_t.clone())).unwrap();
+    trans.create_vertex(&Vertex::with_id(id(2), admin_t.clone())).unwrap();
+    trans.create_vertex(&Vertex::with_id(id(4), user_t.clone())).unwrap();
+    trans.create_edge_at(&edge_key(2), day2).unwrap();
+    trans.create_edge_at(&edge_key(4), day2).unwrap();
+    trans.set_vertex_properties(vertex_q(1, "name"), &json!("b")).unwrap();
+    trans.set_vertex_properties(vertex_q(4, "name"), &json!("c")).unwrap();
+    trans.set_edge_properties(edge_q(2), &json!(1)).unwrap();
+    trans.set_edge_properties(edge_q(4), &json!(2)).unwrap();
+
+    let changes: Vec<GraphChange> = diff_checkpoints(&old, &new).collect::<Result<_>>().unwrap();
+    assert_eq!(
+        changes,
+        vec![
+            GraphChange::VertexModified {
+                old: Vertex::with_id(id(2), user_t.clone()),
+                new: Vertex::with_id(id(2), admin_t),
+            },
+            GraphChange::VertexDeleted(Vertex::with_id(id(3), user_t.clone())),
+            GraphChange::VertexCreated(Vertex::with_id(id(4), user_t)),
+            GraphChange::EdgeModified {
+                old: Edge::new(edge_key(2), day1),
+                new: Edge::new(edge_key(2), day2),
+            },
+            GraphChange::EdgeCreated(Edge::new(edge_key(4), day2)),
+            GraphChange::VertexPropertyModified {
+                id: id(1),
+                name: "name".to_string(),
+                old: json!("a"),
+                new: json!("b"),
+            },
+            GraphChange::VertexPropertyDeleted {
+                id: id(2),
+                name: "age".to_string(),
+                value: json!(1),
+            },
+            GraphChange::VertexPropertyCreated {
+                id: id(4),
+                name: "name".to_string(),
+                value: json!("c"),
+            },
+            GraphChange::EdgePropertyCreated {
+                key: edge_key(4),
+                name: "w".to_string(),
+                value: json!(2),
+            },
+        ]
+    );
+
+    assert_eq!(diff_checkpoints(&new, &new).count(), 0);
+}
+
+#[test]
+fn should_flush_after_mutations_and_on_an_interval() {
+    let t = Type::new("test_vertex_type").unwrap();
+    let datastore = SledConfig::default()
+        .with_flush_every(3)
+        .open(tempdir().unwrap().into_path())
+        .unwrap();
+    let trans = datastore.transaction().unwrap();
+    let create = |i| {
+        trans
+            .create_vertex(&Vertex::with_id(Uuid::from_u128(i), t.clone()))
+            .unwrap()
+    };
+
+    create(1);
+    create(2);
+    assert!(datastore.last_flush().is_none());
+    create(3);
+    let flushed = datastore.last_flush();
+    assert!(flushed.is_some());
+
+    // The count starts over after each flush.
+    thread::sleep(Duration::from_millis(2));
+    create(4);
+    create(5);
+    assert_eq!(datastore.last_flush(), flushed);
+    create(6);
+    assert!(datastore.last_flush() > flushed);
+
+    let datastore = SledConfig::default()
+        .with_flush_interval(Duration::from_millis(10))
+        .open(tempdir().unwrap().into_path())
+        .unwrap();
+    let trans = datastore.transaction().unwrap();
+    trans
+        .create_vertex(&Vertex::with_id(Uuid::from_u128(1), t.clone()))
+        .unwrap();
+
+    for _ in 0..500 {
+        if datastore.last_flush().is_some() {
+            break;
+        }
+
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    let flushed = datastore.last_flush();
+    assert!(flushed.is_some());
+
+    // It keeps flushing while the datastore is open.
+    for _ in 0..500 {
+        if datastore.last_flush() > flushed {
+            break;
+        }
+
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    assert!(datastore.last_flush() > flushed);
+}
+
+#[test]
+fn should_report_and_shrink_cache_memory() {
+    let t = Type::new("test_vertex_type").unwrap();
+    let uncached = datastore(IteratorStability::Live);
+    uncached
+        .transaction()
+        .unwrap()
+        .get_vertices(RangeVertexQuery::new())
+        .unwrap();
+    assert_eq!(uncached.memory_usage().total_bytes(), 0);
+    uncached.shrink_caches();
+
+    let datastore = SledConfig::default()
+        .with_result_cache(100)
+        .open(tempdir().unwrap().into_path())
+        .unwrap();
+    let trans = datastore.transaction().unwrap();
+
+    for i in 1..=10 {
+        trans
+            .create_vertex(&Vertex::with_id(Uuid::from_u128(i), t.clone()))
+            .unwrap();
+    }
+
+    trans.get_vertices(RangeVertexQuery::new().limit(1)).unwrap();
+    let small = datastore.memory_usage();
+    trans.get_vertices(RangeVertexQuery::new().limit(10)).unwrap();
+    let large = datastore.memory_usage();
+    assert_eq!((small.result_cache_entries, large.result_cache_entries), (1, 2));
+    assert!(small.total_bytes() > 0);
+    assert!(large.total_bytes() > 2 * small.total_bytes());
+
+    datastore.shrink_caches();
+    assert_eq!(datastore.memory_usage().result_cache_entries, 0);
+    assert_eq!(datastore.memory_usage().total_bytes(), 0);
+
+    // The cache refills as queries are run.
+    trans.get_vertices(RangeVertexQuery::new().limit(1)).unwrap();
+    assert_eq!(datastore.memory_usage().total_bytes(), small.total_bytes());
+
+    // Writes count until they're committed, or discarded.
+    let mut batch = trans.begin_batch();
+    batch.create_vertex(&Vertex::with_id(Uuid::from_u128(11), t.clone()));
+    let one = datastore.memory_usage().pending_write_bytes;
+    assert!(one > 0);
+    batch.set_vertex_property(Uuid::from_u128(11), "name", &json!("eleven"));
+    assert!(datastore.memory_usage().pending_write_bytes > one);
+    batch.commit().unwrap();
+    assert_eq!(datastore.memory_usage().pending_write_bytes, 0);
+
+    let buffered = datastore.transaction().unwrap().with_write_buffer();
+    buffered
+        .create_vertex(&Vertex::with_id(Uuid::from_u128(12), t.clone()))
+        .unwrap();
+    assert_eq!(datastore.memory_usage().pending_write_bytes, one);
+    buffered.rollback();
+    assert_eq!(datastore.memory_usage().pending_write_bytes, 0);
+    buffered
+        .create_vertex(&Vertex::with_id(Uuid::from_u128(12), t))
+        .unwrap();
+    drop(buffered);
+    assert_eq!(datastore.memory_usage().pending_write_bytes, 0);
+}
+
+#[test]
+fn should_retry_transient_sled_errors() {
+    let policy = RetryPolicy {
+        max_retries: 3,
+        initial_backoff: Duration::from_millis(1),
+        max_backoff: Duration::from_millis(2),
+    };
+    let retrier = Retrier::new(policy);
+    let attempts = &Cell::new(0);
+    let failing_until = |last_failure, kind| {
+        attempts.set(0);
+        move || {
+            attempts.set(attempts.get() + 1);
+
+            if attempts.get() <= last_failure {
+                Err(SledError::Io(IoError::from(kind)))
+            } else {
+                Ok(attempts.get())
+            }
+        }
+    };
+
+    assert_eq!(retrier.run(failing_until(2, ErrorKind::Interrupted)).unwrap(), 3);
+    assert_eq!(retrier.retries(), 2);
+
+    // It gives up after the policy's retries.
+    assert!(retrier.run(failing_until(10, ErrorKind::TimedOut)).is_err());
+    assert_eq!((attempts.get(), retrier.retries()), (4, 5));
+
+    // Other errors aren't expected to go away, so aren't retried.
+    assert!(retrier.run(failing_until(1, ErrorKind::NotFound)).is_err());
+    assert_eq!((attempts.get(), retrier.retries()), (1, 5));
+
+    let retrier = Retrier::new(RetryPolicy::default());
+    assert!(retrier.run(failing_until(1, ErrorKind::Interrupted)).is_err());
+    assert_eq!(retrier.retries(), 0);
+
+    // Datastores run their reads and writes through their policy.
+    let datastore = SledConfig::default()
+        .with_retry_policy(policy)
+        .open(tempdir().unwrap().into_path())
+        .unwrap();
+    let trans = datastore.transaction().unwrap();
+    let vertex = Vertex::with_id(Uuid::from_u128(1), Type::new("test_vertex_type").unwrap());
+    assert!(trans.create_vertex(&vertex).unwrap());
+    assert_eq!(
+        trans.get_vertices(SpecificVertexQuery::single(vertex.id)).unwrap(),
+        vec![vertex]
+    );
+    assert_eq!(datastore.retried_operations(), 0);
+}
+
+#[test]
+fn should_count_properties_without_fetching_them() {
+    let t = Type::new("test_type").unwrap();
+    let datastore = SledConfig::default()
+        .with_value_dedup(1)
+        .open(tempdir().unwrap().into_path())
+        .unwrap();
+    let trans = datastore.transaction().unwrap();
+    let ids: Vec<Uuid> = (1..=3).map(Uuid::from_u128).collect();
+    let vertex_q = |id, name: &str| VertexPropertyQuery::new(SpecificVertexQuery::single(id).into(), name.to_string());
+    let edge_key = |inbound| EdgeKey::new(ids[0], t.clone(), inbound);
+    let edge_q = |inbound, name: &str| {
+        EdgePropertyQuery::new(SpecificEdgeQuery::single(edge_key(inbound)).into(), name.to_string())
+    };
+
+    for id in &ids {
+        trans.create_vertex(&Vertex::with_id(*id, t.clone())).unwrap();
+    }
+
+    for name in &["a", "b", "c"] {
+        trans
+            .set_vertex_properties(vertex_q(ids[0], name), &json!("x"))
+            .unwrap();
+    }
+
+    trans.set_vertex_properties(vertex_q(ids[1], "a"), &json!("x")).unwrap();
+    trans.create_edge(&edge_key(ids[1])).unwrap();
+    trans.create_edge(&edge_key(ids[2])).unwrap();
+    trans.set_edge_properties(edge_q(ids[1], "a"), &json!("x")).unwrap();
+    trans.set_edge_properties(edge_q(ids[1], "b"), &json!(1)).unwrap();
+    trans.set_edge_properties(edge_q(ids[2], "a"), &json!(1)).unwrap();
+
+    // Neighbouring owners' properties aren't counted.
+    assert_eq!(trans.count_vertex_properties(ids[0]).unwrap(), 3);
+    assert_eq!(trans.count_vertex_properties(ids[1]).unwrap(), 1);
+    assert_eq!(trans.count_vertex_properties(ids[2]).unwrap(), 0);
+    assert_eq!(trans.count_edge_properties(&edge_key(ids[1])).unwrap(), 2);
+    assert_eq!(trans.count_edge_properties(&edge_key(ids[2])).unwrap(), 1);
+    assert_eq!(trans.count_edge_properties(&edge_key(ids[0])).unwrap(), 0);
+
+    trans.delete_vertex_properties(vertex_q(ids[0], "b")).unwrap();
+    trans.delete_edges(SpecificEdgeQuery::single(edge_key(ids[1]))).unwrap();
+    assert_eq!(trans.count_vertex_properties(ids[0]).unwrap(), 2);
+    assert_eq!(trans.count_edge_properties(&edge_key(ids[1])).unwrap(), 0);
+}
+
+#[test]
+fn should_catalog_property_names_in_use() {
+    let path = tempdir().unwrap().into_path();
+    let t = Type::new("test_type").unwrap();
+    let ids: Vec<Uuid> = (1..=3).map(Uuid::from_u128).collect();
+    let vertex_q = |id, name: &str| VertexPropertyQuery::new(SpecificVertexQuery::single(id).into(), name.to_string());
+    let usages = |trans: &SledTransaction| {
+        trans
+            .list_property_names()
+            .unwrap()
+            .into_iter()
+            .map(|usage| (usage.name, usage.vertex_count, usage.edge_count))
+            .collect::<Vec<_>>()
+    };
+
+    {
+        let datastore = SledConfig::default().open(&path).unwrap();
+        let trans = datastore.transaction().unwrap();
+        assert!(usages(&trans).is_empty());
+
+        for id in &ids {
+            trans.create_vertex(&Vertex::with_id(*id, t.clone())).unwrap();
+            trans.set_vertex_properties(vertex_q(*id, "name"), &json!("a")).unwrap();
+        }
+
+        // Overwriting a property doesn't count it again.
+        trans
+            .set_vertex_properties(vertex_q(ids[0], "name"), &json!("b"))
+            .unwrap();
+        trans.set_vertex_properties(vertex_q(ids[0], "age"), &json!(1)).unwrap();
+        let key = EdgeKey::new(ids[0], t.clone(), ids[1]);
+        trans.create_edge(&key).unwrap();
+        trans
+            .set_edge_properties(
+                EdgePropertyQuery::new(SpecificEdgeQuery::single(key).into(), "name".to_string()),
+                &json!("c"),
+            )
+            .unwrap();
+
+        assert_eq!(
+            usages(&trans),
+            vec![("age".to_string(), 1, 0), ("name".to_string(), 3, 1)]
+        );
+    }
+
+    let datastore = SledConfig::default().open(&path).unwrap();
+    let trans = datastore.transaction().unwrap();
+    assert_eq!(
+        usages(&trans),
+        vec![("age".to_string(), 1, 0), ("name".to_string(), 3, 1)]
+    );
+
+    // Deleting a vertex takes its properties, and its edges', with it, and
+    // names that are no longer used are dropped.
+    trans.delete_vertices(SpecificVertexQuery::single(ids[0])).unwrap();
+    assert_eq!(usages(&trans), vec![("name".to_string(), 2, 0)]);
+    trans.delete_vertex_properties(vertex_q(ids[1], "name")).unwrap();
+    assert_eq!(usages(&trans), vec![("name".to_string(), 1, 0)]);
+}
+
+#[test]
+fn should_catalog_vertex_and_edge_types() {
+    let datastore = datastore(IteratorStability::Live);
+    let trans = datastore.transaction().unwrap();
+    let (user_t, group_t, follows_t, member_t) = (
+        Type::new("user").unwrap(),
+        Type::new("group").unwrap(),
+        Type::new("follows").unwrap(),
+        Type::new("member").unwrap(),
+    );
+    let ids: Vec<Uuid> = (1..=4).map(Uuid::from_u128).collect();
+
+    for id in &ids[..3] {
+        trans.create_vertex(&Vertex::with_id(*id, user_t.clone())).unwrap();
+    }
+
+    trans.create_vertex(&Vertex::with_id(ids[3], group_t.clone())).unwrap();
+    // Vertices and edges that already exist aren't counted again.
+    assert!(!trans.create_vertex(&Vertex::with_id(ids[0], user_t.clone())).unwrap());
+    trans
+        .create_edge(&EdgeKey::new(ids[0], follows_t.clone(), ids[1]))
+        .unwrap();
+    trans
+        .create_edge(&EdgeKey::new(ids[0], follows_t.clone(), ids[1]))
+        .unwrap();
+    trans
+        .create_edge(&EdgeKey::new(ids[1], follows_t.clone(), ids[2]))
+        .unwrap();
+    trans
+        .create_edge(&EdgeKey::new(ids[0], member_t.clone(), ids[3]))
+        .unwrap();
+
+    assert_eq!(
+        trans.list_vertex_types().unwrap(),
+        vec![(group_t.clone(), 1), (user_t.clone(), 3)]
+    );
+    assert_eq!(
+        trans.list_edge_types().unwrap(),
+        vec![(follows_t.clone(), 2), (member_t.clone(), 1)]
+    );
+
+    // Deleting a vertex takes its edges out of the catalog too, and types
+    // with none left are dropped.
+    trans.delete_vertices(SpecificVertexQuery::single(ids[0])).unwrap();
+    assert_eq!(trans.list_vertex_types().unwrap(), vec![(group_t, 1), (user_t, 2)]);
+    assert_eq!(trans.list_edge_types().unwrap(), vec![(follows_t, 1)]);
+}
+
+#[test]
+fn should_get_inbound_edge_properties_from_the_inbound_index() {
+    let datastore = datastore(IteratorStability::Live);
+    let trans = datastore.transaction().unwrap();
+    let t = Type::new("test_type").unwrap();
+    let ids: Vec<Uuid> = (1..=4).map(Uuid::from_u128).collect();
+    let target = ids[3];
+    let key = |outbound| EdgeKey::new(outbound, t.clone(), target);
+    let edge_q =
+        |key: EdgeKey, name: &str| EdgePropertyQuery::new(SpecificEdgeQuery::single(key).into(), name.to_string());
+
+    for id in &ids {
+        trans.create_vertex(&Vertex::with_id(*id, t.clone())).unwrap();
+    }
+
+    for outbound in &ids[..3] {
+        trans.create_edge(&key(*outbound)).unwrap();
+    }
+
+    trans.set_edge_properties(edge_q(key(ids[0]), "a"), &json!(1)).unwrap();
+    trans.set_edge_properties(edge_q(key(ids[0]), "b"), &json!(2)).unwrap();
+    trans.set_edge_properties(edge_q(key(ids[1]), "a"), &json!(3)).unwrap();
+    // Outbound edges of the vertex, and inbound edges of others, aren't
+    // included.
+    let outbound_key = EdgeKey::new(target, t.clone(), ids[0]);
+    trans.create_edge(&outbound_key).unwrap();
+    trans.set_edge_properties(edge_q(outbound_key, "a"), &json!(4)).unwrap();
+
+    let inbound = |trans: &SledTransaction| {
+        trans
+            .get_inbound_edge_properties(target)
+            .unwrap()
+            .into_iter()
+            .map(|props| {
+                let values: Vec<(String, JsonValue)> =
+                    props.props.into_iter().map(|prop| (prop.name, prop.value)).collect();
+                (props.edge.key.outbound_id, values)
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        inbound(&trans),
+        vec![
+            (ids[0], vec![("a".to_string(), json!(1)), ("b".to_string(), json!(2))]),
+            (ids[1], vec![("a".to_string(), json!(3))]),
+        ]
+    );
+
+    // The index follows deletes of properties, edges and vertices.
+    trans.delete_edge_properties(edge_q(key(ids[0]), "b")).unwrap();
+    assert_eq!(
+        inbound(&trans),
+        vec![
+            (ids[0], vec![("a".to_string(), json!(1))]),
+            (ids[1], vec![("a".to_string(), json!(3))]),
+        ]
+    );
+    trans.delete_edges(SpecificEdgeQuery::single(key(ids[1]))).unwrap();
+    trans.delete_vertices(SpecificVertexQuery::single(ids[0])).unwrap();
+    assert!(inbound(&trans).is_empty());
+}
+
+#[test]
+fn should_get_all_edge_properties_for_a_vertex_in_one_call() {
+    let (t, friends_t) = (Type::new("test_type").unwrap(), Type::new("friends").unwrap());
+    let datastore = SledConfig::default()
+        .with_undirected_edge_type(friends_t.clone())
+        .open(tempdir().unwrap().into_path())
+        .unwrap();
+    let trans = datastore.transaction().unwrap();
+    let ids: Vec<Uuid> = (1..=4).map(Uuid::from_u128).collect();
+    let day = |d| Utc.with_ymd_and_hms(2020, 1, d, 0, 0, 0).unwrap();
+    let edge_q = |key: &EdgeKey, name: &str| {
+        EdgePropertyQuery::new(SpecificEdgeQuery::single(key.clone()).into(), name.to_string())
+    };
+
+    for id in &ids {
+        trans.create_vertex(&Vertex::with_id(*id, t.clone())).unwrap();
+    }
+
+    let with_props = EdgeKey::new(ids[1], t.clone(), ids[2]);
+    let without_props = EdgeKey::new(ids[1], t.clone(), ids[3]);
+    let inbound = EdgeKey::new(ids[0], t.clone(), ids[1]);
+    trans.create_edge_at(&with_props, day(1)).unwrap();
+    trans.create_edge_at(&without_props, day(2)).unwrap();
+    trans.create_edge_at(&inbound, day(3)).unwrap();
+    trans.set_edge_properties(edge_q(&with_props, "x"), &json!(1)).unwrap();
+    trans.set_edge_properties(edge_q(&with_props, "y"), &json!(2)).unwrap();
+    trans.set_edge_properties(edge_q(&inbound, "z"), &json!(3)).unwrap();
+
+    // The undirected edge is stored from the lower ID, but found, with its
+    // properties, from either side.
+    let friends = EdgeKey::new(ids[1], friends_t.clone(), ids[0]);
+    trans.create_edge_at(&friends, day(4)).unwrap();
+    trans
+        .set_edge_properties(edge_q(&friends, "since"), &json!(2020))
+        .unwrap();
+
+    let all = |direction| {
+        trans
+            .get_all_edge_properties_for_vertex(ids[1], direction)
+            .unwrap()
+            .into_iter()
+            .map(|props| {
+                let values: Vec<(String, JsonValue)> =
+                    props.props.into_iter().map(|prop| (prop.name, prop.value)).collect();
+                (props.edge.key, values)
+            })
+            .collect::<Vec<_>>()
+    };
+    let props = |values: &[(&str, JsonValue)]| {
+        values
+            .iter()
+            .map(|&(name, ref value)| (name.to_string(), value.clone()))
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        all(EdgeDirection::Outbound),
+        vec![
+            (friends.clone(), props(&[("since", json!(2020))])),
+            // Edges of a type are newest first.
+            (without_props, Vec::new()),
+            (with_props.clone(), props(&[("x", json!(1)), ("y", json!(2))])),
+        ]
+    );
+    assert_eq!(
+        all(EdgeDirection::Inbound),
+        vec![
+            (
+                EdgeKey::new(ids[0], friends_t, ids[1]),
+                props(&[("since", json!(2020))])
+            ),
+            (inbound, props(&[("z", json!(3))])),
+        ]
+    );
+
+    // The results match those of looking up each edge's properties.
+    for props in trans
+        .get_all_edge_properties_for_vertex(ids[1], EdgeDirection::Outbound)
+        .unwrap()
+    {
+        let one_by_one = trans
+            .get_all_edge_properties(SpecificEdgeQuery::single(props.edge.key.clone()))
+            .unwrap();
+        assert_eq!(one_by_one[0].props, props.props);
+    }
+}
+
+#[test]
+fn should_get_many_edges_by_key_in_order() {
+    let datastore = datastore(IteratorStability::Live);
+    let trans = datastore.transaction().unwrap();
+    let t = Type::new("test_type").unwrap();
+    let ids: Vec<Uuid> = (1..=3).map(Uuid::from_u128).collect();
+    let day = |d| Utc.with_ymd_and_hms(2020, 1, d, 0, 0, 0).unwrap();
+
+    for id in &ids {
+        trans.create_vertex(&Vertex::with_id(*id, t.clone())).unwrap();
+    }
+
+    let first = EdgeKey::new(ids[0], t.clone(), ids[1]);
+    let second = EdgeKey::new(ids[1], t.clone(), ids[2]);
+    let missing = EdgeKey::new(ids[2], t.clone(), ids[0]);
+    trans.create_edge_at(&first, day(1)).unwrap();
+    trans.create_edge_at(&second, day(2)).unwrap();
+
+    // Missing edges keep their position, and repeated keys are returned
+    // each time they're asked for.
+    let edges = trans
+        .multi_get_edges(&[second.clone(), missing, first.clone(), second.clone()])
+        .unwrap();
+    assert_eq!(
+        edges,
+        vec![
+            Some(Edge::new(second.clone(), day(2))),
+            None,
+            Some(Edge::new(first, day(1))),
+            Some(Edge::new(second, day(2))),
+        ]
+    );
+    assert_eq!(trans.multi_get_edges(&[]).unwrap(), Vec::new());
+}
+
+#[test]
+fn should_create_many_edges_at_once() {
+    let datastore = datastore(IteratorStability::Live);
+    let trans = datastore.transaction().unwrap();
+    let t = Type::new("test_type").unwrap();
+    let ids: Vec<Uuid> = (1..=4).map(Uuid::from_u128).collect();
+    let missing_id = Uuid::from_u128(5);
+    let at = Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap();
+
+    for id in &ids[..3] {
+        trans.create_vertex(&Vertex::with_id(*id, t.clone())).unwrap();
+    }
+
+    let keys = vec![
+        EdgeKey::new(ids[0], t.clone(), ids[1]),
+        EdgeKey::new(ids[0], t.clone(), missing_id),
+        EdgeKey::new(ids[0], t.clone(), ids[2]),
+        EdgeKey::new(missing_id, t.clone(), ids[1]),
+        EdgeKey::new(ids[1], t.clone(), ids[2]),
+    ];
+    assert_eq!(
+        trans.create_edges_at(&keys, at).unwrap(),
+        vec![true, false, true, false, true]
+    );
+
+    // Only the edges between existing vertices were written, all with the
+    // same update datetime.
+    let edges = trans
+        .multi_get_edges(&keys)
+        .unwrap()
+        .into_iter()
+        .map(|edge| edge.map(|edge| edge.created_datetime))
+        .collect::<Vec<_>>();
+    assert_eq!(edges, vec![Some(at), None, Some(at), None, Some(at)]);
+    assert_eq!(
+        trans.get_edge_count(ids[0], Some(&t), EdgeDirection::Outbound).unwrap(),
+        2
+    );
+
+    // Without a datetime, the edges are given the current time, and existing
+    // edges are refreshed.
+    let before = Utc::now();
+    assert_eq!(
+        trans.create_edges(&[keys[0].clone(), keys[1].clone()]).unwrap(),
+        vec![true, false]
+    );
+    let edge = trans.multi_get_edges(&keys[..1]).unwrap().remove(0).unwrap();
+    assert!(edge.created_datetime >= before);
+    assert_eq!(
+        trans.get_edge_count(ids[0], Some(&t), EdgeDirection::Outbound).unwrap(),
+        2
+    );
+}
+
+#[test]
+fn should_filter_edges_by_type_time_window_and_limit() {
+    let datastore = datastore(IteratorStability::Live);
+    let trans = datastore.transaction().unwrap();
+    let (a, b) = (Type::new("a").unwrap(), Type::new("b").unwrap());
+    let ids: Vec<Uuid> = (1..=5).map(Uuid::from_u128).collect();
+    let day = |d| Utc.with_ymd_and_hms(2020, 1, d, 0, 0, 0).unwrap();
+
+    for id in &ids {
+        trans.create_vertex(&Vertex::with_id(*id, a.clone())).unwrap();
+    }
+
+    for (i, id) in ids[1..].iter().enumerate() {
+        trans
+            .create_edge_at(&EdgeKey::new(ids[0], a.clone(), *id), day(i as u32 + 1))
+            .unwrap();
+        trans
+            .create_edge_at(&EdgeKey::new(ids[0], b.clone(), *id), day(i as u32 + 1))
+            .unwrap();
+    }
+
+    let get = |q: PipeEdgeQuery| {
+        trans
+            .get_edges(q)
+            .unwrap()
+            .into_iter()
+            .map(|edge| (edge.key.inbound_id, edge.key.t, edge.created_datetime))
+            .collect::<Vec<_>>()
+    };
+    let outbound = || SpecificVertexQuery::single(ids[0]).outbound();
+
+    // Both ends of the window are inclusive, and only edges of the type
+    // are returned, newest first.
+    assert_eq!(
+        get(outbound().t(a.clone()).low(day(2)).high(day(3))),
+        vec![(ids[3], a.clone(), day(3)), (ids[2], a.clone(), day(2))]
+    );
+    assert_eq!(
+        get(outbound().t(a.clone()).low(day(2)).high(day(3)).limit(1)),
+        vec![(ids[3], a.clone(), day(3))]
+    );
+
+    // Without a type, the window applies to the edges of every type.
+    assert_eq!(
+        get(outbound().low(day(4))),
+        vec![(ids[4], a.clone(), day(4)), (ids[4], b.clone(), day(4))]
+    );
+    assert_eq!(get(outbound().t(b.clone()).high(day(1)).limit(5)).len(), 1);
+
+    // The limit applies across all the vertices a query starts from.
+    let inbound = SpecificVertexQuery::new(ids[1..].to_vec())
+        .inbound()
+        .t(a.clone())
+        .low(day(2))
+        .limit(2);
+    let edges = trans.get_edges(inbound).unwrap();
+    assert_eq!(edges.len(), 2);
+    assert!(edges
+        .iter()
+        .all(|edge| edge.key.t == a && edge.created_datetime >= day(2)));
+}
+
+#[test]
+fn should_report_how_the_datastore_was_recovered() {
+    let path = tempdir().unwrap().into_path();
+    let t = Type::new("test_type").unwrap();
+    let key = EdgeKey::new(Uuid::from_u128(1), t.clone(), Uuid::from_u128(2));
+
+    {
+        let datastore = SledConfig::default().open(&path).unwrap();
+        let info = datastore.recovery_info();
+        assert!(!info.recovered);
+        assert!(!info.unclean_shutdown);
+        assert_eq!(info.consistency, None);
+
+        let trans = datastore.transaction().unwrap();
+        trans
+            .create_vertex(&Vertex::with_id(key.outbound_id, t.clone()))
+            .unwrap();
+        trans.create_vertex(&Vertex::with_id(key.inbound_id, t)).unwrap();
+        trans.create_edge(&key).unwrap();
+    }
+
+    // A cleanly closed datastore is recovered from disk, but without
+    // running the check.
+    {
+        let datastore = SledConfig::default().with_recovery_check().open(&path).unwrap();
+        let info = datastore.recovery_info();
+        assert!(info.recovered);
+        assert!(!info.unclean_shutdown);
+        assert_eq!(info.consistency, None);
+    }
+
+    // Leave the open marker behind, as a process that crashed would.
+    {
+        let db = sled::open(&path).unwrap();
+        let metadata = db.open_tree("metadata").unwrap();
+        metadata.insert(OPEN_MARKER_KEY, &[]).unwrap();
+        db.flush().unwrap();
+    }
+    wait_until_unlocked(&path, Duration::from_secs(1));
+
+    let datastore = SledConfig::default().with_recovery_check().open(&path).unwrap();
+    let info = datastore.recovery_info();
+    assert!(info.recovered);
+    assert!(info.unclean_shutdown);
+    assert!(info.salvage_actions.is_empty());
+    let consistency = info.consistency.as_ref().unwrap();
+    assert_eq!(consistency.edges_checked, 1);
+    assert_eq!(consistency.dangling_edges, 0);
+    assert_eq!(consistency.missing_edge_ranges, 0);
+}
+
+#[test]
+fn should_estimate_graph_statistics_from_a_sample() {
+    let datastore = datastore(IteratorStability::Live);
+    let trans = datastore.transaction().unwrap();
+    let t = Type::new("test_type").unwrap();
+    // Samples are taken at evenly spaced points of the key space, so evenly
+    // spread IDs are all sampled when the sample is big enough.
+    let ids: Vec<Uuid> = (0..5).map(|i| Uuid::from_u128(i * (u128::MAX / 5))).collect();
+
+    assert_eq!(trans.estimate_statistics(10).unwrap(), GraphStatistics::default());
+
+    for id in &ids {
+        trans.create_vertex(&Vertex::with_id(*id, t.clone())).unwrap();
+    }
+
+    // Each vertex has one outbound edge, and the first has three inbound.
+    for (outbound, inbound) in &[(0, 1), (1, 2), (2, 0), (3, 0), (4, 0)] {
+        trans
+            .create_edge(&EdgeKey::new(ids[*outbound], t.clone(), ids[*inbound]))
+            .unwrap();
+    }
+
+    for name in &["a", "b"] {
+        let q = VertexPropertyQuery::new(SpecificVertexQuery::single(ids[0]).into(), name.to_string());
+        trans.set_vertex_properties(q, &json!(true)).unwrap();
+    }
+
+    let q = VertexPropertyQuery::new(SpecificVertexQuery::single(ids[1]).into(), "a".to_string());
+    trans.set_vertex_properties(q, &json!(true)).unwrap();
+    let key = EdgeKey::new(ids[1], t.clone(), ids[2]);
+    let q = EdgePropertyQuery::new(SpecificEdgeQuery::single(key).into(), "a".to_string());
+    trans.set_edge_properties(q, &json!(true)).unwrap();
+
+    // A sample that covers the whole graph gives exact statistics.
+    assert_eq!(
+        trans.estimate_statistics(10).unwrap(),
+        GraphStatistics {
+            sampled_vertices: 5,
+            sampled_edges: 5,
+            average_outbound_degree: 1.0,
+            average_inbound_degree: 1.0,
+            outbound_degree_percentiles: DegreePercentiles {
+                p50: 1,
+                p90: 1,
+                p99: 1,
+                max: 1,
+            },
+            inbound_degree_percentiles: DegreePercentiles {
+                p50: 1,
+                p90: 3,
+                p99: 3,
+                max: 3,
+            },
+            vertex_property_density: 0.6,
+            edge_property_density: 0.2,
+        }
+    );
+
+    let stats = trans.estimate_statistics(2).unwrap();
+    assert!(stats.sampled_vertices >= 1 && stats.sampled_vertices <= 2);
+    assert!(stats.sampled_edges >= 1 && stats.sampled_edges <= 2);
+}
+
+#[test]
+fn should_derive_vertex_properties_from_other_properties() {
+    let (person, place) = (Type::new("person").unwrap(), Type::new("place").unwrap());
+    let datastore = SledConfig::default()
+        .with_derived_property(person.clone(), "lower_name", |properties| {
+            // Derived properties aren't passed to `derive`.
+            assert!(properties.iter().all(|prop| prop.name != "lower_name"));
+            let name = properties.iter().find(|prop| prop.name == "name")?;
+            Some(json!(name.value.as_str()?.to_lowercase()))
+        })
+        .open(tempdir().unwrap().into_path())
+        .unwrap();
+    let trans = datastore.transaction().unwrap();
+    let (person_id, place_id) = (Uuid::from_u128(1), Uuid::from_u128(2));
+    trans.create_vertex(&Vertex::with_id(person_id, person)).unwrap();
+    trans.create_vertex(&Vertex::with_id(place_id, place)).unwrap();
+
+    let q = |id, name: &str| VertexPropertyQuery::new(SpecificVertexQuery::single(id).into(), name.to_string());
+    let get = |id, name| {
+        trans
+            .get_vertex_properties(q(id, name))
+            .unwrap()
+            .into_iter()
+            .map(|prop| prop.value)
+            .next()
+    };
+
+    trans
+        .set_vertex_properties(q(person_id, "name"), &json!("Ada"))
+        .unwrap();
+    assert_eq!(get(person_id, "lower_name"), Some(json!("ada")));
+    trans.set_vertex_properties(q(person_id, "age"), &json!(36)).unwrap();
+    trans
+        .set_vertex_properties(q(person_id, "name"), &json!("ADA L"))
+        .unwrap();
+    assert_eq!(get(person_id, "lower_name"), Some(json!("ada l")));
+
+    // Returning `None` deletes the derived property.
+    trans.delete_vertex_properties(q(person_id, "name")).unwrap();
+    assert_eq!(get(person_id, "lower_name"), None);
+    assert_eq!(get(person_id, "age"), Some(json!(36)));
+
+    // Only vertices of the type get the property.
+    trans
+        .set_vertex_properties(q(place_id, "name"), &json!("Paris"))
+        .unwrap();
+    assert_eq!(get(place_id, "lower_name"), None);
+}
+
+#[test]
+fn should_apply_batched_writes_when_committed() {
+    let datastore = datastore(IteratorStability::Live);
+    let trans = datastore.transaction().unwrap();
+    let (t, other_t) = (Type::new("test_type").unwrap(), Type::new("other_type").unwrap());
+    let ids: Vec<Uuid> = (1..=3).map(Uuid::from_u128).collect();
+    trans.create_vertex(&Vertex::with_id(ids[0], t.clone())).unwrap();
+
+    let key = EdgeKey::new(ids[0], t.clone(), ids[1]);
+    let dangling = EdgeKey::new(ids[0], t.clone(), ids[2]);
+    let mut batch = trans.begin_batch();
+    assert!(batch.is_empty());
+    batch
+        .create_vertex(&Vertex::with_id(ids[0], other_t))
+        .create_vertex(&Vertex::with_id(ids[1], t.clone()))
+        .create_edge(&key)
+        .create_edge(&dangling)
+        .set_vertex_property(ids[1], "name", &json!("first"))
+        .set_vertex_property(ids[1], "name", &json!("second"))
+        .set_vertex_property(ids[2], "name", &json!("missing"))
+        .set_edge_property(&key, "weight", &json!(1));
+    assert!(!batch.is_empty());
+
+    // Nothing is written until the batch is committed.
+    let vertices = |ids: Vec<Uuid>| trans.get_vertices(SpecificVertexQuery::new(ids)).unwrap();
+    assert_eq!(vertices(ids.clone()).len(), 1);
+    batch.commit().unwrap();
+
+    // Existing vertices are left alone, and edges can depend on vertices
+    // created in the same batch.
+    assert_eq!(
+        vertices(ids.clone()),
+        vec![Vertex::with_id(ids[0], t.clone()), Vertex::with_id(ids[1], t)]
+    );
+    assert_eq!(trans.multi_get_edges(&[key.clone(), dangling]).unwrap()[1], None);
+
+    let vertex_q = |id| VertexPropertyQuery::new(SpecificVertexQuery::single(id).into(), "name".to_string());
+    let values = |q| {
+        trans
+            .get_vertex_properties(q)
+            .unwrap()
+            .into_iter()
+            .map(|prop| prop.value)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(values(vertex_q(ids[1])), vec![json!("second")]);
+    assert_eq!(values(vertex_q(ids[2])), Vec::<JsonValue>::new());
+
+    let edge_q = EdgePropertyQuery::new(SpecificEdgeQuery::single(key).into(), "weight".to_string());
+    let edge_props = trans.get_edge_properties(edge_q).unwrap();
+    assert_eq!(edge_props.len(), 1);
+    assert_eq!(edge_props[0].value, json!(1));
+}
+
+#[test]
+fn should_archive_and_restore_cold_vertices() {
+    let datastore = datastore(IteratorStability::Live);
+    let trans = datastore.transaction().unwrap();
+    let t = Type::new("test_type").unwrap();
+    let ids: Vec<Uuid> = (1..=4).map(Uuid::from_u128).collect();
+
+    for id in &ids {
+        trans.create_vertex(&Vertex::with_id(*id, t.clone())).unwrap();
+    }
+
+    // The first two vertices are only connected by an old edge, while the
+    // other two have an edge updated after the cutoff.
+    let cold = EdgeKey::new(ids[0], t.clone(), ids[1]);
+    let warm = EdgeKey::new(ids[2], t.clone(), ids[3]);
+    trans
+        .create_edge_at(&cold, Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap())
+        .unwrap();
+    trans
+        .create_edge_at(&warm, Utc.with_ymd_and_hms(2030, 1, 1, 0, 0, 0).unwrap())
+        .unwrap();
+    let vertex_q = VertexPropertyQuery::new(SpecificVertexQuery::single(ids[0]).into(), "name".to_string());
+    trans.set_vertex_properties(vertex_q.clone(), &json!("a")).unwrap();
+    let edge_q = EdgePropertyQuery::new(SpecificEdgeQuery::single(cold.clone()).into(), "weight".to_string());
+    trans.set_edge_properties(edge_q.clone(), &json!(1)).unwrap();
+
+    assert_eq!(
+        datastore
+            .archive_cold_vertices(Utc::now() + ChronoDuration::hours(1))
+            .unwrap(),
+        2
+    );
+
+    let visible = || {
+        trans
+            .get_vertices(SpecificVertexQuery::new(ids.clone()))
+            .unwrap()
+            .into_iter()
+            .map(|vertex| vertex.id)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(visible(), vec![ids[2], ids[3]]);
+    assert!(datastore.is_archived(ids[0]).unwrap());
+    assert!(!datastore.is_archived(ids[2]).unwrap());
+    assert_eq!(
+        trans.get_edges(SpecificEdgeQuery::single(cold.clone())).unwrap(),
+        Vec::new()
+    );
+    assert_eq!(trans.get_edges(SpecificEdgeQuery::single(warm)).unwrap().len(), 1);
+
+    // The vertex comes back with its properties, but its edge waits for
+    // the other vertex to be restored.
+    assert!(datastore.unarchive(ids[0]).unwrap());
+    assert!(!datastore.unarchive(ids[0]).unwrap());
+    assert!(!datastore.is_archived(ids[0]).unwrap());
+    assert_eq!(trans.get_vertex_properties(vertex_q).unwrap()[0].value, json!("a"));
+    assert_eq!(
+        trans.get_edges(SpecificEdgeQuery::single(cold.clone())).unwrap(),
+        Vec::new()
+    );
+
+    assert!(datastore.unarchive(ids[1]).unwrap());
+    assert_eq!(visible(), ids);
+    assert_eq!(trans.get_edges(SpecificEdgeQuery::single(cold)).unwrap().len(), 1);
+    assert_eq!(trans.get_edge_properties(edge_q).unwrap()[0].value, json!(1));
+}
+
+#[test]
+fn should_explain_queries_without_running_them() {
+    let datastore = datastore(IteratorStability::Live);
+    let trans = datastore.transaction().unwrap();
+    let (a, b, follows) = (
+        Type::new("a").unwrap(),
+        Type::new("b").unwrap(),
+        Type::new("follows").unwrap(),
+    );
+    let ids: Vec<Uuid> = (1..=5).map(Uuid::from_u128).collect();
+
+    for id in &ids[..4] {
+        trans.create_vertex(&Vertex::with_id(*id, a.clone())).unwrap();
+    }
+
+    trans.create_vertex(&Vertex::with_id(ids[4], b)).unwrap();
+
+    for &(outbound, inbound) in &[(0, 1), (0, 2), (0, 3), (1, 2)] {
+        trans
+            .create_edge(&EdgeKey::new(ids[outbound], follows.clone(), ids[inbound]))
+            .unwrap();
+    }
+
+    for id in &ids[..2] {
+        let q = VertexPropertyQuery::new(SpecificVertexQuery::single(*id).into(), "name".to_string());
+        trans.set_vertex_properties(q, &json!("x")).unwrap();
+    }
+
+    // Matches of a type are assumed to be spread evenly through the tree.
+    let plan = trans
+        .explain(VertexQuery::from(RangeVertexQuery::new().t(a).limit(2)))
+        .unwrap();
+    assert_eq!(
+        plan.steps,
+        vec![PlanStep {
+            tree: "__sled__default".to_string(),
+            access: Access::Scan { start: vec![0; 16] },
+            uses_index: false,
+            filtered: true,
+            estimated_reads: 3,
+            estimated_items: 2,
+        }]
+    );
+    assert_eq!(plan.estimated_items, 2);
+
+    // Edge queries scan the edge ranges of each input vertex, narrowed to
+    // the type and time range.
+    let q = SpecificVertexQuery::new(ids[..2].to_vec())
+        .outbound()
+        .t(follows.clone());
+    let plan = trans.explain(EdgeQuery::from(q.clone().high(Utc::now()))).unwrap();
+    assert_eq!(plan.steps.len(), 2);
+    assert_eq!(plan.steps[0].access, Access::PointLookups);
+    assert_eq!(plan.steps[1].tree, "edge_ranges");
+    assert_eq!(
+        plan.steps[1].access,
+        Access::PrefixScans {
+            t: Some(follows.clone()),
+            seeks_time_range: true,
+        }
+    );
+    assert!(plan.steps[1].uses_index);
+    assert_eq!(plan.estimated_reads(), 4);
+    assert_eq!(
+        trans.explain(EdgeQuery::from(q)).unwrap().steps[1].access,
+        Access::PrefixScans {
+            t: Some(follows),
+            seeks_time_range: false,
+        }
+    );
+
+    // Property reads are estimated from how many vertices have the
+    // property, according to the catalog.
+    let plan = trans.explain(RangeVertexQuery::new().property("name")).unwrap();
+    assert_eq!(
+        plan.steps
+            .iter()
+            .map(|step| (step.tree.as_str(), step.estimated_reads, step.estimated_items))
+            .collect::<Vec<_>>(),
+        vec![("__sled__default", 5, 5), ("vertex_properties", 5, 2)]
+    );
+    assert_eq!(plan.estimated_reads(), 10);
+    assert_eq!(
+        plan.to_string(),
+        "1. scan `__sled__default` from 00000000000000000000000000000000: ~5 reads, ~5 items\n\
+         2. point lookups in `vertex_properties`: ~5 reads, ~2 items\n\
+         ~2 results"
+    );
+}
+
+#[test]
+fn should_warm_the_records_of_vertices() {
+    let datastore = datastore(IteratorStability::Live);
+    let trans = datastore.transaction().unwrap();
+    let t = Type::new("test_type").unwrap();
+    let ids: Vec<Uuid> = (1..=3).map(Uuid::from_u128).collect();
+
+    for id in &ids[..2] {
+        trans.create_vertex(&Vertex::with_id(*id, t.clone())).unwrap();
+    }
+
+    let key = EdgeKey::new(ids[0], t.clone(), ids[1]);
+    trans.create_edge(&key).unwrap();
+    let q = VertexPropertyQuery::new(SpecificVertexQuery::single(ids[0]).into(), "name".to_string());
+    trans.set_vertex_properties(q, &json!("a")).unwrap();
+    let q = EdgePropertyQuery::new(SpecificEdgeQuery::single(key).into(), "weight".to_string());
+    trans.set_edge_properties(q, &json!(1)).unwrap();
+
+    // The outbound vertex's record, property, edge, edge range and edge
+    // property, and the edge property's entry in the vertex's index.
+    assert_eq!(datastore.warm(&ids[..1]).unwrap(), 6);
+    // The inbound vertex's record, its reversed edge range and edge
+    // property, and the edge property's entry in the vertex's index.
+    assert_eq!(datastore.warm(&ids[1..2]).unwrap(), 4);
+    assert_eq!(datastore.warm(&ids[2..]).unwrap(), 0);
+    assert_eq!(datastore.warm(&ids).unwrap(), 10);
+
+    assert_eq!(datastore.warm_prefix(..).unwrap(), 10);
+    assert_eq!(datastore.warm_prefix(ids[1]..).unwrap(), 4);
+    assert_eq!(datastore.warm_prefix(..ids[1]).unwrap(), 6);
+    assert_eq!(datastore.warm_prefix(ids[2]..).unwrap(), 0);
+}
+
+#[test]
+fn should_record_edge_write_stats_by_type() {
+    let (a, b) = (Type::new("a").unwrap(), Type::new("b").unwrap());
+    let ids: Vec<Uuid> = (1..=3).map(Uuid::from_u128).collect();
+    let open = |config: SledConfig| {
+        let datastore = config.open(tempdir().unwrap().into_path()).unwrap();
+        let trans = datastore.transaction().unwrap();
+
+        for id in &ids {
+            trans.create_vertex(&Vertex::with_id(*id, a.clone())).unwrap();
+        }
+
+        datastore
+    };
+    let writes = |datastore: &SledDatastore| {
+        datastore
+            .edge_write_stats()
+            .unwrap()
+            .into_iter()
+            .map(|stats| (stats.t, stats.writes, stats.bytes))
+            .collect::<Vec<_>>()
+    };
+
+    let datastore = open(SledConfig::default());
+    let trans = datastore.transaction().unwrap();
+    trans.create_edge(&EdgeKey::new(ids[0], a.clone(), ids[1])).unwrap();
+    assert_eq!(writes(&datastore), Vec::new());
+
+    let datastore = open(SledConfig::default().with_edge_write_stats(1));
+    let trans = datastore.transaction().unwrap();
+    let key = EdgeKey::new(ids[0], a.clone(), ids[1]);
+    trans.create_edge(&key).unwrap();
+    trans.create_edge(&EdgeKey::new(ids[0], a.clone(), ids[2])).unwrap();
+    trans.create_edge(&key).unwrap();
+    let key = EdgeKey::new(ids[1], b.clone(), ids[2]);
+    trans.create_edge(&key).unwrap();
+    trans.delete_edges(SpecificEdgeQuery::single(key)).unwrap();
+
+    // Edge records and range entries of single-letter types take up 42
+    // bytes each. New and deleted edges write a record and two range
+    // entries, and updated ones also remove their two old range entries.
+    assert_eq!(
+        writes(&datastore),
+        vec![(a.clone(), 3, 3 * 42 + 8 * 42), (b.clone(), 2, 2 * 42 + 4 * 42)]
+    );
+
+    datastore.reset_edge_write_stats().unwrap();
+    assert_eq!(writes(&datastore), Vec::new());
+
+    // Sampled writes are counted as many times as the sampling interval.
+    let datastore = open(SledConfig::default().with_edge_write_stats(2));
+    let trans = datastore.transaction().unwrap();
+
+    for &(outbound, inbound) in &[(0, 1), (0, 2), (1, 2), (2, 0)] {
+        trans
+            .create_edge(&EdgeKey::new(ids[outbound], a.clone(), ids[inbound]))
+            .unwrap();
+    }
+
+    assert_eq!(writes(&datastore), vec![(a, 4, 4 * 3 * 42)]);
+}
+
+#[test]
+fn should_summarize_inbound_edges_by_type() {
+    let datastore = datastore(IteratorStability::Live);
+    let trans = datastore.transaction().unwrap();
+    let (likes, follows) = (Type::new("likes").unwrap(), Type::new("follows").unwrap());
+    let ids: Vec<Uuid> = (1..=4).map(Uuid::from_u128).collect();
+    let day = |d| Utc.with_ymd_and_hms(2020, 1, d, 0, 0, 0).unwrap();
+
+    for id in &ids {
+        trans.create_vertex(&Vertex::with_id(*id, likes.clone())).unwrap();
+    }
+
+    assert_eq!(trans.inbound_summary(ids[0]).unwrap(), Vec::new());
+
+    trans
+        .create_edge_at(&EdgeKey::new(ids[1], likes.clone(), ids[0]), day(3))
+        .unwrap();
+    trans
+        .create_edge_at(&EdgeKey::new(ids[2], likes.clone(), ids[0]), day(5))
+        .unwrap();
+    trans
+        .create_edge_at(&EdgeKey::new(ids[3], likes.clone(), ids[0]), day(4))
+        .unwrap();
+    trans
+        .create_edge_at(&EdgeKey::new(ids[1], follows.clone(), ids[0]), day(1))
+        .unwrap();
+    // Outbound edges and edges to other vertices aren't counted.
+    trans
+        .create_edge_at(&EdgeKey::new(ids[0], likes.clone(), ids[1]), day(9))
+        .unwrap();
+    trans
+        .create_edge_at(&EdgeKey::new(ids[2], follows.clone(), ids[1]), day(9))
+        .unwrap();
+
+    // Shorter type names come first.
+    assert_eq!(
+        trans.inbound_summary(ids[0]).unwrap(),
+        vec![
+            InboundTypeSummary {
+                t: likes,
+                count: 3,
+                latest_update_datetime: day(5),
+            },
+            InboundTypeSummary {
+                t: follows,
+                count: 1,
+                latest_update_datetime: day(1),
+            },
+        ]
+    );
+}
+
+#[test]
+fn should_check_which_vertices_exist() {
+    let datastore = datastore(IteratorStability::Live);
+    let trans = datastore.transaction().unwrap();
+    let t = Type::new("test_type").unwrap();
+    let ids: Vec<Uuid> = (1..=4).map(Uuid::from_u128).collect();
+
+    trans.create_vertex(&Vertex::with_id(ids[1], t.clone())).unwrap();
+    trans.create_vertex(&Vertex::with_id(ids[3], t)).unwrap();
+
+    // Results are in the order of the IDs, including repeated ones, even
+    // though they're looked up in sorted order.
+    assert_eq!(
+        trans
+            .filter_existing(&[ids[3], ids[0], ids[1], ids[3], ids[2]])
+            .unwrap(),
+        vec![true, false, true, true, false]
+    );
+    assert_eq!(trans.filter_existing(&[]).unwrap(), Vec::<bool>::new());
+
+    trans.delete_vertices(SpecificVertexQuery::single(ids[1])).unwrap();
+    assert_eq!(trans.filter_existing(&ids).unwrap(), vec![false, false, false, true]);
+}
+
+#[test]
+fn should_share_the_result_cache_between_handles_on_a_partition() {
+    let t = Type::new("test_vertex_type").unwrap();
+    let datastore = SledConfig::default()
+        .with_result_cache(100)
+        .open(tempdir().unwrap().into_path())
+        .unwrap();
+    let reader = datastore.partition(1).unwrap();
+    let writer = datastore.partition(1).unwrap();
+    let q = || RangeVertexQuery::new().limit(10);
+
+    let reader_trans = reader.transaction().unwrap();
+    assert!(reader_trans.get_vertices(q()).unwrap().is_empty());
+    assert_eq!(writer.memory_usage().result_cache_entries, 1);
+
+    writer
+        .transaction()
+        .unwrap()
+        .create_vertex(&Vertex::with_id(Uuid::from_u128(1), t.clone()))
+        .unwrap();
+    assert_eq!(reader_trans.get_vertices(q()).unwrap().len(), 1);
+
+    // Other partitions keep caches of their own.
+    let other = datastore.partition(2).unwrap();
+    assert!(other.transaction().unwrap().get_vertices(q()).unwrap().is_empty());
+    assert_eq!(datastore.transaction().unwrap().get_vertices(q()).unwrap().len(), 0);
+    assert_eq!(reader_trans.get_vertices(q()).unwrap().len(), 1);
+
+    // Dropping the partition invalidates the results cached by its handles,
+    // so they fail to read rather than serve stale results.
+    assert!(datastore.drop_partition(1).unwrap());
+    assert!(reader_trans.get_vertices(q()).is_err());
+}
+
+/// Makes a datastore read-only.
+struct ReadOnly;
+
+impl AccessPolicy for ReadOnly {
+    fn authorize(&self, _tenant: Option<u32>, kind: AccessKind, _operation: &str) -> std::result::Result<(), String> {
+        if kind == AccessKind::Write {
+            Err("datastore is read-only".to_string())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[test]
+fn should_keep_a_sharded_vertex_until_its_foreign_edges_are_deleted() {
+    let t = Type::new("test_edge_type").unwrap();
+    let paths: Vec<_> = (0..2).map(|_| tempdir().unwrap().into_path()).collect();
+    let datastore = ShardedSledDatastore::open(&SledConfig::default(), &paths).unwrap();
+    let id_in = |shard: usize| {
+        (0..)
+            .map(Uuid::from_u128)
+            .find(|&id| datastore.shard_of(id) == shard)
+            .unwrap()
+    };
+    let (target_id, source_id) = (id_in(0), id_in(1));
+    let key = EdgeKey::new(source_id, t.clone(), target_id);
+
+    let trans = datastore.transaction().unwrap();
+    trans.create_vertex(&Vertex::with_id(target_id, t.clone())).unwrap();
+    trans.create_vertex(&Vertex::with_id(source_id, t.clone())).unwrap();
+    assert!(trans.create_edge(&key).unwrap());
+
+    // The edge lives in the source's shard, which refuses to delete it.
+    datastore.shards()[1].set_access_policy(ReadOnly);
+    assert!(trans.delete_vertices(SpecificVertexQuery::single(target_id)).is_err());
+    assert_eq!(
+        trans
+            .get_vertices(SpecificVertexQuery::single(target_id))
+            .unwrap()
+            .len(),
+        1
+    );
+    assert_eq!(
+        trans.get_edges(SpecificEdgeQuery::single(key.clone())).unwrap().len(),
+        1
+    );
+
+    datastore.shards()[1].set_access_policy(ReadOnlyTenant);
+    trans.delete_vertices(SpecificVertexQuery::single(target_id)).unwrap();
+    assert!(trans
+        .get_vertices(SpecificVertexQuery::single(target_id))
+        .unwrap()
+        .is_empty());
+    assert!(trans
+        .get_edges(SpecificEdgeQuery::single(key.clone()))
+        .unwrap()
+        .is_empty());
+    assert!(!trans.create_edge(&key).unwrap());
+}
+
+#[test]
+fn should_write_nothing_for_a_rejected_edge_with_properties() {
+    let person = Type::new("person").unwrap();
+    let friend = Type::new("friend").unwrap();
+    let datastore = SledConfig::default()
+        .with_undirected_edge_type(friend.clone())
+        .with_edge_cardinality(person.clone(), friend.clone(), 1)
+        .with_monotonic_edge_datetimes()
+        .with_value_dedup(8)
+        .open(tempdir().unwrap().into_path())
+        .unwrap();
+    let trans = datastore.transaction().unwrap();
+
+    for i in 1..=3 {
+        trans
+            .create_vertex(&Vertex::with_id(Uuid::from_u128(i), person.clone()))
+            .unwrap();
+    }
+
+    let note = |note: &str| NamedProperty::new("note".to_string(), json!(note));
+    let assert_rejected = |result: Result<bool>, expected: &str| match result {
+        Err(IndraError::Datastore { inner }) => match inner.downcast_ref::<Error>() {
+            Some(&Error::EdgeDatetimeNotMonotonic { .. }) if expected == "monotonic" => (),
+            Some(&Error::CardinalityExceeded { .. }) if expected == "cardinality" => (),
+            _ => panic!("unexpected error: {}", inner),
+        },
+        _ => panic!("expected the edge to be rejected"),
+    };
+
+    // Refreshing the edge would move it back in time, which is only
+    // checked as the edge itself is written.
+    let key = EdgeKey::new(Uuid::from_u128(2), friend.clone(), Uuid::from_u128(1));
+    let future = Utc.with_ymd_and_hms(2030, 1, 1, 0, 0, 0).unwrap();
+    assert!(trans.create_edge_at(&key, future).unwrap());
+    assert_rejected(
+        trans.create_edge_with_properties(&key.reversed(), &[note("met at school")]),
+        "monotonic",
+    );
+    assert_eq!(trans.count_edge_properties(&key).unwrap(), 0);
+    assert_eq!(trans.count_edge_properties(&key.reversed()).unwrap(), 0);
+    assert!(datastore.holder.values.store.is_empty());
+
+    // Undirected edges count against both of their vertices, and vertex 1
+    // already has its one friend.
+    let other = EdgeKey::new(Uuid::from_u128(3), friend, Uuid::from_u128(1));
+    assert_rejected(
+        trans.create_edge_with_properties(&other, &[note("met at work")]),
+        "cardinality",
+    );
+    assert!(trans
+        .get_edges(SpecificEdgeQuery::single(other.clone()))
+        .unwrap()
+        .is_empty());
+    assert_eq!(trans.count_edge_properties(&other).unwrap(), 0);
+    assert!(datastore.holder.values.store.is_empty());
+}
+
+#[test]
+fn should_release_a_unique_value_claim_when_the_write_fails() {
+    let t = Type::new("test_vertex_type").unwrap();
+    let path = tempdir().unwrap().into_path();
+    let datastore = SledConfig::default().open(&path).unwrap();
+    let trans = datastore.transaction().unwrap();
+    let ids: Vec<Uuid> = (1..=2).map(Uuid::from_u128).collect();
+
+    for &id in &ids {
+        trans.create_vertex(&Vertex::with_id(id, t.clone())).unwrap();
+    }
+
+    let q = |id: Uuid| VertexPropertyQuery::new(SpecificVertexQuery::single(id).into(), "email".to_string());
+    trans.set_vertex_properties(q(ids[0]), &json!("a")).unwrap();
+    assert!(datastore.index_unique_property("email").unwrap());
+
+    // A reference to a value that isn't in the value store can't be replaced.
+    let vertex_properties = &datastore.holder.vertex_properties;
+    let (key, _) = vertex_properties.iter().next().unwrap().unwrap();
+    vertex_properties.insert(&key, &[0, 1, 2, 3][..]).unwrap();
+    assert!(trans.set_vertex_properties(q(ids[0]), &json!("b")).is_err());
+
+    trans.set_vertex_properties(q(ids[1]), &json!("b")).unwrap();
+    let mut batch = trans.begin_batch();
+    batch.set_vertex_property(ids[0], "email", &json!("c"));
+    assert!(batch.commit().is_err());
+    trans.set_vertex_properties(q(ids[1]), &json!("c")).unwrap();
+}
+
+#[test]
+fn should_reject_edge_datetimes_outside_the_key_range() {
+    let t = Type::new("test_edge_type").unwrap();
+
+    for &precision in &[DatetimePrecision::Nanos, DatetimePrecision::Seconds] {
+        let datastore = SledConfig::default()
+            .with_datetime_precision(precision)
+            .open(tempdir().unwrap().into_path())
+            .unwrap();
+        let trans = datastore.transaction().unwrap();
+        for id in 1..=2 {
+            trans
+                .create_vertex(&Vertex::with_id(Uuid::from_u128(id), t.clone()))
+                .unwrap();
+        }
+
+        let key = EdgeKey::new(Uuid::from_u128(1), t.clone(), Uuid::from_u128(2));
+        let assert_out_of_range = |result: Result<()>, given: DateTime<Utc>| match result {
+            Err(IndraError::Datastore { inner }) => match inner.downcast_ref::<Error>() {
+                Some(Error::DatetimeOutOfRange { datetime }) if *datetime == given => {}
+                _ => panic!("unexpected error: {}", inner),
+            },
+            result => panic!("unexpected result: {:?}", result),
+        };
+
+        for &datetime in &[
+            Utc.with_ymd_and_hms(1969, 12, 31, 23, 59, 59).unwrap(),
+            Utc.with_ymd_and_hms(2039, 1, 1, 0, 0, 0).unwrap(),
+        ] {
+            assert_out_of_range(trans.create_edge_at(&key, datetime).map(|_| ()), datetime);
+            assert_out_of_range(
+                trans.create_edges_at(slice::from_ref(&key), datetime).map(|_| ()),
+                datetime,
+            );
+        }
+
+        assert!(trans
+            .get_edges(SpecificEdgeQuery::single(key.clone()))
+            .unwrap()
+            .is_empty());
+
+        // Both ends of the range are fine.
+        let epoch = Utc.with_ymd_and_hms(1970, 1, 1, 0, 0, 0).unwrap();
+        assert!(trans.create_edge_at(&key, epoch).unwrap());
+        assert!(
+            trans
+                .create_edges_at(slice::from_ref(&key), *indradb::util::MAX_DATETIME)
+                .unwrap()[0]
+        );
+        assert_eq!(
+            trans
+                .get_edge_count(key.outbound_id, Some(&t), EdgeDirection::Outbound)
+                .unwrap(),
+            1
+        );
+    }
+}
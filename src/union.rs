@@ -0,0 +1,305 @@
+use std::collections::{HashMap, HashSet};
+
+use super::access::AccessKind;
+use super::datastore::{SledDatastore, SledTransaction};
+use super::managers::VertexManager;
+
+use indradb::{
+    Datastore, Edge, EdgeDirection, EdgeKey, EdgeProperties, EdgeProperty, EdgePropertyQuery, EdgeQuery, PipeEdgeQuery,
+    Result, SpecificEdgeQuery, SpecificVertexQuery, Transaction, Type, Vertex, VertexProperties, VertexProperty,
+    VertexPropertyQuery, VertexQuery,
+};
+use uuid::Uuid;
+
+/// A read-only view that merges several sled datastores, e.g. a large
+/// static base graph overlaid with a small delta graph.
+///
+/// Queries are evaluated step by step against the union of all layers, so
+/// each step of a pipe looks up the results of the previous step in every
+/// layer - e.g. piping from vertices found only in the base graph to
+/// properties set only in the delta graph. When the same vertex, edge or
+/// property exists in several layers, the one in the earliest layer wins.
+pub struct UnionDatastore {
+    layers: Vec<SledDatastore>,
+}
+
+impl UnionDatastore {
+    /// Creates a new union view.
+    ///
+    /// # Arguments
+    /// * `layers`: The datastores to merge, in order of precedence.
+    pub fn new(layers: Vec<SledDatastore>) -> UnionDatastore {
+        UnionDatastore { layers }
+    }
+
+    /// The merged datastores, in order of precedence.
+    pub fn layers(&self) -> &[SledDatastore] {
+        &self.layers
+    }
+
+    /// Creates a read transaction spanning all layers.
+    pub fn transaction(&self) -> Result<UnionTransaction> {
+        let layers: Result<Vec<SledTransaction>> = self.layers.iter().map(|layer| layer.transaction()).collect();
+        Ok(UnionTransaction { layers: layers? })
+    }
+}
+
+/// A read transaction over a `UnionDatastore`.
+pub struct UnionTransaction {
+    layers: Vec<SledTransaction>,
+}
+
+impl UnionTransaction {
+    /// Gets the vertices matching a query.
+    pub fn get_vertices<Q: Into<VertexQuery>>(&self, q: Q) -> Result<Vec<Vertex>> {
+        match q.into() {
+            VertexQuery::Range(q) => {
+                // Each layer's first `limit` matches contains the union's
+                // first `limit` matches that live in the layer.
+                let mut vertices = Vec::new();
+                let mut seen = HashSet::new();
+
+                for layer in &self.layers {
+                    for vertex in layer.get_vertices(q.clone())? {
+                        if seen.insert(vertex.id) {
+                            vertices.push(vertex);
+                        }
+                    }
+                }
+
+                vertices.sort_by_key(|vertex| vertex.id);
+                vertices.truncate(q.limit as usize);
+                Ok(vertices)
+            }
+            VertexQuery::Specific(q) => self.get_specific_vertices(q.ids, None, u32::MAX),
+            VertexQuery::Pipe(q) => {
+                let direction = q.direction;
+                let ids = self.get_edges(*q.inner)?.into_iter().map(|edge| match direction {
+                    EdgeDirection::Outbound => edge.key.outbound_id,
+                    EdgeDirection::Inbound => edge.key.inbound_id,
+                });
+
+                self.get_specific_vertices(ids.collect(), q.t.as_ref(), q.limit)
+            }
+        }
+    }
+
+    fn get_specific_vertices(&self, ids: Vec<Uuid>, t: Option<&Type>, limit: u32) -> Result<Vec<Vertex>> {
+        let mut found: HashMap<Uuid, Vertex> = HashMap::new();
+
+        // Iterate in reverse so that earlier layers overwrite later ones.
+        for layer in self.layers.iter().rev() {
+            for vertex in layer.get_vertices(SpecificVertexQuery::new(ids.clone()))? {
+                found.insert(vertex.id, vertex);
+            }
+        }
+
+        let mut vertices = Vec::new();
+
+        for id in ids {
+            if vertices.len() == limit as usize {
+                break;
+            }
+
+            if let Some(vertex) = found.get(&id).cloned() {
+                if t.is_none_or(|t| &vertex.t == t) {
+                    vertices.push(vertex);
+                }
+            }
+        }
+
+        Ok(vertices)
+    }
+
+    /// Gets the number of vertices across all layers.
+    pub fn get_vertex_count(&self) -> Result<u64> {
+        let (first, rest) = match self.layers.split_first() {
+            Some(layers) => layers,
+            None => return Ok(0),
+        };
+        let mut count = first.get_vertex_count()?;
+
+        // Each later layer adds the vertices that no earlier layer has,
+        // which are found by key, without reading the vertices.
+        for (i, layer) in rest.iter().enumerate() {
+            layer.authorize(AccessKind::Read, "get_vertex_count")?;
+            let _guard = layer.read_guard();
+            let earlier: Vec<VertexManager> = self.layers[..=i]
+                .iter()
+                .map(|earlier| VertexManager::new(&earlier.holder))
+                .collect();
+
+            'vertices: for item in VertexManager::new(&layer.holder).iterate_for_range(Uuid::default()) {
+                let (id, _) = item?;
+
+                for manager in &earlier {
+                    if manager.exists(id)? {
+                        continue 'vertices;
+                    }
+                }
+
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Gets the edges matching a query.
+    pub fn get_edges<Q: Into<EdgeQuery>>(&self, q: Q) -> Result<Vec<Edge>> {
+        match q.into() {
+            EdgeQuery::Specific(q) => self.merge_edges(|layer| layer.get_edges(q.clone()), u32::MAX),
+            EdgeQuery::Pipe(q) => {
+                let ids: Vec<Uuid> = self
+                    .get_vertices(*q.inner)?
+                    .into_iter()
+                    .map(|vertex| vertex.id)
+                    .collect();
+
+                let layer_q = PipeEdgeQuery {
+                    inner: Box::new(SpecificVertexQuery::new(ids).into()),
+                    direction: q.direction,
+                    t: q.t,
+                    high: q.high,
+                    low: q.low,
+                    limit: q.limit,
+                };
+
+                self.merge_edges(|layer| layer.get_edges(layer_q.clone()), q.limit)
+            }
+        }
+    }
+
+    fn merge_edges<F>(&self, f: F, limit: u32) -> Result<Vec<Edge>>
+    where
+        F: Fn(&SledTransaction) -> Result<Vec<Edge>>,
+    {
+        let mut edges = Vec::new();
+        let mut seen = HashSet::new();
+
+        for layer in &self.layers {
+            for edge in f(layer)? {
+                if seen.insert(edge.key.clone()) {
+                    edges.push(edge);
+                }
+            }
+        }
+
+        edges.truncate(limit as usize);
+        Ok(edges)
+    }
+
+    /// Gets the number of edges of a vertex across all layers.
+    pub fn get_edge_count(&self, id: Uuid, t: Option<&Type>, direction: EdgeDirection) -> Result<u64> {
+        if self.layers.len() == 1 {
+            return self.layers[0].get_edge_count(id, t, direction);
+        }
+
+        let q = PipeEdgeQuery {
+            inner: Box::new(SpecificVertexQuery::single(id).into()),
+            direction,
+            t: t.cloned(),
+            high: None,
+            low: None,
+            limit: u32::MAX,
+        };
+
+        Ok(self.get_edges(q)?.len() as u64)
+    }
+
+    /// Gets a property of the vertices matching a query.
+    pub fn get_vertex_properties(&self, q: VertexPropertyQuery) -> Result<Vec<VertexProperty>> {
+        let ids: Vec<Uuid> = self
+            .get_vertices(q.inner)?
+            .into_iter()
+            .map(|vertex| vertex.id)
+            .collect();
+        let mut found: HashMap<Uuid, VertexProperty> = HashMap::new();
+
+        for layer in self.layers.iter().rev() {
+            let layer_q = VertexPropertyQuery::new(SpecificVertexQuery::new(ids.clone()).into(), q.name.clone());
+            for property in layer.get_vertex_properties(layer_q)? {
+                found.insert(property.id, property);
+            }
+        }
+
+        Ok(ids.into_iter().filter_map(|id| found.remove(&id)).collect())
+    }
+
+    /// Gets all of the properties of the vertices matching a query.
+    pub fn get_all_vertex_properties<Q: Into<VertexQuery>>(&self, q: Q) -> Result<Vec<VertexProperties>> {
+        let vertices = self.get_vertices(q)?;
+        let ids: Vec<Uuid> = vertices.iter().map(|vertex| vertex.id).collect();
+        let mut found: HashMap<Uuid, VertexProperties> = HashMap::new();
+
+        for layer in self.layers.iter().rev() {
+            for layer_properties in layer.get_all_vertex_properties(SpecificVertexQuery::new(ids.clone()))? {
+                match found.get_mut(&layer_properties.vertex.id) {
+                    Some(properties) => {
+                        for prop in layer_properties.props {
+                            properties.props.retain(|existing| existing.name != prop.name);
+                            properties.props.push(prop);
+                        }
+                    }
+                    None => {
+                        found.insert(layer_properties.vertex.id, layer_properties);
+                    }
+                }
+            }
+        }
+
+        Ok(vertices
+            .into_iter()
+            .map(|vertex| match found.remove(&vertex.id) {
+                Some(properties) => VertexProperties::new(vertex, properties.props),
+                None => VertexProperties::new(vertex, Vec::new()),
+            })
+            .collect())
+    }
+
+    /// Gets a property of the edges matching a query.
+    pub fn get_edge_properties(&self, q: EdgePropertyQuery) -> Result<Vec<EdgeProperty>> {
+        let keys: Vec<EdgeKey> = self.get_edges(q.inner)?.into_iter().map(|edge| edge.key).collect();
+        let mut found: HashMap<EdgeKey, EdgeProperty> = HashMap::new();
+
+        for layer in self.layers.iter().rev() {
+            let layer_q = EdgePropertyQuery::new(SpecificEdgeQuery::new(keys.clone()).into(), q.name.clone());
+            for property in layer.get_edge_properties(layer_q)? {
+                found.insert(property.key.clone(), property);
+            }
+        }
+
+        Ok(keys.into_iter().filter_map(|key| found.remove(&key)).collect())
+    }
+
+    /// Gets all of the properties of the edges matching a query.
+    pub fn get_all_edge_properties<Q: Into<EdgeQuery>>(&self, q: Q) -> Result<Vec<EdgeProperties>> {
+        let edges = self.get_edges(q)?;
+        let keys: Vec<EdgeKey> = edges.iter().map(|edge| edge.key.clone()).collect();
+        let mut found: HashMap<EdgeKey, EdgeProperties> = HashMap::new();
+
+        for layer in self.layers.iter().rev() {
+            for layer_properties in layer.get_all_edge_properties(SpecificEdgeQuery::new(keys.clone()))? {
+                match found.get_mut(&layer_properties.edge.key) {
+                    Some(properties) => {
+                        for prop in layer_properties.props {
+                            properties.props.retain(|existing| existing.name != prop.name);
+                            properties.props.push(prop);
+                        }
+                    }
+                    None => {
+                        found.insert(layer_properties.edge.key.clone(), layer_properties);
+                    }
+                }
+            }
+        }
+
+        Ok(edges
+            .into_iter()
+            .map(|edge| match found.remove(&edge.key) {
+                Some(properties) => EdgeProperties::new(edge, properties.props),
+                None => EdgeProperties::new(edge, Vec::new()),
+            })
+            .collect())
+    }
+}
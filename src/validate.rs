@@ -0,0 +1,63 @@
+use super::datastore::SledHolder;
+use super::errors::Error;
+
+use indradb::{EdgeKey, Result, Vertex};
+use serde_json::Value as JsonValue;
+use uuid::Uuid;
+
+/// A mutation about to be written, as seen by a `WriteValidator`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Mutation<'a> {
+    /// A vertex that doesn't exist yet is being created.
+    CreateVertex(&'a Vertex),
+    /// A vertex is being deleted, along with its edges and properties.
+    DeleteVertex(Uuid),
+    /// An edge is being created, or its update datetime refreshed.
+    CreateEdge(&'a EdgeKey),
+    /// An edge is being deleted, along with its properties.
+    DeleteEdge(&'a EdgeKey),
+    /// A vertex property is being set.
+    SetVertexProperty {
+        id: Uuid,
+        name: &'a str,
+        value: &'a JsonValue,
+    },
+    /// A vertex property is being deleted.
+    DeleteVertexProperty { id: Uuid, name: &'a str },
+    /// An edge property is being set.
+    SetEdgeProperty {
+        key: &'a EdgeKey,
+        name: &'a str,
+        value: &'a JsonValue,
+    },
+    /// An edge property is being deleted.
+    DeleteEdgeProperty { key: &'a EdgeKey, name: &'a str },
+}
+
+/// Inspects mutations before they're written, and can veto them, e.g. to
+/// enforce business rules or security policies. Registered with
+/// `SledDatastore::add_write_validator`.
+///
+/// Validators see the mutations requested through the public API, not the
+/// cascading writes they cause: deleting a vertex is validated as one
+/// `DeleteVertex`, not as the deletion of each of its edges. Writes made
+/// by the datastore itself, such as edge retention, aren't validated.
+pub trait WriteValidator: Send + Sync {
+    /// Checks a mutation, returning the reason it's rejected if it is. The
+    /// rejection is surfaced to the caller as `Error::WriteRejected`.
+    fn validate(&self, mutation: &Mutation) -> std::result::Result<(), String>;
+}
+
+/// Runs a mutation past every registered validator, in the order they were
+/// added.
+pub(crate) fn check(holder: &SledHolder, mutation: &Mutation) -> Result<()> {
+    let validators = holder.validators.read().unwrap();
+
+    for validator in validators.iter() {
+        if let Err(reason) = validator.validate(mutation) {
+            return Err(Error::WriteRejected { reason }.into());
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,335 @@
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+
+use super::datastore::SledHolder;
+use super::deadline::Deadline;
+use super::decode::Decoder;
+use super::dedup;
+use super::errors::{map_err, Error};
+use super::managers::{VertexManager, VertexPropertyManager};
+
+use indradb::{Result, Vertex};
+use serde_json::Value as JsonValue;
+use sled::{Batch, Tree};
+use uuid::Uuid;
+
+/// The number of random hyperplanes vectors are hashed against, and so the
+/// number of bits in their bucket.
+const HASH_BITS: u32 = 8;
+
+/// A vertex property holding vectors of a fixed dimension, and the tree
+/// they're indexed in, keyed by `(bucket, vertex ID)` with the vector as
+/// the value. Registered with `SledConfig::with_vector_index`.
+///
+/// Vectors are bucketed by which side of each of a set of random
+/// hyperplanes they fall on, so vectors pointing in similar directions
+/// tend to share a bucket.
+pub(crate) struct VectorIndex {
+    pub(crate) name: String,
+    pub(crate) dimensions: usize,
+    planes: Vec<Vec<f32>>,
+    pub(crate) tree: Tree,
+}
+
+impl VectorIndex {
+    pub(crate) fn new(name: &str, dimensions: usize, tree: Tree) -> Self {
+        VectorIndex {
+            name: name.to_string(),
+            dimensions,
+            planes: planes(name, dimensions),
+            tree,
+        }
+    }
+
+    fn bucket(&self, vector: &[f32]) -> u8 {
+        self.planes.iter().enumerate().fold(0, |bucket, (i, plane)| {
+            if dot(plane, vector) >= 0.0 {
+                bucket | (1 << i)
+            } else {
+                bucket
+            }
+        })
+    }
+
+    /// The definition recorded in the metadata tree, so that changing it
+    /// rebuilds the index on the next open.
+    fn definition(&self) -> String {
+        format!("dimensions={},hash_bits={}", self.dimensions, HASH_BITS)
+    }
+}
+
+/// Generates the hyperplanes of an index. They're derived from its name
+/// and dimension, so that they're the same every time it's opened, without
+/// being stored.
+fn planes(name: &str, dimensions: usize) -> Vec<Vec<f32>> {
+    // FNV-1a, to seed a splitmix64 generator.
+    let mut state = name.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    }) ^ dimensions as u64;
+
+    let mut next = || {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    };
+
+    (0..HASH_BITS)
+        .map(|_| {
+            (0..dimensions)
+                .map(|_| (next() >> 40) as f32 / (1u64 << 23) as f32 - 1.0)
+                .collect()
+        })
+        .collect()
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+/// The cosine similarity of two vectors, or `0` if either is all zeroes.
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let norms = dot(a, a).sqrt() * dot(b, b).sqrt();
+
+    if norms == 0.0 {
+        0.0
+    } else {
+        dot(a, b) / norms
+    }
+}
+
+/// Reads a vector from a JSON value, or returns `None` if it isn't an
+/// array of `dimensions` finite numbers.
+fn from_json(value: &JsonValue, dimensions: usize) -> Option<Vec<f32>> {
+    let items = value.as_array()?;
+
+    if items.len() != dimensions {
+        return None;
+    }
+
+    items
+        .iter()
+        .map(|item| item.as_f64().map(|n| n as f32).filter(|n| n.is_finite()))
+        .collect()
+}
+
+fn encode(vector: &[f32]) -> Vec<u8> {
+    vector.iter().flat_map(|n| n.to_be_bytes().to_vec()).collect()
+}
+
+fn key(bucket: u8, id: Uuid) -> Vec<u8> {
+    let mut key = Vec::with_capacity(17);
+    key.push(bucket);
+    key.extend_from_slice(id.as_bytes());
+    key
+}
+
+fn find<'a>(holder: &'a SledHolder, name: &str) -> Option<&'a VectorIndex> {
+    holder.vector_indexes.iter().find(|index| index.name == name)
+}
+
+/// Checks that a value set for a vector property is a vector of its
+/// dimension. Other properties can have any value.
+pub(crate) fn check_value(holder: &SledHolder, name: &str, value: &JsonValue) -> Result<()> {
+    match find(holder, name) {
+        Some(index) if from_json(value, index.dimensions).is_none() => Err(Error::InvalidVector {
+            name: name.to_string(),
+            dimensions: index.dimensions,
+        }
+        .into()),
+        _ => Ok(()),
+    }
+}
+
+/// The index entry of a property value, given the value as stored, if
+/// it's a vector.
+fn entry(index: &VectorIndex, id: Uuid, value_json: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+    let value = serde_json::from_slice(value_json).ok()?;
+    let vector = from_json(&value, index.dimensions)?;
+    Some((key(index.bucket(&vector), id), encode(&vector)))
+}
+
+/// Updates the index after a vertex property changed, given its old and
+/// new values as stored. `None` means the vertex doesn't have the
+/// property.
+pub(crate) fn on_property_change(
+    holder: &SledHolder,
+    id: Uuid,
+    name: &str,
+    old_value_json: Option<&[u8]>,
+    new_value_json: Option<&[u8]>,
+) -> Result<()> {
+    let index = match find(holder, name) {
+        Some(index) => index,
+        None => return Ok(()),
+    };
+
+    let mut batch = Batch::default();
+
+    if let Some((k, _)) = old_value_json.and_then(|value_json| entry(index, id, value_json)) {
+        batch.remove(k);
+    }
+
+    if let Some((k, v)) = new_value_json.and_then(|value_json| entry(index, id, value_json)) {
+        batch.insert(k, v);
+    }
+
+    holder.retrier.run(|| index.tree.apply_batch(batch.clone()))?;
+    Ok(())
+}
+
+/// Removes every entry of the indexes, so that they can be rebuilt with
+/// `on_property_change`.
+pub(crate) fn clear(holder: &SledHolder) -> Result<()> {
+    for index in &holder.vector_indexes {
+        map_err(index.tree.clear())?;
+    }
+
+    Ok(())
+}
+
+fn definition_key(holder: &SledHolder, index: &VectorIndex) -> Vec<u8> {
+    holder.metadata_key(&format!("vector_index:{}", index.name))
+}
+
+/// Rebuilds an index from the vertex properties.
+fn rebuild(holder: &SledHolder, index: &VectorIndex) -> Result<()> {
+    map_err(index.tree.clear())?;
+
+    for item in holder.vertex_properties.iter() {
+        let (k, v) = map_err(item)?;
+        let mut decoder = Decoder::key(&holder.vertex_properties, &k);
+        let id = decoder.read_uuid()?;
+
+        if decoder.read_fixed_length_string()? != index.name {
+            continue;
+        }
+
+        let value_json = dedup::resolve(&holder.values.store, &holder.vertex_properties, &k, &v)?;
+
+        if let Some((k, v)) = entry(index, id, &value_json) {
+            map_err(index.tree.insert(k, v))?;
+        }
+    }
+
+    map_err(
+        holder
+            .metadata
+            .insert(definition_key(holder, index), index.definition().as_bytes()),
+    )?;
+    Ok(())
+}
+
+/// Rebuilds the indexes that are new, or whose definition has changed,
+/// since the datastore was last opened.
+pub(crate) fn rebuild_stale(holder: &SledHolder) -> Result<()> {
+    for index in &holder.vector_indexes {
+        let recorded = map_err(holder.metadata.get(definition_key(holder, index)))?;
+
+        if recorded.as_ref().map(|recorded| &recorded[..]) != Some(index.definition().as_bytes()) {
+            rebuild(holder, index)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The buckets that differ from `bucket` in exactly `distance` bits.
+fn buckets_at(bucket: u8, distance: u32) -> Vec<u8> {
+    (0..=u8::MAX)
+        .filter(|candidate| (candidate ^ bucket).count_ones() == distance)
+        .collect()
+}
+
+/// Gets the `k` vertices whose vector property `name` is most similar to
+/// `query` by cosine similarity, most similar first, along with their
+/// similarity.
+///
+/// Candidates are read from the query's bucket, then from the buckets
+/// that differ from it in one hyperplane, then two, and so on, until at
+/// least `k` have been found. So the results are approximate: a similar
+/// vector that fell on the other side of a hyperplane may be missed.
+pub(crate) fn knn(
+    holder: &SledHolder,
+    name: &str,
+    query: &[f32],
+    k: usize,
+    deadline: &Deadline,
+) -> Result<Vec<(Vertex, f32)>> {
+    let index = match find(holder, name) {
+        Some(index) => index,
+        None => return Err(Error::UnknownVectorIndex { name: name.to_string() }.into()),
+    };
+
+    if query.len() != index.dimensions || query.iter().any(|n| !n.is_finite()) {
+        return Err(Error::InvalidVector {
+            name: name.to_string(),
+            dimensions: index.dimensions,
+        }
+        .into());
+    }
+
+    let bucket = index.bucket(query);
+    let mut candidates = Vec::new();
+    let mut seen = BTreeSet::new();
+
+    for distance in 0..=HASH_BITS {
+        if k == 0 || candidates.len() >= k {
+            break;
+        }
+
+        for probe in buckets_at(bucket, distance) {
+            for item in index.tree.scan_prefix([probe]) {
+                let (key, value) = map_err(item)?;
+                deadline.tick()?;
+                let mut decoder = Decoder::key(&index.tree, &key);
+                decoder.skip(1)?;
+                let id = decoder.read_uuid()?;
+
+                if value.len() != index.dimensions * 4 {
+                    return Err(decoder.corruption());
+                }
+
+                let vector: Vec<f32> = value
+                    .chunks(4)
+                    .map(|chunk| f32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+                    .collect();
+
+                if seen.insert(id) {
+                    let similarity = cosine_similarity(query, &vector);
+                    candidates.push((id, vector, similarity));
+                }
+            }
+        }
+    }
+
+    candidates.sort_by(|a, b| b.2.partial_cmp(&a.2).unwrap_or(Ordering::Equal).then(a.0.cmp(&b.0)));
+
+    let vertex_manager = VertexManager::new(holder);
+    let vertex_property_manager = VertexPropertyManager::new(holder);
+    let mut results = Vec::with_capacity(k);
+
+    for (id, vector, similarity) in candidates {
+        if results.len() == k {
+            break;
+        }
+
+        // Concurrent writes to the same property can briefly leave an
+        // entry for a value that was replaced, so matches are checked
+        // against the property itself.
+        let current = vertex_property_manager
+            .get(id, name)?
+            .and_then(|value| from_json(&value, index.dimensions));
+
+        if current.as_ref() != Some(&vector) {
+            continue;
+        }
+
+        if let Some(t) = vertex_manager.get(id)? {
+            results.push((Vertex::with_id(id, t), similarity));
+        }
+    }
+
+    Ok(results)
+}
@@ -0,0 +1,303 @@
+use std::collections::BTreeSet;
+
+use super::datastore::SledHolder;
+use super::decode::Decoder;
+use super::errors::{map_err, Error};
+use super::managers::{EdgeRangeManager, VertexManager};
+
+use indradb::{util, EdgeDirection, Result, Type};
+use sled::{Batch, Tree};
+use uuid::Uuid;
+
+/// Defines a materialized view: for each vertex of a type, the set of
+/// vertices reachable from it by following a fixed sequence of edge types,
+/// e.g. the groups a user is a member of. Registered with
+/// `SledConfig::with_materialized_view`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraversalView {
+    source_type: Option<Type>,
+    hops: Vec<(EdgeDirection, Type)>,
+    target_type: Option<Type>,
+}
+
+impl TraversalView {
+    /// Starts a view over vertices of type `source_type`, or of any type if
+    /// it's `None`. Hops are added with `outbound` and `inbound`.
+    pub fn new(source_type: Option<Type>) -> Self {
+        TraversalView {
+            source_type,
+            hops: Vec::new(),
+            target_type: None,
+        }
+    }
+
+    /// Adds a hop along outbound edges of type `t`.
+    pub fn outbound(mut self, t: Type) -> Self {
+        self.hops.push((EdgeDirection::Outbound, t));
+        self
+    }
+
+    /// Adds a hop along inbound edges of type `t`.
+    pub fn inbound(mut self, t: Type) -> Self {
+        self.hops.push((EdgeDirection::Inbound, t));
+        self
+    }
+
+    /// Only includes reached vertices of type `t`.
+    pub fn with_target_type(self, t: Type) -> Self {
+        TraversalView {
+            target_type: Some(t),
+            ..self
+        }
+    }
+}
+
+/// A view and the tree it's materialized in, keyed by `(source ID, target
+/// ID)`.
+pub(crate) struct MaterializedView {
+    pub(crate) name: String,
+    pub(crate) definition: TraversalView,
+    pub(crate) tree: Tree,
+}
+
+fn opposite(direction: EdgeDirection) -> EdgeDirection {
+    match direction {
+        EdgeDirection::Outbound => EdgeDirection::Inbound,
+        EdgeDirection::Inbound => EdgeDirection::Outbound,
+    }
+}
+
+/// Follows `hops` from every vertex in `frontier`, returning the distinct
+/// vertices reached.
+fn follow<'a, I>(holder: &SledHolder, mut frontier: BTreeSet<Uuid>, hops: I) -> Result<BTreeSet<Uuid>>
+where
+    I: Iterator<Item = (EdgeDirection, &'a Type)>,
+{
+    for (direction, t) in hops {
+        let edge_range_manager = EdgeRangeManager::for_direction(holder, direction);
+        let mut next = BTreeSet::new();
+
+        for id in frontier {
+            for item in edge_range_manager.iterate_for_range(id, Some(t), None)? {
+                let (_, _, _, second_id) = item?;
+                next.insert(second_id);
+            }
+        }
+
+        frontier = next;
+    }
+
+    Ok(frontier)
+}
+
+fn has_type(holder: &SledHolder, id: Uuid, t: &Option<Type>) -> Result<bool> {
+    match VertexManager::new(holder).get(id)? {
+        Some(vertex_t) => Ok(t.as_ref().is_none_or(|t| *t == vertex_t)),
+        None => Ok(false),
+    }
+}
+
+/// Recomputes the row of the view for one source vertex.
+fn refresh_row(holder: &SledHolder, view: &MaterializedView, source_id: Uuid) -> Result<()> {
+    let definition = &view.definition;
+
+    let targets = if has_type(holder, source_id, &definition.source_type)? {
+        let hops = definition.hops.iter().map(|&(direction, ref t)| (direction, t));
+        let mut targets = follow(holder, vec![source_id].into_iter().collect(), hops)?;
+
+        if definition.target_type.is_some() {
+            let mut typed_targets = BTreeSet::new();
+
+            for target_id in targets {
+                if has_type(holder, target_id, &definition.target_type)? {
+                    typed_targets.insert(target_id);
+                }
+            }
+
+            targets = typed_targets;
+        }
+
+        targets
+    } else {
+        BTreeSet::new()
+    };
+
+    let prefix = util::build(&[util::Component::Uuid(source_id)]);
+    let mut batch = Batch::default();
+    let mut existing = BTreeSet::new();
+
+    for item in view.tree.scan_prefix(&prefix).keys() {
+        let k = map_err(item)?;
+        let mut decoder = Decoder::key(&view.tree, &k);
+        decoder.read_uuid()?;
+        let target_id = decoder.read_uuid()?;
+
+        if !targets.contains(&target_id) {
+            batch.remove(k);
+        }
+
+        existing.insert(target_id);
+    }
+
+    for target_id in targets.difference(&existing) {
+        batch.insert(
+            util::build(&[util::Component::Uuid(source_id), util::Component::Uuid(*target_id)]),
+            &[],
+        );
+    }
+
+    holder.retrier.run(|| view.tree.apply_batch(batch.clone()))
+}
+
+/// Whether views are maintained as the graph changes. While indexing is
+/// deferred, they can't be, since sources are found through the reversed
+/// edge ranges. They're rebuilt once it's finished.
+fn is_maintained(holder: &SledHolder) -> bool {
+    !holder.views.is_empty() && !holder.is_indexing_deferred()
+}
+
+/// Updates the views after an edge was created or deleted, by recomputing
+/// the rows of every source vertex that can reach it.
+pub(crate) fn on_edge_change(holder: &SledHolder, outbound_id: Uuid, t: &Type, inbound_id: Uuid) -> Result<()> {
+    if !is_maintained(holder) {
+        return Ok(());
+    }
+
+    for view in &holder.views {
+        let hops = &view.definition.hops;
+        let mut sources = BTreeSet::new();
+
+        for (i, &(direction, ref hop_t)) in hops.iter().enumerate() {
+            if hop_t != t {
+                continue;
+            }
+
+            let from_id = match direction {
+                EdgeDirection::Outbound => outbound_id,
+                EdgeDirection::Inbound => inbound_id,
+            };
+
+            // Walk the preceding hops backwards, to find the sources whose
+            // traversals reach the edge at this hop.
+            let preceding = hops[..i]
+                .iter()
+                .rev()
+                .map(|&(direction, ref t)| (opposite(direction), t));
+            sources.extend(follow(holder, vec![from_id].into_iter().collect(), preceding)?);
+        }
+
+        for source_id in sources {
+            refresh_row(holder, view, source_id)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Updates the views after a vertex was created, replaced or deleted. Its
+/// own row is recomputed, and if a view only includes targets of some
+/// type, so are the rows of the sources that can reach it.
+pub(crate) fn on_vertex_change(holder: &SledHolder, id: Uuid) -> Result<()> {
+    if !is_maintained(holder) {
+        return Ok(());
+    }
+
+    for view in &holder.views {
+        refresh_row(holder, view, id)?;
+
+        if view.definition.target_type.is_some() {
+            let hops = view.definition.hops.iter().rev();
+            let preceding = hops.map(|&(direction, ref t)| (opposite(direction), t));
+
+            for source_id in follow(holder, vec![id].into_iter().collect(), preceding)? {
+                refresh_row(holder, view, source_id)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn definition_key(holder: &SledHolder, view: &MaterializedView) -> Vec<u8> {
+    holder.metadata_key(&format!("view:{}", view.name))
+}
+
+/// Rebuilds a view from scratch.
+fn rebuild(holder: &SledHolder, view: &MaterializedView) -> Result<()> {
+    map_err(view.tree.clear())?;
+
+    for item in holder.vertices.iter().keys() {
+        let k = map_err(item)?;
+        let id = Decoder::key(&holder.vertices, &k).read_uuid()?;
+        refresh_row(holder, view, id)?;
+    }
+
+    // The definition is recorded so that changing it rebuilds the view on
+    // the next open.
+    let definition = format!("{:?}", view.definition);
+    map_err(
+        holder
+            .metadata
+            .insert(definition_key(holder, view), definition.as_bytes()),
+    )?;
+    Ok(())
+}
+
+/// Rebuilds the views that are new, or whose definition has changed, since
+/// the datastore was last opened.
+pub(crate) fn rebuild_stale(holder: &SledHolder) -> Result<()> {
+    if !is_maintained(holder) {
+        return Ok(());
+    }
+
+    for view in &holder.views {
+        let definition = format!("{:?}", view.definition);
+        let recorded = map_err(holder.metadata.get(definition_key(holder, view)))?;
+
+        if recorded.as_ref().map(|recorded| &recorded[..]) != Some(definition.as_bytes()) {
+            rebuild(holder, view)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Rebuilds every view, e.g. once deferred indexing is finished.
+pub(crate) fn rebuild_all(holder: &SledHolder) -> Result<()> {
+    for view in &holder.views {
+        rebuild(holder, view)?;
+    }
+
+    Ok(())
+}
+
+fn find<'a>(holder: &'a SledHolder, name: &str) -> Result<&'a MaterializedView> {
+    holder
+        .views
+        .iter()
+        .find(|view| view.name == name)
+        .ok_or_else(|| Error::UnknownView { name: name.to_string() }.into())
+}
+
+/// Gets the targets of a source vertex in a view, in ID order.
+pub(crate) fn get(holder: &SledHolder, name: &str, source_id: Uuid) -> Result<Vec<Uuid>> {
+    let view = find(holder, name)?;
+    let prefix = util::build(&[util::Component::Uuid(source_id)]);
+    let mut targets = Vec::new();
+
+    for item in view.tree.scan_prefix(&prefix).keys() {
+        let k = map_err(item)?;
+        let mut decoder = Decoder::key(&view.tree, &k);
+        decoder.read_uuid()?;
+        targets.push(decoder.read_uuid()?);
+    }
+
+    Ok(targets)
+}
+
+/// Whether a view maps a source vertex to a target vertex.
+pub(crate) fn contains(holder: &SledHolder, name: &str, source_id: Uuid, target_id: Uuid) -> Result<bool> {
+    let view = find(holder, name)?;
+    let key = util::build(&[util::Component::Uuid(source_id), util::Component::Uuid(target_id)]);
+    holder.retrier.run(|| view.tree.contains_key(&key))
+}
@@ -0,0 +1,74 @@
+use std::ops::{Bound, RangeBounds};
+
+use super::datastore::SledHolder;
+use super::decode::Decoder;
+use super::errors::map_err;
+
+use indradb::Result;
+use sled::Tree;
+use uuid::Uuid;
+
+/// Reads every record in `tree` whose key starts with `prefix`, so that
+/// sled pulls the pages holding them into its cache. Returns the number of
+/// records read.
+fn touch_prefix(tree: &Tree, prefix: &[u8]) -> Result<u64> {
+    let mut count = 0;
+
+    for item in tree.scan_prefix(prefix) {
+        map_err(item)?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// Reads a vertex's records: its type, its properties, its edges in both
+/// directions, and the properties of its edges. Returns the number of
+/// records read.
+fn warm_vertex(holder: &SledHolder, id: Uuid) -> Result<u64> {
+    let prefix = id.as_bytes();
+    let mut count = 0;
+
+    if map_err(holder.vertices.get(prefix))?.is_some() {
+        count += 1;
+    }
+
+    count += touch_prefix(&holder.vertex_properties, prefix)?;
+    count += touch_prefix(&holder.edges, prefix)?;
+    count += touch_prefix(&holder.edge_ranges.writer(), prefix)?;
+    count += touch_prefix(&holder.reversed_edge_ranges.writer(), prefix)?;
+    count += touch_prefix(&holder.edge_properties, prefix)?;
+    count += touch_prefix(&holder.reversed_edge_properties.writer(), prefix)?;
+    count += touch_prefix(&holder.vertex_edge_properties.writer(), prefix)?;
+    Ok(count)
+}
+
+pub(crate) fn warm(holder: &SledHolder, ids: &[Uuid]) -> Result<u64> {
+    let mut count = 0;
+
+    for &id in ids {
+        count += warm_vertex(holder, id)?;
+    }
+
+    Ok(count)
+}
+
+pub(crate) fn warm_prefix<R: RangeBounds<Uuid>>(holder: &SledHolder, range: R) -> Result<u64> {
+    let bound = |bound: Bound<&Uuid>| match bound {
+        Bound::Included(id) => Bound::Included(id.as_bytes().to_vec()),
+        Bound::Excluded(id) => Bound::Excluded(id.as_bytes().to_vec()),
+        Bound::Unbounded => Bound::Unbounded,
+    };
+
+    let range = (bound(range.start_bound()), bound(range.end_bound()));
+    let mut count = 0;
+
+    for item in holder.vertices.range::<Vec<u8>, _>(range).keys() {
+        let key = map_err(item)?;
+
+        let id = Decoder::key(&holder.vertices, &key).read_uuid()?;
+        count += warm_vertex(holder, id)?;
+    }
+
+    Ok(count)
+}